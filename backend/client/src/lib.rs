@@ -0,0 +1,405 @@
+//! Smart Patient Room Monitor - Rust Client
+//!
+//! Typed async client for the monitor's REST and WebSocket APIs, built on the
+//! same models the server uses (`patient-monitor-types`).
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), patient_monitor_client::ClientError> {
+//! use patient_monitor_client::MonitorClient;
+//!
+//! let client = MonitorClient::new("http://monitor.local:8080");
+//! println!("{:?}", client.summary().await?);
+//!
+//! let mut stream = client.subscribe().await?;
+//! while let Some(msg) = stream.next_alert().await? {
+//!     println!("ALERT: {:?}", msg);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use tokio_tungstenite::tungstenite::Message;
+
+pub use patient_monitor_types::api::{
+    ActivityAnalysis, AlertPrecisionReport, ApiError, DailySummary, DownsampledReadings, HourlyActivity,
+    MonitorSettings, QuietHoursReport, QuietHoursWeek, RoomQuietHours, PatientState, StateHistory, StatePeriod, SummaryResponse, TimeseriesPoint,
+};
+pub use patient_monitor_types::fhir::{FhirBundle, FhirObservation};
+pub use patient_monitor_types::ws::{
+    Capability, DeltaCodec, RoomState, RoomSummary, WsMessage, WsRequest, PROTOCOL_VERSION,
+};
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// Request could not be sent or the response could not be read
+    Http(reqwest::Error),
+    /// Server answered with an error status
+    Api { status: u16, error: ApiError },
+    WebSocket(tokio_tungstenite::tungstenite::Error),
+    Json(serde_json::Error),
+    MessagePack(rmp_serde::decode::Error),
+    /// The server closed the socket or didn't answer `hello`
+    Handshake(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "HTTP error: {}", e),
+            ClientError::Api { status, error } => {
+                write!(f, "API error {} ({}): {}", status, error.error, error.message)?;
+                match &error.request_id {
+                    Some(id) => write!(f, " [request {}]", id),
+                    None => Ok(()),
+                }
+            }
+            ClientError::WebSocket(e) => write!(f, "WebSocket error: {}", e),
+            ClientError::Json(e) => write!(f, "Invalid JSON: {}", e),
+            ClientError::MessagePack(e) => write!(f, "Invalid MessagePack: {}", e),
+            ClientError::Handshake(msg) => write!(f, "WebSocket handshake failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for ClientError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        ClientError::WebSocket(e)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> Self {
+        ClientError::Json(e)
+    }
+}
+
+impl From<rmp_serde::decode::Error> for ClientError {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        ClientError::MessagePack(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MonitorClient {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl MonitorClient {
+    /// `base_url` is the server root, e.g. `http://localhost:8080`
+    pub fn new(base_url: &str) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+    
+    /// Use a preconfigured HTTP client (timeouts, proxies, headers)
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+    
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T, ClientError> {
+        let response = self.http
+            .get(format!("{}{}", self.base_url, path))
+            .query(query)
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+    
+    async fn decode<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, ClientError> {
+        let status = response.status();
+        let body = response.bytes().await?;
+        
+        if !status.is_success() {
+            let error = serde_json::from_slice(&body).unwrap_or_else(|_| {
+                ApiError::new("http_error", &String::from_utf8_lossy(&body))
+            });
+            return Err(ClientError::Api { status: status.as_u16(), error });
+        }
+        
+        Ok(serde_json::from_slice(&body)?)
+    }
+    
+    pub async fn health(&self) -> Result<serde_json::Value, ClientError> {
+        self.get("/api/health", &[]).await
+    }
+    
+    pub async fn summary(&self) -> Result<SummaryResponse, ClientError> {
+        self.get("/api/summary", &[]).await
+    }
+    
+    /// Most recent observations as a FHIR searchset bundle
+    pub async fn observations(&self, count: usize) -> Result<FhirBundle, ClientError> {
+        self.get("/api/observations", &[("_count", count.to_string())]).await
+    }
+    
+    /// Up to `count` observations older than the reading with `after_id` at
+    /// `before_timestamp`, newest first. A full page's `next` link continues
+    /// with the page after it.
+    pub async fn observations_before(
+        &self,
+        count: usize,
+        before_timestamp: DateTime<Utc>,
+        after_id: i64,
+    ) -> Result<FhirBundle, ClientError> {
+        self.get("/api/observations", &[
+            ("_count", count.to_string()),
+            ("before_timestamp", before_timestamp.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)),
+            ("after_id", after_id.to_string()),
+        ]).await
+    }
+    
+    /// Observations from the last `minutes` minutes
+    pub async fn observations_since(&self, minutes: i64) -> Result<FhirBundle, ClientError> {
+        self.get("/api/observations", &[("minutes", minutes.to_string())]).await
+    }
+    
+    /// Compact readings from the last `minutes` minutes; `fill_max_gap` (seconds)
+    /// enables interpolation of gaps up to that length
+    pub async fn timeseries(
+        &self,
+        minutes: i64,
+        fill_max_gap: Option<i64>,
+    ) -> Result<Vec<TimeseriesPoint>, ClientError> {
+        let mut query = vec![("minutes", minutes.to_string())];
+        if let Some(max_gap) = fill_max_gap {
+            query.push(("fill", "true".to_string()));
+            query.push(("max_gap", max_gap.to_string()));
+        }
+        self.get("/api/timeseries", &query).await
+    }
+    
+    /// Up to `count` observations stored after change-feed position `after`,
+    /// oldest first. The bundle's `next` link continues the feed.
+    pub async fn observation_changes(&self, after: i64, count: usize) -> Result<FhirBundle, ClientError> {
+        self.get("/api/observations/changes", &[("after", after.to_string()), ("_count", count.to_string())]).await
+    }
+    
+    pub async fn latest_observation(&self) -> Result<FhirObservation, ClientError> {
+        self.get("/api/observations/latest", &[]).await
+    }
+    
+    pub async fn observation(&self, id: i64) -> Result<FhirObservation, ClientError> {
+        self.get(&format!("/api/observations/{}", id), &[]).await
+    }
+    
+    /// Sleep analysis between `start_hour` on `date` and `end_hour` (next day if earlier)
+    pub async fn sleep_analysis(
+        &self,
+        date: NaiveDate,
+        start_hour: u32,
+        end_hour: u32,
+    ) -> Result<ActivityAnalysis, ClientError> {
+        self.get("/api/activity/sleep", &[
+            ("date", date.format("%Y-%m-%d").to_string()),
+            ("start_hour", start_hour.to_string()),
+            ("end_hour", end_hour.to_string()),
+        ]).await
+    }
+    
+    /// Per-room aggregates of each day from `from` to `to` (inclusive)
+    pub async fn daily_summary(&self, from: NaiveDate, to: NaiveDate) -> Result<Vec<DailySummary>, ClientError> {
+        self.get("/api/summary/daily", &[
+            ("from", from.format("%Y-%m-%d").to_string()),
+            ("to", to.format("%Y-%m-%d").to_string()),
+        ]).await
+    }
+    
+    /// Temperature and sound level of `room` between `from` and `to`, each
+    /// reduced to at most `points` readings
+    pub async fn downsampled(
+        &self,
+        room: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        points: usize,
+    ) -> Result<DownsampledReadings, ClientError> {
+        self.get("/api/observations/downsampled", &[
+            ("room", room.to_string()),
+            ("from", from.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)),
+            ("to", to.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)),
+            ("points", points.to_string()),
+        ]).await
+    }
+    
+    pub async fn period_analysis(&self, minutes: i64) -> Result<ActivityAnalysis, ClientError> {
+        self.get("/api/activity/period", &[("minutes", minutes.to_string())]).await
+    }
+    
+    pub async fn hourly_activity(&self, date: NaiveDate) -> Result<Vec<HourlyActivity>, ClientError> {
+        self.get("/api/activity/hourly", &[("date", date.format("%Y-%m-%d").to_string())]).await
+    }
+    
+    /// Periods `room` spent in bed, moving or out of the room between `from` and `to`
+    pub async fn state_history(
+        &self,
+        room: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<StateHistory, ClientError> {
+        self.get(&format!("/api/rooms/{}/state-history", room), &[
+            ("from", from.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)),
+            ("to", to.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)),
+        ]).await
+    }
+    
+    /// Outcomes recorded for the alerts from `from` to `to` (inclusive), per
+    /// `day` or `week` and per alert `type` or `room`
+    pub async fn alert_precision(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        interval: &str,
+        by: &str,
+    ) -> Result<AlertPrecisionReport, ClientError> {
+        self.get("/api/analytics/alert-precision", &[
+            ("from", from.format("%Y-%m-%d").to_string()),
+            ("to", to.format("%Y-%m-%d").to_string()),
+            ("interval", interval.to_string()),
+            ("by", by.to_string()),
+        ]).await
+    }
+    
+    /// Quiet-hours noise compliance for the last `weeks` weeks
+    pub async fn quiet_hours_report(&self, weeks: u32) -> Result<QuietHoursReport, ClientError> {
+        self.get("/api/reports/quiet-hours", &[("weeks", weeks.to_string())]).await
+    }
+    
+    pub async fn settings(&self) -> Result<MonitorSettings, ClientError> {
+        self.get("/api/settings", &[]).await
+    }
+    
+    pub async fn update_settings(&self, settings: &MonitorSettings) -> Result<(), ClientError> {
+        let response = self.http
+            .post(format!("{}/api/settings", self.base_url))
+            .json(settings)
+            .send()
+            .await?;
+        Self::decode::<serde_json::Value>(response).await.map(|_| ())
+    }
+    
+    fn ws_url(&self, path: &str) -> String {
+        if let Some(rest) = self.base_url.strip_prefix("https://") {
+            format!("wss://{}{}", rest, path)
+        } else if let Some(rest) = self.base_url.strip_prefix("http://") {
+            format!("ws://{}{}", rest, path)
+        } else {
+            format!("{}{}", self.base_url, path)
+        }
+    }
+    
+    /// Open the live WebSocket stream
+    pub async fn subscribe(&self) -> Result<EventStream, ClientError> {
+        let (socket, _) = tokio_tungstenite::connect_async(self.ws_url("/ws")).await?;
+        Ok(EventStream::new(socket))
+    }
+    
+    /// Open the live stream and negotiate `capabilities`. Deltas are expanded
+    /// back into full readings, so messages look the same as without them;
+    /// check `EventStream::capabilities` for what the server granted.
+    pub async fn subscribe_with(&self, capabilities: &[Capability]) -> Result<EventStream, ClientError> {
+        let mut stream = self.subscribe().await?;
+        let hello = serde_json::to_string(&WsRequest::Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: capabilities.to_vec(),
+        })?;
+        stream.socket.send(Message::text(hello)).await?;
+        
+        loop {
+            match stream.next_message().await? {
+                Some(WsMessage::Welcome { capabilities, .. }) => {
+                    stream.capabilities = capabilities;
+                    return Ok(stream);
+                }
+                // A version 1 server ignores hello and never answers
+                Some(WsMessage::SensorReading { .. }) => {
+                    return Err(ClientError::Handshake("server does not support negotiation".to_string()));
+                }
+                Some(_) => continue,
+                None => return Err(ClientError::Handshake("connection closed".to_string())),
+            }
+        }
+    }
+    
+    /// Open the ward overview stream, which only carries `WardSnapshot` frames
+    pub async fn subscribe_ward(&self) -> Result<EventStream, ClientError> {
+        let (socket, _) = tokio_tungstenite::connect_async(self.ws_url("/ws/ward")).await?;
+        Ok(EventStream::new(socket))
+    }
+}
+
+type Socket = tokio_tungstenite::WebSocketStream<
+    tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+>;
+
+/// Live messages from the monitor's WebSocket
+pub struct EventStream {
+    socket: Socket,
+    capabilities: Vec<Capability>,
+    deltas: DeltaCodec,
+}
+
+impl EventStream {
+    fn new(socket: Socket) -> Self {
+        Self { socket, capabilities: Vec::new(), deltas: DeltaCodec::new() }
+    }
+    
+    /// Capabilities the server granted, empty unless opened with `subscribe_with`
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+    
+    /// Next message, or `None` once the server closed the connection
+    pub async fn next_message(&mut self) -> Result<Option<WsMessage>, ClientError> {
+        while let Some(frame) = self.socket.next().await {
+            let msg: WsMessage = match frame? {
+                Message::Text(text) => serde_json::from_str(&text)?,
+                Message::Binary(bytes) => rmp_serde::from_slice(&bytes)?,
+                Message::Close(_) => return Ok(None),
+                // Pings are answered by tungstenite; other frames aren't used by the server
+                _ => continue,
+            };
+            return Ok(Some(self.deltas.decode(msg)));
+        }
+        Ok(None)
+    }
+    
+    /// Next sensor reading that carries an alert, skipping everything else
+    pub async fn next_alert(&mut self) -> Result<Option<WsMessage>, ClientError> {
+        while let Some(msg) = self.next_message().await? {
+            if matches!(&msg, WsMessage::SensorReading { alerts, .. } if !alerts.is_empty()) {
+                return Ok(Some(msg));
+            }
+        }
+        Ok(None)
+    }
+    
+    /// Ask the server to resend readings recorded since `since`
+    pub async fn request_replay(&mut self, since: DateTime<Utc>) -> Result<(), ClientError> {
+        let request = serde_json::to_string(&WsRequest::Replay { since })?;
+        self.socket.send(Message::text(request)).await?;
+        Ok(())
+    }
+    
+    /// Ask the server to resend readings stored after the `seq` of the last
+    /// reading received, e.g. after reconnecting
+    pub async fn request_resume(&mut self, after: i64) -> Result<(), ClientError> {
+        let request = serde_json::to_string(&WsRequest::Resume { after })?;
+        self.socket.send(Message::text(request)).await?;
+        Ok(())
+    }
+}
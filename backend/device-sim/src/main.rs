@@ -0,0 +1,286 @@
+//! Device simulator
+//!
+//! Speaks the bedside device's serial line protocol, so the monitor's real
+//! serial reader can be exercised without hardware: one
+//! `<temperature>,<motion 0|1>,<sound level>` line per reading and
+//! `LOG:<level>:<message>` lines from the firmware. On Unix it opens a
+//! pseudo-terminal and prints the path of its serial end, to be given to the
+//! monitor as `SERIAL_PORT` (or in `ROOMS`); `--link` also makes a symlink to
+//! it at a fixed path. `--port` writes to an existing port instead, e.g. one
+//! end of a virtual COM port pair on Windows.
+//!
+//! ```text
+//! device-sim [--scenario NAME | --script FILE] [--link PATH] [--port PATH]
+//!            [--baud N] [--fahrenheit] [--speed N] [--seed N]
+//! ```
+//!
+//! Scenarios are scripts (see `scenario`); `--scenario list` lists the
+//! built-in ones. `--speed 60` plays an hour in a minute. The monitor times
+//! readings as they arrive, so a faster scenario is also shorter for it.
+
+mod scenario;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use scenario::{Scenario, Step};
+
+const USAGE: &str = "\
+Usage: device-sim [--scenario NAME | --script FILE] [--link PATH] [--port PATH]
+                  [--baud N] [--fahrenheit] [--speed N] [--seed N]";
+
+struct Options {
+    scenario: Scenario,
+    /// Existing port to write to instead of a pseudo-terminal
+    port: Option<String>,
+    baud_rate: u32,
+    /// Symlink to the pseudo-terminal
+    link: Option<PathBuf>,
+    fahrenheit: bool,
+    speed: f64,
+    seed: Option<u64>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut scenario = None;
+    let mut options = Options {
+        scenario: Scenario { steps: Vec::new(), repeat: false },
+        port: None,
+        baud_rate: 9600,
+        link: None,
+        fahrenheit: false,
+        speed: 1.0,
+        seed: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--scenario" => {
+                let name = value()?;
+                if name == "list" {
+                    for (name, script) in scenario::BUILT_IN {
+                        let about = script.lines().next().unwrap_or_default().trim_start_matches("# ");
+                        println!("{:<14} {}", name, about);
+                    }
+                    std::process::exit(0);
+                }
+                scenario = Some(scenario::built_in(&name)
+                    .ok_or_else(|| format!("Unknown scenario {}; --scenario list lists them", name))?);
+            }
+            "--script" => {
+                let path = value()?;
+                let script = std::fs::read_to_string(&path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+                scenario = Some(scenario::parse(&script).map_err(|e| format!("{}: {}", path, e))?);
+            }
+            "--port" => options.port = Some(value()?),
+            "--baud" => options.baud_rate = value()?.parse().map_err(|_| "Invalid --baud".to_string())?,
+            "--link" => options.link = Some(PathBuf::from(value()?)),
+            "--fahrenheit" => options.fahrenheit = true,
+            "--speed" => {
+                options.speed = value()?.parse().map_err(|_| "Invalid --speed".to_string())?;
+                if options.speed.is_nan() || options.speed <= 0.0 {
+                    return Err("--speed must be positive".to_string());
+                }
+            }
+            "--seed" => options.seed = Some(value()?.parse().map_err(|_| "Invalid --seed".to_string())?),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => return Err(format!("Unknown argument {}\n{}", other, USAGE)),
+        }
+    }
+
+    options.scenario = match scenario {
+        Some(scenario) => scenario,
+        None => scenario::built_in("normal").expect("normal is built in"),
+    };
+    Ok(options)
+}
+
+/// Values readings are sent with, as set by the scenario so far
+struct State {
+    interval: Duration,
+    celsius: f32,
+    temperature_jitter: f32,
+    sound: i32,
+    sound_jitter: i32,
+    motion: f64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            celsius: 22.0,
+            temperature_jitter: 0.0,
+            sound: 30,
+            sound_jitter: 0,
+            motion: 0.0,
+        }
+    }
+}
+
+struct Device<W: Write> {
+    out: W,
+    state: State,
+    rng: StdRng,
+    fahrenheit: bool,
+    speed: f64,
+}
+
+impl<W: Write> Device<W> {
+    fn send(&mut self, line: &str) -> std::io::Result<()> {
+        self.out.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.out.flush()
+    }
+
+    fn send_reading(&mut self, motion: bool, sound: i32) -> std::io::Result<()> {
+        let state = &self.state;
+        let jitter = state.temperature_jitter;
+        let celsius = state.celsius + if jitter > 0.0 { self.rng.gen_range(-jitter..=jitter) } else { 0.0 };
+        let temperature = if self.fahrenheit { celsius * 9.0 / 5.0 + 32.0 } else { celsius };
+        self.send(&format!("{:.2},{},{}", temperature, motion as u8, sound.max(0)))
+    }
+
+    fn wait(&self, duration: Duration) {
+        std::thread::sleep(duration.div_f64(self.speed));
+    }
+
+    fn run(&mut self, step: &Step) -> std::io::Result<()> {
+        match step {
+            Step::Every(interval) => self.state.interval = *interval,
+            Step::Temperature { celsius, jitter } => {
+                self.state.celsius = *celsius;
+                self.state.temperature_jitter = jitter.abs();
+            }
+            Step::Sound { level, jitter } => {
+                self.state.sound = *level;
+                self.state.sound_jitter = jitter.abs();
+            }
+            Step::Motion(probability) => self.state.motion = *probability,
+            Step::Hold(duration) => {
+                let interval = self.state.interval.max(Duration::from_millis(1));
+                let mut held = Duration::ZERO;
+                while held < *duration {
+                    let motion = self.rng.gen_bool(self.state.motion);
+                    let jitter = self.state.sound_jitter;
+                    let sound = self.state.sound + self.rng.gen_range(-jitter..=jitter);
+                    self.send_reading(motion, sound)?;
+                    self.wait(interval);
+                    held += interval;
+                }
+            }
+            Step::Fall(sound) => {
+                eprintln!("Fall (sound level {})", sound);
+                self.send_reading(true, *sound)?;
+                self.wait(self.state.interval);
+            }
+            Step::Log { level, message } => self.send(&format!("LOG:{}:{}", level, message))?,
+            Step::Firmware(version) => self.send(&format!("FW:{}", version))?,
+            Step::Bed(occupied) => {
+                eprintln!("Bed {}", if *occupied { "occupied" } else { "left" });
+                self.send(&format!("BED:{}", *occupied as u8))?;
+            }
+            Step::Raw(line) => self.send(line)?,
+            Step::Silence(duration) => {
+                eprintln!("Silent for {:?}", duration);
+                self.wait(*duration);
+            }
+        }
+        Ok(())
+    }
+
+    fn play(&mut self, scenario: &Scenario) -> std::io::Result<()> {
+        loop {
+            for step in &scenario.steps {
+                self.run(step)?;
+            }
+            if !scenario.repeat {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Pseudo-terminal to write to, the path of its serial end, and that end,
+/// kept open so writes succeed while the monitor hasn't opened it
+#[cfg(unix)]
+fn open_pty(link: Option<&PathBuf>) -> Result<(serialport::TTYPort, String, serialport::TTYPort), String> {
+    use serialport::SerialPort;
+
+    let (master, slave) = serialport::TTYPort::pair().map_err(|e| format!("Can't open a pseudo-terminal: {}", e))?;
+    let path = slave.name().ok_or("The pseudo-terminal has no name")?;
+    if let Some(link) = link {
+        let _ = std::fs::remove_file(link);
+        std::os::unix::fs::symlink(&path, link).map_err(|e| format!("Can't link {}: {}", link.display(), e))?;
+    }
+    Ok((master, path, slave))
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    // Serial end of the pseudo-terminal, open until the simulator exits
+    let mut _serial_end = None;
+    let out: Box<dyn Write> = match &options.port {
+        Some(port) => match serialport::new(port, options.baud_rate).open() {
+            Ok(port) => Box::new(port),
+            Err(e) => {
+                eprintln!("Can't open {}: {}", port, e);
+                std::process::exit(1);
+            }
+        },
+        #[cfg(unix)]
+        None => match open_pty(options.link.as_ref()) {
+            Ok((master, path, slave)) => {
+                // The path on stdout, for scripts starting the monitor
+                println!("{}", path);
+                _serial_end = Some(slave);
+                Box::new(master)
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        #[cfg(not(unix))]
+        None => {
+            eprintln!("Pseudo-terminals need Unix; give an existing port with --port");
+            std::process::exit(2);
+        }
+    };
+
+    let mut device = Device {
+        out,
+        state: State::default(),
+        rng,
+        fahrenheit: options.fahrenheit,
+        speed: options.speed,
+    };
+    if let Err(e) = device.play(&options.scenario) {
+        eprintln!("Write failed: {}", e);
+        std::process::exit(1);
+    }
+
+    // Closing the port looks like an unplugged device to the monitor
+    eprintln!("Scenario finished; keeping the port open until interrupted");
+    loop {
+        std::thread::park();
+    }
+}
@@ -0,0 +1,232 @@
+//! Scenario scripts
+//!
+//! A scenario is a text file with one command per line; `#` starts a
+//! comment, except in `raw` lines. Readings are sent at the current interval
+//! with the current values, each with random jitter, while a `hold` runs:
+//!
+//! ```text
+//! every 1s              # interval between readings
+//! temperature 22.5 0.2  # °C, ± jitter
+//! sound 35 10           # sound level, ± jitter
+//! motion 0.3            # probability of motion in a reading
+//! hold 10m              # send readings for 10 minutes
+//! fall 400              # one reading with motion and a loud sound
+//! log error watchdog reset
+//! firmware 1.4.2        # version announcement sent on boot
+//! bed 1                 # bed sensor: bed occupied (0: left)
+//! # Any line, sent as it is, e.g. a garbled one
+//! raw 22.5,1
+//! silence 2m            # nothing, as if the device were unplugged
+//! repeat                # start over from the top
+//! ```
+//!
+//! Durations take `ms`, `s`, `m` or `h`.
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Every(Duration),
+    Temperature { celsius: f32, jitter: f32 },
+    Sound { level: i32, jitter: i32 },
+    Motion(f64),
+    Hold(Duration),
+    Fall(i32),
+    Log { level: String, message: String },
+    Firmware(String),
+    Bed(bool),
+    Raw(String),
+    Silence(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub steps: Vec<Step>,
+    /// Start over once the last step is done
+    pub repeat: bool,
+}
+
+/// Sound level of a fall without one given, above the default threshold
+const DEFAULT_FALL_SOUND: i32 = 400;
+
+/// Built-in scenarios, by name
+pub const BUILT_IN: &[(&str, &str)] = &[
+    ("normal", "\
+# A resting patient: mostly still, quiet, occasional movement
+every 1s
+temperature 22.0 0.3
+sound 30 10
+motion 0.2
+hold 1h
+repeat
+"),
+    ("fall", "\
+# Movement, a fall, then lying still
+every 1s
+temperature 22.0 0.3
+sound 35 10
+motion 0.4
+hold 1m
+fall 400
+motion 0
+sound 20 5
+hold 2m
+repeat
+"),
+    ("inactivity", "\
+# No movement for ten minutes, longer than the default inactivity limit
+every 1s
+temperature 22.0 0.3
+sound 25 5
+motion 0.3
+hold 30s
+motion 0
+hold 10m
+repeat
+"),
+    ("sound-fault", "\
+# A dead microphone: the sound level doesn't vary at all. It is reported
+# once flat for SOUND_FLATLINE_HOURS.
+every 1s
+temperature 22.0 0.3
+sound 0 0
+motion 0.2
+hold 1h
+repeat
+"),
+    ("night", "\
+# A night with a bed sensor: asleep, up to the bathroom out of the motion
+# sensor's view, back to bed
+every 1s
+temperature 21.5 0.3
+sound 20 5
+bed 1
+motion 0.05
+hold 20m
+bed 0
+motion 0.8
+hold 1m
+motion 0
+hold 15m
+motion 0.8
+hold 1m
+bed 1
+motion 0.05
+hold 20m
+repeat
+"),
+    ("device-faults", "\
+# Firmware logs, garbled lines and an unplugged cable between readings
+firmware 1.4.1
+every 1s
+temperature 22.0 0.3
+sound 30 10
+motion 0.2
+hold 30s
+log warn sensor bus retry
+raw 22.0,1
+raw ##@!
+hold 30s
+log error watchdog reset
+silence 1m
+log info boot v1.4.2
+firmware 1.4.2
+hold 30s
+repeat
+"),
+];
+
+/// Built-in scenario `name`
+pub fn built_in(name: &str) -> Option<Scenario> {
+    BUILT_IN
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, script)| parse(script).expect("built-in scenarios are valid"))
+}
+
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value.parse().map_err(|_| format!("Invalid duration: {}", s))?;
+    let secs = match unit {
+        "ms" => value / 1000.0,
+        "s" | "" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(format!("Invalid duration unit in {}, expected ms, s, m or h", s)),
+    };
+    Ok(Duration::from_secs_f64(secs))
+}
+
+fn number<T: std::str::FromStr>(args: &[&str], i: usize, default: Option<T>) -> Result<T, String> {
+    match args.get(i) {
+        Some(arg) => arg.parse().map_err(|_| format!("Invalid number: {}", arg)),
+        None => default.ok_or_else(|| "Missing value".to_string()),
+    }
+}
+
+fn parse_step(command: &str, rest: &str) -> Result<Option<Step>, String> {
+    let args: Vec<&str> = rest.split_whitespace().collect();
+    let step = match command {
+        "every" => Step::Every(parse_duration(args.first().ok_or("Missing interval")?)?),
+        "temperature" => Step::Temperature { celsius: number(&args, 0, None)?, jitter: number(&args, 1, Some(0.0))? },
+        "sound" => Step::Sound { level: number(&args, 0, None)?, jitter: number(&args, 1, Some(0))? },
+        "motion" => {
+            let probability: f64 = number(&args, 0, None)?;
+            if !(0.0..=1.0).contains(&probability) {
+                return Err("Motion probability must be between 0 and 1".to_string());
+            }
+            Step::Motion(probability)
+        }
+        "hold" => Step::Hold(parse_duration(args.first().ok_or("Missing duration")?)?),
+        "fall" => Step::Fall(number(&args, 0, Some(DEFAULT_FALL_SOUND))?),
+        "log" => match rest.split_once(char::is_whitespace) {
+            Some((level, message)) => Step::Log { level: level.to_string(), message: message.trim().to_string() },
+            None => return Err("Expected a level and a message".to_string()),
+        },
+        "firmware" => Step::Firmware(args.first().ok_or("Missing version")?.to_string()),
+        "bed" => match args.first() {
+            Some(&"1") => Step::Bed(true),
+            Some(&"0") => Step::Bed(false),
+            _ => return Err("Expected 1 (occupied) or 0 (left)".to_string()),
+        },
+        "raw" => Step::Raw(rest.to_string()),
+        "silence" => Step::Silence(parse_duration(args.first().ok_or("Missing duration")?)?),
+        _ => return Ok(None),
+    };
+    Ok(Some(step))
+}
+
+/// Parse a scenario script; errors name the line
+pub fn parse(script: &str) -> Result<Scenario, String> {
+    let mut steps = Vec::new();
+    let mut repeat = false;
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if repeat {
+            return Err(format!("Line {}: repeat must be the last command", i + 1));
+        }
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        // Raw lines are sent as they are, `#` included
+        let rest = match command {
+            "raw" => rest,
+            _ => rest.split('#').next().unwrap_or_default(),
+        };
+        if command == "repeat" {
+            repeat = true;
+            continue;
+        }
+        match parse_step(command, rest.trim()) {
+            Ok(Some(step)) => steps.push(step),
+            Ok(None) => return Err(format!("Line {}: unknown command {}", i + 1, command)),
+            Err(e) => return Err(format!("Line {}: {}", i + 1, e)),
+        }
+    }
+    if repeat && !steps.iter().any(|s| matches!(s, Step::Hold(_) | Step::Silence(_))) {
+        return Err("A repeating scenario needs a hold or silence, or it never waits".to_string());
+    }
+    Ok(Scenario { steps, repeat })
+}
@@ -8,12 +8,14 @@ const CONFIG = {
     apiUrl: `${window.location.protocol}//${window.location.host}/api`,
     maxDataPoints: 60,
     reconnectDelay: 3000,
+    versionCheckInterval: 5 * 60 * 1000,
 };
 
 const state = {
     connected: false,
     ws: null,
     data: [],
+    lastSeq: null,
     alertSummary: { falls: 0, inactivity: 0 },
     currentDetailType: null,
     currentDetailRange: 15,
@@ -66,18 +68,41 @@ function handleWebSocketMessage(message) {
         case 'status':
             console.log('Status:', message.message);
             break;
+        case 'replayComplete':
+            console.log(`Replayed ${message.count} readings since ${message.since}`);
+            break;
+        case 'deviceStatus':
+            if (message.connected) {
+                console.log(`Sensor ${message.device} in ${message.room} connected`);
+            } else {
+                console.warn(`Sensor ${message.device} in ${message.room} disconnected: ${message.error}`);
+            }
+            break;
+        case 'doorPassage':
+            console.log(`Door of ${message.room}: ${message.kind} at ${message.closedAt}`);
+            break;
         case 'ping':
             break;
     }
 }
 
 function handleSensorReading(reading) {
+    // Replayed readings may overlap what we already have
+    if (reading.id && state.data.some(d => d.id === reading.id)) {
+        return;
+    }
+    // IDs and timestamps of imported data aren't in arrival order; seq is
+    if (reading.seq > (state.lastSeq || 0)) {
+        state.lastSeq = reading.seq;
+    }
+    
     state.data.push({
+        id: reading.id,
         timestamp: new Date(reading.timestamp),
         temperature: reading.temperature,
         motion: reading.motion,
         soundLevel: reading.soundLevel,
-        alert: reading.alert,
+        alert: primaryAlert(reading.alerts),
     });
     
     if (state.data.length > CONFIG.maxDataPoints) {
@@ -89,17 +114,48 @@ function handleSensorReading(reading) {
     updateLastTime();
     addEventToTable(reading);
     
-    if (reading.alert) {
-        showAlert(reading.alert);
-        if (reading.alert === 'FALL_DETECTED') {
+    // Don't sound alarms for history caught up after the tab slept
+    const alerts = reading.alerts || [];
+    if (alerts.length > 0 && !reading.replay) {
+        showAlert(primaryAlert(alerts));
+        if (alerts.includes('FALL_DETECTED')) {
             state.alertSummary.falls++;
-        } else if (reading.alert === 'INACTIVITY_ALERT') {
+        }
+        if (alerts.includes('INACTIVITY_ALERT')) {
             state.alertSummary.inactivity++;
         }
         updateAlertCounts();
     }
 }
 
+// A reading can raise several alerts; the banner shows the most severe one
+function primaryAlert(alerts) {
+    if (!alerts || alerts.length === 0) return null;
+    if (alerts.includes('FALL_DETECTED')) return 'FALL_DETECTED';
+    return alerts.includes('LOW_SPO2') ? 'LOW_SPO2' : alerts[0];
+}
+
+/**
+ * Browsers throttle background tabs, so ask the server to replay whatever
+ * was missed since the last reading we received once the tab is visible again.
+ */
+function requestReplay() {
+    if (!state.connected || state.data.length === 0) return;
+    
+    if (state.lastSeq) {
+        state.ws.send(JSON.stringify({ type: 'resume', after: state.lastSeq }));
+        return;
+    }
+    const since = state.data[state.data.length - 1].timestamp.toISOString();
+    state.ws.send(JSON.stringify({ type: 'replay', since }));
+}
+
+document.addEventListener('visibilitychange', () => {
+    if (document.visibilityState === 'visible') {
+        requestReplay();
+    }
+});
+
 // ============================================================================
 // UI Updates
 // ============================================================================
@@ -131,6 +187,19 @@ function updateConnectionStatus(status) {
 
 function updateStats(reading) {
     document.getElementById('tempValue').textContent = reading.temperature.toFixed(1);
+    // Only boards with a DHT22, pulse oximeter, door contact, light or CO2 sensor, presence radar or accelerometer send these
+    const extras = [];
+    if (reading.humidity != null) extras.push(`Humidity ${reading.humidity.toFixed(0)}%`);
+    if (reading.heartRate != null) extras.push(`HR ${reading.heartRate}/min`);
+    if (reading.spo2 != null) extras.push(`SpO2 ${reading.spo2}%`);
+    if (reading.doorOpen != null) extras.push(reading.doorOpen ? 'Door open' : 'Door closed');
+    if (reading.light != null) extras.push(`Light ${reading.light.toFixed(0)} lx`);
+    if (reading.co2 != null) extras.push(`CO2 ${reading.co2} ppm`);
+    if (reading.presence != null) extras.push(reading.presence ? 'Present' : 'Nobody seen');
+    if (reading.acceleration != null) extras.push(`Peak ${reading.acceleration.toFixed(1)} g`);
+    if (extras.length > 0) {
+        document.getElementById('tempFooter').textContent = extras.join(' · ');
+    }
     
     const motionValue = document.getElementById('motionValue');
     const motionStatus = document.getElementById('motionStatus');
@@ -311,6 +380,18 @@ function showAlert(alertType) {
     } else if (alertType === 'INACTIVITY_ALERT') {
         message.textContent = '⚠️ Patient inactivity detected - No movement for extended period';
         playInactivityAlert();
+    } else if (alertType === 'LOW_SPO2') {
+        message.textContent = '⚠️ LOW OXYGEN SATURATION - Check patient immediately!';
+        playFallAlert();
+    } else if (alertType === 'TACHYCARDIA') {
+        message.textContent = '⚠️ High heart rate detected';
+        playInactivityAlert();
+    } else if (alertType === 'VENTILATION_NEEDED') {
+        message.textContent = '⚠️ CO2 has been high for a while - Please air the room';
+        playInactivityAlert();
+    } else if (alertType === 'CALIBRATION_NEEDED') {
+        message.textContent = '⚠️ The room\'s temperature sensors disagree - One needs calibrating';
+        playInactivityAlert();
     }
     
     banner.classList.remove('hidden');
@@ -404,6 +485,15 @@ async function saveSettings() {
             setTimeout(() => {
                 closeSettingsModal();
             }, 1500);
+        } else if (response.status === 403) {
+            // Settings the signed-in role may not change, or a read-only
+            // demo; nothing was saved
+            const error = await response.json();
+            statusEl.className = 'settings-status error';
+            statusEl.innerHTML = '<div class="status-icon">✕</div><span></span>';
+            statusEl.querySelector('span').textContent = error.error === 'demo_read_only'
+                ? 'Settings cannot be changed in this demo.'
+                : `You may not change: ${(error.deniedFields || []).join(', ')}`;
         } else {
             throw new Error('Failed to save');
         }
@@ -720,6 +810,15 @@ function renderReport(data, date, startTime, endTime) {
                         <span class="detail-label">Longest Still Period</span>
                         <span class="detail-value">${data.longestStillPeriodMins} min</span>
                     </div>
+                    <div class="detail-row">
+                        <span class="detail-label">Night Awakenings</span>
+                        <span class="detail-value">${data.nightAwakenings ?? 0}</span>
+                    </div>
+                    ${data.nightLight ? `
+                    <div class="detail-row">
+                        <span class="detail-label">Lights On</span>
+                        <span class="detail-value">${formatNightLight(data.nightLight)}</span>
+                    </div>` : ''}
                     <div class="detail-row">
                         <span class="detail-label">Motion Percentage</span>
                         <span class="detail-value">${data.activityScore.toFixed(1)}%</span>
@@ -741,6 +840,14 @@ function renderReport(data, date, startTime, endTime) {
     setTimeout(() => drawActivityGauge(data.activityScore), 100);
 }
 
+function formatNightLight(light) {
+    if (!light.lightsOn) return 'No';
+    const correlation = light.lightMotionCorrelation != null
+        ? `, correlation with motion ${light.lightMotionCorrelation.toFixed(2)}`
+        : '';
+    return `${light.litPercent.toFixed(0)}% of the night${correlation}`;
+}
+
 function formatActivityLevel(level) {
     const labels = {
         'deep_sleep': '😴 Deep Sleep',
@@ -898,7 +1005,8 @@ Average Temperature: ${data.avgTemperature.toFixed(1)}°C
 Average Sound Level: ${data.avgSoundLevel.toFixed(0)}
 Maximum Sound Level: ${data.maxSoundLevel}
 Longest Still Period: ${data.longestStillPeriodMins} minutes
-
+Night Awakenings (likely bathroom trips): ${data.nightAwakenings ?? 0}
+${data.nightLight ? `Lights On: ${formatNightLight(data.nightLight)}\n` : ''}
 ASSESSMENT
 ----------
 Rest Quality: ${getRestQuality(data.activityScore)}
@@ -1110,7 +1218,7 @@ function renderDetailChart(type, minutes, historyData) {
                         reading.motion = comp.valueBoolean;
                     } else if (code === '89020-2') {
                         reading.soundLevel = comp.valueInteger;
-                    } else if (code === 'AA') {
+                    } else if (code === 'AA' && reading.alert !== 'FALL_DETECTED') {
                         reading.alert = comp.valueString;
                     }
                 });
@@ -1303,10 +1411,9 @@ function addEventToTable(reading) {
     const sound = reading.soundLevel ?? '--';
     let alertStatus = 'normal';
     
-    if (reading.alert) {
-        if (reading.alert === 'FALL_DETECTED') alertStatus = 'fall';
-        else if (reading.alert === 'INACTIVITY_ALERT') alertStatus = 'inactivity';
-    }
+    const alert = primaryAlert(reading.alerts);
+    if (alert === 'FALL_DETECTED') alertStatus = 'fall';
+    else if (alert === 'INACTIVITY_ALERT') alertStatus = 'inactivity';
     
     const newRow = document.createElement('tr');
     newRow.innerHTML = `
@@ -1353,7 +1460,7 @@ function updateEventsTable(entries) {
                     sound = comp.valueInteger ?? '--';
                 } else if (code === 'AA' && comp.valueString) {
                     if (comp.valueString === 'FALL_DETECTED') alertStatus = 'fall';
-                    else if (comp.valueString === 'INACTIVITY_ALERT') alertStatus = 'inactivity';
+                    else if (comp.valueString === 'INACTIVITY_ALERT' && alertStatus !== 'fall') alertStatus = 'inactivity';
                 }
             });
         }
@@ -1370,6 +1477,35 @@ function updateEventsTable(entries) {
     }).join('');
 }
 
+// ============================================================================
+// Dashboard Updates
+// ============================================================================
+
+/**
+ * Reload once the server serves a different dashboard, so kiosks that stay
+ * open for weeks pick up updates
+ */
+async function watchVersion() {
+    let loaded = null;
+    const check = async () => {
+        try {
+            const response = await fetch(`${CONFIG.apiUrl}/version`, { cache: 'no-store' });
+            if (!response.ok) return;
+            const { frontend } = await response.json();
+            if (loaded === null) {
+                loaded = frontend;
+            } else if (frontend && frontend !== loaded) {
+                console.log('Dashboard updated, reloading');
+                window.location.reload();
+            }
+        } catch (e) {
+            console.error('Failed to check dashboard version:', e);
+        }
+    };
+    await check();
+    setInterval(check, CONFIG.versionCheckInterval);
+}
+
 // ============================================================================
 // Initialization
 // ============================================================================
@@ -1380,6 +1516,7 @@ function init() {
     connectWebSocket();
     fetchHistory();
     fetchSummary();
+    watchVersion();
     
     window.addEventListener('resize', () => {
         initCharts();
@@ -0,0 +1,11 @@
+-- Registry of the sensor boards feeding readings: which room each one is
+-- assigned to, the firmware it runs and when it last reported.
+CREATE TABLE IF NOT EXISTS devices (
+    device_id VARCHAR(64) PRIMARY KEY,
+    room_id VARCHAR(64) NOT NULL,
+    firmware_version VARCHAR(50),
+    registered_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_devices_room ON devices(room_id);
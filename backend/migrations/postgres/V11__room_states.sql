@@ -0,0 +1,10 @@
+-- Changes of a room's coarse state (in bed, moving, out of room) derived
+-- from its readings and bed sensor; each state lasts until the next change.
+CREATE TABLE IF NOT EXISTS room_states (
+    id BIGSERIAL PRIMARY KEY,
+    room_id VARCHAR(64) NOT NULL,
+    state VARCHAR(20) NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_room_states_room ON room_states(room_id, started_at);
@@ -0,0 +1,5 @@
+-- How staff judged each alert: a confirmed incident, a false alarm, or
+-- couldn't tell. Unset until recorded at acknowledgement or afterwards.
+ALTER TABLE alerts ADD COLUMN IF NOT EXISTS outcome VARCHAR(32);
+
+CREATE INDEX IF NOT EXISTS idx_alerts_triggered_room ON alerts(room_id, triggered_at);
@@ -0,0 +1,5 @@
+-- Compaction merges runs of identical readings into the first of them,
+-- which then stands for `run_count` readings evenly spaced until
+-- `run_until`. Range queries expand such intervals into readings again.
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS run_until TIMESTAMPTZ;
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS run_count INTEGER NOT NULL DEFAULT 1;
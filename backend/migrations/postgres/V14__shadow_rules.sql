@@ -0,0 +1,15 @@
+-- Shadow rules are evaluated against live readings but raise no alerts;
+-- each run of readings they match in a room is recorded for review.
+ALTER TABLE alert_rules ADD COLUMN IF NOT EXISTS shadow BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS shadow_rule_hits (
+    id BIGSERIAL PRIMARY KEY,
+    rule_id BIGINT NOT NULL REFERENCES alert_rules(id) ON DELETE CASCADE,
+    room_id VARCHAR(64) NOT NULL,
+    hit_at TIMESTAMPTZ NOT NULL,
+    temperature REAL NOT NULL,
+    motion BOOLEAN NOT NULL,
+    sound_level INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_shadow_rule_hits_rule_time ON shadow_rule_hits(rule_id, hit_at DESC);
@@ -0,0 +1,10 @@
+-- Feature flags switched through the admin API; a setting for a room
+-- overrides the one for the whole facility (room_id '').
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name VARCHAR(64) NOT NULL,
+    room_id VARCHAR(64) NOT NULL DEFAULT '',
+    enabled BOOLEAN NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_by VARCHAR(100),
+    PRIMARY KEY (name, room_id)
+);
@@ -0,0 +1,12 @@
+-- Reading lines outside the plausible sensor ranges, kept for review
+-- instead of being stored with the readings.
+CREATE TABLE IF NOT EXISTS quarantined_readings (
+    id BIGSERIAL PRIMARY KEY,
+    room_id VARCHAR(64) NOT NULL,
+    device_id VARCHAR(64),
+    received_at TIMESTAMPTZ NOT NULL,
+    line TEXT NOT NULL,
+    reason TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_quarantined_readings_time ON quarantined_readings(received_at DESC);
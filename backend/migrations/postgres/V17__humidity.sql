@@ -0,0 +1,2 @@
+-- Relative humidity from boards with a DHT22; NULL for boards without one
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS humidity REAL;
@@ -0,0 +1,3 @@
+-- Heart rate and SpO2 from boards with a pulse oximeter; NULL for boards without one
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS heart_rate INTEGER;
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS spo2 INTEGER;
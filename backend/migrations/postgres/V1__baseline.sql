@@ -0,0 +1,292 @@
+-- Schema as created before versioned migrations. Every statement is
+-- idempotent, so databases set up by earlier releases (which have no
+-- migrations table yet) are brought up to date and adopted as version 1.
+
+CREATE TABLE IF NOT EXISTS sensor_data (
+    id BIGSERIAL PRIMARY KEY,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    temperature REAL NOT NULL,
+    motion BOOLEAN NOT NULL,
+    sound_level INTEGER NOT NULL,
+    alert_types TEXT[] NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_timestamp ON sensor_data(timestamp DESC);
+
+-- Rooms monitored by this backend; readings from before multi-room
+-- support are assigned to the first configured room by `register_rooms`
+CREATE TABLE IF NOT EXISTS rooms (
+    id VARCHAR(64) PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS room_id VARCHAR(64) REFERENCES rooms(id);
+
+-- Wards group rooms for reports, alert routing and access control
+CREATE TABLE IF NOT EXISTS wards (
+    id VARCHAR(64) PRIMARY KEY,
+    name VARCHAR(100) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE rooms ADD COLUMN IF NOT EXISTS ward_id VARCHAR(64) REFERENCES wards(id);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_room_timestamp ON sensor_data(room_id, timestamp DESC);
+
+-- Patient registry. A room holds at most one patient; new readings are
+-- attributed to the patient in their room by a trigger, so they keep their
+-- patient when the patient later moves or is discharged.
+CREATE TABLE IF NOT EXISTS patients (
+    id VARCHAR(64) PRIMARY KEY,
+    family_name VARCHAR(100) NOT NULL,
+    given_names TEXT[] NOT NULL DEFAULT '{}',
+    birth_date DATE,
+    gender VARCHAR(16),
+    room_id VARCHAR(64) UNIQUE REFERENCES rooms(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS patient_id VARCHAR(64)
+    REFERENCES patients(id) ON DELETE SET NULL;
+
+CREATE OR REPLACE FUNCTION assign_reading_patient() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.patient_id IS NULL THEN
+        NEW.patient_id := (SELECT id FROM patients WHERE room_id = NEW.room_id);
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER sensor_patient_trigger
+    BEFORE INSERT ON sensor_data
+    FOR EACH ROW EXECUTE FUNCTION assign_reading_patient();
+
+-- Change-feed position of each reading, for clients that page through or
+-- resume after readings they have seen. Sequences never hand out a value
+-- twice, even after a crash, and the lock (held until commit) makes
+-- readings visible in seq order, so a feed reader never skips one that
+-- commits late. Existing readings are numbered by timestamp.
+LOCK TABLE sensor_data IN SHARE ROW EXCLUSIVE MODE;
+
+CREATE SEQUENCE IF NOT EXISTS sensor_seq;
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS seq BIGINT;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM sensor_data WHERE seq IS NULL) THEN
+        UPDATE sensor_data s SET seq = n.seq
+        FROM (SELECT id, (SELECT COALESCE(MAX(seq), 0) FROM sensor_data)
+                         + row_number() OVER (ORDER BY timestamp, id) AS seq
+              FROM sensor_data WHERE seq IS NULL) n
+        WHERE s.id = n.id;
+        PERFORM setval('sensor_seq', (SELECT MAX(seq) FROM sensor_data));
+    END IF;
+END
+$$;
+
+ALTER TABLE sensor_data ALTER COLUMN seq SET NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_sensor_seq ON sensor_data(seq);
+
+CREATE OR REPLACE FUNCTION assign_reading_seq() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext('sensor_seq'));
+    NEW.seq := nextval('sensor_seq');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER sensor_seq_trigger
+    BEFORE INSERT ON sensor_data
+    FOR EACH ROW EXECUTE FUNCTION assign_reading_seq();
+
+-- Running totals for the summary endpoint, kept in sync by a trigger so
+-- the dashboard doesn't COUNT(*) the whole table on every refresh.
+-- Older databases stored a single alert_type per reading; it is migrated
+-- to the alert_types array first (counters stay valid).
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+               WHERE table_schema = current_schema()
+                 AND table_name = 'sensor_data' AND column_name = 'alert_type') THEN
+        DROP TRIGGER IF EXISTS sensor_counters_trigger ON sensor_data;
+        ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS alert_types TEXT[] NOT NULL DEFAULT '{}';
+        UPDATE sensor_data SET alert_types = ARRAY[alert_type] WHERE alert_type <> 'none';
+        ALTER TABLE sensor_data DROP COLUMN alert_type;
+        DELETE FROM sensor_counters WHERE counter = 'none';
+    END IF;
+END
+$$;
+
+CREATE TABLE IF NOT EXISTS sensor_counters (
+    counter VARCHAR(32) PRIMARY KEY,
+    value BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE OR REPLACE FUNCTION update_sensor_counters() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE sensor_counters SET value = value - 1
+        WHERE counter = 'total' OR counter = ANY(OLD.alert_types);
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        INSERT INTO sensor_counters (counter, value)
+        SELECT counter, 1 FROM unnest(ARRAY['total'] || NEW.alert_types) AS counter
+        ON CONFLICT (counter) DO UPDATE SET value = sensor_counters.value + 1;
+        RETURN NEW;
+    END IF;
+    RETURN OLD;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER sensor_counters_trigger
+    AFTER INSERT OR DELETE OR UPDATE OF alert_types ON sensor_data
+    FOR EACH ROW EXECUTE FUNCTION update_sensor_counters();
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM sensor_counters WHERE counter = 'total') THEN
+        INSERT INTO sensor_counters (counter, value)
+        SELECT 'total', COUNT(*) FROM sensor_data;
+        INSERT INTO sensor_counters (counter, value)
+        SELECT alert, COUNT(*) FROM sensor_data, unnest(alert_types) AS alert GROUP BY alert
+        ON CONFLICT (counter) DO UPDATE SET value = EXCLUDED.value;
+    END IF;
+END
+$$;
+
+CREATE TABLE IF NOT EXISTS patient_consent (
+    patient_id VARCHAR(64) PRIMARY KEY,
+    continuous_monitoring BOOLEAN NOT NULL DEFAULT TRUE,
+    ehr_sharing BOOLEAN NOT NULL DEFAULT TRUE,
+    research_export BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS alert_rules (
+    id BIGSERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL UNIQUE,
+    expression TEXT NOT NULL,
+    alert_type VARCHAR(32) NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS rule_thresholds (
+    name VARCHAR(50) PRIMARY KEY,
+    value DOUBLE PRECISION NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS device_logs (
+    id BIGSERIAL PRIMARY KEY,
+    device_id VARCHAR(64) NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    level VARCHAR(10) NOT NULL DEFAULT 'info',
+    message TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_logs_device ON device_logs(device_id, received_at DESC);
+
+CREATE TABLE IF NOT EXISTS tags (
+    id BIGSERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS reading_tags (
+    reading_id BIGINT NOT NULL REFERENCES sensor_data(id) ON DELETE CASCADE,
+    tag_id BIGINT NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (tag_id, reading_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_reading_tags_reading ON reading_tags(reading_id);
+
+CREATE TABLE IF NOT EXISTS alert_snoozes (
+    id BIGSERIAL PRIMARY KEY,
+    reading_id BIGINT NOT NULL,
+    alert_type VARCHAR(32) NOT NULL,
+    snoozed_until TIMESTAMPTZ NOT NULL,
+    snoozed_by VARCHAR(100),
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    action VARCHAR(50) NOT NULL,
+    subject VARCHAR(100) NOT NULL,
+    actor VARCHAR(100),
+    request_id VARCHAR(128),
+    detail TEXT
+);
+
+CREATE TABLE IF NOT EXISTS alert_acks (
+    reading_id BIGINT PRIMARY KEY REFERENCES sensor_data(id) ON DELETE CASCADE,
+    acked_by VARCHAR(100),
+    via VARCHAR(20) NOT NULL,
+    acked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- One row per alert episode, from the first reading that raised the alert
+-- until the first one that didn't. A room has at most one open alert of
+-- each type.
+CREATE TABLE IF NOT EXISTS alerts (
+    id BIGSERIAL PRIMARY KEY,
+    room_id VARCHAR(64) NOT NULL,
+    alert_type VARCHAR(32) NOT NULL,
+    severity VARCHAR(16) NOT NULL,
+    reading_id BIGINT REFERENCES sensor_data(id) ON DELETE SET NULL,
+    triggered_at TIMESTAMPTZ NOT NULL,
+    resolved_at TIMESTAMPTZ,
+    acknowledged_by VARCHAR(100),
+    acknowledged_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_alerts_open
+    ON alerts(room_id, alert_type) WHERE resolved_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_alerts_triggered_at ON alerts(triggered_at DESC);
+
+-- Sensor-fault alert types (`sensor_fault:<channel>`) don't fit the 20
+-- characters older databases allowed
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+               WHERE table_schema = current_schema() AND table_name = 'alerts'
+                 AND column_name = 'alert_type' AND character_maximum_length < 32) THEN
+        ALTER TABLE sensor_counters ALTER COLUMN counter TYPE VARCHAR(32);
+        ALTER TABLE alert_rules ALTER COLUMN alert_type TYPE VARCHAR(32);
+        ALTER TABLE alert_snoozes ALTER COLUMN alert_type TYPE VARCHAR(32);
+        ALTER TABLE alerts ALTER COLUMN alert_type TYPE VARCHAR(32);
+    END IF;
+END
+$$;
+
+CREATE TABLE IF NOT EXISTS phi_access_log (
+    id BIGSERIAL PRIMARY KEY,
+    accessed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    principal VARCHAR(100),
+    patient_id VARCHAR(64) NOT NULL,
+    endpoint VARCHAR(200) NOT NULL,
+    range_start TIMESTAMPTZ,
+    range_end TIMESTAMPTZ,
+    row_count BIGINT NOT NULL,
+    request_id VARCHAR(128)
+);
+
+CREATE INDEX IF NOT EXISTS idx_phi_access_log_at ON phi_access_log(accessed_at DESC);
+
+CREATE TABLE IF NOT EXISTS fall_risk_scores (
+    patient_id VARCHAR(64) NOT NULL,
+    day DATE NOT NULL,
+    score DOUBLE PRECISION NOT NULL,
+    level VARCHAR(16) NOT NULL,
+    window_days INTEGER NOT NULL,
+    falls BIGINT NOT NULL,
+    night_restlessness DOUBLE PRECISION NOT NULL,
+    bed_exits_per_night DOUBLE PRECISION NOT NULL,
+    computed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (patient_id, day)
+);
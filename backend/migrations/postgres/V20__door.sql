@@ -0,0 +1,14 @@
+-- Door contact from boards that have one; NULL for boards without one
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS door_open BOOLEAN;
+
+-- Someone going through a room's door: entry, exit or passage, told from the
+-- motion in the room around the door opening and closing
+CREATE TABLE IF NOT EXISTS door_passages (
+    id BIGSERIAL PRIMARY KEY,
+    room_id VARCHAR(64) NOT NULL,
+    kind VARCHAR(10) NOT NULL,
+    opened_at TIMESTAMPTZ NOT NULL,
+    closed_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_door_passages_room ON door_passages(room_id, opened_at);
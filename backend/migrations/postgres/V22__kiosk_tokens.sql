@@ -0,0 +1,16 @@
+-- Read-only tokens letting a family kiosk or TV show one room's coarse
+-- status. Only a hash of each token is stored; tokens expire and can be
+-- revoked.
+CREATE TABLE IF NOT EXISTS kiosk_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    room_id VARCHAR(64) NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    label VARCHAR(100),
+    created_by VARCHAR(100) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    revoked_by VARCHAR(100)
+);
+
+CREATE INDEX IF NOT EXISTS idx_kiosk_tokens_room ON kiosk_tokens(room_id, expires_at);
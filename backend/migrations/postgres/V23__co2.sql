@@ -0,0 +1,2 @@
+-- Carbon dioxide in ppm from boards with an air-quality sensor; NULL for boards without one
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS co2 INTEGER;
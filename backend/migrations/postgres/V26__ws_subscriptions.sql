@@ -0,0 +1,9 @@
+-- Live feed each user picked for /ws, so it follows them to any device
+-- they log in on
+CREATE TABLE IF NOT EXISTS ws_subscriptions (
+    principal VARCHAR(100) PRIMARY KEY,
+    rooms TEXT[] NOT NULL DEFAULT '{}',
+    severities TEXT[] NOT NULL DEFAULT '{}',
+    message_types TEXT[] NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
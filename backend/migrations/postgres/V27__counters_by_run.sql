@@ -0,0 +1,21 @@
+-- A compacted interval stands for `run_count` readings, so it counts that
+-- many towards the running totals when inserted, deleted or soft-deleted
+CREATE OR REPLACE FUNCTION update_sensor_counters() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        IF OLD.deleted_at IS NULL THEN
+            UPDATE sensor_counters SET value = value - OLD.run_count
+            WHERE counter = 'total' OR counter = ANY(OLD.alert_types);
+        END IF;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        IF NEW.deleted_at IS NULL THEN
+            INSERT INTO sensor_counters (counter, value)
+            SELECT counter, NEW.run_count FROM unnest(ARRAY['total'] || NEW.alert_types) AS counter
+            ON CONFLICT (counter) DO UPDATE SET value = sensor_counters.value + NEW.run_count;
+        END IF;
+        RETURN NEW;
+    END IF;
+    RETURN OLD;
+END
+$$ LANGUAGE plpgsql;
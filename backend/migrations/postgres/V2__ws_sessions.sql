@@ -0,0 +1,17 @@
+-- Statistics of closed WebSocket connections, stored when
+-- WS_STATS_PERSIST_SECS is set
+CREATE TABLE IF NOT EXISTS ws_sessions (
+    id VARCHAR(36) PRIMARY KEY,
+    channel VARCHAR(16) NOT NULL,
+    ip VARCHAR(64),
+    principal VARCHAR(100),
+    user_agent TEXT,
+    connected_at TIMESTAMPTZ NOT NULL,
+    disconnected_at TIMESTAMPTZ NOT NULL,
+    messages_sent BIGINT NOT NULL,
+    lag_events BIGINT NOT NULL,
+    missed_messages BIGINT NOT NULL,
+    close_reason VARCHAR(16) NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_ws_sessions_disconnected ON ws_sessions(disconnected_at);
@@ -0,0 +1,20 @@
+-- Per-room aggregates of each day's readings (UTC), written by the nightly
+-- rollup so trend queries over months don't scan the readings. Summaries
+-- are kept when the readings are purged.
+CREATE TABLE IF NOT EXISTS daily_summary (
+    room_id VARCHAR(64) NOT NULL,
+    day DATE NOT NULL,
+    readings BIGINT NOT NULL,
+    motion_readings BIGINT NOT NULL,
+    avg_temperature DOUBLE PRECISION NOT NULL,
+    min_temperature REAL NOT NULL,
+    max_temperature REAL NOT NULL,
+    fall_alerts BIGINT NOT NULL,
+    inactivity_alerts BIGINT NOT NULL,
+    sensor_fault_alerts BIGINT NOT NULL,
+    longest_still_period_mins BIGINT NOT NULL,
+    computed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (room_id, day)
+);
+
+CREATE INDEX IF NOT EXISTS idx_daily_summary_day ON daily_summary(day);
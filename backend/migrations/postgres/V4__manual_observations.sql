@@ -0,0 +1,16 @@
+-- Vital signs and scores recorded by staff, e.g. spot-check temperatures.
+-- Like readings they keep the patient who was in the room when recorded.
+CREATE TABLE IF NOT EXISTS manual_observations (
+    id BIGSERIAL PRIMARY KEY,
+    room_id VARCHAR(64) NOT NULL REFERENCES rooms(id),
+    patient_id VARCHAR(64) REFERENCES patients(id) ON DELETE SET NULL,
+    kind VARCHAR(32) NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    observed_at TIMESTAMPTZ NOT NULL,
+    recorded_by VARCHAR(100) NOT NULL,
+    note TEXT,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_manual_observations_room_time
+    ON manual_observations(room_id, observed_at DESC);
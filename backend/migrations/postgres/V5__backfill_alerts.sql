@@ -0,0 +1,58 @@
+-- Readings stored before alerts were tracked as episodes only carry their
+-- alert types, so reports counting alerts came up short for those days.
+-- Rebuild the episodes from them: consecutive readings of a room raising
+-- the same alert type form one alert, resolved by the first reading that
+-- didn't (or at its last reading, if none followed). Only readings older
+-- than the room's first tracked alert are used, so no episode is counted
+-- twice. An acknowledgement of any reading of the episode acknowledges it.
+CREATE TEMP TABLE backfilled_alerts ON COMMIT DROP AS
+WITH tracked AS (
+    SELECT room_id, MIN(triggered_at) AS since FROM alerts GROUP BY room_id
+),
+history AS (
+    SELECT s.id, s.room_id, s.timestamp, s.alert_types, t.since,
+           ROW_NUMBER() OVER w AS seq,
+           LEAD(s.timestamp) OVER w AS next_at
+    FROM sensor_data s
+    LEFT JOIN tracked t ON t.room_id = s.room_id
+    WHERE s.room_id IS NOT NULL
+    WINDOW w AS (PARTITION BY s.room_id ORDER BY s.timestamp, s.id)
+),
+raised AS (
+    SELECT h.id, h.room_id, h.timestamp, h.next_at, h.seq, a.alert_type,
+           h.seq - ROW_NUMBER() OVER (PARTITION BY h.room_id, a.alert_type ORDER BY h.seq) AS episode
+    FROM history h, unnest(h.alert_types) AS a(alert_type)
+    WHERE h.since IS NULL OR h.timestamp < h.since
+)
+SELECT r.room_id, r.alert_type,
+       CASE WHEN r.alert_type = 'fall' THEN 'critical' ELSE 'warning' END AS severity,
+       (array_agg(r.id ORDER BY r.seq))[1] AS reading_id,
+       MIN(r.timestamp) AS triggered_at,
+       (array_agg(COALESCE(r.next_at, r.timestamp) ORDER BY r.seq DESC))[1] AS resolved_at,
+       (array_agg(k.acked_by ORDER BY k.acked_at) FILTER (WHERE k.acked_at IS NOT NULL))[1] AS acknowledged_by,
+       MIN(k.acked_at) AS acknowledged_at
+FROM raised r
+LEFT JOIN alert_acks k ON k.reading_id = r.id
+GROUP BY r.room_id, r.alert_type, r.episode;
+
+INSERT INTO alerts (room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+                    acknowledged_by, acknowledged_at)
+SELECT room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+       acknowledged_by, acknowledged_at
+FROM backfilled_alerts
+ORDER BY triggered_at;
+
+-- Days already rolled up counted none of these episodes
+UPDATE daily_summary d
+SET fall_alerts = d.fall_alerts + b.falls,
+    inactivity_alerts = d.inactivity_alerts + b.inactivity,
+    sensor_fault_alerts = d.sensor_fault_alerts + b.sensor_faults
+FROM (
+    SELECT room_id, (triggered_at AT TIME ZONE 'UTC')::date AS day,
+           COUNT(*) FILTER (WHERE alert_type = 'fall') AS falls,
+           COUNT(*) FILTER (WHERE alert_type = 'inactivity') AS inactivity,
+           COUNT(*) FILTER (WHERE alert_type LIKE 'sensor_fault:%') AS sensor_faults
+    FROM backfilled_alerts
+    GROUP BY 1, 2
+) b
+WHERE d.room_id = b.room_id AND d.day = b.day;
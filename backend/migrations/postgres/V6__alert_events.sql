@@ -0,0 +1,12 @@
+-- Alert state transitions sent to webhooks. The sequence numbers them in
+-- delivery order across restarts, so receivers can spot gaps and reordering.
+CREATE TABLE IF NOT EXISTS alert_events (
+    seq BIGSERIAL PRIMARY KEY,
+    event VARCHAR(16) NOT NULL,
+    room_id VARCHAR(64) NOT NULL,
+    alert_type VARCHAR(32) NOT NULL,
+    alert_id BIGINT,
+    occurred_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_alert_events_occurred ON alert_events(occurred_at);
@@ -0,0 +1,28 @@
+-- Readings staff removed, e.g. from a known sensor malfunction, are kept
+-- but marked deleted and left out of every query. They stop counting
+-- towards the running totals when deleted.
+ALTER TABLE sensor_data ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+CREATE OR REPLACE FUNCTION update_sensor_counters() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        IF OLD.deleted_at IS NULL THEN
+            UPDATE sensor_counters SET value = value - 1
+            WHERE counter = 'total' OR counter = ANY(OLD.alert_types);
+        END IF;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        IF NEW.deleted_at IS NULL THEN
+            INSERT INTO sensor_counters (counter, value)
+            SELECT counter, 1 FROM unnest(ARRAY['total'] || NEW.alert_types) AS counter
+            ON CONFLICT (counter) DO UPDATE SET value = sensor_counters.value + 1;
+        END IF;
+        RETURN NEW;
+    END IF;
+    RETURN OLD;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER sensor_counters_trigger
+    AFTER INSERT OR DELETE OR UPDATE OF alert_types, deleted_at ON sensor_data
+    FOR EACH ROW EXECUTE FUNCTION update_sensor_counters();
@@ -0,0 +1,6 @@
+-- Why each alert resolved: its condition cleared, motion resumed, or staff
+-- acknowledged it. Alerts resolved before the reason was recorded all
+-- resolved because a reading no longer raised them.
+ALTER TABLE alerts ADD COLUMN IF NOT EXISTS resolution_reason VARCHAR(32);
+
+UPDATE alerts SET resolution_reason = 'cleared' WHERE resolved_at IS NOT NULL AND resolution_reason IS NULL;
@@ -0,0 +1,14 @@
+-- Every change of the alert thresholds made through the API, with the
+-- values before and after and who made it, so alerting can be traced back.
+CREATE TABLE IF NOT EXISTS settings_history (
+    id BIGSERIAL PRIMARY KEY,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    changed_by VARCHAR(100),
+    request_id VARCHAR(128),
+    old_inactivity_seconds BIGINT NOT NULL,
+    new_inactivity_seconds BIGINT NOT NULL,
+    old_sound_threshold INTEGER NOT NULL,
+    new_sound_threshold INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_settings_history_changed ON settings_history(changed_at);
@@ -0,0 +1,11 @@
+-- Registry of the sensor boards feeding readings: which room each one is
+-- assigned to, the firmware it runs and when it last reported.
+CREATE TABLE IF NOT EXISTS devices (
+    device_id TEXT PRIMARY KEY,
+    room_id TEXT NOT NULL,
+    firmware_version TEXT,
+    registered_at TEXT NOT NULL,
+    last_seen TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_devices_room ON devices(room_id);
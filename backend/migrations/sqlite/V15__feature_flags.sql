@@ -0,0 +1,10 @@
+-- Feature flags switched through the admin API; a setting for a room
+-- overrides the one for the whole facility (room_id '').
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name TEXT NOT NULL,
+    room_id TEXT NOT NULL DEFAULT '',
+    enabled INTEGER NOT NULL,
+    updated_at TEXT NOT NULL,
+    updated_by TEXT,
+    PRIMARY KEY (name, room_id)
+);
@@ -0,0 +1,15 @@
+-- Runs of background jobs (rollup, retention, compaction) with their last
+-- checkpoint, from which a run that didn't complete is resumed.
+-- status is running, completed, failed or interrupted.
+CREATE TABLE IF NOT EXISTS job_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    job TEXT NOT NULL,
+    started_at TEXT NOT NULL,
+    finished_at TEXT,
+    status TEXT NOT NULL DEFAULT 'running',
+    checkpoint TEXT,
+    chunks INTEGER NOT NULL DEFAULT 0,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_runs_job ON job_runs(job, id DESC);
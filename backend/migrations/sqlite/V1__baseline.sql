@@ -0,0 +1,181 @@
+-- Schema as created before versioned migrations. Every statement is
+-- idempotent, so databases set up by earlier releases (which have no
+-- migrations table yet) are adopted as version 1.
+
+CREATE TABLE IF NOT EXISTS wards (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS rooms (
+    id TEXT PRIMARY KEY,
+    created_at TEXT NOT NULL,
+    ward_id TEXT REFERENCES wards(id)
+);
+
+CREATE TABLE IF NOT EXISTS patients (
+    id TEXT PRIMARY KEY,
+    family_name TEXT NOT NULL,
+    given_names TEXT NOT NULL DEFAULT '[]',
+    birth_date TEXT,
+    gender TEXT,
+    room_id TEXT UNIQUE REFERENCES rooms(id),
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+-- AUTOINCREMENT never reuses the id of a purged reading, so the id can
+-- serve as the change-feed position
+CREATE TABLE IF NOT EXISTS sensor_data (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp TEXT NOT NULL,
+    temperature REAL NOT NULL,
+    motion INTEGER NOT NULL,
+    sound_level INTEGER NOT NULL,
+    alert_types TEXT NOT NULL DEFAULT '',
+    room_id TEXT REFERENCES rooms(id),
+    patient_id TEXT REFERENCES patients(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_timestamp ON sensor_data(timestamp DESC);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_room_timestamp ON sensor_data(room_id, timestamp DESC);
+
+-- Running totals for the summary endpoint
+CREATE TABLE IF NOT EXISTS sensor_counters (
+    counter TEXT PRIMARY KEY,
+    value INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO sensor_counters (counter) VALUES ('total'), ('fall'), ('inactivity')
+    ON CONFLICT (counter) DO NOTHING;
+
+CREATE TRIGGER IF NOT EXISTS sensor_counters_insert AFTER INSERT ON sensor_data
+BEGIN
+    UPDATE sensor_counters SET value = value + 1
+    WHERE counter = 'total' OR instr(',' || NEW.alert_types || ',', ',' || counter || ',') > 0;
+END;
+
+CREATE TRIGGER IF NOT EXISTS sensor_counters_delete AFTER DELETE ON sensor_data
+BEGIN
+    UPDATE sensor_counters SET value = value - 1
+    WHERE counter = 'total' OR instr(',' || OLD.alert_types || ',', ',' || counter || ',') > 0;
+END;
+
+CREATE TABLE IF NOT EXISTS patient_consent (
+    patient_id TEXT PRIMARY KEY,
+    continuous_monitoring INTEGER NOT NULL DEFAULT 1,
+    ehr_sharing INTEGER NOT NULL DEFAULT 1,
+    research_export INTEGER NOT NULL DEFAULT 0,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS alert_rules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    expression TEXT NOT NULL,
+    alert_type TEXT NOT NULL,
+    enabled INTEGER NOT NULL DEFAULT 1,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS rule_thresholds (
+    name TEXT PRIMARY KEY,
+    value REAL NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS device_logs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id TEXT NOT NULL,
+    received_at TEXT NOT NULL,
+    level TEXT NOT NULL DEFAULT 'info',
+    message TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_logs_device ON device_logs(device_id, received_at DESC);
+
+CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS reading_tags (
+    reading_id INTEGER NOT NULL REFERENCES sensor_data(id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (tag_id, reading_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_reading_tags_reading ON reading_tags(reading_id);
+
+CREATE TABLE IF NOT EXISTS alert_snoozes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    reading_id INTEGER NOT NULL,
+    alert_type TEXT NOT NULL,
+    snoozed_until TEXT NOT NULL,
+    snoozed_by TEXT,
+    reason TEXT,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at TEXT NOT NULL,
+    action TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    actor TEXT,
+    request_id TEXT,
+    detail TEXT
+);
+
+CREATE TABLE IF NOT EXISTS alert_acks (
+    reading_id INTEGER PRIMARY KEY REFERENCES sensor_data(id) ON DELETE CASCADE,
+    acked_by TEXT,
+    via TEXT NOT NULL,
+    acked_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS alerts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    room_id TEXT NOT NULL,
+    alert_type TEXT NOT NULL,
+    severity TEXT NOT NULL,
+    reading_id INTEGER REFERENCES sensor_data(id) ON DELETE SET NULL,
+    triggered_at TEXT NOT NULL,
+    resolved_at TEXT,
+    acknowledged_by TEXT,
+    acknowledged_at TEXT
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_alerts_open
+    ON alerts(room_id, alert_type) WHERE resolved_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_alerts_triggered_at ON alerts(triggered_at DESC);
+
+CREATE TABLE IF NOT EXISTS phi_access_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    accessed_at TEXT NOT NULL,
+    principal TEXT,
+    patient_id TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    range_start TEXT,
+    range_end TEXT,
+    row_count INTEGER NOT NULL,
+    request_id TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_phi_access_log_at ON phi_access_log(accessed_at DESC);
+
+CREATE TABLE IF NOT EXISTS fall_risk_scores (
+    patient_id TEXT NOT NULL,
+    day TEXT NOT NULL,
+    score REAL NOT NULL,
+    level TEXT NOT NULL,
+    window_days INTEGER NOT NULL,
+    falls INTEGER NOT NULL,
+    night_restlessness REAL NOT NULL,
+    bed_exits_per_night REAL NOT NULL,
+    computed_at TEXT NOT NULL,
+    PRIMARY KEY (patient_id, day)
+);
@@ -0,0 +1,2 @@
+-- Ambient light in lux from boards with a light sensor; NULL for boards without one
+ALTER TABLE sensor_data ADD COLUMN light REAL;
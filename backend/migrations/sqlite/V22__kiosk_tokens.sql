@@ -0,0 +1,16 @@
+-- Read-only tokens letting a family kiosk or TV show one room's coarse
+-- status. Only a hash of each token is stored; tokens expire and can be
+-- revoked.
+CREATE TABLE IF NOT EXISTS kiosk_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    room_id TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    label TEXT,
+    created_by TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    revoked_at TEXT,
+    revoked_by TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_kiosk_tokens_room ON kiosk_tokens(room_id, expires_at);
@@ -0,0 +1,2 @@
+-- Whether a presence radar sees someone, from boards with one; NULL for boards without one
+ALTER TABLE sensor_data ADD COLUMN presence INTEGER;
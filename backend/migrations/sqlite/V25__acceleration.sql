@@ -0,0 +1,2 @@
+-- Peak acceleration in g from boards with an accelerometer; NULL for boards without one
+ALTER TABLE sensor_data ADD COLUMN acceleration REAL;
@@ -0,0 +1,9 @@
+-- Live feed each user picked for /ws, as JSON arrays of rooms, severities
+-- and message types, so it follows them to any device they log in on
+CREATE TABLE IF NOT EXISTS ws_subscriptions (
+    principal TEXT PRIMARY KEY,
+    rooms TEXT NOT NULL,
+    severities TEXT NOT NULL,
+    message_types TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
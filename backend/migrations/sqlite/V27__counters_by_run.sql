@@ -0,0 +1,25 @@
+-- A compacted interval stands for `run_count` readings, so it counts that
+-- many towards the running totals when inserted, deleted or soft-deleted
+DROP TRIGGER IF EXISTS sensor_counters_insert;
+DROP TRIGGER IF EXISTS sensor_counters_delete;
+DROP TRIGGER IF EXISTS sensor_counters_soft_delete;
+
+CREATE TRIGGER sensor_counters_insert AFTER INSERT ON sensor_data
+BEGIN
+    UPDATE sensor_counters SET value = value + NEW.run_count
+    WHERE counter = 'total' OR instr(',' || NEW.alert_types || ',', ',' || counter || ',') > 0;
+END;
+
+CREATE TRIGGER sensor_counters_delete AFTER DELETE ON sensor_data
+WHEN OLD.deleted_at IS NULL
+BEGIN
+    UPDATE sensor_counters SET value = value - OLD.run_count
+    WHERE counter = 'total' OR instr(',' || OLD.alert_types || ',', ',' || counter || ',') > 0;
+END;
+
+CREATE TRIGGER sensor_counters_soft_delete AFTER UPDATE OF deleted_at ON sensor_data
+WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL
+BEGIN
+    UPDATE sensor_counters SET value = value - OLD.run_count
+    WHERE counter = 'total' OR instr(',' || OLD.alert_types || ',', ',' || counter || ',') > 0;
+END;
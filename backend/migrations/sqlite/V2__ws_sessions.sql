@@ -0,0 +1,17 @@
+-- Statistics of closed WebSocket connections, stored when
+-- WS_STATS_PERSIST_SECS is set
+CREATE TABLE IF NOT EXISTS ws_sessions (
+    id TEXT PRIMARY KEY,
+    channel TEXT NOT NULL,
+    ip TEXT,
+    principal TEXT,
+    user_agent TEXT,
+    connected_at TEXT NOT NULL,
+    disconnected_at TEXT NOT NULL,
+    messages_sent INTEGER NOT NULL,
+    lag_events INTEGER NOT NULL,
+    missed_messages INTEGER NOT NULL,
+    close_reason TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_ws_sessions_disconnected ON ws_sessions(disconnected_at);
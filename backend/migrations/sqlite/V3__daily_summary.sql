@@ -0,0 +1,20 @@
+-- Per-room aggregates of each day's readings (UTC), written by the nightly
+-- rollup so trend queries over months don't scan the readings. Summaries
+-- are kept when the readings are purged.
+CREATE TABLE IF NOT EXISTS daily_summary (
+    room_id TEXT NOT NULL,
+    day TEXT NOT NULL,
+    readings INTEGER NOT NULL,
+    motion_readings INTEGER NOT NULL,
+    avg_temperature REAL NOT NULL,
+    min_temperature REAL NOT NULL,
+    max_temperature REAL NOT NULL,
+    fall_alerts INTEGER NOT NULL,
+    inactivity_alerts INTEGER NOT NULL,
+    sensor_fault_alerts INTEGER NOT NULL,
+    longest_still_period_mins INTEGER NOT NULL,
+    computed_at TEXT NOT NULL,
+    PRIMARY KEY (room_id, day)
+);
+
+CREATE INDEX IF NOT EXISTS idx_daily_summary_day ON daily_summary(day);
@@ -0,0 +1,16 @@
+-- Vital signs and scores recorded by staff, e.g. spot-check temperatures.
+-- Like readings they keep the patient who was in the room when recorded.
+CREATE TABLE IF NOT EXISTS manual_observations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    room_id TEXT NOT NULL REFERENCES rooms(id),
+    patient_id TEXT REFERENCES patients(id) ON DELETE SET NULL,
+    kind TEXT NOT NULL,
+    value REAL NOT NULL,
+    observed_at TEXT NOT NULL,
+    recorded_by TEXT NOT NULL,
+    note TEXT,
+    recorded_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_manual_observations_room_time
+    ON manual_observations(room_id, observed_at DESC);
@@ -0,0 +1,73 @@
+-- Readings stored before alerts were tracked as episodes only carry their
+-- alert types, so reports counting alerts came up short for those days.
+-- Rebuild the episodes from them: consecutive readings of a room raising
+-- the same alert type form one alert, resolved by the first reading that
+-- didn't (or at its last reading, if none followed). Only readings older
+-- than the room's first tracked alert are used, so no episode is counted
+-- twice. An acknowledgement of any reading of the episode acknowledges it.
+CREATE TEMP TABLE backfilled_alerts AS
+WITH tracked AS (
+    SELECT room_id, MIN(triggered_at) AS since FROM alerts GROUP BY room_id
+),
+history AS (
+    SELECT s.id, s.room_id, s.timestamp, s.alert_types, t.since,
+           ROW_NUMBER() OVER w AS seq,
+           LEAD(s.timestamp) OVER w AS next_at
+    FROM sensor_data s
+    LEFT JOIN tracked t ON t.room_id = s.room_id
+    WHERE s.room_id IS NOT NULL
+    WINDOW w AS (PARTITION BY s.room_id ORDER BY s.timestamp, s.id)
+),
+raised AS (
+    SELECT h.id, h.room_id, h.timestamp, h.next_at, h.seq, a.value AS alert_type,
+           h.seq - ROW_NUMBER() OVER (PARTITION BY h.room_id, a.value ORDER BY h.seq) AS episode
+    FROM history h, json_each('["' || replace(h.alert_types, ',', '","') || '"]') a
+    WHERE h.alert_types <> '' AND (h.since IS NULL OR h.timestamp < h.since)
+),
+episodes AS (
+    SELECT room_id, alert_type, episode, MIN(seq) AS first_seq, MAX(seq) AS last_seq
+    FROM raised
+    GROUP BY room_id, alert_type, episode
+),
+-- acked_by comes from the row with the earliest acked_at
+acks AS (
+    SELECT r.room_id, r.alert_type, r.episode, k.acked_by, MIN(k.acked_at) AS acked_at
+    FROM raised r
+    JOIN alert_acks k ON k.reading_id = r.id
+    GROUP BY r.room_id, r.alert_type, r.episode
+)
+SELECT e.room_id, e.alert_type,
+       CASE WHEN e.alert_type = 'fall' THEN 'critical' ELSE 'warning' END AS severity,
+       f.id AS reading_id,
+       f.timestamp AS triggered_at,
+       COALESCE(l.next_at, l.timestamp) AS resolved_at,
+       k.acked_by AS acknowledged_by,
+       k.acked_at AS acknowledged_at
+FROM episodes e
+JOIN raised f ON f.room_id = e.room_id AND f.alert_type = e.alert_type AND f.seq = e.first_seq
+JOIN raised l ON l.room_id = e.room_id AND l.alert_type = e.alert_type AND l.seq = e.last_seq
+LEFT JOIN acks k ON k.room_id = e.room_id AND k.alert_type = e.alert_type AND k.episode = e.episode;
+
+INSERT INTO alerts (room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+                    acknowledged_by, acknowledged_at)
+SELECT room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+       acknowledged_by, acknowledged_at
+FROM backfilled_alerts
+ORDER BY triggered_at;
+
+-- Days already rolled up counted none of these episodes
+UPDATE daily_summary
+SET fall_alerts = fall_alerts + b.falls,
+    inactivity_alerts = inactivity_alerts + b.inactivity,
+    sensor_fault_alerts = sensor_fault_alerts + b.sensor_faults
+FROM (
+    SELECT room_id, substr(triggered_at, 1, 10) AS day,
+           SUM(alert_type = 'fall') AS falls,
+           SUM(alert_type = 'inactivity') AS inactivity,
+           SUM(alert_type LIKE 'sensor_fault:%') AS sensor_faults
+    FROM backfilled_alerts
+    GROUP BY 1, 2
+) b
+WHERE daily_summary.room_id = b.room_id AND daily_summary.day = b.day;
+
+DROP TABLE backfilled_alerts;
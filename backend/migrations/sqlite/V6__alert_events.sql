@@ -0,0 +1,13 @@
+-- Alert state transitions sent to webhooks. The sequence numbers them in
+-- delivery order across restarts, so receivers can spot gaps and reordering;
+-- AUTOINCREMENT never reuses the number of a purged event.
+CREATE TABLE IF NOT EXISTS alert_events (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    event TEXT NOT NULL,
+    room_id TEXT NOT NULL,
+    alert_type TEXT NOT NULL,
+    alert_id INTEGER,
+    occurred_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_alert_events_occurred ON alert_events(occurred_at);
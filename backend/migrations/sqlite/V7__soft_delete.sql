@@ -0,0 +1,20 @@
+-- Readings staff removed, e.g. from a known sensor malfunction, are kept
+-- but marked deleted and left out of every query. They stop counting
+-- towards the running totals when deleted, and not again when purged.
+ALTER TABLE sensor_data ADD COLUMN deleted_at TEXT;
+
+DROP TRIGGER IF EXISTS sensor_counters_delete;
+
+CREATE TRIGGER sensor_counters_delete AFTER DELETE ON sensor_data
+WHEN OLD.deleted_at IS NULL
+BEGIN
+    UPDATE sensor_counters SET value = value - 1
+    WHERE counter = 'total' OR instr(',' || OLD.alert_types || ',', ',' || counter || ',') > 0;
+END;
+
+CREATE TRIGGER sensor_counters_soft_delete AFTER UPDATE OF deleted_at ON sensor_data
+WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL
+BEGIN
+    UPDATE sensor_counters SET value = value - 1
+    WHERE counter = 'total' OR instr(',' || OLD.alert_types || ',', ',' || counter || ',') > 0;
+END;
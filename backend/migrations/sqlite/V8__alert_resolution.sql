@@ -0,0 +1,6 @@
+-- Why each alert resolved: its condition cleared, motion resumed, or staff
+-- acknowledged it. Alerts resolved before the reason was recorded all
+-- resolved because a reading no longer raised them.
+ALTER TABLE alerts ADD COLUMN resolution_reason TEXT;
+
+UPDATE alerts SET resolution_reason = 'cleared' WHERE resolved_at IS NOT NULL;
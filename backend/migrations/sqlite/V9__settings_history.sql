@@ -0,0 +1,14 @@
+-- Every change of the alert thresholds made through the API, with the
+-- values before and after and who made it, so alerting can be traced back.
+CREATE TABLE IF NOT EXISTS settings_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    changed_at TEXT NOT NULL,
+    changed_by TEXT,
+    request_id TEXT,
+    old_inactivity_seconds INTEGER NOT NULL,
+    new_inactivity_seconds INTEGER NOT NULL,
+    old_sound_threshold INTEGER NOT NULL,
+    new_sound_threshold INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_settings_history_changed ON settings_history(changed_at);
@@ -0,0 +1,184 @@
+//! PHI access logging
+//!
+//! Every request that returns a patient's readings is recorded in the
+//! `phi_access_log` table: who (the authenticated principal), which endpoint,
+//! the time range and number of readings returned, and the request ID. The
+//! privacy office exports the log through `GET /api/access-log`.
+//!
+//! Authentication happens at the API gateway, which passes the principal in
+//! the `X-Authenticated-User` header. Requests that bypass the gateway are
+//! logged without a principal. A gateway that limits staff to some wards
+//! passes them, comma-separated, in `X-Authenticated-Wards`; readings from
+//! rooms outside those wards are not returned. Roles granting access to
+//! endpoints beyond the care team's, such as `research`, are passed the same
+//! way in `X-Authenticated-Roles`.
+
+use actix_web::http::header::HeaderName;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::future::{ready, Ready};
+use tracing::error;
+
+use crate::db::{Database, PhiAccess, PhiAccessRecord};
+use crate::fhir::{ManualObservation, SensorEvent};
+use crate::request_id::RequestId;
+
+pub const PRINCIPAL_HEADER: HeaderName = HeaderName::from_static("x-authenticated-user");
+pub const WARDS_HEADER: HeaderName = HeaderName::from_static("x-authenticated-wards");
+pub const ROLES_HEADER: HeaderName = HeaderName::from_static("x-authenticated-roles");
+
+/// Longest principal that is stored; longer values are cut off
+const MAX_PRINCIPAL_LEN: usize = 100;
+
+/// Who is accessing data through which endpoint, taken from the request
+#[derive(Debug, Clone)]
+pub struct AccessContext {
+    pub principal: Option<String>,
+    /// Wards the principal may see readings from; `None` for all
+    pub wards: Option<Vec<String>>,
+    pub roles: Vec<String>,
+    pub endpoint: String,
+    pub request_id: Option<String>,
+}
+
+impl FromRequest for AccessContext {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let principal = req
+            .headers()
+            .get(&PRINCIPAL_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| p.chars().take(MAX_PRINCIPAL_LEN).collect());
+        let list = |header: &HeaderName| -> Option<Vec<String>> {
+            req.headers()
+                .get(header)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.split(',').map(str::trim).filter(|w| !w.is_empty()).map(String::from).collect())
+        };
+        let wards = list(&WARDS_HEADER);
+        let roles = list(&ROLES_HEADER).unwrap_or_default();
+
+        ready(Ok(AccessContext {
+            principal,
+            wards,
+            roles,
+            endpoint: req.path().to_string(),
+            request_id: req.extensions().get::<RequestId>().map(|id| id.0.clone()),
+        }))
+    }
+}
+
+impl AccessContext {
+    /// Whether the principal may see readings from rooms in `ward`
+    pub fn permits(&self, ward: Option<&str>) -> bool {
+        match (&self.wards, ward) {
+            (None, _) => true,
+            (Some(wards), Some(ward)) => wards.iter().any(|w| w == ward),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Whether the gateway granted the principal `role`. Roles only count
+    /// for authenticated principals.
+    pub fn has_role(&self, role: &str) -> bool {
+        self.principal.is_some() && self.roles.iter().any(|r| r == role)
+    }
+
+    /// Record that `events` were returned, one entry per patient: the
+    /// registered patient the reading belongs to, or else its room.
+    /// `range` is the requested time range; without one the span of the
+    /// returned readings is logged. A query that returned nothing is logged
+    /// against the requested `room`, or `*` for all rooms.
+    pub async fn record(
+        &self,
+        db: &Database,
+        range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+        room: Option<&str>,
+        events: &[SensorEvent],
+    ) {
+        self.record_with_manual(db, range, room, events, &[]).await
+    }
+
+    /// Like `record`, for readings returned together with observations
+    /// recorded by staff
+    pub async fn record_with_manual(
+        &self,
+        db: &Database,
+        range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+        room: Option<&str>,
+        events: &[SensorEvent],
+        manual: &[ManualObservation],
+    ) {
+        let rows = events
+            .iter()
+            .map(|e| (e.patient_id.as_deref().unwrap_or(&e.room), e.reading.timestamp))
+            .chain(manual.iter().map(|o| (o.patient_id.as_deref().unwrap_or(&o.room), o.observed_at)));
+        let mut by_patient: BTreeMap<&str, Vec<DateTime<Utc>>> = BTreeMap::new();
+        for (patient, time) in rows {
+            by_patient.entry(patient).or_default().push(time);
+        }
+        if by_patient.is_empty() {
+            by_patient.insert(room.unwrap_or("*"), Vec::new());
+        }
+
+        for (patient_id, times) in by_patient {
+            let range = range.or_else(|| span(&times));
+            let access = PhiAccess {
+                principal: self.principal.clone(),
+                patient_id: patient_id.to_string(),
+                endpoint: self.endpoint.clone(),
+                range_start: range.map(|(start, _)| start),
+                range_end: range.map(|(_, end)| end),
+                row_count: times.len() as u64,
+                request_id: self.request_id.clone(),
+            };
+
+            if let Err(e) = db.insert_phi_access(&access).await {
+                error!("Failed to write PHI access log entry for {}: {}", access.endpoint, e);
+            }
+        }
+    }
+}
+
+/// Earliest and latest of the returned readings' times
+fn span(times: &[DateTime<Utc>]) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    Some((*times.iter().min()?, *times.iter().max()?))
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Access log as CSV, one access per line
+pub fn to_csv(records: &[PhiAccessRecord]) -> String {
+    let mut out = String::from(
+        "accessed_at,principal,patient_id,endpoint,range_start,range_end,row_count,request_id\n",
+    );
+    let time = |t: Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339()).unwrap_or_default();
+
+    for record in records {
+        let access = &record.access;
+        let fields = [
+            record.accessed_at.to_rfc3339(),
+            csv_field(access.principal.as_deref().unwrap_or_default()),
+            csv_field(&access.patient_id),
+            csv_field(&access.endpoint),
+            time(access.range_start),
+            time(access.range_end),
+            access.row_count.to_string(),
+            csv_field(access.request_id.as_deref().unwrap_or_default()),
+        ];
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,368 @@
+//! Hospital ADT feed
+//!
+//! Patients are bound to rooms by the hospital's patient administration
+//! system instead of by hand, so a patient moved or discharged on the ward
+//! can't be left attributed to the wrong room's readings:
+//!
+//! - HL7 v2 ADT messages are accepted over MLLP on `ADT_MLLP_BIND`, e.g.
+//!   `0.0.0.0:2575`, and answered with an ACK. Messages that can't be read
+//!   get `AE`; while the database is down they get `AR`, so the interface
+//!   engine sends them again.
+//! - FHIR Encounters are accepted at `POST /api/integrations/fhir/encounter`,
+//!   e.g. from a subscription's rest-hook, on their own or in a Bundle. The
+//!   feed must send `ADT_FHIR_TOKEN` as its bearer token.
+//!
+//! An admission or transfer into a monitored room registers the patient if
+//! needed and assigns them the room; whoever was still assigned to it is
+//! taken out. A discharge, or a transfer to a location that isn't
+//! monitored, takes the patient out of their room, which ends the
+//! attribution of its readings to them. Locations are mapped to rooms with
+//! `ADT_LOCATIONS` (`3W^301=room-101,...`); a location without a mapping is
+//! the monitored room named like its room component, if any. Events for
+//! patients who are in no monitored room are ignored, so the feed of the
+//! whole hospital can be sent.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, warn};
+
+use crate::api::{self, ApiError, AppState};
+use crate::consent::MonitoringConsent;
+use crate::db::{Database, DbError};
+use crate::fhir::{FhirEncounter, Patient};
+use patient_monitor_types::adt::{hl7_ack, parse_hl7_adt, AdtAction, AdtEvent, AdtLocation, Hl7AckCode};
+
+/// MLLP frame delimiters: start block, end block
+const VT: u8 = 0x0b;
+const FS: u8 = 0x1c;
+/// Longer frames close the connection
+const MAX_FRAME_BYTES: u64 = 1024 * 1024;
+/// Longest patient id the registry accepts
+const MAX_PATIENT_ID: usize = 64;
+
+#[derive(Debug, Clone, Default)]
+pub struct AdtConfig {
+    /// Address HL7 messages are accepted on over MLLP; none without
+    pub mllp_bind: Option<SocketAddr>,
+    /// Bearer token of the FHIR Encounter feed; disabled without
+    pub fhir_token: Option<String>,
+    /// Monitored room by ADT location, e.g. `3W^301`
+    pub locations: HashMap<String, String>,
+}
+
+/// Parse `3W^301=room-101,3W^302^B=room-102`
+pub fn parse_locations(value: &str) -> Result<HashMap<String, String>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|mapping| match mapping.split_once('=') {
+            Some((location, room)) if !location.trim().is_empty() && !room.trim().is_empty() => {
+                Ok((location.trim().to_string(), room.trim().to_string()))
+            }
+            _ => Err(format!("Expected location=room, got '{}'", mapping)),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum AdtError {
+    Invalid(String),
+    Database(DbError),
+}
+
+impl std::fmt::Display for AdtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdtError::Invalid(msg) => write!(f, "{}", msg),
+            AdtError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AdtError {}
+
+impl From<DbError> for AdtError {
+    fn from(e: DbError) -> Self {
+        AdtError::Database(e)
+    }
+}
+
+/// Monitored room `location` is mapped to, or else the one named like its room
+async fn room_of(db: &Database, config: &AdtConfig, location: &AdtLocation) -> Result<Option<String>, DbError> {
+    let keys = location.keys();
+    if let Some(room) = keys.iter().find_map(|key| config.locations.get(key)) {
+        return Ok(Some(room.clone()));
+    }
+    let rooms = db.get_rooms().await?;
+    Ok(rooms.into_iter().map(|r| r.id).find(|id| *id == location.room))
+}
+
+/// The registered patient with the details the event names replacing theirs
+fn merged(known: Option<&Patient>, event: &Patient) -> Patient {
+    let Some(known) = known else {
+        return event.clone();
+    };
+    Patient {
+        id: known.id.clone(),
+        family_name: Some(&event.family_name).filter(|n| !n.is_empty()).unwrap_or(&known.family_name).clone(),
+        given_names: Some(&event.given_names).filter(|n| !n.is_empty()).unwrap_or(&known.given_names).clone(),
+        birth_date: event.birth_date.or(known.birth_date),
+        gender: event.gender.or(known.gender),
+        room: known.room.clone(),
+    }
+}
+
+/// Apply an ADT event to the patient registry, and the new patients' consent
+/// to the rooms' monitoring. Returns what it did, for the log.
+pub async fn apply(
+    db: &Database,
+    config: &AdtConfig,
+    consent: &MonitoringConsent,
+    event: &AdtEvent,
+) -> Result<String, AdtError> {
+    let id = &event.patient.id;
+    if id.len() > MAX_PATIENT_ID {
+        return Err(AdtError::Invalid(format!("Patient id must be at most {} characters", MAX_PATIENT_ID)));
+    }
+    let known = db.get_patient(id).await?;
+    let mut patient = merged(known.as_ref(), &event.patient);
+
+    let room = match (event.action, &event.location) {
+        (AdtAction::Assign, Some(location)) => match room_of(db, config, location).await? {
+            Some(room) => Some(room),
+            None if patient.room.is_none() => {
+                return Ok(format!("Patient {} is not in a monitored room ({})", id, location));
+            }
+            // Moved out of monitoring
+            None => None,
+        },
+        (AdtAction::Assign | AdtAction::Discharge, _) => None,
+        (AdtAction::Update, _) => patient.room.clone(),
+    };
+    let Some(known) = known else {
+        let Some(room) = room else {
+            return Ok(format!("Patient {} is not registered", id));
+        };
+        // Encounters only reference the patient; their details follow with an update
+        if patient.family_name.is_empty() {
+            patient.family_name = id.clone();
+        }
+        vacate(db, &room, id).await?;
+        patient.room = Some(room.clone());
+        db.insert_patient(&patient).await?;
+        consent.refresh_or_log(db).await;
+        return Ok(format!("Patient {} registered in {}", id, room));
+    };
+
+    if let Some(room) = room.as_deref().filter(|r| known.room.as_deref() != Some(*r)) {
+        vacate(db, room, id).await?;
+    }
+    patient.room = room;
+    db.update_patient(&patient).await?;
+    consent.refresh_or_log(db).await;
+    Ok(match (known.room, &patient.room) {
+        (from, Some(to)) if from.as_ref() != Some(to) => format!("Patient {} moved to {}", id, to),
+        (Some(from), None) => format!("Patient {} left {}", id, from),
+        _ => format!("Patient {} updated", id),
+    })
+}
+
+/// Take whoever else is assigned `room` out of it; they left without the
+/// feed telling us, e.g. a discharge entered after the next admission
+async fn vacate(db: &Database, room: &str, incoming: &str) -> Result<(), DbError> {
+    for occupant in db.get_patients(Some(room)).await?.into_iter().filter(|p| p.id != incoming) {
+        warn!("Patient {} taken out of {} for {}", occupant.id, room, incoming);
+        db.update_patient(&Patient { room: None, ..occupant }).await?;
+    }
+    Ok(())
+}
+
+// ============================================================================
+// HL7 OVER MLLP
+// ============================================================================
+
+/// Accept HL7 ADT messages over MLLP, unless no address is configured
+pub fn spawn(db: Database, config: AdtConfig, consent: MonitoringConsent) {
+    let Some(addr) = config.mllp_bind else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to listen for ADT messages on {}: {}", addr, e);
+                return;
+            }
+        };
+        info!("Accepting HL7 ADT messages over MLLP on {}", addr);
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(serve_mllp(db.clone(), config.clone(), consent.clone(), stream, peer));
+                }
+                Err(e) => warn!("Failed to accept ADT connection: {}", e),
+            }
+        }
+    });
+}
+
+/// Answer each framed message on a connection with an ACK
+async fn serve_mllp(db: Database, config: AdtConfig, consent: MonitoringConsent, stream: TcpStream, peer: SocketAddr) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut frame = Vec::new();
+
+    loop {
+        frame.clear();
+        // The CR after an end block is skipped with what precedes the next start block
+        match (&mut reader).take(MAX_FRAME_BYTES).read_until(FS, &mut frame).await {
+            Ok(0) => return,
+            Ok(_) if frame.last() == Some(&FS) => {}
+            // Closed after the last frame's CR
+            Ok(_) if !frame.contains(&VT) => return,
+            Ok(_) => {
+                warn!("Closing ADT connection from {}: frame too long or cut off", peer);
+                return;
+            }
+            Err(e) => {
+                warn!("ADT connection from {} failed: {}", peer, e);
+                return;
+            }
+        }
+        let Some(start) = frame.iter().position(|b| *b == VT) else {
+            continue;
+        };
+        let message = String::from_utf8_lossy(&frame[start + 1..frame.len() - 1]);
+
+        let ack = receive(&db, &config, &consent, &message).await;
+        let mut response = vec![VT];
+        response.extend_from_slice(ack.as_bytes());
+        response.extend_from_slice(&[FS, b'\r']);
+        if let Err(e) = writer.write_all(&response).await {
+            warn!("Failed to acknowledge ADT message from {}: {}", peer, e);
+            return;
+        }
+    }
+}
+
+/// Apply an HL7 message and build its ACK
+async fn receive(db: &Database, config: &AdtConfig, consent: &MonitoringConsent, message: &str) -> String {
+    let result = match parse_hl7_adt(message) {
+        Ok(Some(event)) => apply(db, config, consent, &event).await.map(|outcome| {
+            info!("ADT message {}: {}", event.source_id, outcome);
+        }),
+        // Nothing to do, e.g. a pre-admission
+        Ok(None) => Ok(()),
+        Err(e) => Err(AdtError::Invalid(e)),
+    };
+
+    let (code, text) = match result {
+        Ok(()) => (Hl7AckCode::Accept, String::new()),
+        Err(AdtError::Invalid(e)) => {
+            warn!("Rejected ADT message: {}", e);
+            (Hl7AckCode::Error, e)
+        }
+        Err(AdtError::Database(e)) => {
+            error!("Failed to apply ADT message: {}", e);
+            (Hl7AckCode::Reject, "Database unavailable, try again".to_string())
+        }
+    };
+    hl7_ack(message, code, &text, Utc::now())
+}
+
+// ============================================================================
+// FHIR ENCOUNTERS
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+struct EncounterOutcome {
+    id: String,
+    outcome: String,
+}
+
+/// Compare without giving away how much of the token matched
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Encounters in a request body: one, or the entries of a Bundle
+fn encounters(body: serde_json::Value) -> Result<Vec<FhirEncounter>, String> {
+    let resources = match body.get("resourceType").and_then(|t| t.as_str()) {
+        Some("Bundle") => body
+            .get("entry")
+            .and_then(|e| e.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.get("resource"))
+            .filter(|r| r.get("resourceType").and_then(|t| t.as_str()) == Some("Encounter"))
+            .cloned()
+            .collect(),
+        _ => vec![body],
+    };
+    resources
+        .into_iter()
+        .map(|r| serde_json::from_value(r).map_err(|e| format!("Invalid Encounter: {}", e)))
+        .collect()
+}
+
+/// POST /api/integrations/fhir/encounter
+///
+/// FHIR Encounter feed: an Encounter, or a Bundle such as a subscription
+/// notification whose Encounters are applied in order
+#[post("/api/integrations/fhir/encounter")]
+pub async fn encounter(
+    state: web::Data<AppState>,
+    adt: web::Data<AdtConfig>,
+    req: HttpRequest,
+    body: web::Json<serde_json::Value>,
+) -> impl Responder {
+    let Some(token) = &adt.fhir_token else {
+        return HttpResponse::NotFound().json(ApiError::not_found("The FHIR Encounter feed is not configured"));
+    };
+    let given = req.headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    if !token_matches(token, given) {
+        warn!("Rejected Encounter with invalid token");
+        return HttpResponse::Unauthorized().json(ApiError::new("invalid_token", "Invalid ADT feed token"));
+    }
+
+    let encounters = match encounters(body.into_inner()) {
+        Ok(encounters) => encounters,
+        Err(e) => return HttpResponse::BadRequest().json(ApiError::new("invalid_encounter", &e)),
+    };
+    let mut outcomes = Vec::new();
+    for encounter in encounters {
+        let event = match encounter.to_adt() {
+            Ok(Some(event)) => event,
+            Ok(None) => {
+                outcomes.push(EncounterOutcome { id: encounter.id, outcome: format!("Ignored {} encounter", encounter.status) });
+                continue;
+            }
+            Err(e) => return HttpResponse::UnprocessableEntity().json(ApiError::new("invalid_encounter", &e)),
+        };
+        match apply(&state.db, &adt, &state.monitoring_consent, &event).await {
+            Ok(outcome) => {
+                info!("Encounter {}: {}", event.source_id, outcome);
+                outcomes.push(EncounterOutcome { id: event.source_id, outcome });
+            }
+            Err(AdtError::Invalid(e)) => {
+                return HttpResponse::UnprocessableEntity().json(ApiError::new("invalid_encounter", &e));
+            }
+            Err(AdtError::Database(e)) => return api::db_error(e, "Failed to apply encounter"),
+        }
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({ "applied": outcomes }))
+}
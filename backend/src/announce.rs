@@ -0,0 +1,67 @@
+//! Spoken alert announcements for the ward's overhead paging system
+//!
+//! Alert routes with an `announce` target (see `notify`) turn new and
+//! escalated alerts into `announcement` messages whose text is ready for a
+//! text-to-speech engine, worded by `ANNOUNCE_TEMPLATE` (see
+//! `patient_monitor_types::announce`). Targets with a `url` have them POSTed
+//! there; the others publish them on `/ws/announcements`, which a paging
+//! gateway keeps open.
+
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+
+use crate::notify::{AlertContext, AlertEventKind};
+use crate::websocket::WsMessage;
+
+pub use patient_monitor_types::announce::{spell, AnnouncementTemplate};
+
+/// Announcements held for a slow paging gateway before it misses some
+const ANNOUNCE_CAPACITY: usize = 32;
+
+/// Words announcements and hands them to the listening paging gateways
+#[derive(Clone)]
+pub struct Announcer {
+    template: AnnouncementTemplate,
+    sender: broadcast::Sender<WsMessage>,
+}
+
+impl Default for Announcer {
+    fn default() -> Self {
+        Self::new(AnnouncementTemplate::default())
+    }
+}
+
+impl Announcer {
+    pub fn new(template: AnnouncementTemplate) -> Self {
+        let (sender, _) = broadcast::channel(ANNOUNCE_CAPACITY);
+        Self { template, sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<WsMessage> {
+        self.sender.subscribe()
+    }
+
+    /// Announcement of `event` of the alert of `ctx`
+    pub fn announcement(&self, ctx: &AlertContext, event: AlertEventKind, at: DateTime<Utc>) -> WsMessage {
+        let mut text = self.template.render(ctx.alert.description(), &ctx.room, ctx.ward.as_deref());
+        if event == AlertEventKind::Escalated {
+            text = format!("Repeat, still unanswered. {}", text);
+        }
+        WsMessage::Announcement {
+            text,
+            room: ctx.room.clone(),
+            room_spoken: spell(&ctx.room),
+            ward: ctx.ward.clone(),
+            alert: ctx.alert.code().to_string(),
+            severity: ctx.severity,
+            event: event.as_str().to_string(),
+            timestamp: at.to_rfc3339(),
+        }
+    }
+
+    /// Send `announcement` to `/ws/announcements`; returns whether a gateway
+    /// was listening
+    pub fn publish(&self, announcement: WsMessage) -> bool {
+        self.sender.send(announcement).is_ok()
+    }
+}
@@ -1,116 +1,502 @@
 //! REST API endpoints
 
-use actix_web::{get, post, web, HttpResponse, Responder};
-use chrono::{Duration, Utc, TimeZone, NaiveTime};
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use futures_util::stream::{self, StreamExt};
+use parquet::errors::ParquetError;
+use chrono::{Duration, Utc, NaiveDate};
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, RwLock};
-use tracing::{debug, error, info};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::{debug, error, info, warn};
 
-use crate::db::Database;
-use crate::fhir::FhirBundle;
+pub use patient_monitor_types::api::{
+    AlertPrecisionReport, AlertStats, ApiError, DoorPassages, DownsampledReadings, MonitorSettings, OutcomeCounts, PartitionStorage,
+    PrecisionGroup, ResearchAggregates, RoomStorage, SettingsPermissions, StateHistory, StorageReport, SummaryResponse,
+    TimeseriesPoint,
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct MonitorSettings {
-    pub inactivity_seconds: u64,
-    pub sound_threshold: i32,
-}
+use crate::access_log::{self, AccessContext};
+use crate::consent::{MonitoringConsent, PatientConsent};
+use crate::days::FacilityDays;
+use crate::db::{self, ActivityAnalysis, Alert, AlertSnooze, Bucket, AuditEntry, Consent, Database, DbError, JobRun, ReadingCorrection, ReadingCursor};
+use crate::export::{self, Column, ExportConfig, ExportOptions, ParquetExport, EXPORT_PAGE_SIZE};
+use crate::fhir::{AlertOutcome, AlertType, FhirBundle, ManualObservation, ObservationKind, Patient, SensorEvent, SensorRanges};
+use crate::flags::{self, FeatureFlags, FlagSetting};
+use crate::gapfill::GapFill;
+use crate::handover;
+use crate::import::{self, ImportFormat};
+use crate::ingest::{QueueSnapshot, QueueStats};
+use crate::jobs;
+use crate::limits::{self, BodyLimits};
+use crate::notify::{AckNotice, AckSender, Snoozes};
+use crate::reports::{self, ReportConfig};
+use crate::request_id::RequestId;
+use crate::research::{self, ResearchConfig};
+use crate::retention::{self, RetentionConfig};
+use crate::risk;
+use crate::rollup;
+use crate::rules::{self, AlertRule, RuleSet, ShadowHit};
+use crate::seed::{self, SeedError, SeedOptions};
+use crate::serial::{
+    device_log_level, DeviceConnection, DeviceConnectionSnapshot, ReadingQueue, ReadingQueueSnapshot, DEVICE_LOG_LEVELS,
+};
+use crate::supervisor::{TaskHealth, TaskHealthSnapshot};
+use crate::ward::WardMap;
+use crate::websocket::{LiveEvent, SensorBroadcaster};
+use crate::ws_clients::{self, WsClients};
+use patient_monitor_types::ws::Subscription as WsSubscription;
+use patient_monitor_types::analysis::{state_periods, AutoResolve, MatrixStat, ResolutionPolicies, SoundMatrix, TemperatureCrossCheck};
 
 pub struct AppState {
     pub db: Database,
     pub base_url: String,
     pub settings: Arc<RwLock<MonitorSettings>>,
+    /// Rooms this instance ingests readings from
+    pub rooms: Vec<String>,
+    /// Per room, whether the patient assigned to it consents to readings being stored
+    pub monitoring_consent: MonitoringConsent,
+    pub rules: Arc<RwLock<RuleSet>>,
+    /// Per room, the health of its ingest task
+    pub ingest_health: BTreeMap<String, Arc<TaskHealth>>,
+    /// Per room, readings queued or dropped during database outages
+    pub ingest_queues: BTreeMap<String, Arc<QueueStats>>,
+    /// Per room, readings waiting between its reader and ingest task
+    pub reading_queues: BTreeMap<String, Arc<ReadingQueue>>,
+    /// Per room, whether each of its serial devices is connected, by port;
+    /// empty in mock mode
+    pub device_connections: BTreeMap<String, BTreeMap<String, Arc<DeviceConnection>>>,
+    /// Per room with several sensor nodes, the cross-check of their
+    /// temperatures
+    pub temperature_checks: BTreeMap<String, Arc<Mutex<TemperatureCrossCheck>>>,
+    pub snoozes: Snoozes,
+    /// Acknowledgements for the notifier to pass on as `acked` events
+    pub alert_acks: AckSender,
+    /// Alert types staff acknowledging resolves
+    pub alert_resolution: ResolutionPolicies,
+    /// Roles allowed to change each setting
+    pub settings_permissions: SettingsPermissions,
+    /// Sends live clients alerts resolved by acknowledgement
+    pub broadcaster: Arc<SensorBroadcaster>,
+    pub reports: ReportConfig,
+    /// Ward of each room, kept in sync by the ward endpoints
+    pub wards: WardMap,
+    pub retention: RetentionConfig,
+    pub export: ExportConfig,
+    pub research: ResearchConfig,
+    pub body_limits: BodyLimits,
+    /// Plausible sensor values; imported readings outside them are refused
+    pub sensor_ranges: SensorRanges,
+    /// Where days start, for summaries and date parameters
+    pub days: FacilityDays,
+    /// Statistics of open and recently closed WebSocket connections
+    pub ws_clients: WsClients,
+    /// Behaviours switched per room or facility, kept in sync by the flag
+    /// endpoints
+    pub flags: FeatureFlags,
+    /// Synthetic data can only be seeded into mock-mode instances
+    pub mock_mode: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ListObservationsQuery {
-    #[serde(default = "default_limit")]
-    pub _count: usize,
+    /// Page size; default 50, or everything in the period with `minutes`
+    pub _count: Option<usize>,
     pub minutes: Option<i64>,
+    /// Cursor from the previous page's `next` link: timestamp and id of the
+    /// last reading seen
+    pub before_timestamp: Option<chrono::DateTime<Utc>>,
+    pub after_id: Option<i64>,
 }
 
 fn default_limit() -> usize {
     50
 }
 
-#[derive(Debug, Serialize)]
-pub struct ApiError {
-    pub error: String,
-    pub message: String,
+/// Optional gap filling, accepted by the timeseries and activity endpoints
+#[derive(Debug, Deserialize)]
+pub struct FillQuery {
+    #[serde(default)]
+    pub fill: bool,
+    /// Longest gap (seconds) to interpolate, default 60
+    pub max_gap: Option<i64>,
+    /// Spacing (seconds) of filled readings, inferred from the data by default
+    pub interval: Option<i64>,
 }
 
-impl ApiError {
-    fn not_found(msg: &str) -> Self {
-        Self { error: "not_found".to_string(), message: msg.to_string() }
+impl FillQuery {
+    pub fn gap_fill(&self) -> Option<GapFill> {
+        self.fill.then(|| GapFill {
+            max_gap: Duration::seconds(self.max_gap.unwrap_or(60).clamp(1, 3600)),
+            interval: self.interval.filter(|i| *i > 0).map(Duration::seconds),
+        })
     }
-    
-    fn internal_error(msg: &str) -> Self {
-        Self { error: "internal_error".to_string(), message: msg.to_string() }
+}
+
+/// Optional tag filter, accepted by the observation, timeseries and analytics endpoints
+#[derive(Debug, Deserialize)]
+pub struct TagQuery {
+    pub tag: Option<String>,
+}
+
+/// Optional room filter, accepted by the observation, timeseries and analytics endpoints
+#[derive(Debug, Deserialize)]
+pub struct RoomQuery {
+    pub room: Option<String>,
+}
+
+/// Gap filling interpolates between consecutive readings, so it needs a
+/// single room when several are monitored
+fn check_fill_room(state: &AppState, fill: &FillQuery, room: &RoomQuery) -> Option<HttpResponse> {
+    (fill.fill && room.room.is_none() && state.rooms.len() > 1).then(|| {
+        HttpResponse::BadRequest().json(ApiError::new("room_required",
+            "Gap filling needs a room when several rooms are monitored"))
+    })
+}
+
+/// Response to a failed storage operation: 404 for a missing row, 409 for a
+/// write a constraint rejected, 503 while the database is unreachable and
+/// 504 for an analytics query that timed out. Anything else is a 500 with
+/// `message`.
+pub(crate) fn db_error(e: DbError, message: &str) -> HttpResponse {
+    match e {
+        DbError::NotFound(_) => {
+            warn!("Database error: {}", e);
+            HttpResponse::NotFound().json(ApiError::not_found(&format!("{}: not found", message)))
+        }
+        DbError::Constraint(_) => {
+            warn!("Database error: {}", e);
+            HttpResponse::Conflict().json(ApiError::new("conflict",
+                &format!("{}: conflicts with existing data", message)))
+        }
+        DbError::Unavailable(_) => {
+            error!("Database error: {}", e);
+            HttpResponse::ServiceUnavailable()
+                .insert_header(("Retry-After", "10"))
+                .json(ApiError::new("database_unavailable",
+                    &format!("{}: the database is temporarily unavailable", message)))
+        }
+        DbError::Timeout(_) => {
+            warn!("Database error: {}", e);
+            HttpResponse::GatewayTimeout().json(ApiError::new("query_timeout",
+                &format!("{}: the query took too long, try a shorter period", message)))
+        }
+        DbError::Other(_) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(ApiError::internal_error(message))
+        }
     }
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct SummaryResponse {
-    pub total_readings: u64,
-    pub fall_alerts: u64,
-    pub inactivity_alerts: u64,
-    pub system_status: String,
-    pub last_updated: String,
+/// Activity analysis for a period, gap-filled if requested
+async fn analyze_activity(
+    db: &Database,
+    start: chrono::DateTime<Utc>,
+    end: chrono::DateTime<Utc>,
+    fill: Option<GapFill>,
+    room: Option<&str>,
+    tag: Option<&str>,
+) -> Result<ActivityAnalysis, DbError> {
+    match fill {
+        Some(fill) => {
+            let events = oldest_first(db.get_readings_in_range(start, end, room, tag, None, None).await?);
+            Ok(fill.analyze(events, start, end))
+        }
+        None => db.get_activity_analysis(start, end, room, tag).await,
+    }
+}
+
+fn oldest_first(mut events: Vec<SensorEvent>) -> Vec<SensorEvent> {
+    events.reverse();
+    events
+}
+
+/// Readings whose patients allow sharing them with the EHR, see
+/// `consenting_readings`
+async fn ehr_sharing_readings(
+    state: &AppState,
+    access: &AccessContext,
+    room: Option<&str>,
+) -> Result<ConsentingReadings, HttpResponse> {
+    consenting_readings(state, access, room, |c| c.ehr_sharing).await
+}
+
+/// Readings a request may see: from its rooms, taken of patients who give
+/// the consent it needs
+struct ConsentingReadings {
+    rooms: HashSet<String>,
+    patients: PatientConsent,
+}
+
+impl ConsentingReadings {
+    /// Look up the consent of those of `patients` not seen yet
+    async fn load<'a>(
+        &mut self,
+        db: &Database,
+        patients: impl IntoIterator<Item = &'a Option<String>>,
+    ) -> Result<(), DbError> {
+        self.patients.load(db, patients).await
+    }
+
+    /// Whether what was taken of `patient` in `room` may be seen, once the
+    /// patient's consent is loaded
+    fn allows(&self, room: &str, patient: &Option<String>) -> bool {
+        self.rooms.contains(room) && self.patients.allows(patient)
+    }
+
+    /// Keep the readings that may be seen
+    async fn retain(&mut self, db: &Database, events: &mut Vec<SensorEvent>) -> Result<(), DbError> {
+        self.load(db, events.iter().map(|e| &e.patient_id)).await?;
+        events.retain(|e| self.allows(&e.room, &e.patient_id));
+        Ok(())
+    }
+}
+
+/// Readings of `room`, or of all rooms the principal may see, taken of
+/// patients who give the consent checked by `allows`. Consent goes by the
+/// patient assigned to the room when the reading was taken, not by whoever
+/// is in it now.
+async fn consenting_readings(
+    state: &AppState,
+    access: &AccessContext,
+    room: Option<&str>,
+    allows: fn(&Consent) -> bool,
+) -> Result<ConsentingReadings, HttpResponse> {
+    let rooms = match room {
+        Some(room) if !access.permits(state.wards.ward_of(room).as_deref()) => {
+            return Err(HttpResponse::Forbidden()
+                .json(ApiError::new("ward_forbidden", &format!("No access to readings from {}", room))));
+        }
+        Some(room) => HashSet::from([room.to_string()]),
+        None => state.db.get_rooms().await
+            .map_err(|e| db_error(e, "Failed to retrieve rooms"))?
+            .into_iter()
+            .filter(|r| access.permits(r.ward.as_deref()))
+            .map(|r| r.id)
+            .collect(),
+    };
+    Ok(ConsentingReadings { rooms, patients: PatientConsent::new(allows) })
 }
 
+/// GET /api/observations?_count=50[&minutes=60][&room=...][&tag=...]
+/// 
+/// Readings and manual observations, newest first. A full page has a `next`
+/// link continuing with older observations, so dashboards can scroll back
+/// through history page by page; with `minutes` the period is counted from
+/// the time of each request.
 #[get("/api/observations")]
 pub async fn list_observations(
     state: web::Data<AppState>,
     query: web::Query<ListObservationsQuery>,
+    room: web::Query<RoomQuery>,
+    tag: web::Query<TagQuery>,
+    access: AccessContext,
 ) -> impl Responder {
     debug!("GET /api/observations");
     
-    let limit = query._count.min(1000).max(1);
+    let room = room.room.as_deref();
+    let mut sharing = match ehr_sharing_readings(&state, &access, room).await {
+        Ok(sharing) => sharing,
+        Err(denied) => return denied,
+    };
+    
+    let cursor = match (query.before_timestamp, query.after_id) {
+        (Some(before_timestamp), Some(after_id)) => Some(ReadingCursor { before_timestamp, after_id }),
+        (None, None) => None,
+        _ => return HttpResponse::BadRequest().json(ApiError::new("invalid_cursor",
+            "before_timestamp and after_id must be given together")),
+    };
     
-    let result = if let Some(minutes) = query.minutes {
+    let range = query.minutes.map(|minutes| {
         let end = Utc::now();
-        let start = end - Duration::minutes(minutes);
-        state.db.get_readings_in_range(start, end).await
-    } else {
-        state.db.get_recent_readings(limit).await
+        (end - Duration::minutes(minutes), end)
+    });
+    // A period is returned whole unless a page size is asked for
+    let limit = match range {
+        Some(_) => query._count,
+        None => Some(query._count.unwrap_or(default_limit())),
+    }.map(|l| l.clamp(1, 1000));
+    
+    let tag = tag.tag.as_deref();
+    let result = match (range, limit) {
+        (Some((start, end)), _) => state.db.get_readings_in_range(start, end, room, tag, cursor, limit).await,
+        (None, Some(limit)) => state.db.get_recent_readings(limit, room, tag, cursor).await,
+        (None, None) => unreachable!("listings without a period have a limit"),
+    };
+    // Manual observations carry no tags
+    let manual = match tag {
+        Some(_) => Ok(Vec::new()),
+        None => {
+            let end = [range.map(|(_, end)| end), cursor.map(|c| c.before_timestamp - Duration::microseconds(1))]
+                .into_iter()
+                .flatten()
+                .min();
+            state.db.get_manual_observations(range.map(|(start, _)| start), end, room, limit.map(|l| l as i64)).await
+        }
     };
     
-    match result {
-        Ok(events) => {
-            let bundle = FhirBundle::from_events(events, &state.base_url);
+    match (result, manual) {
+        (Ok(mut events), Ok(mut manual)) => {
+            // The newest `limit` of readings and manual observations together
+            let mut next = None;
+            if let Some(limit) = limit {
+                let full = events.len() + manual.len() >= limit;
+                while events.len() + manual.len() > limit {
+                    let manual_older = match (events.last(), manual.last()) {
+                        (Some(e), Some(o)) => o.observed_at < e.reading.timestamp,
+                        (None, _) => true,
+                        (_, None) => false,
+                    };
+                    if manual_older {
+                        manual.pop();
+                    } else {
+                        events.pop();
+                    }
+                }
+                
+                // The page ends with the oldest observation kept; pages move
+                // past observations left out for lack of consent
+                let last_reading = events.last().and_then(ReadingCursor::after);
+                let last_manual = manual.last().map(|o| o.observed_at);
+                next = match (last_reading, last_manual) {
+                    (Some(r), Some(t)) if t < r.before_timestamp => Some(ReadingCursor { before_timestamp: t, after_id: 0 }),
+                    (Some(r), _) => Some(r),
+                    // Readings with the observation's timestamp were listed before it
+                    (None, Some(t)) => Some(ReadingCursor { before_timestamp: t, after_id: 0 }),
+                    (None, None) => None,
+                }.filter(|_| full);
+            }
+            
+            // Observations of patients who haven't consented are left out
+            let patients = events.iter().map(|e| &e.patient_id).chain(manual.iter().map(|o| &o.patient_id));
+            if let Err(e) = sharing.load(&state.db, patients).await {
+                return db_error(e, "Failed to check patient consent");
+            }
+            events.retain(|e| sharing.allows(&e.room, &e.patient_id));
+            manual.retain(|o| sharing.allows(&o.room, &o.patient_id));
+            access.record_with_manual(&state.db, range, room, &events, &manual).await;
+            
+            let mut observations: Vec<_> = events
+                .iter()
+                .map(|e| (e.reading.timestamp, e.to_fhir(&state.base_url)))
+                .chain(manual.iter().map(|o| (o.observed_at, o.to_fhir())))
+                .collect();
+            observations.sort_by_key(|(t, _)| std::cmp::Reverse(*t));
+            let mut bundle = FhirBundle::from_observations(
+                observations.into_iter().map(|(_, o)| o).collect(), &state.base_url);
+            if let (Some(cursor), Some(limit)) = (next, limit) {
+                let mut params = vec![
+                    ("_count", limit.to_string()),
+                    ("before_timestamp", cursor.before_timestamp.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)),
+                    ("after_id", cursor.after_id.to_string()),
+                ];
+                params.extend(query.minutes.map(|m| ("minutes", m.to_string())));
+                params.extend(room.map(|r| ("room", r.to_string())));
+                params.extend(tag.map(|t| ("tag", t.to_string())));
+                bundle = bundle.with_next(format!("{}/api/observations?{}",
+                    state.base_url, serde_urlencoded::to_string(&params).unwrap_or_default()));
+            }
             HttpResponse::Ok()
                 .content_type("application/fhir+json")
                 .json(bundle)
         }
-        Err(e) => {
-            error!("Database error: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiError::internal_error("Failed to retrieve observations"))
-        }
+        (Err(e), _) | (_, Err(e)) => db_error(e, "Failed to retrieve observations"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangesQuery {
+    /// Change-feed position of the last reading already seen, 0 for the start
+    #[serde(default)]
+    pub after: i64,
+    #[serde(default = "default_limit")]
+    pub _count: usize,
+}
+
+/// GET /api/observations/changes?after=0&_count=50[&room=...][&tag=...]
+/// 
+/// Readings in the order they were stored, for clients that sync or page
+/// through all data. Imported or backfilled readings appear here when they
+/// were stored, whatever their timestamps. The bundle's `next` link continues
+/// after the last reading returned; poll it to follow new readings.
+#[get("/api/observations/changes")]
+pub async fn list_observation_changes(
+    state: web::Data<AppState>,
+    query: web::Query<ChangesQuery>,
+    room: web::Query<RoomQuery>,
+    tag: web::Query<TagQuery>,
+    access: AccessContext,
+) -> impl Responder {
+    debug!("GET /api/observations/changes?after={}", query.after);
+    
+    let room = room.room.as_deref();
+    let mut sharing = match ehr_sharing_readings(&state, &access, room).await {
+        Ok(sharing) => sharing,
+        Err(denied) => return denied,
+    };
+    
+    let limit = query._count.clamp(1, 1000);
+    let mut events = match state.db.get_readings_after(query.after, limit, room, tag.tag.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return db_error(e, "Failed to retrieve observations"),
+    };
+    
+    // The cursor moves past readings that are left out for lack of consent
+    let next = events.last().and_then(|e| e.seq).unwrap_or(query.after);
+    if let Err(e) = sharing.retain(&state.db, &mut events).await {
+        return db_error(e, "Failed to check patient consent");
     }
+    access.record(&state.db, None, room, &events).await;
+    
+    let mut params = vec![("after", next.to_string()), ("_count", limit.to_string())];
+    params.extend(room.map(|r| ("room", r.to_string())));
+    params.extend(tag.tag.clone().map(|t| ("tag", t)));
+    let next_url = format!("{}/api/observations/changes?{}",
+        state.base_url, serde_urlencoded::to_string(&params).unwrap_or_default());
+    
+    let bundle = FhirBundle::from_events(events, &state.base_url).with_next(next_url);
+    HttpResponse::Ok()
+        .content_type("application/fhir+json")
+        .json(bundle)
 }
 
 #[get("/api/observations/latest")]
-pub async fn get_latest_observation(state: web::Data<AppState>) -> impl Responder {
+pub async fn get_latest_observation(
+    state: web::Data<AppState>,
+    room: web::Query<RoomQuery>,
+    access: AccessContext,
+) -> impl Responder {
     debug!("GET /api/observations/latest");
     
-    match state.db.get_recent_readings(1).await {
-        Ok(events) => {
-            if let Some(event) = events.into_iter().next() {
-                let observation = event.to_fhir(&state.base_url);
-                HttpResponse::Ok()
-                    .content_type("application/fhir+json")
-                    .json(observation)
-            } else {
-                HttpResponse::NotFound()
-                    .json(ApiError::not_found("No observations recorded yet"))
-            }
+    let room = room.room.as_deref();
+    let mut sharing = match ehr_sharing_readings(&state, &access, room).await {
+        Ok(sharing) => sharing,
+        Err(denied) => return denied,
+    };
+    
+    // The newest reading of any room, of a patient who consents
+    let mut events = Vec::new();
+    for room in &sharing.rooms {
+        match state.db.get_recent_readings(1, Some(room), None, None).await {
+            Ok(latest) => events.extend(latest),
+            Err(e) => return db_error(e, "Failed to retrieve observation"),
         }
-        Err(e) => {
-            error!("Database error: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiError::internal_error("Failed to retrieve observation"))
+    }
+    if let Err(e) = sharing.retain(&state.db, &mut events).await {
+        return db_error(e, "Failed to check patient consent");
+    }
+    events.sort_by_key(|e| std::cmp::Reverse(e.reading.timestamp));
+    events.truncate(1);
+    access.record(&state.db, None, room, &events).await;
+    
+    match events.into_iter().next() {
+        Some(event) => {
+            let observation = event.to_fhir(&state.base_url);
+            HttpResponse::Ok()
+                .content_type("application/fhir+json")
+                .json(observation)
         }
+        None => HttpResponse::NotFound()
+            .json(ApiError::not_found("No observations recorded yet")),
     }
 }
 
@@ -118,12 +504,26 @@ pub async fn get_latest_observation(state: web::Data<AppState>) -> impl Responde
 pub async fn get_observation_by_id(
     state: web::Data<AppState>,
     path: web::Path<i64>,
+    access: AccessContext,
 ) -> impl Responder {
     let id = path.into_inner();
     debug!("GET /api/observations/{}", id);
     
     match state.db.get_reading_by_id(id).await {
         Ok(Some(event)) => {
+            let mut sharing = match ehr_sharing_readings(&state, &access, Some(&event.room)).await {
+                Ok(sharing) => sharing,
+                Err(denied) => return denied,
+            };
+            let mut events = vec![event];
+            if let Err(e) = sharing.retain(&state.db, &mut events).await {
+                return db_error(e, "Failed to check patient consent");
+            }
+            let Some(event) = events.pop() else {
+                return HttpResponse::Forbidden()
+                    .json(ApiError::new("consent_required", "Patient has not consented to EHR data sharing"));
+            };
+            access.record(&state.db, None, None, std::slice::from_ref(&event)).await;
             let observation = event.to_fhir(&state.base_url);
             HttpResponse::Ok()
                 .content_type("application/fhir+json")
@@ -133,179 +533,3895 @@ pub async fn get_observation_by_id(
             HttpResponse::NotFound()
                 .json(ApiError::not_found(&format!("Observation {} not found", id)))
         }
+        Err(e) => db_error(e, "Failed to retrieve observation"),
+    }
+}
+
+/// Corrected values of a reading, see `PUT /api/observations/{id}`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadingCorrectionRequest {
+    pub temperature: Option<f32>,
+    pub motion: Option<bool>,
+    pub sound_level: Option<i32>,
+    /// Why the reading is corrected, for the audit log
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteReadingQuery {
+    /// Why the reading is deleted, for the audit log
+    pub reason: Option<String>,
+}
+
+/// Principal changing reading `id`, and the reading, if they may
+async fn reading_to_change(
+    state: &AppState,
+    access: &AccessContext,
+    id: i64,
+) -> Result<(String, SensorEvent), HttpResponse> {
+    let Some(principal) = access.principal.clone() else {
+        return Err(HttpResponse::Unauthorized().json(ApiError::new("authentication_required",
+            "Changing readings needs an authenticated staff member")));
+    };
+    let event = match state.db.get_reading_by_id(id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return Err(HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Observation {} not found", id)))),
+        Err(e) => return Err(db_error(e, "Failed to retrieve observation")),
+    };
+    if !access.permits(state.wards.ward_of(&event.room).as_deref()) {
+        return Err(HttpResponse::Forbidden()
+            .json(ApiError::new("ward_forbidden", &format!("No access to {}", event.room))));
+    }
+    Ok((principal, event))
+}
+
+/// Roll up again the days of `timestamps` that were already rolled up, after
+/// their readings changed. Later days are left to the nightly rollup, which
+/// catches up from the last one.
+async fn rollup_again(state: &AppState, timestamps: impl IntoIterator<Item = chrono::DateTime<Utc>>, after: &str) {
+    let last_rolled_up = match state.db.last_daily_summary_day().await {
+        Ok(last) => last,
         Err(e) => {
-            error!("Database error: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiError::internal_error("Failed to retrieve observation"))
+            error!("Failed to look up the last daily summary: {}", e);
+            None
+        }
+    };
+    let days: BTreeSet<NaiveDate> = timestamps
+        .into_iter()
+        .map(|t| state.days.day_of(t))
+        .filter(|day| last_rolled_up.is_some_and(|last| *day <= last))
+        .collect();
+    for day in days {
+        if let Err(e) = rollup::rollup_day(&state.db, &state.days, day).await {
+            // The day keeps its old summary until rolled up again
+            error!("Failed to roll up {} after {}: {}", day, after, e);
         }
     }
 }
 
-#[get("/api/summary")]
-pub async fn get_summary(state: web::Data<AppState>) -> impl Responder {
-    debug!("GET /api/summary");
+/// PUT /api/observations/{id}
+/// 
+/// Correct a sensor reading's values, e.g. a temperature misread by a
+/// faulty sensor. Fields left out keep their values; alerts raised by the
+/// reading stay as they were. Needs an authenticated principal with access
+/// to the room's ward, and is audited. Returns the corrected observation.
+#[put("/api/observations/{id}")]
+pub async fn correct_observation(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    body: web::Json<ReadingCorrectionRequest>,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    let id = path.into_inner();
+    let body = body.into_inner();
+    debug!("PUT /api/observations/{}", id);
     
-    match state.db.get_alert_summary().await {
-        Ok(summary) => {
-            HttpResponse::Ok().json(SummaryResponse {
-                total_readings: summary.total_readings,
-                fall_alerts: summary.fall_alerts,
-                inactivity_alerts: summary.inactivity_alerts,
-                system_status: "active".to_string(),
-                last_updated: Utc::now().to_rfc3339(),
-            })
-        }
-        Err(e) => {
-            error!("Database error: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiError::internal_error("Failed to retrieve summary"))
-        }
+    let correction = ReadingCorrection {
+        temperature: body.temperature,
+        motion: body.motion,
+        sound_level: body.sound_level,
+    };
+    if correction.temperature.is_none() && correction.motion.is_none() && correction.sound_level.is_none() {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_body",
+            "Give at least one of temperature, motion and soundLevel"));
+    }
+    if correction.temperature.is_some_and(|t| !t.is_finite()) {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_value", "temperature is not a number"));
+    }
+    
+    let (principal, before) = match reading_to_change(&state, &access, id).await {
+        Ok(found) => found,
+        Err(denied) => return denied,
+    };
+    let event = match state.db.update_reading(id, &correction).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Observation {} not found", id))),
+        Err(e) => return db_error(e, "Failed to correct observation"),
+    };
+    
+    let (old, new) = (&before.reading, &event.reading);
+    let changes = [
+        ("temperature", old.temperature.to_string(), new.temperature.to_string()),
+        ("motion", old.motion.to_string(), new.motion.to_string()),
+        ("soundLevel", old.sound_level.to_string(), new.sound_level.to_string()),
+    ];
+    let mut detail: Vec<String> = changes
+        .iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| format!("{} {} -> {}", field, old, new))
+        .collect();
+    detail.extend(body.reason.filter(|r| !r.trim().is_empty()).map(|r| format!("reason: {}", r.trim())));
+    let audit = AuditEntry {
+        action: "reading.correct".to_string(),
+        subject: format!("observation/{}", id),
+        actor: Some(principal.clone()),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(detail.join("; ")),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
     }
+    info!("{} corrected observation {} in {}", principal, id, event.room);
+    
+    rollup_again(&state, [new.timestamp], "correction").await;
+    
+    HttpResponse::Ok()
+        .content_type("application/fhir+json")
+        .json(event.to_fhir(&state.base_url))
 }
 
-#[get("/api/health")]
-pub async fn health_check() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "healthy",
-        "timestamp": Utc::now().to_rfc3339()
-    }))
+/// DELETE /api/observations/{id}[?reason=...]
+/// 
+/// Remove a sensor reading, e.g. one of a known sensor malfunction. The
+/// reading is kept, marked deleted, until retention purges it, but is left
+/// out of every listing, export and analysis. Needs an authenticated
+/// principal with access to the room's ward, and is audited.
+#[delete("/api/observations/{id}")]
+pub async fn delete_observation(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    query: web::Query<DeleteReadingQuery>,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    let id = path.into_inner();
+    debug!("DELETE /api/observations/{}", id);
+    
+    let (principal, event) = match reading_to_change(&state, &access, id).await {
+        Ok(found) => found,
+        Err(denied) => return denied,
+    };
+    match state.db.mark_deleted(id, Utc::now()).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Observation {} not found", id))),
+        Err(e) => return db_error(e, "Failed to delete observation"),
+    }
+    
+    let reading = &event.reading;
+    let mut detail = format!("{} at {}: temperature {}, motion {}, soundLevel {}", event.room,
+        reading.timestamp.to_rfc3339(), reading.temperature, reading.motion, reading.sound_level);
+    if let Some(reason) = query.reason.as_deref().map(str::trim).filter(|r| !r.is_empty()) {
+        detail.push_str(&format!("; reason: {}", reason));
+    }
+    let audit = AuditEntry {
+        action: "reading.delete".to_string(),
+        subject: format!("observation/{}", id),
+        actor: Some(principal.clone()),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(detail),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    info!("{} deleted observation {} in {}", principal, id, event.room);
+    
+    rollup_again(&state, [reading.timestamp], "deletion").await;
+    
+    HttpResponse::NoContent().finish()
 }
 
-/// Query params for activity analysis
+/// Staff observation to record, see `POST /api/observations`
 #[derive(Debug, Deserialize)]
-pub struct ActivityQuery {
-    /// Start hour (0-23), default 22 (10 PM)
-    pub start_hour: Option<u32>,
-    /// End hour (0-23), default 6 (6 AM)  
-    pub end_hour: Option<u32>,
-    /// Date in YYYY-MM-DD format, default today
-    pub date: Option<String>,
+#[serde(rename_all = "camelCase")]
+pub struct ObservationRequest {
+    pub room: String,
+    pub kind: ObservationKind,
+    pub value: f64,
+    /// When the observation was made, default now
+    pub observed_at: Option<chrono::DateTime<Utc>>,
+    pub note: Option<String>,
 }
 
-/// GET /api/activity/sleep
+/// Observations may be timestamped this far ahead, for clock differences
+const MAX_OBSERVATION_AHEAD_MINUTES: i64 = 5;
+const MAX_OBSERVATION_NOTE_LEN: usize = 1000;
+
+/// POST /api/observations
 /// 
-/// Analyze sleep activity (default 10 PM to 6 AM)
-/// Example: /api/activity/sleep?start_hour=22&end_hour=6&date=2024-01-15
-#[get("/api/activity/sleep")]
-pub async fn get_sleep_analysis(
+/// Record a vital sign or score taken by staff, e.g. a spot-check body
+/// temperature or a pain score. Needs an authenticated principal with
+/// access to the room's ward; it is stored as the observation's performer.
+/// Returns the FHIR observation.
+#[post("/api/observations")]
+pub async fn record_observation(
     state: web::Data<AppState>,
-    query: web::Query<ActivityQuery>,
+    body: web::Json<ObservationRequest>,
+    access: AccessContext,
+    request_id: RequestId,
 ) -> impl Responder {
-    debug!("GET /api/activity/sleep");
+    let body = body.into_inner();
     
-    let start_hour = query.start_hour.unwrap_or(22);
-    let end_hour = query.end_hour.unwrap_or(6);
-    
-    // Parse date or use today
-    let base_date = if let Some(date_str) = &query.date {
-        chrono::NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
-            .unwrap_or_else(|_| Utc::now().date_naive())
-    } else {
-        Utc::now().date_naive()
+    let Some(principal) = access.principal.clone() else {
+        return HttpResponse::Unauthorized().json(ApiError::new("authentication_required",
+            "Recording observations needs an authenticated staff member"));
     };
+    if !access.permits(state.wards.ward_of(&body.room).as_deref()) {
+        return HttpResponse::Forbidden()
+            .json(ApiError::new("ward_forbidden", &format!("No access to {}", body.room)));
+    }
+    if let Err(message) = body.kind.validate(body.value) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_value", &message));
+    }
+    let now = Utc::now();
+    let observed_at = body.observed_at.unwrap_or(now);
+    if observed_at > now + Duration::minutes(MAX_OBSERVATION_AHEAD_MINUTES) {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_time", "Observation time is in the future"));
+    }
+    let note = body.note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    if note.as_ref().is_some_and(|n| n.chars().count() > MAX_OBSERVATION_NOTE_LEN) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_note",
+            &format!("Note may be at most {} characters", MAX_OBSERVATION_NOTE_LEN)));
+    }
     
-    // Calculate start and end times
-    let start = Utc.from_utc_datetime(
-        &base_date.and_time(NaiveTime::from_hms_opt(start_hour, 0, 0).unwrap())
-    );
+    match state.db.get_rooms().await {
+        Ok(rooms) if rooms.iter().any(|r| r.id == body.room) => {}
+        Ok(_) => return HttpResponse::BadRequest()
+            .json(ApiError::new("unknown_room", &format!("Room {} not found", body.room))),
+        Err(e) => return db_error(e, "Failed to check room"),
+    }
     
-    // If end_hour < start_hour, it's the next day
-    let end_date = if end_hour < start_hour {
-        base_date + chrono::Duration::days(1)
-    } else {
-        base_date
+    let observation = ManualObservation {
+        id: None,
+        room: body.room,
+        patient_id: None,
+        kind: body.kind,
+        value: body.value,
+        observed_at,
+        recorded_by: principal,
+        note,
+    };
+    let observation = match state.db.insert_manual_observation(&observation).await {
+        Ok(observation) => observation,
+        Err(e) => return db_error(e, "Failed to record observation"),
     };
-    let end = Utc.from_utc_datetime(
-        &end_date.and_time(NaiveTime::from_hms_opt(end_hour, 0, 0).unwrap())
-    );
     
-    match state.db.get_activity_analysis(start, end).await {
-        Ok(analysis) => HttpResponse::Ok().json(analysis),
-        Err(e) => {
-            error!("Database error: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiError::internal_error("Failed to analyze activity"))
-        }
+    let audit = AuditEntry {
+        action: "observation.record".to_string(),
+        subject: format!("manual-observation/{}", observation.id.unwrap_or_default()),
+        actor: Some(observation.recorded_by.clone()),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("{} {} {} in {}", observation.kind.as_str(), observation.value,
+            observation.kind.unit(), observation.room)),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
     }
+    info!("{} recorded {} in {}", observation.recorded_by, observation.kind.as_str(), observation.room);
+    
+    HttpResponse::Created()
+        .content_type("application/fhir+json")
+        .json(observation.to_fhir())
 }
 
-/// GET /api/activity/period
+/// GET /api/rooms
 /// 
-/// Analyze activity for custom time period
-/// Example: /api/activity/period?minutes=60 (last 60 minutes)
-#[get("/api/activity/period")]
-pub async fn get_period_analysis(
+/// Known rooms with the time of their latest reading
+#[get("/api/rooms")]
+pub async fn list_rooms(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/rooms");
+    
+    match state.db.get_rooms().await {
+        Ok(rooms) => HttpResponse::Ok().json(rooms),
+        Err(e) => db_error(e, "Failed to retrieve rooms"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewWard {
+    pub id: String,
+    /// Display name, default the id
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoomWard {
+    /// Ward to move the room to; null takes it out of its ward
+    pub ward: Option<String>,
+}
+
+/// Refresh the ward of each room after it was changed through the API
+async fn reload_wards(state: &AppState) {
+    if let Err(e) = state.wards.reload(&state.db).await {
+        error!("Failed to reload wards: {}", e);
+    }
+}
+
+/// GET /api/wards
+/// 
+/// Wards with their rooms
+#[get("/api/wards")]
+pub async fn list_wards(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/wards");
+    
+    match state.db.get_wards().await {
+        Ok(wards) => HttpResponse::Ok().json(wards),
+        Err(e) => db_error(e, "Failed to retrieve wards"),
+    }
+}
+
+/// POST /api/wards
+/// 
+/// Create a ward without rooms; assign rooms with `PUT /api/rooms/{id}/ward`
+/// Example body: {"id": "ward-3a", "name": "Geriatrics 3A"}
+#[post("/api/wards")]
+pub async fn create_ward(
     state: web::Data<AppState>,
-    query: web::Query<ListObservationsQuery>,
+    body: web::Json<NewWard>,
 ) -> impl Responder {
-    debug!("GET /api/activity/period");
-    
-    let minutes = query.minutes.unwrap_or(60);
-    let end = Utc::now();
-    let start = end - Duration::minutes(minutes);
+    let id = body.id.trim();
+    if id.is_empty() || id.len() > 64 {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_ward", "Ward id must be 1-64 characters"));
+    }
+    let name = body.name.as_deref().map(str::trim).filter(|n| !n.is_empty()).unwrap_or(id);
     
-    match state.db.get_activity_analysis(start, end).await {
-        Ok(analysis) => HttpResponse::Ok().json(analysis),
-        Err(e) => {
-            error!("Database error: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiError::internal_error("Failed to analyze activity"))
+    match state.db.insert_ward(id, name).await {
+        Ok(true) => {
+            info!("Ward {} created", id);
+            HttpResponse::Created().json(serde_json::json!({
+                "id": id,
+                "name": name,
+                "rooms": [],
+            }))
         }
+        Ok(false) => HttpResponse::Conflict()
+            .json(ApiError::new("ward_exists", &format!("Ward {} already exists", id))),
+        Err(e) => db_error(e, "Failed to create ward"),
     }
 }
 
-/// GET /api/activity/hourly
+/// DELETE /api/wards/{id}
 /// 
-/// Get hourly activity breakdown for a day
-/// Example: /api/activity/hourly?date=2024-01-15
-#[get("/api/activity/hourly")]
-pub async fn get_hourly_analysis(
+/// Delete a ward. Its rooms must be moved out first.
+#[delete("/api/wards/{id}")]
+pub async fn delete_ward(
     state: web::Data<AppState>,
-    query: web::Query<ActivityQuery>,
+    path: web::Path<String>,
 ) -> impl Responder {
-    debug!("GET /api/activity/hourly");
+    let id = path.into_inner();
+    debug!("DELETE /api/wards/{}", id);
     
-    let date = if let Some(date_str) = &query.date {
-        chrono::NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
-            .map(|d| Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap()))
-            .unwrap_or_else(|_| Utc::now())
-    } else {
-        Utc::now()
+    let wards = match state.db.get_wards().await {
+        Ok(wards) => wards,
+        Err(e) => return db_error(e, "Failed to delete ward"),
     };
+    match wards.iter().find(|w| w.id == id) {
+        None => return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Ward {} not found", id))),
+        Some(ward) if !ward.rooms.is_empty() => return HttpResponse::Conflict()
+            .json(ApiError::new("ward_not_empty",
+                &format!("Ward {} still has rooms: {}", id, ward.rooms.join(", ")))),
+        Some(_) => {}
+    }
     
-    match state.db.get_hourly_activity(date).await {
-        Ok(hourly) => HttpResponse::Ok().json(hourly),
-        Err(e) => {
-            error!("Database error: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiError::internal_error("Failed to get hourly activity"))
+    match state.db.delete_ward(&id).await {
+        Ok(true) => {
+            info!("Ward {} deleted", id);
+            HttpResponse::NoContent().finish()
         }
+        Ok(false) => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Ward {} not found", id))),
+        Err(e) => db_error(e, "Failed to delete ward"),
     }
 }
 
-#[get("/api/settings")]
-pub async fn get_settings(state: web::Data<AppState>) -> impl Responder {
-    let settings = state.settings.read().unwrap();
-    HttpResponse::Ok().json(MonitorSettings {
-        inactivity_seconds: settings.inactivity_seconds,
-        sound_threshold: settings.sound_threshold,
-    })
-}
-
+/// PUT /api/rooms/{id}/ward
+/// 
+/// Assign a room to a ward, move it to another one, or take it out of its
+/// ward. Alert routing, ward reports and access control follow at once.
+/// Example body: {"ward": "ward-3a"}
+#[put("/api/rooms/{id}/ward")]
+pub async fn set_room_ward(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<RoomWard>,
+) -> impl Responder {
+    let room = path.into_inner();
+    let ward = body.ward.as_deref();
+    debug!("PUT /api/rooms/{}/ward", room);
+    
+    if let Some(ward) = ward {
+        match state.db.get_wards().await {
+            Ok(wards) if wards.iter().any(|w| w.id == ward) => {}
+            Ok(_) => return HttpResponse::BadRequest()
+                .json(ApiError::new("unknown_ward", &format!("Ward {} does not exist", ward))),
+            Err(e) => return db_error(e, "Failed to move room"),
+        }
+    }
+    
+    match state.db.set_room_ward(&room, ward).await {
+        Ok(true) => {
+            reload_wards(&state).await;
+            info!("Room {} moved to {}", room, ward.unwrap_or("no ward"));
+            HttpResponse::Ok().json(serde_json::json!({
+                "room": room,
+                "ward": ward,
+            }))
+        }
+        Ok(false) => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Room {} not found", room))),
+        Err(e) => db_error(e, "Failed to move room"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StateHistoryQuery {
+    /// RFC 3339 start time, default 24 hours before `to`
+    pub from: Option<chrono::DateTime<Utc>>,
+    /// RFC 3339 end time, default now
+    pub to: Option<chrono::DateTime<Utc>>,
+}
+
+const STATE_HISTORY_DEFAULT_HOURS: i64 = 24;
+const MAX_STATE_HISTORY_DAYS: i64 = 31;
+
+/// GET /api/rooms/{id}/state-history
+/// 
+/// Periods the patient spent in bed, moving in the room or out of it, for
+/// showing a day as a ribbon of states. States are derived as readings
+/// arrive, so the last period ends now.
+/// Example: /api/rooms/room-101/state-history?from=2024-03-01T07:00:00Z&to=2024-03-02T07:00:00Z
+#[get("/api/rooms/{id}/state-history")]
+pub async fn get_state_history(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<StateHistoryQuery>,
+    access: AccessContext,
+) -> impl Responder {
+    let room = path.into_inner();
+    debug!("GET /api/rooms/{}/state-history", room);
+    
+    if !access.permits(state.wards.ward_of(&room).as_deref()) {
+        return HttpResponse::Forbidden()
+            .json(ApiError::new("ward_forbidden", &format!("No access to {}", room)));
+    }
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::hours(STATE_HISTORY_DEFAULT_HOURS));
+    if from >= to {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must be before to"));
+    }
+    if to - from > Duration::days(MAX_STATE_HISTORY_DAYS) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_range",
+            &format!("At most {} days of states can be requested at once", MAX_STATE_HISTORY_DAYS)));
+    }
+    
+    match state.db.get_room_states(&room, from, to).await {
+        Ok(changes) => HttpResponse::Ok().json(StateHistory {
+            periods: state_periods(&changes, from, to.min(Utc::now())),
+            room,
+            from,
+            to,
+        }),
+        Err(e) => db_error(e, "Failed to retrieve room states"),
+    }
+}
+
+/// GET /api/rooms/{id}/door-passages
+/// 
+/// Entries, exits and other passages through the room's door, from rooms
+/// with a door contact. Takes `from` and `to` like the state history; a
+/// passage is listed once the door has closed and the motion after it told
+/// which way it went.
+/// Example: /api/rooms/room-101/door-passages?from=2024-03-01T07:00:00Z&to=2024-03-02T07:00:00Z
+#[get("/api/rooms/{id}/door-passages")]
+pub async fn get_door_passages(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<StateHistoryQuery>,
+    access: AccessContext,
+) -> impl Responder {
+    let room = path.into_inner();
+    debug!("GET /api/rooms/{}/door-passages", room);
+    
+    if !access.permits(state.wards.ward_of(&room).as_deref()) {
+        return HttpResponse::Forbidden()
+            .json(ApiError::new("ward_forbidden", &format!("No access to {}", room)));
+    }
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::hours(STATE_HISTORY_DEFAULT_HOURS));
+    if from >= to {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must be before to"));
+    }
+    if to - from > Duration::days(MAX_STATE_HISTORY_DAYS) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_range",
+            &format!("At most {} days of passages can be requested at once", MAX_STATE_HISTORY_DAYS)));
+    }
+    
+    match state.db.get_door_passages(&room, from, to).await {
+        Ok(passages) => HttpResponse::Ok().json(DoorPassages { room, from, to, passages }),
+        Err(e) => db_error(e, "Failed to retrieve door passages"),
+    }
+}
+
+#[get("/api/summary")]
+pub async fn get_summary(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/summary");
+    
+    match state.db.get_alert_summary().await {
+        Ok(summary) => {
+            HttpResponse::Ok().json(SummaryResponse {
+                total_readings: summary.total_readings,
+                fall_alerts: summary.fall_alerts,
+                inactivity_alerts: summary.inactivity_alerts,
+                system_status: "active".to_string(),
+                last_updated: Utc::now().to_rfc3339(),
+            })
+        }
+        Err(e) => db_error(e, "Failed to retrieve summary"),
+    }
+}
+
+/// Query params for daily summaries
+#[derive(Debug, Deserialize)]
+pub struct DailySummaryQuery {
+    /// First day (YYYY-MM-DD), default 30 days before `to`
+    pub from: Option<NaiveDate>,
+    /// Last day (YYYY-MM-DD), default yesterday, the last day rolled up
+    pub to: Option<NaiveDate>,
+}
+
+const DAILY_SUMMARY_DEFAULT_DAYS: i64 = 30;
+const MAX_DAILY_SUMMARY_DAYS: i64 = 366;
+
+/// GET /api/summary/daily
+/// 
+/// Per-room aggregates of each day from the nightly rollup, oldest first
+/// Example: /api/summary/daily?from=2024-01-01&to=2024-01-31&room=room-101
+#[get("/api/summary/daily")]
+pub async fn get_daily_summary(
+    state: web::Data<AppState>,
+    query: web::Query<DailySummaryQuery>,
+    room: web::Query<RoomQuery>,
+) -> impl Responder {
+    debug!("GET /api/summary/daily");
+    
+    let to = query.to.unwrap_or_else(|| state.days.today() - Duration::days(1));
+    let from = query.from.unwrap_or(to - Duration::days(DAILY_SUMMARY_DEFAULT_DAYS - 1));
+    if from > to {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must not be after to"));
+    }
+    if (to - from).num_days() >= MAX_DAILY_SUMMARY_DAYS {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_range",
+            &format!("At most {} days can be requested", MAX_DAILY_SUMMARY_DAYS)));
+    }
+    
+    match state.db.get_daily_summaries(from, to, room.room.as_deref()).await {
+        Ok(summaries) => HttpResponse::Ok().json(summaries),
+        Err(e) => db_error(e, "Failed to retrieve daily summaries"),
+    }
+}
+
+/// Longest the health check waits for each database query
+const HEALTH_DB_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Ingest state of one room in the health check
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IngestHealth {
+    /// `serial`, or `mock` for generated readings
+    source: &'static str,
+    #[serde(flatten)]
+    task: TaskHealthSnapshot,
+    #[serde(flatten)]
+    queue: Option<QueueSnapshot>,
+    #[serde(flatten)]
+    reader_queue: Option<ReadingQueueSnapshot>,
+    /// Serial devices by port
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    devices: BTreeMap<String, DeviceConnectionSnapshot>,
+    /// Largest difference between the temperatures of the room's sensor
+    /// nodes, in °C, in rooms with several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature_disagreement: Option<f32>,
+    /// Seconds since the room's latest stored reading; `None` if it has none
+    /// or the database didn't answer
+    last_reading_age_secs: Option<i64>,
+}
+
+/// GET /api/health
+/// 
+/// Whether the database answers and each room's ingest task is running,
+/// with the pool's usage and the age of the latest readings. Responds 503
+/// `unhealthy` when the database can't be reached, so orchestrators can take
+/// the instance out of rotation, and 200 `degraded` while an ingest task is
+/// down or restarting or a serial device is disconnected.
+#[get("/api/health")]
+pub async fn health_check(state: web::Data<AppState>) -> impl Responder {
+    let started = std::time::Instant::now();
+    let ping = tokio::time::timeout(HEALTH_DB_TIMEOUT, state.db.ping())
+        .await
+        .unwrap_or(Err(DbError::Timeout(HEALTH_DB_TIMEOUT)));
+    let latency_ms = started.elapsed().as_millis() as u64;
+    
+    let rooms = match &ping {
+        Ok(()) => match tokio::time::timeout(HEALTH_DB_TIMEOUT, state.db.get_rooms()).await {
+            Ok(Ok(rooms)) => rooms,
+            Ok(Err(e)) => {
+                warn!("Health check could not read the latest readings: {}", e);
+                Vec::new()
+            }
+            Err(_) => {
+                warn!("Health check timed out reading the latest readings");
+                Vec::new()
+            }
+        },
+        Err(e) => {
+            error!("Health check could not reach the database: {}", e);
+            Vec::new()
+        }
+    };
+    
+    let now = Utc::now();
+    let age = |at: chrono::DateTime<Utc>| (now - at).num_seconds().max(0);
+    let last_reading: HashMap<&str, _> = rooms
+        .iter()
+        .filter_map(|r| Some((r.id.as_str(), r.last_reading_at?)))
+        .collect();
+    let latest_reading = last_reading.values().max().copied();
+    
+    let source = if state.mock_mode { "mock" } else { "serial" };
+    let ingest: BTreeMap<_, _> = state.ingest_health
+        .iter()
+        .map(|(room, health)| (room, IngestHealth {
+            source,
+            task: health.snapshot(),
+            queue: state.ingest_queues.get(room).map(|q| q.snapshot()),
+            reader_queue: state.reading_queues.get(room).map(|q| q.snapshot()),
+            devices: state.device_connections
+                .get(room)
+                .map(|devices| devices.iter().map(|(port, d)| (port.clone(), d.snapshot())).collect())
+                .unwrap_or_default(),
+            temperature_disagreement: state.temperature_checks
+                .get(room)
+                .and_then(|check| check.lock().unwrap().disagreement()),
+            last_reading_age_secs: last_reading.get(room.as_str()).copied().map(age),
+        }))
+        .collect();
+    
+    let status = if ping.is_err() {
+        "unhealthy"
+    } else if state.ingest_health.values().all(|h| h.is_healthy())
+        && state.device_connections.values().flat_map(BTreeMap::values).all(|d| d.is_connected())
+    {
+        "healthy"
+    } else {
+        "degraded"
+    };
+    let body = serde_json::json!({
+        "status": status,
+        "timestamp": now.to_rfc3339(),
+        "database": {
+            "reachable": ping.is_ok(),
+            "latencyMs": latency_ms,
+            "error": ping.as_ref().err().map(|e| e.to_string()),
+            "pool": state.db.pool_status(),
+            "latestReadingAt": latest_reading.map(|at| at.to_rfc3339()),
+            "latestReadingAgeSecs": latest_reading.map(age),
+        },
+        "ingest": ingest
+    });
+    if ping.is_err() {
+        HttpResponse::ServiceUnavailable().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    }
+}
+
+/// GET /api/timeseries
+/// 
+/// Compact readings for charts, oldest first, optionally gap-filled
+/// Example: /api/timeseries?minutes=60&fill=true&max_gap=30
+#[get("/api/timeseries")]
+pub async fn get_timeseries(
+    state: web::Data<AppState>,
+    query: web::Query<ListObservationsQuery>,
+    fill: web::Query<FillQuery>,
+    room: web::Query<RoomQuery>,
+    tag: web::Query<TagQuery>,
+    access: AccessContext,
+) -> impl Responder {
+    debug!("GET /api/timeseries");
+    
+    if let Some(invalid) = check_fill_room(&state, &fill, &room) {
+        return invalid;
+    }
+    
+    let end = Utc::now();
+    let start = end - Duration::minutes(query.minutes.unwrap_or(60));
+    let room = room.room.as_deref();
+    
+    let events = match state.db.get_readings_in_range(start, end, room, tag.tag.as_deref(), None, None).await {
+        Ok(events) => oldest_first(events),
+        Err(e) => return db_error(e, "Failed to retrieve readings"),
+    };
+    access.record(&state.db, Some((start, end)), room, &events).await;
+    
+    let points: Vec<(SensorEvent, bool)> = match fill.gap_fill() {
+        Some(fill) => fill.apply(events).into_iter().map(|p| (p.event, p.filled)).collect(),
+        None => events.into_iter().map(|e| (e, false)).collect(),
+    };
+    
+    let points: Vec<TimeseriesPoint> = points.into_iter().map(|(event, filled)| TimeseriesPoint {
+        room: event.room.clone(),
+        timestamp: event.reading.timestamp.to_rfc3339(),
+        temperature: event.reading.temperature,
+        motion: event.reading.motion,
+        sound_level: event.reading.sound_level,
+        alerts: event.alerts.codes(),
+        filled,
+    }).collect();
+    
+    HttpResponse::Ok().json(points)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownsampleQuery {
+    /// RFC 3339 start time, default 24 hours before `to`
+    pub from: Option<chrono::DateTime<Utc>>,
+    /// RFC 3339 end time, default now
+    pub to: Option<chrono::DateTime<Utc>>,
+    /// Most points per series, default 500
+    #[serde(default = "default_downsample_points")]
+    pub points: usize,
+}
+
+fn default_downsample_points() -> usize {
+    500
+}
+
+const DOWNSAMPLE_DEFAULT_HOURS: i64 = 24;
+const MAX_DOWNSAMPLE_DAYS: i64 = 31;
+const MAX_DOWNSAMPLE_POINTS: usize = 5000;
+
+/// GET /api/observations/downsampled
+/// 
+/// Temperature and sound level over a period for charts, each reduced to
+/// at most `points` readings with largest-triangle-three-buckets, so a day
+/// of readings fits in a few hundred points without losing the spikes.
+/// Needs a room when several are monitored.
+/// Example: /api/observations/downsampled?from=2024-01-01T00:00:00Z&to=2024-01-02T00:00:00Z&points=500&room=room-101
+#[get("/api/observations/downsampled")]
+pub async fn get_downsampled(
+    state: web::Data<AppState>,
+    query: web::Query<DownsampleQuery>,
+    room: web::Query<RoomQuery>,
+    access: AccessContext,
+) -> impl Responder {
+    debug!("GET /api/observations/downsampled");
+    
+    if room.room.is_none() && state.rooms.len() > 1 {
+        return HttpResponse::BadRequest().json(ApiError::new("room_required",
+            "Downsampling needs a room when several rooms are monitored"));
+    }
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::hours(DOWNSAMPLE_DEFAULT_HOURS));
+    if from >= to {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must be before to"));
+    }
+    if to - from > Duration::days(MAX_DOWNSAMPLE_DAYS) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_range",
+            &format!("At most {} days can be downsampled at once", MAX_DOWNSAMPLE_DAYS)));
+    }
+    let points = query.points.clamp(3, MAX_DOWNSAMPLE_POINTS);
+    let room = room.room.as_deref();
+    
+    let events = match state.db.get_readings_in_range(from, to, room, None, None, None).await {
+        Ok(events) => oldest_first(events),
+        Err(e) => return db_error(e, "Failed to retrieve readings"),
+    };
+    access.record(&state.db, Some((from, to)), room, &events).await;
+    
+    let (temperature, sound_level) = db::downsample(&events, points);
+    HttpResponse::Ok().json(DownsampledReadings {
+        room: room.map(str::to_string),
+        from,
+        to,
+        points,
+        raw_readings: events.len() as u64,
+        temperature,
+        sound_level,
+    })
+}
+
+/// Query params for activity analysis
+#[derive(Debug, Deserialize)]
+pub struct ActivityQuery {
+    /// Start hour (0-23), default 22 (10 PM)
+    pub start_hour: Option<u32>,
+    /// End hour (0-23), default 6 (6 AM)  
+    pub end_hour: Option<u32>,
+    /// Date in YYYY-MM-DD format, default today
+    pub date: Option<String>,
+}
+
+/// GET /api/activity/sleep
+/// 
+/// Analyze sleep activity (default 10 PM to 6 AM), including the number of
+/// night-time awakenings (likely bathroom trips) and, in rooms with a light
+/// sensor, whether the lights were on and how light went with restlessness
+/// Example: /api/activity/sleep?start_hour=22&end_hour=6&date=2024-01-15
+#[get("/api/activity/sleep")]
+pub async fn get_sleep_analysis(
+    state: web::Data<AppState>,
+    query: web::Query<ActivityQuery>,
+    fill: web::Query<FillQuery>,
+    room: web::Query<RoomQuery>,
+    tag: web::Query<TagQuery>,
+) -> impl Responder {
+    debug!("GET /api/activity/sleep");
+    
+    if let Some(invalid) = check_fill_room(&state, &fill, &room) {
+        return invalid;
+    }
+    
+    let start_hour = query.start_hour.unwrap_or(22);
+    let end_hour = query.end_hour.unwrap_or(6);
+    
+    // Parse date or use today
+    let base_date = if let Some(date_str) = &query.date {
+        chrono::NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
+            .unwrap_or_else(|_| state.days.today())
+    } else {
+        state.days.today()
+    };
+    
+    // Calculate start and end times, in the facility's time zone
+    let start = state.days.at(base_date, start_hour);
+    
+    // If end_hour < start_hour, it's the next day
+    let end_date = if end_hour < start_hour {
+        base_date + chrono::Duration::days(1)
+    } else {
+        base_date
+    };
+    let end = state.days.at(end_date, end_hour);
+    
+    let (room, tag) = (room.room.as_deref(), tag.tag.as_deref());
+    let analysis = match analyze_activity(&state.db, start, end, fill.gap_fill(), room, tag).await {
+        Ok(analysis) => analysis,
+        Err(e) => return db_error(e, "Failed to analyze activity"),
+    };
+    
+    let awakenings = match state.db.count_night_awakenings(start, end, room, tag).await {
+        Ok(awakenings) => awakenings,
+        Err(e) => return db_error(e, "Failed to count night awakenings"),
+    };
+    
+    match state.db.get_light_sums(start, end, room, tag).await {
+        Ok(light) => HttpResponse::Ok().json(ActivityAnalysis {
+            night_awakenings: Some(awakenings),
+            night_light: light.night_light(),
+            ..analysis
+        }),
+        Err(e) => db_error(e, "Failed to summarize the night's light"),
+    }
+}
+
+/// GET /api/activity/period
+/// 
+/// Analyze activity for custom time period
+/// Example: /api/activity/period?minutes=60 (last 60 minutes)
+#[get("/api/activity/period")]
+pub async fn get_period_analysis(
+    state: web::Data<AppState>,
+    query: web::Query<ListObservationsQuery>,
+    fill: web::Query<FillQuery>,
+    room: web::Query<RoomQuery>,
+    tag: web::Query<TagQuery>,
+) -> impl Responder {
+    debug!("GET /api/activity/period");
+    
+    if let Some(invalid) = check_fill_room(&state, &fill, &room) {
+        return invalid;
+    }
+    
+    let minutes = query.minutes.unwrap_or(60);
+    let end = Utc::now();
+    let start = end - Duration::minutes(minutes);
+    
+    match analyze_activity(&state.db, start, end, fill.gap_fill(), room.room.as_deref(), tag.tag.as_deref()).await {
+        Ok(analysis) => HttpResponse::Ok().json(analysis),
+        Err(e) => db_error(e, "Failed to analyze activity"),
+    }
+}
+
+/// GET /api/activity/hourly
+/// 
+/// Get hourly activity breakdown for a day
+/// Example: /api/activity/hourly?date=2024-01-15
+#[get("/api/activity/hourly")]
+pub async fn get_hourly_analysis(
+    state: web::Data<AppState>,
+    query: web::Query<ActivityQuery>,
+    fill: web::Query<FillQuery>,
+    room: web::Query<RoomQuery>,
+    tag: web::Query<TagQuery>,
+) -> impl Responder {
+    debug!("GET /api/activity/hourly");
+    
+    if let Some(invalid) = check_fill_room(&state, &fill, &room) {
+        return invalid;
+    }
+    let room = room.room.as_deref();
+    
+    let day = query.date.as_deref()
+        .and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        .unwrap_or_else(|| state.days.today());
+    let (start, end) = state.days.bounds(day);
+    let timezone = state.days.timezone;
+    
+    let result = match fill.gap_fill() {
+        Some(fill) => {
+            let last = end - Duration::microseconds(1);
+            state.db.get_readings_in_range(start, last, room, tag.tag.as_deref(), None, None).await
+                .map(|events| fill.hourly(oldest_first(events), timezone))
+        }
+        None => state.db.get_hourly_activity(start, end, timezone, room, tag.tag.as_deref()).await,
+    };
+    
+    match result {
+        Ok(hourly) => HttpResponse::Ok().json(hourly),
+        Err(e) => db_error(e, "Failed to get hourly activity"),
+    }
+}
+
+/// Longest period and most points a threshold sweep may cover
+const MAX_SWEEP_DAYS: i64 = 90;
+const MAX_SWEEP_POINTS: i32 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct ThresholdSweepQuery {
+    /// Only `sound` (the fall detection threshold) is supported
+    pub metric: String,
+    pub from: i32,
+    pub to: i32,
+    #[serde(default = "default_sweep_step")]
+    pub step: i32,
+    #[serde(default = "default_sweep_days")]
+    pub days: i64,
+}
+
+fn default_sweep_step() -> i32 {
+    10
+}
+
+fn default_sweep_days() -> i64 {
+    14
+}
+
+/// GET /api/analytics/threshold-sweep
+/// 
+/// How many fall alerts each sound threshold would have raised over the last `days` days
+/// Example: /api/analytics/threshold-sweep?metric=sound&from=100&to=300&step=10&days=14
+#[get("/api/analytics/threshold-sweep")]
+pub async fn get_threshold_sweep(
+    state: web::Data<AppState>,
+    query: web::Query<ThresholdSweepQuery>,
+    room: web::Query<RoomQuery>,
+    tag: web::Query<TagQuery>,
+) -> impl Responder {
+    debug!("GET /api/analytics/threshold-sweep");
+    
+    if query.metric != "sound" {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_metric",
+            &format!("Unsupported metric '{}', expected 'sound'", query.metric)));
+    }
+    if query.step <= 0 || query.from > query.to {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_range",
+            "Expected from <= to and a positive step"));
+    }
+    if (query.to - query.from) / query.step >= MAX_SWEEP_POINTS {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_range",
+            &format!("Sweep may contain at most {} thresholds", MAX_SWEEP_POINTS)));
+    }
+    
+    let days = query.days.clamp(1, MAX_SWEEP_DAYS);
+    let end = Utc::now();
+    let start = end - Duration::days(days);
+    
+    match state.db
+        .get_fall_threshold_sweep(start, end, query.from..=query.to, query.step, room.room.as_deref(), tag.tag.as_deref())
+        .await {
+        Ok(points) => {
+            let current = state.settings.read().unwrap_or_else(|e| e.into_inner()).sound_threshold;
+            HttpResponse::Ok().json(serde_json::json!({
+                "metric": query.metric,
+                "periodStart": start.to_rfc3339(),
+                "periodEnd": end.to_rfc3339(),
+                "currentThreshold": current,
+                "points": points,
+            }))
+        }
+        Err(e) => db_error(e, "Failed to compute threshold sweep"),
+    }
+}
+
+const MAX_REPORT_WEEKS: u32 = 52;
+
+#[derive(Debug, Deserialize)]
+pub struct QuietHoursQuery {
+    #[serde(default = "default_report_weeks")]
+    pub weeks: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WardQuery {
+    /// Only report on the rooms in this ward
+    pub ward: Option<String>,
+}
+
+/// Report configuration for `ward`'s rooms, or for all rooms
+async fn report_scope(state: &AppState, ward: Option<&str>) -> Result<ReportConfig, HttpResponse> {
+    let Some(ward) = ward else {
+        return Ok(state.reports.clone());
+    };
+    
+    match state.db.get_wards().await {
+        Ok(wards) => match wards.into_iter().find(|w| w.id == ward) {
+            Some(ward) => Ok(ReportConfig {
+                rooms: ward.rooms,
+                ward: Some(ward.id),
+                quiet_hours: state.reports.quiet_hours,
+                procedures: state.reports.procedures.clone(),
+                shifts: state.reports.shifts.clone(),
+                days: state.reports.days,
+            }),
+            None => Err(HttpResponse::NotFound()
+                .json(ApiError::not_found(&format!("Ward {} not found", ward)))),
+        },
+        Err(e) => Err(db_error(e, "Failed to retrieve wards")),
+    }
+}
+
+fn default_report_weeks() -> u32 {
+    4
+}
+
+/// GET /api/reports/quiet-hours
+/// 
+/// Percent of quiet-hours minutes above the noise limit, per room and ward,
+/// for each of the last `weeks` weeks (current week included), optionally
+/// only for one ward's rooms
+/// Example: /api/reports/quiet-hours?weeks=8&ward=ward-3a
+#[get("/api/reports/quiet-hours")]
+pub async fn get_quiet_hours_report(
+    state: web::Data<AppState>,
+    query: web::Query<QuietHoursQuery>,
+    ward: web::Query<WardQuery>,
+) -> impl Responder {
+    debug!("GET /api/reports/quiet-hours");
+    
+    let weeks = query.weeks.clamp(1, MAX_REPORT_WEEKS);
+    let scope = match report_scope(&state, ward.ward.as_deref()).await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    
+    match reports::quiet_hours_report(&state.db, &scope, weeks, Utc::now()).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => db_error(e, "Failed to build quiet-hours report"),
+    }
+}
+
+/// GET /api/reports/morning
+/// 
+/// Fall risk of every room (or every room in `?ward=`) as of now, highest
+/// first. The same report for all rooms is sent daily when quiet hours end.
+#[get("/api/reports/morning")]
+pub async fn get_morning_report(
+    state: web::Data<AppState>,
+    ward: web::Query<WardQuery>,
+) -> impl Responder {
+    debug!("GET /api/reports/morning");
+    
+    let scope = match report_scope(&state, ward.ward.as_deref()).await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    
+    match reports::morning_report(&state.db, &scope, Utc::now()).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => db_error(e, "Failed to build morning report"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HandoverQuery {
+    /// Name of the shift, e.g. `night`
+    pub shift: String,
+    pub ward: Option<String>,
+    /// `json` (default) or `html`
+    pub format: Option<String>,
+}
+
+/// GET /api/handover
+/// 
+/// Handover of every room (or every room in `?ward=`) at the end of the
+/// latest shift of that name: open alerts, alerts raised during the shift,
+/// rest over its quiet hours and notes to follow up, with a summary of each
+/// room to read out. `format=html` renders it as a page to print.
+/// Example: /api/handover?shift=night&format=html
+#[get("/api/handover")]
+pub async fn get_handover(
+    state: web::Data<AppState>,
+    query: web::Query<HandoverQuery>,
+) -> impl Responder {
+    debug!("GET /api/handover");
+    
+    let html = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("html") => true,
+        Some(other) => return HttpResponse::BadRequest().json(ApiError::new("invalid_format",
+            &format!("Unknown format '{}', expected json or html", other))),
+    };
+    let Some(shift) = state.reports.shifts.get(&query.shift) else {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_shift",
+            &format!("Unknown shift '{}', expected one of {}", query.shift, state.reports.shifts.names().join(", "))));
+    };
+    let scope = match report_scope(&state, query.ward.as_deref()).await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    
+    match handover::handover(&state.db, &scope, shift, Utc::now()).await {
+        Ok(handover) if html => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(handover::render_html(&handover, &scope.days)),
+        Ok(handover) => HttpResponse::Ok().json(handover),
+        Err(e) => db_error(e, "Failed to build handover"),
+    }
+}
+
+#[get("/api/settings")]
+pub async fn get_settings(state: web::Data<AppState>) -> impl Responder {
+    let settings = state.settings.read().unwrap_or_else(|e| e.into_inner());
+    HttpResponse::Ok().json(MonitorSettings {
+        inactivity_seconds: settings.inactivity_seconds,
+        sound_threshold: settings.sound_threshold,
+    })
+}
+
+/// POST /api/settings
+/// 
+/// Change the alert thresholds. Every change is recorded in the settings
+/// history first, with the values before and after and the principal that
+/// made it; a change that can't be recorded isn't made. Settings limited to
+/// some roles (`SETTINGS_PERMISSIONS`) may only be changed by principals
+/// with one of them; a request changing any other is refused as a whole,
+/// listing the settings it may not change.
 #[post("/api/settings")]
 pub async fn update_settings(
     state: web::Data<AppState>,
-    body: web::Json<MonitorSettings>,
+    body: web::Json<MonitorSettings>,
+    access: AccessContext,
+) -> impl Responder {
+    let new = body.into_inner();
+    let old = state.settings.read().unwrap_or_else(|e| e.into_inner()).clone();
+    
+    let changed = old.changed_fields(&new);
+    let denied = state.settings_permissions.denied(&changed, |role| access.has_role(role));
+    if !denied.is_empty() {
+        warn!("Settings change by {} refused: may not change {}",
+            access.principal.as_deref().unwrap_or("unknown"), denied.join(", "));
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "settings_forbidden",
+            "message": format!("Not permitted to change {}", denied.join(", ")),
+            "deniedFields": denied,
+        }));
+    }
+    
+    if new != old {
+        let recorded = state.db.insert_settings_change(
+            &old,
+            &new,
+            access.principal.as_deref(),
+            access.request_id.as_deref(),
+        ).await;
+        if let Err(e) = recorded {
+            return db_error(e, "Failed to record settings change");
+        }
+        *state.settings.write().unwrap_or_else(|e| e.into_inner()) = new.clone();
+    }
+    
+    info!("Settings updated by {}: inactivity={}s, sound_threshold={}",
+        access.principal.as_deref().unwrap_or("unknown"), new.inactivity_seconds, new.sound_threshold);
+    
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok",
+        "message": "Settings updated successfully"
+    }))
+}
+
+/// Most settings changes returned at once
+const MAX_SETTINGS_HISTORY: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct SettingsHistoryQuery {
+    #[serde(default = "default_settings_history_limit")]
+    pub limit: i64,
+}
+
+fn default_settings_history_limit() -> i64 {
+    100
+}
+
+/// GET /api/settings/history?limit=
+/// 
+/// Changes of the alert thresholds, newest first
+#[get("/api/settings/history")]
+pub async fn get_settings_history(
+    state: web::Data<AppState>,
+    query: web::Query<SettingsHistoryQuery>,
+) -> impl Responder {
+    debug!("GET /api/settings/history");
+    
+    match state.db.get_settings_history(query.limit.clamp(1, MAX_SETTINGS_HISTORY)).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => db_error(e, "Failed to retrieve settings history"),
+    }
+}
+
+/// GET /api/devices
+/// 
+/// Sensor boards feeding data, with their room, firmware version and when
+/// they last reported
+#[get("/api/devices")]
+pub async fn list_devices(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/devices");
+    
+    match state.db.get_devices().await {
+        Ok(devices) => HttpResponse::Ok().json(devices),
+        Err(e) => db_error(e, "Failed to retrieve devices"),
+    }
+}
+
+/// Log line or crash report uploaded by a device over HTTP
+#[derive(Debug, Deserialize)]
+pub struct DeviceLogUpload {
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    pub message: String,
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+/// Longest log message stored per entry; crash dumps beyond this are truncated
+const MAX_DEVICE_LOG_LEN: usize = 16 * 1024;
+
+/// POST /api/devices/{id}/logs
+/// 
+/// Store a log line or crash report of a registered device; 404 for devices
+/// that never reported, 400 for a level other than debug, info, warn or
+/// error. Crash dumps make for large bodies, so the route is registered in
+/// `main` with the bulk body limit.
+pub async fn upload_device_log(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<DeviceLogUpload>,
+) -> impl Responder {
+    let device_id = path.into_inner();
+    debug!("POST /api/devices/{}/logs", device_id);
+    
+    let Some(level) = device_log_level(&body.level) else {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_level",
+            &format!("Log level must be one of {}", DEVICE_LOG_LEVELS.join(", "))));
+    };
+    
+    match state.db.get_devices().await {
+        Ok(devices) if devices.iter().any(|d| d.id == device_id) => {}
+        Ok(_) => return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Device {} not found", device_id))),
+        Err(e) => return db_error(e, "Failed to check device"),
+    }
+    
+    let mut message = body.message.clone();
+    if message.len() > MAX_DEVICE_LOG_LEN {
+        let mut end = MAX_DEVICE_LOG_LEN;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+    }
+    
+    match state.db.insert_device_log(&device_id, level, &message).await {
+        Ok(id) => HttpResponse::Created().json(serde_json::json!({
+            "status": "ok",
+            "id": id
+        })),
+        Err(e) => db_error(e, "Failed to store device log"),
+    }
+}
+
+/// GET /api/devices/{id}/logs
+/// 
+/// Most recent log lines and crash reports for a device
+/// Example: /api/devices/COM3/logs?_count=100
+pub async fn get_device_logs(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ListObservationsQuery>,
+) -> impl Responder {
+    let device_id = path.into_inner();
+    debug!("GET /api/devices/{}/logs", device_id);
+    
+    match state.db.get_device_logs(&device_id, query._count.unwrap_or(default_limit()).clamp(1, 1000)).await {
+        Ok(logs) => HttpResponse::Ok().json(logs),
+        Err(e) => db_error(e, "Failed to retrieve device logs"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuarantineQuery {
+    /// RFC 3339 start time, default a day before `to`
+    pub from: Option<chrono::DateTime<Utc>>,
+    /// RFC 3339 end time, default now
+    pub to: Option<chrono::DateTime<Utc>>,
+    pub room: Option<String>,
+    /// Most readings listed, newest first, default 100
+    pub limit: Option<usize>,
+}
+
+/// GET /api/quarantine
+/// 
+/// Reading lines outside the plausible sensor ranges (`SENSOR_RANGES`),
+/// which were kept out of the readings, with why, for tracking down a
+/// faulty sensor
+/// Example: /api/quarantine?room=room-101&from=2024-01-01T00:00:00Z
+#[get("/api/quarantine")]
+pub async fn list_quarantined(
+    state: web::Data<AppState>,
+    query: web::Query<QuarantineQuery>,
+) -> impl Responder {
+    debug!("GET /api/quarantine");
+    
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::days(1));
+    if from >= to {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must be before to"));
+    }
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    
+    match state.db.get_quarantined_readings(from, to, query.room.as_deref(), limit).await {
+        Ok(readings) => HttpResponse::Ok().json(readings),
+        Err(e) => db_error(e, "Failed to retrieve quarantined readings"),
+    }
+}
+
+/// GET /api/patients/{id}/consent
+#[get("/api/patients/{id}/consent")]
+pub async fn get_consent(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let patient_id = path.into_inner();
+    debug!("GET /api/patients/{}/consent", patient_id);
+    
+    match state.db.get_consent(&patient_id).await {
+        Ok(consent) => HttpResponse::Ok().json(consent),
+        Err(e) => db_error(e, "Failed to retrieve consent"),
+    }
+}
+
+/// PUT /api/patients/{id}/consent
+/// 
+/// Record the patient's consent for continuous monitoring, EHR sharing and research export
+#[put("/api/patients/{id}/consent")]
+pub async fn update_consent(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<Consent>,
+) -> impl Responder {
+    let patient_id = path.into_inner();
+    
+    if let Err(e) = state.db.set_consent(&patient_id, &body).await {
+        return db_error(e, "Failed to update consent");
+    }
+    
+    state.monitoring_consent.refresh_or_log(&state.db).await;
+    
+    info!("Consent updated for {}: monitoring={}, ehr={}, research={}",
+        patient_id, body.continuous_monitoring, body.ehr_sharing, body.research_export);
+    
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok",
+        "message": "Consent updated successfully"
+    }))
+}
+
+const MAX_RISK_HISTORY_DAYS: i64 = 365;
+
+#[derive(Debug, Deserialize)]
+pub struct RiskQuery {
+    /// Days of stored scores to return, default 30
+    #[serde(default = "default_risk_days")]
+    pub days: i64,
+}
+
+fn default_risk_days() -> i64 {
+    30
+}
+
+/// GET /api/patients/{id}/risk
+/// 
+/// The patient's fall-risk score as of now, and the daily scores stored
+/// over the last `days` days (newest first)
+/// Example: /api/patients/room-101/risk?days=14
+#[get("/api/patients/{id}/risk")]
+pub async fn get_fall_risk(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<RiskQuery>,
+) -> impl Responder {
+    let patient_id = path.into_inner();
+    debug!("GET /api/patients/{}/risk", patient_id);
+    
+    match state.db.get_rooms().await {
+        Ok(rooms) if rooms.iter().any(|r| r.id == patient_id) => {}
+        Ok(_) => return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Patient {} not found", patient_id))),
+        Err(e) => return db_error(e, "Failed to retrieve fall risk"),
+    }
+    
+    let now = Utc::now();
+    let since = state.days.day_of(now - Duration::days(query.days.clamp(1, MAX_RISK_HISTORY_DAYS)));
+    let current = risk::score(&state.db, &patient_id, &state.reports.quiet_hours, &state.days, now).await;
+    let history = state.db.get_fall_risk_history(&patient_id, since).await;
+    
+    match (current, history) {
+        (Ok(current), Ok(history)) => HttpResponse::Ok().json(serde_json::json!({
+            "current": current,
+            "history": history,
+        })),
+        (Err(e), _) | (_, Err(e)) => db_error(e, "Failed to retrieve fall risk"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatientQuery {
+    /// Only the patient in this room
+    pub room: Option<String>,
+}
+
+/// Reject a patient with an invalid id, an unknown room, or a room taken by
+/// someone else
+async fn check_patient(state: &AppState, patient: &Patient) -> Option<HttpResponse> {
+    if patient.id.is_empty() || patient.id.len() > 64 {
+        return Some(HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_patient", "Patient id must be 1-64 characters")));
+    }
+    if patient.family_name.trim().is_empty() {
+        return Some(HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_patient", "Family name is required")));
+    }
+    let Some(room) = &patient.room else {
+        return None;
+    };
+    
+    let (rooms, occupants) = match (state.db.get_rooms().await, state.db.get_patients(Some(room)).await) {
+        (Ok(rooms), Ok(occupants)) => (rooms, occupants),
+        (Err(e), _) | (_, Err(e)) => return Some(db_error(e, "Failed to check room")),
+    };
+    if !rooms.iter().any(|r| &r.id == room) {
+        return Some(HttpResponse::BadRequest()
+            .json(ApiError::new("unknown_room", &format!("Room {} not found", room))));
+    }
+    if let Some(occupant) = occupants.iter().find(|p| p.id != patient.id) {
+        return Some(HttpResponse::Conflict().json(ApiError::new(
+            "room_occupied",
+            &format!("Room {} is assigned to patient {}", room, occupant.id),
+        )));
+    }
+    None
+}
+
+/// GET /api/patients
+/// 
+/// Registered patients, optionally only the one in `room`
+#[get("/api/patients")]
+pub async fn list_patients(
+    state: web::Data<AppState>,
+    query: web::Query<PatientQuery>,
+) -> impl Responder {
+    debug!("GET /api/patients");
+    
+    match state.db.get_patients(query.room.as_deref()).await {
+        Ok(patients) => HttpResponse::Ok().json(patients),
+        Err(e) => db_error(e, "Failed to retrieve patients"),
+    }
+}
+
+/// POST /api/patients
+/// 
+/// Register a patient, optionally assigned to a room. Readings from the room
+/// are attributed to the patient from then on.
+/// Example body: {"id": "MRN-0042", "familyName": "Jansen", "givenNames": ["Anna"],
+///                "birthDate": "1941-03-09", "gender": "female", "room": "room-101"}
+#[post("/api/patients")]
+pub async fn create_patient(
+    state: web::Data<AppState>,
+    body: web::Json<Patient>,
+) -> impl Responder {
+    if let Some(rejected) = check_patient(&state, &body).await {
+        return rejected;
+    }
+    
+    match state.db.insert_patient(&body).await {
+        Ok(true) => {
+            info!("Patient {} registered in {}", body.id, body.room.as_deref().unwrap_or("no room"));
+            state.monitoring_consent.refresh_or_log(&state.db).await;
+            HttpResponse::Created().json(body.into_inner())
+        }
+        Ok(false) => HttpResponse::Conflict()
+            .json(ApiError::new("patient_exists", &format!("Patient {} already exists", body.id))),
+        Err(e) => db_error(e, "Failed to register patient"),
+    }
+}
+
+/// GET /api/patients/{id}
+/// 
+/// The patient as a FHIR Patient resource
+#[get("/api/patients/{id}")]
+pub async fn get_patient(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let id = path.into_inner();
+    debug!("GET /api/patients/{}", id);
+    
+    match state.db.get_patient(&id).await {
+        Ok(Some(patient)) => HttpResponse::Ok()
+            .content_type("application/fhir+json")
+            .json(patient.to_fhir()),
+        Ok(None) => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Patient {} not found", id))),
+        Err(e) => db_error(e, "Failed to retrieve patient"),
+    }
+}
+
+/// PUT /api/patients/{id}
+/// 
+/// Replace the patient's details; set or clear `room` to move, admit or
+/// discharge them. Earlier readings keep their patient.
+#[put("/api/patients/{id}")]
+pub async fn update_patient(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<Patient>,
+) -> impl Responder {
+    let mut patient = body.into_inner();
+    patient.id = path.into_inner();
+    
+    if let Some(rejected) = check_patient(&state, &patient).await {
+        return rejected;
+    }
+    
+    match state.db.update_patient(&patient).await {
+        Ok(true) => {
+            info!("Patient {} updated, room {}", patient.id, patient.room.as_deref().unwrap_or("none"));
+            state.monitoring_consent.refresh_or_log(&state.db).await;
+            HttpResponse::Ok().json(patient)
+        }
+        Ok(false) => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Patient {} not found", patient.id))),
+        Err(e) => db_error(e, "Failed to update patient"),
+    }
+}
+
+#[delete("/api/patients/{id}")]
+pub async fn delete_patient(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match state.db.delete_patient(&id).await {
+        Ok(true) => {
+            info!("Patient {} removed from the registry", id);
+            state.monitoring_consent.refresh_or_log(&state.db).await;
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Patient {} not found", id))),
+        Err(e) => db_error(e, "Failed to delete patient"),
+    }
+}
+
+/// Reload the shared rule set after rules or thresholds changed
+async fn reload_rules(state: &AppState) -> Result<(), DbError> {
+    let rule_set = RuleSet::load(&state.db, state.days).await?;
+    *state.rules.write().unwrap() = rule_set;
+    Ok(())
+}
+
+/// GET /api/rules
+/// 
+/// Stored alert rules and the named thresholds they can use
+#[get("/api/rules")]
+pub async fn list_rules(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/rules");
+    
+    let rules = match state.db.get_alert_rules().await {
+        Ok(rules) => rules,
+        Err(e) => return db_error(e, "Failed to retrieve rules"),
+    };
+    
+    let thresholds = state.rules.read().unwrap().threshold_names();
+    HttpResponse::Ok().json(serde_json::json!({
+        "rules": rules,
+        "thresholds": thresholds,
+        "variables": rules::VARIABLES,
+    }))
+}
+
+/// POST /api/rules
+/// 
+/// Example body: {"name": "night fall", "alert": "fall",
+///                "expression": "motion && sound > threshold(\"night\") && hour in 22..6"}
+#[post("/api/rules")]
+pub async fn create_rule(
+    state: web::Data<AppState>,
+    body: web::Json<AlertRule>,
+) -> impl Responder {
+    let names = state.rules.read().unwrap().threshold_names();
+    if let Err(message) = rules::compile(body.clone(), &names) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_rule", &message));
+    }
+    
+    let id = match state.db.insert_alert_rule(&body).await {
+        Ok(id) => id,
+        Err(e) => return db_error(e, "Failed to save rule"),
+    };
+    
+    if let Err(e) = reload_rules(&state).await {
+        error!("Failed to reload rules: {}", e);
+    }
+    
+    info!("Alert rule '{}' created{}: {}", body.name, if body.shadow { " in shadow mode" } else { "" }, body.expression);
+    HttpResponse::Created().json(serde_json::json!({
+        "status": "ok",
+        "id": id
+    }))
+}
+
+#[delete("/api/rules/{id}")]
+pub async fn delete_rule(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match state.db.delete_alert_rule(id).await {
+        Ok(true) => {
+            if let Err(e) = reload_rules(&state).await {
+                error!("Failed to reload rules: {}", e);
+            }
+            info!("Alert rule {} deleted", id);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Rule {} not found", id))),
+        Err(e) => db_error(e, "Failed to delete rule"),
+    }
+}
+
+/// Query params for a rule's shadow hits
+#[derive(Debug, Deserialize)]
+pub struct ShadowHitsQuery {
+    /// RFC 3339 start time, default 7 days before `to`
+    pub from: Option<chrono::DateTime<Utc>>,
+    /// RFC 3339 end time, default now
+    pub to: Option<chrono::DateTime<Utc>>,
+    /// Most hits listed, newest first; all are counted
+    #[serde(default = "default_shadow_hits_limit")]
+    pub limit: usize,
+}
+
+fn default_shadow_hits_limit() -> usize {
+    100
+}
+
+const SHADOW_HITS_DEFAULT_DAYS: i64 = 7;
+const MAX_SHADOW_HITS_LIMIT: usize = 1000;
+
+/// How often a rule matched while in shadow mode
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShadowReview {
+    pub rule: AlertRule,
+    pub from: chrono::DateTime<Utc>,
+    pub to: chrono::DateTime<Utc>,
+    /// Hits in the period, each a run of matching readings in a room, i.e.
+    /// the alerts the rule would have raised
+    pub total: u64,
+    pub per_day: f64,
+    pub by_room: BTreeMap<String, u64>,
+    pub hits: Vec<ShadowHit>,
+}
+
+/// GET /api/rules/{id}/shadow-hits
+/// 
+/// Review a shadow rule before promoting it: the alerts it would have
+/// raised against live readings, per room and in total.
+/// Example: /api/rules/3/shadow-hits?from=2024-03-01T00:00:00Z&limit=20
+#[get("/api/rules/{id}/shadow-hits")]
+pub async fn get_shadow_hits(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    query: web::Query<ShadowHitsQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    debug!("GET /api/rules/{}/shadow-hits", id);
+    
+    let rule = match state.db.get_alert_rules().await {
+        Ok(rules) => rules.into_iter().find(|r| r.id == id),
+        Err(e) => return db_error(e, "Failed to retrieve rules"),
+    };
+    let Some(rule) = rule else {
+        return HttpResponse::NotFound().json(ApiError::not_found(&format!("Rule {} not found", id)));
+    };
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::days(SHADOW_HITS_DEFAULT_DAYS));
+    if from >= to {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must be before to"));
+    }
+    
+    let limit = query.limit.min(MAX_SHADOW_HITS_LIMIT);
+    let (counts, hits) = match tokio::try_join!(
+        state.db.count_shadow_hits(id, from, to),
+        state.db.get_shadow_hits(id, from, to, limit),
+    ) {
+        Ok(result) => result,
+        Err(e) => return db_error(e, "Failed to retrieve shadow hits"),
+    };
+    let total = counts.values().sum();
+    let days = (to - from).num_seconds() as f64 / 86_400.0;
+    
+    HttpResponse::Ok().json(ShadowReview {
+        rule,
+        from,
+        to,
+        total,
+        per_day: total as f64 / days,
+        by_room: counts.into_iter().collect(),
+        hits,
+    })
+}
+
+/// POST /api/rules/{id}/promote
+/// 
+/// Turn a shadow rule into a live one, raising alerts from now on
+#[post("/api/rules/{id}/promote")]
+pub async fn promote_rule(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match state.db.set_alert_rule_shadow(id, false).await {
+        Ok(true) => {
+            if let Err(e) = reload_rules(&state).await {
+                error!("Failed to reload rules: {}", e);
+            }
+            info!("Alert rule {} promoted from shadow to live", id);
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "ok",
+                "id": id
+            }))
+        }
+        Ok(false) => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Rule {} not found", id))),
+        Err(e) => db_error(e, "Failed to promote rule"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThresholdValue {
+    pub value: f64,
+}
+
+/// PUT /api/rules/thresholds/{name}
+/// 
+/// Define a named threshold usable as `threshold("name")` in rules
+#[put("/api/rules/thresholds/{name}")]
+pub async fn set_rule_threshold(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<ThresholdValue>,
+) -> impl Responder {
+    let name = path.into_inner();
+    
+    if rules::BUILTIN_THRESHOLDS.contains(&name.as_str()) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_rule", 
+            &format!("'{}' is a built-in threshold; change it via /api/settings", name)));
+    }
+    
+    if let Err(e) = state.db.set_rule_threshold(&name, body.value).await {
+        return db_error(e, "Failed to save threshold");
+    }
+    
+    if let Err(e) = reload_rules(&state).await {
+        error!("Failed to reload rules: {}", e);
+    }
+    
+    info!("Rule threshold '{}' set to {}", name, body.value);
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok",
+        "message": "Threshold updated successfully"
+    }))
+}
+
+/// Longest accepted tag name
+const MAX_TAG_LEN: usize = 100;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagRequest {
+    /// Readings to tag by id
+    #[serde(default)]
+    pub reading_ids: Vec<i64>,
+    /// Or every reading in a time range
+    pub start: Option<chrono::DateTime<Utc>>,
+    pub end: Option<chrono::DateTime<Utc>>,
+    /// With a time range, only tag readings that raised an alert
+    #[serde(default)]
+    pub alerts_only: bool,
+}
+
+/// GET /api/tags
+/// 
+/// All tags with the number of readings and alerts they label
+#[get("/api/tags")]
+pub async fn list_tags(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/tags");
+    
+    match state.db.get_tags().await {
+        Ok(tags) => HttpResponse::Ok().json(tags),
+        Err(e) => db_error(e, "Failed to retrieve tags"),
+    }
+}
+
+/// POST /api/tags/{name}
+/// 
+/// Example body: {"readingIds": [101, 102]}
+///           or: {"start": "2024-01-15T00:00:00Z", "end": "2024-01-16T00:00:00Z", "alertsOnly": true}
+#[post("/api/tags/{name}")]
+pub async fn apply_tag(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<TagRequest>,
+) -> impl Responder {
+    let name = path.into_inner().trim().to_string();
+    if name.is_empty() || name.len() > MAX_TAG_LEN {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_tag",
+            &format!("Tag names must be 1-{} characters", MAX_TAG_LEN)));
+    }
+    
+    let result = match (&body.reading_ids[..], body.start, body.end) {
+        (ids, None, None) if !ids.is_empty() => state.db.tag_readings(&name, ids).await,
+        ([], Some(start), Some(end)) if start <= end => {
+            state.db.tag_range(&name, start, end, body.alerts_only).await
+        }
+        _ => {
+            return HttpResponse::BadRequest().json(ApiError::new("invalid_tag",
+                "Expected either readingIds or a start/end range"));
+        }
+    };
+    
+    match result {
+        Ok(tagged) => {
+            info!("Tagged {} readings with '{}'", tagged, name);
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "ok",
+                "tagged": tagged
+            }))
+        }
+        Err(e) => db_error(e, "Failed to tag readings"),
+    }
+}
+
+#[delete("/api/tags/{name}")]
+pub async fn delete_tag(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let name = path.into_inner();
+    
+    match state.db.delete_tag(&name).await {
+        Ok(true) => {
+            info!("Tag '{}' deleted", name);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Tag '{}' not found", name))),
+        Err(e) => db_error(e, "Failed to delete tag"),
+    }
+}
+
+#[delete("/api/tags/{name}/readings/{id}")]
+pub async fn untag_reading(
+    state: web::Data<AppState>,
+    path: web::Path<(String, i64)>,
+) -> impl Responder {
+    let (name, id) = path.into_inner();
+    
+    match state.db.untag_reading(&name, id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Reading {} is not tagged '{}'", id, name))),
+        Err(e) => db_error(e, "Failed to remove tag"),
+    }
+}
+
+#[get("/api/observations/{id}/tags")]
+pub async fn get_observation_tags(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    let id = path.into_inner();
+    debug!("GET /api/observations/{}/tags", id);
+    
+    match state.db.get_reading_tags(id).await {
+        Ok(tags) => HttpResponse::Ok().json(tags),
+        Err(e) => db_error(e, "Failed to retrieve tags"),
+    }
+}
+
+/// Longest a single snooze may last
+const MAX_SNOOZE_MINUTES: i64 = 240;
+
+#[derive(Debug, Deserialize)]
+pub struct SnoozeQuery {
+    #[serde(default = "default_snooze_minutes")]
+    pub minutes: i64,
+}
+
+fn default_snooze_minutes() -> i64 {
+    30
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SnoozeRequest {
+    /// Staff member snoozing the alert
+    pub by: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// Selects one alert type when a reading raised several
+#[derive(Debug, Deserialize)]
+pub struct AlertTypeQuery {
+    #[serde(rename = "type")]
+    pub alert_type: Option<AlertType>,
+}
+
+/// Room of reading `id` and the alert types to act on: the requested one, or all it raised
+async fn alerts_of_reading(
+    state: &AppState,
+    id: i64,
+    only: Option<AlertType>,
+) -> Result<(String, Vec<AlertType>), HttpResponse> {
+    let (room, alerts) = match state.db.get_reading_by_id(id).await {
+        Ok(Some(event)) if !event.alerts.is_empty() => (event.room, event.alerts),
+        Ok(Some(_)) => return Err(HttpResponse::BadRequest()
+            .json(ApiError::new("not_an_alert", &format!("Observation {} did not raise an alert", id)))),
+        Ok(None) => return Err(HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Alert {} not found", id)))),
+        Err(e) => return Err(db_error(e, "Failed to retrieve alert")),
+    };
+    
+    match only {
+        Some(alert) if alerts.contains(alert) => Ok((room, vec![alert])),
+        Some(alert) => Err(HttpResponse::BadRequest()
+            .json(ApiError::new("not_an_alert", &format!("Observation {} did not raise {:?}", id, alert)))),
+        None => Ok((room, alerts.iter().collect())),
+    }
+}
+
+/// POST /api/alerts/{id}/snooze?minutes=30[&type=fall]
+/// 
+/// Suppress re-notification of the alert's condition for a while. The alert
+/// itself stays open; the snooze expires on its own. Without `type`, every
+/// alert type the reading raised is snoozed.
+/// Optional body: {"by": "nurse-42", "reason": "patient repositioning"}
+#[post("/api/alerts/{id}/snooze")]
+pub async fn snooze_alert(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    query: web::Query<SnoozeQuery>,
+    only: web::Query<AlertTypeQuery>,
+    body: Option<web::Json<SnoozeRequest>>,
+    request_id: RequestId,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    if !(1..=MAX_SNOOZE_MINUTES).contains(&query.minutes) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_snooze",
+            &format!("Snooze must be 1-{} minutes", MAX_SNOOZE_MINUTES)));
+    }
+    
+    let (room, alerts) = match alerts_of_reading(&state, id, only.alert_type).await {
+        Ok(alerts) => alerts,
+        Err(response) => return response,
+    };
+    
+    let body = body.map(|b| b.into_inner()).unwrap_or_default();
+    let until = Utc::now() + Duration::minutes(query.minutes);
+    let mut snoozes = Vec::new();
+    
+    for alert in alerts {
+        let snooze = AlertSnooze {
+            reading_id: id,
+            room: room.clone(),
+            alert,
+            until,
+            snoozed_by: body.by.clone(),
+            reason: body.reason.clone(),
+        };
+        
+        if let Err(e) = state.db.insert_snooze(&snooze).await {
+            return db_error(e, "Failed to snooze alert");
+        }
+        state.snoozes.snooze(&room, alert, until);
+        
+        let audit = AuditEntry {
+            action: "alert.snooze".to_string(),
+            subject: format!("alert/{}", id),
+            actor: snooze.snoozed_by.clone(),
+            request_id: Some(request_id.0.clone()),
+            detail: Some(format!("{:?} snoozed for {} minutes until {}{}", alert, query.minutes,
+                until.to_rfc3339(),
+                snooze.reason.as_deref().map(|r| format!(": {}", r)).unwrap_or_default())),
+        };
+        if let Err(e) = state.db.insert_audit(&audit).await {
+            error!("Failed to write audit entry: {}", e);
+        }
+        
+        info!("{:?} alerts in {} snoozed until {} (alert {})", alert, room, until, id);
+        snoozes.push(snooze);
+    }
+    
+    HttpResponse::Ok().json(snoozes)
+}
+
+/// DELETE /api/alerts/{id}/snooze[?type=fall]
+/// 
+/// End the snoozes covering this alert's conditions early
+#[delete("/api/alerts/{id}/snooze")]
+pub async fn unsnooze_alert(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    only: web::Query<AlertTypeQuery>,
+    request_id: RequestId,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    let (room, alerts) = match alerts_of_reading(&state, id, only.alert_type).await {
+        Ok(alerts) => alerts,
+        Err(response) => return response,
+    };
+    
+    let mut ended_any = false;
+    for alert in alerts {
+        let ended = match state.db.end_snooze(&room, alert).await {
+            Ok(ended) => ended,
+            Err(e) => return db_error(e, "Failed to end snooze"),
+        };
+        state.snoozes.unsnooze(&room, alert);
+        if !ended {
+            continue;
+        }
+        ended_any = true;
+        
+        let audit = AuditEntry {
+            action: "alert.unsnooze".to_string(),
+            subject: format!("alert/{}", id),
+            actor: None,
+            request_id: Some(request_id.0.clone()),
+            detail: Some(format!("{:?} snooze ended early", alert)),
+        };
+        if let Err(e) = state.db.insert_audit(&audit).await {
+            error!("Failed to write audit entry: {}", e);
+        }
+        
+        info!("{:?} alert snooze in {} ended (alert {})", alert, room, id);
+    }
+    
+    if !ended_any {
+        return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Alert {} is not snoozed", id)));
+    }
+    HttpResponse::NoContent().finish()
+}
+
+const MAX_ALERTS: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct AlertListQuery {
+    /// Only alerts that haven't resolved yet
+    #[serde(default)]
+    pub open: bool,
+    pub room: Option<String>,
+    #[serde(default = "default_alert_limit")]
+    pub limit: i64,
+}
+
+fn default_alert_limit() -> i64 {
+    100
+}
+
+/// GET /api/alerts?open=true&room=room-101&limit=100
+/// 
+/// Alert episodes, newest first. An alert opens with the first reading that
+/// raises it and resolves with the first one that doesn't.
+#[get("/api/alerts")]
+pub async fn list_alerts(
+    state: web::Data<AppState>,
+    query: web::Query<AlertListQuery>,
+) -> impl Responder {
+    debug!("GET /api/alerts");
+    
+    let limit = query.limit.clamp(1, MAX_ALERTS);
+    match state.db.get_alerts(query.open, query.room.as_deref(), limit).await {
+        Ok(alerts) => HttpResponse::Ok().json(alerts),
+        Err(e) => db_error(e, "Failed to retrieve alerts"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlertStatsQuery {
+    /// `day` (default) or `week`
+    #[serde(default = "default_stats_bucket")]
+    pub bucket: String,
+    /// First facility day, default 30 days or 12 weeks before `to`
+    pub from: Option<NaiveDate>,
+    /// Last facility day, default today
+    pub to: Option<NaiveDate>,
+    pub room: Option<String>,
+}
+
+fn default_stats_bucket() -> String {
+    "day".to_string()
+}
+
+const ALERT_STATS_DEFAULT_DAYS: i64 = 30;
+const ALERT_STATS_DEFAULT_WEEKS: i64 = 12;
+const MAX_ALERT_STATS_DAYS: i64 = 366;
+
+/// GET /api/alerts/stats?bucket=day|week&from=&to=&room=
+/// 
+/// Alerts triggered per facility day or week, by type, oldest first, to
+/// compare e.g. this week's falls with last week's. Weeks start on Monday.
+#[get("/api/alerts/stats")]
+pub async fn get_alert_stats(
+    state: web::Data<AppState>,
+    query: web::Query<AlertStatsQuery>,
+) -> impl Responder {
+    debug!("GET /api/alerts/stats");
+    
+    let bucket: Bucket = match query.bucket.parse() {
+        Ok(bucket) => bucket,
+        Err(e) => return HttpResponse::BadRequest().json(ApiError::new("invalid_bucket", &e)),
+    };
+    let to = query.to.unwrap_or_else(|| state.days.today());
+    let from = query.from.unwrap_or(match bucket {
+        Bucket::Day => to - Duration::days(ALERT_STATS_DEFAULT_DAYS - 1),
+        Bucket::Week => to - Duration::weeks(ALERT_STATS_DEFAULT_WEEKS - 1),
+    });
+    if from > to {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must not be after to"));
+    }
+    if (to - from).num_days() >= MAX_ALERT_STATS_DAYS {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_range",
+            &format!("At most {} days can be requested", MAX_ALERT_STATS_DAYS)));
+    }
+    
+    let spans = bucket.spans(&state.days, from, to);
+    match state.db.get_alert_counts(&spans, query.room.as_deref()).await {
+        Ok(buckets) => HttpResponse::Ok().json(AlertStats {
+            bucket: bucket.as_str().to_string(),
+            from,
+            to,
+            room: query.room.clone(),
+            buckets,
+        }),
+        Err(e) => db_error(e, "Failed to count alerts"),
+    }
+}
+
+#[get("/api/alerts/snoozes")]
+pub async fn list_snoozes(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/alerts/snoozes");
+    
+    match state.db.get_active_snoozes().await {
+        Ok(snoozes) => HttpResponse::Ok().json(snoozes),
+        Err(e) => db_error(e, "Failed to retrieve snoozes"),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AckRequest {
+    pub by: Option<String>,
+    /// How staff judged the alert, if they already know
+    pub outcome: Option<AlertOutcome>,
+}
+
+/// Acknowledge alert `id` and audit it; shared by the API and chat integrations
+pub async fn acknowledge(
+    state: &AppState,
+    id: i64,
+    by: Option<&str>,
+    outcome: Option<AlertOutcome>,
+    via: &str,
+    request_id: &RequestId,
+) -> HttpResponse {
+    let (room, alerts) = match alerts_of_reading(state, id, None).await {
+        Ok(alerts) => alerts,
+        Err(response) => return response,
+    };
+    
+    let (ack, new) = match state.db.ack_alert(id, by, via).await {
+        Ok(result) => result,
+        Err(e) => return db_error(e, "Failed to acknowledge alert"),
+    };
+    let ack_resolved: Vec<AlertType> = alerts
+        .iter()
+        .copied()
+        .filter(|a| state.alert_resolution.policy(*a) == AutoResolve::Ack)
+        .collect();
+    
+    // A repeated ack (e.g. a second click in the channel) returns the first one
+    if new {
+        let audit = AuditEntry {
+            action: "alert.ack".to_string(),
+            subject: format!("alert/{}", id),
+            actor: by.map(str::to_string),
+            request_id: Some(request_id.0.clone()),
+            detail: Some(format!("{} acknowledged via {}",
+                alerts.iter().map(|a| format!("{:?}", a)).collect::<Vec<_>>().join(", "), via)),
+        };
+        if let Err(e) = state.db.insert_audit(&audit).await {
+            error!("Failed to write audit entry: {}", e);
+        }
+        info!("Alert {} acknowledged via {}", id, via);
+        
+        let notice = AckNotice {
+            reading_id: id,
+            room,
+            alerts: alerts.clone(),
+            by: by.map(str::to_string),
+            via: via.to_string(),
+            at: ack.acked_at,
+        };
+        if state.alert_acks.send(notice).is_err() {
+            warn!("Notifier is not running, acknowledgement of alert {} not notified", id);
+        }
+    }
+    
+    // Alerts that don't resolve by themselves, such as falls, end here.
+    // Also on a repeated ack, in case resolving failed the first time.
+    if !ack_resolved.is_empty() {
+        match state.db.resolve_acknowledged(id, &ack_resolved, ack.acked_at).await {
+            Ok(resolved) => {
+                for alert in resolved {
+                    info!("{:?} alert resolved in {} by acknowledgement", alert.alert, alert.room);
+                    state.broadcaster.broadcast(LiveEvent::AlertResolved { alert, reading: None });
+                }
+            }
+            Err(e) => error!("Failed to resolve acknowledged alert {}: {}", id, e),
+        }
+    }
+    
+    if let Some(outcome) = outcome {
+        if let Err(e) = record_outcome(state, id, &alerts, outcome, by, request_id).await {
+            return db_error(e, "Failed to record alert outcome");
+        }
+    }
+    
+    HttpResponse::Ok().json(ack)
+}
+
+/// Record staff's judgement of the alerts of reading `id` and audit it
+async fn record_outcome(
+    state: &AppState,
+    id: i64,
+    alerts: &[AlertType],
+    outcome: AlertOutcome,
+    by: Option<&str>,
+    request_id: &RequestId,
+) -> Result<Vec<Alert>, DbError> {
+    let updated = state.db.set_alert_outcome(id, alerts, outcome).await?;
+    
+    let audit = AuditEntry {
+        action: "alert.outcome".to_string(),
+        subject: format!("alert/{}", id),
+        actor: by.map(str::to_string),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("{} judged {}",
+            updated.iter().map(|a| format!("{:?}", a.alert)).collect::<Vec<_>>().join(", "), outcome.as_str())),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    Ok(updated)
+}
+
+/// POST /api/alerts/{id}/ack
+/// 
+/// Acknowledge an alert. Acknowledging twice keeps the first acknowledgement.
+/// Optional body: {"by": "nurse-42"}
+#[post("/api/alerts/{id}/ack")]
+pub async fn ack_alert(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    body: Option<web::Json<AckRequest>>,
+    request_id: RequestId,
+) -> impl Responder {
+    let body = body.map(|b| b.into_inner()).unwrap_or_default();
+    acknowledge(&state, path.into_inner(), body.by.as_deref(), body.outcome, "api", &request_id).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OutcomeRequest {
+    pub outcome: AlertOutcome,
+    pub by: Option<String>,
+}
+
+/// PUT /api/alerts/{id}/outcome[?type=fall]
+/// 
+/// Record whether an alert was a confirmed incident, a false alarm or
+/// couldn't be told, e.g. once it resolved. Replaces an outcome recorded
+/// before. Without `type`, every alert the reading raised is judged.
+/// Body: {"outcome": "false_alarm", "by": "nurse-42"}
+#[put("/api/alerts/{id}/outcome")]
+pub async fn set_alert_outcome(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    query: web::Query<AlertTypeQuery>,
+    body: web::Json<OutcomeRequest>,
+    request_id: RequestId,
+) -> impl Responder {
+    let id = path.into_inner();
+    let (_, alerts) = match alerts_of_reading(&state, id, query.alert_type).await {
+        Ok(alerts) => alerts,
+        Err(response) => return response,
+    };
+    
+    match record_outcome(&state, id, &alerts, body.outcome, body.by.as_deref(), &request_id).await {
+        Ok(updated) if updated.is_empty() => HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("No alert episode of observation {} is stored", id))),
+        Ok(updated) => HttpResponse::Ok().json(updated),
+        Err(e) => db_error(e, "Failed to record alert outcome"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrecisionQuery {
+    /// First facility day, default 30 days before `to`
+    pub from: Option<NaiveDate>,
+    /// Last facility day, default today
+    pub to: Option<NaiveDate>,
+    /// `day` or `week` (default)
+    #[serde(default = "default_precision_interval")]
+    pub interval: String,
+    /// `type` (default) or `room`
+    #[serde(default = "default_precision_by")]
+    pub by: String,
+}
+
+fn default_precision_interval() -> String {
+    "week".to_string()
+}
+
+fn default_precision_by() -> String {
+    "type".to_string()
+}
+
+const PRECISION_DEFAULT_DAYS: i64 = 30;
+const MAX_PRECISION_DAYS: i64 = 366;
+
+/// GET /api/analytics/alert-precision
+/// 
+/// Outcomes staff recorded for the alerts triggered in each facility day or
+/// week, per alert type or per room, and the share of confirmed incidents
+/// among the alerts judged, to follow how alarm fatigue develops. Alerts
+/// without an outcome or judged unknown don't count towards precision.
+/// Example: /api/analytics/alert-precision?from=2024-01-01&to=2024-03-31&interval=week&by=room
+#[get("/api/analytics/alert-precision")]
+pub async fn get_alert_precision(
+    state: web::Data<AppState>,
+    query: web::Query<PrecisionQuery>,
+    room: web::Query<RoomQuery>,
+) -> impl Responder {
+    debug!("GET /api/analytics/alert-precision");
+    
+    let weekly = match query.interval.as_str() {
+        "day" => false,
+        "week" => true,
+        other => return HttpResponse::BadRequest().json(ApiError::new("invalid_interval",
+            &format!("Unknown interval '{}', expected day or week", other))),
+    };
+    let by_room = match query.by.as_str() {
+        "type" => false,
+        "room" => true,
+        other => return HttpResponse::BadRequest().json(ApiError::new("invalid_group_by",
+            &format!("Unknown grouping '{}', expected type or room", other))),
+    };
+    let to = query.to.unwrap_or_else(|| state.days.today());
+    let from = query.from.unwrap_or(to - Duration::days(PRECISION_DEFAULT_DAYS - 1));
+    if from > to {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must not be after to"));
+    }
+    if (to - from).num_days() >= MAX_PRECISION_DAYS {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_range",
+            &format!("At most {} days can be requested", MAX_PRECISION_DAYS)));
+    }
+    
+    let start = state.days.start_of(from);
+    let end = state.days.start_of(to + Duration::days(1));
+    let alerts = match state.db.get_alerts_in_range(start, end, room.room.as_deref()).await {
+        Ok(alerts) => alerts,
+        Err(e) => return db_error(e, "Failed to retrieve alerts"),
+    };
+    
+    let mut total = OutcomeCounts::default();
+    let mut groups: BTreeMap<(NaiveDate, String), OutcomeCounts> = BTreeMap::new();
+    for alert in &alerts {
+        let period = if weekly {
+            state.days.day_of(state.days.week_start(alert.triggered_at))
+        } else {
+            state.days.day_of(alert.triggered_at)
+        };
+        let key = if by_room { alert.room.clone() } else { alert.alert.as_str().to_string() };
+        groups.entry((period, key)).or_default().add(alert.outcome);
+        total.add(alert.outcome);
+    }
+    
+    let groups = groups
+        .into_iter()
+        .map(|((period, key), counts)| PrecisionGroup {
+            period,
+            alert_type: (!by_room).then(|| key.clone()),
+            room: by_room.then_some(key),
+            precision: counts.precision(),
+            counts,
+        })
+        .collect();
+    HttpResponse::Ok().json(AlertPrecisionReport {
+        from,
+        to,
+        interval: query.interval.clone(),
+        by: query.by.clone(),
+        groups,
+        precision: total.precision(),
+        total,
+    })
+}
+
+/// Minutes of readings shown either side of the observation in its chart
+const CHART_WINDOW_MINUTES: i64 = 30;
+const CHART_WIDTH: f64 = 600.0;
+const CHART_HEIGHT: f64 = 200.0;
+
+/// GET /api/observations/{id}/chart.svg
+/// 
+/// Mini chart of sound level and motion around an observation, linked from
+/// chat alert cards. The observation's time is marked with a red line.
+#[get("/api/observations/{id}/chart.svg")]
+pub async fn get_observation_chart(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    access: AccessContext,
+) -> impl Responder {
+    let id = path.into_inner();
+    debug!("GET /api/observations/{}/chart.svg", id);
+    
+    let event = match state.db.get_reading_by_id(id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Observation {} not found", id))),
+        Err(e) => return db_error(e, "Failed to retrieve observation"),
+    };
+    
+    let at = event.reading.timestamp;
+    let window = Duration::minutes(CHART_WINDOW_MINUTES);
+    let readings = match state.db.get_readings_in_range(at - window, at + window, Some(&event.room), None, None, None).await {
+        Ok(readings) => readings,
+        Err(e) => return db_error(e, "Failed to retrieve readings"),
+    };
+    access.record(&state.db, Some((at - window, at + window)), Some(&event.room), &readings).await;
+    
+    HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .body(chart_svg(&readings, at - window, at, &state.settings.read().unwrap_or_else(|e| e.into_inner())))
+}
+
+fn chart_svg(
+    readings: &[SensorEvent],
+    start: chrono::DateTime<Utc>,
+    at: chrono::DateTime<Utc>,
+    settings: &MonitorSettings,
+) -> String {
+    let span = (CHART_WINDOW_MINUTES * 2 * 60) as f64;
+    let x = |t: chrono::DateTime<Utc>| (t - start).num_seconds() as f64 / span * CHART_WIDTH;
+    let max_sound = readings
+        .iter()
+        .map(|e| e.reading.sound_level)
+        .chain([settings.sound_threshold])
+        .max()
+        .unwrap_or(1)
+        .max(1) as f64;
+    let y = |level: i32| CHART_HEIGHT - level as f64 / max_sound * (CHART_HEIGHT - 10.0);
+    
+    // Readings arrive newest first
+    let points: Vec<String> = readings
+        .iter()
+        .rev()
+        .map(|e| format!("{:.1},{:.1}", x(e.reading.timestamp), y(e.reading.sound_level)))
+        .collect();
+    let motion: String = readings
+        .iter()
+        .filter(|e| e.reading.motion)
+        .map(|e| format!(r##"<rect x="{:.1}" y="{}" width="2" height="8" fill="#2e7d32"/>"##,
+            x(e.reading.timestamp), CHART_HEIGHT - 8.0))
+        .collect();
+    let threshold = y(settings.sound_threshold);
+    let marker = x(at);
+    
+    format!(
+        concat!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"##,
+            r##"<rect width="{w}" height="{h}" fill="#fafafa"/>"##,
+            r##"<line x1="0" y1="{t:.1}" x2="{w}" y2="{t:.1}" stroke="#999" stroke-dasharray="4 4"/>"##,
+            r##"{motion}"##,
+            r##"<polyline points="{points}" fill="none" stroke="#1565c0" stroke-width="1.5"/>"##,
+            r##"<line x1="{m:.1}" y1="0" x2="{m:.1}" y2="{h}" stroke="#d32f2f" stroke-width="2"/>"##,
+            r##"</svg>"##,
+        ),
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT,
+        t = threshold,
+        m = marker,
+        motion = motion,
+        points = points.join(" "),
+    )
+}
+
+/// Default period of an access log export
+const ACCESS_LOG_DEFAULT_DAYS: i64 = 30;
+
+#[derive(Debug, Deserialize)]
+pub struct AccessLogQuery {
+    /// RFC 3339 start time, default 30 days before `end`
+    pub start: Option<chrono::DateTime<Utc>>,
+    /// RFC 3339 end time, default now
+    pub end: Option<chrono::DateTime<Utc>>,
+    pub principal: Option<String>,
+    /// `json` (default) or `csv`
+    pub format: Option<String>,
+}
+
+/// GET /api/access-log
+/// 
+/// Export the PHI access log for the privacy office. Exports are audited.
+/// Example: /api/access-log?start=2024-01-01T00:00:00Z&principal=dr.smith&format=csv
+#[get("/api/access-log")]
+pub async fn export_access_log(
+    state: web::Data<AppState>,
+    query: web::Query<AccessLogQuery>,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    debug!("GET /api/access-log");
+    
+    let csv = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => return HttpResponse::BadRequest().json(ApiError::new("invalid_format",
+            &format!("Unknown format '{}', expected json or csv", other))),
+    };
+    let end = query.end.unwrap_or_else(Utc::now);
+    let start = query.start.unwrap_or(end - Duration::days(ACCESS_LOG_DEFAULT_DAYS));
+    if start > end {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "start must be before end"));
+    }
+    
+    let records = match state.db.get_phi_access_log(start, end, query.principal.as_deref()).await {
+        Ok(records) => records,
+        Err(e) => return db_error(e, "Failed to retrieve access log"),
+    };
+    
+    let audit = AuditEntry {
+        action: "access_log.export".to_string(),
+        subject: "access_log".to_string(),
+        actor: access.principal.clone(),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("{} entries from {} to {}{}", records.len(), start.to_rfc3339(),
+            end.to_rfc3339(),
+            query.principal.as_deref().map(|p| format!(" for {}", p)).unwrap_or_default())),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    
+    if csv {
+        HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header(("Content-Disposition", "attachment; filename=\"phi-access-log.csv\""))
+            .body(access_log::to_csv(&records))
+    } else {
+        HttpResponse::Ok().json(records)
+    }
+}
+
+/// Default period of a readings export
+const EXPORT_DEFAULT_DAYS: i64 = 7;
+
+/// Columns, pseudonymization and rounding of a readings export, or why the
+/// request is invalid
+fn export_options(
+    state: &AppState,
+    columns: Option<&str>,
+    pseudonymize: bool,
+    round: Option<i64>,
+) -> Result<ExportOptions, ApiError> {
+    let columns = match columns.map(export::parse_columns) {
+        None => Column::DEFAULT.to_vec(),
+        Some(Ok(columns)) => columns,
+        Some(Err(msg)) => return Err(ApiError::new("invalid_columns", &msg)),
+    };
+    let pseudonym_key = match (pseudonymize, &state.export.pseudonym_key) {
+        (false, _) => None,
+        (true, Some(key)) => Some(key.clone()),
+        (true, None) => return Err(ApiError::new("pseudonymization_unavailable",
+            "Pseudonymized exports need EXPORT_PSEUDONYM_KEY to be configured")),
+    };
+    let round = match round {
+        None => None,
+        Some(secs) if (1..=86400).contains(&secs) => Some(Duration::seconds(secs)),
+        Some(_) => return Err(ApiError::new("invalid_round", "round must be between 1 and 86400 seconds")),
+    };
+    Ok(ExportOptions { columns, pseudonym_key, round })
+}
+
+/// Columns and anonymization of an export, for its audit entry
+fn export_detail(options: &ExportOptions) -> String {
+    format!(", columns {}{}{}",
+        options.columns.iter().map(|c| c.as_str()).collect::<Vec<_>>().join(","),
+        if options.pseudonym_key.is_some() { ", pseudonymized" } else { "" },
+        options.round.map(|r| format!(", rounded to {}s", r.num_seconds())).unwrap_or_default())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// RFC 3339 start time, default 7 days before `end`
+    pub start: Option<chrono::DateTime<Utc>>,
+    /// RFC 3339 end time, default now
+    pub end: Option<chrono::DateTime<Utc>>,
+    /// Comma-separated columns, in output order
+    pub columns: Option<String>,
+    /// Replace room and patient IDs with stable pseudonyms
+    #[serde(default)]
+    pub pseudonymize: bool,
+    /// Round timestamps down to a multiple of this many seconds
+    pub round: Option<i64>,
+    /// `json` (default) or `csv`
+    pub format: Option<String>,
+}
+
+/// GET /api/export/readings
+/// 
+/// Export readings for research, of patients who consent to it. Exports are
+/// audited.
+/// Example: /api/export/readings?start=2024-01-01T00:00:00Z&columns=timestamp,room,sound_level&pseudonymize=true&round=3600&format=csv
+#[get("/api/export/readings")]
+pub async fn export_readings(
+    state: web::Data<AppState>,
+    query: web::Query<ExportQuery>,
+    room: web::Query<RoomQuery>,
+    tag: web::Query<TagQuery>,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    debug!("GET /api/export/readings");
+    
+    let csv = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => return HttpResponse::BadRequest().json(ApiError::new("invalid_format",
+            &format!("Unknown format '{}', expected json or csv", other))),
+    };
+    let options = match export_options(&state, query.columns.as_deref(), query.pseudonymize, query.round) {
+        Ok(options) => options,
+        Err(invalid) => return HttpResponse::BadRequest().json(invalid),
+    };
+    let end = query.end.unwrap_or_else(Utc::now);
+    let start = query.start.unwrap_or(end - Duration::days(EXPORT_DEFAULT_DAYS));
+    if start > end {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "start must be before end"));
+    }
+    
+    let room = room.room.as_deref();
+    let mut consenting = match consenting_readings(&state, &access, room, |c| c.research_export).await {
+        Ok(consenting) => consenting,
+        Err(denied) => return denied,
+    };
+    
+    let mut events = match state.db.get_readings_in_range(start, end, room, tag.tag.as_deref(), None, None).await {
+        Ok(events) => oldest_first(events),
+        Err(e) => return db_error(e, "Failed to retrieve readings"),
+    };
+    if let Err(e) = consenting.retain(&state.db, &mut events).await {
+        return db_error(e, "Failed to check patient consent");
+    }
+    access.record(&state.db, Some((start, end)), room, &events).await;
+    
+    let audit = AuditEntry {
+        action: "readings.export".to_string(),
+        subject: room.unwrap_or("*").to_string(),
+        actor: access.principal.clone(),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("{} readings from {} to {}{}", events.len(), start.to_rfc3339(),
+            end.to_rfc3339(), export_detail(&options))),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    
+    if csv {
+        HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header(("Content-Disposition", "attachment; filename=\"readings.csv\""))
+            .body(options.to_csv(&events))
+    } else {
+        HttpResponse::Ok().json(options.to_json(&events))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamExportQuery {
+    /// `csv` (default) or `parquet`
+    pub format: Option<String>,
+    /// RFC 3339 start time, default 7 days before `to`
+    pub from: Option<chrono::DateTime<Utc>>,
+    /// RFC 3339 end time, default now
+    pub to: Option<chrono::DateTime<Utc>>,
+    /// Comma-separated columns, in output order
+    pub columns: Option<String>,
+    /// Replace room and patient IDs with stable pseudonyms
+    #[serde(default)]
+    pub pseudonymize: bool,
+    /// Round timestamps down to a multiple of this many seconds
+    pub round: Option<i64>,
+}
+
+/// Writes the pages of a streaming export
+enum ExportWriter {
+    Csv(ExportOptions),
+    Parquet(Box<ParquetExport>),
+}
+
+#[derive(Debug)]
+enum ExportFailure {
+    Db(DbError),
+    Parquet(ParquetError),
+}
+
+impl std::fmt::Display for ExportFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFailure::Db(e) => write!(f, "{}", e),
+            ExportFailure::Parquet(e) => write!(f, "writing Parquet failed: {}", e),
+        }
+    }
+}
+
+/// Readings export sent a page at a time
+struct ExportStream {
+    db: Database,
+    access: AccessContext,
+    room: Option<String>,
+    consenting: ConsentingReadings,
+    start: chrono::DateTime<Utc>,
+    end: chrono::DateTime<Utc>,
+    /// Timestamp and id of the last reading read
+    after: Option<(chrono::DateTime<Utc>, i64)>,
+    /// CSV header, sent with the first page
+    header: Option<String>,
+    pages: usize,
+    /// `None` once the export is complete
+    writer: Option<ExportWriter>,
+}
+
+impl ExportStream {
+    /// Next chunk of the file, or `None` once it is complete
+    async fn next_chunk(&mut self) -> Option<Result<Vec<u8>, ExportFailure>> {
+        self.writer.as_ref()?;
+        let mut page = match self.db.export_range(self.start, self.end, self.room.as_deref(), self.after, EXPORT_PAGE_SIZE).await {
+            Ok(page) => page,
+            Err(e) => {
+                self.writer = None;
+                return Some(Err(ExportFailure::Db(e)));
+            }
+        };
+        let last = page.len() < EXPORT_PAGE_SIZE;
+        self.after = page.last().and_then(|e| Some((e.reading.timestamp, e.id?)));
+        if let Err(e) = self.consenting.retain(&self.db, &mut page).await {
+            self.writer = None;
+            return Some(Err(ExportFailure::Db(e)));
+        }
+        if !page.is_empty() || self.pages == 0 {
+            self.access.record(&self.db, Some((self.start, self.end)), self.room.as_deref(), &page).await;
+        }
+        self.pages += 1;
+        
+        let mut chunk = self.header.take().unwrap_or_default().into_bytes();
+        let written = match self.writer.as_mut()? {
+            ExportWriter::Csv(options) => Ok(options.csv_rows(&page).into_bytes()),
+            ExportWriter::Parquet(_) if page.is_empty() => Ok(Vec::new()),
+            ExportWriter::Parquet(parquet) => parquet.write(&page),
+        };
+        let finished = match self.writer.take_if(|_| last) {
+            Some(ExportWriter::Parquet(parquet)) => parquet.finish(),
+            _ => Ok(Vec::new()),
+        };
+        match written.and_then(|bytes| Ok([bytes, finished?].concat())) {
+            Ok(bytes) => chunk.extend(bytes),
+            Err(e) => {
+                self.writer = None;
+                return Some(Err(ExportFailure::Parquet(e)));
+            }
+        }
+        Some(Ok(chunk))
+    }
+}
+
+/// GET /api/export
+/// 
+/// Stream readings for research as CSV or Parquet, of patients who consent
+/// to it, for loading long periods into e.g. pandas. Takes the
+/// options of /api/export/readings. Exports are audited.
+/// Example: /api/export?format=parquet&from=2024-01-01T00:00:00Z&to=2024-04-01T00:00:00Z
+#[get("/api/export")]
+pub async fn export_stream(
+    state: web::Data<AppState>,
+    query: web::Query<StreamExportQuery>,
+    room: web::Query<RoomQuery>,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    debug!("GET /api/export");
+    
+    let parquet = match query.format.as_deref() {
+        None | Some("csv") => false,
+        Some("parquet") => true,
+        Some(other) => return HttpResponse::BadRequest().json(ApiError::new("invalid_format",
+            &format!("Unknown format '{}', expected csv or parquet", other))),
+    };
+    let options = match export_options(&state, query.columns.as_deref(), query.pseudonymize, query.round) {
+        Ok(options) => options,
+        Err(invalid) => return HttpResponse::BadRequest().json(invalid),
+    };
+    let end = query.to.unwrap_or_else(Utc::now);
+    let start = query.from.unwrap_or(end - Duration::days(EXPORT_DEFAULT_DAYS));
+    if start > end {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must be before to"));
+    }
+    
+    let consenting = match consenting_readings(&state, &access, room.room.as_deref(), |c| c.research_export).await {
+        Ok(consenting) => consenting,
+        Err(denied) => return denied,
+    };
+    
+    let audit = AuditEntry {
+        action: "readings.export".to_string(),
+        subject: room.room.as_deref().unwrap_or("*").to_string(),
+        actor: access.principal.clone(),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("{} stream from {} to {}{}", if parquet { "Parquet" } else { "CSV" },
+            start.to_rfc3339(), end.to_rfc3339(), export_detail(&options))),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    
+    let (header, writer) = if parquet {
+        match ParquetExport::new(options) {
+            Ok(parquet) => (None, ExportWriter::Parquet(Box::new(parquet))),
+            Err(e) => {
+                error!("Failed to start Parquet export: {}", e);
+                return HttpResponse::InternalServerError()
+                    .json(ApiError::internal_error("Failed to start Parquet export"));
+            }
+        }
+    } else {
+        (Some(options.csv_header()), ExportWriter::Csv(options))
+    };
+    let mut export = ExportStream {
+        db: state.db.clone(),
+        access,
+        room: room.into_inner().room,
+        consenting,
+        start,
+        end,
+        after: None,
+        header,
+        pages: 0,
+        writer: Some(writer),
+    };
+    
+    // The first page is read up front, so a failing database gets an error
+    // response rather than an empty file
+    let first = match export.next_chunk().await.unwrap_or(Ok(Vec::new())) {
+        Ok(chunk) => chunk,
+        Err(ExportFailure::Db(e)) => return db_error(e, "Failed to retrieve readings"),
+        Err(e) => {
+            error!("Failed to export readings: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiError::internal_error("Failed to export readings"));
+        }
+    };
+    let rest = stream::unfold(export, |mut export| async move {
+        let chunk = export.next_chunk().await?;
+        Some((chunk, export))
+    });
+    let body = stream::once(async { Ok(first) })
+        .chain(rest)
+        .map(|chunk| match chunk {
+            Ok(bytes) => Ok(web::Bytes::from(bytes)),
+            Err(e) => {
+                // Too late for an error response; the truncated body ends the request
+                error!("Export stream failed: {}", e);
+                Err(actix_web::error::ErrorInternalServerError(e.to_string()))
+            }
+        });
+    
+    let (content_type, filename) = if parquet {
+        ("application/vnd.apache.parquet", "readings.parquet")
+    } else {
+        ("text/csv", "readings.csv")
+    };
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
+        .streaming(body)
+}
+
+/// Default step of a sound matrix
+const MATRIX_DEFAULT_STEP_SECS: i64 = 60;
+/// Rows a sound matrix may have; a week at one-second steps is 604800
+const MATRIX_MAX_STEPS: i64 = 1_000_000;
+
+#[derive(Debug, Deserialize)]
+pub struct SoundMatrixQuery {
+    /// RFC 3339 start time, default 7 days before `to`
+    pub from: Option<chrono::DateTime<Utc>>,
+    /// RFC 3339 end time, default now
+    pub to: Option<chrono::DateTime<Utc>>,
+    /// Seconds per row, 1 to 3600, default 60
+    pub step: Option<i64>,
+    /// `mean` (default) or `max` of a room's levels within a step
+    #[serde(default)]
+    pub stat: MatrixStat,
+    /// Replace room IDs with stable pseudonyms
+    #[serde(default)]
+    pub pseudonymize: bool,
+}
+
+/// Sound matrix export sent a page of readings at a time
+struct MatrixStream {
+    db: Database,
+    access: AccessContext,
+    consenting: ConsentingReadings,
+    options: ExportOptions,
+    start: chrono::DateTime<Utc>,
+    end: chrono::DateTime<Utc>,
+    /// Timestamp and id of the last reading read
+    after: Option<(chrono::DateTime<Utc>, i64)>,
+    /// CSV header, sent with the first page
+    header: Option<String>,
+    pages: usize,
+    /// `None` once the export is complete
+    matrix: Option<SoundMatrix>,
+}
+
+impl MatrixStream {
+    /// Next chunk of the file, or `None` once it is complete
+    async fn next_chunk(&mut self) -> Option<Result<Vec<u8>, DbError>> {
+        let matrix = self.matrix.as_mut()?;
+        let mut page = match self.db.export_range(self.start, self.end, None, self.after, EXPORT_PAGE_SIZE).await {
+            Ok(page) => page,
+            Err(e) => {
+                self.matrix = None;
+                return Some(Err(e));
+            }
+        };
+        let last = page.len() < EXPORT_PAGE_SIZE;
+        self.after = page.last().and_then(|e| Some((e.reading.timestamp, e.id?)));
+        if let Err(e) = self.consenting.retain(&self.db, &mut page).await {
+            self.matrix = None;
+            return Some(Err(e));
+        }
+        if !page.is_empty() || self.pages == 0 {
+            self.access.record(&self.db, Some((self.start, self.end)), None, &page).await;
+        }
+        self.pages += 1;
+        
+        let mut rows = Vec::new();
+        for event in &page {
+            rows.extend(matrix.push(&event.room, event.reading.timestamp, event.reading.sound_level));
+        }
+        if let Some(matrix) = self.matrix.take_if(|_| last) {
+            rows.extend(matrix.finish());
+        }
+        let mut chunk = self.header.take().unwrap_or_default();
+        chunk.push_str(&self.options.matrix_csv_rows(&rows));
+        Some(Ok(chunk.into_bytes()))
+    }
+}
+
+/// GET /api/export/sound-matrix
+/// 
+/// Stream the sound levels of all rooms as a time-aligned CSV matrix, a row
+/// per `step` and a column per room, for correlating noise across rooms.
+/// Cells are the mean or loudest level of the room within the step, empty if
+/// it sent no reading of a patient who consents to research exports. Exports
+/// are audited.
+/// Example: /api/export/sound-matrix?from=2024-01-01T00:00:00Z&to=2024-01-02T00:00:00Z&step=60&stat=max
+#[get("/api/export/sound-matrix")]
+pub async fn export_sound_matrix(
+    state: web::Data<AppState>,
+    query: web::Query<SoundMatrixQuery>,
+    access: AccessContext,
+    request_id: RequestId,
 ) -> impl Responder {
-    let mut settings = state.settings.write().unwrap();
-    settings.inactivity_seconds = body.inactivity_seconds;
-    settings.sound_threshold = body.sound_threshold;
+    debug!("GET /api/export/sound-matrix");
     
-    info!("Settings updated: inactivity={}s, sound_threshold={}", 
-        settings.inactivity_seconds, settings.sound_threshold);
+    let options = match export_options(&state, None, query.pseudonymize, None) {
+        Ok(options) => options,
+        Err(invalid) => return HttpResponse::BadRequest().json(invalid),
+    };
+    let step = query.step.unwrap_or(MATRIX_DEFAULT_STEP_SECS);
+    if !(1..=3600).contains(&step) {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_step", "step must be between 1 and 3600 seconds"));
+    }
+    let end = query.to.unwrap_or_else(Utc::now);
+    let start = query.from.unwrap_or(end - Duration::days(EXPORT_DEFAULT_DAYS));
+    if start > end {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must be before to"));
+    }
     
-    HttpResponse::Ok().json(serde_json::json!({
+    let consenting = match consenting_readings(&state, &access, None, |c| c.research_export).await {
+        Ok(consenting) => consenting,
+        Err(denied) => return denied,
+    };
+    let rooms: Vec<String> = consenting.rooms.iter().cloned().collect::<BTreeSet<_>>().into_iter().collect();
+    let matrix = SoundMatrix::new(rooms, start, end, Duration::seconds(step), query.stat);
+    if matrix.steps() > MATRIX_MAX_STEPS {
+        return HttpResponse::BadRequest().json(ApiError::new("too_many_steps",
+            &format!("The matrix would have {} rows, at most {} are allowed; use a longer step or a shorter period",
+                matrix.steps(), MATRIX_MAX_STEPS)));
+    }
+    
+    let audit = AuditEntry {
+        action: "readings.export".to_string(),
+        subject: "*".to_string(),
+        actor: access.principal.clone(),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("Sound matrix of {} rooms from {} to {}, {}s steps, {:?}{}", matrix.rooms().len(),
+            start.to_rfc3339(), end.to_rfc3339(), step, query.stat,
+            if options.pseudonym_key.is_some() { ", pseudonymized" } else { "" })),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    
+    let mut export = MatrixStream {
+        db: state.db.clone(),
+        access,
+        consenting,
+        header: Some(options.matrix_csv_header(matrix.rooms())),
+        options,
+        start,
+        end,
+        after: None,
+        pages: 0,
+        matrix: Some(matrix),
+    };
+    
+    // The first page is read up front, so a failing database gets an error
+    // response rather than an empty file
+    let first = match export.next_chunk().await.unwrap_or(Ok(Vec::new())) {
+        Ok(chunk) => chunk,
+        Err(e) => return db_error(e, "Failed to retrieve readings"),
+    };
+    let rest = stream::unfold(export, |mut export| async move {
+        let chunk = export.next_chunk().await?;
+        Some((chunk, export))
+    });
+    let body = stream::once(async { Ok(first) })
+        .chain(rest)
+        .map(|chunk| match chunk {
+            Ok(bytes) => Ok(web::Bytes::from(bytes)),
+            Err(e) => {
+                // Too late for an error response; the truncated body ends the request
+                error!("Sound matrix export failed: {}", e);
+                Err(actix_web::error::ErrorInternalServerError(e.to_string()))
+            }
+        });
+    
+    HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header(("Content-Disposition", "attachment; filename=\"sound-matrix.csv\""))
+        .streaming(body)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// `csv` or `ndjson`; by default taken from the Content-Type
+    pub format: Option<ImportFormat>,
+}
+
+/// POST /api/import
+/// 
+/// Bulk-load historical readings, e.g. from an older logger, as CSV or
+/// NDJSON with the columns of /api/export (see `import`). They keep their
+/// timestamps, bypass alert detection and are stored in one transaction, so
+/// a failed import stores nothing. Days already rolled up are rolled up
+/// again to count them. Imports are audited.
+/// Example: curl -X POST --data-binary @readings.csv -H 'Content-Type: text/csv' /api/import
+#[post("/api/import")]
+pub async fn import_readings(
+    state: web::Data<AppState>,
+    req: actix_web::HttpRequest,
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    debug!("POST /api/import");
+    
+    let Some(principal) = access.principal.clone() else {
+        return HttpResponse::Unauthorized().json(ApiError::new("authentication_required",
+            "Importing readings needs an authenticated staff member"));
+    };
+    let content_type = req.headers().get("Content-Type").and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let Some(format) = query.format.or_else(|| ImportFormat::from_content_type(content_type)) else {
+        return HttpResponse::UnsupportedMediaType().json(ApiError::new("unsupported_media_type",
+            "Expected CSV (text/csv) or NDJSON (application/x-ndjson), or a format parameter"));
+    };
+    let body = match limits::read_body(&req, payload, state.body_limits.bulk).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let events = match import::parse(format, &body, &state.sensor_ranges) {
+        Ok(events) => events,
+        Err(message) => return HttpResponse::BadRequest().json(ApiError::new("invalid_body", &message)),
+    };
+    
+    let now = Utc::now();
+    if let Some(event) = events.iter().find(|e| e.reading.timestamp > now) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_time",
+            &format!("Reading at {} is in the future", event.reading.timestamp.to_rfc3339())));
+    }
+    let rooms: BTreeSet<&str> = events.iter().map(|e| e.room.as_str()).collect();
+    let known = match state.db.get_rooms().await {
+        Ok(known) => known,
+        Err(e) => return db_error(e, "Failed to check rooms"),
+    };
+    for room in &rooms {
+        if !known.iter().any(|r| r.id == *room) {
+            return HttpResponse::BadRequest()
+                .json(ApiError::new("unknown_room", &format!("Room {} not found", room)));
+        }
+        if !access.permits(state.wards.ward_of(room).as_deref()) {
+            return HttpResponse::Forbidden()
+                .json(ApiError::new("ward_forbidden", &format!("No access to {}", room)));
+        }
+        if !state.monitoring_consent.allows(room) {
+            return HttpResponse::Conflict().json(ApiError::new("consent_withheld",
+                &format!("The patient in {} has not consented to readings being stored", room)));
+        }
+    }
+    
+    let imported = match state.db.insert_historical(&events).await {
+        Ok(imported) => imported,
+        Err(e) => return db_error(e, "Failed to import readings"),
+    };
+    let from = events.iter().map(|e| e.reading.timestamp).min().unwrap_or(now);
+    let to = events.iter().map(|e| e.reading.timestamp).max().unwrap_or(now);
+    let rooms: Vec<&str> = rooms.into_iter().collect();
+    
+    let audit = AuditEntry {
+        action: "readings.import".to_string(),
+        subject: rooms.join(","),
+        actor: Some(principal.clone()),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("{} readings from {} to {}", imported, from.to_rfc3339(), to.to_rfc3339())),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    info!("{} imported {} readings of {}", principal, imported, rooms.join(", "));
+    
+    // Days already rolled up didn't count the imported readings
+    rollup_again(&state, events.iter().map(|e| e.reading.timestamp), "import").await;
+    
+    HttpResponse::Created().json(serde_json::json!({
         "status": "ok",
-        "message": "Settings updated successfully"
+        "imported": imported,
+        "rooms": rooms,
+        "from": from,
+        "to": to,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResearchQuery {
+    /// First facility day, default 30 days before `to`
+    pub from: Option<NaiveDate>,
+    /// Last facility day, default yesterday
+    pub to: Option<NaiveDate>,
+    /// `ward`, `day` or `ward,day`; default one group for everything
+    pub group_by: Option<String>,
+}
+
+const RESEARCH_DEFAULT_DAYS: i64 = 30;
+const MAX_RESEARCH_DAYS: i64 = 366;
+
+/// GET /api/research/aggregates
+/// 
+/// Statistics across patients who consent to research, for principals with
+/// the research role. Groups with too few patients are left out. Queries are
+/// audited.
+/// Example: /api/research/aggregates?from=2024-01-01&to=2024-01-31&group_by=ward,day
+#[get("/api/research/aggregates")]
+pub async fn research_aggregates(
+    state: web::Data<AppState>,
+    query: web::Query<ResearchQuery>,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    debug!("GET /api/research/aggregates");
+    
+    if access.principal.is_none() {
+        return HttpResponse::Unauthorized().json(ApiError::new("authentication_required",
+            "Research queries need an authenticated principal"));
+    }
+    if !access.has_role(research::RESEARCH_ROLE) {
+        return HttpResponse::Forbidden().json(ApiError::new("research_role_required",
+            &format!("Research queries need the '{}' role", research::RESEARCH_ROLE)));
+    }
+    
+    let (mut by_ward, mut by_day) = (false, false);
+    for key in query.group_by.as_deref().unwrap_or_default().split(',').map(str::trim).filter(|k| !k.is_empty()) {
+        match key {
+            "ward" => by_ward = true,
+            "day" => by_day = true,
+            other => return HttpResponse::BadRequest().json(ApiError::new("invalid_group_by",
+                &format!("Unknown grouping '{}', expected ward or day", other))),
+        }
+    }
+    let to = query.to.unwrap_or_else(|| state.days.today() - Duration::days(1));
+    let from = query.from.unwrap_or(to - Duration::days(RESEARCH_DEFAULT_DAYS - 1));
+    if from > to {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_range", "from must not be after to"));
+    }
+    if (to - from).num_days() >= MAX_RESEARCH_DAYS {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_range",
+            &format!("At most {} days can be requested", MAX_RESEARCH_DAYS)));
+    }
+    
+    let start = state.days.start_of(from);
+    let end = state.days.start_of(to + Duration::days(1));
+    let by_day = by_day.then_some(&state.days);
+    let groups = match state.db.research_aggregates(start, end, by_ward, by_day).await {
+        Ok(groups) => groups,
+        Err(e) => return db_error(e, "Failed to compute research aggregates"),
+    };
+    let (groups, suppressed_groups) = research::publish(groups, &state.research);
+    
+    let audit = AuditEntry {
+        action: "research.aggregates".to_string(),
+        subject: query.group_by.clone().unwrap_or_else(|| "*".to_string()),
+        actor: access.principal.clone(),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("{} groups from {} to {}, {} suppressed{}", groups.len(), from, to,
+            suppressed_groups, if state.research.noise_epsilon.is_some() { ", noised" } else { "" })),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    
+    HttpResponse::Ok().json(ResearchAggregates {
+        from,
+        to,
+        min_group_size: state.research.min_group_size,
+        noise_epsilon: state.research.noise_epsilon,
+        suppressed_groups,
+        groups,
+    })
+}
+
+/// GET /api/admin/ws-clients
+/// 
+/// Open and recently closed WebSocket connections, and per address and
+/// principal how often they connected and dropped, most connections first
+#[get("/api/admin/ws-clients")]
+pub async fn list_ws_clients(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/admin/ws-clients");
+    
+    let clients = state.ws_clients.sessions();
+    HttpResponse::Ok().json(serde_json::json!({
+        "connected": clients.iter().filter(|c| c.disconnected_at.is_none()).count(),
+        "peers": ws_clients::peers(&clients),
+        "clients": clients,
     }))
 }
+
+const SUBSCRIPTION_AUTH_MESSAGE: &str = "Live feed subscriptions are kept per authenticated staff member";
+
+/// GET /api/ws/subscription
+/// 
+/// The live feed the caller subscribed to, streamed by `/ws` on any device
+/// they connect from; empty lists if they never picked one
+#[get("/api/ws/subscription")]
+pub async fn get_ws_subscription(state: web::Data<AppState>, access: AccessContext) -> impl Responder {
+    debug!("GET /api/ws/subscription");
+    
+    let Some(principal) = access.principal.as_deref() else {
+        return HttpResponse::Unauthorized().json(ApiError::new("authentication_required", SUBSCRIPTION_AUTH_MESSAGE));
+    };
+    match state.db.get_ws_subscription(principal).await {
+        Ok(subscription) => HttpResponse::Ok().json(subscription.unwrap_or_default()),
+        Err(e) => db_error(e, "Failed to retrieve subscription"),
+    }
+}
+
+/// PUT /api/ws/subscription
+/// 
+/// Pick the rooms, alert severities and message types `/ws` streams to the
+/// caller from now on, on every device. Rooms must be known and in wards
+/// the caller may see.
+#[put("/api/ws/subscription")]
+pub async fn update_ws_subscription(
+    state: web::Data<AppState>,
+    body: web::Json<WsSubscription>,
+    access: AccessContext,
+) -> impl Responder {
+    let mut subscription = body.into_inner();
+    
+    let Some(principal) = access.principal.clone() else {
+        return HttpResponse::Unauthorized().json(ApiError::new("authentication_required", SUBSCRIPTION_AUTH_MESSAGE));
+    };
+    let rooms = match state.db.get_rooms().await {
+        Ok(rooms) => rooms,
+        Err(e) => return db_error(e, "Failed to check rooms"),
+    };
+    for room in &subscription.rooms {
+        if !rooms.iter().any(|r| &r.id == room) {
+            return HttpResponse::BadRequest()
+                .json(ApiError::new("unknown_room", &format!("Room {} not found", room)));
+        }
+        if !access.permits(state.wards.ward_of(room).as_deref()) {
+            return HttpResponse::Forbidden()
+                .json(ApiError::new("ward_forbidden", &format!("No access to {}", room)));
+        }
+    }
+    subscription.rooms.sort();
+    subscription.rooms.dedup();
+    subscription.severities.sort();
+    subscription.severities.dedup();
+    subscription.message_types.sort();
+    subscription.message_types.dedup();
+    
+    if let Err(e) = state.db.set_ws_subscription(&principal, &subscription).await {
+        return db_error(e, "Failed to update subscription");
+    }
+    info!("{} subscribed to rooms {:?}, severities {:?}, messages {:?}",
+        principal, subscription.rooms, subscription.severities, subscription.message_types);
+    
+    match state.db.get_ws_subscription(&principal).await {
+        Ok(stored) => HttpResponse::Ok().json(stored.unwrap_or(subscription)),
+        Err(e) => db_error(e, "Failed to retrieve subscription"),
+    }
+}
+
+/// DELETE /api/ws/subscription
+/// 
+/// Forget the caller's subscription, so `/ws` streams everything again
+#[delete("/api/ws/subscription")]
+pub async fn delete_ws_subscription(state: web::Data<AppState>, access: AccessContext) -> impl Responder {
+    debug!("DELETE /api/ws/subscription");
+    
+    let Some(principal) = access.principal.as_deref() else {
+        return HttpResponse::Unauthorized().json(ApiError::new("authentication_required", SUBSCRIPTION_AUTH_MESSAGE));
+    };
+    match state.db.delete_ws_subscription(principal).await {
+        Ok(true) => {
+            info!("{} cleared their live feed subscription", principal);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound().json(ApiError::not_found("No subscription stored")),
+        Err(e) => db_error(e, "Failed to delete subscription"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobsQuery {
+    /// Only runs of `rollup`, `retention` or `compaction`
+    pub job: Option<String>,
+    /// Only runs that are `running`, `completed`, `failed` or `interrupted`
+    pub status: Option<String>,
+    /// Most runs listed, newest first, default 50
+    pub limit: Option<usize>,
+}
+
+/// Run of a background job with how long it took, or has taken so far
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JobRunStatus {
+    #[serde(flatten)]
+    run: JobRun,
+    /// `None` for runs cut short by a crash
+    duration_seconds: Option<f64>,
+}
+
+impl JobRunStatus {
+    fn new(run: JobRun, now: chrono::DateTime<Utc>) -> Self {
+        let end = match run.status.as_str() {
+            "running" => Some(now),
+            _ => run.finished_at,
+        };
+        let duration_seconds = end.map(|end| (end - run.started_at).num_milliseconds() as f64 / 1000.0);
+        Self { run, duration_seconds }
+    }
+}
+
+/// Latest and latest failed run of a background job
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JobStatus {
+    job: &'static str,
+    last_run: Option<JobRunStatus>,
+    last_failure: Option<JobRunStatus>,
+}
+
+/// GET /api/admin/jobs[?job=retention&status=failed&limit=50]
+/// 
+/// Status of the background jobs (rollup, retention, compaction), each with
+/// its latest run and latest failure, and their recent runs with durations,
+/// errors and the checkpoint an unfinished run resumes from
+#[get("/api/admin/jobs")]
+pub async fn list_jobs(
+    state: web::Data<AppState>,
+    query: web::Query<JobsQuery>,
+) -> impl Responder {
+    debug!("GET /api/admin/jobs");
+    
+    let now = Utc::now();
+    let mut jobs = Vec::new();
+    for job in [jobs::ROLLUP, jobs::RETENTION, jobs::COMPACTION] {
+        let last_run = match state.db.get_job_runs(Some(job), None, 1).await {
+            Ok(runs) => runs.into_iter().next(),
+            Err(e) => return db_error(e, "Failed to retrieve job runs"),
+        };
+        let last_failure = match state.db.get_job_runs(Some(job), Some("failed"), 1).await {
+            Ok(runs) => runs.into_iter().next(),
+            Err(e) => return db_error(e, "Failed to retrieve job runs"),
+        };
+        jobs.push(JobStatus {
+            job,
+            last_run: last_run.map(|r| JobRunStatus::new(r, now)),
+            last_failure: last_failure.map(|r| JobRunStatus::new(r, now)),
+        });
+    }
+    
+    let limit = query.limit.unwrap_or(50).clamp(1, 1000);
+    match state.db.get_job_runs(query.job.as_deref(), query.status.as_deref(), limit).await {
+        Ok(runs) => HttpResponse::Ok().json(serde_json::json!({
+            "jobs": jobs,
+            "runs": runs.into_iter().map(|r| JobRunStatus::new(r, now)).collect::<Vec<_>>(),
+        })),
+        Err(e) => db_error(e, "Failed to retrieve job runs"),
+    }
+}
+
+/// Days of readings the ingest rates of `GET /api/admin/storage` are measured over
+const STORAGE_RATE_DAYS: i64 = 7;
+/// Days ahead `GET /api/admin/storage` projects the growth
+const STORAGE_PROJECTION_DAYS: i64 = 30;
+
+/// GET /api/admin/storage
+/// 
+/// Disk space taken by the readings, per partition and per room, with each
+/// room's ingest rate over the last week and the growth it projects, so ops
+/// can plan disk capacity before an edge box fills up
+#[get("/api/admin/storage")]
+pub async fn get_storage(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/admin/storage");
+    
+    let now = Utc::now();
+    let usage = match state.db.get_storage_usage(now - Duration::days(STORAGE_RATE_DAYS)).await {
+        Ok(usage) => usage,
+        Err(e) => return db_error(e, "Failed to measure storage usage"),
+    };
+    
+    let readings: i64 = usage.rooms.iter().map(|(_, total, _)| total).sum();
+    let bytes_per_reading = (readings > 0).then(|| usage.bytes as f64 / readings as f64);
+    let rooms: Vec<RoomStorage> = usage.rooms
+        .into_iter()
+        .map(|(room, total, recent)| {
+            let readings_per_day = recent as f64 / STORAGE_RATE_DAYS as f64;
+            RoomStorage {
+                room,
+                readings: total,
+                bytes: bytes_per_reading.map_or(0, |b| (b * total as f64).round() as i64),
+                readings_per_day,
+                bytes_per_day: bytes_per_reading.map_or(0, |b| (b * readings_per_day).round() as i64),
+            }
+        })
+        .collect();
+    let bytes_per_day: i64 = rooms.iter().map(|r| r.bytes_per_day).sum();
+    
+    HttpResponse::Ok().json(StorageReport {
+        generated_at: now,
+        bytes: usage.bytes,
+        readings,
+        bytes_per_reading,
+        rate_window_days: STORAGE_RATE_DAYS,
+        bytes_per_day,
+        projected_bytes: usage.bytes + bytes_per_day * STORAGE_PROJECTION_DAYS,
+        projection_days: STORAGE_PROJECTION_DAYS,
+        // Purging keeps the readings to the retention period's worth
+        steady_state_bytes: (state.retention.days > 0).then(|| bytes_per_day * state.retention.days as i64),
+        partitions: usage.partitions
+            .into_iter()
+            .map(|(name, bytes)| PartitionStorage { name, bytes })
+            .collect(),
+        rooms,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlagUpdate {
+    pub enabled: bool,
+    /// Room to switch it in; the whole facility if unset
+    pub room: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlagScopeQuery {
+    /// Room whose setting to remove; the facility's if unset
+    pub room: Option<String>,
+}
+
+/// Refresh the cached flags after they were changed through the API
+async fn reload_flags(state: &AppState) {
+    if let Err(e) = state.flags.reload(&state.db).await {
+        error!("Failed to reload feature flags: {}", e);
+    }
+}
+
+/// GET /api/admin/flags
+/// 
+/// Every feature flag with its default and where it was switched
+#[get("/api/admin/flags")]
+pub async fn list_flags(state: web::Data<AppState>) -> impl Responder {
+    debug!("GET /api/admin/flags");
+    
+    HttpResponse::Ok().json(state.flags.states())
+}
+
+/// PUT /api/admin/flags/{name}
+/// 
+/// Switch a flag for the whole facility, or for one room with e.g.
+/// {"enabled": true, "room": "room-101"}. A room's setting overrides the
+/// facility's.
+#[put("/api/admin/flags/{name}")]
+pub async fn set_flag(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<FlagUpdate>,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    let name = path.into_inner();
+    let update = body.into_inner();
+    if flags::find(&name).is_none() {
+        return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("No feature flag named {}", name)));
+    }
+    if update.room.as_deref().is_some_and(|r| r.trim().is_empty()) {
+        return HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_room", "room must not be empty; leave it out to switch the flag for the facility"));
+    }
+    
+    let setting = FlagSetting {
+        name: name.clone(),
+        room: update.room,
+        enabled: update.enabled,
+        updated_at: Utc::now(),
+        updated_by: access.principal.clone(),
+    };
+    if let Err(e) = state.db.set_feature_flag(&setting).await {
+        return db_error(e, "Failed to store feature flag");
+    }
+    reload_flags(&state).await;
+    
+    let scope = setting.room.as_deref().unwrap_or("the facility");
+    let audit = AuditEntry {
+        action: "flag.set".to_string(),
+        subject: name.clone(),
+        actor: access.principal.clone(),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("{} for {}", if setting.enabled { "enabled" } else { "disabled" }, scope)),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    
+    info!("Feature flag {} {} for {}", name, if setting.enabled { "enabled" } else { "disabled" }, scope);
+    HttpResponse::Ok().json(setting)
+}
+
+/// DELETE /api/admin/flags/{name}[?room=room-101]
+/// 
+/// Remove a room's setting of a flag, or the facility's, falling back to
+/// the facility's setting or the flag's default
+#[delete("/api/admin/flags/{name}")]
+pub async fn reset_flag(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<FlagScopeQuery>,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    let name = path.into_inner();
+    let room = query.into_inner().room;
+    let scope = room.as_deref().unwrap_or("the facility");
+    
+    match state.db.delete_feature_flag(&name, room.as_deref()).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::NotFound()
+                .json(ApiError::not_found(&format!("Feature flag {} is not set for {}", name, scope)));
+        }
+        Err(e) => return db_error(e, "Failed to remove feature flag"),
+    }
+    reload_flags(&state).await;
+    
+    let audit = AuditEntry {
+        action: "flag.reset".to_string(),
+        subject: name.clone(),
+        actor: access.principal.clone(),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("setting for {} removed", scope)),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    
+    info!("Feature flag {} reset for {}", name, scope);
+    HttpResponse::NoContent().finish()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeQuery {
+    /// Keep this many days of readings instead of `RETENTION_DAYS`
+    pub days: Option<u32>,
+}
+
+/// POST /api/admin/retention/purge[?days=365]
+/// 
+/// Purge expired readings now instead of waiting for the hourly job
+#[post("/api/admin/retention/purge")]
+pub async fn purge_expired(
+    state: web::Data<AppState>,
+    query: web::Query<PurgeQuery>,
+    access: AccessContext,
+    request_id: RequestId,
+) -> impl Responder {
+    let days = query.days.unwrap_or(state.retention.days);
+    if days == 0 {
+        return HttpResponse::BadRequest().json(ApiError::new("retention_disabled",
+            "Retention is disabled; set RETENTION_DAYS or pass days"));
+    }
+    
+    let summary = match retention::purge(&state.db, &state.retention, &state.days, days, Utc::now()).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            error!("Purge failed: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiError::internal_error("Failed to purge expired readings"));
+        }
+    };
+    
+    let audit = AuditEntry {
+        action: "retention.purge".to_string(),
+        subject: "sensor_data".to_string(),
+        actor: access.principal.clone(),
+        request_id: Some(request_id.0.clone()),
+        detail: Some(format!("{} readings and {} alerts from before {} purged{}",
+            summary.readings, summary.alerts, summary.cutoff.to_rfc3339(),
+            summary.archive.as_deref().map(|a| format!(", archived to {}", a)).unwrap_or_default())),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    
+    info!("Manual purge: {} readings and {} alerts from before {}", summary.readings, summary.alerts, summary.cutoff);
+    HttpResponse::Ok().json(summary)
+}
+
+/// POST /api/dev/seed
+/// 
+/// Fill the database with synthetic readings for development and demos.
+/// Only available in mock mode. Optional body, e.g. {"days": 3, "fallsPerDay": 2}
+#[post("/api/dev/seed")]
+pub async fn seed_data(
+    state: web::Data<AppState>,
+    body: Option<web::Json<SeedOptions>>,
+) -> impl Responder {
+    if !state.mock_mode {
+        return HttpResponse::NotFound()
+            .json(ApiError::not_found("Seeding is only available in mock mode"));
+    }
+    
+    let options = body.map(|b| b.into_inner()).unwrap_or_default();
+    let settings = state.settings.read().unwrap_or_else(|e| e.into_inner()).clone();
+    
+    match seed::seed(&state.db, &options, &settings, &state.rooms).await {
+        Ok(summary) => HttpResponse::Created().json(summary),
+        Err(SeedError::Invalid(msg)) => HttpResponse::BadRequest()
+            .json(ApiError::new("invalid_seed", &msg)),
+        Err(e @ SeedError::DataExists(_)) => HttpResponse::Conflict()
+            .json(ApiError::new("data_exists", &e.to_string())),
+        Err(SeedError::Database(e)) => db_error(e, "Failed to seed data"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{BackendKind, DbConfig};
+    use crate::fhir::{AlertSet, SensorReading};
+    use chrono::TimeZone;
+
+    async fn database() -> Database {
+        let mut config = DbConfig::from_env();
+        config.backend = BackendKind::Sqlite;
+        config.sqlite_path = format!("file:api_test_{}?mode=memory&cache=shared", uuid::Uuid::new_v4().simple()).into();
+        config.auto_migrate = true;
+        let db = Database::new(config).await.unwrap();
+        db.register_rooms(&["room-101".to_string(), "room-102".to_string()]).await.unwrap();
+        db
+    }
+
+    fn at(minute: u32) -> chrono::DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 15, 10, minute, 0).unwrap()
+    }
+
+    fn event(room: &str, minute: u32) -> SensorEvent {
+        SensorEvent {
+            id: None,
+            seq: None,
+            room: room.to_string(),
+            device: None,
+            patient_id: None,
+            reading: SensorReading {
+                temperature: 22.0,
+                sound_level: 30,
+                timestamp: at(minute),
+                ..Default::default()
+            },
+            alerts: AlertSet::new(),
+        }
+    }
+
+    fn patient(id: &str, room: &str) -> Patient {
+        Patient {
+            id: id.to_string(),
+            family_name: "Doe".to_string(),
+            given_names: Vec::new(),
+            birth_date: None,
+            gender: None,
+            room: Some(room.to_string()),
+        }
+    }
+
+    async fn set_research_export(db: &Database, patient: &str, research_export: bool) {
+        let consent = Consent { continuous_monitoring: true, ehr_sharing: true, research_export, updated_at: None };
+        db.set_consent(patient, &consent).await.unwrap();
+    }
+
+    /// Rows of the CSV `/api/export` streams of `room-101` and `room-102`
+    async fn export(db: &Database) -> Vec<String> {
+        let options = ExportOptions { columns: vec![Column::Room, Column::PatientId], pseudonym_key: None, round: None };
+        let access = AccessContext {
+            principal: Some("researcher".to_string()),
+            wards: None,
+            roles: Vec::new(),
+            endpoint: "/api/export".to_string(),
+            request_id: None,
+        };
+        let mut export = ExportStream {
+            db: db.clone(),
+            access,
+            room: None,
+            consenting: ConsentingReadings {
+                rooms: HashSet::from(["room-101".to_string(), "room-102".to_string()]),
+                patients: PatientConsent::new(|c| c.research_export),
+            },
+            start: at(0),
+            end: at(59),
+            after: None,
+            header: None,
+            pages: 0,
+            writer: Some(ExportWriter::Csv(options)),
+        };
+        let mut csv = Vec::new();
+        while let Some(chunk) = export.next_chunk().await {
+            csv.extend(chunk.unwrap());
+        }
+        String::from_utf8(csv).unwrap().lines().map(String::from).collect()
+    }
+
+    #[tokio::test]
+    async fn export_leaves_out_patients_without_research_consent() {
+        let db = database().await;
+        db.insert_patient(&patient("refusing", "room-101")).await.unwrap();
+        set_research_export(&db, "refusing", false).await;
+        db.insert_patient(&patient("consenting", "room-102")).await.unwrap();
+        set_research_export(&db, "consenting", true).await;
+        db.insert_readings_batch(&[event("room-101", 0), event("room-102", 1)]).await.unwrap();
+
+        // Consent moves with the patient, not the room
+        db.update_patient(&Patient { room: None, ..patient("refusing", "room-101") }).await.unwrap();
+        db.update_patient(&patient("consenting", "room-101")).await.unwrap();
+        db.insert_readings_batch(&[event("room-101", 2)]).await.unwrap();
+
+        assert_eq!(export(&db).await, vec!["room-102,consenting", "room-101,consenting"]);
+    }
+}
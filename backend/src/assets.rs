@@ -0,0 +1,178 @@
+//! Dashboard asset serving
+//!
+//! Assets are served from `FRONTEND_DIR` when it is set (handy while editing
+//! the dashboard), otherwise from the copy embedded at build time with the
+//! `embed-frontend` feature, otherwise from `./frontend`.
+//!
+//! `index.html` links the local scripts and stylesheets with their content
+//! hash (`app.js?v=<hash>`) and is always revalidated, so a kiosk picks up a
+//! new dashboard on its next load. An asset requested with its current hash
+//! never changes and is cached for a year; everything else is revalidated
+//! with its ETag. `GET /api/version` reports the deployed versions so
+//! kiosks can reload when the dashboard changes.
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use tracing::info;
+
+#[cfg(feature = "embed-frontend")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "frontend/"]
+struct Frontend;
+
+/// Cache lifetime of an asset requested with its content hash
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Cache policy of everything else: keep, but check the ETag before use
+const REVALIDATE: &str = "no-cache";
+
+/// Where the dashboard's files come from
+#[derive(Debug, Clone)]
+enum Source {
+    Dir(PathBuf),
+    #[cfg(feature = "embed-frontend")]
+    Embedded,
+}
+
+impl Source {
+    fn new(frontend_dir: Option<&str>) -> Self {
+        match frontend_dir {
+            Some(dir) => Source::Dir(dir.into()),
+            #[cfg(feature = "embed-frontend")]
+            None => Source::Embedded,
+            #[cfg(not(feature = "embed-frontend"))]
+            None => Source::Dir("./frontend".into()),
+        }
+    }
+
+    /// Contents of the asset at `path`, relative to the dashboard root
+    async fn load(&self, path: &str) -> Option<Vec<u8>> {
+        match self {
+            Source::Dir(dir) => {
+                // Only plain relative paths, nothing outside the directory
+                let relative = Path::new(path);
+                if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+                    return None;
+                }
+                tokio::fs::read(dir.join(relative)).await.ok()
+            }
+            #[cfg(feature = "embed-frontend")]
+            Source::Embedded => Frontend::get(path).map(|file| file.data.into_owned()),
+        }
+    }
+
+    /// `index.html` with the local scripts and stylesheets it links
+    /// versioned by their content hash
+    async fn index(&self) -> Option<Vec<u8>> {
+        let html = String::from_utf8(self.load("index.html").await?).ok()?;
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html.as_str();
+
+        while let Some(start) = ["src=\"", "href=\""]
+            .iter()
+            .filter_map(|attr| rest.find(attr).map(|i| i + attr.len()))
+            .min()
+        {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find('"').unwrap_or(rest.len());
+            let url = &rest[..end];
+            out.push_str(url);
+            if is_local(url) {
+                if let Some(asset) = self.load(url).await {
+                    out.push_str("?v=");
+                    out.push_str(&content_hash(&asset)[..12]);
+                }
+            }
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+        Some(out.into_bytes())
+    }
+}
+
+/// Whether `url` names a file of the dashboard rather than another site,
+/// an anchor or inline data
+fn is_local(url: &str) -> bool {
+    !url.is_empty() && !url.contains(':') && !url.contains('?') && !url.starts_with('#') && !url.starts_with("//")
+}
+
+fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Register the version endpoint and the dashboard as the catch-all service
+pub fn configure(cfg: &mut web::ServiceConfig, frontend_dir: Option<&str>) {
+    cfg.app_data(web::Data::new(Source::new(frontend_dir)))
+        .route("/api/version", web::get().to(version))
+        .default_service(web::to(serve));
+}
+
+/// Log where the dashboard is served from
+pub fn log_source(frontend_dir: Option<&str>) {
+    match frontend_dir {
+        Some(dir) => info!("Serving dashboard from {}", dir),
+        None if cfg!(feature = "embed-frontend") => info!("Serving embedded dashboard"),
+        None => info!("Serving dashboard from ./frontend"),
+    }
+}
+
+async fn serve(req: HttpRequest, source: web::Data<Source>) -> HttpResponse {
+    let path = match req.path().trim_start_matches('/') {
+        "" => "index.html",
+        path => path,
+    };
+    let body = match path {
+        "index.html" => source.index().await,
+        path => source.load(path).await,
+    };
+    let Some(body) = body else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let hash = content_hash(&body);
+    let requested = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.get("v").cloned());
+    let cache_control = match requested {
+        Some(v) if path != "index.html" && hash.starts_with(&v) && v.len() >= 8 => IMMUTABLE,
+        _ => REVALIDATE,
+    };
+    let etag = format!("\"{}\"", hash);
+
+    let unchanged = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    if unchanged {
+        return HttpResponse::NotModified()
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::CACHE_CONTROL, cache_control))
+            .finish();
+    }
+
+    let extension = path.rsplit_once('.').map_or("", |(_, ext)| ext);
+    HttpResponse::Ok()
+        .content_type(actix_files::file_extension_to_mime(extension))
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::CACHE_CONTROL, cache_control))
+        .body(body)
+}
+
+/// GET /api/version
+///
+/// Versions of the running backend and of the dashboard it serves; the
+/// dashboard's changes whenever any of its linked files does
+async fn version(source: web::Data<Source>) -> HttpResponse {
+    let frontend = source.index().await.map(|index| content_hash(&index)[..12].to_string());
+
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(serde_json::json!({
+            "backend": env!("CARGO_PKG_VERSION"),
+            "frontend": frontend,
+        }))
+}
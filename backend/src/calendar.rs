@@ -0,0 +1,299 @@
+//! Procedure calendars
+//!
+//! Each room can have an iCalendar feed of scheduled procedures, e.g. a
+//! wound dressing or physiotherapy session, configured with
+//! `PROCEDURE_CALENDARS`. CalDAV calendars are read through their iCal
+//! export URL; credentials can be given in the URL. The feeds are polled
+//! every `PROCEDURE_CALENDAR_POLL_SECS`, and while a procedure runs, alert
+//! notifications for its room are suppressed: the motion and noise of staff
+//! at the bedside would otherwise page the ward. Procedures are also listed
+//! in the morning report.
+//!
+//! Only single events are read. Recurrence rules are not expanded, so a
+//! recurring event counts at its first occurrence only. Times without a
+//! time zone are taken in `PROCEDURE_CALENDAR_TZ`.
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use patient_monitor_types::api::ScheduledProcedure;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+/// Procedures that ended longer ago than this are forgotten
+const KEEP_ENDED_HOURS: i64 = 24;
+const FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Calendar feed of a room
+#[derive(Debug, Clone)]
+pub struct CalendarFeed {
+    pub room: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CalendarConfig {
+    pub feeds: Vec<CalendarFeed>,
+    pub poll_interval: std::time::Duration,
+    /// Time zone of times without one
+    pub timezone: Tz,
+}
+
+/// Parse `room-101=https://...,room-102=https://...`
+pub fn parse_feeds(value: &str) -> Result<Vec<CalendarFeed>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|feed| match feed.split_once('=') {
+            Some((room, url)) if !room.trim().is_empty() && !url.trim().is_empty() => Ok(CalendarFeed {
+                room: room.trim().to_string(),
+                url: url.trim().to_string(),
+            }),
+            _ => Err(format!("Expected room=url, got '{}'", feed)),
+        })
+        .collect()
+}
+
+/// Scheduled procedures per room. Shared between the calendar poller, the
+/// notifier and the reports.
+#[derive(Debug, Clone, Default)]
+pub struct Procedures {
+    by_room: Arc<RwLock<HashMap<String, Vec<ScheduledProcedure>>>>,
+}
+
+impl Procedures {
+    /// Replace the procedures of `room` with a fresh copy of its feed
+    pub fn replace(&self, room: &str, procedures: Vec<ScheduledProcedure>) {
+        self.by_room.write().unwrap().insert(room.to_string(), procedures);
+    }
+
+    /// Procedure running in `room` at `at`, if any
+    pub fn active(&self, room: &str, at: DateTime<Utc>) -> Option<ScheduledProcedure> {
+        self.by_room
+            .read()
+            .unwrap()
+            .get(room)?
+            .iter()
+            .find(|p| p.start <= at && at < p.end)
+            .cloned()
+    }
+
+    /// Procedures in `rooms` overlapping `start..end`, soonest first
+    pub fn between(&self, rooms: &[String], start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<ScheduledProcedure> {
+        let by_room = self.by_room.read().unwrap();
+        let mut procedures: Vec<ScheduledProcedure> = rooms
+            .iter()
+            .filter_map(|room| by_room.get(room))
+            .flatten()
+            .filter(|p| p.start < end && p.end > start)
+            .cloned()
+            .collect();
+        procedures.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.room.cmp(&b.room)));
+        procedures
+    }
+}
+
+/// Content lines with folded continuation lines joined
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.lines() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Property of a content line: name, parameters and value
+struct Property<'a> {
+    name: String,
+    params: Vec<(String, &'a str)>,
+    value: &'a str,
+}
+
+impl Property<'_> {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(n, _)| n == name).map(|(_, v)| v.trim_matches('"'))
+    }
+}
+
+fn parse_line(line: &str) -> Option<Property<'_>> {
+    // The value starts at the first colon outside quoted parameter values
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+    let mut parts = head.split(';');
+    let name = parts.next()?.to_ascii_uppercase();
+    let params = parts
+        .filter_map(|p| p.split_once('='))
+        .map(|(n, v)| (n.to_ascii_uppercase(), v))
+        .collect();
+    Some(Property { name, params, value })
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(escaped) => out.push(escaped),
+            None => {}
+        }
+    }
+    out
+}
+
+/// DATE or DATE-TIME value, in UTC
+fn parse_time(property: &Property, default_tz: Tz) -> Option<DateTime<Utc>> {
+    let value = property.value.trim();
+    let tz = property
+        .param("TZID")
+        .and_then(|id| id.parse::<Tz>().ok())
+        .unwrap_or(default_tz);
+
+    if let Some(utc) = value.strip_suffix('Z') {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        return Some(time.and_utc());
+    }
+    let time = if property.param("VALUE") == Some("DATE") || value.len() == 8 {
+        NaiveDate::parse_from_str(value, "%Y%m%d").ok()?.and_hms_opt(0, 0, 0)?
+    } else {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?
+    };
+    tz.from_local_datetime(&time).earliest().map(|t| t.with_timezone(&Utc))
+}
+
+/// DURATION value such as `PT1H30M` or `P1D`
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut rest = value.strip_prefix('P')?;
+    let mut total = Duration::zero();
+    let mut in_time = false;
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('T') {
+            in_time = true;
+            rest = r;
+            continue;
+        }
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let n: i64 = rest[..digits].parse().ok()?;
+        let unit = rest[digits..].chars().next()?;
+        total += match (unit, in_time) {
+            ('W', false) => Duration::weeks(n),
+            ('D', false) => Duration::days(n),
+            ('H', true) => Duration::hours(n),
+            ('M', true) => Duration::minutes(n),
+            ('S', true) => Duration::seconds(n),
+            _ => return None,
+        };
+        rest = &rest[digits + unit.len_utf8()..];
+    }
+    Some(if negative { -total } else { total })
+}
+
+/// Events of an iCalendar feed as procedures in `room`. Cancelled events
+/// and events without a valid start are left out.
+pub fn parse_ical(ics: &str, room: &str, default_tz: Tz) -> Vec<ScheduledProcedure> {
+    let mut procedures = Vec::new();
+    let mut event: Option<Vec<String>> = None;
+
+    for line in unfold(ics) {
+        match line.trim_end().to_ascii_uppercase().as_str() {
+            "BEGIN:VEVENT" => event = Some(Vec::new()),
+            "END:VEVENT" => {
+                if let Some(procedure) = event.take().and_then(|lines| to_procedure(&lines, room, default_tz)) {
+                    procedures.push(procedure);
+                }
+            }
+            _ => {
+                if let Some(lines) = event.as_mut() {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    procedures
+}
+
+fn to_procedure(lines: &[String], room: &str, default_tz: Tz) -> Option<ScheduledProcedure> {
+    let properties: Vec<Property> = lines.iter().filter_map(|l| parse_line(l)).collect();
+    let get = |name: &str| properties.iter().find(|p| p.name == name);
+
+    if get("STATUS").is_some_and(|s| s.value.trim().eq_ignore_ascii_case("CANCELLED")) {
+        return None;
+    }
+    let dtstart = get("DTSTART")?;
+    let start = parse_time(dtstart, default_tz)?;
+    let all_day = dtstart.param("VALUE") == Some("DATE") || dtstart.value.trim().len() == 8;
+    let end = match (get("DTEND"), get("DURATION")) {
+        (Some(dtend), _) => parse_time(dtend, default_tz)?,
+        (None, Some(duration)) => start + parse_duration(duration.value)?,
+        // An all-day event without an end lasts the day
+        (None, None) if all_day => start + Duration::days(1),
+        (None, None) => start,
+    };
+    if end <= start {
+        return None;
+    }
+
+    Some(ScheduledProcedure {
+        room: room.to_string(),
+        uid: get("UID").map(|u| u.value.trim().to_string()).unwrap_or_default(),
+        summary: get("SUMMARY").map(|s| unescape(s.value.trim())).unwrap_or_else(|| "Procedure".to_string()),
+        start,
+        end,
+    })
+}
+
+async fn fetch(http: &reqwest::Client, feed: &CalendarFeed, tz: Tz) -> Result<Vec<ScheduledProcedure>, reqwest::Error> {
+    let ics = http.get(&feed.url).send().await?.error_for_status()?.text().await?;
+    let cutoff = Utc::now() - Duration::hours(KEEP_ENDED_HOURS);
+    let mut procedures = parse_ical(&ics, &feed.room, tz);
+    procedures.retain(|p| p.end > cutoff);
+    Ok(procedures)
+}
+
+/// Poll the feeds every `poll_interval`. A feed that can't be read keeps its
+/// previous procedures until the next poll.
+pub fn spawn(config: CalendarConfig, procedures: Procedures) {
+    if config.feeds.is_empty() {
+        return;
+    }
+    info!("Polling {} procedure calendars every {}s", config.feeds.len(), config.poll_interval.as_secs());
+
+    tokio::spawn(async move {
+        let http = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client");
+        let mut ticker = tokio::time::interval(config.poll_interval);
+        loop {
+            ticker.tick().await;
+            for feed in &config.feeds {
+                match fetch(&http, feed, config.timezone).await {
+                    Ok(list) => procedures.replace(&feed.room, list),
+                    Err(e) => warn!("Failed to read procedure calendar of {}: {}", feed.room, e.without_url()),
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,397 @@
+//! Alert cards for Slack and Microsoft Teams
+//!
+//! Chat targets receive a formatted card with the room, severity, a link to a
+//! mini chart around the alerting reading and an "Acknowledge" button:
+//!
+//! - Slack: the button is handled by the app's interactivity Request URL,
+//!   `POST /api/integrations/slack/interactive`, verified with the app's
+//!   signing secret (`SLACK_SIGNING_SECRET`).
+//! - Teams: the card's HttpPOST action calls `POST /api/integrations/teams/ack`
+//!   with a signature made with `TEAMS_ACK_SECRET`, so only buttons from
+//!   cards we sent can acknowledge alerts.
+//!
+//! Webhooks relaying alerts to email or SMS (a paging gateway) get a
+//! one-click `ackUrl` with new and escalated alerts when `ALERT_ACK_LINK_SECRET`
+//! is set. It opens `GET /api/alerts/ack-token/{token}`, whose token names the
+//! alert and the webhook's recipient, who is recorded as acknowledging it,
+//! and expires after `ALERT_ACK_LINK_MINUTES`. Night staff can so acknowledge
+//! from their phone without signing in to the dashboard.
+
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, KeyInit, Mac};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use tracing::{info, warn};
+
+use crate::api::{self, ApiError, AppState};
+use crate::fhir::{AlertSeverity, AlertType};
+use crate::request_id::RequestId;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Slack requests older than this are rejected to prevent replays
+const SLACK_MAX_AGE_SECS: i64 = 300;
+const ACK_ACTION_ID: &str = "ack_alert";
+
+#[derive(Debug, Clone, Default)]
+pub struct ChatOpsConfig {
+    /// URL chat users reach this server at, for chart links and Teams actions
+    pub public_url: String,
+    pub slack_signing_secret: Option<String>,
+    pub teams_ack_secret: Option<String>,
+    /// Secret signing the ack links sent to webhooks; none are sent without
+    pub ack_link_secret: Option<String>,
+    /// How long an ack link stays valid
+    pub ack_link_ttl: Duration,
+}
+
+/// What an alert card shows
+#[derive(Debug, Clone)]
+pub struct AlertCard<'a> {
+    pub alert: AlertType,
+    pub room: &'a str,
+    pub ward: Option<&'a str>,
+    pub timestamp: DateTime<Utc>,
+    pub observation_id: Option<i64>,
+}
+
+fn chart_url(config: &ChatOpsConfig, id: i64) -> String {
+    format!("{}/api/observations/{}/chart.svg", config.public_url, id)
+}
+
+fn title(card: &AlertCard) -> String {
+    format!("{} in {}", card.alert.description(), card.room)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn mac(secret: &str, parts: &[&[u8]]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+// ============================================================================
+// SLACK
+// ============================================================================
+
+/// Block Kit message for a Slack incoming webhook
+pub fn slack_message(card: &AlertCard, config: &ChatOpsConfig) -> serde_json::Value {
+    let mut fields = vec![
+        json!({"type": "mrkdwn", "text": format!("*Room*\n{}", card.room)}),
+        json!({"type": "mrkdwn", "text": format!("*Severity*\n{:?}", card.alert.severity())}),
+        json!({"type": "mrkdwn", "text": format!("*Time*\n<!date^{}^{{date_short_pretty}} {{time_secs}}|{}>",
+            card.timestamp.timestamp(), card.timestamp.to_rfc3339())}),
+    ];
+    if let Some(ward) = card.ward {
+        fields.push(json!({"type": "mrkdwn", "text": format!("*Ward*\n{}", ward)}));
+    }
+
+    let mut blocks = vec![
+        json!({"type": "header", "text": {"type": "plain_text", "text": title(card)}}),
+        json!({"type": "section", "fields": fields}),
+    ];
+    if let Some(id) = card.observation_id {
+        blocks.push(json!({
+            "type": "actions",
+            "elements": [
+                {
+                    "type": "button",
+                    "text": {"type": "plain_text", "text": "View chart"},
+                    "url": chart_url(config, id),
+                },
+                {
+                    "type": "button",
+                    "style": "primary",
+                    "action_id": ACK_ACTION_ID,
+                    "value": id.to_string(),
+                    "text": {"type": "plain_text", "text": "Acknowledge"},
+                },
+            ],
+        }));
+    }
+
+    json!({
+        // Shown in notifications and clients without Block Kit support
+        "text": title(card),
+        "blocks": blocks,
+    })
+}
+
+/// Check `X-Slack-Signature` (`v0=<hex hmac>` over `v0:<timestamp>:<body>`)
+fn verify_slack_signature(secret: &str, timestamp: &str, body: &[u8], signature: &str) -> bool {
+    let fresh = timestamp
+        .parse::<i64>()
+        .is_ok_and(|ts| (Utc::now().timestamp() - ts).abs() <= SLACK_MAX_AGE_SECS);
+    let Some(tag) = signature.strip_prefix("v0=").and_then(from_hex) else {
+        return false;
+    };
+    fresh && mac(secret, &[b"v0:", timestamp.as_bytes(), b":", body]).verify_slice(&tag).is_ok()
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackForm {
+    payload: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackInteraction {
+    #[serde(rename = "type")]
+    kind: String,
+    user: SlackUser,
+    #[serde(default)]
+    actions: Vec<SlackAction>,
+    response_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackUser {
+    id: String,
+    username: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackAction {
+    action_id: String,
+    value: Option<String>,
+}
+
+/// POST /api/integrations/slack/interactive
+///
+/// Slack interactivity Request URL; handles the alert card's Acknowledge button
+#[post("/api/integrations/slack/interactive")]
+pub async fn slack_interactive(
+    state: web::Data<AppState>,
+    chatops: web::Data<ChatOpsConfig>,
+    req: HttpRequest,
+    body: web::Bytes,
+    request_id: RequestId,
+) -> impl Responder {
+    let Some(secret) = &chatops.slack_signing_secret else {
+        return HttpResponse::NotFound().json(ApiError::not_found("Slack integration is not configured"));
+    };
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or("");
+    if !verify_slack_signature(secret, header("x-slack-request-timestamp"), &body, header("x-slack-signature")) {
+        warn!("Rejected Slack request with invalid signature");
+        return HttpResponse::Unauthorized().json(ApiError::new("invalid_signature", "Invalid Slack signature"));
+    }
+
+    let interaction = serde_urlencoded::from_bytes::<SlackForm>(&body)
+        .ok()
+        .and_then(|form| serde_json::from_str::<SlackInteraction>(&form.payload).ok());
+    let Some(interaction) = interaction else {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_payload", "Unreadable Slack payload"));
+    };
+
+    let ack = interaction.actions.iter().find(|a| a.action_id == ACK_ACTION_ID);
+    let id = match ack.and_then(|a| a.value.as_deref()).map(str::parse::<i64>) {
+        Some(Ok(id)) if interaction.kind == "block_actions" => id,
+        // Other interactions aren't ours to handle
+        _ => return HttpResponse::Ok().finish(),
+    };
+
+    let user = interaction.user.username.as_deref().unwrap_or(&interaction.user.id).to_string();
+    let response = api::acknowledge(&state, id, Some(&user), None, "slack", &request_id).await;
+
+    if response.status().is_success() {
+        if let Some(url) = interaction.response_url {
+            let message = json!({
+                "replace_original": false,
+                "text": format!(":white_check_mark: Alert {} acknowledged by <@{}>", id, interaction.user.id),
+            });
+            tokio::spawn(async move {
+                let result = reqwest::Client::new().post(&url).json(&message).send().await;
+                if let Err(e) = result.and_then(|r| r.error_for_status()) {
+                    warn!("Failed to confirm acknowledgement in Slack: {}", e);
+                }
+            });
+        }
+        // Slack only needs a 200; the confirmation goes to the channel
+        return HttpResponse::Ok().finish();
+    }
+    response
+}
+
+// ============================================================================
+// MICROSOFT TEAMS
+// ============================================================================
+
+fn severity_color(severity: AlertSeverity) -> &'static str {
+    match severity {
+        AlertSeverity::Critical => "D13438",
+        AlertSeverity::Warning => "FFB900",
+        AlertSeverity::Info => "0078D7",
+    }
+}
+
+/// Signature proving a Teams ack action came from a card we sent
+pub fn teams_ack_signature(secret: &str, id: i64) -> String {
+    hex(&mac(secret, &[b"ack:", id.to_string().as_bytes()]).finalize().into_bytes())
+}
+
+/// MessageCard for a Teams incoming webhook
+pub fn teams_message(card: &AlertCard, config: &ChatOpsConfig) -> serde_json::Value {
+    let mut facts = vec![
+        json!({"name": "Room", "value": card.room}),
+        json!({"name": "Severity", "value": format!("{:?}", card.alert.severity())}),
+        json!({"name": "Time", "value": card.timestamp.to_rfc3339()}),
+    ];
+    if let Some(ward) = card.ward {
+        facts.push(json!({"name": "Ward", "value": ward}));
+    }
+
+    let mut actions = Vec::new();
+    if let Some(id) = card.observation_id {
+        actions.push(json!({
+            "@type": "OpenUri",
+            "name": "View chart",
+            "targets": [{"os": "default", "uri": chart_url(config, id)}],
+        }));
+        if let Some(secret) = &config.teams_ack_secret {
+            let body = json!({"alertId": id, "signature": teams_ack_signature(secret, id)});
+            actions.push(json!({
+                "@type": "HttpPOST",
+                "name": "Acknowledge",
+                "target": format!("{}/api/integrations/teams/ack", config.public_url),
+                "body": body.to_string(),
+            }));
+        }
+    }
+
+    json!({
+        "@type": "MessageCard",
+        "@context": "https://schema.org/extensions",
+        "themeColor": severity_color(card.alert.severity()),
+        "summary": title(card),
+        "title": title(card),
+        "sections": [{"facts": facts}],
+        "potentialAction": actions,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamsAck {
+    pub alert_id: i64,
+    pub signature: String,
+}
+
+/// POST /api/integrations/teams/ack
+///
+/// Target of the Teams card's Acknowledge action
+#[post("/api/integrations/teams/ack")]
+pub async fn teams_ack(
+    state: web::Data<AppState>,
+    chatops: web::Data<ChatOpsConfig>,
+    body: web::Json<TeamsAck>,
+    request_id: RequestId,
+) -> impl Responder {
+    let Some(secret) = &chatops.teams_ack_secret else {
+        return HttpResponse::NotFound().json(ApiError::not_found("Teams integration is not configured"));
+    };
+    let valid = from_hex(&body.signature).is_some_and(|tag| {
+        mac(secret, &[b"ack:", body.alert_id.to_string().as_bytes()]).verify_slice(&tag).is_ok()
+    });
+    if !valid {
+        warn!("Rejected Teams acknowledgement with invalid signature");
+        return HttpResponse::Unauthorized().json(ApiError::new("invalid_signature", "Invalid signature"));
+    }
+
+    info!("Alert {} acknowledged from Teams", body.alert_id);
+    api::acknowledge(&state, body.alert_id, None, None, "teams", &request_id).await
+}
+
+// ============================================================================
+// ACK LINKS
+// ============================================================================
+
+/// Link acknowledging alert `id` as `recipient` until `ack_link_ttl` after
+/// `now`; `None` without `ack_link_secret`
+pub fn ack_link(config: &ChatOpsConfig, id: i64, recipient: &str, now: DateTime<Utc>) -> Option<String> {
+    let secret = config.ack_link_secret.as_deref()?;
+    let payload = format!("{}.{}.{}", id, (now + config.ack_link_ttl).timestamp(), hex(recipient.as_bytes()));
+    let signature = hex(&mac(secret, &[b"ack-link:", payload.as_bytes()]).finalize().into_bytes());
+    Some(format!("{}/api/alerts/ack-token/{}.{}", config.public_url, payload, signature))
+}
+
+/// What a valid ack link's token says
+struct AckToken {
+    alert_id: i64,
+    expires: DateTime<Utc>,
+    recipient: String,
+}
+
+/// Token `<alert id>.<expiry>.<hex recipient>.<hex hmac>` if we signed it
+fn verify_ack_token(secret: &str, token: &str) -> Option<AckToken> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    mac(secret, &[b"ack-link:", payload.as_bytes()]).verify_slice(&from_hex(signature)?).ok()?;
+    let mut parts = payload.splitn(3, '.');
+    Some(AckToken {
+        alert_id: parts.next()?.parse().ok()?,
+        expires: DateTime::from_timestamp(parts.next()?.parse().ok()?, 0)?,
+        recipient: String::from_utf8(from_hex(parts.next()?)?).ok()?,
+    })
+}
+
+/// Minimal page for a phone's browser
+fn ack_page(status: StatusCode, message: &str) -> HttpResponse {
+    let escaped = message.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+             <title>Alert acknowledgement</title></head><body><p>{}</p></body></html>",
+            escaped,
+        ))
+}
+
+/// GET /api/alerts/ack-token/{token}
+///
+/// Target of the ack link in email and SMS notifications; acknowledges the
+/// alert as the link's recipient
+#[get("/api/alerts/ack-token/{token}")]
+pub async fn ack_link_target(
+    state: web::Data<AppState>,
+    chatops: web::Data<ChatOpsConfig>,
+    token: web::Path<String>,
+    request_id: RequestId,
+) -> impl Responder {
+    let Some(secret) = &chatops.ack_link_secret else {
+        return HttpResponse::NotFound().json(ApiError::not_found("Ack links are not configured"));
+    };
+    let Some(token) = verify_ack_token(secret, &token) else {
+        warn!("Rejected ack link with invalid signature");
+        return ack_page(StatusCode::UNAUTHORIZED, "This link is not valid.");
+    };
+    if token.expires < Utc::now() {
+        return ack_page(StatusCode::GONE, "This link has expired. Please acknowledge the alert on the dashboard.");
+    }
+
+    let by = Some(token.recipient.as_str()).filter(|r| !r.is_empty());
+    let response = api::acknowledge(&state, token.alert_id, by, None, "link", &request_id).await;
+    match response.status() {
+        status if status.is_success() => {
+            info!("Alert {} acknowledged by link", token.alert_id);
+            ack_page(StatusCode::OK, &format!("Alert {} acknowledged. Thank you.", token.alert_id))
+        }
+        StatusCode::NOT_FOUND => ack_page(StatusCode::NOT_FOUND, "This alert no longer exists."),
+        status => ack_page(status, "The alert could not be acknowledged. Please try again or use the dashboard."),
+    }
+}
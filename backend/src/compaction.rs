@@ -0,0 +1,124 @@
+//! Cold-path compaction
+//!
+//! While nothing changes in a room, e.g. with the patient asleep, the device
+//! still sends the same reading every second. With `COMPACT_AFTER_DAYS` set,
+//! readings from before the facility day that many days before today are
+//! compacted once an hour: each room's runs of consecutive readings without
+//! motion, alerts, tags or vital signs, and with the same temperature, sound
+//! level, humidity, door contact, light level, CO2, radar presence,
+//! acceleration and patient, are merged into an interval row, the first
+//! reading of the run with the run's end and number of readings. Queries of
+//! readings, from listings, charts and exports to the activity analysis and
+//! other aggregates, expand intervals into evenly spaced readings again, so
+//! clients don't see the difference; the running totals count an interval
+//! as its readings. Progress is checkpointed an hour at a time (see `jobs`),
+//! so after a restart compaction continues where it got to.
+//!
+//! Runs end at gaps longer than `MAX_GAP`, so a device outage stays a gap,
+//! and span at most `MAX_SPAN`.
+
+use chrono::{DateTime, Duration, Utc};
+use patient_monitor_types::analysis::compaction_runs;
+use tracing::{error, info};
+
+use crate::days::FacilityDays;
+use crate::db::{Database, DbError};
+use crate::jobs::{self, Run};
+
+/// Readings further apart than this aren't merged
+const MAX_GAP: Duration = Duration::seconds(60);
+/// Longest interval; range queries look this far back for intervals reaching
+/// into their range
+pub const MAX_SPAN: Duration = Duration::hours(1);
+/// Rows are read and merged an hour of one room at a time, and runs are
+/// checkpointed after each hour of all rooms
+const CHUNK: Duration = Duration::hours(1);
+/// Days before the cutoff compacted by the first run
+const BACKFILL_DAYS: i64 = 7;
+const COMPACT_INTERVAL_SECS: u64 = 3600;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompactionSummary {
+    /// Intervals created or extended
+    pub intervals: u64,
+    /// Readings merged into them and deleted
+    pub readings: u64,
+}
+
+/// Merge the runs of identical readings of `rooms` from `start` to `end`,
+/// checkpointing `run` with the time compacted up to
+pub async fn compact(
+    db: &Database,
+    run: &Run,
+    rooms: &[String],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<CompactionSummary, DbError> {
+    let mut summary = CompactionSummary::default();
+
+    let mut from = start;
+    while from < end {
+        let to = (from + CHUNK).min(end);
+        for room in rooms {
+            let rows = db.get_compaction_rows(room, from, to).await?;
+            let runs = compaction_runs(&rows, MAX_GAP, MAX_SPAN);
+            if !runs.is_empty() {
+                summary.readings += db.merge_runs(&runs).await?;
+                summary.intervals += runs.len() as u64;
+            }
+        }
+        run.checkpoint(&to.to_rfc3339()).await?;
+        from = to;
+    }
+
+    Ok(summary)
+}
+
+/// Compact the readings older than `days` facility days once an hour,
+/// unless compaction is disabled
+pub fn spawn(db: Database, rooms: Vec<String>, days: u32, facility: FacilityDays) {
+    if days == 0 {
+        return;
+    }
+    info!("Compacting identical readings older than {} days", days);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(COMPACT_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let cutoff = facility.start_of(facility.day_of(Utc::now()) - Duration::days(days as i64));
+            // Runs continue from where the last one got to, completed or not
+            let checkpoint = match jobs::last_run(&db, jobs::COMPACTION).await {
+                Ok(run) => run.and_then(|r| r.checkpoint),
+                Err(e) => {
+                    error!("Failed to look up the last compaction run: {}", e);
+                    continue;
+                }
+            };
+            let start = checkpoint
+                .as_deref()
+                .and_then(|c| DateTime::parse_from_rfc3339(c).ok())
+                .map(|t| t.with_timezone(&Utc))
+                .unwrap_or(cutoff - Duration::days(BACKFILL_DAYS));
+            if start >= cutoff {
+                continue;
+            }
+            let run = match Run::start(&db, jobs::COMPACTION, checkpoint.as_deref()).await {
+                Ok(run) => run,
+                Err(e) => {
+                    error!("Failed to record the compaction run: {}", e);
+                    continue;
+                }
+            };
+            let result = compact(&db, &run, &rooms, start, cutoff).await;
+            match &result {
+                Ok(summary) if summary.readings > 0 => info!("Compacted {} readings into {} intervals from before {}",
+                    summary.readings, summary.intervals, cutoff),
+                Ok(_) => {}
+                // Retried with the next run
+                Err(e) => error!("Failed to compact readings: {}", e),
+            }
+            run.finish(&result).await;
+        }
+    });
+}
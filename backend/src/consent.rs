@@ -0,0 +1,105 @@
+//! Patient consent applied to readings
+//!
+//! Consent is given by patients, not rooms. Readings are served or exported
+//! by the consent of the patient they were taken of, so a patient who moves
+//! or is discharged takes their consent along and the next patient of the
+//! room doesn't inherit it. Readings taken while nobody was assigned to the
+//! room have the default consent. A room's readings are stored while the
+//! patient now assigned to it consents to monitoring.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::db::{Consent, Database, DbError};
+
+/// Per room, whether the patient assigned to it consents to readings being
+/// stored. Shared by the room's ingest task and the API, which refreshes it
+/// whenever a patient's room or consent changes.
+#[derive(Debug, Clone, Default)]
+pub struct MonitoringConsent(Arc<HashMap<String, Arc<AtomicBool>>>);
+
+impl MonitoringConsent {
+    pub async fn load(db: &Database, rooms: &[String]) -> Result<Self, DbError> {
+        let consent = Self(Arc::new(
+            rooms.iter().map(|room| (room.clone(), Arc::new(AtomicBool::new(true)))).collect(),
+        ));
+        consent.refresh(db).await?;
+        Ok(consent)
+    }
+
+    /// Flag of `room`, as read by its ingest task
+    pub fn room(&self, room: &str) -> Option<Arc<AtomicBool>> {
+        self.0.get(room).cloned()
+    }
+
+    /// Whether readings of `room` may be stored; unknown rooms have no
+    /// patient to refuse
+    pub fn allows(&self, room: &str) -> bool {
+        self.0.get(room).is_none_or(|c| c.load(Ordering::Relaxed))
+    }
+
+    /// Look up the consent of the patients now assigned to the rooms
+    pub async fn refresh(&self, db: &Database) -> Result<(), DbError> {
+        for (room, flag) in self.0.iter() {
+            let consent = match db.get_patients(Some(room)).await?.first() {
+                Some(patient) => db.get_consent(&patient.id).await?,
+                None => Consent::default(),
+            };
+            let was = flag.swap(consent.continuous_monitoring, Ordering::Relaxed);
+            if was && !consent.continuous_monitoring {
+                info!("Patient in {} has not consented to monitoring; readings will not be stored", room);
+            }
+        }
+        Ok(())
+    }
+
+    /// `refresh`, logging a failure rather than failing the change that led to it
+    pub async fn refresh_or_log(&self, db: &Database) {
+        if let Err(e) = self.refresh(db).await {
+            error!("Failed to refresh monitoring consent: {}", e);
+        }
+    }
+}
+
+/// Consent of the patients readings were taken of, as checked by `allows`,
+/// looked up once per patient
+pub struct PatientConsent {
+    allows: fn(&Consent) -> bool,
+    patients: HashMap<Option<String>, bool>,
+}
+
+impl PatientConsent {
+    pub fn new(allows: fn(&Consent) -> bool) -> Self {
+        Self { allows, patients: HashMap::new() }
+    }
+
+    /// Look up the consent of those of `patients` not seen yet
+    pub async fn load<'a>(
+        &mut self,
+        db: &Database,
+        patients: impl IntoIterator<Item = &'a Option<String>>,
+    ) -> Result<(), DbError> {
+        let mut unknown: Vec<Option<String>> = patients
+            .into_iter()
+            .filter(|p| !self.patients.contains_key(*p))
+            .cloned()
+            .collect();
+        unknown.sort();
+        unknown.dedup();
+        for patient in unknown {
+            let consent = match &patient {
+                Some(id) => db.get_consent(id).await?,
+                None => Consent::default(),
+            };
+            self.patients.insert(patient, (self.allows)(&consent));
+        }
+        Ok(())
+    }
+
+    /// Whether `patient` gives the consent; patients not loaded don't
+    pub fn allows(&self, patient: &Option<String>) -> bool {
+        self.patients.get(patient).copied().unwrap_or(false)
+    }
+}
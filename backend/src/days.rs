@@ -0,0 +1,75 @@
+//! Facility days
+//!
+//! Daily summaries, reports and retention count days in the facility's time
+//! zone (`FACILITY_TZ`), starting at `DAY_START_HOUR` local time, rather than
+//! from UTC midnight to midnight. A day starting at 07:00 keeps a night in
+//! one day, named by the date it started on. The defaults, UTC and hour 0,
+//! make a day a UTC calendar day.
+//!
+//! Quiet hours, and with them the report schedules and handover sleep
+//! windows, are hours of the same local time.
+//!
+//! Days are 23 or 25 hours long when daylight saving time starts or ends. If
+//! the start hour is skipped that day, the day starts an hour later; if it
+//! occurs twice, at the first.
+
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FacilityDays {
+    pub timezone: Tz,
+    /// Local hour (0-23) days start at
+    pub start_hour: u32,
+}
+
+impl Default for FacilityDays {
+    fn default() -> Self {
+        Self { timezone: chrono_tz::UTC, start_hour: 0 }
+    }
+}
+
+impl FacilityDays {
+    /// Local time of `t`
+    pub fn local(&self, t: DateTime<Utc>) -> NaiveDateTime {
+        t.with_timezone(&self.timezone).naive_local()
+    }
+
+    /// Instant of local time `time`
+    pub fn instant(&self, time: NaiveDateTime) -> DateTime<Utc> {
+        match self.timezone.from_local_datetime(&time) {
+            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t.with_timezone(&Utc),
+            LocalResult::None => self.instant(time + Duration::hours(1)),
+        }
+    }
+
+    /// Instant `hour` o'clock local time on `date`
+    pub fn at(&self, date: NaiveDate, hour: u32) -> DateTime<Utc> {
+        self.instant(date.and_time(NaiveTime::from_hms_opt(hour, 0, 0).unwrap_or(NaiveTime::MIN)))
+    }
+
+    /// When `day` starts
+    pub fn start_of(&self, day: NaiveDate) -> DateTime<Utc> {
+        self.at(day, self.start_hour)
+    }
+
+    /// Start and end (exclusive) of `day`
+    pub fn bounds(&self, day: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+        (self.start_of(day), self.start_of(day + Duration::days(1)))
+    }
+
+    /// Day `t` falls on
+    pub fn day_of(&self, t: DateTime<Utc>) -> NaiveDate {
+        (self.local(t) - Duration::hours(self.start_hour as i64)).date()
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.day_of(Utc::now())
+    }
+
+    /// Start of the week (from Monday) `t` falls in
+    pub fn week_start(&self, t: DateTime<Utc>) -> DateTime<Utc> {
+        let day = self.day_of(t);
+        self.start_of(day - Duration::days(day.weekday().num_days_from_monday() as i64))
+    }
+}
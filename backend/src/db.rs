@@ -1,24 +1,272 @@
-//! Database module for PostgreSQL
+//! Storage
+//!
+//! The server talks to storage through the `StorageBackend` trait. PostgreSQL
+//! (`db::postgres`) is the default; small bedside deployments can use a
+//! single SQLite file instead (`db::sqlite`, `DB_BACKEND=sqlite`).
+//!
+//! The schema of each backend is defined by versioned SQL migrations in
+//! `migrations/<backend>/` (`V<n>__<name>.sql`), embedded in the binary and
+//! recorded in the `migrations` table once applied. To change the schema, add
+//! a migration with the next version; applied migrations must not be edited.
 
-use chrono::{DateTime, Utc};
-use deadpool_postgres::{Config, Pool, Runtime, ManagerConfig, RecyclingMethod};
-use tokio_postgres::{NoTls, Row};
-use tracing::{info, debug};
+mod postgres;
+mod sqlite;
+mod tls;
 
-use crate::fhir::{AlertType, SensorEvent, SensorReading};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::days::FacilityDays;
+use crate::flags::FlagSetting;
+use crate::fhir::{
+    AlertOutcome, AlertSet, AlertSeverity, AlertType, Gender, ManualObservation, Patient, ResolutionReason, SensorEvent,
+};
+pub use patient_monitor_types::api::{
+    ActivityAnalysis, AlertCounts, ChartPoint, DailySummary, DoorPassage, FallRiskFactors, FallRiskScore, HourlyActivity, MonitorSettings,
+    PatientState, ResearchGroup, StillPeriod, StillPeriods, STILL_PERIOD_BUCKETS, TOP_STILL_PERIODS,
+};
+use crate::reports::QuietHours;
+use crate::rules::{AlertRule, ShadowHit};
+use patient_monitor_types::analysis::lttb;
+pub use patient_monitor_types::analysis::{CompactionRow, LightSums, ReadingRun};
+use patient_monitor_types::ws::{MessageKind, Subscription};
+
+fn alert_to_str(alert: AlertType) -> &'static str {
+    alert.as_str()
+}
+
+fn severity_to_str(severity: AlertSeverity) -> &'static str {
+    match severity {
+        AlertSeverity::Info => "info",
+        AlertSeverity::Warning => "warning",
+        AlertSeverity::Critical => "critical",
+    }
+}
+
+fn severity_from_str(s: &str) -> Option<AlertSeverity> {
+    match s {
+        "info" => Some(AlertSeverity::Info),
+        "warning" => Some(AlertSeverity::Warning),
+        "critical" => Some(AlertSeverity::Critical),
+        _ => None,
+    }
+}
+
+fn message_kind_to_str(kind: MessageKind) -> &'static str {
+    match kind {
+        MessageKind::SensorReading => "sensorReading",
+        MessageKind::AlertResolved => "alertResolved",
+        MessageKind::DeviceStatus => "deviceStatus",
+        MessageKind::DoorPassage => "doorPassage",
+    }
+}
+
+fn message_kind_from_str(s: &str) -> Option<MessageKind> {
+    match s {
+        "sensorReading" => Some(MessageKind::SensorReading),
+        "alertResolved" => Some(MessageKind::AlertResolved),
+        "deviceStatus" => Some(MessageKind::DeviceStatus),
+        "doorPassage" => Some(MessageKind::DoorPassage),
+        _ => None,
+    }
+}
+
+fn gender_from_str(s: &str) -> Option<Gender> {
+    match s {
+        "male" => Some(Gender::Male),
+        "female" => Some(Gender::Female),
+        "other" => Some(Gender::Other),
+        "unknown" => Some(Gender::Unknown),
+        _ => None,
+    }
+}
+
+fn alert_from_str(s: &str) -> Option<AlertType> {
+    s.parse().ok()
+}
+
+/// Failure of a storage operation, classified so the API can answer with a
+/// fitting status instead of a blanket 500
+#[derive(Debug, thiserror::Error)]
+pub enum DbError {
+    /// A row the operation expected doesn't exist
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// A constraint rejected the write, e.g. a duplicate key or a reference
+    /// to a missing row
+    #[error("constraint violation: {0}")]
+    Constraint(String),
+    /// The database can't be reached or the connection was lost; retrying
+    /// later may succeed
+    #[error("database unavailable: {0}")]
+    Unavailable(String),
+    /// An analytics query exceeded `DB_ANALYTICS_TIMEOUT_SECS`
+    #[error("query exceeded {}s timeout", .0.as_secs())]
+    Timeout(Duration),
+    /// Anything else, e.g. a failed migration or a malformed stored value
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl DbError {
+    pub fn other(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        DbError::Other(e.into())
+    }
+}
+
+impl From<refinery::Error> for DbError {
+    fn from(e: refinery::Error) -> Self {
+        DbError::Other(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for DbError {
+    fn from(e: serde_json::Error) -> Self {
+        DbError::Other(Box::new(e))
+    }
+}
+
+impl From<chrono::RoundingError> for DbError {
+    fn from(e: chrono::RoundingError) -> Self {
+        DbError::Other(Box::new(e))
+    }
+}
+
+impl From<tokio::task::JoinError> for DbError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        DbError::Other(Box::new(e))
+    }
+}
+
+/// Table recording the applied schema migrations
+const MIGRATIONS_TABLE: &str = "migrations";
+
+fn log_migrations(report: &refinery::Report) {
+    for migration in report.applied_migrations() {
+        info!("Applied schema migration {}", migration);
+    }
+}
+
+/// Fail unless all of the runner's migrations have been applied
+fn ensure_migrated(runner: &refinery::Runner, applied: &[refinery::Migration]) -> Result<(), String> {
+    let mut pending: Vec<&refinery::Migration> = runner
+        .get_migrations()
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.version() == m.version()))
+        .collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+    pending.sort_by_key(|m| m.version());
+    Err(format!(
+        "Database schema is not up to date, pending migrations: {}. Run `monitor --migrate` or set DB_AUTO_MIGRATE=true",
+        pending.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Postgres,
+    Sqlite,
+}
+
+/// Whether and how PostgreSQL connections are encrypted, named and behaving
+/// like libpq's `sslmode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SslMode {
+    #[default]
+    Disable,
+    /// Encrypt if the server supports it, without checking its certificate
+    Prefer,
+    /// Always encrypt, without checking the server's certificate unless a CA
+    /// certificate is configured
+    Require,
+    /// Always encrypt and check that the server's certificate is signed by a
+    /// trusted CA
+    VerifyCa,
+    /// Like `VerifyCa`, and check that the certificate is for `DB_HOST`
+    VerifyFull,
+}
+
+impl std::str::FromStr for SslMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "disable" => Ok(SslMode::Disable),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            other => Err(format!("Unknown SSL mode: {}", other)),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct DbConfig {
+    pub backend: BackendKind,
     pub host: String,
     pub port: u16,
     pub user: String,
     pub password: String,
     pub dbname: String,
+    pub ssl_mode: SslMode,
+    /// PEM file of the CA certificates trusted for the server's certificate;
+    /// without one the Mozilla root certificates are trusted
+    pub ssl_root_cert: Option<PathBuf>,
+    /// Database file of the SQLite backend
+    pub sqlite_path: PathBuf,
+    /// Store readings in a TimescaleDB hypertable and serve activity
+    /// analytics from hourly continuous aggregates (PostgreSQL only)
+    pub timescale: bool,
+    /// Store readings in a table partitioned by month, keeping each
+    /// partition's indexes small (PostgreSQL only)
+    pub partitioned: bool,
+    /// Months of readings kept when partitioned, besides the current one;
+    /// older partitions are detached and dropped. 0 keeps them forever.
+    pub partition_keep_months: u32,
+    /// Longest an analytics query may run before it is cancelled
+    pub analytics_timeout: Option<Duration>,
+    /// How long startup waits for an unavailable PostgreSQL server
+    pub connect_wait: Duration,
+    /// Apply pending schema migrations on startup; otherwise refuse to start
+    /// until they are applied with `monitor --migrate`
+    pub auto_migrate: bool,
 }
 
 impl DbConfig {
     pub fn from_env() -> Self {
+        let backend = match std::env::var("DB_BACKEND").as_deref() {
+            Ok("sqlite") => BackendKind::Sqlite,
+            Ok("postgres") | Err(_) => BackendKind::Postgres,
+            Ok(other) => {
+                warn!("Unknown DB_BACKEND '{}', using postgres", other);
+                BackendKind::Postgres
+            }
+        };
+        let timescale = std::env::var("DB_TIMESCALE").map(|v| v == "true" || v == "1").unwrap_or(false);
+        if timescale && backend == BackendKind::Sqlite {
+            warn!("DB_TIMESCALE only applies to the postgres backend; ignoring it");
+        }
+        let mut partitioned = std::env::var("DB_PARTITIONED").map(|v| v == "true" || v == "1").unwrap_or(false);
+        if partitioned && backend == BackendKind::Sqlite {
+            warn!("DB_PARTITIONED only applies to the postgres backend; ignoring it");
+            partitioned = false;
+        } else if partitioned && timescale {
+            // Hypertables are already partitioned by time
+            warn!("DB_PARTITIONED has no effect with DB_TIMESCALE; ignoring it");
+            partitioned = false;
+        }
         Self {
+            backend,
             host: std::env::var("DB_HOST").unwrap_or_else(|_| "localhost".to_string()),
             port: std::env::var("DB_PORT")
                 .ok()
@@ -27,339 +275,754 @@ impl DbConfig {
             user: std::env::var("DB_USER").unwrap_or_else(|_| "postgres".to_string()),
             password: std::env::var("DB_PASSWORD").unwrap_or_else(|_| "postgres".to_string()),
             dbname: std::env::var("DB_NAME").unwrap_or_else(|_| "patient_monitor".to_string()),
+            ssl_mode: std::env::var("DB_SSLMODE")
+                .map(|m| m.parse().expect("DB_SSLMODE must be disable, prefer, require, verify-ca or verify-full"))
+                .unwrap_or_default(),
+            ssl_root_cert: std::env::var("DB_SSLROOTCERT").ok().filter(|p| !p.is_empty()).map(PathBuf::from),
+            sqlite_path: std::env::var("SQLITE_PATH")
+                .unwrap_or_else(|_| "patient_monitor.db".to_string())
+                .into(),
+            timescale,
+            partitioned,
+            partition_keep_months: std::env::var("DB_PARTITION_KEEP_MONTHS").ok().and_then(|m| m.parse().ok()).unwrap_or(0),
+            // 0 disables the timeout
+            analytics_timeout: Some(std::env::var("DB_ANALYTICS_TIMEOUT_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(30))
+                .filter(|s| *s > 0)
+                .map(Duration::from_secs),
+            connect_wait: Duration::from_secs(std::env::var("DB_CONNECT_WAIT_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(60)),
+            auto_migrate: std::env::var("DB_AUTO_MIGRATE").map(|v| v == "true" || v == "1").unwrap_or(true),
         }
     }
 }
 
+/// Handle to the configured storage backend, cheap to clone
 #[derive(Clone)]
-pub struct Database {
-    pool: Pool,
-}
+pub struct Database(Arc<dyn StorageBackend>);
 
 impl Database {
-    pub async fn new(config: DbConfig) -> Result<Self, Box<dyn std::error::Error>> {
-        info!("Connecting to PostgreSQL at {}:{}", config.host, config.port);
-        
-        let mut cfg = Config::new();
-        cfg.host = Some(config.host);
-        cfg.port = Some(config.port);
-        cfg.user = Some(config.user);
-        cfg.password = Some(config.password);
-        cfg.dbname = Some(config.dbname);
-        cfg.manager = Some(ManagerConfig {
-            recycling_method: RecyclingMethod::Fast,
-        });
-        
-        let pool = cfg.create_pool(Some(Runtime::Tokio1), NoTls)?;
-        
-        let db = Self { pool };
-        db.init_schema().await?;
-        
-        info!("Database initialized successfully");
-        Ok(db)
+    pub async fn new(config: DbConfig) -> Result<Self, DbError> {
+        Ok(match config.backend {
+            BackendKind::Postgres => Self(Arc::new(postgres::Postgres::connect(config).await?)),
+            BackendKind::Sqlite => Self(Arc::new(sqlite::Sqlite::open(config).await?)),
+        })
     }
-    
-    async fn init_schema(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let client = self.pool.get().await?;
-        
-        client.execute(
-            "CREATE TABLE IF NOT EXISTS sensor_data (
-                id BIGSERIAL PRIMARY KEY,
-                timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW(),
-                temperature REAL NOT NULL,
-                motion BOOLEAN NOT NULL,
-                sound_level INTEGER NOT NULL,
-                alert_type VARCHAR(20) NOT NULL DEFAULT 'none'
-            )",
-            &[],
-        ).await?;
-        
-        client.execute(
-            "CREATE INDEX IF NOT EXISTS idx_sensor_timestamp ON sensor_data(timestamp DESC)",
-            &[],
-        ).await?;
-        
-        Ok(())
+}
+
+impl Deref for Database {
+    type Target = dyn StorageBackend;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
     }
+}
+
+/// Operations the server needs from its storage. Implemented for
+/// PostgreSQL and for SQLite, selected with `DB_BACKEND`.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Round trip to the database, failing if it can't be reached
+    async fn ping(&self) -> Result<(), DbError>;
     
-    pub async fn insert_reading(&self, event: &SensorEvent) -> Result<i64, Box<dyn std::error::Error>> {
-        let client = self.pool.get().await?;
-        
-        let alert_str = match event.alert {
-            AlertType::None => "none",
-            AlertType::Fall => "fall",
-            AlertType::Inactivity => "inactivity",
-        };
-        
-        let row = client.query_one(
-            "INSERT INTO sensor_data (timestamp, temperature, motion, sound_level, alert_type)
-             VALUES ($1, $2, $3, $4, $5)
-             RETURNING id",
-            &[
-                &event.reading.timestamp,
-                &event.reading.temperature,
-                &event.reading.motion,
-                &event.reading.sound_level,
-                &alert_str,
-            ],
-        ).await?;
-        
-        let id: i64 = row.get(0);
-        debug!("Inserted reading with ID: {}", id);
-        
-        Ok(id)
-    }
+    /// Connections of the pool, `None` for a backend without one
+    fn pool_status(&self) -> Option<PoolStatus>;
     
-    pub async fn get_recent_readings(&self, limit: usize) -> Result<Vec<SensorEvent>, Box<dyn std::error::Error>> {
-        let client = self.pool.get().await?;
-        
-        let rows = client.query(
-            "SELECT id, timestamp, temperature, motion, sound_level, alert_type
-             FROM sensor_data
-             ORDER BY timestamp DESC
-             LIMIT $1",
-            &[&(limit as i64)],
-        ).await?;
-        
-        let events = rows.iter().map(Self::row_to_event).collect();
-        Ok(events)
-    }
+    /// Add the configured rooms. Readings stored before rooms existed are
+    /// assigned to the first one.
+    async fn register_rooms(&self, rooms: &[String]) -> Result<(), DbError>;
+    
+    /// Registered rooms with the time of their latest reading
+    async fn get_rooms(&self) -> Result<Vec<Room>, DbError>;
+    
+    /// Create `ward` if it doesn't exist and assign it the given rooms that
+    /// aren't in a ward yet. Used to carry a configured `WARD_ID` over.
+    async fn register_ward(&self, ward: &str, rooms: &[String]) -> Result<(), DbError>;
+    
+    /// Wards by id, with the ids of their rooms
+    async fn get_wards(&self) -> Result<Vec<Ward>, DbError>;
+    
+    /// Create a ward. Returns false if the id is already taken.
+    async fn insert_ward(&self, id: &str, name: &str) -> Result<bool, DbError>;
+    
+    /// Delete a ward without rooms. Returns false if there is no such ward.
+    async fn delete_ward(&self, id: &str) -> Result<bool, DbError>;
+    
+    /// Move a room to `ward`, or out of any ward. Returns false if there is
+    /// no such room.
+    async fn set_room_ward(&self, room: &str, ward: Option<&str>) -> Result<bool, DbError>;
+    
+    /// Registered patients by id, optionally only the one in `room`
+    async fn get_patients(&self, room: Option<&str>) -> Result<Vec<Patient>, DbError>;
+    
+    async fn get_patient(&self, id: &str) -> Result<Option<Patient>, DbError>;
+    
+    /// Register a patient. Returns false if the id is already taken.
+    async fn insert_patient(&self, patient: &Patient) -> Result<bool, DbError>;
+    
+    /// Update a patient's details and room. Returns false if there is no such patient.
+    async fn update_patient(&self, patient: &Patient) -> Result<bool, DbError>;
+    
+    /// Remove a patient from the registry; their readings stay, attributed to no one
+    async fn delete_patient(&self, id: &str) -> Result<bool, DbError>;
     
-    pub async fn get_readings_in_range(
+    /// The newest readings, or with a cursor the ones following it, newest first
+    async fn get_recent_readings(
+        &self,
+        limit: usize,
+        room: Option<&str>,
+        tag: Option<&str>,
+        cursor: Option<ReadingCursor>,
+    ) -> Result<Vec<SensorEvent>, DbError>;
+    
+    /// Readings stored after change-feed position `after`, oldest first
+    async fn get_readings_after(
+        &self,
+        after: i64,
+        limit: usize,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Vec<SensorEvent>, DbError>;
+    
+    /// Insert readings in one statement; returns what was stored for each,
+    /// in the order of `events`
+    async fn insert_readings_batch(
+        &self,
+        events: &[SensorEvent],
+    ) -> Result<Vec<StoredReading>, DbError>;
+    
+    /// Insert readings taken before they reached this server, e.g. by an
+    /// older logger, in one transaction. Their alerts aren't stored and no
+    /// alerts are opened or resolved; returns the number stored.
+    async fn insert_historical(&self, events: &[SensorEvent]) -> Result<u64, DbError>;
+    
+    async fn count_readings_in_range(
         &self,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
-    ) -> Result<Vec<SensorEvent>, Box<dyn std::error::Error>> {
-        let client = self.pool.get().await?;
-        
-        let rows = client.query(
-            "SELECT id, timestamp, temperature, motion, sound_level, alert_type
-             FROM sensor_data
-             WHERE timestamp BETWEEN $1 AND $2
-             ORDER BY timestamp DESC",
-            &[&start, &end],
-        ).await?;
-        
-        let events = rows.iter().map(Self::row_to_event).collect();
-        Ok(events)
-    }
+        room: Option<&str>,
+    ) -> Result<u64, DbError>;
     
-    pub async fn get_reading_by_id(&self, id: i64) -> Result<Option<SensorEvent>, Box<dyn std::error::Error>> {
-        let client = self.pool.get().await?;
-        
-        let row = client.query_opt(
-            "SELECT id, timestamp, temperature, motion, sound_level, alert_type
-             FROM sensor_data WHERE id = $1",
-            &[&id],
-        ).await?;
-        
-        Ok(row.map(|r| Self::row_to_event(&r)))
-    }
+    /// Readings in the range, newest first, optionally only those of `room`
+    /// or tagged `tag`. With a cursor only those following it, and with a
+    /// limit at most that many.
+    async fn get_readings_in_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+        cursor: Option<ReadingCursor>,
+        limit: Option<usize>,
+    ) -> Result<Vec<SensorEvent>, DbError>;
     
-    pub async fn get_alert_summary(&self) -> Result<AlertSummary, Box<dyn std::error::Error>> {
-        let client = self.pool.get().await?;
-        
-        let total: i64 = client.query_one("SELECT COUNT(*) FROM sensor_data", &[])
-            .await?.get(0);
-        
-        let falls: i64 = client.query_one(
-            "SELECT COUNT(*) FROM sensor_data WHERE alert_type = 'fall'", &[]
-        ).await?.get(0);
-        
-        let inactivity: i64 = client.query_one(
-            "SELECT COUNT(*) FROM sensor_data WHERE alert_type = 'inactivity'", &[]
-        ).await?.get(0);
-        
-        Ok(AlertSummary {
-            total_readings: total as u64,
-            fall_alerts: falls as u64,
-            inactivity_alerts: inactivity as u64,
-        })
-    }
+    /// One page of an export: readings from `start` to `end`, oldest first
+    /// (ties by ascending id), after the reading with the timestamp and id
+    /// `after` that ended the previous page
+    async fn export_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        after: Option<(DateTime<Utc>, i64)>,
+        limit: usize,
+    ) -> Result<Vec<SensorEvent>, DbError>;
     
-    fn row_to_event(row: &Row) -> SensorEvent {
-        let id: i64 = row.get(0);
-        let timestamp: DateTime<Utc> = row.get(1);
-        let temperature: f32 = row.get(2);
-        let motion: bool = row.get(3);
-        let sound_level: i32 = row.get(4);
-        let alert_str: &str = row.get(5);
-        
-        let alert = match alert_str {
-            "fall" => AlertType::Fall,
-            "inactivity" => AlertType::Inactivity,
-            _ => AlertType::None,
-        };
-        
-        SensorEvent {
-            id: Some(id),
-            reading: SensorReading {
-                temperature,
-                motion,
-                sound_level,
-                timestamp,
-            },
-            alert,
-        }
-    }
+    async fn get_reading_by_id(&self, id: i64) -> Result<Option<SensorEvent>, DbError>;
+    
+    /// Correct the values of a reading that isn't deleted and return it, or
+    /// `None` if there is no such reading. Its alerts are left as detected.
+    async fn update_reading(&self, id: i64, correction: &ReadingCorrection) -> Result<Option<SensorEvent>, DbError>;
+    
+    /// Mark a reading deleted at `at`, leaving it out of every query from
+    /// then on. It is kept until retention purges it. False if there is no
+    /// such reading or it already is deleted.
+    async fn mark_deleted(&self, id: i64, at: DateTime<Utc>) -> Result<bool, DbError>;
+    
+    async fn get_alert_summary(&self) -> Result<AlertSummary, DbError>;
+    
+    /// Consent recorded for a patient, or the defaults if none was recorded
+    async fn get_consent(&self, patient_id: &str) -> Result<Consent, DbError>;
+    
+    async fn set_consent(&self, patient_id: &str, consent: &Consent) -> Result<(), DbError>;
+    
+    async fn get_alert_rules(&self) -> Result<Vec<AlertRule>, DbError>;
+    
+    async fn insert_alert_rule(&self, rule: &AlertRule) -> Result<i64, DbError>;
+    
+    /// Returns false if no rule with that ID exists
+    async fn delete_alert_rule(&self, id: i64) -> Result<bool, DbError>;
+    
+    /// Make a rule a shadow rule or a live one. Returns false if no rule
+    /// with that ID exists.
+    async fn set_alert_rule_shadow(&self, id: i64, shadow: bool) -> Result<bool, DbError>;
+    
+    /// Record a shadow rule starting to match a room's readings
+    async fn insert_shadow_hit(&self, hit: &ShadowHit) -> Result<(), DbError>;
+    
+    /// Hits of rule `rule_id` from `from` to `to`, newest first, at most `limit`
+    async fn get_shadow_hits(
+        &self,
+        rule_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<ShadowHit>, DbError>;
+    
+    /// Number of hits of rule `rule_id` per room from `from` to `to`
+    async fn count_shadow_hits(
+        &self,
+        rule_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<HashMap<String, u64>, DbError>;
+    
+    async fn get_feature_flags(&self) -> Result<Vec<FlagSetting>, DbError>;
+    
+    /// Switch a flag for its room, or the facility, replacing its setting
+    async fn set_feature_flag(&self, setting: &FlagSetting) -> Result<(), DbError>;
+    
+    /// Remove a flag's setting for a room, or the facility if `room` is
+    /// unset. Returns false if there was none.
+    async fn delete_feature_flag(&self, name: &str, room: Option<&str>) -> Result<bool, DbError>;
+    
+    async fn get_rule_thresholds(&self) -> Result<HashMap<String, f64>, DbError>;
+    
+    async fn set_rule_threshold(&self, name: &str, value: f64) -> Result<(), DbError>;
+    
+    /// Store a log line or crash report uploaded by a device
+    async fn insert_device_log(
+        &self,
+        device_id: &str,
+        level: &str,
+        message: &str,
+    ) -> Result<i64, DbError>;
+    
+    async fn get_device_logs(
+        &self,
+        device_id: &str,
+        limit: usize,
+    ) -> Result<Vec<DeviceLog>, DbError>;
+    
+    /// Delete device logs received before `cutoff`, returning the number removed
+    async fn purge_device_logs(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError>;
+    
+    async fn insert_quarantined_reading(&self, reading: &QuarantinedReading) -> Result<(), DbError>;
+    
+    /// Readings quarantined from `from` to `to`, newest first, at most
+    /// `limit`; optionally only those of one room
+    async fn get_quarantined_readings(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        room: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<QuarantinedReading>, DbError>;
+    
+    /// Delete readings quarantined before `cutoff`, returning the number removed
+    async fn purge_quarantined_readings(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError>;
+    
+    /// Register a device as feeding `room`, or update its entry. The firmware
+    /// version and `seen_at` keep their stored values when not given
+    async fn upsert_device(
+        &self,
+        device_id: &str,
+        room: &str,
+        firmware_version: Option<&str>,
+        seen_at: Option<DateTime<Utc>>,
+    ) -> Result<(), DbError>;
+    
+    /// All registered devices, ordered by room
+    async fn get_devices(&self) -> Result<Vec<Device>, DbError>;
+    
+    /// Store statistics of closed WebSocket connections; sessions already
+    /// stored are skipped
+    async fn insert_ws_sessions(&self, sessions: &[WsSession]) -> Result<(), DbError>;
+    
+    /// Delete WebSocket sessions that ended before `cutoff`, returning the number removed
+    async fn purge_ws_sessions(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError>;
+    
+    /// Live feed `principal` subscribed to, or `None` if they never picked one
+    async fn get_ws_subscription(&self, principal: &str) -> Result<Option<Subscription>, DbError>;
+    
+    /// Store the live feed `principal` subscribed to, replacing any earlier one
+    async fn set_ws_subscription(&self, principal: &str, subscription: &Subscription) -> Result<(), DbError>;
+    
+    /// Forget `principal`'s subscription; false if they had none
+    async fn delete_ws_subscription(&self, principal: &str) -> Result<bool, DbError>;
+    
+    /// Up to `limit` of the oldest readings taken before `cutoff`, oldest
+    /// first (ties by ascending id) and as stored, i.e. with intervals left by
+    /// compaction not expanded
+    async fn readings_older_than(
+        &self,
+        cutoff: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<SensorEvent>, DbError>;
+    
+    /// Delete readings by ID, returning the number removed. Tags and
+    /// acknowledgements of the readings go with them.
+    async fn delete_readings(&self, ids: &[i64]) -> Result<u64, DbError>;
+    
+    /// Record the start of a run of the background job `job`, with the
+    /// checkpoint it resumes from. Returns the run's ID.
+    async fn start_job_run(
+        &self,
+        job: &str,
+        started_at: DateTime<Utc>,
+        checkpoint: Option<&str>,
+    ) -> Result<i64, DbError>;
+    
+    /// Record that a run completed another chunk, after which it would
+    /// resume from `checkpoint`
+    async fn checkpoint_job_run(&self, id: i64, checkpoint: &str) -> Result<(), DbError>;
+    
+    /// Record the end of a run, as failed with `error` if given
+    async fn finish_job_run(
+        &self,
+        id: i64,
+        finished_at: DateTime<Utc>,
+        error: Option<&str>,
+    ) -> Result<(), DbError>;
+    
+    /// Mark the runs still running as interrupted, returning their number
+    async fn interrupt_job_runs(&self) -> Result<u64, DbError>;
+    
+    /// Job runs, newest first, at most `limit`; optionally only those of one
+    /// job and/or with one status
+    async fn get_job_runs(
+        &self,
+        job: Option<&str>,
+        status: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<JobRun>, DbError>;
+    
+    /// Delete job runs started before `cutoff`, returning the number removed
+    async fn purge_job_runs(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError>;
+    
+    /// Rows of `room` from `start` to `end` (exclusive) that aren't deleted,
+    /// oldest first and as stored, i.e. with intervals left by an earlier
+    /// compaction not expanded
+    async fn get_compaction_rows(
+        &self,
+        room: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<CompactionRow>, DbError>;
+    
+    /// Turn the first reading of each run into the run's interval and delete
+    /// the others, in one transaction. Returns the number deleted.
+    async fn merge_runs(&self, runs: &[ReadingRun]) -> Result<u64, DbError>;
+    
+    /// Delete alert episodes that resolved before `cutoff`, and alert events
+    /// that occurred before it
+    async fn purge_alerts_resolved_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError>;
+    
+    /// Record that `room` entered `state` at `started_at`
+    async fn insert_room_state(
+        &self,
+        room: &str,
+        state: PatientState,
+        started_at: DateTime<Utc>,
+    ) -> Result<(), DbError>;
+    
+    /// State changes of `room` before `to`, oldest first: those from `from`
+    /// on and the last one before, which was still in effect at `from`
+    async fn get_room_states(
+        &self,
+        room: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(PatientState, DateTime<Utc>)>, DbError>;
+    
+    async fn purge_room_states_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError>;
+    
+    async fn insert_door_passage(&self, room: &str, passage: &DoorPassage) -> Result<(), DbError>;
+    
+    /// Passages through the door of `room` that opened from `from` to `to`,
+    /// oldest first
+    async fn get_door_passages(
+        &self,
+        room: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DoorPassage>, DbError>;
+    
+    async fn purge_door_passages_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError>;
+    
+    /// Store a kiosk token by the hash of its secret
+    async fn insert_kiosk_token(
+        &self,
+        room: &str,
+        token_hash: &str,
+        label: Option<&str>,
+        created_by: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<KioskToken, DbError>;
+    
+    /// Kiosk tokens neither revoked nor expired at `now`, optionally of one
+    /// room, newest first
+    async fn get_kiosk_tokens(&self, room: Option<&str>, now: DateTime<Utc>) -> Result<Vec<KioskToken>, DbError>;
+    
+    async fn get_kiosk_token(&self, id: i64) -> Result<Option<KioskToken>, DbError>;
+    
+    /// The kiosk token whose secret hashes to `token_hash`, if it is neither
+    /// revoked nor expired at `now`
+    async fn find_kiosk_token(&self, token_hash: &str, now: DateTime<Utc>) -> Result<Option<KioskToken>, DbError>;
+    
+    /// Revoke a kiosk token; false if it was revoked already
+    async fn revoke_kiosk_token(&self, id: i64, revoked_by: &str) -> Result<bool, DbError>;
+    
+    /// With readings partitioned by month, create the partitions of the
+    /// coming months and drop those older than the months kept, as of `now`
+    async fn maintain_partitions(&self, now: DateTime<Utc>) -> Result<PartitionChanges, DbError>;
+    
+    /// Disk space taken by the readings, and per room the readings stored
+    /// in total and since `since` (deleted ones included, as they take space)
+    async fn get_storage_usage(&self, since: DateTime<Utc>) -> Result<StorageUsage, DbError>;
+    
+    async fn get_tags(&self) -> Result<Vec<Tag>, DbError>;
+    
+    /// Attach `tag` (created if needed) to the given readings; unknown ids are skipped
+    async fn tag_readings(&self, tag: &str, ids: &[i64]) -> Result<u64, DbError>;
+    
+    /// Attach `tag` to every reading in the range, or only to alerts
+    async fn tag_range(
+        &self,
+        tag: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        alerts_only: bool,
+    ) -> Result<u64, DbError>;
+    
+    async fn untag_reading(&self, tag: &str, reading_id: i64) -> Result<bool, DbError>;
+    
+    /// Delete a tag and detach it from all readings
+    async fn delete_tag(&self, tag: &str) -> Result<bool, DbError>;
+    
+    /// Record a snooze; earlier snoozes of the same alert type are ended
+    async fn insert_snooze(&self, snooze: &AlertSnooze) -> Result<i64, DbError>;
+    
+    /// Acknowledge an alert. If it was already acknowledged the existing
+    /// acknowledgement is returned, with `false` for "newly acknowledged".
+    async fn ack_alert(
+        &self,
+        reading_id: i64,
+        acked_by: Option<&str>,
+        via: &str,
+    ) -> Result<(AlertAck, bool), DbError>;
+    
+    /// Bring a room's alerts up to date with its latest reading: open an alert
+    /// for each type the reading raised that isn't open yet, and resolve open
+    /// alerts of the types in `resolve`, with their reason. Returns the types
+    /// newly opened and the alerts resolved.
+    async fn sync_alerts(
+        &self,
+        room: &str,
+        alerts: &AlertSet,
+        reading_id: i64,
+        at: DateTime<Utc>,
+        resolve: &[(AlertType, ResolutionReason)],
+    ) -> Result<AlertSync, DbError>;
+    
+    /// Resolve the open alerts of types `alerts` that reading `reading_id`
+    /// belongs to as acknowledged. Returns the alerts resolved.
+    async fn resolve_acknowledged(
+        &self,
+        reading_id: i64,
+        alerts: &[AlertType],
+        at: DateTime<Utc>,
+    ) -> Result<Vec<Alert>, DbError>;
+    
+    /// Record an alert state transition. Returns its sequence number, which
+    /// is greater than that of any transition recorded before.
+    async fn insert_alert_event(&self, event: &AlertEvent) -> Result<i64, DbError>;
+    
+    /// Record staff's judgement of the alerts of types `alerts` that reading
+    /// `reading_id` belongs to, open or resolved, replacing one recorded
+    /// before. Returns the alerts updated.
+    async fn set_alert_outcome(
+        &self,
+        reading_id: i64,
+        alerts: &[AlertType],
+        outcome: AlertOutcome,
+    ) -> Result<Vec<Alert>, DbError>;
+    
+    /// Alerts newest first, optionally only open ones or those of one room
+    async fn get_alerts(
+        &self,
+        open_only: bool,
+        room: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Alert>, DbError>;
+    
+    /// Alerts triggered from `start` to `end` (exclusive), oldest first,
+    /// optionally only those of one room
+    async fn get_alerts_in_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+    ) -> Result<Vec<Alert>, DbError>;
+    
+    /// Alerts triggered in each of `buckets`, given as their first day and
+    /// bounds (see `Bucket::spans`), by type; optionally only those of one
+    /// room. Returns a count for every bucket, in the order given.
+    async fn get_alert_counts(
+        &self,
+        buckets: &[(NaiveDate, DateTime<Utc>, DateTime<Utc>)],
+        room: Option<&str>,
+    ) -> Result<Vec<AlertCounts>, DbError>;
+    
+    /// End the active snooze of an alert type in a room early; false if there was none
+    async fn end_snooze(&self, room: &str, alert: AlertType) -> Result<bool, DbError>;
+    
+    async fn get_active_snoozes(&self) -> Result<Vec<AlertSnooze>, DbError>;
+    
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<(), DbError>;
+    
+    /// Record a change of the monitor settings from `old` to `new`
+    async fn insert_settings_change(
+        &self,
+        old: &MonitorSettings,
+        new: &MonitorSettings,
+        changed_by: Option<&str>,
+        request_id: Option<&str>,
+    ) -> Result<SettingsChange, DbError>;
+    
+    /// Changes of the monitor settings, newest first
+    async fn get_settings_history(&self, limit: i64) -> Result<Vec<SettingsChange>, DbError>;
+    
+    async fn insert_phi_access(&self, access: &PhiAccess) -> Result<(), DbError>;
+    
+    /// PHI accesses in the range, oldest first, optionally by one principal
+    async fn get_phi_access_log(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        principal: Option<&str>,
+    ) -> Result<Vec<PhiAccessRecord>, DbError>;
+    
+    async fn get_reading_tags(&self, reading_id: i64) -> Result<Vec<String>, DbError>;
     
     /// Analyze patient activity for a specific time period
-    pub async fn get_activity_analysis(
+    async fn get_activity_analysis(
         &self,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
-    ) -> Result<ActivityAnalysis, Box<dyn std::error::Error>> {
-        let client = self.pool.get().await?;
-        
-        // Get aggregate statistics
-        let stats_row = client.query_one(
-            "SELECT 
-                COUNT(*) as total,
-                COUNT(*) FILTER (WHERE motion = true) as motion_count,
-                COALESCE(AVG(temperature), 0.0::float) as avg_temp,
-                COALESCE(AVG(sound_level), 0.0::float) as avg_sound,
-                COALESCE(MAX(sound_level), 0) as max_sound,
-                COUNT(*) FILTER (WHERE alert_type = 'fall') as falls
-             FROM sensor_data 
-             WHERE timestamp BETWEEN $1 AND $2",
-            &[&start, &end],
-        ).await?;
-        
-        let total: i64 = stats_row.get(0);
-        let motion_count: i64 = stats_row.get(1);
-        let avg_temp: f64 = stats_row.get(2);
-        let avg_sound: f64 = stats_row.get(3);
-        let max_sound: i32 = stats_row.get(4);
-        let falls: i64 = stats_row.get(5);
-        
-        // Calculate activity score (0-100)
-        let activity_score = if total > 0 {
-            (motion_count as f64 / total as f64) * 100.0
-        } else {
-            0.0
-        };
-        
-        // Determine activity level
-        let activity_level = match activity_score {
-            s if s < 20.0 => "deep_sleep",
-            s if s < 40.0 => "light_sleep", 
-            s if s < 60.0 => "restless",
-            _ => "active",
-        }.to_string();
-        
-        // Calculate longest still period
-        let longest_still = self.calculate_longest_still_period(start, end).await?;
-        
-        Ok(ActivityAnalysis {
-            period_start: start.to_rfc3339(),
-            period_end: end.to_rfc3339(),
-            total_readings: total as u64,
-            motion_readings: motion_count as u64,
-            activity_score: (activity_score * 100.0).round() / 100.0,
-            activity_level,
-            avg_temperature: (avg_temp * 100.0).round() / 100.0,
-            avg_sound_level: (avg_sound * 100.0).round() / 100.0,
-            max_sound_level: max_sound,
-            fall_alerts: falls as u64,
-            longest_still_period_mins: longest_still,
-        })
-    }
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<ActivityAnalysis, DbError>;
     
-    async fn calculate_longest_still_period(
+    /// Night-time awakenings (likely bathroom trips) over the period, summed
+    /// over rooms
+    async fn count_night_awakenings(
         &self,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
-    ) -> Result<u64, Box<dyn std::error::Error>> {
-        let client = self.pool.get().await?;
-        
-        let rows = client.query(
-            "SELECT timestamp, motion FROM sensor_data 
-             WHERE timestamp BETWEEN $1 AND $2 
-             ORDER BY timestamp ASC",
-            &[&start, &end],
-        ).await?;
-        
-        if rows.is_empty() {
-            return Ok(0);
-        }
-        
-        let mut longest_still: i64 = 0;
-        let mut current_still_start: Option<DateTime<Utc>> = None;
-        
-        for row in &rows {
-            let timestamp: DateTime<Utc> = row.get(0);
-            let motion: bool = row.get(1);
-            
-            if !motion {
-                if current_still_start.is_none() {
-                    current_still_start = Some(timestamp);
-                }
-            } else {
-                if let Some(start_time) = current_still_start {
-                    let duration = timestamp.signed_duration_since(start_time).num_minutes();
-                    if duration > longest_still {
-                        longest_still = duration;
-                    }
-                    current_still_start = None;
-                }
-            }
-        }
-        
-        if let Some(start_time) = current_still_start {
-            let duration = end.signed_duration_since(start_time).num_minutes();
-            if duration > longest_still {
-                longest_still = duration;
-            }
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<u64, DbError>;
+    
+    /// Light levels and motion of the readings with a light level over the
+    /// period, summed over rooms
+    async fn get_light_sums(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<LightSums, DbError>;
+    
+    /// Number of fall alerts each sound threshold in `thresholds` (by `step`)
+    /// would have raised over the period. Like the live notifier, a run of
+    /// consecutive qualifying readings in a room counts as one alert.
+    async fn get_fall_threshold_sweep(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        thresholds: std::ops::RangeInclusive<i32>,
+        step: i32,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Vec<ThresholdPoint>, DbError>;
+    
+    /// Quiet-hours minutes per facility week (`FacilityDays::week_start`)
+    /// between `start` and `end`: minutes with a reading, and those whose
+    /// loudest reading exceeded the noise limit. Quiet hours are local to
+    /// `days` and wrap past midnight if `end_hour < start_hour`.
+    async fn get_night_noise_minutes(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        days: &FacilityDays,
+        quiet: &QuietHours,
+        room: &str,
+    ) -> Result<Vec<NightNoiseWeek>, DbError>;
+    
+    /// Number of falls in `room` between `start` and `end`; a run of
+    /// consecutive readings with a fall alert counts as one
+    async fn count_falls(
+        &self,
+        room: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<u64, DbError>;
+    
+    /// Quiet-hours minutes with readings in `room`, oldest first, and
+    /// whether any reading in the minute detected motion. Quiet hours are
+    /// local to `days`, as for `get_night_noise_minutes`.
+    async fn get_night_motion_minutes(
+        &self,
+        room: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        days: &FacilityDays,
+        quiet: &QuietHours,
+    ) -> Result<Vec<(DateTime<Utc>, bool)>, DbError>;
+    
+    /// Store a patient's fall-risk score, replacing one already stored for the day
+    async fn upsert_fall_risk(&self, risk: &FallRiskScore) -> Result<(), DbError>;
+    
+    /// Stored fall-risk scores of a patient since `since`, newest first
+    async fn get_fall_risk_history(
+        &self,
+        patient_id: &str,
+        since: NaiveDate,
+    ) -> Result<Vec<FallRiskScore>, DbError>;
+    
+    /// Aggregates of each room's readings on `day`, which lasts from `start`
+    /// to `end` (exclusive), for rooms with readings
+    async fn compute_daily_summaries(
+        &self,
+        day: NaiveDate,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<DailySummary>, DbError>;
+    
+    /// Store daily summaries, replacing those already stored for the room and day
+    async fn upsert_daily_summaries(&self, summaries: &[DailySummary]) -> Result<(), DbError>;
+    
+    /// Latest day with stored summaries
+    async fn last_daily_summary_day(&self) -> Result<Option<NaiveDate>, DbError>;
+    
+    /// Stored daily summaries from `from` to `to` (inclusive), oldest first
+    async fn get_daily_summaries(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        room: Option<&str>,
+    ) -> Result<Vec<DailySummary>, DbError>;
+    
+    /// Statistics of the readings of patients who consent to research, from
+    /// `start` to `end`, grouped by ward and/or by facility day (`by_day`) if
+    /// asked. Groups are not suppressed or noised here, see
+    /// `research::publish`.
+    async fn research_aggregates(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        by_ward: bool,
+        by_day: Option<&FacilityDays>,
+    ) -> Result<Vec<ResearchGroup>, DbError>;
+    
+    /// Store an observation recorded by staff, attributed to the patient in
+    /// its room. Returns it with its ID and patient.
+    async fn insert_manual_observation(
+        &self,
+        observation: &ManualObservation,
+    ) -> Result<ManualObservation, DbError>;
+    
+    /// Observations recorded by staff, newest first; `start` and `end` bound
+    /// the time observed, `limit` the number returned
+    async fn get_manual_observations(
+        &self,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        room: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<Vec<ManualObservation>, DbError>;
+    
+    /// Hourly activity breakdown from `start` to `end` (exclusive), oldest
+    /// first, with hours labelled in `timezone`
+    async fn get_hourly_activity(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        timezone: Tz,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Vec<HourlyActivity>, DbError>;
+}
+
+/// Length of the periods alerts are counted over, see `get_alert_counts`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bucket {
+    Day,
+    /// Facility week, from Monday
+    Week,
+}
+
+impl Bucket {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Bucket::Day => "day",
+            Bucket::Week => "week",
         }
-        
-        Ok(longest_still as u64)
     }
     
-    /// Get hourly activity breakdown
-    pub async fn get_hourly_activity(
-        &self,
-        date: DateTime<Utc>,
-    ) -> Result<Vec<HourlyActivity>, Box<dyn std::error::Error>> {
-        let client = self.pool.get().await?;
-        
-        let rows = client.query(
-            "SELECT 
-                DATE_TRUNC('hour', timestamp) as hour,
-                COUNT(*) as total,
-                COUNT(*) FILTER (WHERE motion = true) as motion_count,
-                COALESCE(AVG(sound_level), 0.0::float) as avg_sound
-             FROM sensor_data 
-             WHERE timestamp::date = $1::date
-             GROUP BY DATE_TRUNC('hour', timestamp)
-             ORDER BY hour",
-            &[&date],
-        ).await?;
-        
-        let mut hourly = Vec::new();
-        for row in rows {
-            let hour: DateTime<Utc> = row.get(0);
-            let total: i64 = row.get(1);
-            let motion_count: i64 = row.get(2);
-            let avg_sound: f64 = row.get(3);
-            
-            let activity_score = if total > 0 {
-                (motion_count as f64 / total as f64) * 100.0
-            } else {
-                0.0
-            };
-            
-            hourly.push(HourlyActivity {
-                hour: hour.format("%H:00").to_string(),
-                activity_score: (activity_score * 100.0).round() / 100.0,
-                readings: total as u64,
-                avg_sound_level: (avg_sound * 100.0).round() / 100.0,
-            });
+    /// Buckets covering the facility days `from` to `to`, as their first day
+    /// and bounds. Weeks are whole, so the first may start before `from` and
+    /// the last end after `to`.
+    pub fn spans(
+        &self,
+        days: &FacilityDays,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Vec<(NaiveDate, DateTime<Utc>, DateTime<Utc>)> {
+        let (first, step) = match self {
+            Bucket::Day => (from, chrono::Duration::days(1)),
+            Bucket::Week => (
+                from - chrono::Duration::days(from.weekday().num_days_from_monday() as i64),
+                chrono::Duration::days(7),
+            ),
+        };
+        std::iter::successors(Some(first), |day| Some(*day + step))
+            .take_while(|day| *day <= to)
+            .map(|day| (day, days.start_of(day), days.start_of(day + step)))
+            .collect()
+    }
+}
+
+impl std::str::FromStr for Bucket {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Bucket::Day),
+            "week" => Ok(Bucket::Week),
+            other => Err(format!("Unknown bucket '{}', expected day or week", other)),
         }
-        
-        Ok(hourly)
     }
 }
 
+/// Usage of the connection pool, see `GET /api/health`
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStatus {
+    /// Open connections, idle or in use
+    pub size: usize,
+    pub max_size: usize,
+    /// Idle connections
+    pub available: usize,
+    /// Requests waiting for a connection
+    pub waiting: usize,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct AlertSummary {
     pub total_readings: u64,
@@ -367,29 +1030,349 @@ pub struct AlertSummary {
     pub inactivity_alerts: u64,
 }
 
-/// Activity analysis for a time period
+/// One point of a threshold sweep curve
 #[derive(Debug, Clone, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct ActivityAnalysis {
-    pub period_start: String,
-    pub period_end: String,
-    pub total_readings: u64,
-    pub motion_readings: u64,
-    pub activity_score: f64,
-    pub activity_level: String,
-    pub avg_temperature: f64,
-    pub avg_sound_level: f64,
-    pub max_sound_level: i32,
+pub struct ThresholdPoint {
+    pub threshold: i32,
     pub fall_alerts: u64,
-    pub longest_still_period_mins: u64,
 }
 
-/// Hourly activity breakdown
+/// Quiet-hours minutes of one week, see `get_night_noise_minutes`
+#[derive(Debug, Clone)]
+pub struct NightNoiseWeek {
+    pub week_start: DateTime<Utc>,
+    pub observed_minutes: u64,
+    pub noisy_minutes: u64,
+}
+
+/// What a patient has agreed their monitoring data may be used for
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Consent {
+    /// Readings may be recorded and stored
+    pub continuous_monitoring: bool,
+    /// Observations may be served through the FHIR endpoints to the EHR
+    pub ehr_sharing: bool,
+    /// Observations may be included in bulk/research exports
+    pub research_export: bool,
+    #[serde(default, skip_deserializing)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Default for Consent {
+    /// Monitoring and EHR sharing are part of standard care; research is opt-in
+    fn default() -> Self {
+        Self {
+            continuous_monitoring: true,
+            ehr_sharing: true,
+            research_export: false,
+            updated_at: None,
+        }
+    }
+}
+
+/// New values of a reading's measurements, see `update_reading`; `None`
+/// keeps the stored value
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadingCorrection {
+    pub temperature: Option<f32>,
+    pub motion: Option<bool>,
+    pub sound_level: Option<i32>,
+}
+
+/// Position in a newest-first listing of readings: the timestamp and id of
+/// the last reading already seen. Listings continue with older readings;
+/// readings with the same timestamp are ordered by descending id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadingCursor {
+    pub before_timestamp: DateTime<Utc>,
+    pub after_id: i64,
+}
+
+impl ReadingCursor {
+    pub fn after(event: &SensorEvent) -> Option<Self> {
+        Some(Self { before_timestamp: event.reading.timestamp, after_id: event.id? })
+    }
+}
+
+/// Temperature and sound level series of `events`, sorted oldest first,
+/// each downsampled to at most `points` readings with largest-triangle-
+/// three-buckets, which keeps spikes a chart shouldn't lose
+pub fn downsample(events: &[SensorEvent], points: usize) -> (Vec<ChartPoint>, Vec<ChartPoint>) {
+    let series = |value: fn(&SensorEvent) -> f64| -> Vec<ChartPoint> {
+        let xy: Vec<(f64, f64)> = events
+            .iter()
+            .map(|e| (e.reading.timestamp.timestamp_millis() as f64, value(e)))
+            .collect();
+        lttb(&xy, points)
+            .into_iter()
+            .map(|i| ChartPoint { timestamp: events[i].reading.timestamp, value: xy[i].1 })
+            .collect()
+    };
+    (series(|e| e.reading.temperature as f64), series(|e| e.reading.sound_level as f64))
+}
+
+/// What the database assigned to a newly stored reading
+#[derive(Debug, Clone)]
+pub struct StoredReading {
+    pub id: i64,
+    pub seq: i64,
+    /// Registered patient in the reading's room
+    pub patient_id: Option<String>,
+}
+
+/// Room monitored by this backend
 #[derive(Debug, Clone, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct HourlyActivity {
-    pub hour: String,
-    pub activity_score: f64,
+pub struct Room {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_reading_at: Option<DateTime<Utc>>,
+    pub ward: Option<String>,
+}
+
+/// Group of rooms, e.g. a hospital ward or unit
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ward {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub rooms: Vec<String>,
+}
+
+/// Staff snooze of an alert type in a room, started from a specific alert
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertSnooze {
+    pub reading_id: i64,
+    pub room: String,
+    pub alert: AlertType,
+    pub until: DateTime<Utc>,
+    pub snoozed_by: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// Read-only token a kiosk shows one room's coarse status with, see `kiosk`.
+/// The token's secret is only known when it is created.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KioskToken {
+    pub id: i64,
+    pub room: String,
+    /// Where the kiosk is, e.g. "Family TV"
+    pub label: Option<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub revoked_by: Option<String>,
+}
+
+/// Alert episode: from the reading that raised it until it cleared
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    pub id: i64,
+    pub room: String,
+    #[serde(rename = "type")]
+    pub alert: AlertType,
+    pub severity: AlertSeverity,
+    /// Reading that raised the alert
+    pub reading_id: Option<i64>,
+    pub triggered_at: DateTime<Utc>,
+    /// Unset while the alert is open
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub acknowledged_by: Option<String>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    /// Unset while the alert is open
+    pub resolution_reason: Option<ResolutionReason>,
+    /// How staff judged the alert, unset until recorded
+    pub outcome: Option<AlertOutcome>,
+}
+
+/// What a reading changed about its room's alerts
+#[derive(Debug, Clone, Default)]
+pub struct AlertSync {
+    pub opened: Vec<AlertType>,
+    pub resolved: Vec<Alert>,
+}
+
+/// Readings partitions created and dropped by `maintain_partitions`
+#[derive(Debug, Clone, Default)]
+pub struct PartitionChanges {
+    pub created: Vec<String>,
+    pub dropped: Vec<String>,
+}
+
+/// Disk usage of the readings, see `get_storage_usage`
+#[derive(Debug, Clone, Default)]
+pub struct StorageUsage {
+    /// Bytes of the readings with their indexes; for SQLite, of the whole
+    /// database file
+    pub bytes: i64,
+    /// Partitions or TimescaleDB chunks of the readings and their bytes
+    pub partitions: Vec<(String, i64)>,
+    /// Per room, readings stored in total and in the recent period
+    pub rooms: Vec<(String, i64, i64)>,
+}
+
+/// State transition of an alert, see `notify::AlertEventKind`
+#[derive(Debug, Clone)]
+pub struct AlertEvent {
+    pub event: &'static str,
+    pub room: String,
+    pub alert: AlertType,
+    /// Reading that raised the alert
+    pub alert_id: Option<i64>,
+    pub at: DateTime<Utc>,
+}
+
+/// Staff acknowledgement of an alert
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertAck {
+    pub reading_id: i64,
+    pub acked_by: Option<String>,
+    /// Where it was acknowledged: `api`, `slack` or `teams`
+    pub via: String,
+    pub acked_at: DateTime<Utc>,
+}
+
+/// Record of a staff action, with the request it came from
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub action: String,
+    pub subject: String,
+    pub actor: Option<String>,
+    pub request_id: Option<String>,
+    pub detail: Option<String>,
+}
+
+/// Change of the monitor settings through the API
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsChange {
+    pub id: i64,
+    pub changed_at: DateTime<Utc>,
+    /// Authenticated principal that made the change, if any
+    pub changed_by: Option<String>,
+    pub request_id: Option<String>,
+    pub old: MonitorSettings,
+    pub new: MonitorSettings,
+}
+
+/// Access to a patient's observation data
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PhiAccess {
+    /// Authenticated principal, if the request came through the gateway
+    pub principal: Option<String>,
+    pub patient_id: String,
+    pub endpoint: String,
+    /// Time range of the data that was returned
+    pub range_start: Option<DateTime<Utc>>,
+    pub range_end: Option<DateTime<Utc>>,
+    pub row_count: u64,
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PhiAccessRecord {
+    pub accessed_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub access: PhiAccess,
+}
+
+/// Label attached to readings, e.g. "post-op day 1"
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
     pub readings: u64,
-    pub avg_sound_level: f64,
+    pub alerts: u64,
+}
+
+/// Log line or crash report uploaded by a device
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceLog {
+    pub id: i64,
+    pub device_id: String,
+    pub received_at: DateTime<Utc>,
+    pub level: String,
+    pub message: String,
+}
+
+/// Reading line outside the plausible sensor ranges, kept for review
+/// instead of being stored with the readings
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantinedReading {
+    pub id: i64,
+    pub room: String,
+    /// Serial port it was read from
+    pub device_id: Option<String>,
+    pub received_at: DateTime<Utc>,
+    pub line: String,
+    /// Which value was out of range
+    pub reason: String,
+}
+
+/// Run of a background job, see `jobs`
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobRun {
+    pub id: i64,
+    /// `rollup`, `retention` or `compaction`
+    pub job: String,
+    pub started_at: DateTime<Utc>,
+    /// `None` while running, and for runs cut short by a crash
+    pub finished_at: Option<DateTime<Utc>>,
+    /// `running`, `completed`, `failed` or `interrupted`
+    pub status: String,
+    /// Where the job resumes if the run doesn't complete; its format is up to the job
+    pub checkpoint: Option<String>,
+    /// Chunks completed, e.g. days rolled up
+    pub chunks: u64,
+    pub error: Option<String>,
+}
+
+/// Sensor board in the device registry
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    /// Serial port for boards attached to this host
+    pub id: String,
+    pub room: String,
+    /// Reported by the firmware on boot; unknown until then
+    pub firmware_version: Option<String>,
+    pub registered_at: DateTime<Utc>,
+    /// Time of the last reading or log line received from the device
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Statistics of one WebSocket connection
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WsSession {
+    pub id: String,
+    /// `readings` or `ward`
+    pub channel: String,
+    pub ip: Option<String>,
+    pub principal: Option<String>,
+    pub user_agent: Option<String>,
+    pub connected_at: DateTime<Utc>,
+    /// `None` while the connection is open
+    pub disconnected_at: Option<DateTime<Utc>>,
+    pub messages_sent: u64,
+    /// Times the client fell behind the live stream
+    pub lag_events: u64,
+    /// Readings skipped because the client fell behind
+    pub missed_messages: u64,
+    /// `client`, `error`, `send_failed` or `server`
+    pub close_reason: Option<String>,
 }
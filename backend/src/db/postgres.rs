@@ -0,0 +1,3380 @@
+//! PostgreSQL storage, the default backend
+
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, DurationRound, Months, NaiveDate, TimeDelta, Utc};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use deadpool_postgres::{Config, Object, Pool, PoolError, Runtime, ManagerConfig, RecyclingMethod};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::{CancelToken, Client, NoTls, Row};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tracing::{info, debug, warn};
+
+use super::*;
+use crate::fhir::{AlertSet, AlertType, ManualObservation, Patient, SensorEvent, SensorReading, DEFAULT_ROOM_ID};
+use patient_monitor_types::analysis::{activity_level, activity_score, count_night_awakenings, LIGHTS_ON_LUX};
+use crate::rules::{AlertRule, ShadowHit};
+
+mod embedded {
+    refinery::embed_migrations!("migrations/postgres");
+}
+
+/// Longest a connection attempt may take, so an unreachable host fails fast
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Backoff before retrying to connect, doubled per attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// How long a query waits for an unavailable server, e.g. one restarting,
+/// before it fails
+const RECONNECT_WAIT: Duration = Duration::from_secs(10);
+/// Imported readings stored per statement
+const HISTORICAL_BATCH_SIZE: usize = 5000;
+/// Monthly readings partitions created ahead of the current month
+const PARTITION_MONTHS_AHEAD: u32 = 2;
+/// Stand-in for `sensor_data` in queries of the readings from $1 to $2, with
+/// the intervals left by compaction expanded into `run_count` readings evenly
+/// spaced from the first to `run_until`. Intervals span at most an hour
+/// (`compaction::MAX_SPAN`), so those reaching into the range are found by
+/// their start.
+const EXPANDED_READINGS: &str = "(
+    SELECT s.id, s.room_id, s.patient_id, s.seq, s.temperature, s.motion, s.sound_level, s.humidity, s.heart_rate, s.spo2,
+           s.door_open, s.light, s.co2, s.presence, s.acceleration, s.alert_types, s.deleted_at,
+           CASE WHEN n = 0 THEN s.timestamp
+                ELSE s.timestamp + (s.run_until - s.timestamp) * (n::float8 / (s.run_count - 1)) END AS timestamp
+    FROM sensor_data s, generate_series(0, s.run_count - 1) AS n
+    WHERE s.timestamp BETWEEN $1::timestamptz - interval '1 hour' AND $2
+      AND COALESCE(s.run_until, s.timestamp) >= $1
+) AS sensor_data";
+
+/// Whether a failed connection attempt may succeed later: the server is
+/// unreachable or still starting up, as opposed to e.g. rejecting the login
+fn is_transient(e: &PoolError) -> bool {
+    match e {
+        PoolError::Backend(e) if is_tls_rejection(e) => false,
+        PoolError::Backend(e) => e.as_db_error().is_none_or(|db| *db.code() == SqlState::CANNOT_CONNECT_NOW),
+        PoolError::Timeout(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether the TLS handshake failed on our side, e.g. because the server's
+/// certificate isn't trusted. Retrying won't help.
+fn is_tls_rejection(e: &tokio_postgres::Error) -> bool {
+    std::error::Error::source(e)
+        .and_then(|s| s.downcast_ref::<std::io::Error>())
+        .and_then(|io| io.get_ref())
+        .is_some_and(|inner| inner.is::<rustls::Error>())
+}
+
+impl From<tokio_postgres::Error> for DbError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        let Some(db) = e.as_db_error() else {
+            // Errors without a server response are I/O or protocol failures
+            return if e.is_closed() || std::error::Error::source(&e).is_some_and(|s| s.is::<std::io::Error>()) {
+                // The message alone doesn't say what went wrong, e.g. which certificate check failed
+                DbError::Unavailable(match std::error::Error::source(&e) {
+                    Some(cause) => format!("{}: {}", e, cause),
+                    None => e.to_string(),
+                })
+            } else {
+                DbError::Other(Box::new(e))
+            };
+        };
+        let code = db.code();
+        if *code == SqlState::UNIQUE_VIOLATION
+            || *code == SqlState::FOREIGN_KEY_VIOLATION
+            || *code == SqlState::CHECK_VIOLATION
+            || *code == SqlState::NOT_NULL_VIOLATION
+        {
+            DbError::Constraint(db.message().to_string())
+        } else if code.code().starts_with("08")
+            || *code == SqlState::ADMIN_SHUTDOWN
+            || *code == SqlState::CRASH_SHUTDOWN
+            || *code == SqlState::CANNOT_CONNECT_NOW
+            || *code == SqlState::TOO_MANY_CONNECTIONS
+        {
+            DbError::Unavailable(db.message().to_string())
+        } else {
+            DbError::Other(Box::new(e))
+        }
+    }
+}
+
+impl From<PoolError> for DbError {
+    fn from(e: PoolError) -> Self {
+        match e {
+            PoolError::Backend(e) => e.into(),
+            PoolError::Timeout(_) | PoolError::Closed | PoolError::NoRuntimeSpecified => DbError::Unavailable(e.to_string()),
+            e => DbError::Other(Box::new(e)),
+        }
+    }
+}
+
+/// Get a pooled connection, retrying with backoff for up to `wait` while the
+/// server is unavailable. Connections the server has closed (e.g. when it
+/// restarted) are discarded by the pool and replaced with new ones.
+async fn get_client(pool: &Pool, wait: Duration) -> Result<Object, PoolError> {
+    let deadline = Instant::now() + wait;
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        match pool.get().await {
+            Err(e) if is_transient(&e) && Instant::now() + backoff <= deadline => {
+                debug!("Database unavailable ({}), retrying in {}ms", e, backoff.as_millis());
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Cancels the statement running on a connection if dropped while armed.
+///
+/// Dropping a query future doesn't stop the statement on the server. When a
+/// dashboard request is abandoned, actix drops the handler future and with it
+/// this guard, so Postgres is told to stop working on the query.
+struct CancelOnDrop(Option<(CancelToken, Option<MakeRustlsConnect>)>);
+
+impl CancelOnDrop {
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some((token, tls)) = self.0.take() {
+            tokio::spawn(async move {
+                // The cancel request is a connection of its own, encrypted like the others
+                let cancelled = match tls {
+                    Some(tls) => token.cancel_query(tls).await,
+                    None => token.cancel_query(NoTls).await,
+                };
+                match cancelled {
+                    Ok(()) => debug!("Cancelled abandoned analytics query"),
+                    Err(e) => warn!("Failed to cancel analytics query: {}", e),
+                }
+            });
+        }
+    }
+}
+
+/// First day of the UTC month of `t`, where readings partitions start
+fn month_start(t: DateTime<Utc>) -> NaiveDate {
+    t.date_naive().with_day(1).expect("every month has a first day")
+}
+
+/// Name of the readings partition of the month starting `month`
+fn partition_name(month: NaiveDate) -> String {
+    format!("sensor_data_{}", month.format("y%Ym%m"))
+}
+
+/// End of the range of readings partition `name`: the next month for a
+/// monthly partition, or the month in its name for the table of readings
+/// stored before partitioning (`sensor_data_before_y<year>m<month>`)
+fn partition_end(name: &str) -> Option<NaiveDate> {
+    let parse = |month: &str| NaiveDate::parse_from_str(&format!("{}d01", month), "y%Ym%md%d").ok();
+    match name.strip_prefix("sensor_data_before_") {
+        Some(month) => parse(month),
+        None => parse(name.strip_prefix("sensor_data_")?)?.checked_add_months(Months::new(1)),
+    }
+}
+
+/// Names of the partitions of `sensor_data`
+async fn partitions(client: &Client) -> Result<Vec<String>, DbError> {
+    let rows = client.query(
+        "SELECT c.relname::text FROM pg_inherits i JOIN pg_class c ON c.oid = i.inhrelid
+         WHERE i.inhparent = 'sensor_data'::regclass
+         ORDER BY c.relname",
+        &[],
+    ).await?;
+    Ok(rows.iter().map(|r| r.get(0)).collect())
+}
+
+/// Whether readings partition `name` holds the readings of the month starting `month`
+fn covers(name: &str, month: NaiveDate) -> bool {
+    match name.strip_prefix("sensor_data_before_") {
+        Some(_) => partition_end(name).is_some_and(|end| month < end),
+        None => name == partition_name(month),
+    }
+}
+
+/// Create the partition of the month starting `month`, unless a concurrent
+/// insert just did
+async fn create_partition(client: &Client, month: NaiveDate) -> Result<String, DbError> {
+    let name = partition_name(month);
+    let end = month + Months::new(1);
+    client.batch_execute(&format!(
+        "CREATE TABLE IF NOT EXISTS {name} PARTITION OF sensor_data
+         FOR VALUES FROM ('{month} 00:00:00+00') TO ('{end} 00:00:00+00')"
+    )).await?;
+    Ok(name)
+}
+
+/// Create the monthly partitions missing up to `PARTITION_MONTHS_AHEAD`
+/// months after the month of `now`. Months missed while the monitor was
+/// down are created too, so late readings of them can still be stored.
+async fn create_partitions(client: &Client, now: DateTime<Utc>) -> Result<Vec<String>, DbError> {
+    let this_month = month_start(now);
+    let last = this_month + Months::new(PARTITION_MONTHS_AHEAD);
+    let existing = partitions(client).await?;
+    // Partitions created further ahead for readings from skewed clocks
+    // don't count, or the months before them would never be created
+    let mut month = existing
+        .iter()
+        .filter_map(|name| partition_end(name))
+        .filter(|end| *end <= last)
+        .max()
+        .unwrap_or(this_month);
+
+    let mut created = Vec::new();
+    while month <= last {
+        if !existing.iter().any(|name| covers(name, month)) {
+            created.push(create_partition(client, month).await?);
+        }
+        month = month + Months::new(1);
+    }
+    Ok(created)
+}
+
+/// Create the missing partitions of the months of `timestamps`, e.g. for
+/// readings from a device with a skewed clock or replayed long after,
+/// beyond the months created ahead
+async fn create_partitions_for(client: &Client, timestamps: &[DateTime<Utc>]) -> Result<Vec<String>, DbError> {
+    let existing = partitions(client).await?;
+    let mut months: Vec<NaiveDate> = timestamps.iter().map(|t| month_start(*t)).collect();
+    months.sort();
+    months.dedup();
+
+    let mut created = Vec::new();
+    for month in months {
+        if !existing.iter().any(|name| covers(name, month)) {
+            created.push(create_partition(client, month).await?);
+        }
+    }
+    if !created.is_empty() {
+        warn!("Created readings partitions {} for readings outside the months created ahead", created.join(", "));
+    }
+    Ok(created)
+}
+
+/// Whether an insert failed because no partition holds a reading's timestamp
+fn is_missing_partition(e: &tokio_postgres::Error) -> bool {
+    e.as_db_error().is_some_and(|db| {
+        *db.code() == SqlState::CHECK_VIOLATION && db.message().starts_with("no partition of relation")
+    })
+}
+
+/// Detach and drop readings partition `name` together with what refers to
+/// its readings. Dropping a table doesn't fire the row triggers, so the
+/// running totals and references are updated as a delete would.
+async fn drop_partition(client: &mut Object, name: &str) -> Result<(), DbError> {
+    let tx = client.transaction().await?;
+    tx.batch_execute(&format!(
+        "ALTER TABLE sensor_data DETACH PARTITION {name};
+         
+         UPDATE sensor_counters c SET value = c.value - n.readings
+         FROM (SELECT counter, SUM(run_count) AS readings
+               FROM {name}, unnest(ARRAY['total'] || alert_types) AS counter
+               WHERE deleted_at IS NULL
+               GROUP BY counter) n
+         WHERE c.counter = n.counter;
+         
+         DELETE FROM reading_tags WHERE reading_id IN (SELECT id FROM {name});
+         DELETE FROM alert_acks WHERE reading_id IN (SELECT id FROM {name});
+         UPDATE alerts SET reading_id = NULL WHERE reading_id IN (SELECT id FROM {name});
+         
+         DROP TABLE {name};"
+    )).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub struct Postgres {
+    pool: Pool,
+    /// TLS connector of the pool's connections, `None` if unencrypted
+    tls: Option<MakeRustlsConnect>,
+    analytics_timeout: Option<Duration>,
+    /// Readings are a TimescaleDB hypertable with hourly aggregates
+    timescale: bool,
+    /// Readings are partitioned by month
+    partitioned: bool,
+    partition_keep_months: u32,
+}
+
+impl Postgres {
+    pub async fn connect(config: DbConfig) -> Result<Self, DbError> {
+        info!("Connecting to PostgreSQL at {}:{} (sslmode {:?})", config.host, config.port, config.ssl_mode);
+        
+        let mut cfg = Config::new();
+        cfg.host = Some(config.host);
+        cfg.port = Some(config.port);
+        cfg.user = Some(config.user);
+        cfg.password = Some(config.password);
+        cfg.dbname = Some(config.dbname);
+        cfg.connect_timeout = Some(CONNECT_TIMEOUT);
+        cfg.ssl_mode = Some(match config.ssl_mode {
+            SslMode::Disable => deadpool_postgres::SslMode::Disable,
+            SslMode::Prefer => deadpool_postgres::SslMode::Prefer,
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => deadpool_postgres::SslMode::Require,
+        });
+        cfg.manager = Some(ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        });
+        
+        let tls = tls::connector(config.ssl_mode, config.ssl_root_cert.as_deref())?;
+        let pool = match &tls {
+            Some(tls) => cfg.create_pool(Some(Runtime::Tokio1), tls.clone()),
+            None => cfg.create_pool(Some(Runtime::Tokio1), NoTls),
+        }.map_err(DbError::other)?;
+        
+        // The server may still be starting, e.g. when both come up together
+        if let Err(e) = pool.get().await {
+            if !is_transient(&e) || config.connect_wait.is_zero() {
+                return Err(e.into());
+            }
+            warn!("Database not available yet ({}), retrying for up to {}s", e, config.connect_wait.as_secs());
+            drop(get_client(&pool, config.connect_wait).await?);
+            info!("Database is available");
+        }
+        
+        let db = Self {
+            pool,
+            tls,
+            analytics_timeout: config.analytics_timeout,
+            timescale: config.timescale,
+            partitioned: config.partitioned,
+            partition_keep_months: config.partition_keep_months,
+        };
+        db.migrate(config.auto_migrate).await?;
+        
+        info!("Database initialized successfully");
+        Ok(db)
+    }
+    
+    async fn client(&self) -> Result<Object, PoolError> {
+        get_client(&self.pool, RECONNECT_WAIT).await
+    }
+    
+    /// Run a potentially long analytics query on `client`. The statement is
+    /// cancelled on the server if the caller goes away before it completes
+    /// or if it exceeds the analytics timeout.
+    async fn analytics<T>(
+        &self,
+        client: &Client,
+        query: impl Future<Output = Result<T, tokio_postgres::Error>>,
+    ) -> Result<T, DbError> {
+        let guard = CancelOnDrop(Some((client.cancel_token(), self.tls.clone())));
+        let result = match self.analytics_timeout {
+            Some(limit) => tokio::time::timeout(limit, query)
+                .await
+                .map_err(|_| DbError::Timeout(limit))?,
+            None => query.await,
+        };
+        guard.disarm();
+        Ok(result?)
+    }
+    
+    /// Apply pending migrations from `migrations/postgres`, or with
+    /// `auto_migrate` off check that there are none
+    async fn migrate(&self, auto_migrate: bool) -> Result<(), DbError> {
+        let mut client = self.client().await?;
+        let mut runner = embedded::migrations::runner();
+        runner.set_migration_table_name(MIGRATIONS_TABLE);
+        
+        if auto_migrate {
+            log_migrations(&runner.run_async(&mut **client).await?);
+        } else {
+            // No migrations table means nothing has been applied yet
+            let applied = runner.get_applied_migrations_async(&mut **client).await.unwrap_or_default();
+            ensure_migrated(&runner, &applied).map_err(DbError::other)?;
+        }
+        
+        if self.timescale {
+            self.init_timescale(&client).await?;
+        }
+        if self.partitioned {
+            self.init_partitions(&mut client).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Replace the foreign keys to readings by a trigger that cleans up
+    /// tags, acknowledgements and alert references when a reading is
+    /// deleted, for readings tables whose primary key includes the time
+    async fn init_reading_refs(&self, client: &Client) -> Result<(), DbError> {
+        client.batch_execute(
+            "CREATE INDEX IF NOT EXISTS idx_alerts_reading ON alerts(reading_id);
+             
+             CREATE OR REPLACE FUNCTION delete_reading_refs() RETURNS TRIGGER AS $$
+             BEGIN
+                 DELETE FROM reading_tags WHERE reading_id = OLD.id;
+                 DELETE FROM alert_acks WHERE reading_id = OLD.id;
+                 UPDATE alerts SET reading_id = NULL WHERE reading_id = OLD.id;
+                 RETURN OLD;
+             END
+             $$ LANGUAGE plpgsql;
+             
+             CREATE OR REPLACE TRIGGER sensor_refs_trigger
+                 AFTER DELETE ON sensor_data
+                 FOR EACH ROW EXECUTE FUNCTION delete_reading_refs();"
+        ).await?;
+        Ok(())
+    }
+    
+    /// Turn `sensor_data` into a hypertable and maintain `sensor_hourly`, a
+    /// continuous aggregate per room and hour of the readings not deleted,
+    /// which activity analytics read instead of scanning the readings.
+    ///
+    /// Unique constraints on a hypertable must include the time column, so
+    /// the primary key becomes `(id, timestamp)` and foreign keys to readings
+    /// are replaced by a trigger that cleans up tags, acknowledgements and
+    /// alert references when a reading is deleted.
+    async fn init_timescale(&self, client: &Client) -> Result<(), DbError> {
+        client.execute("CREATE EXTENSION IF NOT EXISTS timescaledb", &[]).await?;
+        self.init_reading_refs(client).await?;
+        
+        let converted: bool = client.query_one(
+            "SELECT EXISTS (SELECT 1 FROM timescaledb_information.hypertables
+                            WHERE hypertable_schema = current_schema() AND hypertable_name = 'sensor_data')",
+            &[],
+        ).await?.get(0);
+        
+        if !converted {
+            info!("Converting sensor_data to a TimescaleDB hypertable; this may take a while for large tables");
+            client.batch_execute(
+                "BEGIN;
+                 ALTER TABLE sensor_data DROP CONSTRAINT sensor_data_pkey CASCADE;
+                 ALTER TABLE sensor_data ADD PRIMARY KEY (id, timestamp);
+                 DROP INDEX IF EXISTS idx_sensor_seq;
+                 CREATE INDEX idx_sensor_seq ON sensor_data(seq);
+                 SELECT create_hypertable('sensor_data', 'timestamp', migrate_data => true);
+                 COMMIT;"
+            ).await?;
+        }
+        
+        let definition: Option<String> = client.query_opt(
+            "SELECT view_definition FROM timescaledb_information.continuous_aggregates
+             WHERE view_schema = current_schema() AND view_name = 'sensor_hourly'",
+            &[],
+        ).await?.map(|row| row.get(0));
+        
+        // Aggregates created before readings could be deleted counted them,
+        // those created before the light channel don't sum it, and those
+        // created before compaction count an interval as one reading
+        let current = definition
+            .as_deref()
+            .is_some_and(|d| d.contains("deleted_at") && d.contains("light_sum") && d.contains("run_count"));
+        if definition.is_some() && !current {
+            info!("Recreating hourly activity aggregates");
+            client.batch_execute("DROP MATERIALIZED VIEW sensor_hourly").await?;
+        }
+        
+        if !current {
+            // Real-time aggregation: buckets not materialized yet are computed
+            // from the readings at query time, so results are never stale.
+            // An interval counts as its readings, in the hour it starts.
+            client.execute(
+                "CREATE MATERIALIZED VIEW sensor_hourly
+                 WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+                 SELECT time_bucket(INTERVAL '1 hour', timestamp) AS bucket,
+                        room_id,
+                        SUM(run_count) AS readings,
+                        COUNT(*) FILTER (WHERE motion) AS motion_readings,
+                        SUM(temperature::float8 * run_count) AS temperature_sum,
+                        SUM(sound_level * run_count) AS sound_sum,
+                        MAX(sound_level) AS max_sound,
+                        COUNT(*) FILTER (WHERE 'fall' = ANY(alert_types)) AS falls,
+                        SUM(light::float8 * run_count) AS light_sum,
+                        SUM(run_count) FILTER (WHERE light IS NOT NULL) AS light_readings
+                 FROM sensor_data
+                 WHERE deleted_at IS NULL
+                 GROUP BY bucket, room_id
+                 WITH NO DATA",
+                &[],
+            ).await?;
+            
+            info!("Materializing hourly activity aggregates");
+            // Refreshing can't run in a transaction, so not as a prepared statement
+            client.batch_execute(
+                "CALL refresh_continuous_aggregate('sensor_hourly', NULL, NOW() - INTERVAL '1 hour')"
+            ).await?;
+        }
+        
+        // No start offset: readings imported or purged long after the fact are
+        // picked up too, and only invalidated buckets are recomputed
+        client.execute(
+            "SELECT add_continuous_aggregate_policy('sensor_hourly',
+                 start_offset => NULL,
+                 end_offset => INTERVAL '1 hour',
+                 schedule_interval => INTERVAL '15 minutes',
+                 if_not_exists => true)",
+            &[],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    /// Turn `sensor_data` into a table partitioned by month, so each month's
+    /// readings have indexes of their own and expired months can be dropped
+    /// whole instead of deleted reading by reading.
+    ///
+    /// As for a hypertable, the primary key becomes `(id, timestamp)` and
+    /// foreign keys to readings are replaced by a trigger. The existing table
+    /// becomes the partition of the readings before the month after its
+    /// latest one, and the monthly partitions follow it.
+    async fn init_partitions(&self, client: &mut Object) -> Result<(), DbError> {
+        self.init_reading_refs(client).await?;
+        
+        let partitioned: bool = client.query_one(
+            "SELECT relkind = 'p' FROM pg_class WHERE oid = 'sensor_data'::regclass",
+            &[],
+        ).await?.get(0);
+        
+        if !partitioned {
+            info!("Converting sensor_data to a table partitioned by month; this may take a while for large tables");
+            let tx = client.transaction().await?;
+            tx.batch_execute("LOCK TABLE sensor_data IN ACCESS EXCLUSIVE MODE").await?;
+            let latest: Option<DateTime<Utc>> = tx.query_one("SELECT MAX(timestamp) FROM sensor_data", &[])
+                .await?
+                .get(0);
+            let end = month_start(latest.map_or(Utc::now(), |t| t.max(Utc::now()))) + Months::new(1);
+            let legacy = format!("sensor_data_before_{}", end.format("y%Ym%m"));
+            
+            // The parent's indexes and row triggers apply to every partition,
+            // so the old table's own are dropped or renamed out of the way
+            tx.batch_execute(&format!(
+                "ALTER TABLE sensor_data DROP CONSTRAINT sensor_data_pkey CASCADE;
+                 DROP INDEX idx_sensor_seq;
+                 DROP TRIGGER sensor_patient_trigger ON sensor_data;
+                 DROP TRIGGER sensor_seq_trigger ON sensor_data;
+                 DROP TRIGGER sensor_counters_trigger ON sensor_data;
+                 DROP TRIGGER sensor_refs_trigger ON sensor_data;
+                 ALTER INDEX idx_sensor_timestamp RENAME TO {legacy}_timestamp_idx;
+                 ALTER INDEX idx_sensor_room_timestamp RENAME TO {legacy}_room_id_timestamp_idx;
+                 ALTER TABLE sensor_data RENAME TO {legacy};
+                 ALTER TABLE {legacy} ADD PRIMARY KEY (id, timestamp);
+                 
+                 CREATE TABLE sensor_data (LIKE {legacy} INCLUDING DEFAULTS, PRIMARY KEY (id, timestamp))
+                     PARTITION BY RANGE (timestamp);
+                 ALTER SEQUENCE sensor_data_id_seq OWNED BY sensor_data.id;
+                 ALTER TABLE sensor_data
+                     ADD FOREIGN KEY (room_id) REFERENCES rooms(id),
+                     ADD FOREIGN KEY (patient_id) REFERENCES patients(id) ON DELETE SET NULL;
+                 ALTER TABLE sensor_data ATTACH PARTITION {legacy} FOR VALUES FROM (MINVALUE) TO ('{end} 00:00:00+00');
+                 
+                 CREATE INDEX idx_sensor_timestamp ON sensor_data(timestamp DESC);
+                 CREATE INDEX idx_sensor_room_timestamp ON sensor_data(room_id, timestamp DESC);
+                 CREATE INDEX idx_sensor_seq ON sensor_data(seq);
+                 
+                 CREATE TRIGGER sensor_patient_trigger
+                     BEFORE INSERT ON sensor_data
+                     FOR EACH ROW EXECUTE FUNCTION assign_reading_patient();
+                 CREATE TRIGGER sensor_seq_trigger
+                     BEFORE INSERT ON sensor_data
+                     FOR EACH ROW EXECUTE FUNCTION assign_reading_seq();
+                 CREATE TRIGGER sensor_counters_trigger
+                     AFTER INSERT OR DELETE OR UPDATE OF alert_types, deleted_at ON sensor_data
+                     FOR EACH ROW EXECUTE FUNCTION update_sensor_counters();
+                 CREATE TRIGGER sensor_refs_trigger
+                     AFTER DELETE ON sensor_data
+                     FOR EACH ROW EXECUTE FUNCTION delete_reading_refs();"
+            )).await?;
+            tx.commit().await?;
+        }
+        
+        // Readings arriving before the first maintenance run need a partition
+        let created = create_partitions(client, Utc::now()).await?;
+        if !created.is_empty() {
+            info!("Created readings partitions {}", created.join(", "));
+        }
+        Ok(())
+    }
+    
+    fn row_to_patient(row: &Row) -> Patient {
+        let gender: Option<String> = row.get(4);
+        Patient {
+            id: row.get(0),
+            family_name: row.get(1),
+            given_names: row.get(2),
+            birth_date: row.get(3),
+            gender: gender.and_then(|g| gender_from_str(&g)),
+            room: row.get(5),
+        }
+    }
+    
+    async fn upsert_tag(client: &deadpool_postgres::Client, tag: &str) -> Result<i64, DbError> {
+        let row = client.query_one(
+            "INSERT INTO tags (name) VALUES ($1)
+             ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+             RETURNING id",
+            &[&tag],
+        ).await?;
+        
+        Ok(row.get(0))
+    }
+    
+    /// `None` for an observation of a kind this version doesn't know
+    fn row_to_manual_observation(row: &Row) -> Option<ManualObservation> {
+        let kind: &str = row.get(3);
+        Some(ManualObservation {
+            id: Some(row.get(0)),
+            room: row.get(1),
+            patient_id: row.get(2),
+            kind: kind.parse().ok()?,
+            value: row.get(4),
+            observed_at: row.get(5),
+            recorded_by: row.get(6),
+            note: row.get(7),
+        })
+    }
+    
+    fn row_to_settings_change(row: &Row) -> SettingsChange {
+        let old_inactivity: i64 = row.get(4);
+        let new_inactivity: i64 = row.get(5);
+        SettingsChange {
+            id: row.get(0),
+            changed_at: row.get(1),
+            changed_by: row.get(2),
+            request_id: row.get(3),
+            old: MonitorSettings { inactivity_seconds: old_inactivity as u64, sound_threshold: row.get(6) },
+            new: MonitorSettings { inactivity_seconds: new_inactivity as u64, sound_threshold: row.get(7) },
+        }
+    }
+    
+    fn row_to_kiosk_token(row: &Row) -> KioskToken {
+        KioskToken {
+            id: row.get(0),
+            room: row.get(1),
+            label: row.get(2),
+            created_by: row.get(3),
+            created_at: row.get(4),
+            expires_at: row.get(5),
+            revoked_at: row.get(6),
+            revoked_by: row.get(7),
+        }
+    }
+    
+    /// `None` for an alert of a type or severity this version doesn't know
+    fn row_to_alert(row: &Row) -> Option<Alert> {
+        let reason: Option<&str> = row.get(9);
+        let outcome: Option<&str> = row.get(10);
+        Some(Alert {
+            id: row.get(0),
+            room: row.get(1),
+            alert: alert_from_str(row.get(2))?,
+            severity: severity_from_str(row.get(3))?,
+            reading_id: row.get(4),
+            triggered_at: row.get(5),
+            resolved_at: row.get(6),
+            acknowledged_by: row.get(7),
+            acknowledged_at: row.get(8),
+            resolution_reason: reason.and_then(|r| r.parse().ok()),
+            outcome: outcome.and_then(|o| o.parse().ok()),
+        })
+    }
+    
+    fn row_to_event(row: &Row) -> SensorEvent {
+        let id: i64 = row.get(0);
+        let timestamp: DateTime<Utc> = row.get(1);
+        let temperature: f32 = row.get(2);
+        let motion: bool = row.get(3);
+        let sound_level: i32 = row.get(4);
+        let alert_strs: Vec<&str> = row.get(5);
+        let room: Option<String> = row.get(6);
+        let patient_id: Option<String> = row.get(7);
+        let seq: i64 = row.get(8);
+        let humidity: Option<f32> = row.get(9);
+        let heart_rate: Option<i32> = row.get(10);
+        let spo2: Option<i32> = row.get(11);
+        let door_open: Option<bool> = row.get(12);
+        let light: Option<f32> = row.get(13);
+        let co2: Option<i32> = row.get(14);
+        let presence: Option<bool> = row.get(15);
+        let acceleration: Option<f32> = row.get(16);
+        
+        let alerts = alert_strs.into_iter().filter_map(alert_from_str).collect();
+        
+        SensorEvent {
+            id: Some(id),
+            seq: Some(seq),
+            room: room.unwrap_or_else(|| DEFAULT_ROOM_ID.to_string()),
+            device: None,
+            patient_id,
+            reading: SensorReading {
+                temperature,
+                motion,
+                sound_level,
+                timestamp,
+                humidity,
+                heart_rate,
+                spo2,
+                door_open,
+                light,
+                co2,
+                presence,
+                acceleration,
+            },
+            alerts,
+        }
+    }
+    
+    /// Distribution of the still periods and the longest of them. Runs of
+    /// readings without or with motion are found per room by comparing each
+    /// reading with the one before (gaps and islands); a still run lasts
+    /// until the next run starts, or until `end`. Only the bucket counts and
+    /// the `TOP_STILL_PERIODS` longest periods leave the database.
+    async fn calculate_still_periods(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<(StillPeriods, Vec<StillPeriod>), DbError> {
+        let client = self.client().await?;
+        let bounds: Vec<i64> = STILL_PERIOD_BUCKETS.iter().map(|&b| b as i64).collect();
+        
+        let rows = self.analytics(&client, client.query(
+            &format!("WITH readings AS (
+                SELECT room_id, timestamp, id, motion,
+                       motion IS DISTINCT FROM LAG(motion) OVER (PARTITION BY room_id ORDER BY timestamp, id)
+                           AS starts_run
+                FROM {EXPANDED_READINGS}
+                WHERE deleted_at IS NULL AND timestamp BETWEEN $1 AND $2
+                  AND ($3::text IS NULL OR id IN (
+                    SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                    WHERE t.name = $3))
+                  AND ($4::text IS NULL OR room_id = $4)
+             ),
+             runs AS (
+                SELECT room_id, motion, timestamp AS started,
+                       COALESCE(LEAD(timestamp) OVER (PARTITION BY room_id ORDER BY timestamp, id), $2) AS ended
+                FROM readings
+                WHERE starts_run
+             ),
+             still AS (
+                SELECT room_id, started, ended,
+                       GREATEST(FLOOR(EXTRACT(EPOCH FROM ended - started) / 60), 0)::bigint AS mins
+                FROM runs
+                WHERE NOT motion
+             )
+             SELECT width_bucket(mins, $5::bigint[]), COUNT(*), NULL::text, NULL::timestamptz, NULL::timestamptz, NULL::bigint
+             FROM still
+             GROUP BY 1
+             UNION ALL
+             (SELECT NULL::int, NULL::bigint, room_id, started, ended, mins
+              FROM still
+              ORDER BY mins DESC, started
+              LIMIT $6)"),
+            &[&start, &end, &tag, &room, &bounds, &(TOP_STILL_PERIODS as i64)],
+        )).await?;
+        
+        // Bucket counts have a bucket, the longest periods don't
+        let mut counts = [0; STILL_PERIOD_BUCKETS.len() + 1];
+        let mut longest = Vec::new();
+        for row in &rows {
+            match row.get::<_, Option<i32>>(0) {
+                Some(bucket) => counts[bucket as usize] = row.get::<_, i64>(1) as u64,
+                None => longest.push(StillPeriod {
+                    room: row.get::<_, Option<String>>(2).unwrap_or_else(|| DEFAULT_ROOM_ID.to_string()),
+                    start: row.get(3),
+                    end: row.get(4),
+                    duration_mins: row.get::<_, i64>(5) as u64,
+                }),
+            }
+        }
+        Ok((StillPeriods::from_bucket_counts(counts), longest))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for Postgres {
+    async fn ping(&self) -> Result<(), DbError> {
+        // Straight from the pool: a health check shouldn't wait out an outage
+        let client = self.pool.get().await?;
+        client.simple_query("SELECT 1").await?;
+        Ok(())
+    }
+    
+    fn pool_status(&self) -> Option<PoolStatus> {
+        let status = self.pool.status();
+        Some(PoolStatus {
+            size: status.size,
+            max_size: status.max_size,
+            available: status.available,
+            waiting: status.waiting,
+        })
+    }
+    
+    async fn register_rooms(&self, rooms: &[String]) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO rooms (id) SELECT UNNEST($1::text[]) ON CONFLICT (id) DO NOTHING",
+            &[&rooms],
+        ).await?;
+        
+        if let Some(first) = rooms.first() {
+            let assigned = client.execute(
+                "UPDATE sensor_data SET room_id = $1 WHERE room_id IS NULL",
+                &[first],
+            ).await?;
+            if assigned > 0 {
+                info!("Assigned {} readings without a room to {}", assigned, first);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    async fn get_rooms(&self) -> Result<Vec<Room>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT r.id, r.created_at,
+                    (SELECT MAX(timestamp) FROM sensor_data WHERE deleted_at IS NULL AND room_id = r.id),
+                    r.ward_id
+             FROM rooms r
+             ORDER BY r.id",
+            &[],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| Room {
+            id: row.get(0),
+            created_at: row.get(1),
+            last_reading_at: row.get(2),
+            ward: row.get(3),
+        }).collect())
+    }
+    
+    async fn register_ward(&self, ward: &str, rooms: &[String]) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO wards (id, name) VALUES ($1, $1) ON CONFLICT (id) DO NOTHING",
+            &[&ward],
+        ).await?;
+        client.execute(
+            "UPDATE rooms SET ward_id = $1 WHERE id = ANY($2) AND ward_id IS NULL",
+            &[&ward, &rooms],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_wards(&self) -> Result<Vec<Ward>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT w.id, w.name, w.created_at,
+                    COALESCE(ARRAY_AGG(r.id ORDER BY r.id) FILTER (WHERE r.id IS NOT NULL), '{}')
+             FROM wards w
+             LEFT JOIN rooms r ON r.ward_id = w.id
+             GROUP BY w.id
+             ORDER BY w.id",
+            &[],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| Ward {
+            id: row.get(0),
+            name: row.get(1),
+            created_at: row.get(2),
+            rooms: row.get(3),
+        }).collect())
+    }
+    
+    async fn insert_ward(&self, id: &str, name: &str) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let inserted = client.execute(
+            "INSERT INTO wards (id, name) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING",
+            &[&id, &name],
+        ).await?;
+        
+        Ok(inserted > 0)
+    }
+    
+    async fn delete_ward(&self, id: &str) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute("DELETE FROM wards WHERE id = $1", &[&id]).await?;
+        Ok(deleted > 0)
+    }
+    
+    async fn set_room_ward(&self, room: &str, ward: Option<&str>) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let updated = client.execute(
+            "UPDATE rooms SET ward_id = $2 WHERE id = $1",
+            &[&room, &ward],
+        ).await?;
+        
+        Ok(updated > 0)
+    }
+    
+    async fn get_patients(&self, room: Option<&str>) -> Result<Vec<Patient>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, family_name, given_names, birth_date, gender, room_id
+             FROM patients
+             WHERE ($1::text IS NULL OR room_id = $1)
+             ORDER BY id",
+            &[&room],
+        ).await?;
+        
+        Ok(rows.iter().map(Self::row_to_patient).collect())
+    }
+    
+    async fn get_patient(&self, id: &str) -> Result<Option<Patient>, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_opt(
+            "SELECT id, family_name, given_names, birth_date, gender, room_id
+             FROM patients WHERE id = $1",
+            &[&id],
+        ).await?;
+        
+        Ok(row.map(|r| Self::row_to_patient(&r)))
+    }
+    
+    async fn insert_patient(&self, patient: &Patient) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let inserted = client.execute(
+            "INSERT INTO patients (id, family_name, given_names, birth_date, gender, room_id)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (id) DO NOTHING",
+            &[&patient.id, &patient.family_name, &patient.given_names, &patient.birth_date,
+              &patient.gender.map(|g| g.code()), &patient.room],
+        ).await?;
+        
+        Ok(inserted > 0)
+    }
+    
+    async fn update_patient(&self, patient: &Patient) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let updated = client.execute(
+            "UPDATE patients SET family_name = $2, given_names = $3, birth_date = $4, gender = $5,
+                    room_id = $6, updated_at = NOW()
+             WHERE id = $1",
+            &[&patient.id, &patient.family_name, &patient.given_names, &patient.birth_date,
+              &patient.gender.map(|g| g.code()), &patient.room],
+        ).await?;
+        
+        Ok(updated > 0)
+    }
+    
+    async fn delete_patient(&self, id: &str) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute("DELETE FROM patients WHERE id = $1", &[&id]).await?;
+        Ok(deleted > 0)
+    }
+    
+    async fn get_recent_readings(
+        &self,
+        limit: usize,
+        room: Option<&str>,
+        tag: Option<&str>,
+        cursor: Option<ReadingCursor>,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, seq, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+             FROM sensor_data
+             WHERE deleted_at IS NULL AND ($2::text IS NULL OR id IN (
+                 SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                 WHERE t.name = $2))
+               AND ($3::text IS NULL OR room_id = $3)
+               AND ($4::timestamptz IS NULL OR (timestamp, id) < ($4, $5))
+             ORDER BY timestamp DESC, id DESC
+             LIMIT $1",
+            &[&(limit as i64), &tag, &room,
+              &cursor.map(|c| c.before_timestamp), &cursor.map(|c| c.after_id)],
+        ).await?;
+        
+        let events = rows.iter().map(Self::row_to_event).collect();
+        Ok(events)
+    }
+    
+    async fn get_readings_after(
+        &self,
+        after: i64,
+        limit: usize,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, seq, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+             FROM sensor_data
+             WHERE deleted_at IS NULL AND seq > $1
+               AND ($3::text IS NULL OR id IN (
+                 SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                 WHERE t.name = $3))
+               AND ($4::text IS NULL OR room_id = $4)
+             ORDER BY seq
+             LIMIT $2",
+            &[&after, &(limit as i64), &tag, &room],
+        ).await?;
+        
+        Ok(rows.iter().map(Self::row_to_event).collect())
+    }
+    
+    async fn insert_readings_batch(
+        &self,
+        events: &[SensorEvent],
+    ) -> Result<Vec<StoredReading>, DbError> {
+        let client = self.client().await?;
+        
+        let timestamps: Vec<DateTime<Utc>> = events.iter().map(|e| e.reading.timestamp).collect();
+        let temperatures: Vec<f32> = events.iter().map(|e| e.reading.temperature).collect();
+        let motion: Vec<bool> = events.iter().map(|e| e.reading.motion).collect();
+        let sound_levels: Vec<i32> = events.iter().map(|e| e.reading.sound_level).collect();
+        let humidities: Vec<Option<f32>> = events.iter().map(|e| e.reading.humidity).collect();
+        let heart_rates: Vec<Option<i32>> = events.iter().map(|e| e.reading.heart_rate).collect();
+        let spo2s: Vec<Option<i32>> = events.iter().map(|e| e.reading.spo2).collect();
+        let doors: Vec<Option<bool>> = events.iter().map(|e| e.reading.door_open).collect();
+        let lights: Vec<Option<f32>> = events.iter().map(|e| e.reading.light).collect();
+        let co2s: Vec<Option<i32>> = events.iter().map(|e| e.reading.co2).collect();
+        let presences: Vec<Option<bool>> = events.iter().map(|e| e.reading.presence).collect();
+        let accelerations: Vec<Option<f32>> = events.iter().map(|e| e.reading.acceleration).collect();
+        let rooms: Vec<&str> = events.iter().map(|e| e.room.as_str()).collect();
+        // Arrays of arrays must be rectangular, so alerts travel comma-joined
+        let alerts: Vec<String> = events
+            .iter()
+            .map(|e| e.alerts.iter().map(alert_to_str).collect::<Vec<_>>().join(","))
+            .collect();
+        
+        let insert = "INSERT INTO sensor_data (timestamp, temperature, motion, sound_level, alert_types, room_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration)
+             SELECT t, temp, m, s, COALESCE(string_to_array(NULLIF(a, ''), ','), '{}'), room, h, hr, o, d, l, c, p, g
+             FROM UNNEST($1::timestamptz[], $2::real[], $3::bool[], $4::int[], $5::text[], $6::text[], $7::real[],
+                         $8::int[], $9::int[], $10::bool[], $11::real[], $12::int[], $13::bool[], $14::real[])
+                  WITH ORDINALITY AS r(t, temp, m, s, a, room, h, hr, o, d, l, c, p, g, n)
+             ORDER BY n
+             RETURNING id, seq, patient_id";
+        let params: [&(dyn tokio_postgres::types::ToSql + Sync); 14] = [
+            &timestamps, &temperatures, &motion, &sound_levels, &alerts, &rooms, &humidities, &heart_rates, &spo2s,
+            &doors, &lights, &co2s, &presences, &accelerations,
+        ];
+        let rows = match client.query(insert, &params).await {
+            // A reading timestamped past the partitions created ahead
+            Err(e) if self.partitioned && is_missing_partition(&e) => {
+                create_partitions_for(&client, &timestamps).await?;
+                client.query(insert, &params).await?
+            }
+            result => result?,
+        };
+        
+        Ok(rows.iter().map(|r| StoredReading {
+            id: r.get(0),
+            seq: r.get(1),
+            patient_id: r.get(2),
+        }).collect())
+    }
+    
+    async fn insert_historical(&self, events: &[SensorEvent]) -> Result<u64, DbError> {
+        let mut client = self.client().await?;
+        if self.partitioned {
+            let timestamps: Vec<DateTime<Utc>> = events.iter().map(|e| e.reading.timestamp).collect();
+            create_partitions_for(&client, &timestamps).await?;
+        }
+        let tx = client.transaction().await?;
+        
+        let mut stored = 0;
+        for chunk in events.chunks(HISTORICAL_BATCH_SIZE) {
+            let timestamps: Vec<DateTime<Utc>> = chunk.iter().map(|e| e.reading.timestamp).collect();
+            let temperatures: Vec<f32> = chunk.iter().map(|e| e.reading.temperature).collect();
+            let motion: Vec<bool> = chunk.iter().map(|e| e.reading.motion).collect();
+            let sound_levels: Vec<i32> = chunk.iter().map(|e| e.reading.sound_level).collect();
+            let humidities: Vec<Option<f32>> = chunk.iter().map(|e| e.reading.humidity).collect();
+            let heart_rates: Vec<Option<i32>> = chunk.iter().map(|e| e.reading.heart_rate).collect();
+            let spo2s: Vec<Option<i32>> = chunk.iter().map(|e| e.reading.spo2).collect();
+            let doors: Vec<Option<bool>> = chunk.iter().map(|e| e.reading.door_open).collect();
+            let lights: Vec<Option<f32>> = chunk.iter().map(|e| e.reading.light).collect();
+            let co2s: Vec<Option<i32>> = chunk.iter().map(|e| e.reading.co2).collect();
+            let presences: Vec<Option<bool>> = chunk.iter().map(|e| e.reading.presence).collect();
+            let accelerations: Vec<Option<f32>> = chunk.iter().map(|e| e.reading.acceleration).collect();
+            let rooms: Vec<&str> = chunk.iter().map(|e| e.room.as_str()).collect();
+            
+            stored += tx.execute(
+                "INSERT INTO sensor_data (timestamp, temperature, motion, sound_level, alert_types, room_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration)
+                 SELECT t, temp, m, s, '{}', room, h, hr, o, d, l, c, p, g
+                 FROM UNNEST($1::timestamptz[], $2::real[], $3::bool[], $4::int[], $5::text[], $6::real[],
+                             $7::int[], $8::int[], $9::bool[], $10::real[], $11::int[], $12::bool[], $13::real[])
+                      WITH ORDINALITY AS r(t, temp, m, s, room, h, hr, o, d, l, c, p, g, n)
+                 ORDER BY n",
+                &[&timestamps, &temperatures, &motion, &sound_levels, &rooms, &humidities, &heart_rates, &spo2s, &doors, &lights, &co2s, &presences, &accelerations],
+            ).await?;
+        }
+        tx.commit().await?;
+        
+        Ok(stored)
+    }
+    
+    async fn count_readings_in_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+    ) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_one(
+            &format!("SELECT COUNT(*) FROM {EXPANDED_READINGS}
+             WHERE deleted_at IS NULL AND timestamp BETWEEN $1 AND $2 AND ($3::text IS NULL OR room_id = $3)"),
+            &[&start, &end, &room],
+        ).await?;
+        
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+    
+    async fn get_readings_in_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+        cursor: Option<ReadingCursor>,
+        limit: Option<usize>,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = self.analytics(&client, client.query(
+            &format!("SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, seq, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+             FROM {EXPANDED_READINGS}
+             WHERE deleted_at IS NULL AND timestamp BETWEEN $1 AND $2
+               AND ($3::text IS NULL OR id IN (
+                 SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                 WHERE t.name = $3))
+               AND ($4::text IS NULL OR room_id = $4)
+               AND ($5::timestamptz IS NULL OR (timestamp, id) < ($5, $6))
+             ORDER BY timestamp DESC, id DESC
+             LIMIT $7"),
+            &[&start, &end, &tag, &room,
+              &cursor.map(|c| c.before_timestamp), &cursor.map(|c| c.after_id), &limit.map(|l| l as i64)],
+        )).await?;
+        
+        let events = rows.iter().map(Self::row_to_event).collect();
+        Ok(events)
+    }
+    
+    async fn export_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        after: Option<(DateTime<Utc>, i64)>,
+        limit: usize,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = self.analytics(&client, client.query(
+            &format!("SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, seq, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+             FROM {EXPANDED_READINGS}
+             WHERE deleted_at IS NULL AND timestamp BETWEEN $1 AND $2
+               AND ($3::text IS NULL OR room_id = $3)
+               AND ($4::timestamptz IS NULL OR (timestamp, id) > ($4, $5))
+             ORDER BY timestamp, id
+             LIMIT $6"),
+            &[&start, &end, &room, &after.map(|(t, _)| t), &after.map(|(_, id)| id), &(limit as i64)],
+        )).await?;
+        
+        Ok(rows.iter().map(Self::row_to_event).collect())
+    }
+    
+    async fn get_reading_by_id(&self, id: i64) -> Result<Option<SensorEvent>, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_opt(
+            "SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, seq, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+             FROM sensor_data WHERE deleted_at IS NULL AND id = $1",
+            &[&id],
+        ).await?;
+        
+        Ok(row.map(|r| Self::row_to_event(&r)))
+    }
+    
+    async fn update_reading(&self, id: i64, correction: &ReadingCorrection) -> Result<Option<SensorEvent>, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_opt(
+            "UPDATE sensor_data
+             SET temperature = COALESCE($2, temperature),
+                 motion = COALESCE($3, motion),
+                 sound_level = COALESCE($4, sound_level)
+             WHERE id = $1 AND deleted_at IS NULL
+             RETURNING id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, seq, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration",
+            &[&id, &correction.temperature, &correction.motion, &correction.sound_level],
+        ).await?;
+        
+        Ok(row.map(|r| Self::row_to_event(&r)))
+    }
+    
+    async fn mark_deleted(&self, id: i64, at: DateTime<Utc>) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let updated = client.execute(
+            "UPDATE sensor_data SET deleted_at = $2 WHERE id = $1 AND deleted_at IS NULL",
+            &[&id, &at],
+        ).await?;
+        
+        Ok(updated > 0)
+    }
+    
+    async fn get_alert_summary(&self) -> Result<AlertSummary, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query("SELECT counter, value FROM sensor_counters", &[]).await?;
+        
+        let mut summary = AlertSummary {
+            total_readings: 0,
+            fall_alerts: 0,
+            inactivity_alerts: 0,
+        };
+        for row in rows {
+            let counter: &str = row.get(0);
+            let value = row.get::<_, i64>(1).max(0) as u64;
+            match counter {
+                "total" => summary.total_readings = value,
+                "fall" => summary.fall_alerts = value,
+                "inactivity" => summary.inactivity_alerts = value,
+                _ => {}
+            }
+        }
+        
+        Ok(summary)
+    }
+    
+    async fn get_consent(&self, patient_id: &str) -> Result<Consent, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_opt(
+            "SELECT continuous_monitoring, ehr_sharing, research_export, updated_at
+             FROM patient_consent WHERE patient_id = $1",
+            &[&patient_id],
+        ).await?;
+        
+        Ok(row.map(|r| Consent {
+            continuous_monitoring: r.get(0),
+            ehr_sharing: r.get(1),
+            research_export: r.get(2),
+            updated_at: Some(r.get(3)),
+        }).unwrap_or_default())
+    }
+    
+    async fn set_consent(&self, patient_id: &str, consent: &Consent) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO patient_consent (patient_id, continuous_monitoring, ehr_sharing, research_export)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (patient_id) DO UPDATE SET
+                continuous_monitoring = EXCLUDED.continuous_monitoring,
+                ehr_sharing = EXCLUDED.ehr_sharing,
+                research_export = EXCLUDED.research_export,
+                updated_at = NOW()",
+            &[&patient_id, &consent.continuous_monitoring, &consent.ehr_sharing, &consent.research_export],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_alert_rules(&self) -> Result<Vec<AlertRule>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, name, expression, alert_type, enabled, shadow FROM alert_rules ORDER BY id",
+            &[],
+        ).await?;
+        
+        let rules = rows.iter().filter_map(|row| {
+            let alert_str: &str = row.get(3);
+            let Some(alert) = alert_from_str(alert_str) else {
+                warn!("Skipping rule {} with unknown alert type '{}'", row.get::<_, i64>(0), alert_str);
+                return None;
+            };
+            Some(AlertRule {
+                id: row.get(0),
+                name: row.get(1),
+                expression: row.get(2),
+                alert,
+                enabled: row.get(4),
+                shadow: row.get(5),
+            })
+        }).collect();
+        
+        Ok(rules)
+    }
+    
+    async fn insert_alert_rule(&self, rule: &AlertRule) -> Result<i64, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_one(
+            "INSERT INTO alert_rules (name, expression, alert_type, enabled, shadow)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING id",
+            &[&rule.name, &rule.expression, &alert_to_str(rule.alert), &rule.enabled, &rule.shadow],
+        ).await?;
+        
+        Ok(row.get(0))
+    }
+    
+    async fn delete_alert_rule(&self, id: i64) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute("DELETE FROM alert_rules WHERE id = $1", &[&id]).await?;
+        Ok(deleted > 0)
+    }
+    
+    async fn set_alert_rule_shadow(&self, id: i64, shadow: bool) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let updated = client.execute("UPDATE alert_rules SET shadow = $2 WHERE id = $1", &[&id, &shadow]).await?;
+        Ok(updated > 0)
+    }
+    
+    async fn insert_shadow_hit(&self, hit: &ShadowHit) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO shadow_rule_hits (rule_id, room_id, hit_at, temperature, motion, sound_level)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&hit.rule_id, &hit.room, &hit.at, &hit.temperature, &hit.motion, &hit.sound_level],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_shadow_hits(
+        &self,
+        rule_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<ShadowHit>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, rule_id, room_id, hit_at, temperature, motion, sound_level
+             FROM shadow_rule_hits
+             WHERE rule_id = $1 AND hit_at >= $2 AND hit_at < $3
+             ORDER BY hit_at DESC
+             LIMIT $4",
+            &[&rule_id, &from, &to, &(limit as i64)],
+        ).await?;
+        
+        let hits = rows.iter().map(|row| ShadowHit {
+            id: row.get(0),
+            rule_id: row.get(1),
+            room: row.get(2),
+            at: row.get(3),
+            temperature: row.get(4),
+            motion: row.get(5),
+            sound_level: row.get(6),
+        }).collect();
+        
+        Ok(hits)
+    }
+    
+    async fn count_shadow_hits(
+        &self,
+        rule_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<HashMap<String, u64>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT room_id, COUNT(*)
+             FROM shadow_rule_hits
+             WHERE rule_id = $1 AND hit_at >= $2 AND hit_at < $3
+             GROUP BY room_id",
+            &[&rule_id, &from, &to],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| (row.get(0), row.get::<_, i64>(1) as u64)).collect())
+    }
+    
+    async fn get_feature_flags(&self) -> Result<Vec<FlagSetting>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query("SELECT name, room_id, enabled, updated_at, updated_by FROM feature_flags", &[]).await?;
+        Ok(rows.iter().map(|row| FlagSetting {
+            name: row.get(0),
+            room: Some(row.get::<_, String>(1)).filter(|r| !r.is_empty()),
+            enabled: row.get(2),
+            updated_at: row.get(3),
+            updated_by: row.get(4),
+        }).collect())
+    }
+    
+    async fn set_feature_flag(&self, setting: &FlagSetting) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO feature_flags (name, room_id, enabled, updated_at, updated_by) VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (name, room_id) DO UPDATE
+             SET enabled = EXCLUDED.enabled, updated_at = EXCLUDED.updated_at, updated_by = EXCLUDED.updated_by",
+            &[&setting.name, &setting.room.as_deref().unwrap_or_default(), &setting.enabled, &setting.updated_at, &setting.updated_by],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn delete_feature_flag(&self, name: &str, room: Option<&str>) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute(
+            "DELETE FROM feature_flags WHERE name = $1 AND room_id = $2",
+            &[&name, &room.unwrap_or_default()],
+        ).await?;
+        Ok(deleted > 0)
+    }
+    
+    async fn get_rule_thresholds(&self) -> Result<HashMap<String, f64>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query("SELECT name, value FROM rule_thresholds", &[]).await?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+    
+    async fn set_rule_threshold(&self, name: &str, value: f64) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO rule_thresholds (name, value) VALUES ($1, $2)
+             ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value",
+            &[&name, &value],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn insert_device_log(
+        &self,
+        device_id: &str,
+        level: &str,
+        message: &str,
+    ) -> Result<i64, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_one(
+            "INSERT INTO device_logs (device_id, level, message)
+             VALUES ($1, $2, $3)
+             RETURNING id",
+            &[&device_id, &level, &message],
+        ).await?;
+        
+        Ok(row.get(0))
+    }
+    
+    async fn get_device_logs(
+        &self,
+        device_id: &str,
+        limit: usize,
+    ) -> Result<Vec<DeviceLog>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, device_id, received_at, level, message
+             FROM device_logs
+             WHERE device_id = $1
+             ORDER BY received_at DESC
+             LIMIT $2",
+            &[&device_id, &(limit as i64)],
+        ).await?;
+        
+        let logs = rows.iter().map(|row| DeviceLog {
+            id: row.get(0),
+            device_id: row.get(1),
+            received_at: row.get(2),
+            level: row.get(3),
+            message: row.get(4),
+        }).collect();
+        
+        Ok(logs)
+    }
+    
+    async fn purge_device_logs(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute(
+            "DELETE FROM device_logs WHERE received_at < $1",
+            &[&cutoff],
+        ).await?;
+        
+        Ok(deleted)
+    }
+    
+    async fn insert_quarantined_reading(&self, reading: &QuarantinedReading) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO quarantined_readings (room_id, device_id, received_at, line, reason)
+             VALUES ($1, $2, $3, $4, $5)",
+            &[&reading.room, &reading.device_id, &reading.received_at, &reading.line, &reading.reason],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_quarantined_readings(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        room: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<QuarantinedReading>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, room_id, device_id, received_at, line, reason
+             FROM quarantined_readings
+             WHERE received_at >= $1 AND received_at <= $2 AND ($3::text IS NULL OR room_id = $3)
+             ORDER BY received_at DESC
+             LIMIT $4",
+            &[&from, &to, &room, &(limit as i64)],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| QuarantinedReading {
+            id: row.get(0),
+            room: row.get(1),
+            device_id: row.get(2),
+            received_at: row.get(3),
+            line: row.get(4),
+            reason: row.get(5),
+        }).collect())
+    }
+    
+    async fn purge_quarantined_readings(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute(
+            "DELETE FROM quarantined_readings WHERE received_at < $1",
+            &[&cutoff],
+        ).await?;
+        
+        Ok(deleted)
+    }
+    
+    async fn upsert_device(
+        &self,
+        device_id: &str,
+        room: &str,
+        firmware_version: Option<&str>,
+        seen_at: Option<DateTime<Utc>>,
+    ) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO devices (device_id, room_id, firmware_version, last_seen)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (device_id) DO UPDATE SET
+                room_id = EXCLUDED.room_id,
+                firmware_version = COALESCE(EXCLUDED.firmware_version, devices.firmware_version),
+                last_seen = GREATEST(EXCLUDED.last_seen, devices.last_seen)",
+            &[&device_id, &room, &firmware_version, &seen_at],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_devices(&self) -> Result<Vec<Device>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT device_id, room_id, firmware_version, registered_at, last_seen
+             FROM devices
+             ORDER BY room_id, device_id",
+            &[],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| Device {
+            id: row.get(0),
+            room: row.get(1),
+            firmware_version: row.get(2),
+            registered_at: row.get(3),
+            last_seen: row.get(4),
+        }).collect())
+    }
+    
+    async fn insert_ws_sessions(&self, sessions: &[WsSession]) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        let statement = client.prepare(
+            "INSERT INTO ws_sessions (id, channel, ip, principal, user_agent, connected_at, disconnected_at,
+                                      messages_sent, lag_events, missed_messages, close_reason)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+             ON CONFLICT (id) DO NOTHING",
+        ).await?;
+        
+        for session in sessions {
+            let (Some(disconnected_at), Some(reason)) = (session.disconnected_at, &session.close_reason) else {
+                continue;
+            };
+            client.execute(&statement, &[
+                &session.id,
+                &session.channel,
+                &session.ip,
+                &session.principal,
+                &session.user_agent,
+                &session.connected_at,
+                &disconnected_at,
+                &(session.messages_sent as i64),
+                &(session.lag_events as i64),
+                &(session.missed_messages as i64),
+                reason,
+            ]).await?;
+        }
+        
+        Ok(())
+    }
+    
+    async fn purge_ws_sessions(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute(
+            "DELETE FROM ws_sessions WHERE disconnected_at < $1",
+            &[&cutoff],
+        ).await?;
+        
+        Ok(deleted)
+    }
+    
+    async fn get_ws_subscription(&self, principal: &str) -> Result<Option<Subscription>, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_opt(
+            "SELECT rooms, severities, message_types, updated_at FROM ws_subscriptions WHERE principal = $1",
+            &[&principal],
+        ).await?;
+        
+        Ok(row.map(|r| Subscription {
+            rooms: r.get(0),
+            severities: r.get::<_, Vec<&str>>(1).into_iter().filter_map(severity_from_str).collect(),
+            message_types: r.get::<_, Vec<&str>>(2).into_iter().filter_map(message_kind_from_str).collect(),
+            updated_at: Some(r.get(3)),
+        }))
+    }
+    
+    async fn set_ws_subscription(&self, principal: &str, subscription: &Subscription) -> Result<(), DbError> {
+        let client = self.client().await?;
+        let severities: Vec<&str> = subscription.severities.iter().map(|s| severity_to_str(*s)).collect();
+        let message_types: Vec<&str> = subscription.message_types.iter().map(|k| message_kind_to_str(*k)).collect();
+        
+        client.execute(
+            "INSERT INTO ws_subscriptions (principal, rooms, severities, message_types)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (principal) DO UPDATE SET
+                rooms = EXCLUDED.rooms,
+                severities = EXCLUDED.severities,
+                message_types = EXCLUDED.message_types,
+                updated_at = NOW()",
+            &[&principal, &subscription.rooms, &severities, &message_types],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn delete_ws_subscription(&self, principal: &str) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute("DELETE FROM ws_subscriptions WHERE principal = $1", &[&principal]).await?;
+        Ok(deleted > 0)
+    }
+    
+    async fn readings_older_than(
+        &self,
+        cutoff: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, seq, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+             FROM sensor_data
+             WHERE timestamp < $1
+             ORDER BY timestamp, id
+             LIMIT $2",
+            &[&cutoff, &limit],
+        ).await?;
+        
+        Ok(rows.iter().map(Self::row_to_event).collect())
+    }
+    
+    async fn delete_readings(&self, ids: &[i64]) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute(
+            "DELETE FROM sensor_data WHERE id = ANY($1)",
+            &[&ids],
+        ).await?;
+        
+        Ok(deleted)
+    }
+    
+    async fn start_job_run(
+        &self,
+        job: &str,
+        started_at: DateTime<Utc>,
+        checkpoint: Option<&str>,
+    ) -> Result<i64, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_one(
+            "INSERT INTO job_runs (job, started_at, checkpoint) VALUES ($1, $2, $3) RETURNING id",
+            &[&job, &started_at, &checkpoint],
+        ).await?;
+        
+        Ok(row.get(0))
+    }
+    
+    async fn checkpoint_job_run(&self, id: i64, checkpoint: &str) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "UPDATE job_runs SET checkpoint = $2, chunks = chunks + 1 WHERE id = $1",
+            &[&id, &checkpoint],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn finish_job_run(
+        &self,
+        id: i64,
+        finished_at: DateTime<Utc>,
+        error: Option<&str>,
+    ) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "UPDATE job_runs
+             SET finished_at = $2, status = CASE WHEN $3::text IS NULL THEN 'completed' ELSE 'failed' END, error = $3
+             WHERE id = $1",
+            &[&id, &finished_at, &error],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn interrupt_job_runs(&self) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let interrupted = client.execute(
+            "UPDATE job_runs SET status = 'interrupted' WHERE status = 'running'",
+            &[],
+        ).await?;
+        
+        Ok(interrupted)
+    }
+    
+    async fn get_job_runs(
+        &self,
+        job: Option<&str>,
+        status: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<JobRun>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, job, started_at, finished_at, status, checkpoint, chunks, error
+             FROM job_runs
+             WHERE ($1::text IS NULL OR job = $1) AND ($2::text IS NULL OR status = $2)
+             ORDER BY id DESC
+             LIMIT $3",
+            &[&job, &status, &(limit as i64)],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| JobRun {
+            id: row.get(0),
+            job: row.get(1),
+            started_at: row.get(2),
+            finished_at: row.get(3),
+            status: row.get(4),
+            checkpoint: row.get(5),
+            chunks: row.get::<_, i64>(6) as u64,
+            error: row.get(7),
+        }).collect())
+    }
+    
+    async fn purge_job_runs(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute(
+            "DELETE FROM job_runs WHERE started_at < $1 AND status <> 'running'",
+            &[&cutoff],
+        ).await?;
+        
+        Ok(deleted)
+    }
+    
+    async fn get_compaction_rows(
+        &self,
+        room: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<CompactionRow>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, timestamp, COALESCE(run_until, timestamp), run_count, temperature, sound_level, humidity, door_open, light, co2, presence, acceleration, patient_id,
+                    NOT motion AND alert_types = '{}' AND heart_rate IS NULL AND spo2 IS NULL
+                    AND NOT EXISTS (SELECT 1 FROM reading_tags rt WHERE rt.reading_id = s.id)
+             FROM sensor_data s
+             WHERE deleted_at IS NULL AND room_id = $1 AND timestamp >= $2 AND timestamp < $3
+             ORDER BY timestamp, id",
+            &[&room, &start, &end],
+        ).await?;
+        
+        Ok(rows.iter().map(|r| CompactionRow {
+            id: r.get(0),
+            start: r.get(1),
+            end: r.get(2),
+            count: r.get::<_, i32>(3) as i64,
+            temperature: r.get(4),
+            sound_level: r.get(5),
+            humidity: r.get(6),
+            door_open: r.get(7),
+            light: r.get(8),
+            co2: r.get(9),
+            presence: r.get(10),
+            acceleration: r.get(11),
+            patient_id: r.get(12),
+            mergeable: r.get(13),
+        }).collect())
+    }
+    
+    async fn merge_runs(&self, runs: &[ReadingRun]) -> Result<u64, DbError> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        
+        let ids: Vec<i64> = runs.iter().map(|r| r.id).collect();
+        let ends: Vec<DateTime<Utc>> = runs.iter().map(|r| r.end).collect();
+        let counts: Vec<i32> = runs.iter().map(|r| r.count as i32).collect();
+        let merged: Vec<i64> = runs.iter().flat_map(|r| r.merged.iter().copied()).collect();
+        
+        tx.execute(
+            "UPDATE sensor_data s SET run_until = r.until, run_count = r.count
+             FROM UNNEST($1::bigint[], $2::timestamptz[], $3::int[]) AS r(id, until, count)
+             WHERE s.id = r.id",
+            &[&ids, &ends, &counts],
+        ).await?;
+        // The merged readings live on in their interval, so the running
+        // totals their deletion lowers are raised again
+        tx.execute(
+            "UPDATE sensor_counters c SET value = c.value + n.readings
+             FROM (SELECT counter, SUM(run_count) AS readings
+                   FROM sensor_data, unnest(ARRAY['total'] || alert_types) AS counter
+                   WHERE id = ANY($1) AND deleted_at IS NULL
+                   GROUP BY counter) n
+             WHERE c.counter = n.counter",
+            &[&merged],
+        ).await?;
+        let deleted = tx.execute("DELETE FROM sensor_data WHERE id = ANY($1)", &[&merged]).await?;
+        tx.commit().await?;
+        
+        Ok(deleted)
+    }
+    
+    async fn purge_alerts_resolved_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute("DELETE FROM alerts WHERE resolved_at < $1", &[&cutoff]).await?;
+        client.execute("DELETE FROM alert_events WHERE occurred_at < $1", &[&cutoff]).await?;
+        Ok(deleted)
+    }
+    
+    async fn insert_room_state(
+        &self,
+        room: &str,
+        state: PatientState,
+        started_at: DateTime<Utc>,
+    ) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO room_states (room_id, state, started_at) VALUES ($1, $2, $3)",
+            &[&room, &state.as_str(), &started_at],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_room_states(
+        &self,
+        room: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(PatientState, DateTime<Utc>)>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT state, started_at
+             FROM room_states
+             WHERE room_id = $1
+               AND started_at < $3
+               AND started_at >= COALESCE(
+                   (SELECT MAX(started_at) FROM room_states WHERE room_id = $1 AND started_at <= $2), $2)
+             ORDER BY started_at, id",
+            &[&room, &from, &to],
+        ).await?;
+        
+        Ok(rows.iter().filter_map(|row| {
+            let state: String = row.get(0);
+            Some((state.parse().ok()?, row.get(1)))
+        }).collect())
+    }
+    
+    async fn purge_room_states_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute("DELETE FROM room_states WHERE started_at < $1", &[&cutoff]).await?;
+        Ok(deleted)
+    }
+    
+    async fn insert_door_passage(&self, room: &str, passage: &DoorPassage) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO door_passages (room_id, kind, opened_at, closed_at) VALUES ($1, $2, $3, $4)",
+            &[&room, &passage.kind.as_str(), &passage.opened_at, &passage.closed_at],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_door_passages(
+        &self,
+        room: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DoorPassage>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT kind, opened_at, closed_at
+             FROM door_passages
+             WHERE room_id = $1 AND opened_at >= $2 AND opened_at < $3
+             ORDER BY opened_at, id",
+            &[&room, &from, &to],
+        ).await?;
+        
+        Ok(rows.iter().filter_map(|row| {
+            let kind: String = row.get(0);
+            Some(DoorPassage {
+                kind: kind.parse().ok()?,
+                opened_at: row.get(1),
+                closed_at: row.get(2),
+            })
+        }).collect())
+    }
+    
+    async fn purge_door_passages_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute("DELETE FROM door_passages WHERE opened_at < $1", &[&cutoff]).await?;
+        Ok(deleted)
+    }
+    
+    async fn insert_kiosk_token(
+        &self,
+        room: &str,
+        token_hash: &str,
+        label: Option<&str>,
+        created_by: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<KioskToken, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_one(
+            "INSERT INTO kiosk_tokens (room_id, token_hash, label, created_by, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, NOW(), $5)
+             RETURNING id, room_id, label, created_by, created_at, expires_at, revoked_at, revoked_by",
+            &[&room, &token_hash, &label, &created_by, &expires_at],
+        ).await?;
+        
+        Ok(Self::row_to_kiosk_token(&row))
+    }
+    
+    async fn get_kiosk_tokens(&self, room: Option<&str>, now: DateTime<Utc>) -> Result<Vec<KioskToken>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, room_id, label, created_by, created_at, expires_at, revoked_at, revoked_by
+             FROM kiosk_tokens
+             WHERE revoked_at IS NULL AND expires_at > $2 AND ($1::text IS NULL OR room_id = $1)
+             ORDER BY created_at DESC, id DESC",
+            &[&room, &now],
+        ).await?;
+        
+        Ok(rows.iter().map(Self::row_to_kiosk_token).collect())
+    }
+    
+    async fn get_kiosk_token(&self, id: i64) -> Result<Option<KioskToken>, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_opt(
+            "SELECT id, room_id, label, created_by, created_at, expires_at, revoked_at, revoked_by
+             FROM kiosk_tokens WHERE id = $1",
+            &[&id],
+        ).await?;
+        
+        Ok(row.as_ref().map(Self::row_to_kiosk_token))
+    }
+    
+    async fn find_kiosk_token(&self, token_hash: &str, now: DateTime<Utc>) -> Result<Option<KioskToken>, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_opt(
+            "SELECT id, room_id, label, created_by, created_at, expires_at, revoked_at, revoked_by
+             FROM kiosk_tokens
+             WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > $2",
+            &[&token_hash, &now],
+        ).await?;
+        
+        Ok(row.as_ref().map(Self::row_to_kiosk_token))
+    }
+    
+    async fn revoke_kiosk_token(&self, id: i64, revoked_by: &str) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let revoked = client.execute(
+            "UPDATE kiosk_tokens SET revoked_at = NOW(), revoked_by = $2 WHERE id = $1 AND revoked_at IS NULL",
+            &[&id, &revoked_by],
+        ).await?;
+        Ok(revoked > 0)
+    }
+    
+    async fn maintain_partitions(&self, now: DateTime<Utc>) -> Result<PartitionChanges, DbError> {
+        if !self.partitioned {
+            return Ok(PartitionChanges::default());
+        }
+        let mut client = self.client().await?;
+        
+        let created = create_partitions(&client, now).await?;
+        let mut dropped = Vec::new();
+        if self.partition_keep_months > 0 {
+            let cutoff = month_start(now) - Months::new(self.partition_keep_months);
+            for name in partitions(&client).await? {
+                if partition_end(&name).is_some_and(|end| end <= cutoff) {
+                    drop_partition(&mut client, &name).await?;
+                    dropped.push(name);
+                }
+            }
+        }
+        
+        Ok(PartitionChanges { created, dropped })
+    }
+    
+    async fn get_storage_usage(&self, since: DateTime<Utc>) -> Result<StorageUsage, DbError> {
+        let client = self.client().await?;
+        
+        // Partitions and TimescaleDB chunks are both child tables of sensor_data
+        let partitions: Vec<(String, i64)> = client.query(
+            "SELECT c.relname::text, pg_total_relation_size(c.oid)
+             FROM pg_inherits i JOIN pg_class c ON c.oid = i.inhrelid
+             WHERE i.inhparent = 'sensor_data'::regclass
+             ORDER BY c.relname",
+            &[],
+        ).await?.iter().map(|r| (r.get(0), r.get(1))).collect();
+        let own: i64 = client.query_one("SELECT pg_total_relation_size('sensor_data')", &[]).await?.get(0);
+        
+        let rows = self.analytics(&client, client.query(
+            "SELECT room_id, COUNT(*), COUNT(*) FILTER (WHERE timestamp >= $1)
+             FROM sensor_data
+             GROUP BY room_id
+             ORDER BY room_id",
+            &[&since],
+        )).await?;
+        
+        Ok(StorageUsage {
+            bytes: own + partitions.iter().map(|(_, bytes)| bytes).sum::<i64>(),
+            partitions,
+            rooms: rows.iter().map(|r| (
+                r.get::<_, Option<String>>(0).unwrap_or_else(|| DEFAULT_ROOM_ID.to_string()),
+                r.get(1),
+                r.get(2),
+            )).collect(),
+        })
+    }
+    
+    async fn get_tags(&self) -> Result<Vec<Tag>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT t.name, t.created_at,
+                    COUNT(s.id) AS readings,
+                    COUNT(s.id) FILTER (WHERE cardinality(s.alert_types) > 0) AS alerts
+             FROM tags t
+             LEFT JOIN reading_tags rt ON rt.tag_id = t.id
+             LEFT JOIN sensor_data s ON s.id = rt.reading_id AND s.deleted_at IS NULL
+             GROUP BY t.id
+             ORDER BY t.name",
+            &[],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| {
+            let readings: i64 = row.get(2);
+            let alerts: i64 = row.get(3);
+            Tag {
+                name: row.get(0),
+                created_at: row.get(1),
+                readings: readings as u64,
+                alerts: alerts as u64,
+            }
+        }).collect())
+    }
+    
+    async fn tag_readings(&self, tag: &str, ids: &[i64]) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        let tag_id = Self::upsert_tag(&client, tag).await?;
+        
+        let tagged = client.execute(
+            "INSERT INTO reading_tags (reading_id, tag_id)
+             SELECT id, $2 FROM sensor_data WHERE deleted_at IS NULL AND id = ANY($1)
+             ON CONFLICT DO NOTHING",
+            &[&ids, &tag_id],
+        ).await?;
+        
+        Ok(tagged)
+    }
+    
+    async fn tag_range(
+        &self,
+        tag: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        alerts_only: bool,
+    ) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        let tag_id = Self::upsert_tag(&client, tag).await?;
+        
+        let tagged = client.execute(
+            "INSERT INTO reading_tags (reading_id, tag_id)
+             SELECT id, $3 FROM sensor_data
+             WHERE deleted_at IS NULL AND timestamp BETWEEN $1 AND $2
+               AND (NOT $4 OR cardinality(alert_types) > 0)
+             ON CONFLICT DO NOTHING",
+            &[&start, &end, &tag_id, &alerts_only],
+        ).await?;
+        
+        Ok(tagged)
+    }
+    
+    async fn untag_reading(&self, tag: &str, reading_id: i64) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute(
+            "DELETE FROM reading_tags
+             WHERE reading_id = $2 AND tag_id = (SELECT id FROM tags WHERE name = $1)",
+            &[&tag, &reading_id],
+        ).await?;
+        
+        Ok(deleted > 0)
+    }
+    
+    async fn delete_tag(&self, tag: &str) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let deleted = client.execute("DELETE FROM tags WHERE name = $1", &[&tag]).await?;
+        
+        Ok(deleted > 0)
+    }
+    
+    async fn insert_snooze(&self, snooze: &AlertSnooze) -> Result<i64, DbError> {
+        let client = self.client().await?;
+        let alert_str = alert_to_str(snooze.alert);
+        
+        client.execute(
+            "UPDATE alert_snoozes SET snoozed_until = NOW()
+             WHERE alert_type = $1 AND snoozed_until > NOW()
+               AND reading_id IN (SELECT id FROM sensor_data WHERE deleted_at IS NULL AND room_id = $2)",
+            &[&alert_str, &snooze.room],
+        ).await?;
+        
+        let row = client.query_one(
+            "INSERT INTO alert_snoozes (reading_id, alert_type, snoozed_until, snoozed_by, reason)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING id",
+            &[&snooze.reading_id, &alert_str, &snooze.until, &snooze.snoozed_by, &snooze.reason],
+        ).await?;
+        
+        Ok(row.get(0))
+    }
+    
+    async fn ack_alert(
+        &self,
+        reading_id: i64,
+        acked_by: Option<&str>,
+        via: &str,
+    ) -> Result<(AlertAck, bool), DbError> {
+        let client = self.client().await?;
+        
+        let inserted = client.execute(
+            "INSERT INTO alert_acks (reading_id, acked_by, via) VALUES ($1, $2, $3)
+             ON CONFLICT (reading_id) DO NOTHING",
+            &[&reading_id, &acked_by, &via],
+        ).await?;
+        
+        // Acknowledge the episode the reading belongs to as well
+        client.execute(
+            "UPDATE alerts a SET acknowledged_by = $2, acknowledged_at = NOW()
+             FROM sensor_data s
+             WHERE s.id = $1 AND a.room_id = s.room_id AND a.alert_type = ANY(s.alert_types)
+               AND a.triggered_at <= s.timestamp
+               AND (a.resolved_at IS NULL OR a.resolved_at > s.timestamp)
+               AND a.acknowledged_at IS NULL",
+            &[&reading_id, &acked_by],
+        ).await?;
+        
+        let row = client.query_one(
+            "SELECT reading_id, acked_by, via, acked_at FROM alert_acks WHERE reading_id = $1",
+            &[&reading_id],
+        ).await?;
+        
+        let ack = AlertAck {
+            reading_id: row.get(0),
+            acked_by: row.get(1),
+            via: row.get(2),
+            acked_at: row.get(3),
+        };
+        Ok((ack, inserted == 1))
+    }
+    
+    async fn sync_alerts(
+        &self,
+        room: &str,
+        alerts: &AlertSet,
+        reading_id: i64,
+        at: DateTime<Utc>,
+        resolve: &[(AlertType, ResolutionReason)],
+    ) -> Result<AlertSync, DbError> {
+        let client = self.client().await?;
+        let types: Vec<&str> = alerts.iter().map(alert_to_str).collect();
+        let severities: Vec<&str> = alerts.iter().map(|a| severity_to_str(a.severity())).collect();
+        let resolve_types: Vec<&str> = resolve.iter().map(|(a, _)| alert_to_str(*a)).collect();
+        let reasons: Vec<&str> = resolve.iter().map(|(_, r)| r.as_str()).collect();
+        
+        let mut sync = AlertSync::default();
+        if !resolve.is_empty() {
+            let rows = client.query(
+                "UPDATE alerts a SET resolved_at = $2, resolution_reason = r.reason
+                 FROM UNNEST($3::text[], $4::text[]) AS r(alert_type, reason)
+                 WHERE a.room_id = $1 AND a.resolved_at IS NULL AND a.alert_type = r.alert_type
+                 RETURNING a.id, a.room_id, a.alert_type, a.severity, a.reading_id, a.triggered_at,
+                           a.resolved_at, a.acknowledged_by, a.acknowledged_at, a.resolution_reason, a.outcome",
+                &[&room, &at, &resolve_types, &reasons],
+            ).await?;
+            sync.resolved = rows.iter().filter_map(Self::row_to_alert).collect();
+            if !sync.resolved.is_empty() {
+                debug!("Resolved {} alerts in {}", sync.resolved.len(), room);
+            }
+        }
+        
+        if types.is_empty() {
+            return Ok(sync);
+        }
+        let rows = client.query(
+            "INSERT INTO alerts (room_id, alert_type, severity, reading_id, triggered_at)
+             SELECT $1, t.alert_type, t.severity, $4, $5
+             FROM UNNEST($2::text[], $3::text[]) AS t(alert_type, severity)
+             ON CONFLICT (room_id, alert_type) WHERE resolved_at IS NULL DO NOTHING
+             RETURNING alert_type",
+            &[&room, &types, &severities, &reading_id, &at],
+        ).await?;
+        
+        sync.opened = rows.iter().filter_map(|row| alert_from_str(row.get(0))).collect();
+        Ok(sync)
+    }
+    
+    async fn resolve_acknowledged(
+        &self,
+        reading_id: i64,
+        alerts: &[AlertType],
+        at: DateTime<Utc>,
+    ) -> Result<Vec<Alert>, DbError> {
+        let client = self.client().await?;
+        let types: Vec<&str> = alerts.iter().map(|a| alert_to_str(*a)).collect();
+        
+        let rows = client.query(
+            "UPDATE alerts a SET resolved_at = $3, resolution_reason = 'acknowledged'
+             FROM sensor_data s
+             WHERE s.id = $1 AND a.room_id = s.room_id AND a.alert_type = ANY($2)
+               AND a.alert_type = ANY(s.alert_types)
+               AND a.triggered_at <= s.timestamp AND a.resolved_at IS NULL
+             RETURNING a.id, a.room_id, a.alert_type, a.severity, a.reading_id, a.triggered_at,
+                       a.resolved_at, a.acknowledged_by, a.acknowledged_at, a.resolution_reason, a.outcome",
+            &[&reading_id, &types, &at],
+        ).await?;
+        
+        Ok(rows.iter().filter_map(Self::row_to_alert).collect())
+    }
+    
+    async fn set_alert_outcome(
+        &self,
+        reading_id: i64,
+        alerts: &[AlertType],
+        outcome: AlertOutcome,
+    ) -> Result<Vec<Alert>, DbError> {
+        let client = self.client().await?;
+        let types: Vec<&str> = alerts.iter().map(|a| alert_to_str(*a)).collect();
+        
+        let rows = client.query(
+            "UPDATE alerts a SET outcome = $3
+             FROM sensor_data s
+             WHERE s.id = $1 AND a.room_id = s.room_id AND a.alert_type = ANY($2)
+               AND a.alert_type = ANY(s.alert_types)
+               AND a.triggered_at <= s.timestamp
+               AND (a.resolved_at IS NULL OR a.resolved_at > s.timestamp)
+             RETURNING a.id, a.room_id, a.alert_type, a.severity, a.reading_id, a.triggered_at,
+                       a.resolved_at, a.acknowledged_by, a.acknowledged_at, a.resolution_reason, a.outcome",
+            &[&reading_id, &types, &outcome.as_str()],
+        ).await?;
+        
+        Ok(rows.iter().filter_map(Self::row_to_alert).collect())
+    }
+    
+    async fn get_alerts(
+        &self,
+        open_only: bool,
+        room: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Alert>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+                    acknowledged_by, acknowledged_at, resolution_reason, outcome
+             FROM alerts
+             WHERE (NOT $1 OR resolved_at IS NULL) AND ($2::text IS NULL OR room_id = $2)
+             ORDER BY triggered_at DESC
+             LIMIT $3",
+            &[&open_only, &room, &limit],
+        ).await?;
+        
+        Ok(rows.iter().filter_map(Self::row_to_alert).collect())
+    }
+    
+    async fn get_alerts_in_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+    ) -> Result<Vec<Alert>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+                    acknowledged_by, acknowledged_at, resolution_reason, outcome
+             FROM alerts
+             WHERE triggered_at >= $1 AND triggered_at < $2 AND ($3::text IS NULL OR room_id = $3)
+             ORDER BY triggered_at, id",
+            &[&start, &end, &room],
+        ).await?;
+        
+        Ok(rows.iter().filter_map(Self::row_to_alert).collect())
+    }
+    
+    async fn get_alert_counts(
+        &self,
+        buckets: &[(NaiveDate, DateTime<Utc>, DateTime<Utc>)],
+        room: Option<&str>,
+    ) -> Result<Vec<AlertCounts>, DbError> {
+        let client = self.client().await?;
+        let days: Vec<NaiveDate> = buckets.iter().map(|(day, _, _)| *day).collect();
+        let starts: Vec<DateTime<Utc>> = buckets.iter().map(|(_, start, _)| *start).collect();
+        let ends: Vec<DateTime<Utc>> = buckets.iter().map(|(_, _, end)| *end).collect();
+        
+        let rows = self.analytics(&client, client.query(
+            "SELECT b.day,
+                    COUNT(a.id) FILTER (WHERE a.alert_type = 'fall'),
+                    COUNT(a.id) FILTER (WHERE a.alert_type = 'inactivity'),
+                    COUNT(a.id) FILTER (WHERE a.alert_type LIKE 'sensor_fault:%')
+             FROM UNNEST($1::date[], $2::timestamptz[], $3::timestamptz[]) AS b(day, start_at, end_at)
+             LEFT JOIN alerts a ON a.triggered_at >= b.start_at AND a.triggered_at < b.end_at
+                               AND ($4::text IS NULL OR a.room_id = $4)
+             GROUP BY b.day
+             ORDER BY b.day",
+            &[&days, &starts, &ends, &room],
+        )).await?;
+        
+        Ok(rows.iter().map(|row| AlertCounts {
+            period: row.get(0),
+            fall_alerts: row.get::<_, i64>(1) as u64,
+            inactivity_alerts: row.get::<_, i64>(2) as u64,
+            sensor_fault_alerts: row.get::<_, i64>(3) as u64,
+        }).collect())
+    }
+    
+    async fn end_snooze(&self, room: &str, alert: AlertType) -> Result<bool, DbError> {
+        let client = self.client().await?;
+        
+        let updated = client.execute(
+            "UPDATE alert_snoozes SET snoozed_until = NOW()
+             WHERE alert_type = $1 AND snoozed_until > NOW()
+               AND reading_id IN (SELECT id FROM sensor_data WHERE deleted_at IS NULL AND room_id = $2)",
+            &[&alert_to_str(alert), &room],
+        ).await?;
+        
+        Ok(updated > 0)
+    }
+    
+    async fn get_active_snoozes(&self) -> Result<Vec<AlertSnooze>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT z.reading_id, z.alert_type, z.snoozed_until, z.snoozed_by, z.reason, s.room_id
+             FROM alert_snoozes z
+             JOIN sensor_data s ON s.id = z.reading_id
+             WHERE z.snoozed_until > NOW()
+             ORDER BY z.snoozed_until",
+            &[],
+        ).await?;
+        
+        Ok(rows.iter().filter_map(|row| Some(AlertSnooze {
+            reading_id: row.get(0),
+            room: row.get::<_, Option<String>>(5).unwrap_or_else(|| DEFAULT_ROOM_ID.to_string()),
+            alert: alert_from_str(row.get(1))?,
+            until: row.get(2),
+            snoozed_by: row.get(3),
+            reason: row.get(4),
+        })).collect())
+    }
+    
+    async fn insert_alert_event(&self, event: &AlertEvent) -> Result<i64, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_one(
+            "INSERT INTO alert_events (event, room_id, alert_type, alert_id, occurred_at)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING seq",
+            &[&event.event, &event.room, &alert_to_str(event.alert), &event.alert_id, &event.at],
+        ).await?;
+        Ok(row.get(0))
+    }
+    
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO audit_log (action, subject, actor, request_id, detail)
+             VALUES ($1, $2, $3, $4, $5)",
+            &[&entry.action, &entry.subject, &entry.actor, &entry.request_id, &entry.detail],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn insert_settings_change(
+        &self,
+        old: &MonitorSettings,
+        new: &MonitorSettings,
+        changed_by: Option<&str>,
+        request_id: Option<&str>,
+    ) -> Result<SettingsChange, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_one(
+            "INSERT INTO settings_history (changed_by, request_id, old_inactivity_seconds, new_inactivity_seconds,
+                                           old_sound_threshold, new_sound_threshold)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id, changed_at, changed_by, request_id, old_inactivity_seconds, new_inactivity_seconds,
+                       old_sound_threshold, new_sound_threshold",
+            &[&changed_by, &request_id, &(old.inactivity_seconds as i64), &(new.inactivity_seconds as i64),
+              &old.sound_threshold, &new.sound_threshold],
+        ).await?;
+        
+        Ok(Self::row_to_settings_change(&row))
+    }
+    
+    async fn get_settings_history(&self, limit: i64) -> Result<Vec<SettingsChange>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, changed_at, changed_by, request_id, old_inactivity_seconds, new_inactivity_seconds,
+                    old_sound_threshold, new_sound_threshold
+             FROM settings_history
+             ORDER BY changed_at DESC, id DESC
+             LIMIT $1",
+            &[&limit],
+        ).await?;
+        
+        Ok(rows.iter().map(Self::row_to_settings_change).collect())
+    }
+    
+    async fn insert_phi_access(&self, access: &PhiAccess) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO phi_access_log
+                (principal, patient_id, endpoint, range_start, range_end, row_count, request_id)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[&access.principal, &access.patient_id, &access.endpoint, &access.range_start,
+              &access.range_end, &(access.row_count as i64), &access.request_id],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_phi_access_log(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        principal: Option<&str>,
+    ) -> Result<Vec<PhiAccessRecord>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT accessed_at, principal, patient_id, endpoint, range_start, range_end,
+                    row_count, request_id
+             FROM phi_access_log
+             WHERE accessed_at BETWEEN $1 AND $2
+               AND ($3::text IS NULL OR principal = $3)
+             ORDER BY accessed_at, id",
+            &[&start, &end, &principal],
+        ).await?;
+        
+        let records = rows.iter().map(|row| PhiAccessRecord {
+            accessed_at: row.get(0),
+            access: PhiAccess {
+                principal: row.get(1),
+                patient_id: row.get(2),
+                endpoint: row.get(3),
+                range_start: row.get(4),
+                range_end: row.get(5),
+                row_count: row.get::<_, i64>(6) as u64,
+                request_id: row.get(7),
+            },
+        }).collect();
+        
+        Ok(records)
+    }
+    
+    async fn get_reading_tags(&self, reading_id: i64) -> Result<Vec<String>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT t.name FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+             WHERE rt.reading_id = $1
+             ORDER BY t.name",
+            &[&reading_id],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+    
+    async fn get_activity_analysis(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<ActivityAnalysis, DbError> {
+        let client = self.client().await?;
+        
+        // Get aggregate statistics. Tags are per reading, so tagged periods
+        // always read the readings themselves.
+        let stats_row = if self.timescale && tag.is_none() {
+            // Whole hours come from the aggregate and the partial hours at
+            // either end from the readings. A period within one hour has no
+            // whole hours; all of it is read from the readings.
+            let first_hour = start.duration_trunc(TimeDelta::hours(1))?;
+            let first_hour = if first_hour < start { first_hour + TimeDelta::hours(1) } else { first_hour };
+            let last_hour = end.duration_trunc(TimeDelta::hours(1))?;
+            let (first_hour, last_hour) = if first_hour <= last_hour { (first_hour, last_hour) } else { (end, end) };
+            
+            self.analytics(&client, client.query_one(
+                &format!("WITH parts AS (
+                    SELECT readings, motion_readings, temperature_sum, sound_sum, max_sound, falls
+                    FROM sensor_hourly
+                    WHERE bucket >= $3 AND bucket < $4
+                      AND ($5::text IS NULL OR room_id = $5)
+                    UNION ALL
+                    SELECT COUNT(*), COUNT(*) FILTER (WHERE motion), SUM(temperature::float8),
+                           SUM(sound_level), MAX(sound_level),
+                           COUNT(*) FILTER (WHERE 'fall' = ANY(alert_types))
+                    FROM {EXPANDED_READINGS}
+                    WHERE deleted_at IS NULL AND ((timestamp >= $1 AND timestamp < $3) OR (timestamp >= $4 AND timestamp <= $2))
+                      AND ($5::text IS NULL OR room_id = $5)
+                 )
+                 SELECT
+                    COALESCE(SUM(readings), 0)::bigint as total,
+                    COALESCE(SUM(motion_readings), 0)::bigint as motion_count,
+                    COALESCE(SUM(temperature_sum) / NULLIF(SUM(readings), 0), 0.0)::float8 as avg_temp,
+                    COALESCE(SUM(sound_sum) / NULLIF(SUM(readings), 0), 0.0)::float8 as avg_sound,
+                    COALESCE(MAX(max_sound), 0) as max_sound,
+                    COALESCE(SUM(falls), 0)::bigint as falls
+                 FROM parts"),
+                &[&start, &end, &first_hour, &last_hour, &room],
+            )).await?
+        } else {
+            self.analytics(&client, client.query_one(
+                &format!("SELECT 
+                    COUNT(*) as total,
+                    COUNT(*) FILTER (WHERE motion = true) as motion_count,
+                    COALESCE(AVG(temperature), 0.0::float) as avg_temp,
+                    COALESCE(AVG(sound_level), 0.0::float) as avg_sound,
+                    COALESCE(MAX(sound_level), 0) as max_sound,
+                    COUNT(*) FILTER (WHERE 'fall' = ANY(alert_types)) as falls
+                 FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp BETWEEN $1 AND $2
+                   AND ($3::text IS NULL OR id IN (
+                     SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                     WHERE t.name = $3))
+                   AND ($4::text IS NULL OR room_id = $4)"),
+                &[&start, &end, &tag, &room],
+            )).await?
+        };
+        
+        let total: i64 = stats_row.get(0);
+        let motion_count: i64 = stats_row.get(1);
+        let avg_temp: f64 = stats_row.get(2);
+        let avg_sound: f64 = stats_row.get(3);
+        let max_sound: i32 = stats_row.get(4);
+        let falls: i64 = stats_row.get(5);
+        
+        // Calculate activity score (0-100)
+        let activity_score = activity_score(motion_count as u64, total as u64);
+        
+        let (still_periods, longest_still_periods) = self.calculate_still_periods(start, end, room, tag).await?;
+        
+        Ok(ActivityAnalysis {
+            period_start: start.to_rfc3339(),
+            period_end: end.to_rfc3339(),
+            total_readings: total as u64,
+            motion_readings: motion_count as u64,
+            activity_score: (activity_score * 100.0).round() / 100.0,
+            activity_level: activity_level(activity_score).to_string(),
+            avg_temperature: (avg_temp * 100.0).round() / 100.0,
+            avg_sound_level: (avg_sound * 100.0).round() / 100.0,
+            max_sound_level: max_sound,
+            fall_alerts: falls as u64,
+            longest_still_period_mins: longest_still_periods.first().map_or(0, |p| p.duration_mins),
+            still_periods,
+            longest_still_periods,
+            filled_readings: 0,
+            night_awakenings: None,
+            night_light: None,
+        })
+    }
+    
+    async fn count_night_awakenings(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let rows = self.analytics(&client, client.query(
+            &format!("SELECT timestamp, motion, room_id FROM {EXPANDED_READINGS}
+             WHERE deleted_at IS NULL AND timestamp BETWEEN $1 AND $2 
+               AND ($3::text IS NULL OR id IN (
+                 SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                 WHERE t.name = $3))
+               AND ($4::text IS NULL OR room_id = $4)
+             ORDER BY room_id, timestamp ASC"),
+            &[&start, &end, &tag, &room],
+        )).await?;
+        
+        Ok(rows
+            .chunk_by(|a, b| a.get::<_, Option<String>>(2) == b.get::<_, Option<String>>(2))
+            .map(|rows| count_night_awakenings(rows.iter().map(|row| (row.get(0), row.get(1)))))
+            .sum())
+    }
+    
+    async fn get_light_sums(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<LightSums, DbError> {
+        let client = self.client().await?;
+        
+        let row = self.analytics(&client, client.query_one(
+            &format!("SELECT COUNT(*),
+                    COUNT(*) FILTER (WHERE light >= $5),
+                    COUNT(*) FILTER (WHERE motion),
+                    COUNT(*) FILTER (WHERE motion AND light >= $5),
+                    COALESCE(SUM(light::float8), 0.0),
+                    COALESCE(SUM(light::float8 * light::float8), 0.0),
+                    COALESCE(SUM(light::float8) FILTER (WHERE motion), 0.0)
+             FROM {EXPANDED_READINGS}
+             WHERE deleted_at IS NULL AND light IS NOT NULL AND timestamp BETWEEN $1 AND $2
+               AND ($3::text IS NULL OR id IN (
+                 SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                 WHERE t.name = $3))
+               AND ($4::text IS NULL OR room_id = $4)"),
+            &[&start, &end, &tag, &room, &LIGHTS_ON_LUX],
+        )).await?;
+        
+        Ok(LightSums {
+            readings: row.get::<_, i64>(0) as u64,
+            lit_readings: row.get::<_, i64>(1) as u64,
+            motion_readings: row.get::<_, i64>(2) as u64,
+            lit_motion_readings: row.get::<_, i64>(3) as u64,
+            lux: row.get(4),
+            lux_squared: row.get(5),
+            motion_lux: row.get(6),
+        })
+    }
+    
+    async fn get_fall_threshold_sweep(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        thresholds: std::ops::RangeInclusive<i32>,
+        step: i32,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Vec<ThresholdPoint>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = self.analytics(&client, client.query(
+            &format!("WITH readings AS (
+                SELECT motion, sound_level,
+                       LAG(motion) OVER w AS prev_motion,
+                       LAG(sound_level) OVER w AS prev_sound
+                FROM {EXPANDED_READINGS}
+                WHERE deleted_at IS NULL AND timestamp BETWEEN $1 AND $2
+                  AND ($6::text IS NULL OR id IN (
+                    SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                    WHERE t.name = $6))
+                  AND ($7::text IS NULL OR room_id = $7)
+                WINDOW w AS (PARTITION BY room_id ORDER BY timestamp)
+             )
+             SELECT t, COUNT(r.sound_level) AS alerts
+             FROM generate_series($3::int, $4::int, $5::int) AS t
+             LEFT JOIN readings r
+                ON r.motion AND r.sound_level > t
+               AND NOT (COALESCE(r.prev_motion, false) AND COALESCE(r.prev_sound, 0) > t)
+             GROUP BY t
+             ORDER BY t"),
+            &[&start, &end, thresholds.start(), thresholds.end(), &step, &tag, &room],
+        )).await?;
+        
+        Ok(rows.iter().map(|row| {
+            let alerts: i64 = row.get(1);
+            ThresholdPoint {
+                threshold: row.get(0),
+                fall_alerts: alerts as u64,
+            }
+        }).collect())
+    }
+    
+    async fn get_night_noise_minutes(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        days: &FacilityDays,
+        quiet: &QuietHours,
+        room: &str,
+    ) -> Result<Vec<NightNoiseWeek>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = self.analytics(&client, client.query(
+            &format!("WITH minutes AS (
+                SELECT date_trunc('minute', timestamp AT TIME ZONE 'UTC') AS minute,
+                       MAX(sound_level) AS peak
+                FROM {EXPANDED_READINGS}
+                WHERE deleted_at IS NULL AND timestamp >= $1 AND timestamp < $2 AND room_id = $6
+                  AND CASE WHEN $3::int <= $4::int
+                      THEN EXTRACT(HOUR FROM timestamp AT TIME ZONE $7::text)::int >= $3
+                       AND EXTRACT(HOUR FROM timestamp AT TIME ZONE $7::text)::int < $4
+                      ELSE EXTRACT(HOUR FROM timestamp AT TIME ZONE $7::text)::int >= $3
+                        OR EXTRACT(HOUR FROM timestamp AT TIME ZONE $7::text)::int < $4
+                  END
+                GROUP BY 1
+             )
+             SELECT (date_trunc('week', ((minute AT TIME ZONE 'UTC') AT TIME ZONE $7::text) - make_interval(hours => $8::int))
+                     + make_interval(hours => $8::int)) AT TIME ZONE $7::text AS week,
+                    COUNT(*) AS observed,
+                    COUNT(*) FILTER (WHERE peak > $5) AS noisy
+             FROM minutes
+             GROUP BY 1
+             ORDER BY 1"),
+            &[&start, &end, &(quiet.start_hour as i32), &(quiet.end_hour as i32), &quiet.noise_limit, &room,
+              &days.timezone.name(), &(days.start_hour as i32)],
+        )).await?;
+        
+        Ok(rows.iter().map(|row| {
+            let observed: i64 = row.get(1);
+            let noisy: i64 = row.get(2);
+            NightNoiseWeek {
+                week_start: row.get(0),
+                observed_minutes: observed as u64,
+                noisy_minutes: noisy as u64,
+            }
+        }).collect())
+    }
+    
+    async fn count_falls(
+        &self,
+        room: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<u64, DbError> {
+        let client = self.client().await?;
+        
+        let row = self.analytics(&client, client.query_one(
+            "SELECT COUNT(*) FROM (
+                SELECT 'fall' = ANY(alert_types) AS fall,
+                       LAG('fall' = ANY(alert_types)) OVER (ORDER BY timestamp) AS prev_fall
+                FROM sensor_data
+                WHERE deleted_at IS NULL AND room_id = $1 AND timestamp >= $2 AND timestamp < $3
+             ) r
+             WHERE fall AND NOT COALESCE(prev_fall, false)",
+            &[&room, &start, &end],
+        )).await?;
+        
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+    
+    async fn get_night_motion_minutes(
+        &self,
+        room: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        days: &FacilityDays,
+        quiet: &QuietHours,
+    ) -> Result<Vec<(DateTime<Utc>, bool)>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = self.analytics(&client, client.query(
+            &format!("SELECT date_trunc('minute', timestamp) AS minute, BOOL_OR(motion)
+             FROM {EXPANDED_READINGS}
+             WHERE deleted_at IS NULL AND room_id = $3 AND timestamp >= $1 AND timestamp < $2
+               AND CASE WHEN $4::int <= $5::int
+                   THEN EXTRACT(HOUR FROM timestamp AT TIME ZONE $6::text)::int >= $4
+                    AND EXTRACT(HOUR FROM timestamp AT TIME ZONE $6::text)::int < $5
+                   ELSE EXTRACT(HOUR FROM timestamp AT TIME ZONE $6::text)::int >= $4
+                     OR EXTRACT(HOUR FROM timestamp AT TIME ZONE $6::text)::int < $5
+               END
+             GROUP BY 1
+             ORDER BY 1"),
+            &[&start, &end, &room, &(quiet.start_hour as i32), &(quiet.end_hour as i32), &days.timezone.name()],
+        )).await?;
+        
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+    
+    async fn upsert_fall_risk(&self, risk: &FallRiskScore) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        client.execute(
+            "INSERT INTO fall_risk_scores
+                (patient_id, day, score, level, window_days, falls, night_restlessness, bed_exits_per_night)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (patient_id, day) DO UPDATE SET
+                score = EXCLUDED.score, level = EXCLUDED.level, window_days = EXCLUDED.window_days,
+                falls = EXCLUDED.falls, night_restlessness = EXCLUDED.night_restlessness,
+                bed_exits_per_night = EXCLUDED.bed_exits_per_night, computed_at = NOW()",
+            &[&risk.patient_id, &risk.date, &risk.score, &risk.level, &(risk.window_days as i32),
+              &(risk.factors.falls as i64), &risk.factors.night_restlessness,
+              &risk.factors.bed_exits_per_night],
+        ).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_fall_risk_history(
+        &self,
+        patient_id: &str,
+        since: NaiveDate,
+    ) -> Result<Vec<FallRiskScore>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT patient_id, day, score, level, window_days, falls, night_restlessness,
+                    bed_exits_per_night
+             FROM fall_risk_scores
+             WHERE patient_id = $1 AND day >= $2
+             ORDER BY day DESC",
+            &[&patient_id, &since],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| FallRiskScore {
+            patient_id: row.get(0),
+            date: row.get(1),
+            score: row.get(2),
+            level: row.get(3),
+            window_days: row.get::<_, i32>(4) as u32,
+            factors: FallRiskFactors {
+                falls: row.get::<_, i64>(5) as u64,
+                night_restlessness: row.get(6),
+                bed_exits_per_night: row.get(7),
+            },
+        }).collect())
+    }
+    
+    async fn insert_manual_observation(
+        &self,
+        observation: &ManualObservation,
+    ) -> Result<ManualObservation, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_one(
+            "INSERT INTO manual_observations
+                (room_id, patient_id, kind, value, observed_at, recorded_by, note)
+             VALUES ($1::VARCHAR, (SELECT id FROM patients WHERE room_id = $1::VARCHAR), $2, $3, $4, $5, $6)
+             RETURNING id, patient_id",
+            &[&observation.room, &observation.kind.as_str(), &observation.value,
+              &observation.observed_at, &observation.recorded_by, &observation.note],
+        ).await?;
+        
+        Ok(ManualObservation {
+            id: Some(row.get(0)),
+            patient_id: row.get(1),
+            ..observation.clone()
+        })
+    }
+    
+    async fn get_manual_observations(
+        &self,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        room: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<Vec<ManualObservation>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT id, room_id, patient_id, kind, value, observed_at, recorded_by, note
+             FROM manual_observations
+             WHERE ($1::timestamptz IS NULL OR observed_at >= $1)
+               AND ($2::timestamptz IS NULL OR observed_at <= $2)
+               AND ($3::text IS NULL OR room_id = $3)
+             ORDER BY observed_at DESC, id DESC
+             LIMIT $4",
+            &[&start, &end, &room, &limit],
+        ).await?;
+        
+        Ok(rows.iter().filter_map(Self::row_to_manual_observation).collect())
+    }
+    
+    async fn compute_daily_summaries(
+        &self,
+        day: NaiveDate,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<DailySummary>, DbError> {
+        let client = self.client().await?;
+        
+        let stats = self.analytics(&client, client.query(
+            &format!("WITH raised AS (
+                SELECT room_id,
+                       COUNT(*) FILTER (WHERE alert_type = 'fall') AS falls,
+                       COUNT(*) FILTER (WHERE alert_type = 'inactivity') AS inactivity,
+                       COUNT(*) FILTER (WHERE alert_type LIKE 'sensor_fault:%') AS sensor_faults
+                FROM alerts
+                WHERE triggered_at >= $1 AND triggered_at < $2
+                GROUP BY room_id
+             )
+             SELECT s.room_id, COUNT(*), COUNT(*) FILTER (WHERE s.motion),
+                    AVG(s.temperature)::float8, MIN(s.temperature), MAX(s.temperature),
+                    COALESCE(MAX(r.falls), 0), COALESCE(MAX(r.inactivity), 0),
+                    COALESCE(MAX(r.sensor_faults), 0)
+             FROM (SELECT * FROM {EXPANDED_READINGS}) s
+             LEFT JOIN raised r ON r.room_id = s.room_id
+             WHERE s.deleted_at IS NULL AND s.timestamp >= $1 AND s.timestamp < $2 AND s.room_id IS NOT NULL
+             GROUP BY s.room_id
+             ORDER BY s.room_id"),
+            &[&start, &end],
+        )).await?;
+        
+        // A still period at the end of the day lasts until the day ends, or until
+        // now for a day that isn't over
+        let still_until = end.min(Utc::now());
+        let still = self.analytics(&client, client.query(
+            &format!("WITH readings AS (
+                SELECT room_id, timestamp, id, motion,
+                       motion IS DISTINCT FROM LAG(motion) OVER (PARTITION BY room_id ORDER BY timestamp, id)
+                           AS starts_run
+                FROM {EXPANDED_READINGS}
+                WHERE deleted_at IS NULL AND timestamp >= $1 AND timestamp < $2 AND room_id IS NOT NULL
+             ),
+             runs AS (
+                SELECT room_id, motion,
+                       COALESCE(LEAD(timestamp) OVER (PARTITION BY room_id ORDER BY timestamp, id), $3)
+                           - timestamp AS length
+                FROM readings
+                WHERE starts_run
+             )
+             SELECT room_id, GREATEST(FLOOR(EXTRACT(EPOCH FROM MAX(length)) / 60), 0)::bigint
+             FROM runs
+             WHERE NOT motion
+             GROUP BY room_id"),
+            &[&start, &end, &still_until],
+        )).await?;
+        let longest_still: HashMap<String, u64> = still
+            .iter()
+            .map(|row| (row.get(0), row.get::<_, i64>(1) as u64))
+            .collect();
+        
+        Ok(stats.iter().map(|row| {
+            let room: String = row.get(0);
+            let readings = row.get::<_, i64>(1) as u64;
+            let motion_readings = row.get::<_, i64>(2) as u64;
+            DailySummary {
+                longest_still_period_mins: longest_still.get(&room).copied().unwrap_or(0),
+                room,
+                date: day,
+                readings,
+                motion_readings,
+                motion_percent: (activity_score(motion_readings, readings) * 100.0).round() / 100.0,
+                avg_temperature: row.get(3),
+                min_temperature: row.get(4),
+                max_temperature: row.get(5),
+                fall_alerts: row.get::<_, i64>(6) as u64,
+                inactivity_alerts: row.get::<_, i64>(7) as u64,
+                sensor_fault_alerts: row.get::<_, i64>(8) as u64,
+            }
+        }).collect())
+    }
+    
+    async fn upsert_daily_summaries(&self, summaries: &[DailySummary]) -> Result<(), DbError> {
+        let client = self.client().await?;
+        
+        for s in summaries {
+            client.execute(
+                "INSERT INTO daily_summary
+                    (room_id, day, readings, motion_readings, avg_temperature, min_temperature,
+                     max_temperature, fall_alerts, inactivity_alerts, sensor_fault_alerts,
+                     longest_still_period_mins)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                 ON CONFLICT (room_id, day) DO UPDATE SET
+                    readings = EXCLUDED.readings, motion_readings = EXCLUDED.motion_readings,
+                    avg_temperature = EXCLUDED.avg_temperature,
+                    min_temperature = EXCLUDED.min_temperature,
+                    max_temperature = EXCLUDED.max_temperature,
+                    fall_alerts = EXCLUDED.fall_alerts, inactivity_alerts = EXCLUDED.inactivity_alerts,
+                    sensor_fault_alerts = EXCLUDED.sensor_fault_alerts,
+                    longest_still_period_mins = EXCLUDED.longest_still_period_mins,
+                    computed_at = NOW()",
+                &[&s.room, &s.date, &(s.readings as i64), &(s.motion_readings as i64),
+                  &s.avg_temperature, &s.min_temperature, &s.max_temperature,
+                  &(s.fall_alerts as i64), &(s.inactivity_alerts as i64),
+                  &(s.sensor_fault_alerts as i64), &(s.longest_still_period_mins as i64)],
+            ).await?;
+        }
+        
+        Ok(())
+    }
+    
+    async fn last_daily_summary_day(&self) -> Result<Option<NaiveDate>, DbError> {
+        let client = self.client().await?;
+        
+        let row = client.query_one("SELECT MAX(day) FROM daily_summary", &[]).await?;
+        Ok(row.get(0))
+    }
+    
+    async fn get_daily_summaries(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        room: Option<&str>,
+    ) -> Result<Vec<DailySummary>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = client.query(
+            "SELECT room_id, day, readings, motion_readings, avg_temperature, min_temperature,
+                    max_temperature, fall_alerts, inactivity_alerts, sensor_fault_alerts,
+                    longest_still_period_mins
+             FROM daily_summary
+             WHERE day BETWEEN $1 AND $2
+               AND ($3::text IS NULL OR room_id = $3)
+             ORDER BY day, room_id",
+            &[&from, &to, &room],
+        ).await?;
+        
+        Ok(rows.iter().map(|row| {
+            let readings = row.get::<_, i64>(2) as u64;
+            let motion_readings = row.get::<_, i64>(3) as u64;
+            DailySummary {
+                room: row.get(0),
+                date: row.get(1),
+                readings,
+                motion_readings,
+                motion_percent: (activity_score(motion_readings, readings) * 100.0).round() / 100.0,
+                avg_temperature: row.get(4),
+                min_temperature: row.get(5),
+                max_temperature: row.get(6),
+                fall_alerts: row.get::<_, i64>(7) as u64,
+                inactivity_alerts: row.get::<_, i64>(8) as u64,
+                sensor_fault_alerts: row.get::<_, i64>(9) as u64,
+                longest_still_period_mins: row.get::<_, i64>(10) as u64,
+            }
+        }).collect())
+    }
+    
+    async fn research_aggregates(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        by_ward: bool,
+        by_day: Option<&FacilityDays>,
+    ) -> Result<Vec<ResearchGroup>, DbError> {
+        let client = self.client().await?;
+        // Facility day of a reading: its local date, counting the hours
+        // before the day starts towards the day before
+        let timezone = by_day.map(|days| days.timezone.name());
+        let start_hour = by_day.map_or(0, |days| days.start_hour as i32);
+        
+        let rows = self.analytics(&client, client.query(
+            &format!("WITH raised AS (
+                SELECT reading_id,
+                       COUNT(*) FILTER (WHERE alert_type = 'fall') AS falls,
+                       COUNT(*) FILTER (WHERE alert_type = 'inactivity') AS inactivity
+                FROM alerts
+                WHERE triggered_at >= $1 AND triggered_at < $2 AND reading_id IS NOT NULL
+                GROUP BY reading_id
+             )
+             SELECT CASE WHEN $3 THEN r.ward_id END AS ward,
+                    ((s.timestamp AT TIME ZONE $4::text) - make_interval(hours => $5::int))::date AS day,
+                    COUNT(DISTINCT s.patient_id), COUNT(*), COUNT(*) FILTER (WHERE s.motion),
+                    AVG(s.temperature)::float8,
+                    COALESCE(SUM(a.falls), 0)::bigint, COALESCE(SUM(a.inactivity), 0)::bigint
+             FROM (SELECT * FROM {EXPANDED_READINGS}) s
+             JOIN patient_consent c ON c.patient_id = s.patient_id AND c.research_export
+             LEFT JOIN rooms r ON r.id = s.room_id
+             LEFT JOIN raised a ON a.reading_id = s.id
+             WHERE s.deleted_at IS NULL AND s.timestamp >= $1 AND s.timestamp < $2
+             GROUP BY 1, 2
+             ORDER BY 2 NULLS FIRST, 1 NULLS FIRST"),
+            &[&start, &end, &by_ward, &timezone, &start_hour],
+        )).await?;
+        
+        Ok(rows.iter().map(|row| {
+            let readings = row.get::<_, i64>(3) as u64;
+            let motion_readings = row.get::<_, i64>(4) as u64;
+            ResearchGroup {
+                ward: row.get(0),
+                date: row.get(1),
+                patients: row.get::<_, i64>(2) as u64,
+                readings,
+                motion_percent: activity_score(motion_readings, readings),
+                avg_temperature: row.get(5),
+                fall_alerts: row.get::<_, i64>(6) as u64,
+                inactivity_alerts: row.get::<_, i64>(7) as u64,
+            }
+        }).collect())
+    }
+    
+    async fn get_hourly_activity(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        timezone: Tz,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Vec<HourlyActivity>, DbError> {
+        let client = self.client().await?;
+        
+        let rows = if self.timescale && tag.is_none() {
+            self.analytics(&client, client.query(
+                "SELECT
+                    bucket as hour,
+                    SUM(readings)::bigint as total,
+                    SUM(motion_readings)::bigint as motion_count,
+                    COALESCE(SUM(sound_sum) / NULLIF(SUM(readings), 0), 0.0)::float8 as avg_sound,
+                    (SUM(light_sum) / NULLIF(SUM(light_readings), 0))::float8 as avg_light
+                 FROM sensor_hourly
+                 WHERE bucket >= $1 AND bucket < $2
+                   AND ($3::text IS NULL OR room_id = $3)
+                 GROUP BY bucket
+                 ORDER BY hour",
+                &[&start, &end, &room],
+            )).await?
+        } else {
+            self.analytics(&client, client.query(
+                &format!("SELECT 
+                    DATE_TRUNC('hour', timestamp) as hour,
+                    COUNT(*) as total,
+                    COUNT(*) FILTER (WHERE motion = true) as motion_count,
+                    COALESCE(AVG(sound_level), 0.0::float) as avg_sound,
+                    AVG(light)::float8 as avg_light
+                 FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp >= $1 AND timestamp < $2
+                   AND ($3::text IS NULL OR id IN (
+                     SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                     WHERE t.name = $3))
+                   AND ($4::text IS NULL OR room_id = $4)
+                 GROUP BY DATE_TRUNC('hour', timestamp)
+                 ORDER BY hour"),
+                &[&start, &end, &tag, &room],
+            )).await?
+        };
+        
+        let mut hourly = Vec::new();
+        for row in rows {
+            let hour: DateTime<Utc> = row.get(0);
+            let total: i64 = row.get(1);
+            let motion_count: i64 = row.get(2);
+            let avg_sound: f64 = row.get(3);
+            let avg_light: Option<f64> = row.get(4);
+            
+            let activity_score = activity_score(motion_count as u64, total as u64);
+            
+            hourly.push(HourlyActivity {
+                hour: hour.with_timezone(&timezone).format("%H:00").to_string(),
+                activity_score: (activity_score * 100.0).round() / 100.0,
+                readings: total as u64,
+                avg_sound_level: (avg_sound * 100.0).round() / 100.0,
+                filled_readings: 0,
+                avg_light: avg_light.map(|lux| (lux * 10.0).round() / 10.0),
+            });
+        }
+        
+        Ok(hourly)
+    }
+}
+
+/// These run against the PostgreSQL server the monitor is configured for
+/// (`DB_HOST`, `DB_PORT`, `DB_USER`, `DB_PASSWORD`) when `TEST_POSTGRES=true`,
+/// each in a database of its own, and pass trivially otherwise.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+    use patient_monitor_types::analysis::compaction_runs;
+
+    async fn database(partitioned: bool) -> Option<Postgres> {
+        if std::env::var("TEST_POSTGRES").as_deref() != Ok("true") {
+            return None;
+        }
+        let mut config = DbConfig::from_env();
+        let (client, connection) = tokio_postgres::connect(
+            &format!("host={} port={} user={} password={} dbname=postgres",
+                config.host, config.port, config.user, config.password),
+            NoTls,
+        ).await.expect("TEST_POSTGRES server");
+        tokio::spawn(connection);
+        let name = format!("monitor_test_{}", uuid::Uuid::new_v4().simple());
+        client.batch_execute(&format!("CREATE DATABASE {name}")).await.unwrap();
+
+        config.backend = BackendKind::Postgres;
+        config.dbname = name;
+        config.ssl_mode = SslMode::Disable;
+        config.timescale = false;
+        config.partitioned = partitioned;
+        config.auto_migrate = true;
+        let db = Postgres::connect(config).await.unwrap();
+        db.register_rooms(&["room-101".to_string()]).await.unwrap();
+        Some(db)
+    }
+
+    fn event(timestamp: DateTime<Utc>) -> SensorEvent {
+        SensorEvent {
+            id: None,
+            seq: None,
+            room: "room-101".to_string(),
+            device: None,
+            patient_id: None,
+            reading: SensorReading { temperature: 22.0, sound_level: 30, timestamp, ..Default::default() },
+            alerts: AlertSet::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reading_beyond_partitions_gets_its_month() {
+        let Some(db) = database(true).await else { return };
+        let now = Utc::now();
+        let skewed = now + TimeDelta::days(400);
+
+        let stored = db.insert_readings_batch(&[event(now), event(skewed)]).await.unwrap();
+        assert_eq!(stored.len(), 2);
+        let client = db.client().await.unwrap();
+        assert!(partitions(&client).await.unwrap().contains(&partition_name(month_start(skewed))));
+
+        // The skewed month doesn't stop the months before it being created
+        let later = now + TimeDelta::days(70);
+        db.maintain_partitions(later).await.unwrap();
+        let names = partitions(&client).await.unwrap();
+        let ahead = month_start(later) + Months::new(PARTITION_MONTHS_AHEAD);
+        assert!(names.iter().any(|name| covers(name, ahead)), "{:?}", names);
+    }
+
+    #[tokio::test]
+    async fn test_merging_runs_keeps_the_summary() {
+        let Some(db) = database(false).await else { return };
+        let start = Utc::now().duration_trunc(TimeDelta::hours(1)).unwrap() - TimeDelta::days(1);
+        let still: Vec<SensorEvent> = (0..4).map(|m| event(start + TimeDelta::minutes(m))).collect();
+        db.insert_readings_batch(&still).await.unwrap();
+        let before = db.get_alert_summary().await.unwrap();
+
+        let end = start + TimeDelta::minutes(10);
+        let rows = db.get_compaction_rows("room-101", start, end).await.unwrap();
+        let runs = compaction_runs(&rows, TimeDelta::minutes(5), TimeDelta::hours(1));
+        assert_eq!(db.merge_runs(&runs).await.unwrap(), 3);
+
+        assert_eq!(db.get_alert_summary().await.unwrap().total_readings, before.total_readings);
+        assert_eq!(db.count_readings_in_range(start, end, None).await.unwrap(), 4);
+
+        // Purging the interval takes all its readings out of the totals
+        assert_eq!(db.delete_readings(&[runs[0].id]).await.unwrap(), 1);
+        assert_eq!(db.get_alert_summary().await.unwrap().total_readings, before.total_readings - 4);
+    }
+
+    #[tokio::test]
+    async fn test_aggregates_count_merged_readings() {
+        let Some(db) = database(false).await else { return };
+        let patient = Patient {
+            id: "patient-1".to_string(),
+            family_name: "Doe".to_string(),
+            given_names: Vec::new(),
+            birth_date: None,
+            gender: None,
+            room: Some("room-101".to_string()),
+        };
+        db.insert_patient(&patient).await.unwrap();
+        let consent = Consent { continuous_monitoring: true, ehr_sharing: false, research_export: true, updated_at: None };
+        db.set_consent("patient-1", &consent).await.unwrap();
+        let start = Utc::now().duration_trunc(TimeDelta::hours(1)).unwrap() - TimeDelta::days(1);
+        let still: Vec<SensorEvent> = (0..4)
+            .map(|m| {
+                let mut event = event(start + TimeDelta::minutes(m));
+                event.reading.light = Some(120.0);
+                event
+            })
+            .collect();
+        db.insert_readings_batch(&still).await.unwrap();
+        let rows = db.get_compaction_rows("room-101", start, start + TimeDelta::minutes(10)).await.unwrap();
+        let runs = compaction_runs(&rows, TimeDelta::minutes(5), TimeDelta::hours(1));
+        assert_eq!(db.merge_runs(&runs).await.unwrap(), 3);
+
+        let end = start + TimeDelta::minutes(30);
+        let activity = db.get_activity_analysis(start, end, None, None).await.unwrap();
+        assert_eq!(activity.total_readings, 4);
+        assert_eq!(activity.avg_temperature, 22.0);
+        assert_eq!(activity.longest_still_period_mins, 30);
+        let hourly = db.get_hourly_activity(start, end, chrono_tz::UTC, None, None).await.unwrap();
+        assert_eq!(hourly.iter().map(|h| h.readings).sum::<u64>(), 4);
+        assert_eq!(db.get_light_sums(start, end, None, None).await.unwrap().readings, 4);
+        assert_eq!(db.count_night_awakenings(start, end, None, None).await.unwrap(), 0);
+        let sweep = db.get_fall_threshold_sweep(start, end, 20..=40, 10, None, None).await.unwrap();
+        assert!(sweep.iter().all(|point| point.fall_alerts == 0));
+
+        let day_end = start + TimeDelta::hours(1);
+        let summaries = db.compute_daily_summaries(start.date_naive(), start, day_end).await.unwrap();
+        assert_eq!(summaries.iter().map(|s| s.readings).sum::<u64>(), 4);
+        let research = db.research_aggregates(start, end, false, None).await.unwrap();
+        assert_eq!(research.iter().map(|g| g.readings).sum::<u64>(), 4);
+
+        // Each merged reading keeps its quiet-hours minute
+        let hour = start.hour();
+        let (days, quiet) = (FacilityDays::default(), QuietHours { start_hour: hour, end_hour: (hour + 1) % 24, noise_limit: 60 });
+        assert_eq!(db.get_night_motion_minutes("room-101", start, end, &days, &quiet).await.unwrap().len(), 4);
+        let noise = db.get_night_noise_minutes(start, end, &days, &quiet, "room-101").await.unwrap();
+        assert_eq!(noise.iter().map(|w| w.observed_minutes).sum::<u64>(), 4);
+    }
+}
@@ -0,0 +1,3010 @@
+//! SQLite storage for small single-node deployments, e.g. a bedside
+//! Raspberry Pi, selected with `DB_BACKEND=sqlite`
+//!
+//! The database is one file (`SQLITE_PATH`) in WAL mode. Writes go through a
+//! single connection on the blocking thread pool, so readings commit in id
+//! order and the id doubles as the change-feed position. Analytics queries
+//! run on their own connection, so a long report doesn't hold up ingest, and
+//! are interrupted when abandoned or past the analytics timeout.
+//!
+//! Timestamps are stored as fixed-width UTC text (see `Ts`), which sorts in
+//! time order; alert types as a comma-separated list; given names and other
+//! lists as JSON arrays.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration as ChronoDuration, DurationRound, NaiveDate, Timelike, Utc};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, Type, ValueRef};
+use rusqlite::{params, Connection, ErrorCode, InterruptHandle, OptionalExtension, Row};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::*;
+use crate::fhir::{AlertSet, AlertType, ManualObservation, Patient, SensorEvent, SensorReading, DEFAULT_ROOM_ID};
+use patient_monitor_types::analysis::{activity_level, activity_score, count_night_awakenings, LIGHTS_ON_LUX};
+use crate::rules::{AlertRule, ShadowHit};
+
+mod embedded {
+    refinery::embed_migrations!("migrations/sqlite");
+}
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6fZ";
+
+/// How long a statement waits for another connection's write lock
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stand-in for `sensor_data` in queries of the readings from ?1 to ?2, with
+/// the intervals left by compaction expanded into `run_count` readings evenly
+/// spaced from the first to `run_until` (to the millisecond). Intervals span
+/// at most an hour (`compaction::MAX_SPAN`), so those reaching into the range
+/// are found by their start.
+const EXPANDED_READINGS: &str = "(
+    WITH RECURSIVE runs(n, id, timestamp, run_until, run_count, temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration,
+                        alert_types, room_id, patient_id, deleted_at) AS (
+        SELECT 0, id, timestamp, run_until, run_count, temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration,
+               alert_types, room_id, patient_id, deleted_at
+        FROM sensor_data
+        WHERE timestamp BETWEEN strftime('%Y-%m-%dT%H:%M:%f000Z', ?1, '-1 hour') AND ?2
+          AND COALESCE(run_until, timestamp) >= ?1
+        UNION ALL
+        SELECT n + 1, id, timestamp, run_until, run_count, temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration,
+               alert_types, room_id, patient_id, deleted_at
+        FROM runs WHERE n + 1 < run_count
+    )
+    SELECT id, room_id, patient_id, temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration, alert_types, deleted_at,
+           CASE WHEN n = 0 THEN timestamp
+                ELSE strftime('%Y-%m-%dT%H:%M:%f000Z',
+                    julianday(timestamp) + (julianday(run_until) - julianday(timestamp)) * n / (run_count - 1)) END
+               AS timestamp
+    FROM runs
+) AS sensor_data";
+
+/// Whole minutes of a run of readings from `started` to `ended`, rounded to
+/// the millisecond first so that floating-point error doesn't cost a minute
+const RUN_MINUTES: &str = "MAX(CAST(ROUND((julianday(ended) - julianday(started)) * 86400000) AS INTEGER) / 60000, 0)";
+
+/// Bucket of a still period lasting `mins` minutes, as `StillPeriods::bucket_of`
+fn still_bucket() -> String {
+    STILL_PERIOD_BUCKETS.iter().map(|b| format!("(mins >= {})", b)).collect::<Vec<_>>().join(" + ")
+}
+
+/// Timestamp as stored: RFC 3339 UTC with microseconds, always the same
+/// width so that text comparison and ordering follow time
+struct Ts(DateTime<Utc>);
+
+impl ToSql for Ts {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0.format(TIMESTAMP_FORMAT).to_string()))
+    }
+}
+
+impl FromSql for Ts {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        DateTime::parse_from_rfc3339(value.as_str()?)
+            .map(|t| Ts(t.with_timezone(&Utc)))
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+fn time(row: &Row, idx: usize) -> rusqlite::Result<DateTime<Utc>> {
+    Ok(row.get::<_, Ts>(idx)?.0)
+}
+
+fn opt_time(row: &Row, idx: usize) -> rusqlite::Result<Option<DateTime<Utc>>> {
+    Ok(row.get::<_, Option<Ts>>(idx)?.map(|t| t.0))
+}
+
+fn date(row: &Row, idx: usize) -> rusqlite::Result<Option<NaiveDate>> {
+    row.get::<_, Option<String>>(idx)?
+        .map(|d| d.parse().map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e))))
+        .transpose()
+}
+
+fn json<T: serde::de::DeserializeOwned>(row: &Row, idx: usize) -> rusqlite::Result<T> {
+    serde_json::from_str(&row.get::<_, String>(idx)?)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e)))
+}
+
+fn alerts_to_str(alerts: &AlertSet) -> String {
+    alerts.iter().map(alert_to_str).collect::<Vec<_>>().join(",")
+}
+
+fn alerts_from_str(s: &str) -> AlertSet {
+    s.split(',').filter_map(alert_from_str).collect()
+}
+
+/// Interrupts the statement running on an analytics connection if dropped
+/// while armed, like `CancelOnDrop` does for Postgres
+struct InterruptOnDrop(Option<InterruptHandle>);
+
+impl InterruptOnDrop {
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for InterruptOnDrop {
+    fn drop(&mut self) {
+        if let Some(handle) = self.0.take() {
+            handle.interrupt();
+            debug!("Interrupted abandoned analytics query");
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        match &e {
+            rusqlite::Error::QueryReturnedNoRows => DbError::NotFound(e.to_string()),
+            rusqlite::Error::SqliteFailure(failure, _) => match failure.code {
+                ErrorCode::ConstraintViolation => DbError::Constraint(e.to_string()),
+                // Another process holds the file longer than the busy timeout
+                ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked | ErrorCode::CannotOpen => {
+                    DbError::Unavailable(e.to_string())
+                }
+                _ => DbError::Other(Box::new(e)),
+            },
+            _ => DbError::Other(Box::new(e)),
+        }
+    }
+}
+
+pub struct Sqlite {
+    conn: Arc<Mutex<Connection>>,
+    path: PathBuf,
+    analytics_timeout: Option<Duration>,
+}
+
+impl Sqlite {
+    pub async fn open(config: DbConfig) -> Result<Self, DbError> {
+        info!("Opening SQLite database {}", config.sqlite_path.display());
+
+        let path = config.sqlite_path.clone();
+        let auto_migrate = config.auto_migrate;
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection, DbError> {
+            let mut conn = Self::connect(&path)?;
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+            Self::migrate(&mut conn, auto_migrate)?;
+            Ok(conn)
+        }).await??;
+
+        info!("Database initialized successfully");
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            path: config.sqlite_path,
+            analytics_timeout: config.analytics_timeout,
+        })
+    }
+
+    fn connect(path: &std::path::Path) -> rusqlite::Result<Connection> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // Off by default in SQLite; tags and acknowledgements go with their readings
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Ok(conn)
+    }
+
+    /// Run `f` on the shared connection on the blocking thread pool
+    async fn call<T, F>(&self, f: F) -> Result<T, DbError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        let result = tokio::task::spawn_blocking(move || {
+            // A panic while querying leaves the connection itself usable
+            let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut conn)
+        }).await?;
+        Ok(result?)
+    }
+
+    /// Run a potentially long analytics query on a connection of its own.
+    /// The query is interrupted if the caller goes away before it completes
+    /// or if it exceeds the analytics timeout.
+    async fn analytics<T, F>(&self, f: F) -> Result<T, DbError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = Self::connect(&self.path)?;
+        let guard = InterruptOnDrop(Some(conn.get_interrupt_handle()));
+        let task = tokio::task::spawn_blocking(move || f(&conn));
+        let result = match self.analytics_timeout {
+            Some(limit) => tokio::time::timeout(limit, task)
+                .await
+                .map_err(|_| DbError::Timeout(limit))?,
+            None => task.await,
+        };
+        guard.disarm();
+        Ok(result??)
+    }
+
+    /// Apply pending migrations from `migrations/sqlite`, or with
+    /// `auto_migrate` off check that there are none
+    fn migrate(conn: &mut Connection, auto_migrate: bool) -> Result<(), DbError> {
+        let mut runner = embedded::migrations::runner();
+        runner.set_migration_table_name(MIGRATIONS_TABLE);
+
+        if auto_migrate {
+            log_migrations(&runner.run(conn)?);
+        } else {
+            // No migrations table means nothing has been applied yet
+            let applied = runner.get_applied_migrations(conn).unwrap_or_default();
+            ensure_migrated(&runner, &applied).map_err(DbError::other)?;
+        }
+        Ok(())
+    }
+
+    fn row_to_event(row: &Row) -> rusqlite::Result<SensorEvent> {
+        let id: i64 = row.get(0)?;
+        let alerts: String = row.get(5)?;
+        let room: Option<String> = row.get(6)?;
+
+        Ok(SensorEvent {
+            id: Some(id),
+            seq: Some(id),
+            room: room.unwrap_or_else(|| DEFAULT_ROOM_ID.to_string()),
+            device: None,
+            patient_id: row.get(7)?,
+            reading: SensorReading {
+                temperature: row.get(2)?,
+                motion: row.get(3)?,
+                sound_level: row.get(4)?,
+                timestamp: time(row, 1)?,
+                humidity: row.get(8)?,
+                heart_rate: row.get(9)?,
+                spo2: row.get(10)?,
+                door_open: row.get(11)?,
+                light: row.get(12)?,
+                co2: row.get(13)?,
+                presence: row.get(14)?,
+                acceleration: row.get(15)?,
+            },
+            alerts: alerts_from_str(&alerts),
+        })
+    }
+
+    fn row_to_settings_change(row: &Row) -> rusqlite::Result<SettingsChange> {
+        let old_inactivity: i64 = row.get(4)?;
+        let new_inactivity: i64 = row.get(5)?;
+        Ok(SettingsChange {
+            id: row.get(0)?,
+            changed_at: time(row, 1)?,
+            changed_by: row.get(2)?,
+            request_id: row.get(3)?,
+            old: MonitorSettings { inactivity_seconds: old_inactivity as u64, sound_threshold: row.get(6)? },
+            new: MonitorSettings { inactivity_seconds: new_inactivity as u64, sound_threshold: row.get(7)? },
+        })
+    }
+
+    fn row_to_kiosk_token(row: &Row) -> rusqlite::Result<KioskToken> {
+        Ok(KioskToken {
+            id: row.get(0)?,
+            room: row.get(1)?,
+            label: row.get(2)?,
+            created_by: row.get(3)?,
+            created_at: time(row, 4)?,
+            expires_at: time(row, 5)?,
+            revoked_at: opt_time(row, 6)?,
+            revoked_by: row.get(7)?,
+        })
+    }
+
+    /// `None` for an alert of a type or severity this version doesn't know
+    fn row_to_alert(row: &Row) -> rusqlite::Result<Option<Alert>> {
+        let alert: String = row.get(2)?;
+        let severity: String = row.get(3)?;
+        let reason: Option<String> = row.get(9)?;
+        let outcome: Option<String> = row.get(10)?;
+        let (Some(alert), Some(severity)) = (alert_from_str(&alert), severity_from_str(&severity)) else {
+            return Ok(None);
+        };
+        Ok(Some(Alert {
+            id: row.get(0)?,
+            room: row.get(1)?,
+            alert,
+            severity,
+            reading_id: row.get(4)?,
+            triggered_at: time(row, 5)?,
+            resolved_at: opt_time(row, 6)?,
+            acknowledged_by: row.get(7)?,
+            acknowledged_at: opt_time(row, 8)?,
+            resolution_reason: reason.and_then(|r| r.parse().ok()),
+            outcome: outcome.and_then(|o| o.parse().ok()),
+        }))
+    }
+
+    /// `None` for an observation of a kind this version doesn't know
+    fn row_to_manual_observation(row: &Row) -> rusqlite::Result<Option<ManualObservation>> {
+        let kind: String = row.get(3)?;
+        let Ok(kind) = kind.parse() else {
+            return Ok(None);
+        };
+        Ok(Some(ManualObservation {
+            id: Some(row.get(0)?),
+            room: row.get(1)?,
+            patient_id: row.get(2)?,
+            kind,
+            value: row.get(4)?,
+            observed_at: time(row, 5)?,
+            recorded_by: row.get(6)?,
+            note: row.get(7)?,
+        }))
+    }
+
+    fn row_to_patient(row: &Row) -> rusqlite::Result<Patient> {
+        let gender: Option<String> = row.get(4)?;
+        Ok(Patient {
+            id: row.get(0)?,
+            family_name: row.get(1)?,
+            given_names: json(row, 2)?,
+            birth_date: date(row, 3)?,
+            gender: gender.and_then(|g| gender_from_str(&g)),
+            room: row.get(5)?,
+        })
+    }
+
+    fn upsert_tag(conn: &Connection, tag: &str) -> rusqlite::Result<i64> {
+        conn.query_row(
+            "INSERT INTO tags (name, created_at) VALUES (?1, ?2)
+             ON CONFLICT (name) DO UPDATE SET name = excluded.name
+             RETURNING id",
+            params![tag, Ts(Utc::now())],
+            |row| row.get(0),
+        )
+    }
+
+    fn insert_event(conn: &Connection, event: &SensorEvent) -> rusqlite::Result<StoredReading> {
+        conn.prepare_cached(
+            "INSERT INTO sensor_data (timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, (SELECT id FROM patients WHERE room_id = ?6), ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+             RETURNING id, patient_id",
+        )?.query_row(
+            params![
+                Ts(event.reading.timestamp),
+                event.reading.temperature,
+                event.reading.motion,
+                event.reading.sound_level,
+                alerts_to_str(&event.alerts),
+                event.room,
+                event.reading.humidity,
+                event.reading.heart_rate,
+                event.reading.spo2,
+                event.reading.door_open,
+                event.reading.light,
+                event.reading.co2,
+                event.reading.presence,
+                event.reading.acceleration,
+            ],
+            |row| Ok(StoredReading {
+                id: row.get(0)?,
+                seq: row.get(0)?,
+                patient_id: row.get(1)?,
+            }),
+        )
+    }
+}
+
+#[async_trait]
+impl StorageBackend for Sqlite {
+    async fn ping(&self) -> Result<(), DbError> {
+        self.call(|conn| conn.query_row("SELECT 1", [], |_| Ok(()))).await
+    }
+    
+    fn pool_status(&self) -> Option<PoolStatus> {
+        None
+    }
+    
+    async fn register_rooms(&self, rooms: &[String]) -> Result<(), DbError> {
+        let rooms = rooms.to_vec();
+
+        let assigned = self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let now = Ts(Utc::now());
+            for room in &rooms {
+                tx.execute(
+                    "INSERT INTO rooms (id, created_at) VALUES (?1, ?2) ON CONFLICT (id) DO NOTHING",
+                    params![room, now],
+                )?;
+            }
+            let assigned = match rooms.first() {
+                Some(first) => tx.execute(
+                    "UPDATE sensor_data SET room_id = ?1 WHERE room_id IS NULL",
+                    params![first],
+                )?,
+                None => 0,
+            };
+            tx.commit()?;
+            Ok(rooms.first().cloned().filter(|_| assigned > 0).map(|first| (first, assigned)))
+        }).await?;
+
+        if let Some((first, assigned)) = assigned {
+            info!("Assigned {} readings without a room to {}", assigned, first);
+        }
+        Ok(())
+    }
+
+    async fn get_rooms(&self) -> Result<Vec<Room>, DbError> {
+        self.call(|conn| {
+            conn.prepare(
+                "SELECT r.id, r.created_at,
+                        (SELECT MAX(timestamp) FROM sensor_data WHERE deleted_at IS NULL AND room_id = r.id),
+                        r.ward_id
+                 FROM rooms r
+                 ORDER BY r.id",
+            )?.query_map([], |row| Ok(Room {
+                id: row.get(0)?,
+                created_at: time(row, 1)?,
+                last_reading_at: opt_time(row, 2)?,
+                ward: row.get(3)?,
+            }))?.collect()
+        }).await
+    }
+
+    async fn register_ward(&self, ward: &str, rooms: &[String]) -> Result<(), DbError> {
+        let ward = ward.to_string();
+        let rooms = serde_json::to_string(rooms)?;
+
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO wards (id, name, created_at) VALUES (?1, ?1, ?2) ON CONFLICT (id) DO NOTHING",
+                params![ward, Ts(Utc::now())],
+            )?;
+            conn.execute(
+                "UPDATE rooms SET ward_id = ?1
+                 WHERE id IN (SELECT value FROM json_each(?2)) AND ward_id IS NULL",
+                params![ward, rooms],
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn get_wards(&self) -> Result<Vec<Ward>, DbError> {
+        self.call(|conn| {
+            conn.prepare(
+                "SELECT w.id, w.name, w.created_at,
+                        (SELECT json_group_array(id) FROM
+                             (SELECT id FROM rooms WHERE ward_id = w.id ORDER BY id))
+                 FROM wards w
+                 ORDER BY w.id",
+            )?.query_map([], |row| Ok(Ward {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                created_at: time(row, 2)?,
+                rooms: json(row, 3)?,
+            }))?.collect()
+        }).await
+    }
+
+    async fn insert_ward(&self, id: &str, name: &str) -> Result<bool, DbError> {
+        let (id, name) = (id.to_string(), name.to_string());
+
+        let inserted = self.call(move |conn| conn.execute(
+            "INSERT INTO wards (id, name, created_at) VALUES (?1, ?2, ?3) ON CONFLICT (id) DO NOTHING",
+            params![id, name, Ts(Utc::now())],
+        )).await?;
+
+        Ok(inserted > 0)
+    }
+
+    async fn delete_ward(&self, id: &str) -> Result<bool, DbError> {
+        let id = id.to_string();
+        let deleted = self.call(move |conn| conn.execute("DELETE FROM wards WHERE id = ?1", params![id])).await?;
+        Ok(deleted > 0)
+    }
+
+    async fn set_room_ward(&self, room: &str, ward: Option<&str>) -> Result<bool, DbError> {
+        let (room, ward) = (room.to_string(), ward.map(str::to_string));
+
+        let updated = self.call(move |conn| conn.execute(
+            "UPDATE rooms SET ward_id = ?2 WHERE id = ?1",
+            params![room, ward],
+        )).await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn get_patients(&self, room: Option<&str>) -> Result<Vec<Patient>, DbError> {
+        let room = room.map(str::to_string);
+
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, family_name, given_names, birth_date, gender, room_id
+                 FROM patients
+                 WHERE (?1 IS NULL OR room_id = ?1)
+                 ORDER BY id",
+            )?.query_map(params![room], Self::row_to_patient)?.collect()
+        }).await
+    }
+
+    async fn get_patient(&self, id: &str) -> Result<Option<Patient>, DbError> {
+        let id = id.to_string();
+
+        self.call(move |conn| conn.query_row(
+            "SELECT id, family_name, given_names, birth_date, gender, room_id
+             FROM patients WHERE id = ?1",
+            params![id],
+            Self::row_to_patient,
+        ).optional()).await
+    }
+
+    async fn insert_patient(&self, patient: &Patient) -> Result<bool, DbError> {
+        let patient = patient.clone();
+        let given_names = serde_json::to_string(&patient.given_names)?;
+
+        let inserted = self.call(move |conn| {
+            let now = Ts(Utc::now());
+            conn.execute(
+                "INSERT INTO patients
+                    (id, family_name, given_names, birth_date, gender, room_id, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)
+                 ON CONFLICT (id) DO NOTHING",
+                params![patient.id, patient.family_name, given_names,
+                        patient.birth_date.map(|d| d.to_string()), patient.gender.map(|g| g.code()),
+                        patient.room, now],
+            )
+        }).await?;
+
+        Ok(inserted > 0)
+    }
+
+    async fn update_patient(&self, patient: &Patient) -> Result<bool, DbError> {
+        let patient = patient.clone();
+        let given_names = serde_json::to_string(&patient.given_names)?;
+
+        let updated = self.call(move |conn| conn.execute(
+            "UPDATE patients SET family_name = ?2, given_names = ?3, birth_date = ?4, gender = ?5,
+                    room_id = ?6, updated_at = ?7
+             WHERE id = ?1",
+            params![patient.id, patient.family_name, given_names,
+                    patient.birth_date.map(|d| d.to_string()), patient.gender.map(|g| g.code()),
+                    patient.room, Ts(Utc::now())],
+        )).await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn delete_patient(&self, id: &str) -> Result<bool, DbError> {
+        let id = id.to_string();
+        let deleted = self.call(move |conn| conn.execute("DELETE FROM patients WHERE id = ?1", params![id])).await?;
+        Ok(deleted > 0)
+    }
+
+    async fn get_recent_readings(
+        &self,
+        limit: usize,
+        room: Option<&str>,
+        tag: Option<&str>,
+        cursor: Option<ReadingCursor>,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        let (room, tag) = (room.map(str::to_string), tag.map(str::to_string));
+
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+                 FROM sensor_data
+                 WHERE deleted_at IS NULL AND (?2 IS NULL OR id IN (
+                     SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                     WHERE t.name = ?2))
+                   AND (?3 IS NULL OR room_id = ?3)
+                   AND (?4 IS NULL OR (timestamp, id) < (?4, ?5))
+                 ORDER BY timestamp DESC, id DESC
+                 LIMIT ?1",
+            )?.query_map(
+                params![limit as i64, tag, room, cursor.map(|c| Ts(c.before_timestamp)), cursor.map(|c| c.after_id)],
+                Self::row_to_event,
+            )?.collect()
+        }).await
+    }
+
+    async fn get_readings_after(
+        &self,
+        after: i64,
+        limit: usize,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        let (room, tag) = (room.map(str::to_string), tag.map(str::to_string));
+
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+                 FROM sensor_data
+                 WHERE deleted_at IS NULL AND id > ?1
+                   AND (?3 IS NULL OR id IN (
+                     SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                     WHERE t.name = ?3))
+                   AND (?4 IS NULL OR room_id = ?4)
+                 ORDER BY id
+                 LIMIT ?2",
+            )?.query_map(params![after, limit as i64, tag, room], Self::row_to_event)?.collect()
+        }).await
+    }
+
+    async fn insert_readings_batch(
+        &self,
+        events: &[SensorEvent],
+    ) -> Result<Vec<StoredReading>, DbError> {
+        let events = events.to_vec();
+
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let stored = events
+                .iter()
+                .map(|event| Self::insert_event(&tx, event))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            tx.commit()?;
+            Ok(stored)
+        }).await
+    }
+
+    async fn insert_historical(&self, events: &[SensorEvent]) -> Result<u64, DbError> {
+        let events = events.to_vec();
+
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut stored = 0;
+            {
+                let mut insert = tx.prepare(
+                    "INSERT INTO sensor_data (timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration)
+                     VALUES (?1, ?2, ?3, ?4, '', ?5, (SELECT id FROM patients WHERE room_id = ?5), ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                )?;
+                for event in &events {
+                    stored += insert.execute(params![
+                        Ts(event.reading.timestamp),
+                        event.reading.temperature,
+                        event.reading.motion,
+                        event.reading.sound_level,
+                        event.room,
+                        event.reading.humidity,
+                        event.reading.heart_rate,
+                        event.reading.spo2,
+                        event.reading.door_open,
+                        event.reading.light,
+                        event.reading.co2,
+                        event.reading.presence,
+                        event.reading.acceleration,
+                    ])? as u64;
+                }
+            }
+            tx.commit()?;
+            Ok(stored)
+        }).await
+    }
+
+    async fn count_readings_in_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+    ) -> Result<u64, DbError> {
+        let room = room.map(str::to_string);
+
+        let count: i64 = self.call(move |conn| conn.query_row(
+            &format!("SELECT COUNT(*) FROM {EXPANDED_READINGS}
+             WHERE deleted_at IS NULL AND timestamp BETWEEN ?1 AND ?2 AND (?3 IS NULL OR room_id = ?3)"),
+            params![Ts(start), Ts(end), room],
+            |row| row.get(0),
+        )).await?;
+
+        Ok(count as u64)
+    }
+
+    async fn get_readings_in_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+        cursor: Option<ReadingCursor>,
+        limit: Option<usize>,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        let (room, tag) = (room.map(str::to_string), tag.map(str::to_string));
+
+        self.analytics(move |conn| {
+            conn.prepare(
+                &format!("SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+                 FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp BETWEEN ?1 AND ?2
+                   AND (?3 IS NULL OR id IN (
+                     SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                     WHERE t.name = ?3))
+                   AND (?4 IS NULL OR room_id = ?4)
+                   AND (?5 IS NULL OR (timestamp, id) < (?5, ?6))
+                 ORDER BY timestamp DESC, id DESC
+                 LIMIT ?7"),
+            )?.query_map(
+                params![Ts(start), Ts(end), tag, room, cursor.map(|c| Ts(c.before_timestamp)), cursor.map(|c| c.after_id),
+                    limit.map_or(-1, |l| l as i64)],
+                Self::row_to_event,
+            )?.collect()
+        }).await
+    }
+
+    async fn export_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        after: Option<(DateTime<Utc>, i64)>,
+        limit: usize,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        let room = room.map(str::to_string);
+
+        self.analytics(move |conn| {
+            conn.prepare(
+                &format!("SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+                 FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp BETWEEN ?1 AND ?2
+                   AND (?3 IS NULL OR room_id = ?3)
+                   AND (?4 IS NULL OR (timestamp, id) > (?4, ?5))
+                 ORDER BY timestamp, id
+                 LIMIT ?6"),
+            )?.query_map(
+                params![Ts(start), Ts(end), room, after.map(|(t, _)| Ts(t)), after.map(|(_, id)| id), limit as i64],
+                Self::row_to_event,
+            )?.collect()
+        }).await
+    }
+
+    async fn get_reading_by_id(&self, id: i64) -> Result<Option<SensorEvent>, DbError> {
+        self.call(move |conn| conn.query_row(
+            "SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+             FROM sensor_data WHERE deleted_at IS NULL AND id = ?1",
+            params![id],
+            Self::row_to_event,
+        ).optional()).await
+    }
+
+    async fn update_reading(&self, id: i64, correction: &ReadingCorrection) -> Result<Option<SensorEvent>, DbError> {
+        let correction = *correction;
+        self.call(move |conn| conn.query_row(
+            "UPDATE sensor_data
+             SET temperature = COALESCE(?2, temperature),
+                 motion = COALESCE(?3, motion),
+                 sound_level = COALESCE(?4, sound_level)
+             WHERE id = ?1 AND deleted_at IS NULL
+             RETURNING id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration",
+            params![id, correction.temperature, correction.motion, correction.sound_level],
+            Self::row_to_event,
+        ).optional()).await
+    }
+
+    async fn mark_deleted(&self, id: i64, at: DateTime<Utc>) -> Result<bool, DbError> {
+        let updated = self.call(move |conn| conn.execute(
+            "UPDATE sensor_data SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+            params![id, Ts(at)],
+        )).await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn get_alert_summary(&self) -> Result<AlertSummary, DbError> {
+        let counters: Vec<(String, i64)> = self.call(|conn| {
+            conn.prepare("SELECT counter, value FROM sensor_counters")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        }).await?;
+
+        let mut summary = AlertSummary {
+            total_readings: 0,
+            fall_alerts: 0,
+            inactivity_alerts: 0,
+        };
+        for (counter, value) in counters {
+            let value = value.max(0) as u64;
+            match counter.as_str() {
+                "total" => summary.total_readings = value,
+                "fall" => summary.fall_alerts = value,
+                "inactivity" => summary.inactivity_alerts = value,
+                _ => {}
+            }
+        }
+
+        Ok(summary)
+    }
+
+    async fn get_consent(&self, patient_id: &str) -> Result<Consent, DbError> {
+        let patient_id = patient_id.to_string();
+
+        let consent = self.call(move |conn| conn.query_row(
+            "SELECT continuous_monitoring, ehr_sharing, research_export, updated_at
+             FROM patient_consent WHERE patient_id = ?1",
+            params![patient_id],
+            |row| Ok(Consent {
+                continuous_monitoring: row.get(0)?,
+                ehr_sharing: row.get(1)?,
+                research_export: row.get(2)?,
+                updated_at: Some(time(row, 3)?),
+            }),
+        ).optional()).await?;
+
+        Ok(consent.unwrap_or_default())
+    }
+
+    async fn set_consent(&self, patient_id: &str, consent: &Consent) -> Result<(), DbError> {
+        let patient_id = patient_id.to_string();
+        let consent = consent.clone();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO patient_consent
+                (patient_id, continuous_monitoring, ehr_sharing, research_export, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (patient_id) DO UPDATE SET
+                continuous_monitoring = excluded.continuous_monitoring,
+                ehr_sharing = excluded.ehr_sharing,
+                research_export = excluded.research_export,
+                updated_at = excluded.updated_at",
+            params![patient_id, consent.continuous_monitoring, consent.ehr_sharing,
+                    consent.research_export, Ts(Utc::now())],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn get_alert_rules(&self) -> Result<Vec<AlertRule>, DbError> {
+        let rows: Vec<(i64, String, String, String, bool, bool)> = self.call(|conn| {
+            conn.prepare("SELECT id, name, expression, alert_type, enabled, shadow FROM alert_rules ORDER BY id")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)))?
+                .collect()
+        }).await?;
+
+        let rules = rows.into_iter().filter_map(|(id, name, expression, alert_str, enabled, shadow)| {
+            let Some(alert) = alert_from_str(&alert_str) else {
+                warn!("Skipping rule {} with unknown alert type '{}'", id, alert_str);
+                return None;
+            };
+            Some(AlertRule { id, name, expression, alert, enabled, shadow })
+        }).collect();
+
+        Ok(rules)
+    }
+
+    async fn insert_alert_rule(&self, rule: &AlertRule) -> Result<i64, DbError> {
+        let rule = rule.clone();
+
+        self.call(move |conn| conn.query_row(
+            "INSERT INTO alert_rules (name, expression, alert_type, enabled, shadow, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             RETURNING id",
+            params![rule.name, rule.expression, alert_to_str(rule.alert), rule.enabled, rule.shadow, Ts(Utc::now())],
+            |row| row.get(0),
+        )).await
+    }
+
+    async fn delete_alert_rule(&self, id: i64) -> Result<bool, DbError> {
+        let deleted = self.call(move |conn| conn.execute("DELETE FROM alert_rules WHERE id = ?1", params![id])).await?;
+        Ok(deleted > 0)
+    }
+
+    async fn set_alert_rule_shadow(&self, id: i64, shadow: bool) -> Result<bool, DbError> {
+        let updated = self.call(move |conn| conn.execute(
+            "UPDATE alert_rules SET shadow = ?2 WHERE id = ?1",
+            params![id, shadow],
+        )).await?;
+        Ok(updated > 0)
+    }
+
+    async fn insert_shadow_hit(&self, hit: &ShadowHit) -> Result<(), DbError> {
+        let hit = hit.clone();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO shadow_rule_hits (rule_id, room_id, hit_at, temperature, motion, sound_level)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![hit.rule_id, hit.room, Ts(hit.at), hit.temperature, hit.motion, hit.sound_level],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn get_shadow_hits(
+        &self,
+        rule_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<ShadowHit>, DbError> {
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, rule_id, room_id, hit_at, temperature, motion, sound_level
+                 FROM shadow_rule_hits
+                 WHERE rule_id = ?1 AND hit_at >= ?2 AND hit_at < ?3
+                 ORDER BY hit_at DESC
+                 LIMIT ?4",
+            )?.query_map(params![rule_id, Ts(from), Ts(to), limit as i64], |row| Ok(ShadowHit {
+                id: row.get(0)?,
+                rule_id: row.get(1)?,
+                room: row.get(2)?,
+                at: time(row, 3)?,
+                temperature: row.get(4)?,
+                motion: row.get(5)?,
+                sound_level: row.get(6)?,
+            }))?.collect()
+        }).await
+    }
+
+    async fn count_shadow_hits(
+        &self,
+        rule_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<HashMap<String, u64>, DbError> {
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT room_id, COUNT(*)
+                 FROM shadow_rule_hits
+                 WHERE rule_id = ?1 AND hit_at >= ?2 AND hit_at < ?3
+                 GROUP BY room_id",
+            )?.query_map(params![rule_id, Ts(from), Ts(to)], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?
+                .collect()
+        }).await
+    }
+
+    async fn get_feature_flags(&self) -> Result<Vec<FlagSetting>, DbError> {
+        self.call(|conn| {
+            conn.prepare("SELECT name, room_id, enabled, updated_at, updated_by FROM feature_flags")?
+                .query_map([], |row| Ok(FlagSetting {
+                    name: row.get(0)?,
+                    room: Some(row.get::<_, String>(1)?).filter(|r| !r.is_empty()),
+                    enabled: row.get(2)?,
+                    updated_at: time(row, 3)?,
+                    updated_by: row.get(4)?,
+                }))?
+                .collect()
+        }).await
+    }
+
+    async fn set_feature_flag(&self, setting: &FlagSetting) -> Result<(), DbError> {
+        let setting = setting.clone();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO feature_flags (name, room_id, enabled, updated_at, updated_by) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (name, room_id) DO UPDATE
+             SET enabled = excluded.enabled, updated_at = excluded.updated_at, updated_by = excluded.updated_by",
+            params![setting.name, setting.room.unwrap_or_default(), setting.enabled, Ts(setting.updated_at), setting.updated_by],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn delete_feature_flag(&self, name: &str, room: Option<&str>) -> Result<bool, DbError> {
+        let (name, room) = (name.to_string(), room.unwrap_or_default().to_string());
+
+        let deleted = self.call(move |conn| conn.execute(
+            "DELETE FROM feature_flags WHERE name = ?1 AND room_id = ?2",
+            params![name, room],
+        )).await?;
+        Ok(deleted > 0)
+    }
+
+    async fn get_rule_thresholds(&self) -> Result<HashMap<String, f64>, DbError> {
+        self.call(|conn| {
+            conn.prepare("SELECT name, value FROM rule_thresholds")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        }).await
+    }
+
+    async fn set_rule_threshold(&self, name: &str, value: f64) -> Result<(), DbError> {
+        let name = name.to_string();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO rule_thresholds (name, value) VALUES (?1, ?2)
+             ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+            params![name, value],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn insert_device_log(
+        &self,
+        device_id: &str,
+        level: &str,
+        message: &str,
+    ) -> Result<i64, DbError> {
+        let (device_id, level, message) = (device_id.to_string(), level.to_string(), message.to_string());
+
+        self.call(move |conn| conn.query_row(
+            "INSERT INTO device_logs (device_id, received_at, level, message)
+             VALUES (?1, ?2, ?3, ?4)
+             RETURNING id",
+            params![device_id, Ts(Utc::now()), level, message],
+            |row| row.get(0),
+        )).await
+    }
+
+    async fn get_device_logs(
+        &self,
+        device_id: &str,
+        limit: usize,
+    ) -> Result<Vec<DeviceLog>, DbError> {
+        let device_id = device_id.to_string();
+
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, device_id, received_at, level, message
+                 FROM device_logs
+                 WHERE device_id = ?1
+                 ORDER BY received_at DESC
+                 LIMIT ?2",
+            )?.query_map(params![device_id, limit as i64], |row| Ok(DeviceLog {
+                id: row.get(0)?,
+                device_id: row.get(1)?,
+                received_at: time(row, 2)?,
+                level: row.get(3)?,
+                message: row.get(4)?,
+            }))?.collect()
+        }).await
+    }
+
+    async fn purge_device_logs(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let deleted = self.call(move |conn| conn.execute(
+            "DELETE FROM device_logs WHERE received_at < ?1",
+            params![Ts(cutoff)],
+        )).await?;
+
+        Ok(deleted as u64)
+    }
+
+    async fn insert_quarantined_reading(&self, reading: &QuarantinedReading) -> Result<(), DbError> {
+        let reading = reading.clone();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO quarantined_readings (room_id, device_id, received_at, line, reason)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![reading.room, reading.device_id, Ts(reading.received_at), reading.line, reading.reason],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn get_quarantined_readings(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        room: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<QuarantinedReading>, DbError> {
+        let room = room.map(str::to_string);
+
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, room_id, device_id, received_at, line, reason
+                 FROM quarantined_readings
+                 WHERE received_at >= ?1 AND received_at <= ?2 AND (?3 IS NULL OR room_id = ?3)
+                 ORDER BY received_at DESC
+                 LIMIT ?4",
+            )?.query_map(params![Ts(from), Ts(to), room, limit as i64], |row| Ok(QuarantinedReading {
+                id: row.get(0)?,
+                room: row.get(1)?,
+                device_id: row.get(2)?,
+                received_at: time(row, 3)?,
+                line: row.get(4)?,
+                reason: row.get(5)?,
+            }))?.collect()
+        }).await
+    }
+
+    async fn purge_quarantined_readings(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let deleted = self.call(move |conn| conn.execute(
+            "DELETE FROM quarantined_readings WHERE received_at < ?1",
+            params![Ts(cutoff)],
+        )).await?;
+
+        Ok(deleted as u64)
+    }
+
+    async fn upsert_device(
+        &self,
+        device_id: &str,
+        room: &str,
+        firmware_version: Option<&str>,
+        seen_at: Option<DateTime<Utc>>,
+    ) -> Result<(), DbError> {
+        let device_id = device_id.to_string();
+        let room = room.to_string();
+        let firmware_version = firmware_version.map(str::to_string);
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO devices (device_id, room_id, firmware_version, registered_at, last_seen)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (device_id) DO UPDATE SET
+                room_id = excluded.room_id,
+                firmware_version = COALESCE(excluded.firmware_version, devices.firmware_version),
+                last_seen = MAX(COALESCE(excluded.last_seen, ''), COALESCE(devices.last_seen, ''))",
+            params![device_id, room, firmware_version, Ts(Utc::now()), seen_at.map(Ts)],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn get_devices(&self) -> Result<Vec<Device>, DbError> {
+        self.call(|conn| {
+            conn.prepare(
+                "SELECT device_id, room_id, firmware_version, registered_at, NULLIF(last_seen, '')
+                 FROM devices
+                 ORDER BY room_id, device_id",
+            )?.query_map([], |row| Ok(Device {
+                id: row.get(0)?,
+                room: row.get(1)?,
+                firmware_version: row.get(2)?,
+                registered_at: time(row, 3)?,
+                last_seen: opt_time(row, 4)?,
+            }))?.collect()
+        }).await
+    }
+
+    async fn insert_ws_sessions(&self, sessions: &[WsSession]) -> Result<(), DbError> {
+        let sessions = sessions.to_vec();
+
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut insert = tx.prepare(
+                    "INSERT INTO ws_sessions (id, channel, ip, principal, user_agent, connected_at, disconnected_at,
+                                              messages_sent, lag_events, missed_messages, close_reason)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                     ON CONFLICT (id) DO NOTHING",
+                )?;
+                for session in &sessions {
+                    let (Some(disconnected_at), Some(reason)) = (session.disconnected_at, &session.close_reason) else {
+                        continue;
+                    };
+                    insert.execute(params![
+                        session.id,
+                        session.channel,
+                        session.ip,
+                        session.principal,
+                        session.user_agent,
+                        Ts(session.connected_at),
+                        Ts(disconnected_at),
+                        session.messages_sent as i64,
+                        session.lag_events as i64,
+                        session.missed_messages as i64,
+                        reason,
+                    ])?;
+                }
+            }
+            tx.commit()
+        }).await
+    }
+
+    async fn purge_ws_sessions(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let deleted = self.call(move |conn| conn.execute(
+            "DELETE FROM ws_sessions WHERE disconnected_at < ?1",
+            params![Ts(cutoff)],
+        )).await?;
+
+        Ok(deleted as u64)
+    }
+
+    async fn get_ws_subscription(&self, principal: &str) -> Result<Option<Subscription>, DbError> {
+        let principal = principal.to_string();
+
+        self.call(move |conn| conn.query_row(
+            "SELECT rooms, severities, message_types, updated_at FROM ws_subscriptions WHERE principal = ?1",
+            params![principal],
+            |row| Ok(Subscription {
+                rooms: json(row, 0)?,
+                severities: json::<Vec<String>>(row, 1)?.iter().filter_map(|s| severity_from_str(s)).collect(),
+                message_types: json::<Vec<String>>(row, 2)?.iter().filter_map(|s| message_kind_from_str(s)).collect(),
+                updated_at: Some(time(row, 3)?),
+            }),
+        ).optional()).await
+    }
+
+    async fn set_ws_subscription(&self, principal: &str, subscription: &Subscription) -> Result<(), DbError> {
+        let principal = principal.to_string();
+        let rooms = serde_json::to_string(&subscription.rooms)?;
+        let severities: Vec<&str> = subscription.severities.iter().map(|s| severity_to_str(*s)).collect();
+        let severities = serde_json::to_string(&severities)?;
+        let message_types: Vec<&str> = subscription.message_types.iter().map(|k| message_kind_to_str(*k)).collect();
+        let message_types = serde_json::to_string(&message_types)?;
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO ws_subscriptions (principal, rooms, severities, message_types, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (principal) DO UPDATE SET
+                rooms = excluded.rooms,
+                severities = excluded.severities,
+                message_types = excluded.message_types,
+                updated_at = excluded.updated_at",
+            params![principal, rooms, severities, message_types, Ts(Utc::now())],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn delete_ws_subscription(&self, principal: &str) -> Result<bool, DbError> {
+        let principal = principal.to_string();
+
+        let deleted = self.call(move |conn| conn.execute(
+            "DELETE FROM ws_subscriptions WHERE principal = ?1",
+            params![principal],
+        )).await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn readings_older_than(
+        &self,
+        cutoff: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<SensorEvent>, DbError> {
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, timestamp, temperature, motion, sound_level, alert_types, room_id, patient_id, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration
+                 FROM sensor_data
+                 WHERE timestamp < ?1
+                 ORDER BY timestamp, id
+                 LIMIT ?2",
+            )?.query_map(params![Ts(cutoff), limit], Self::row_to_event)?.collect()
+        }).await
+    }
+
+    async fn delete_readings(&self, ids: &[i64]) -> Result<u64, DbError> {
+        let ids = ids.to_vec();
+
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut deleted = 0;
+            {
+                let mut delete = tx.prepare("DELETE FROM sensor_data WHERE id = ?1")?;
+                for id in &ids {
+                    deleted += delete.execute(params![id])? as u64;
+                }
+            }
+            tx.commit()?;
+            Ok(deleted)
+        }).await
+    }
+
+    async fn start_job_run(
+        &self,
+        job: &str,
+        started_at: DateTime<Utc>,
+        checkpoint: Option<&str>,
+    ) -> Result<i64, DbError> {
+        let job = job.to_string();
+        let checkpoint = checkpoint.map(str::to_string);
+
+        self.call(move |conn| conn.query_row(
+            "INSERT INTO job_runs (job, started_at, checkpoint) VALUES (?1, ?2, ?3) RETURNING id",
+            params![job, Ts(started_at), checkpoint],
+            |row| row.get(0),
+        )).await
+    }
+
+    async fn checkpoint_job_run(&self, id: i64, checkpoint: &str) -> Result<(), DbError> {
+        let checkpoint = checkpoint.to_string();
+
+        self.call(move |conn| conn.execute(
+            "UPDATE job_runs SET checkpoint = ?2, chunks = chunks + 1 WHERE id = ?1",
+            params![id, checkpoint],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn finish_job_run(
+        &self,
+        id: i64,
+        finished_at: DateTime<Utc>,
+        error: Option<&str>,
+    ) -> Result<(), DbError> {
+        let error = error.map(str::to_string);
+
+        self.call(move |conn| conn.execute(
+            "UPDATE job_runs
+             SET finished_at = ?2, status = CASE WHEN ?3 IS NULL THEN 'completed' ELSE 'failed' END, error = ?3
+             WHERE id = ?1",
+            params![id, Ts(finished_at), error],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn interrupt_job_runs(&self) -> Result<u64, DbError> {
+        let interrupted = self.call(move |conn| conn.execute(
+            "UPDATE job_runs SET status = 'interrupted' WHERE status = 'running'",
+            [],
+        )).await?;
+
+        Ok(interrupted as u64)
+    }
+
+    async fn get_job_runs(
+        &self,
+        job: Option<&str>,
+        status: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<JobRun>, DbError> {
+        let job = job.map(str::to_string);
+        let status = status.map(str::to_string);
+
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, job, started_at, finished_at, status, checkpoint, chunks, error
+                 FROM job_runs
+                 WHERE (?1 IS NULL OR job = ?1) AND (?2 IS NULL OR status = ?2)
+                 ORDER BY id DESC
+                 LIMIT ?3",
+            )?.query_map(params![job, status, limit as i64], |row| Ok(JobRun {
+                id: row.get(0)?,
+                job: row.get(1)?,
+                started_at: time(row, 2)?,
+                finished_at: opt_time(row, 3)?,
+                status: row.get(4)?,
+                checkpoint: row.get(5)?,
+                chunks: row.get::<_, i64>(6)? as u64,
+                error: row.get(7)?,
+            }))?.collect()
+        }).await
+    }
+
+    async fn purge_job_runs(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let deleted = self.call(move |conn| conn.execute(
+            "DELETE FROM job_runs WHERE started_at < ?1 AND status <> 'running'",
+            params![Ts(cutoff)],
+        )).await?;
+
+        Ok(deleted as u64)
+    }
+
+    async fn get_compaction_rows(
+        &self,
+        room: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<CompactionRow>, DbError> {
+        let room = room.to_string();
+
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, timestamp, COALESCE(run_until, timestamp), run_count, temperature, sound_level, humidity, door_open, light, co2, presence, acceleration, patient_id,
+                        NOT motion AND alert_types = '' AND heart_rate IS NULL AND spo2 IS NULL
+                        AND NOT EXISTS (SELECT 1 FROM reading_tags rt WHERE rt.reading_id = s.id)
+                 FROM sensor_data s
+                 WHERE deleted_at IS NULL AND room_id = ?1 AND timestamp >= ?2 AND timestamp < ?3
+                 ORDER BY timestamp, id",
+            )?.query_map(params![room, Ts(start), Ts(end)], |row| Ok(CompactionRow {
+                id: row.get(0)?,
+                start: time(row, 1)?,
+                end: time(row, 2)?,
+                count: row.get(3)?,
+                temperature: row.get(4)?,
+                sound_level: row.get(5)?,
+                humidity: row.get(6)?,
+                door_open: row.get(7)?,
+                light: row.get(8)?,
+                co2: row.get(9)?,
+                presence: row.get(10)?,
+                acceleration: row.get(11)?,
+                patient_id: row.get(12)?,
+                mergeable: row.get(13)?,
+            }))?.collect()
+        }).await
+    }
+
+    async fn merge_runs(&self, runs: &[ReadingRun]) -> Result<u64, DbError> {
+        let runs = runs.to_vec();
+
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut deleted = 0;
+            {
+                let mut extend = tx.prepare("UPDATE sensor_data SET run_until = ?2, run_count = ?3 WHERE id = ?1")?;
+                // The merged readings live on in their interval, so the
+                // running totals their deletion lowers are raised again
+                let mut keep_counted = tx.prepare(
+                    "UPDATE sensor_counters SET value = value + (SELECT run_count FROM sensor_data WHERE id = ?1)
+                     WHERE counter IN (
+                         SELECT c.counter FROM sensor_counters c, sensor_data s
+                         WHERE s.id = ?1 AND s.deleted_at IS NULL
+                           AND (c.counter = 'total' OR instr(',' || s.alert_types || ',', ',' || c.counter || ',') > 0))",
+                )?;
+                let mut delete = tx.prepare("DELETE FROM sensor_data WHERE id = ?1")?;
+                for run in &runs {
+                    extend.execute(params![run.id, Ts(run.end), run.count])?;
+                    for id in &run.merged {
+                        keep_counted.execute(params![id])?;
+                        deleted += delete.execute(params![id])? as u64;
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(deleted)
+        }).await
+    }
+
+    async fn purge_alerts_resolved_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let deleted = self.call(move |conn| {
+            let deleted = conn.execute("DELETE FROM alerts WHERE resolved_at < ?1", params![Ts(cutoff)])?;
+            conn.execute("DELETE FROM alert_events WHERE occurred_at < ?1", params![Ts(cutoff)])?;
+            Ok(deleted)
+        }).await?;
+
+        Ok(deleted as u64)
+    }
+
+    async fn insert_room_state(
+        &self,
+        room: &str,
+        state: PatientState,
+        started_at: DateTime<Utc>,
+    ) -> Result<(), DbError> {
+        let room = room.to_string();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO room_states (room_id, state, started_at) VALUES (?1, ?2, ?3)",
+            params![room, state.as_str(), Ts(started_at)],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn get_room_states(
+        &self,
+        room: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<(PatientState, DateTime<Utc>)>, DbError> {
+        let room = room.to_string();
+
+        let states: Vec<Option<(PatientState, DateTime<Utc>)>> = self.call(move |conn| {
+            conn.prepare(
+                "SELECT state, started_at
+                 FROM room_states
+                 WHERE room_id = ?1
+                   AND started_at < ?3
+                   AND started_at >= COALESCE(
+                       (SELECT MAX(started_at) FROM room_states WHERE room_id = ?1 AND started_at <= ?2), ?2)
+                 ORDER BY started_at, id",
+            )?.query_map(params![room, Ts(from), Ts(to)], |row| {
+                let state: String = row.get(0)?;
+                Ok(match state.parse() {
+                    Ok(state) => Some((state, time(row, 1)?)),
+                    Err(_) => None,
+                })
+            })?.collect()
+        }).await?;
+
+        Ok(states.into_iter().flatten().collect())
+    }
+
+    async fn purge_room_states_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let deleted = self.call(move |conn| conn.execute(
+            "DELETE FROM room_states WHERE started_at < ?1",
+            params![Ts(cutoff)],
+        )).await?;
+
+        Ok(deleted as u64)
+    }
+
+    async fn insert_door_passage(&self, room: &str, passage: &DoorPassage) -> Result<(), DbError> {
+        let room = room.to_string();
+        let passage = passage.clone();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO door_passages (room_id, kind, opened_at, closed_at) VALUES (?1, ?2, ?3, ?4)",
+            params![room, passage.kind.as_str(), Ts(passage.opened_at), Ts(passage.closed_at)],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn get_door_passages(
+        &self,
+        room: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DoorPassage>, DbError> {
+        let room = room.to_string();
+
+        let passages: Vec<Option<DoorPassage>> = self.call(move |conn| {
+            conn.prepare(
+                "SELECT kind, opened_at, closed_at
+                 FROM door_passages
+                 WHERE room_id = ?1 AND opened_at >= ?2 AND opened_at < ?3
+                 ORDER BY opened_at, id",
+            )?.query_map(params![room, Ts(from), Ts(to)], |row| {
+                let kind: String = row.get(0)?;
+                Ok(match kind.parse() {
+                    Ok(kind) => Some(DoorPassage { kind, opened_at: time(row, 1)?, closed_at: time(row, 2)? }),
+                    Err(_) => None,
+                })
+            })?.collect()
+        }).await?;
+
+        Ok(passages.into_iter().flatten().collect())
+    }
+
+    async fn purge_door_passages_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbError> {
+        let deleted = self.call(move |conn| conn.execute(
+            "DELETE FROM door_passages WHERE opened_at < ?1",
+            params![Ts(cutoff)],
+        )).await?;
+
+        Ok(deleted as u64)
+    }
+
+    async fn insert_kiosk_token(
+        &self,
+        room: &str,
+        token_hash: &str,
+        label: Option<&str>,
+        created_by: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<KioskToken, DbError> {
+        let (room, token_hash) = (room.to_string(), token_hash.to_string());
+        let (label, created_by) = (label.map(str::to_string), created_by.to_string());
+
+        self.call(move |conn| conn.query_row(
+            "INSERT INTO kiosk_tokens (room_id, token_hash, label, created_by, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             RETURNING id, room_id, label, created_by, created_at, expires_at, revoked_at, revoked_by",
+            params![room, token_hash, label, created_by, Ts(Utc::now()), Ts(expires_at)],
+            Self::row_to_kiosk_token,
+        )).await
+    }
+
+    async fn get_kiosk_tokens(&self, room: Option<&str>, now: DateTime<Utc>) -> Result<Vec<KioskToken>, DbError> {
+        let room = room.map(str::to_string);
+
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, room_id, label, created_by, created_at, expires_at, revoked_at, revoked_by
+                 FROM kiosk_tokens
+                 WHERE revoked_at IS NULL AND expires_at > ?2 AND (?1 IS NULL OR room_id = ?1)
+                 ORDER BY created_at DESC, id DESC",
+            )?.query_map(params![room, Ts(now)], Self::row_to_kiosk_token)?.collect()
+        }).await
+    }
+
+    async fn get_kiosk_token(&self, id: i64) -> Result<Option<KioskToken>, DbError> {
+        self.call(move |conn| conn.query_row(
+            "SELECT id, room_id, label, created_by, created_at, expires_at, revoked_at, revoked_by
+             FROM kiosk_tokens WHERE id = ?1",
+            params![id],
+            Self::row_to_kiosk_token,
+        ).optional()).await
+    }
+
+    async fn find_kiosk_token(&self, token_hash: &str, now: DateTime<Utc>) -> Result<Option<KioskToken>, DbError> {
+        let token_hash = token_hash.to_string();
+
+        self.call(move |conn| conn.query_row(
+            "SELECT id, room_id, label, created_by, created_at, expires_at, revoked_at, revoked_by
+             FROM kiosk_tokens
+             WHERE token_hash = ?1 AND revoked_at IS NULL AND expires_at > ?2",
+            params![token_hash, Ts(now)],
+            Self::row_to_kiosk_token,
+        ).optional()).await
+    }
+
+    async fn revoke_kiosk_token(&self, id: i64, revoked_by: &str) -> Result<bool, DbError> {
+        let revoked_by = revoked_by.to_string();
+
+        let revoked = self.call(move |conn| conn.execute(
+            "UPDATE kiosk_tokens SET revoked_at = ?3, revoked_by = ?2 WHERE id = ?1 AND revoked_at IS NULL",
+            params![id, revoked_by, Ts(Utc::now())],
+        )).await?;
+
+        Ok(revoked > 0)
+    }
+
+    async fn maintain_partitions(&self, _now: DateTime<Utc>) -> Result<PartitionChanges, DbError> {
+        // Readings are never partitioned in a SQLite file
+        Ok(PartitionChanges::default())
+    }
+
+    async fn get_storage_usage(&self, since: DateTime<Utc>) -> Result<StorageUsage, DbError> {
+        self.analytics(move |conn| {
+            // Readings share the file with everything else; its size is what fills the disk
+            let bytes = conn.query_row(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                [],
+                |row| row.get(0),
+            )?;
+            let rooms = conn.prepare(
+                "SELECT COALESCE(room_id, ?2), COUNT(*), COUNT(*) FILTER (WHERE timestamp >= ?1)
+                 FROM sensor_data
+                 GROUP BY room_id
+                 ORDER BY room_id",
+            )?.query_map(params![Ts(since), DEFAULT_ROOM_ID], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<_, _>>()?;
+            Ok(StorageUsage { bytes, partitions: Vec::new(), rooms })
+        }).await
+    }
+
+    async fn get_tags(&self) -> Result<Vec<Tag>, DbError> {
+        self.call(|conn| {
+            conn.prepare(
+                "SELECT t.name, t.created_at,
+                        COUNT(s.id) AS readings,
+                        COUNT(s.id) FILTER (WHERE s.alert_types <> '') AS alerts
+                 FROM tags t
+                 LEFT JOIN reading_tags rt ON rt.tag_id = t.id
+                 LEFT JOIN sensor_data s ON s.id = rt.reading_id AND s.deleted_at IS NULL
+                 GROUP BY t.id
+                 ORDER BY t.name",
+            )?.query_map([], |row| Ok(Tag {
+                name: row.get(0)?,
+                created_at: time(row, 1)?,
+                readings: row.get::<_, i64>(2)? as u64,
+                alerts: row.get::<_, i64>(3)? as u64,
+            }))?.collect()
+        }).await
+    }
+
+    async fn tag_readings(&self, tag: &str, ids: &[i64]) -> Result<u64, DbError> {
+        let tag = tag.to_string();
+        let ids = serde_json::to_string(ids)?;
+
+        let tagged = self.call(move |conn| {
+            let tag_id = Self::upsert_tag(conn, &tag)?;
+            conn.execute(
+                "INSERT INTO reading_tags (reading_id, tag_id)
+                 SELECT id, ?2 FROM sensor_data WHERE deleted_at IS NULL AND id IN (SELECT value FROM json_each(?1))
+                 ON CONFLICT DO NOTHING",
+                params![ids, tag_id],
+            )
+        }).await?;
+
+        Ok(tagged as u64)
+    }
+
+    async fn tag_range(
+        &self,
+        tag: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        alerts_only: bool,
+    ) -> Result<u64, DbError> {
+        let tag = tag.to_string();
+
+        let tagged = self.call(move |conn| {
+            let tag_id = Self::upsert_tag(conn, &tag)?;
+            conn.execute(
+                "INSERT INTO reading_tags (reading_id, tag_id)
+                 SELECT id, ?3 FROM sensor_data
+                 WHERE deleted_at IS NULL AND timestamp BETWEEN ?1 AND ?2
+                   AND (NOT ?4 OR alert_types <> '')
+                 ON CONFLICT DO NOTHING",
+                params![Ts(start), Ts(end), tag_id, alerts_only],
+            )
+        }).await?;
+
+        Ok(tagged as u64)
+    }
+
+    async fn untag_reading(&self, tag: &str, reading_id: i64) -> Result<bool, DbError> {
+        let tag = tag.to_string();
+
+        let deleted = self.call(move |conn| conn.execute(
+            "DELETE FROM reading_tags
+             WHERE reading_id = ?2 AND tag_id = (SELECT id FROM tags WHERE name = ?1)",
+            params![tag, reading_id],
+        )).await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn delete_tag(&self, tag: &str) -> Result<bool, DbError> {
+        let tag = tag.to_string();
+        let deleted = self.call(move |conn| conn.execute("DELETE FROM tags WHERE name = ?1", params![tag])).await?;
+        Ok(deleted > 0)
+    }
+
+    async fn insert_snooze(&self, snooze: &AlertSnooze) -> Result<i64, DbError> {
+        let snooze = snooze.clone();
+
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let now = Ts(Utc::now());
+            tx.execute(
+                "UPDATE alert_snoozes SET snoozed_until = ?3
+                 WHERE alert_type = ?1 AND snoozed_until > ?3
+                   AND reading_id IN (SELECT id FROM sensor_data WHERE deleted_at IS NULL AND room_id = ?2)",
+                params![alert_to_str(snooze.alert), snooze.room, now],
+            )?;
+            let id = tx.query_row(
+                "INSERT INTO alert_snoozes (reading_id, alert_type, snoozed_until, snoozed_by, reason, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 RETURNING id",
+                params![snooze.reading_id, alert_to_str(snooze.alert), Ts(snooze.until),
+                        snooze.snoozed_by, snooze.reason, now],
+                |row| row.get(0),
+            )?;
+            tx.commit()?;
+            Ok(id)
+        }).await
+    }
+
+    async fn ack_alert(
+        &self,
+        reading_id: i64,
+        acked_by: Option<&str>,
+        via: &str,
+    ) -> Result<(AlertAck, bool), DbError> {
+        let (acked_by, via) = (acked_by.map(str::to_string), via.to_string());
+
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let now = Ts(Utc::now());
+            let inserted = tx.execute(
+                "INSERT INTO alert_acks (reading_id, acked_by, via, acked_at) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (reading_id) DO NOTHING",
+                params![reading_id, acked_by, via, now],
+            )?;
+
+            // Acknowledge the episode the reading belongs to as well
+            tx.execute(
+                "UPDATE alerts SET acknowledged_by = ?2, acknowledged_at = ?3
+                 WHERE acknowledged_at IS NULL
+                   AND EXISTS (
+                     SELECT 1 FROM sensor_data s
+                     WHERE s.id = ?1 AND s.room_id = alerts.room_id
+                       AND instr(',' || s.alert_types || ',', ',' || alerts.alert_type || ',') > 0
+                       AND alerts.triggered_at <= s.timestamp
+                       AND (alerts.resolved_at IS NULL OR alerts.resolved_at > s.timestamp))",
+                params![reading_id, acked_by, now],
+            )?;
+
+            let ack = tx.query_row(
+                "SELECT reading_id, acked_by, via, acked_at FROM alert_acks WHERE reading_id = ?1",
+                params![reading_id],
+                |row| Ok(AlertAck {
+                    reading_id: row.get(0)?,
+                    acked_by: row.get(1)?,
+                    via: row.get(2)?,
+                    acked_at: time(row, 3)?,
+                }),
+            )?;
+            tx.commit()?;
+            Ok((ack, inserted == 1))
+        }).await
+    }
+
+    async fn sync_alerts(
+        &self,
+        room: &str,
+        alerts: &AlertSet,
+        reading_id: i64,
+        at: DateTime<Utc>,
+        resolve: &[(AlertType, ResolutionReason)],
+    ) -> Result<AlertSync, DbError> {
+        let room = room.to_string();
+        let alerts = alerts.clone();
+        let resolve = resolve.to_vec();
+
+        let sync = self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut sync = AlertSync::default();
+            {
+                let mut update = tx.prepare(
+                    "UPDATE alerts SET resolved_at = ?3, resolution_reason = ?4
+                     WHERE room_id = ?1 AND alert_type = ?2 AND resolved_at IS NULL
+                     RETURNING id, room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+                               acknowledged_by, acknowledged_at, resolution_reason, outcome",
+                )?;
+                for (alert, reason) in &resolve {
+                    let resolved: Vec<Option<Alert>> = update
+                        .query_map(params![room, alert_to_str(*alert), Ts(at), reason.as_str()], Self::row_to_alert)?
+                        .collect::<rusqlite::Result<_>>()?;
+                    sync.resolved.extend(resolved.into_iter().flatten());
+                }
+            }
+
+            for alert in alerts.iter() {
+                let inserted = tx.execute(
+                    "INSERT INTO alerts (room_id, alert_type, severity, reading_id, triggered_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)
+                     ON CONFLICT DO NOTHING",
+                    params![room, alert_to_str(alert), severity_to_str(alert.severity()), reading_id, Ts(at)],
+                )?;
+                if inserted > 0 {
+                    sync.opened.push(alert);
+                }
+            }
+            tx.commit()?;
+            Ok(sync)
+        }).await?;
+
+        if !sync.resolved.is_empty() {
+            debug!("Resolved {} alerts", sync.resolved.len());
+        }
+        Ok(sync)
+    }
+
+    async fn resolve_acknowledged(
+        &self,
+        reading_id: i64,
+        alerts: &[AlertType],
+        at: DateTime<Utc>,
+    ) -> Result<Vec<Alert>, DbError> {
+        let types = alerts.iter().map(|a| alert_to_str(*a)).collect::<Vec<_>>().join(",");
+
+        let rows: Vec<Option<Alert>> = self.call(move |conn| {
+            conn.prepare(
+                "UPDATE alerts SET resolved_at = ?3, resolution_reason = 'acknowledged'
+                 WHERE resolved_at IS NULL
+                   AND instr(',' || ?2 || ',', ',' || alert_type || ',') > 0
+                   AND EXISTS (SELECT 1 FROM sensor_data s
+                               WHERE s.id = ?1 AND s.room_id = alerts.room_id
+                                 AND instr(',' || s.alert_types || ',', ',' || alerts.alert_type || ',') > 0
+                                 AND alerts.triggered_at <= s.timestamp)
+                 RETURNING id, room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+                           acknowledged_by, acknowledged_at, resolution_reason, outcome",
+            )?.query_map(params![reading_id, types, Ts(at)], Self::row_to_alert)?.collect()
+        }).await?;
+
+        Ok(rows.into_iter().flatten().collect())
+    }
+
+    async fn set_alert_outcome(
+        &self,
+        reading_id: i64,
+        alerts: &[AlertType],
+        outcome: AlertOutcome,
+    ) -> Result<Vec<Alert>, DbError> {
+        let types = alerts.iter().map(|a| alert_to_str(*a)).collect::<Vec<_>>().join(",");
+
+        let rows: Vec<Option<Alert>> = self.call(move |conn| {
+            conn.prepare(
+                "UPDATE alerts SET outcome = ?3
+                 WHERE instr(',' || ?2 || ',', ',' || alert_type || ',') > 0
+                   AND EXISTS (SELECT 1 FROM sensor_data s
+                               WHERE s.id = ?1 AND s.room_id = alerts.room_id
+                                 AND instr(',' || s.alert_types || ',', ',' || alerts.alert_type || ',') > 0
+                                 AND alerts.triggered_at <= s.timestamp
+                                 AND (alerts.resolved_at IS NULL OR alerts.resolved_at > s.timestamp))
+                 RETURNING id, room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+                           acknowledged_by, acknowledged_at, resolution_reason, outcome",
+            )?.query_map(params![reading_id, types, outcome.as_str()], Self::row_to_alert)?.collect()
+        }).await?;
+
+        Ok(rows.into_iter().flatten().collect())
+    }
+
+    async fn get_alerts(
+        &self,
+        open_only: bool,
+        room: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Alert>, DbError> {
+        let room = room.map(str::to_string);
+
+        let rows: Vec<Option<Alert>> = self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+                        acknowledged_by, acknowledged_at, resolution_reason, outcome
+                 FROM alerts
+                 WHERE (NOT ?1 OR resolved_at IS NULL) AND (?2 IS NULL OR room_id = ?2)
+                 ORDER BY triggered_at DESC
+                 LIMIT ?3",
+            )?.query_map(params![open_only, room, limit], Self::row_to_alert)?.collect()
+        }).await?;
+
+        Ok(rows.into_iter().flatten().collect())
+    }
+
+    async fn get_alerts_in_range(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+    ) -> Result<Vec<Alert>, DbError> {
+        let room = room.map(str::to_string);
+
+        let rows: Vec<Option<Alert>> = self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, room_id, alert_type, severity, reading_id, triggered_at, resolved_at,
+                        acknowledged_by, acknowledged_at, resolution_reason, outcome
+                 FROM alerts
+                 WHERE triggered_at >= ?1 AND triggered_at < ?2 AND (?3 IS NULL OR room_id = ?3)
+                 ORDER BY triggered_at, id",
+            )?.query_map(params![Ts(start), Ts(end), room], Self::row_to_alert)?.collect()
+        }).await?;
+
+        Ok(rows.into_iter().flatten().collect())
+    }
+
+    async fn get_alert_counts(
+        &self,
+        buckets: &[(NaiveDate, DateTime<Utc>, DateTime<Utc>)],
+        room: Option<&str>,
+    ) -> Result<Vec<AlertCounts>, DbError> {
+        // Buckets as `[day, start, end]` JSON arrays, as for `research_aggregates`
+        let spans: Vec<[String; 3]> = buckets
+            .iter()
+            .map(|(day, start, end)| [
+                day.to_string(),
+                start.format(TIMESTAMP_FORMAT).to_string(),
+                end.format(TIMESTAMP_FORMAT).to_string(),
+            ])
+            .collect();
+        let spans = serde_json::to_string(&spans)?;
+        let room = room.map(str::to_string);
+
+        let counts: Vec<(String, i64, i64, i64)> = self.analytics(move |conn| {
+            conn.prepare(
+                "SELECT json_extract(b.value, '$[0]'),
+                        COUNT(a.id) FILTER (WHERE a.alert_type = 'fall'),
+                        COUNT(a.id) FILTER (WHERE a.alert_type = 'inactivity'),
+                        COUNT(a.id) FILTER (WHERE a.alert_type LIKE 'sensor_fault:%')
+                 FROM json_each(?1) b
+                 LEFT JOIN alerts a ON a.triggered_at >= json_extract(b.value, '$[1]')
+                                   AND a.triggered_at < json_extract(b.value, '$[2]')
+                                   AND (?2 IS NULL OR a.room_id = ?2)
+                 GROUP BY b.key
+                 ORDER BY b.key",
+            )?.query_map(params![spans, room], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+                .collect()
+        }).await?;
+
+        Ok(counts.into_iter().filter_map(|(day, falls, inactivity, faults)| Some(AlertCounts {
+            period: day.parse().ok()?,
+            fall_alerts: falls as u64,
+            inactivity_alerts: inactivity as u64,
+            sensor_fault_alerts: faults as u64,
+        })).collect())
+    }
+
+    async fn end_snooze(&self, room: &str, alert: AlertType) -> Result<bool, DbError> {
+        let room = room.to_string();
+
+        let updated = self.call(move |conn| conn.execute(
+            "UPDATE alert_snoozes SET snoozed_until = ?3
+             WHERE alert_type = ?1 AND snoozed_until > ?3
+               AND reading_id IN (SELECT id FROM sensor_data WHERE deleted_at IS NULL AND room_id = ?2)",
+            params![alert_to_str(alert), room, Ts(Utc::now())],
+        )).await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn get_active_snoozes(&self) -> Result<Vec<AlertSnooze>, DbError> {
+        let rows: Vec<Option<AlertSnooze>> = self.call(|conn| {
+            conn.prepare(
+                "SELECT z.reading_id, z.alert_type, z.snoozed_until, z.snoozed_by, z.reason, s.room_id
+                 FROM alert_snoozes z
+                 JOIN sensor_data s ON s.id = z.reading_id
+                 WHERE z.snoozed_until > ?1
+                 ORDER BY z.snoozed_until",
+            )?.query_map(params![Ts(Utc::now())], |row| {
+                let Some(alert) = alert_from_str(&row.get::<_, String>(1)?) else {
+                    return Ok(None);
+                };
+                Ok(Some(AlertSnooze {
+                    reading_id: row.get(0)?,
+                    room: row.get::<_, Option<String>>(5)?.unwrap_or_else(|| DEFAULT_ROOM_ID.to_string()),
+                    alert,
+                    until: time(row, 2)?,
+                    snoozed_by: row.get(3)?,
+                    reason: row.get(4)?,
+                }))
+            })?.collect()
+        }).await?;
+
+        Ok(rows.into_iter().flatten().collect())
+    }
+
+    async fn insert_alert_event(&self, event: &AlertEvent) -> Result<i64, DbError> {
+        let event = event.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO alert_events (event, room_id, alert_type, alert_id, occurred_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![event.event, event.room, alert_to_str(event.alert), event.alert_id, Ts(event.at)],
+            )?;
+            Ok(conn.last_insert_rowid())
+        }).await
+    }
+
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<(), DbError> {
+        let entry = entry.clone();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO audit_log (at, action, subject, actor, request_id, detail)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![Ts(Utc::now()), entry.action, entry.subject, entry.actor, entry.request_id, entry.detail],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn insert_settings_change(
+        &self,
+        old: &MonitorSettings,
+        new: &MonitorSettings,
+        changed_by: Option<&str>,
+        request_id: Option<&str>,
+    ) -> Result<SettingsChange, DbError> {
+        let (old, new) = (old.clone(), new.clone());
+        let changed_by = changed_by.map(str::to_string);
+        let request_id = request_id.map(str::to_string);
+
+        Ok(self.call(move |conn| conn.query_row(
+            "INSERT INTO settings_history (changed_at, changed_by, request_id, old_inactivity_seconds,
+                                           new_inactivity_seconds, old_sound_threshold, new_sound_threshold)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             RETURNING id, changed_at, changed_by, request_id, old_inactivity_seconds, new_inactivity_seconds,
+                       old_sound_threshold, new_sound_threshold",
+            params![Ts(Utc::now()), changed_by, request_id, old.inactivity_seconds as i64,
+                    new.inactivity_seconds as i64, old.sound_threshold, new.sound_threshold],
+            Self::row_to_settings_change,
+        )).await?)
+    }
+
+    async fn get_settings_history(&self, limit: i64) -> Result<Vec<SettingsChange>, DbError> {
+        Ok(self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, changed_at, changed_by, request_id, old_inactivity_seconds, new_inactivity_seconds,
+                        old_sound_threshold, new_sound_threshold
+                 FROM settings_history
+                 ORDER BY changed_at DESC, id DESC
+                 LIMIT ?1",
+            )?.query_map(params![limit], Self::row_to_settings_change)?.collect()
+        }).await?)
+    }
+
+    async fn insert_phi_access(&self, access: &PhiAccess) -> Result<(), DbError> {
+        let access = access.clone();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO phi_access_log
+                (accessed_at, principal, patient_id, endpoint, range_start, range_end, row_count, request_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![Ts(Utc::now()), access.principal, access.patient_id, access.endpoint,
+                    access.range_start.map(Ts), access.range_end.map(Ts), access.row_count as i64,
+                    access.request_id],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn get_phi_access_log(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        principal: Option<&str>,
+    ) -> Result<Vec<PhiAccessRecord>, DbError> {
+        let principal = principal.map(str::to_string);
+
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT accessed_at, principal, patient_id, endpoint, range_start, range_end,
+                        row_count, request_id
+                 FROM phi_access_log
+                 WHERE accessed_at BETWEEN ?1 AND ?2
+                   AND (?3 IS NULL OR principal = ?3)
+                 ORDER BY accessed_at, id",
+            )?.query_map(params![Ts(start), Ts(end), principal], |row| Ok(PhiAccessRecord {
+                accessed_at: time(row, 0)?,
+                access: PhiAccess {
+                    principal: row.get(1)?,
+                    patient_id: row.get(2)?,
+                    endpoint: row.get(3)?,
+                    range_start: opt_time(row, 4)?,
+                    range_end: opt_time(row, 5)?,
+                    row_count: row.get::<_, i64>(6)? as u64,
+                    request_id: row.get(7)?,
+                },
+            }))?.collect()
+        }).await
+    }
+
+    async fn get_reading_tags(&self, reading_id: i64) -> Result<Vec<String>, DbError> {
+        self.call(move |conn| {
+            conn.prepare(
+                "SELECT t.name FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                 WHERE rt.reading_id = ?1
+                 ORDER BY t.name",
+            )?.query_map(params![reading_id], |row| row.get(0))?.collect()
+        }).await
+    }
+
+    async fn get_activity_analysis(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<ActivityAnalysis, DbError> {
+        let (room, tag) = (room.map(str::to_string), tag.map(str::to_string));
+
+        let ((total, motion_count, avg_temp, avg_sound, max_sound, falls), (counts, longest)) = self.analytics(move |conn| {
+            let stats: (i64, i64, f64, f64, i32, i64) = conn.query_row(
+                &format!("SELECT
+                    COUNT(*),
+                    COUNT(*) FILTER (WHERE motion),
+                    COALESCE(AVG(temperature), 0.0),
+                    COALESCE(AVG(sound_level), 0.0),
+                    COALESCE(MAX(sound_level), 0),
+                    COUNT(*) FILTER (WHERE instr(',' || alert_types || ',', ',fall,') > 0)
+                 FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp BETWEEN ?1 AND ?2
+                   AND (?3 IS NULL OR id IN (
+                     SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                     WHERE t.name = ?3))
+                   AND (?4 IS NULL OR room_id = ?4)"),
+                params![Ts(start), Ts(end), tag, room],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)),
+            )?;
+
+            // Still periods per room as runs of readings without or with
+            // motion (gaps and islands); a still run lasts until the next run
+            // starts, or until the end. Only the bucket counts and the longest
+            // periods leave the database.
+            let mut statement = conn.prepare(&format!(
+                "WITH readings AS (
+                    SELECT room_id, timestamp, id, motion,
+                           motion IS NOT LAG(motion) OVER (PARTITION BY room_id ORDER BY timestamp, id) AS starts_run
+                    FROM {EXPANDED_READINGS}
+                    WHERE deleted_at IS NULL AND timestamp BETWEEN ?1 AND ?2
+                      AND (?3 IS NULL OR id IN (
+                        SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                        WHERE t.name = ?3))
+                      AND (?4 IS NULL OR room_id = ?4)
+                 ),
+                 runs AS (
+                    SELECT room_id, motion, timestamp AS started,
+                           COALESCE(LEAD(timestamp) OVER (PARTITION BY room_id ORDER BY timestamp, id), ?2) AS ended
+                    FROM readings
+                    WHERE starts_run
+                 ),
+                 still AS (
+                    SELECT room_id, started, ended, {RUN_MINUTES} AS mins
+                    FROM runs
+                    WHERE NOT motion
+                 )
+                 SELECT {bucket}, COUNT(*), NULL, NULL, NULL, NULL
+                 FROM still
+                 GROUP BY 1
+                 UNION ALL
+                 SELECT * FROM (
+                    SELECT NULL, NULL, room_id, started, ended, mins
+                    FROM still
+                    ORDER BY mins DESC, started
+                    LIMIT ?5
+                 )",
+                bucket = still_bucket(),
+            ))?;
+            let mut rows = statement.query(params![Ts(start), Ts(end), tag, room, TOP_STILL_PERIODS as i64])?;
+
+            // Bucket counts have a bucket, the longest periods don't
+            let mut counts = [0; STILL_PERIOD_BUCKETS.len() + 1];
+            let mut longest = Vec::new();
+            while let Some(row) = rows.next()? {
+                match row.get::<_, Option<i64>>(0)? {
+                    Some(bucket) => counts[bucket as usize] = row.get::<_, i64>(1)? as u64,
+                    None => longest.push(StillPeriod {
+                        room: row.get::<_, Option<String>>(2)?.unwrap_or_else(|| DEFAULT_ROOM_ID.to_string()),
+                        start: time(row, 3)?,
+                        end: time(row, 4)?,
+                        duration_mins: row.get::<_, i64>(5)? as u64,
+                    }),
+                }
+            }
+
+            Ok((stats, (counts, longest)))
+        }).await?;
+
+        let activity_score = activity_score(motion_count as u64, total as u64);
+
+        Ok(ActivityAnalysis {
+            period_start: start.to_rfc3339(),
+            period_end: end.to_rfc3339(),
+            total_readings: total as u64,
+            motion_readings: motion_count as u64,
+            activity_score: (activity_score * 100.0).round() / 100.0,
+            activity_level: activity_level(activity_score).to_string(),
+            avg_temperature: (avg_temp * 100.0).round() / 100.0,
+            avg_sound_level: (avg_sound * 100.0).round() / 100.0,
+            max_sound_level: max_sound,
+            fall_alerts: falls as u64,
+            longest_still_period_mins: longest.first().map_or(0, |p: &StillPeriod| p.duration_mins),
+            still_periods: StillPeriods::from_bucket_counts(counts),
+            longest_still_periods: longest,
+            filled_readings: 0,
+            night_awakenings: None,
+            night_light: None,
+        })
+    }
+
+    async fn count_night_awakenings(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<u64, DbError> {
+        let (room, tag) = (room.map(str::to_string), tag.map(str::to_string));
+
+        let motion: Vec<(Option<String>, DateTime<Utc>, bool)> = self.analytics(move |conn| {
+            conn.prepare(
+                &format!("SELECT room_id, timestamp, motion FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp BETWEEN ?1 AND ?2
+                   AND (?3 IS NULL OR id IN (
+                     SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                     WHERE t.name = ?3))
+                   AND (?4 IS NULL OR room_id = ?4)
+                 ORDER BY room_id, timestamp ASC"),
+            )?.query_map(params![Ts(start), Ts(end), tag, room], |row| {
+                Ok((row.get(0)?, time(row, 1)?, row.get(2)?))
+            })?.collect()
+        }).await?;
+
+        Ok(motion
+            .chunk_by(|a, b| a.0 == b.0)
+            .map(|rows| count_night_awakenings(rows.iter().map(|(_, t, motion)| (*t, *motion))))
+            .sum())
+    }
+
+    async fn get_light_sums(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<LightSums, DbError> {
+        let (room, tag) = (room.map(str::to_string), tag.map(str::to_string));
+
+        self.analytics(move |conn| conn.query_row(
+            &format!("SELECT COUNT(*),
+                    COUNT(*) FILTER (WHERE light >= ?5),
+                    COUNT(*) FILTER (WHERE motion),
+                    COUNT(*) FILTER (WHERE motion AND light >= ?5),
+                    COALESCE(SUM(light), 0.0),
+                    COALESCE(SUM(light * light), 0.0),
+                    COALESCE(SUM(light) FILTER (WHERE motion), 0.0)
+             FROM {EXPANDED_READINGS}
+             WHERE deleted_at IS NULL AND light IS NOT NULL AND timestamp BETWEEN ?1 AND ?2
+               AND (?3 IS NULL OR id IN (
+                 SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                 WHERE t.name = ?3))
+               AND (?4 IS NULL OR room_id = ?4)"),
+            params![Ts(start), Ts(end), tag, room, LIGHTS_ON_LUX],
+            |row| Ok(LightSums {
+                readings: row.get::<_, i64>(0)? as u64,
+                lit_readings: row.get::<_, i64>(1)? as u64,
+                motion_readings: row.get::<_, i64>(2)? as u64,
+                lit_motion_readings: row.get::<_, i64>(3)? as u64,
+                lux: row.get(4)?,
+                lux_squared: row.get(5)?,
+                motion_lux: row.get(6)?,
+            }),
+        )).await
+    }
+
+    async fn get_fall_threshold_sweep(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        thresholds: std::ops::RangeInclusive<i32>,
+        step: i32,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Vec<ThresholdPoint>, DbError> {
+        let (room, tag) = (room.map(str::to_string), tag.map(str::to_string));
+
+        let readings: Vec<(Option<String>, bool, i32)> = self.analytics(move |conn| {
+            conn.prepare(
+                &format!("SELECT room_id, motion, sound_level
+                 FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp BETWEEN ?1 AND ?2
+                   AND (?3 IS NULL OR id IN (
+                     SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                     WHERE t.name = ?3))
+                   AND (?4 IS NULL OR room_id = ?4)
+                 ORDER BY room_id, timestamp"),
+            )?.query_map(params![Ts(start), Ts(end), tag, room], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?.collect()
+        }).await?;
+
+        // A reading starts an alert if it qualifies and the room's previous one didn't
+        let points = thresholds.step_by(step.max(1) as usize).map(|threshold| {
+            let qualifies = |(_, motion, sound): &(Option<String>, bool, i32)| *motion && *sound > threshold;
+            let alerts = readings
+                .chunk_by(|a, b| a.0 == b.0)
+                .map(|room| {
+                    let starts = room.windows(2).filter(|w| qualifies(&w[1]) && !qualifies(&w[0])).count();
+                    starts + usize::from(room.first().is_some_and(qualifies))
+                })
+                .sum::<usize>();
+            ThresholdPoint {
+                threshold,
+                fall_alerts: alerts as u64,
+            }
+        }).collect();
+
+        Ok(points)
+    }
+
+    async fn get_night_noise_minutes(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        days: &FacilityDays,
+        quiet: &QuietHours,
+        room: &str,
+    ) -> Result<Vec<NightNoiseWeek>, DbError> {
+        let room = room.to_string();
+
+        let readings: Vec<(DateTime<Utc>, i32)> = self.analytics(move |conn| {
+            conn.prepare(
+                &format!("SELECT timestamp, sound_level FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp >= ?1 AND timestamp < ?2 AND room_id = ?3"),
+            )?.query_map(params![Ts(start), Ts(end), room], |row| Ok((time(row, 0)?, row.get(1)?)))?
+                .collect()
+        }).await?;
+
+        // Loudest reading of each quiet-hours minute
+        let mut minutes: BTreeMap<DateTime<Utc>, i32> = BTreeMap::new();
+        for (t, sound) in readings {
+            if quiet.contains(days.local(t).hour()) {
+                let peak = minutes.entry(t.duration_trunc(ChronoDuration::minutes(1))?).or_insert(sound);
+                *peak = (*peak).max(sound);
+            }
+        }
+
+        let mut weeks: BTreeMap<DateTime<Utc>, NightNoiseWeek> = BTreeMap::new();
+        for (minute, peak) in minutes {
+            let week_start = days.week_start(minute);
+            let week = weeks.entry(week_start).or_insert(NightNoiseWeek {
+                week_start,
+                observed_minutes: 0,
+                noisy_minutes: 0,
+            });
+            week.observed_minutes += 1;
+            if peak > quiet.noise_limit {
+                week.noisy_minutes += 1;
+            }
+        }
+
+        Ok(weeks.into_values().collect())
+    }
+
+    async fn count_falls(
+        &self,
+        room: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<u64, DbError> {
+        let room = room.to_string();
+
+        let falls: Vec<bool> = self.analytics(move |conn| {
+            conn.prepare(
+                "SELECT instr(',' || alert_types || ',', ',fall,') > 0
+                 FROM sensor_data
+                 WHERE deleted_at IS NULL AND room_id = ?1 AND timestamp >= ?2 AND timestamp < ?3
+                 ORDER BY timestamp",
+            )?.query_map(params![room, Ts(start), Ts(end)], |row| row.get(0))?.collect()
+        }).await?;
+
+        // A run of readings with a fall alert is one fall
+        let starts = falls.windows(2).filter(|w| w[1] && !w[0]).count();
+        Ok((starts + usize::from(falls.first() == Some(&true))) as u64)
+    }
+
+    async fn get_night_motion_minutes(
+        &self,
+        room: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        days: &FacilityDays,
+        quiet: &QuietHours,
+    ) -> Result<Vec<(DateTime<Utc>, bool)>, DbError> {
+        let room = room.to_string();
+
+        let readings: Vec<(DateTime<Utc>, bool)> = self.analytics(move |conn| {
+            conn.prepare(
+                &format!("SELECT timestamp, motion FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp >= ?1 AND timestamp < ?2 AND room_id = ?3"),
+            )?.query_map(params![Ts(start), Ts(end), room], |row| Ok((time(row, 0)?, row.get(1)?)))?
+                .collect()
+        }).await?;
+
+        let mut minutes: BTreeMap<DateTime<Utc>, bool> = BTreeMap::new();
+        for (t, motion) in readings {
+            if quiet.contains(days.local(t).hour()) {
+                *minutes.entry(t.duration_trunc(ChronoDuration::minutes(1))?).or_default() |= motion;
+            }
+        }
+
+        Ok(minutes.into_iter().collect())
+    }
+
+    async fn upsert_fall_risk(&self, risk: &FallRiskScore) -> Result<(), DbError> {
+        let risk = risk.clone();
+
+        self.call(move |conn| conn.execute(
+            "INSERT INTO fall_risk_scores
+                (patient_id, day, score, level, window_days, falls, night_restlessness,
+                 bed_exits_per_night, computed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             ON CONFLICT (patient_id, day) DO UPDATE SET
+                score = excluded.score, level = excluded.level, window_days = excluded.window_days,
+                falls = excluded.falls, night_restlessness = excluded.night_restlessness,
+                bed_exits_per_night = excluded.bed_exits_per_night, computed_at = excluded.computed_at",
+            params![risk.patient_id, risk.date.to_string(), risk.score, risk.level, risk.window_days,
+                    risk.factors.falls as i64, risk.factors.night_restlessness,
+                    risk.factors.bed_exits_per_night, Ts(Utc::now())],
+        )).await?;
+
+        Ok(())
+    }
+
+    async fn get_fall_risk_history(
+        &self,
+        patient_id: &str,
+        since: NaiveDate,
+    ) -> Result<Vec<FallRiskScore>, DbError> {
+        let patient_id = patient_id.to_string();
+
+        let rows: Vec<Option<FallRiskScore>> = self.call(move |conn| {
+            conn.prepare(
+                "SELECT patient_id, day, score, level, window_days, falls, night_restlessness,
+                        bed_exits_per_night
+                 FROM fall_risk_scores
+                 WHERE patient_id = ?1 AND day >= ?2
+                 ORDER BY day DESC",
+            )?.query_map(params![patient_id, since.to_string()], |row| {
+                let Some(date) = date(row, 1)? else {
+                    return Ok(None);
+                };
+                Ok(Some(FallRiskScore {
+                    patient_id: row.get(0)?,
+                    date,
+                    score: row.get(2)?,
+                    level: row.get(3)?,
+                    window_days: row.get(4)?,
+                    factors: FallRiskFactors {
+                        falls: row.get::<_, i64>(5)? as u64,
+                        night_restlessness: row.get(6)?,
+                        bed_exits_per_night: row.get(7)?,
+                    },
+                }))
+            })?.collect()
+        }).await?;
+
+        Ok(rows.into_iter().flatten().collect())
+    }
+
+    async fn insert_manual_observation(
+        &self,
+        observation: &ManualObservation,
+    ) -> Result<ManualObservation, DbError> {
+        let o = observation.clone();
+
+        let (id, patient_id) = self.call(move |conn| conn.query_row(
+            "INSERT INTO manual_observations
+                (room_id, patient_id, kind, value, observed_at, recorded_by, note, recorded_at)
+             VALUES (?1, (SELECT id FROM patients WHERE room_id = ?1), ?2, ?3, ?4, ?5, ?6, ?7)
+             RETURNING id, patient_id",
+            params![o.room, o.kind.as_str(), o.value, Ts(o.observed_at), o.recorded_by, o.note, Ts(Utc::now())],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )).await?;
+
+        Ok(ManualObservation {
+            id: Some(id),
+            patient_id,
+            ..observation.clone()
+        })
+    }
+
+    async fn get_manual_observations(
+        &self,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        room: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<Vec<ManualObservation>, DbError> {
+        let room = room.map(str::to_string);
+
+        let rows: Vec<Option<ManualObservation>> = self.call(move |conn| {
+            conn.prepare(
+                "SELECT id, room_id, patient_id, kind, value, observed_at, recorded_by, note
+                 FROM manual_observations
+                 WHERE (?1 IS NULL OR observed_at >= ?1)
+                   AND (?2 IS NULL OR observed_at <= ?2)
+                   AND (?3 IS NULL OR room_id = ?3)
+                 ORDER BY observed_at DESC, id DESC
+                 LIMIT ?4",
+            )?.query_map(
+                // A negative limit returns all rows
+                params![start.map(Ts), end.map(Ts), room, limit.unwrap_or(-1)],
+                Self::row_to_manual_observation,
+            )?.collect()
+        }).await?;
+
+        Ok(rows.into_iter().flatten().collect())
+    }
+
+    async fn compute_daily_summaries(
+        &self,
+        day: NaiveDate,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<DailySummary>, DbError> {
+
+        // A still period at the end of the day lasts until the day ends, or until
+        // now for a day that isn't over
+        let still_until = end.min(Utc::now());
+
+        type Stats = (String, i64, i64, f64, f32, f32, i64, i64, i64);
+        let (stats, longest_still) = self.analytics(move |conn| {
+            let stats: Vec<Stats> = conn.prepare(
+                &format!("WITH raised AS (
+                    SELECT room_id,
+                           COUNT(*) FILTER (WHERE alert_type = 'fall') AS falls,
+                           COUNT(*) FILTER (WHERE alert_type = 'inactivity') AS inactivity,
+                           COUNT(*) FILTER (WHERE alert_type LIKE 'sensor_fault:%') AS sensor_faults
+                    FROM alerts
+                    WHERE triggered_at >= ?1 AND triggered_at < ?2
+                    GROUP BY room_id
+                 )
+                 SELECT s.room_id, COUNT(*), COUNT(*) FILTER (WHERE s.motion),
+                        AVG(s.temperature), MIN(s.temperature), MAX(s.temperature),
+                        COALESCE(MAX(r.falls), 0), COALESCE(MAX(r.inactivity), 0),
+                        COALESCE(MAX(r.sensor_faults), 0)
+                 FROM (SELECT * FROM {EXPANDED_READINGS}) s
+                 LEFT JOIN raised r ON r.room_id = s.room_id
+                 WHERE s.deleted_at IS NULL AND s.timestamp >= ?1 AND s.timestamp < ?2 AND s.room_id IS NOT NULL
+                 GROUP BY s.room_id
+                 ORDER BY s.room_id"),
+            )?.query_map(params![Ts(start), Ts(end)], |row| Ok((
+                row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?,
+                row.get(6)?, row.get(7)?, row.get(8)?,
+            )))?.collect::<rusqlite::Result<_>>()?;
+
+            let longest_still: HashMap<String, u64> = conn.prepare(&format!(
+                "WITH readings AS (
+                    SELECT room_id, timestamp, id, motion,
+                           motion IS NOT LAG(motion) OVER (PARTITION BY room_id ORDER BY timestamp, id) AS starts_run
+                    FROM {EXPANDED_READINGS}
+                    WHERE deleted_at IS NULL AND timestamp >= ?1 AND timestamp < ?2 AND room_id IS NOT NULL
+                 ),
+                 runs AS (
+                    SELECT room_id, motion, timestamp AS started,
+                           COALESCE(LEAD(timestamp) OVER (PARTITION BY room_id ORDER BY timestamp, id), ?3) AS ended
+                    FROM readings
+                    WHERE starts_run
+                 )
+                 SELECT room_id, MAX({RUN_MINUTES})
+                 FROM runs
+                 WHERE NOT motion
+                 GROUP BY room_id",
+            ))?.query_map(params![Ts(start), Ts(end), Ts(still_until)], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+            })?.collect::<rusqlite::Result<_>>()?;
+
+            Ok((stats, longest_still))
+        }).await?;
+
+        Ok(stats.into_iter().map(|(room, readings, motion_readings, avg, min, max, falls, inactivity, faults)| {
+            let (readings, motion_readings) = (readings as u64, motion_readings as u64);
+            DailySummary {
+                longest_still_period_mins: longest_still.get(room.as_str()).copied().unwrap_or(0),
+                room,
+                date: day,
+                readings,
+                motion_readings,
+                motion_percent: (activity_score(motion_readings, readings) * 100.0).round() / 100.0,
+                avg_temperature: avg,
+                min_temperature: min,
+                max_temperature: max,
+                fall_alerts: falls as u64,
+                inactivity_alerts: inactivity as u64,
+                sensor_fault_alerts: faults as u64,
+            }
+        }).collect())
+    }
+
+    async fn upsert_daily_summaries(&self, summaries: &[DailySummary]) -> Result<(), DbError> {
+        let summaries = summaries.to_vec();
+
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            for s in &summaries {
+                tx.execute(
+                    "INSERT INTO daily_summary
+                        (room_id, day, readings, motion_readings, avg_temperature, min_temperature,
+                         max_temperature, fall_alerts, inactivity_alerts, sensor_fault_alerts,
+                         longest_still_period_mins, computed_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                     ON CONFLICT (room_id, day) DO UPDATE SET
+                        readings = excluded.readings, motion_readings = excluded.motion_readings,
+                        avg_temperature = excluded.avg_temperature,
+                        min_temperature = excluded.min_temperature,
+                        max_temperature = excluded.max_temperature,
+                        fall_alerts = excluded.fall_alerts, inactivity_alerts = excluded.inactivity_alerts,
+                        sensor_fault_alerts = excluded.sensor_fault_alerts,
+                        longest_still_period_mins = excluded.longest_still_period_mins,
+                        computed_at = excluded.computed_at",
+                    params![s.room, s.date.to_string(), s.readings as i64, s.motion_readings as i64,
+                            s.avg_temperature, s.min_temperature, s.max_temperature,
+                            s.fall_alerts as i64, s.inactivity_alerts as i64,
+                            s.sensor_fault_alerts as i64, s.longest_still_period_mins as i64,
+                            Ts(Utc::now())],
+                )?;
+            }
+            tx.commit()
+        }).await?;
+
+        Ok(())
+    }
+
+    async fn last_daily_summary_day(&self) -> Result<Option<NaiveDate>, DbError> {
+        self.call(|conn| conn.query_row("SELECT MAX(day) FROM daily_summary", [], |row| date(row, 0))).await
+    }
+
+    async fn get_daily_summaries(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        room: Option<&str>,
+    ) -> Result<Vec<DailySummary>, DbError> {
+        let room = room.map(str::to_string);
+
+        let rows: Vec<Option<DailySummary>> = self.call(move |conn| {
+            conn.prepare(
+                "SELECT room_id, day, readings, motion_readings, avg_temperature, min_temperature,
+                        max_temperature, fall_alerts, inactivity_alerts, sensor_fault_alerts,
+                        longest_still_period_mins
+                 FROM daily_summary
+                 WHERE day BETWEEN ?1 AND ?2
+                   AND (?3 IS NULL OR room_id = ?3)
+                 ORDER BY day, room_id",
+            )?.query_map(params![from.to_string(), to.to_string(), room], |row| {
+                let Some(date) = date(row, 1)? else {
+                    return Ok(None);
+                };
+                let readings = row.get::<_, i64>(2)? as u64;
+                let motion_readings = row.get::<_, i64>(3)? as u64;
+                Ok(Some(DailySummary {
+                    room: row.get(0)?,
+                    date,
+                    readings,
+                    motion_readings,
+                    motion_percent: (activity_score(motion_readings, readings) * 100.0).round() / 100.0,
+                    avg_temperature: row.get(4)?,
+                    min_temperature: row.get(5)?,
+                    max_temperature: row.get(6)?,
+                    fall_alerts: row.get::<_, i64>(7)? as u64,
+                    inactivity_alerts: row.get::<_, i64>(8)? as u64,
+                    sensor_fault_alerts: row.get::<_, i64>(9)? as u64,
+                    longest_still_period_mins: row.get::<_, i64>(10)? as u64,
+                }))
+            })?.collect()
+        }).await?;
+
+        Ok(rows.into_iter().flatten().collect())
+    }
+
+    async fn research_aggregates(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        by_ward: bool,
+        by_day: Option<&FacilityDays>,
+    ) -> Result<Vec<ResearchGroup>, DbError> {
+        // SQLite has no time zones, so readings are matched against the
+        // bounds of each day, as `[day, start, end]` JSON arrays
+        let days = by_day.map(|days| {
+            let first = days.day_of(start);
+            let spans: Vec<[String; 3]> = first
+                .iter_days()
+                .map(|day| (day, days.bounds(day)))
+                .take_while(|(_, (day_start, _))| *day_start < end)
+                .map(|(day, (day_start, day_end))| [
+                    day.to_string(),
+                    day_start.format(TIMESTAMP_FORMAT).to_string(),
+                    day_end.format(TIMESTAMP_FORMAT).to_string(),
+                ])
+                .collect();
+            serde_json::to_string(&spans).unwrap_or_default()
+        });
+        type Stats = (Option<String>, Option<String>, i64, i64, i64, f64, i64, i64);
+        let stats: Vec<Stats> = self.analytics(move |conn| {
+            conn.prepare(
+                &format!("WITH raised AS (
+                    SELECT reading_id,
+                           COUNT(*) FILTER (WHERE alert_type = 'fall') AS falls,
+                           COUNT(*) FILTER (WHERE alert_type = 'inactivity') AS inactivity
+                    FROM alerts
+                    WHERE triggered_at >= ?1 AND triggered_at < ?2 AND reading_id IS NOT NULL
+                    GROUP BY reading_id
+                 )
+                 SELECT CASE WHEN ?3 THEN r.ward_id END AS ward,
+                        (SELECT json_extract(d.value, '$[0]') FROM json_each(?4) d
+                         WHERE s.timestamp >= json_extract(d.value, '$[1]')
+                           AND s.timestamp < json_extract(d.value, '$[2]')) AS day,
+                        COUNT(DISTINCT s.patient_id), COUNT(*), COUNT(*) FILTER (WHERE s.motion),
+                        AVG(s.temperature), COALESCE(SUM(a.falls), 0), COALESCE(SUM(a.inactivity), 0)
+                 FROM (SELECT * FROM {EXPANDED_READINGS}) s
+                 JOIN patient_consent c ON c.patient_id = s.patient_id AND c.research_export
+                 LEFT JOIN rooms r ON r.id = s.room_id
+                 LEFT JOIN raised a ON a.reading_id = s.id
+                 WHERE s.deleted_at IS NULL AND s.timestamp >= ?1 AND s.timestamp < ?2
+                 GROUP BY 1, 2
+                 ORDER BY 2 NULLS FIRST, 1 NULLS FIRST"),
+            )?.query_map(params![Ts(start), Ts(end), by_ward, days], |row| Ok((
+                row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?,
+                row.get(6)?, row.get(7)?,
+            )))?.collect::<rusqlite::Result<_>>()
+        }).await?;
+
+        Ok(stats.into_iter().map(|(ward, day, patients, readings, motion_readings, avg, falls, inactivity)| {
+            let (readings, motion_readings) = (readings as u64, motion_readings as u64);
+            ResearchGroup {
+                ward,
+                date: day.and_then(|d| d.parse().ok()),
+                patients: patients as u64,
+                readings,
+                motion_percent: activity_score(motion_readings, readings),
+                avg_temperature: avg,
+                fall_alerts: falls as u64,
+                inactivity_alerts: inactivity as u64,
+            }
+        }).collect())
+    }
+
+    async fn get_hourly_activity(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        timezone: Tz,
+        room: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Vec<HourlyActivity>, DbError> {
+        let (room, tag) = (room.map(str::to_string), tag.map(str::to_string));
+
+        // Timestamps start with `YYYY-MM-DDTHH`, so the first 13 characters are the hour
+        let rows: Vec<(DateTime<Utc>, i64, i64, f64, Option<f64>)> = self.analytics(move |conn| {
+            conn.prepare(
+                &format!("SELECT substr(timestamp, 1, 13) || ':00:00Z' AS hour,
+                        COUNT(*),
+                        COUNT(*) FILTER (WHERE motion),
+                        COALESCE(AVG(sound_level), 0.0),
+                        AVG(light)
+                 FROM {EXPANDED_READINGS}
+                 WHERE deleted_at IS NULL AND timestamp >= ?1 AND timestamp < ?2
+                   AND (?3 IS NULL OR id IN (
+                     SELECT rt.reading_id FROM reading_tags rt JOIN tags t ON t.id = rt.tag_id
+                     WHERE t.name = ?3))
+                   AND (?4 IS NULL OR room_id = ?4)
+                 GROUP BY 1
+                 ORDER BY hour"),
+            )?.query_map(params![Ts(start), Ts(end), tag, room], |row| {
+                Ok((time(row, 0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+            })?.collect()
+        }).await?;
+
+        Ok(rows.into_iter().map(|(hour, total, motion_count, avg_sound, avg_light)| {
+            let activity_score = activity_score(motion_count as u64, total as u64);
+            HourlyActivity {
+                hour: hour.with_timezone(&timezone).format("%H:00").to_string(),
+                activity_score: (activity_score * 100.0).round() / 100.0,
+                readings: total as u64,
+                avg_sound_level: (avg_sound * 100.0).round() / 100.0,
+                filled_readings: 0,
+                avg_light: avg_light.map(|lux| (lux * 10.0).round() / 10.0),
+            }
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use patient_monitor_types::analysis::compaction_runs;
+
+    /// A migrated database in memory, shared by the connections of one test
+    async fn database() -> Sqlite {
+        let mut config = DbConfig::from_env();
+        config.backend = BackendKind::Sqlite;
+        config.sqlite_path = format!("file:monitor_test_{}?mode=memory&cache=shared", uuid::Uuid::new_v4().simple()).into();
+        config.auto_migrate = true;
+        let db = Sqlite::open(config).await.unwrap();
+        db.register_rooms(&["room-101".to_string(), "room-102".to_string()]).await.unwrap();
+        db
+    }
+
+    fn at(minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 15, 10, minute, 0).unwrap()
+    }
+
+    fn event(room: &str, minute: u32, alerts: AlertSet) -> SensorEvent {
+        SensorEvent {
+            id: None,
+            seq: None,
+            room: room.to_string(),
+            device: None,
+            patient_id: None,
+            reading: SensorReading {
+                temperature: 22.0 + minute as f32 / 10.0,
+                motion: !alerts.is_empty(),
+                sound_level: 30,
+                timestamp: at(minute),
+                humidity: Some(45.0),
+                ..Default::default()
+            },
+            alerts,
+        }
+    }
+
+    fn minutes(events: &[SensorEvent]) -> Vec<u32> {
+        events.iter().map(|e| e.reading.timestamp.minute()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_get_readings() {
+        let db = database().await;
+        let stored = db.insert_readings_batch(&[
+            event("room-101", 0, AlertSet::new()),
+            event("room-102", 1, AlertSet::new()),
+            event("room-101", 2, AlertSet::from(AlertType::Fall)),
+        ]).await.unwrap();
+        assert_eq!(stored.len(), 3);
+        assert!(stored.windows(2).all(|w| w[0].seq < w[1].seq));
+
+        let recent = db.get_recent_readings(10, None, None, None).await.unwrap();
+        assert_eq!(minutes(&recent), vec![2, 1, 0]);
+        let room = db.get_recent_readings(10, Some("room-101"), None, None).await.unwrap();
+        assert_eq!(minutes(&room), vec![2, 0]);
+
+        let fall = db.get_reading_by_id(stored[2].id).await.unwrap().unwrap();
+        assert_eq!(fall.room, "room-101");
+        assert_eq!(fall.reading.humidity, Some(45.0));
+        assert!(fall.alerts.contains(AlertType::Fall));
+        assert!(db.get_reading_by_id(stored[2].id + 100).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_range_count_and_export() {
+        let db = database().await;
+        let events: Vec<SensorEvent> = (0..5).map(|m| event("room-101", m, AlertSet::new())).collect();
+        db.insert_readings_batch(&events).await.unwrap();
+        db.insert_readings_batch(&[event("room-102", 3, AlertSet::new())]).await.unwrap();
+
+        // Both ends of a range are inclusive, as in PostgreSQL
+        assert_eq!(db.count_readings_in_range(at(1), at(3), None).await.unwrap(), 4);
+        assert_eq!(db.count_readings_in_range(at(1), at(3), Some("room-101")).await.unwrap(), 3);
+        let range = db.get_readings_in_range(at(1), at(3), Some("room-101"), None, None, None).await.unwrap();
+        assert_eq!(minutes(&range), vec![3, 2, 1]);
+
+        // Pages are oldest first and continue after the last reading of the previous one
+        let first = db.export_range(at(0), at(10), Some("room-101"), None, 2).await.unwrap();
+        assert_eq!(minutes(&first), vec![0, 1]);
+        let last = first.last().unwrap();
+        let after = Some((last.reading.timestamp, last.id.unwrap()));
+        let second = db.export_range(at(0), at(10), Some("room-101"), after, 10).await.unwrap();
+        assert_eq!(minutes(&second), vec![2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_soft_deleted_readings_are_left_out() {
+        let db = database().await;
+        let stored = db.insert_readings_batch(&[
+            event("room-101", 0, AlertSet::new()),
+            event("room-101", 1, AlertSet::from(AlertType::Fall)),
+        ]).await.unwrap();
+        let fall = stored[1].id;
+
+        assert!(db.mark_deleted(fall, at(30)).await.unwrap());
+        assert!(!db.mark_deleted(fall, at(31)).await.unwrap());
+        assert!(db.get_reading_by_id(fall).await.unwrap().is_none());
+        assert_eq!(minutes(&db.get_recent_readings(10, None, None, None).await.unwrap()), vec![0]);
+        assert_eq!(db.count_readings_in_range(at(0), at(10), None).await.unwrap(), 1);
+        assert_eq!(db.export_range(at(0), at(10), None, None, 10).await.unwrap().len(), 1);
+
+        let summary = db.get_alert_summary().await.unwrap();
+        assert_eq!((summary.total_readings, summary.fall_alerts), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_summary_counts_readings_and_alerts() {
+        let db = database().await;
+        let stored = db.insert_readings_batch(&[
+            event("room-101", 0, AlertSet::new()),
+            event("room-101", 1, AlertSet::from(AlertType::Fall)),
+            event("room-102", 2, AlertSet::from(AlertType::Inactivity)),
+            event("room-102", 3, AlertSet::from(AlertType::Inactivity)),
+        ]).await.unwrap();
+
+        let summary = db.get_alert_summary().await.unwrap();
+        assert_eq!((summary.total_readings, summary.fall_alerts, summary.inactivity_alerts), (4, 1, 2));
+
+        // Purging a reading counts it out, and a soft-deleted one isn't counted out twice
+        db.mark_deleted(stored[2].id, at(30)).await.unwrap();
+        db.delete_readings(&[stored[2].id, stored[3].id]).await.unwrap();
+        let summary = db.get_alert_summary().await.unwrap();
+        assert_eq!((summary.total_readings, summary.fall_alerts, summary.inactivity_alerts), (2, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_merging_runs_keeps_the_summary() {
+        let db = database().await;
+        let still: Vec<SensorEvent> = (0..4)
+            .map(|m| {
+                let mut event = event("room-101", m, AlertSet::new());
+                event.reading.temperature = 22.0;
+                event
+            })
+            .collect();
+        db.insert_readings_batch(&still).await.unwrap();
+        let before = db.get_alert_summary().await.unwrap();
+
+        let rows = db.get_compaction_rows("room-101", at(0), at(10)).await.unwrap();
+        let runs = compaction_runs(&rows, ChronoDuration::minutes(5), ChronoDuration::hours(1));
+        assert_eq!(db.merge_runs(&runs).await.unwrap(), 3);
+
+        assert_eq!(db.get_alert_summary().await.unwrap().total_readings, before.total_readings);
+        assert_eq!(db.count_readings_in_range(at(0), at(10), None).await.unwrap(), 4);
+
+        // Purging the interval takes all its readings out of the totals
+        assert_eq!(db.delete_readings(&[runs[0].id]).await.unwrap(), 1);
+        assert_eq!(db.get_alert_summary().await.unwrap().total_readings, before.total_readings - 4);
+    }
+
+    #[tokio::test]
+    async fn test_aggregates_count_merged_readings() {
+        let db = database().await;
+        let patient = Patient {
+            id: "patient-1".to_string(),
+            family_name: "Doe".to_string(),
+            given_names: Vec::new(),
+            birth_date: None,
+            gender: None,
+            room: Some("room-101".to_string()),
+        };
+        db.insert_patient(&patient).await.unwrap();
+        let consent = Consent { continuous_monitoring: true, ehr_sharing: false, research_export: true, updated_at: None };
+        db.set_consent("patient-1", &consent).await.unwrap();
+        let still: Vec<SensorEvent> = (0..4)
+            .map(|m| {
+                let mut event = event("room-101", m, AlertSet::new());
+                event.reading.temperature = 22.0;
+                event.reading.light = Some(120.0);
+                event
+            })
+            .collect();
+        db.insert_readings_batch(&still).await.unwrap();
+        let rows = db.get_compaction_rows("room-101", at(0), at(10)).await.unwrap();
+        let runs = compaction_runs(&rows, ChronoDuration::minutes(5), ChronoDuration::hours(1));
+        assert_eq!(db.merge_runs(&runs).await.unwrap(), 3);
+
+        let (start, end) = (at(0), at(30));
+        let activity = db.get_activity_analysis(start, end, None, None).await.unwrap();
+        assert_eq!(activity.total_readings, 4);
+        assert_eq!(activity.avg_temperature, 22.0);
+        assert_eq!(activity.longest_still_period_mins, 30);
+        let hourly = db.get_hourly_activity(start, end, chrono_tz::UTC, None, None).await.unwrap();
+        assert_eq!(hourly.iter().map(|h| h.readings).sum::<u64>(), 4);
+        assert_eq!(db.get_light_sums(start, end, None, None).await.unwrap().readings, 4);
+        assert_eq!(db.count_night_awakenings(start, end, None, None).await.unwrap(), 0);
+        let sweep = db.get_fall_threshold_sweep(start, end, 20..=40, 10, None, None).await.unwrap();
+        assert!(sweep.iter().all(|point| point.fall_alerts == 0));
+
+        let day = start.date_naive();
+        let summaries = db.compute_daily_summaries(day, at(0), at(59)).await.unwrap();
+        assert_eq!(summaries.iter().map(|s| s.readings).sum::<u64>(), 4);
+        let research = db.research_aggregates(start, end, false, None).await.unwrap();
+        assert_eq!(research.iter().map(|g| g.readings).sum::<u64>(), 4);
+
+        // Each merged reading keeps its quiet-hours minute
+        let (days, quiet) = (FacilityDays::default(), QuietHours { start_hour: 9, end_hour: 12, noise_limit: 60 });
+        assert_eq!(db.get_night_motion_minutes("room-101", start, end, &days, &quiet).await.unwrap().len(), 4);
+        let noise = db.get_night_noise_minutes(start, end, &days, &quiet, "room-101").await.unwrap();
+        assert_eq!(noise.iter().map(|w| w.observed_minutes).sum::<u64>(), 4);
+    }
+}
@@ -0,0 +1,72 @@
+//! Public demo mode
+//!
+//! With `DEMO_MODE=true` the monitor runs as a public demo instance, e.g. for
+//! procurement evaluations: the full API and dashboard are served, but only
+//! from synthetic data, and nothing can be changed.
+//!
+//! - Live readings are generated as in mock mode.
+//! - On startup `DEMO_SEED_DAYS` days of synthetic history are seeded (see
+//!   `seed`), unless the period already has readings. Anonymized readings
+//!   can be loaded instead with `POST /api/import` before the demo starts;
+//!   its CSV carries no patient details.
+//! - The monitor refuses to start on a database with registered patients,
+//!   so a demo pointed at a production database by mistake exposes nothing.
+//! - Requests other than reads (`GET`, `HEAD`, `OPTIONS`) are refused with
+//!   403 `demo_read_only`, including chat integrations and device uploads,
+//!   and ack links (see `chatops`) aren't sent or accepted.
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+use tracing::info;
+
+use crate::db::Database;
+use crate::seed::{self, SeedError, SeedOptions};
+use patient_monitor_types::api::{ApiError, MonitorSettings};
+
+/// Check that the database holds no patients and seed the demo's history
+pub async fn prepare(
+    db: &Database,
+    settings: &MonitorSettings,
+    rooms: &[String],
+    seed_days: u32,
+) -> Result<(), String> {
+    let patients = db.get_patients(None)
+        .await
+        .map_err(|e| format!("Failed to check for registered patients: {}", e))?;
+    if !patients.is_empty() {
+        return Err(format!(
+            "Refusing to run a demo on a database with {} registered patients; give the demo a database of its own",
+            patients.len(),
+        ));
+    }
+    if seed_days == 0 {
+        return Ok(());
+    }
+
+    let options = SeedOptions { days: seed_days, ..SeedOptions::default() };
+    match seed::seed(db, &options, settings, rooms).await {
+        Ok(_) => Ok(()),
+        Err(SeedError::DataExists(n)) => {
+            info!("Demo database already has {} readings in the last {} days, not seeding", n, seed_days);
+            Ok(())
+        }
+        Err(e) => Err(format!("Failed to seed demo data: {}", e)),
+    }
+}
+
+/// Refuse every request that would change something
+pub async fn read_only(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+    Ok(req.into_response(
+        HttpResponse::Forbidden()
+            .json(ApiError::new("demo_read_only", "This is a demo; changes are disabled")),
+    ))
+}
@@ -0,0 +1,452 @@
+//! Research dataset export
+//!
+//! `GET /api/export/readings` returns readings as CSV or JSON for sharing
+//! outside the care team, e.g. with students. Only readings from rooms whose
+//! patients consent to research exports are included. Callers pick the
+//! columns, can replace room and patient IDs with pseudonyms and can round
+//! timestamps down to a coarser granularity.
+//!
+//! `GET /api/export` streams the same readings as CSV or Parquet, for
+//! periods too long to hold in memory: readings are read a page at a time
+//! and each page is sent as soon as it is written, a Parquet file getting
+//! one row group per page.
+//!
+//! `GET /api/export/sound-matrix` streams the sound levels of all those
+//! rooms as a time-aligned matrix, a row per step and a column per room, for
+//! correlating noise across a ward. The matrix is built as the pages are
+//! read (see `SoundMatrix`), so its memory doesn't grow with the window.
+//!
+//! Pseudonyms are an HMAC of the ID keyed with `EXPORT_PSEUDONYM_KEY`, so the
+//! same room gets the same pseudonym in every export, but nobody without the
+//! key can map it back by hashing known room IDs.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use hmac::{Hmac, KeyInit, Mac};
+use parquet::basic::Compression;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, FloatType, Int32Type, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+
+use crate::access_log::csv_field;
+use crate::fhir::SensorEvent;
+use patient_monitor_types::analysis::MatrixRow;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Hex digits kept of a pseudonym's HMAC
+const PSEUDONYM_LEN: usize = 16;
+
+/// Readings read and sent at a time by streaming exports
+pub const EXPORT_PAGE_SIZE: usize = 5000;
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportConfig {
+    /// Key pseudonyms are derived with; pseudonymized exports need one
+    pub pseudonym_key: Option<String>,
+}
+
+/// Column of an exported reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Id,
+    Timestamp,
+    Room,
+    PatientId,
+    Temperature,
+    Motion,
+    SoundLevel,
+    Humidity,
+    HeartRate,
+    Spo2,
+    DoorOpen,
+    Light,
+    Co2,
+    Presence,
+    Acceleration,
+    Alerts,
+}
+
+impl Column {
+    pub const ALL: [Column; 16] = [
+        Column::Id,
+        Column::Timestamp,
+        Column::Room,
+        Column::PatientId,
+        Column::Temperature,
+        Column::Motion,
+        Column::SoundLevel,
+        Column::Humidity,
+        Column::HeartRate,
+        Column::Spo2,
+        Column::DoorOpen,
+        Column::Light,
+        Column::Co2,
+        Column::Presence,
+        Column::Acceleration,
+        Column::Alerts,
+    ];
+
+    /// Exported when no columns are requested; reading IDs are left out as
+    /// they link rows back to the live API
+    pub const DEFAULT: [Column; 15] = [
+        Column::Timestamp,
+        Column::Room,
+        Column::PatientId,
+        Column::Temperature,
+        Column::Motion,
+        Column::SoundLevel,
+        Column::Humidity,
+        Column::HeartRate,
+        Column::Spo2,
+        Column::DoorOpen,
+        Column::Light,
+        Column::Co2,
+        Column::Presence,
+        Column::Acceleration,
+        Column::Alerts,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::Timestamp => "timestamp",
+            Column::Room => "room",
+            Column::PatientId => "patient_id",
+            Column::Temperature => "temperature",
+            Column::Motion => "motion",
+            Column::SoundLevel => "sound_level",
+            Column::Humidity => "humidity",
+            Column::HeartRate => "heart_rate",
+            Column::Spo2 => "spo2",
+            Column::DoorOpen => "door_open",
+            Column::Light => "light",
+            Column::Co2 => "co2",
+            Column::Presence => "presence",
+            Column::Acceleration => "acceleration",
+            Column::Alerts => "alerts",
+        }
+    }
+
+    /// Field of the column in a Parquet schema
+    fn parquet_field(self) -> String {
+        let field = match self {
+            Column::Id => "optional int64",
+            Column::Timestamp => "required int64",
+            Column::Room => "required binary",
+            Column::PatientId => "optional binary",
+            Column::Temperature => "required float",
+            Column::Motion => "required boolean",
+            Column::SoundLevel => "required int32",
+            Column::Humidity | Column::Light | Column::Acceleration => "optional float",
+            Column::HeartRate | Column::Spo2 | Column::Co2 => "optional int32",
+            Column::DoorOpen | Column::Presence => "optional boolean",
+            Column::Alerts => "required binary",
+        };
+        let annotation = match self {
+            Column::Timestamp => " (TIMESTAMP(MICROS,true))",
+            Column::Room | Column::PatientId | Column::Alerts => " (STRING)",
+            _ => "",
+        };
+        format!("{} {}{};", field, self.as_str(), annotation)
+    }
+}
+
+impl std::str::FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Column::ALL
+            .into_iter()
+            .find(|c| c.as_str() == s.trim())
+            .ok_or_else(|| format!(
+                "Unknown column '{}', expected one of {}",
+                s.trim(),
+                Column::ALL.map(Column::as_str).join(", "),
+            ))
+    }
+}
+
+/// Parse a comma-separated column list, keeping the given order
+pub fn parse_columns(list: &str) -> Result<Vec<Column>, String> {
+    let mut columns = Vec::new();
+    for column in list.split(',').filter(|c| !c.trim().is_empty()) {
+        let column = column.parse()?;
+        if !columns.contains(&column) {
+            columns.push(column);
+        }
+    }
+    if columns.is_empty() {
+        return Err("At least one column is required".to_string());
+    }
+    Ok(columns)
+}
+
+/// How readings are written out
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub columns: Vec<Column>,
+    /// Replace room and patient IDs with pseudonyms derived with this key
+    pub pseudonym_key: Option<String>,
+    /// Round timestamps down to a multiple of this
+    pub round: Option<Duration>,
+}
+
+impl ExportOptions {
+    fn pseudonym(&self, kind: &str, id: &str) -> String {
+        let Some(key) = &self.pseudonym_key else {
+            return id.to_string();
+        };
+        let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+        // Rooms and patients with the same ID get different pseudonyms
+        mac.update(kind.as_bytes());
+        mac.update(b":");
+        mac.update(id.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        digest.iter().map(|b| format!("{:02x}", b)).collect::<String>()[..PSEUDONYM_LEN].to_string()
+    }
+
+    fn timestamp(&self, t: DateTime<Utc>) -> DateTime<Utc> {
+        self.round.and_then(|round| t.duration_trunc(round).ok()).unwrap_or(t)
+    }
+
+    fn value(&self, column: Column, event: &SensorEvent) -> Value {
+        match column {
+            Column::Id => json!(event.id),
+            Column::Timestamp => json!(self.timestamp(event.reading.timestamp)),
+            Column::Room => json!(self.pseudonym("room", &event.room)),
+            Column::PatientId => json!(event.patient_id.as_deref().map(|p| self.pseudonym("patient", p))),
+            Column::Temperature => json!(event.reading.temperature),
+            Column::Motion => json!(event.reading.motion),
+            Column::SoundLevel => json!(event.reading.sound_level),
+            Column::Humidity => json!(event.reading.humidity),
+            Column::HeartRate => json!(event.reading.heart_rate),
+            Column::Spo2 => json!(event.reading.spo2),
+            Column::DoorOpen => json!(event.reading.door_open),
+            Column::Light => json!(event.reading.light),
+            Column::Co2 => json!(event.reading.co2),
+            Column::Presence => json!(event.reading.presence),
+            Column::Acceleration => json!(event.reading.acceleration),
+            Column::Alerts => json!(event.alerts.iter().map(|a| a.as_str()).collect::<Vec<_>>()),
+        }
+    }
+
+    /// Readings as JSON objects with the selected columns
+    pub fn to_json(&self, events: &[SensorEvent]) -> Vec<Map<String, Value>> {
+        events
+            .iter()
+            .map(|event| {
+                self.columns
+                    .iter()
+                    .map(|&c| (c.as_str().to_string(), self.value(c, event)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// CSV header of a sound matrix: `timestamp`, then a column per room
+    pub fn matrix_csv_header(&self, rooms: &[String]) -> String {
+        let mut out = "timestamp".to_string();
+        for room in rooms {
+            out.push(',');
+            out.push_str(&csv_field(&self.pseudonym("room", room)));
+        }
+        out.push('\n');
+        out
+    }
+
+    /// CSV lines of sound matrix rows, empty where a room sent no reading
+    pub fn matrix_csv_rows(&self, rows: &[MatrixRow]) -> String {
+        let mut out = String::new();
+        for row in rows {
+            out.push_str(&row.start.to_rfc3339());
+            for level in &row.levels {
+                out.push(',');
+                out.push_str(&level.map(|l| l.to_string()).unwrap_or_default());
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Readings as CSV with a header line; alerts are separated by `;`
+    pub fn to_csv(&self, events: &[SensorEvent]) -> String {
+        let mut out = self.csv_header();
+        out.push_str(&self.csv_rows(events));
+        out
+    }
+
+    pub fn csv_header(&self) -> String {
+        let mut out = self.columns.iter().map(|c| c.as_str()).collect::<Vec<_>>().join(",");
+        out.push('\n');
+        out
+    }
+
+    /// CSV lines of `events`, without the header
+    pub fn csv_rows(&self, events: &[SensorEvent]) -> String {
+        let mut out = String::new();
+        for event in events {
+            let fields: Vec<String> = self
+                .columns
+                .iter()
+                .map(|&column| match self.value(column, event) {
+                    Value::Null => String::new(),
+                    Value::String(s) => csv_field(&s),
+                    Value::Array(alerts) => alerts.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(";"),
+                    other => other.to_string(),
+                })
+                .collect();
+            out.push_str(&fields.join(","));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Sink of a Parquet export, drained as each row group is written
+#[derive(Debug, Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Parquet file written a row group at a time. Timestamps are UTC
+/// microseconds, alerts separated by `;` as in CSV.
+pub struct ParquetExport {
+    options: ExportOptions,
+    writer: SerializedFileWriter<SharedBuffer>,
+    buffer: SharedBuffer,
+}
+
+impl ParquetExport {
+    pub fn new(options: ExportOptions) -> Result<Self, ParquetError> {
+        let fields: String = options.columns.iter().map(|c| c.parquet_field()).collect();
+        let schema = parse_message_type(&format!("message reading {{ {} }}", fields))?;
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let buffer = SharedBuffer::default();
+        let writer = SerializedFileWriter::new(buffer.clone(), Arc::new(schema), Arc::new(properties))?;
+        Ok(Self { options, writer, buffer })
+    }
+
+    /// Write `events` as one row group; returns the bytes of the file written
+    /// since the last call
+    pub fn write(&mut self, events: &[SensorEvent]) -> Result<Vec<u8>, ParquetError> {
+        let text = |values: Vec<String>| values.into_iter().map(|v| ByteArray::from(v.into_bytes())).collect::<Vec<_>>();
+        let options = &self.options;
+        let mut row_group = self.writer.next_row_group()?;
+        for &column in &options.columns {
+            let mut writer = row_group.next_column()?
+                .ok_or_else(|| ParquetError::General(format!("No column {} in schema", column.as_str())))?;
+            match column {
+                Column::Id => {
+                    let ids: Vec<i64> = events.iter().filter_map(|e| e.id).collect();
+                    let defined: Vec<i16> = events.iter().map(|e| e.id.is_some() as i16).collect();
+                    writer.typed::<Int64Type>().write_batch(&ids, Some(&defined), None)?;
+                }
+                Column::Timestamp => {
+                    let times: Vec<i64> = events.iter().map(|e| options.timestamp(e.reading.timestamp).timestamp_micros()).collect();
+                    writer.typed::<Int64Type>().write_batch(&times, None, None)?;
+                }
+                Column::Room => {
+                    let rooms = text(events.iter().map(|e| options.pseudonym("room", &e.room)).collect());
+                    writer.typed::<ByteArrayType>().write_batch(&rooms, None, None)?;
+                }
+                Column::PatientId => {
+                    let patients = text(events.iter()
+                        .filter_map(|e| e.patient_id.as_deref().map(|p| options.pseudonym("patient", p)))
+                        .collect());
+                    let defined: Vec<i16> = events.iter().map(|e| e.patient_id.is_some() as i16).collect();
+                    writer.typed::<ByteArrayType>().write_batch(&patients, Some(&defined), None)?;
+                }
+                Column::Temperature => {
+                    let temperatures: Vec<f32> = events.iter().map(|e| e.reading.temperature).collect();
+                    writer.typed::<FloatType>().write_batch(&temperatures, None, None)?;
+                }
+                Column::Motion => {
+                    let motion: Vec<bool> = events.iter().map(|e| e.reading.motion).collect();
+                    writer.typed::<BoolType>().write_batch(&motion, None, None)?;
+                }
+                Column::SoundLevel => {
+                    let levels: Vec<i32> = events.iter().map(|e| e.reading.sound_level).collect();
+                    writer.typed::<Int32Type>().write_batch(&levels, None, None)?;
+                }
+                Column::Humidity => {
+                    let humidities: Vec<f32> = events.iter().filter_map(|e| e.reading.humidity).collect();
+                    let defined: Vec<i16> = events.iter().map(|e| e.reading.humidity.is_some() as i16).collect();
+                    writer.typed::<FloatType>().write_batch(&humidities, Some(&defined), None)?;
+                }
+                Column::HeartRate => {
+                    let rates: Vec<i32> = events.iter().filter_map(|e| e.reading.heart_rate).collect();
+                    let defined: Vec<i16> = events.iter().map(|e| e.reading.heart_rate.is_some() as i16).collect();
+                    writer.typed::<Int32Type>().write_batch(&rates, Some(&defined), None)?;
+                }
+                Column::Spo2 => {
+                    let saturations: Vec<i32> = events.iter().filter_map(|e| e.reading.spo2).collect();
+                    let defined: Vec<i16> = events.iter().map(|e| e.reading.spo2.is_some() as i16).collect();
+                    writer.typed::<Int32Type>().write_batch(&saturations, Some(&defined), None)?;
+                }
+                Column::DoorOpen => {
+                    let doors: Vec<bool> = events.iter().filter_map(|e| e.reading.door_open).collect();
+                    let defined: Vec<i16> = events.iter().map(|e| e.reading.door_open.is_some() as i16).collect();
+                    writer.typed::<BoolType>().write_batch(&doors, Some(&defined), None)?;
+                }
+                Column::Light => {
+                    let levels: Vec<f32> = events.iter().filter_map(|e| e.reading.light).collect();
+                    let defined: Vec<i16> = events.iter().map(|e| e.reading.light.is_some() as i16).collect();
+                    writer.typed::<FloatType>().write_batch(&levels, Some(&defined), None)?;
+                }
+                Column::Co2 => {
+                    let ppm: Vec<i32> = events.iter().filter_map(|e| e.reading.co2).collect();
+                    let defined: Vec<i16> = events.iter().map(|e| e.reading.co2.is_some() as i16).collect();
+                    writer.typed::<Int32Type>().write_batch(&ppm, Some(&defined), None)?;
+                }
+                Column::Presence => {
+                    let present: Vec<bool> = events.iter().filter_map(|e| e.reading.presence).collect();
+                    let defined: Vec<i16> = events.iter().map(|e| e.reading.presence.is_some() as i16).collect();
+                    writer.typed::<BoolType>().write_batch(&present, Some(&defined), None)?;
+                }
+                Column::Acceleration => {
+                    let peaks: Vec<f32> = events.iter().filter_map(|e| e.reading.acceleration).collect();
+                    let defined: Vec<i16> = events.iter().map(|e| e.reading.acceleration.is_some() as i16).collect();
+                    writer.typed::<FloatType>().write_batch(&peaks, Some(&defined), None)?;
+                }
+                Column::Alerts => {
+                    let alerts = text(events.iter()
+                        .map(|e| e.alerts.iter().map(|a| a.as_str()).collect::<Vec<_>>().join(";"))
+                        .collect());
+                    writer.typed::<ByteArrayType>().write_batch(&alerts, None, None)?;
+                }
+            }
+            writer.close()?;
+        }
+        row_group.close()?;
+        Ok(self.buffer.take())
+    }
+
+    /// Finish the file; returns its remaining bytes, including the footer
+    pub fn finish(self) -> Result<Vec<u8>, ParquetError> {
+        let buffer = self.buffer.clone();
+        self.writer.close()?;
+        Ok(buffer.take())
+    }
+}
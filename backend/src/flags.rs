@@ -0,0 +1,142 @@
+//! Feature flags for staged rollouts
+//!
+//! Risky new behaviours are gated by a named flag that ops switch per room or
+//! for the whole facility through `/api/admin/flags`, without redeploying.
+//! Settings are stored in `feature_flags` and cached in a `FeatureFlags`
+//! shared by the readers and live sessions, so checking a flag never touches
+//! the database. A room's setting overrides the facility's, which overrides
+//! the flag's default.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use crate::db::{Database, DbError};
+
+/// Fall detection that also counts a loud sound shortly after motion, see
+/// `detect_fall_after_motion`
+pub const FALL_DETECTOR_V2: &str = "fall_detector_v2";
+/// Live readings sent as deltas to clients that negotiated them
+pub const WS_DELTAS: &str = "ws_deltas";
+
+/// A behaviour that can be switched at runtime
+#[derive(Debug, Clone, Copy)]
+pub struct Flag {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Whether it is on where nobody switched it
+    pub default: bool,
+}
+
+/// Every flag that can be switched; others are refused
+pub const FLAGS: &[Flag] = &[
+    Flag {
+        name: FALL_DETECTOR_V2,
+        description: "Raise a fall for a loud sound within a few seconds after motion, not only in the same reading",
+        default: false,
+    },
+    Flag {
+        name: WS_DELTAS,
+        description: "Send live readings as deltas to WebSocket clients that negotiated them",
+        default: true,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Flag> {
+    FLAGS.iter().find(|f| f.name == name)
+}
+
+/// A flag switched on or off for the facility or one room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagSetting {
+    pub name: String,
+    /// The room it applies to, or the whole facility if unset
+    pub room: Option<String>,
+    pub enabled: bool,
+    pub updated_at: DateTime<Utc>,
+    pub updated_by: Option<String>,
+}
+
+/// A flag's default and where it was switched, for `GET /api/admin/flags`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagState {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: bool,
+    /// Setting for the whole facility, if any
+    pub facility: Option<bool>,
+    /// Settings of single rooms
+    pub rooms: BTreeMap<String, bool>,
+}
+
+/// Flag name and room, `None` for the facility
+type Scope = (String, Option<String>);
+
+/// The stored flag settings
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags {
+    settings: Arc<RwLock<HashMap<Scope, bool>>>,
+}
+
+impl FeatureFlags {
+    pub async fn load(db: &Database) -> Result<Self, DbError> {
+        let flags = Self::default();
+        flags.reload(db).await?;
+        Ok(flags)
+    }
+
+    /// Pick up changes made through the API
+    pub async fn reload(&self, db: &Database) -> Result<(), DbError> {
+        let settings = db
+            .get_feature_flags()
+            .await?
+            .into_iter()
+            .map(|s| ((s.name, s.room), s.enabled))
+            .collect();
+        *self.settings.write().unwrap_or_else(|e| e.into_inner()) = settings;
+        Ok(())
+    }
+
+    /// Whether flag `name` is on in `room`. Unknown flags are off.
+    pub fn enabled(&self, name: &str, room: &str) -> bool {
+        let Some(flag) = find(name) else {
+            return false;
+        };
+        let settings = self.settings.read().unwrap_or_else(|e| e.into_inner());
+        settings
+            .get(&(name.to_string(), Some(room.to_string())))
+            .or_else(|| settings.get(&(name.to_string(), None)))
+            .copied()
+            .unwrap_or(flag.default)
+    }
+
+    /// Every flag with where it was switched
+    pub fn states(&self) -> Vec<FlagState> {
+        let settings = self.settings.read().unwrap_or_else(|e| e.into_inner());
+        FLAGS
+            .iter()
+            .map(|flag| {
+                let mut state = FlagState {
+                    name: flag.name,
+                    description: flag.description,
+                    default: flag.default,
+                    facility: None,
+                    rooms: BTreeMap::new(),
+                };
+                for ((name, room), enabled) in settings.iter() {
+                    match room {
+                        _ if name != flag.name => {}
+                        Some(room) => {
+                            state.rooms.insert(room.clone(), *enabled);
+                        }
+                        None => state.facility = Some(*enabled),
+                    }
+                }
+                state
+            })
+            .collect()
+    }
+}
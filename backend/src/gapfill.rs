@@ -0,0 +1,237 @@
+//! Gap filling for timeseries and activity analysis
+//!
+//! Short dropouts (serial hiccups, USB resets) leave holes in the stored
+//! readings that make charts jagged and split still periods. When enabled,
+//! gaps up to `max_gap` are filled with linearly interpolated readings that
+//! are flagged as `filled`; longer gaps are left alone and treated as
+//! "not observed".
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use chrono_tz::Tz;
+use std::collections::BTreeMap;
+
+use crate::db::{ActivityAnalysis, HourlyActivity, LightSums, StillPeriod, StillPeriods, TOP_STILL_PERIODS};
+use crate::fhir::{AlertSet, AlertType, SensorEvent, SensorReading};
+use patient_monitor_types::analysis::{activity_level, activity_score, longest_still_periods};
+
+/// Used when the sampling interval can't be inferred from the data
+const DEFAULT_INTERVAL_SECS: i64 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GapFill {
+    /// Longest gap that is interpolated
+    pub max_gap: Duration,
+    /// Spacing of filled readings, inferred from the data if not set
+    pub interval: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FilledEvent {
+    pub event: SensorEvent,
+    /// True if the reading was interpolated rather than measured
+    pub filled: bool,
+}
+
+/// Median spacing between consecutive readings (sorted oldest first)
+fn infer_interval(events: &[SensorEvent]) -> Duration {
+    let mut deltas: Vec<i64> = events
+        .windows(2)
+        .map(|w| (w[1].reading.timestamp - w[0].reading.timestamp).num_milliseconds())
+        .filter(|ms| *ms > 0)
+        .collect();
+    if deltas.is_empty() {
+        return Duration::seconds(DEFAULT_INTERVAL_SECS);
+    }
+    deltas.sort_unstable();
+    Duration::milliseconds(deltas[deltas.len() / 2])
+}
+
+fn interpolate(a: &SensorReading, b: &SensorReading, timestamp: DateTime<Utc>) -> SensorReading {
+    let span = (b.timestamp - a.timestamp).num_milliseconds() as f64;
+    let t = (timestamp - a.timestamp).num_milliseconds() as f64 / span;
+    SensorReading {
+        temperature: a.temperature + (b.temperature - a.temperature) * t as f32,
+        // Motion is only assumed where it was seen on both sides of the gap
+        motion: a.motion && b.motion,
+        sound_level: (a.sound_level as f64 + (b.sound_level - a.sound_level) as f64 * t).round() as i32,
+        humidity: a.humidity.zip(b.humidity).map(|(ha, hb)| ha + (hb - ha) * t as f32),
+        // Vital signs aren't made up for a patient who wasn't measured
+        heart_rate: None,
+        spo2: None,
+        // Nor is the door taken to have moved
+        door_open: None,
+        light: a.light.zip(b.light).map(|(la, lb)| la + (lb - la) * t as f32),
+        co2: a.co2.zip(b.co2).map(|(ca, cb)| ca + ((cb - ca) as f64 * t).round() as i32),
+        // Presence, like motion, only where the radar saw someone on both sides
+        presence: a.presence.zip(b.presence).map(|(pa, pb)| pa && pb),
+        // Nor is an impact made up
+        acceleration: None,
+        timestamp,
+    }
+}
+
+impl GapFill {
+    /// Fill gaps in `events`, which must be from one room and sorted oldest first
+    pub fn apply(&self, events: Vec<SensorEvent>) -> Vec<FilledEvent> {
+        let interval = self.interval.unwrap_or_else(|| infer_interval(&events));
+        if interval <= Duration::zero() {
+            return events.into_iter().map(|event| FilledEvent { event, filled: false }).collect();
+        }
+
+        let mut out = Vec::with_capacity(events.len());
+        let mut prev: Option<SensorReading> = None;
+
+        for event in events {
+            if let Some(a) = &prev {
+                let b = &event.reading;
+                let gap = b.timestamp - a.timestamp;
+                // Tolerate normal jitter; only fill clearly missing samples
+                if gap > interval + interval / 2 && gap <= self.max_gap {
+                    let mut timestamp = a.timestamp + interval;
+                    while timestamp < b.timestamp - interval / 2 {
+                        out.push(FilledEvent {
+                            event: SensorEvent {
+                                id: None,
+                                seq: None,
+                                room: event.room.clone(),
+                                device: None,
+                                patient_id: event.patient_id.clone(),
+                                reading: interpolate(a, b, timestamp),
+                                alerts: AlertSet::new(),
+                            },
+                            filled: true,
+                        });
+                        timestamp += interval;
+                    }
+                }
+            }
+            prev = Some(event.reading.clone());
+            out.push(FilledEvent { event, filled: false });
+        }
+
+        out
+    }
+
+    /// Activity analysis over gap-filled readings. Unlike the plain analysis,
+    /// a gap longer than `max_gap` ends a still period, since the patient
+    /// wasn't observed during it.
+    pub fn analyze(
+        &self,
+        events: Vec<SensorEvent>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> ActivityAnalysis {
+        let points = self.apply(events);
+
+        let total = points.len();
+        let motion_count = points.iter().filter(|p| p.event.reading.motion).count();
+        let filled_count = points.iter().filter(|p| p.filled).count();
+        let falls = points.iter().filter(|p| p.event.alerts.contains(AlertType::Fall)).count();
+        let max_sound = points.iter().map(|p| p.event.reading.sound_level).max().unwrap_or(0);
+        let (avg_temp, avg_sound) = if total > 0 {
+            (
+                points.iter().map(|p| p.event.reading.temperature as f64).sum::<f64>() / total as f64,
+                points.iter().map(|p| p.event.reading.sound_level as f64).sum::<f64>() / total as f64,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+        let activity_score = activity_score(motion_count as u64, total as u64);
+        let still = self.still_periods(&points, end);
+
+        ActivityAnalysis {
+            period_start: start.to_rfc3339(),
+            period_end: end.to_rfc3339(),
+            total_readings: total as u64,
+            motion_readings: motion_count as u64,
+            activity_score: (activity_score * 100.0).round() / 100.0,
+            activity_level: activity_level(activity_score).to_string(),
+            avg_temperature: (avg_temp * 100.0).round() / 100.0,
+            avg_sound_level: (avg_sound * 100.0).round() / 100.0,
+            max_sound_level: max_sound,
+            fall_alerts: falls as u64,
+            longest_still_period_mins: still.iter().map(|p| p.duration_mins).max().unwrap_or(0),
+            still_periods: StillPeriods::from_durations(still.iter().map(|p| p.duration_mins)),
+            longest_still_periods: longest_still_periods(still, TOP_STILL_PERIODS),
+            filled_readings: filled_count as u64,
+            night_awakenings: None,
+            night_light: None,
+        }
+    }
+
+    /// Still periods, oldest first
+    fn still_periods(&self, points: &[FilledEvent], end: DateTime<Utc>) -> Vec<StillPeriod> {
+        let mut periods = Vec::new();
+        let mut still_start: Option<&FilledEvent> = None;
+        let mut last: Option<DateTime<Utc>> = None;
+        let period = |begin: &FilledEvent, until: DateTime<Utc>| StillPeriod {
+            room: begin.event.room.clone(),
+            start: begin.event.reading.timestamp,
+            end: until,
+            duration_mins: (until - begin.event.reading.timestamp).num_minutes().max(0) as u64,
+        };
+
+        for point in points {
+            let timestamp = point.event.reading.timestamp;
+
+            // An unfilled gap ends the current still period at the last reading
+            if let (Some(begin), Some(prev)) = (still_start, last) {
+                if timestamp - prev > self.max_gap {
+                    periods.push(period(begin, prev));
+                    still_start = None;
+                }
+            }
+
+            if point.event.reading.motion {
+                if let Some(begin) = still_start.take() {
+                    periods.push(period(begin, timestamp));
+                }
+            } else if still_start.is_none() {
+                still_start = Some(point);
+            }
+            last = Some(timestamp);
+        }
+
+        if let (Some(begin), Some(prev)) = (still_start, last) {
+            let until = if end - prev > self.max_gap { prev } else { end };
+            periods.push(period(begin, until));
+        }
+
+        periods
+    }
+
+    /// Hourly breakdown over gap-filled readings, oldest first, with hours
+    /// labelled in `timezone`
+    pub fn hourly(&self, events: Vec<SensorEvent>, timezone: Tz) -> Vec<HourlyActivity> {
+        // hour -> (total, motion, sound sum, filled, light)
+        let mut hours: BTreeMap<DateTime<Utc>, (u64, u64, f64, u64, LightSums)> = BTreeMap::new();
+        for point in self.apply(events) {
+            let reading = &point.event.reading;
+            let hour = reading.timestamp.duration_trunc(Duration::hours(1)).unwrap_or(reading.timestamp);
+            let entry = hours.entry(hour).or_default();
+            entry.0 += 1;
+            entry.1 += reading.motion as u64;
+            entry.2 += reading.sound_level as f64;
+            entry.3 += point.filled as u64;
+            if let Some(lux) = reading.light {
+                entry.4.add(lux, reading.motion);
+            }
+        }
+
+        hours
+            .into_iter()
+            .map(|(hour, (total, motion, sound, filled, light))| {
+                let activity_score = activity_score(motion, total);
+                HourlyActivity {
+                    hour: hour.with_timezone(&timezone).format("%H:00").to_string(),
+                    activity_score: (activity_score * 100.0).round() / 100.0,
+                    readings: total,
+                    avg_sound_level: (sound / total as f64 * 100.0).round() / 100.0,
+                    filled_readings: filled,
+                    avg_light: light.night_light().map(|light| light.avg_lux),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,201 @@
+//! End-of-shift handover
+//!
+//! `GET /api/handover?shift=night` summarizes every room for the staff
+//! taking over: the alerts still open, those raised during the shift, how
+//! the patient rested over the quiet hours in it and the observations staff
+//! left a note on. Each room comes with a few sentences to read out, and
+//! `format=html` renders the handover as a page to print. Shifts are
+//! configured with `SHIFTS` (by default `day=7-19,night=19-7`); the
+//! handover covers the latest one of that name to have started, up to now
+//! if it hasn't ended yet.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::days::FacilityDays;
+use crate::db::{Alert, Database, DbError};
+use crate::reports::{QuietHours, ReportConfig};
+use patient_monitor_types::analysis::rest_quality;
+use patient_monitor_types::api::{Handover, HandoverAlert, RoomHandover, Shift, ShiftSleep};
+use patient_monitor_types::fhir::{ManualObservation, ObservationKind};
+
+/// Most open alerts listed per room
+const MAX_OPEN_ALERTS: i64 = 100;
+
+/// Start and end of the latest `shift` to have started by `now`, ending at
+/// `now` if it's still going on
+pub fn shift_window(shift: &Shift, days: &FacilityDays, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let today = days.local(now).date();
+    let date = if days.at(today, shift.start_hour) <= now { today } else { today - Duration::days(1) };
+    let end_date = if shift.end_hour <= shift.start_hour { date + Duration::days(1) } else { date };
+    (days.at(date, shift.start_hour), days.at(end_date, shift.end_hour).min(now))
+}
+
+/// The quiet hours overlapping `start..end` the most, clipped to it
+fn quiet_window(
+    quiet: &QuietHours,
+    days: &FacilityDays,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let first = days.local(start).date() - Duration::days(1);
+    (0..3)
+        .map(|i| {
+            let date = first + Duration::days(i);
+            let end_date = if quiet.end_hour <= quiet.start_hour { date + Duration::days(1) } else { date };
+            (days.at(date, quiet.start_hour).max(start), days.at(end_date, quiet.end_hour).min(end))
+        })
+        .filter(|(from, to)| from < to)
+        .max_by_key(|(from, to)| *to - *from)
+}
+
+fn handover_alert(alert: Alert) -> HandoverAlert {
+    HandoverAlert {
+        description: alert.alert.description().to_string(),
+        alert: alert.alert,
+        severity: alert.severity,
+        triggered_at: alert.triggered_at,
+        resolved_at: alert.resolved_at,
+        acknowledged_by: alert.acknowledged_by,
+    }
+}
+
+async fn room_handover(
+    db: &Database,
+    config: &ReportConfig,
+    room: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<RoomHandover, DbError> {
+    let mut open_alerts = db.get_alerts(true, Some(room), MAX_OPEN_ALERTS).await?;
+    open_alerts.reverse();
+    let events = db.get_alerts_in_range(start, end, Some(room)).await?;
+
+    let sleep = match quiet_window(&config.quiet_hours, &config.days, start, end) {
+        Some((from, to)) => {
+            let analysis = db.get_activity_analysis(from, to, Some(room), None).await?;
+            if analysis.total_readings > 0 {
+                Some(ShiftSleep {
+                    start: from,
+                    end: to,
+                    rest_quality: rest_quality(analysis.activity_score).to_string(),
+                    activity_score: analysis.activity_score,
+                    activity_level: analysis.activity_level,
+                    awakenings: db.count_night_awakenings(from, to, Some(room), None).await?,
+                    longest_still_period_mins: analysis.longest_still_period_mins,
+                })
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+
+    let mut follow_ups = db.get_manual_observations(Some(start), Some(end), Some(room), None).await?;
+    follow_ups.retain(|o| o.note.is_some());
+    follow_ups.reverse();
+
+    let mut handover = RoomHandover {
+        room: room.to_string(),
+        open_alerts: open_alerts.into_iter().map(handover_alert).collect(),
+        events: events.into_iter().map(handover_alert).collect(),
+        sleep,
+        follow_ups,
+        summary: String::new(),
+    };
+    handover.summary = handover.read_out();
+    Ok(handover)
+}
+
+/// Handover of the rooms in `config` at the end of the latest `shift`
+pub async fn handover(
+    db: &Database,
+    config: &ReportConfig,
+    shift: &Shift,
+    now: DateTime<Utc>,
+) -> Result<Handover, DbError> {
+    let (start, end) = shift_window(shift, &config.days, now);
+    let mut rooms = Vec::with_capacity(config.rooms.len());
+    for room in &config.rooms {
+        rooms.push(room_handover(db, config, room, start, end).await?);
+    }
+
+    Ok(Handover {
+        ward: config.ward.clone(),
+        shift: shift.name.clone(),
+        start,
+        end,
+        rooms,
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn observation_value(observation: &ManualObservation) -> String {
+    match observation.kind {
+        ObservationKind::PainScore => format!("{}/10", observation.value),
+        kind => format!("{} {}", observation.value, kind.unit()),
+    }
+}
+
+/// The handover as a page to print, with times in facility-local time
+pub fn render_html(handover: &Handover, days: &FacilityDays) -> String {
+    let time = |t: DateTime<Utc>| days.local(t).format("%H:%M").to_string();
+    let alerts = |title: &str, alerts: &[HandoverAlert]| {
+        if alerts.is_empty() {
+            return String::new();
+        }
+        let items: String = alerts
+            .iter()
+            .map(|a| {
+                let mut item = format!("{} {}", time(a.triggered_at), escape(&a.description));
+                if let Some(by) = &a.acknowledged_by {
+                    item += &format!(", acknowledged by {}", escape(by));
+                }
+                if let Some(resolved) = a.resolved_at {
+                    item += &format!(", resolved {}", time(resolved));
+                }
+                format!("<li>{}</li>", item)
+            })
+            .collect();
+        format!("<h3>{}</h3><ul>{}</ul>", title, items)
+    };
+
+    let mut rooms = String::new();
+    for room in &handover.rooms {
+        rooms += &format!("<section><h2>{}</h2><p class=\"summary\">{}</p>", escape(&room.room), escape(&room.summary));
+        rooms += &alerts("Open alerts", &room.open_alerts);
+        rooms += &alerts("During the shift", &room.events);
+        if !room.follow_ups.is_empty() {
+            let items: String = room.follow_ups
+                .iter()
+                .map(|o| format!("<li>{} {} {}: {} ({})</li>",
+                    time(o.observed_at),
+                    o.kind.as_str().replace('_', " "),
+                    observation_value(o),
+                    escape(o.note.as_deref().unwrap_or_default()),
+                    escape(&o.recorded_by)))
+                .collect();
+            rooms += &format!("<h3>To follow up</h3><ul>{}</ul>", items);
+        }
+        rooms += "</section>";
+    }
+
+    let ward = handover.ward.as_deref().map(|w| format!("{} · ", escape(w))).unwrap_or_default();
+    format!(
+        "<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"UTF-8\">\
+         <title>Handover: {shift} shift</title>\
+         <style>body{{font-family:sans-serif;margin:2rem}}\
+         section{{border-top:1px solid #999;padding:0.5rem 0;break-inside:avoid}}\
+         .summary{{font-size:1.1rem}}h3{{margin-bottom:0.25rem}}\
+         @media print{{body{{margin:0}}}}</style></head>\
+         <body><h1>Handover: {shift} shift</h1><p>{ward}{from} – {to} ({days})</p>{rooms}</body></html>",
+        shift = escape(&handover.shift),
+        ward = ward,
+        from = days.local(handover.start).format("%a %d %b %H:%M"),
+        to = days.local(handover.end).format("%a %d %b %H:%M"),
+        days = days.timezone,
+        rooms = rooms,
+    )
+}
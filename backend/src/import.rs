@@ -0,0 +1,158 @@
+//! Import of historical readings
+//!
+//! `POST /api/import` loads readings recorded before they reached this
+//! server, e.g. by an older logger being migrated from, with their original
+//! timestamps. The body is CSV with a header line or newline-delimited JSON,
+//! with the columns `GET /api/export` writes: `timestamp`, `room`,
+//! `temperature`, `motion`, `sound_level` and, optionally, `humidity`,
+//! `heart_rate`, `spo2`, `door_open`, `light`, `co2`, `presence` and
+//! `acceleration`. Other columns, such as `alerts`, are ignored, so an export
+//! can be imported into another server.
+//!
+//! Alert detection only runs on live readings: imported readings are stored
+//! without alerts, open none and aren't broadcast to live clients. Readings
+//! outside the plausible sensor ranges are refused like unreadable ones.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::fhir::{AlertSet, SensorEvent, SensorRanges, SensorReading};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ImportFormat {
+    /// Format of a body with `Content-Type` `content_type`
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type.split(';').next()?.trim() {
+            "text/csv" => Some(ImportFormat::Csv),
+            "application/x-ndjson" | "application/jsonl" => Some(ImportFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+/// One imported reading, as exported
+#[derive(Debug, Deserialize)]
+struct ImportRow {
+    timestamp: DateTime<Utc>,
+    room: String,
+    temperature: f32,
+    motion: bool,
+    sound_level: i32,
+    #[serde(default)]
+    humidity: Option<f32>,
+    #[serde(default)]
+    heart_rate: Option<i32>,
+    #[serde(default)]
+    spo2: Option<i32>,
+    #[serde(default)]
+    door_open: Option<bool>,
+    #[serde(default)]
+    light: Option<f32>,
+    #[serde(default)]
+    co2: Option<i32>,
+    #[serde(default)]
+    presence: Option<bool>,
+    #[serde(default)]
+    acceleration: Option<f32>,
+}
+
+impl ImportRow {
+    fn into_event(self, line: usize, ranges: &SensorRanges) -> Result<SensorEvent, String> {
+        let room = self.room.trim().to_string();
+        if room.is_empty() {
+            return Err(format!("Line {}: room is empty", line));
+        }
+        if !self.temperature.is_finite() {
+            return Err(format!("Line {}: temperature is not a number", line));
+        }
+        let reading = SensorReading {
+            temperature: self.temperature,
+            motion: self.motion,
+            sound_level: self.sound_level,
+            humidity: self.humidity,
+            heart_rate: self.heart_rate,
+            spo2: self.spo2,
+            door_open: self.door_open,
+            light: self.light,
+            co2: self.co2,
+            presence: self.presence,
+            acceleration: self.acceleration,
+            timestamp: self.timestamp,
+        };
+        if let Some(reason) = ranges.check(&reading) {
+            return Err(format!("Line {}: {}", line, reason));
+        }
+        Ok(SensorEvent {
+            id: None,
+            seq: None,
+            room,
+            device: None,
+            patient_id: None,
+            reading,
+            alerts: AlertSet::new(),
+        })
+    }
+}
+
+/// Readings of an import body, in the order given. Errors name the line of
+/// the first reading that can't be read.
+pub fn parse(format: ImportFormat, body: &[u8], ranges: &SensorRanges) -> Result<Vec<SensorEvent>, String> {
+    let events = match format {
+        ImportFormat::Csv => parse_csv(body, ranges)?,
+        ImportFormat::Ndjson => parse_ndjson(body, ranges)?,
+    };
+    if events.is_empty() {
+        return Err("No readings to import".to_string());
+    }
+    Ok(events)
+}
+
+fn parse_csv(body: &[u8], ranges: &SensorRanges) -> Result<Vec<SensorEvent>, String> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body);
+    let headers = reader.headers().map_err(|e| format!("Line 1: {}", e))?.clone();
+    let mut events = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let line = record.position().map_or(events.len() + 2, |p| p.line() as usize);
+        let row: ImportRow = record
+            .deserialize(Some(&headers))
+            .map_err(|e| format!("Line {}: {}", line, csv_message(&e, &headers)))?;
+        events.push(row.into_event(line, ranges)?);
+    }
+    Ok(events)
+}
+
+/// What is wrong with a row, naming the column rather than its index
+fn csv_message(e: &csv::Error, headers: &csv::StringRecord) -> String {
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => match err.field().and_then(|i| headers.get(i as usize)) {
+            Some(column) => format!("{}: {}", column, err.kind()),
+            None => err.kind().to_string(),
+        },
+        _ => e.to_string(),
+    }
+}
+
+fn parse_ndjson(body: &[u8], ranges: &SensorRanges) -> Result<Vec<SensorEvent>, String> {
+    let body = std::str::from_utf8(body).map_err(|e| format!("Body is not UTF-8: {}", e))?;
+    let mut events = Vec::new();
+    for (i, line) in body.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row: ImportRow = serde_json::from_str(line).map_err(|e| {
+            // serde_json counts lines within the one it was given
+            let message = e.to_string();
+            let message = message.split(" at line ").next().unwrap_or_default();
+            format!("Line {}, column {}: {}", i + 1, e.column(), message)
+        })?;
+        events.push(row.into_event(i + 1, ranges)?);
+    }
+    Ok(events)
+}
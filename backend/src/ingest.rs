@@ -0,0 +1,462 @@
+//! Batched storage of incoming readings
+//!
+//! The ingestion loop of a room buffers readings and stores them with one
+//! insert once `INGEST_BATCH_SIZE` readings are waiting or the oldest has
+//! waited `INGEST_BATCH_MS`, instead of a round-trip per reading. Readings are
+//! broadcast after they are stored, so clients receive them with their IDs.
+//!
+//! When readings arrive faster than they are stored, e.g. a device uploading
+//! its backlog after an outage, the batch size doubles per insert up to
+//! `INGEST_BATCH_MAX` and shrinks back once the backlog clears. Past
+//! `INGEST_SHED_DEPTH` waiting readings the room sheds broadcasts: every
+//! reading is still stored and its alerts synced, but only readings that
+//! raise alerts or change the room's alerts, and the latest, are broadcast,
+//! so alert notifications aren't stuck behind the backlog. Shedding stops
+//! once fewer than half as many readings wait.
+//!
+//! Storing a reading also resolves the room's open alerts its type's policy
+//! (`ALERT_AUTO_RESOLVE`) lets it resolve: by default inactivity once a
+//! reading has motion, a fall never (only staff acknowledging it does), and
+//! other alerts once a reading no longer raises them. Each resolution is
+//! broadcast right after the reading that caused it.
+//!
+//! While the database is unavailable, readings that can't be stored are
+//! still broadcast and are queued, up to `INGEST_QUEUE_MAX` per room, then
+//! stored in order (and their alerts synced) once it answers again; newer
+//! readings wait behind them. A full queue drops its oldest readings. The
+//! number of queued and dropped readings is reported per room by
+//! `/api/health`. With a write-ahead log (see `wal`) queued readings also
+//! survive a restart of the monitor.
+
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::db::{Database, DbError, StoredReading};
+use crate::fhir::{AlertSet, SensorEvent};
+use crate::wal::Wal;
+use crate::websocket::LiveEvent;
+use patient_monitor_types::analysis::{AutoResolve, ResolutionPolicies};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// Readings stored with one insert at most
+    pub max_readings: usize,
+    /// Longest a reading waits for others before it is stored
+    pub max_delay: Duration,
+    /// Readings stored with one insert at most while catching up on a backlog
+    pub max_burst_readings: usize,
+    /// Waiting readings past which broadcasts are shed; `None` never sheds
+    pub shed_depth: Option<usize>,
+    /// Readings queued at most while the database is unavailable
+    pub max_queued: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_readings: 50,
+            max_delay: Duration::from_millis(200),
+            max_burst_readings: 1000,
+            shed_depth: Some(2000),
+            max_queued: 10_000,
+        }
+    }
+}
+
+/// Counts of a room's readings held back by a database outage, kept across
+/// restarts of its ingest task
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    queued: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueSnapshot {
+    /// Readings waiting for the database to recover
+    pub queued_readings: usize,
+    /// Readings dropped from a full queue, or lost with a restarted task
+    pub dropped_readings: u64,
+}
+
+impl QueueStats {
+    pub fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            queued_readings: self.queued.load(Ordering::Relaxed),
+            dropped_readings: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Readings of one room waiting to be stored and broadcast
+pub struct IngestBuffer {
+    config: BatchConfig,
+    policies: ResolutionPolicies,
+    /// Readings in arrival order and whether to store them; readings the
+    /// patient hasn't consented to storing wait too, to keep their order
+    events: Vec<(SensorEvent, bool)>,
+    oldest: Option<Instant>,
+    /// Alerts of the room's last stored reading, once synced
+    last_alerts: Option<AlertSet>,
+    /// Whether alerts resolved by motion may be open, so the next reading
+    /// with motion is synced even if its alerts are unchanged
+    awaiting_motion: bool,
+    /// Readings currently stored with one insert, grown under load
+    batch_limit: usize,
+    /// Whether broadcasts are being shed, and how many were since it started
+    shedding: bool,
+    shed: usize,
+    /// Alerts of the room's last broadcast reading
+    last_broadcast: Option<AlertSet>,
+    /// Readings not stored yet because the database was unavailable, oldest first
+    queued: VecDeque<SensorEvent>,
+    queue_stats: Arc<QueueStats>,
+    /// Log readings are written to before they are stored
+    wal: Option<Wal>,
+    /// Whether queued readings recovered from the log may include some
+    /// stored before the monitor went down
+    recovered: bool,
+}
+
+impl IngestBuffer {
+    pub fn new(config: BatchConfig, policies: ResolutionPolicies, queue_stats: Arc<QueueStats>) -> Self {
+        Self {
+            config,
+            policies,
+            events: Vec::new(),
+            oldest: None,
+            last_alerts: None,
+            awaiting_motion: true,
+            batch_limit: config.max_readings,
+            shedding: false,
+            shed: 0,
+            last_broadcast: None,
+            queued: VecDeque::new(),
+            queue_stats,
+            wal: None,
+            recovered: false,
+        }
+    }
+
+    /// Write readings to `wal` before storing them. Readings left in it by
+    /// an earlier run are queued, to be stored before any new ones.
+    pub async fn attach_wal(&mut self, wal: Wal) -> std::io::Result<()> {
+        let recovered = wal.read().await?;
+        if !recovered.is_empty() {
+            info!("Recovering {} readings not stored before from {}", recovered.len(), wal.path().display());
+            self.recovered = true;
+            self.queued.extend(recovered);
+            self.queue_stats.queued.store(self.queued.len(), Ordering::Relaxed);
+        }
+        self.wal = Some(wal);
+        Ok(())
+    }
+
+    pub fn push(&mut self, event: SensorEvent, store: bool) {
+        self.oldest.get_or_insert_with(Instant::now);
+        self.events.push((event, store));
+    }
+
+    /// Whether the buffered readings should be flushed now
+    pub fn is_due(&self) -> bool {
+        self.events.len() >= self.config.max_readings.max(1)
+            || self.oldest.is_some_and(|t| t.elapsed() >= self.config.max_delay)
+    }
+
+    /// Wait until the oldest buffered reading is due to be flushed; never
+    /// completes while none are buffered
+    pub async fn due(&self) {
+        match self.oldest {
+            Some(oldest) => tokio::time::sleep_until((oldest + self.config.max_delay).into()).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Store the buffered readings and keep the room's alerts in step.
+    /// Returns what to broadcast in order: the alerts resolved by queued
+    /// readings stored now, then the readings, with the IDs of those
+    /// stored, unless broadcasts are being shed, each followed by the
+    /// alerts it resolved.
+    pub async fn flush(&mut self, db: &Database) -> Vec<LiveEvent> {
+        self.oldest = None;
+        let mut live = self.store_queued(db).await;
+        let mut events = std::mem::take(&mut self.events);
+        let Some(room) = events.first().map(|(e, _)| e.room.clone()) else {
+            return live;
+        };
+        self.update_shedding(&room, events.len());
+
+        let mut resolved = Vec::new();
+        let mut start = 0;
+        while start < events.len() {
+            self.resize(&room, events.len() - start);
+            let end = (start + self.batch_limit).min(events.len());
+            self.store(db, &mut events[start..end], start, &mut resolved).await;
+            start = end;
+        }
+
+        let mut resolved = resolved.into_iter().peekable();
+        for (i, event) in self.broadcastable(events.into_iter().map(|(e, _)| e).collect()) {
+            while let Some((_, resolution)) = resolved.next_if(|(j, _)| *j < i) {
+                live.push(resolution);
+            }
+            live.push(LiveEvent::Reading(event));
+        }
+        live.extend(resolved.map(|(_, resolution)| resolution));
+        live
+    }
+
+    /// Grow the batch size while more than a batch is waiting, and shrink it
+    /// back once no more than a normal batch is
+    fn resize(&mut self, room: &str, waiting: usize) {
+        let base = self.config.max_readings;
+        let burst = self.config.max_burst_readings.max(base);
+        if waiting > self.batch_limit && self.batch_limit < burst {
+            self.batch_limit = (self.batch_limit * 2).min(burst);
+            info!("{} readings waiting in {}, storing up to {} per insert", waiting, room, self.batch_limit);
+        } else if waiting <= base && self.batch_limit > base {
+            self.batch_limit = (self.batch_limit / 2).max(base);
+        }
+    }
+
+    /// Start shedding broadcasts at `INGEST_SHED_DEPTH` waiting readings, and
+    /// stop below half that
+    fn update_shedding(&mut self, room: &str, waiting: usize) {
+        let Some(shed_depth) = self.config.shed_depth else { return };
+        if !self.shedding && waiting >= shed_depth {
+            self.shedding = true;
+            self.shed = 0;
+            warn!("{} readings waiting in {}, broadcasting only alert changes until it catches up", waiting, room);
+        } else if self.shedding && waiting < shed_depth / 2 {
+            self.shedding = false;
+            info!("{} caught up, broadcasting all readings again ({} not broadcast)", room, self.shed);
+        }
+    }
+
+    /// `events` to broadcast, with their positions: while shedding, only
+    /// those that raise alerts or change the room's alerts, and the latest
+    fn broadcastable(&mut self, events: Vec<SensorEvent>) -> Vec<(usize, SensorEvent)> {
+        let count = events.len();
+        if !self.shedding {
+            if let Some(last) = events.last() {
+                self.last_broadcast = Some(last.alerts.clone());
+            }
+            return events.into_iter().enumerate().collect();
+        }
+
+        let mut kept = Vec::new();
+        for (i, event) in events.into_iter().enumerate() {
+            let changed = self.last_broadcast.as_ref() != Some(&event.alerts);
+            if changed || !event.alerts.is_empty() || i + 1 == count {
+                self.last_broadcast = Some(event.alerts.clone());
+                kept.push((i, event));
+            }
+        }
+        self.shed += count - kept.len();
+        kept
+    }
+
+    /// Store one insert's worth of readings, filling in their IDs. The
+    /// alerts they resolve are added to `resolved` with the position of the
+    /// reading, `offset` being that of the first.
+    async fn store(
+        &mut self,
+        db: &Database,
+        events: &mut [(SensorEvent, bool)],
+        offset: usize,
+        resolved: &mut Vec<(usize, LiveEvent)>,
+    ) {
+        let to_store: Vec<SensorEvent> = events.iter().filter(|(_, store)| *store).map(|(e, _)| e.clone()).collect();
+        if to_store.is_empty() {
+            return;
+        }
+
+        if let Some(wal) = &mut self.wal {
+            if let Err(e) = wal.append(&to_store).await {
+                error!("Failed to write {} readings to {}: {}", to_store.len(), wal.path().display(), e);
+            }
+        }
+
+        // Readings wait behind those queued, to be stored in order
+        if !self.queued.is_empty() {
+            self.enqueue(to_store);
+            return;
+        }
+
+        let stored = match db.insert_readings_batch(&to_store).await {
+            Ok(stored) => stored,
+            Err(DbError::Unavailable(e)) => {
+                warn!("Database unavailable ({}), queueing {} readings until it recovers", e, to_store.len());
+                self.enqueue(to_store);
+                return;
+            }
+            Err(e) => {
+                error!("Failed to save {} readings: {}", to_store.len(), e);
+                self.checkpoint().await;
+                return;
+            }
+        };
+        self.checkpoint().await;
+
+        let mut stored = stored.into_iter();
+        for (i, (event, _)) in events.iter_mut().enumerate().filter(|(_, (_, store))| *store) {
+            let Some(reading) = stored.next() else { break };
+            for resolution in self.stored(db, event, reading).await {
+                resolved.push((offset + i, resolution));
+            }
+        }
+    }
+
+    /// Store the readings queued during an outage, oldest first, until all
+    /// are stored or the database fails again. Returns the alerts they
+    /// resolved; the readings themselves were broadcast when they arrived.
+    async fn store_queued(&mut self, db: &Database) -> Vec<LiveEvent> {
+        let mut resolved = Vec::new();
+        if self.recovered {
+            match self.skip_stored(db).await {
+                Ok(()) => self.recovered = false,
+                Err(DbError::Unavailable(_)) => return resolved,
+                Err(e) => {
+                    error!("Failed to check which recovered readings are stored: {}", e);
+                    self.recovered = false;
+                }
+            }
+        }
+        while !self.queued.is_empty() {
+            let count = self.queued.len().min(self.config.max_burst_readings.max(self.config.max_readings));
+            let batch: Vec<SensorEvent> = self.queued.range(..count).cloned().collect();
+            let result = db.insert_readings_batch(&batch).await;
+            if matches!(result, Err(DbError::Unavailable(_))) {
+                break;
+            }
+            self.queued.drain(..count);
+            self.queue_stats.queued.store(self.queued.len(), Ordering::Relaxed);
+            self.checkpoint().await;
+
+            match result {
+                Ok(stored) => {
+                    for (mut event, reading) in batch.into_iter().zip(stored) {
+                        resolved.extend(self.stored(db, &mut event, reading).await);
+                    }
+                    if self.queued.is_empty() {
+                        info!("Database recovered, stored the readings queued during the outage");
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to save {} queued readings: {}", count, e);
+                    self.queue_stats.dropped.fetch_add(count as u64, Ordering::Relaxed);
+                }
+            }
+        }
+        resolved
+    }
+
+    /// Leave readings recovered from the write-ahead log out of the queue if
+    /// they were stored before the monitor went down
+    async fn skip_stored(&mut self, db: &Database) -> Result<(), DbError> {
+        let Some(room) = self.queued.front().map(|e| e.room.clone()) else {
+            return Ok(());
+        };
+        let timestamps = self.queued.iter().map(|e| e.reading.timestamp);
+        let (Some(first), Some(last)) = (timestamps.clone().min(), timestamps.max()) else {
+            return Ok(());
+        };
+
+        // Stored timestamps may be less precise than those taken
+        let stored: HashSet<i64> = db
+            .get_readings_in_range(first, last + chrono::Duration::milliseconds(1), Some(&room), None, None, None)
+            .await?
+            .iter()
+            .map(|e| e.reading.timestamp.timestamp_millis())
+            .collect();
+        let before = self.queued.len();
+        self.queued.retain(|e| !stored.contains(&e.reading.timestamp.timestamp_millis()));
+        if self.queued.len() < before {
+            info!("{} recovered readings of {} were already stored", before - self.queued.len(), room);
+        }
+        self.queue_stats.queued.store(self.queued.len(), Ordering::Relaxed);
+        self.checkpoint().await;
+        Ok(())
+    }
+
+    /// Keep only the readings not stored yet in the write-ahead log
+    async fn checkpoint(&mut self) {
+        if let Some(wal) = &mut self.wal {
+            if let Err(e) = wal.reset(&self.queued).await {
+                error!("Failed to update {}: {}", wal.path().display(), e);
+            }
+        }
+    }
+
+    /// Queue readings the database couldn't take, dropping the oldest
+    /// beyond `max_queued`
+    fn enqueue(&mut self, events: Vec<SensorEvent>) {
+        let was_full = self.queued.len() >= self.config.max_queued;
+        self.queued.extend(events);
+        let excess = self.queued.len().saturating_sub(self.config.max_queued);
+        if excess > 0 {
+            if !was_full {
+                warn!("{} readings queued, dropping the oldest until the database recovers", self.queued.len());
+            }
+            self.queued.drain(..excess);
+            self.queue_stats.dropped.fetch_add(excess as u64, Ordering::Relaxed);
+        }
+        self.queue_stats.queued.store(self.queued.len(), Ordering::Relaxed);
+    }
+
+    /// Fill in the ID of `event`, now stored as `reading`, and sync its
+    /// room's alerts. Returns the alerts it resolved.
+    async fn stored(&mut self, db: &Database, event: &mut SensorEvent, reading: StoredReading) -> Vec<LiveEvent> {
+        event.id = Some(reading.id);
+        event.seq = Some(reading.seq);
+        event.patient_id = reading.patient_id;
+
+        // An unchanged alert set opens and resolves nothing, unless it
+        // comes with the motion open alerts wait for
+        let motion_awaited = self.policies.moved(&event.reading) && self.awaiting_motion;
+        if self.last_alerts.as_ref() == Some(&event.alerts) && !motion_awaited {
+            return Vec::new();
+        }
+        let mut resolved = Vec::new();
+        let resolve = self.policies.resolved_by(&event.reading, &event.alerts);
+        match db.sync_alerts(&event.room, &event.alerts, reading.id, event.reading.timestamp, &resolve).await {
+            Ok(sync) => {
+                for alert in sync.opened {
+                    info!("{:?} alert opened in {}", alert, event.room);
+                }
+                for alert in sync.resolved {
+                    info!("{:?} alert resolved in {} ({})", alert.alert, event.room,
+                        alert.resolution_reason.map_or("", |r| r.as_str()));
+                    resolved.push(LiveEvent::AlertResolved { alert, reading: Some(event.clone()) });
+                }
+                self.last_alerts = Some(event.alerts.clone());
+                self.awaiting_motion = !self.policies.moved(&event.reading)
+                    || event.alerts.iter().any(|a| self.policies.policy(a) == AutoResolve::Motion);
+            }
+            Err(e) => {
+                error!("Failed to update alerts: {}", e);
+                self.last_alerts = None;
+            }
+        }
+        resolved
+    }
+}
+
+impl Drop for IngestBuffer {
+    /// Readings still queued when the ingest task stops are lost, unless
+    /// they are in the write-ahead log
+    fn drop(&mut self) {
+        if self.wal.is_some() {
+            self.queue_stats.queued.store(0, Ordering::Relaxed);
+        } else if !self.queued.is_empty() {
+            warn!("Dropping {} readings queued during a database outage", self.queued.len());
+            self.queue_stats.dropped.fetch_add(self.queued.len() as u64, Ordering::Relaxed);
+            self.queue_stats.queued.store(0, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Background job runs
+//!
+//! The daily rollup, retention purges and compaction record each run in the
+//! `job_runs` table and checkpoint it after every chunk of work they
+//! complete: a day rolled up, a batch of readings archived and purged, an
+//! hour compacted. A run that doesn't complete, because it failed or the
+//! monitor went down, keeps its last checkpoint, and the job's next run
+//! resumes from there instead of starting over. Runs still marked running on
+//! startup were cut short by a crash and are marked interrupted.
+//!
+//! `GET /api/admin/jobs` lists the runs with their durations and errors.
+//! Runs are kept for `DEVICE_LOG_RETENTION_DAYS`.
+
+use chrono::Utc;
+use tracing::{error, info, warn};
+
+use crate::db::{Database, DbError, JobRun};
+
+pub const ROLLUP: &str = "rollup";
+pub const RETENTION: &str = "retention";
+pub const COMPACTION: &str = "compaction";
+
+/// The last run of `job`, if it has run before
+pub async fn last_run(db: &Database, job: &str) -> Result<Option<JobRun>, DbError> {
+    Ok(db.get_job_runs(Some(job), None, 1).await?.into_iter().next())
+}
+
+/// Checkpoint a run of `job` resumes from: that of its last run, unless that
+/// run completed
+pub async fn resume_point(db: &Database, job: &str) -> Result<Option<String>, DbError> {
+    let last = last_run(db, job).await?.filter(|r| r.status != "completed");
+    if let Some(run) = &last {
+        info!("Resuming {} run {}, which was {}", job, run.id, run.status);
+    }
+    Ok(last.and_then(|r| r.checkpoint))
+}
+
+/// Mark the runs left running by a previous process as interrupted. Call on
+/// startup, before any job runs.
+pub async fn recover(db: &Database) {
+    match db.interrupt_job_runs().await {
+        Ok(0) => {}
+        Ok(n) => warn!("{} background job runs were interrupted and will resume from their checkpoints", n),
+        Err(e) => error!("Failed to mark interrupted job runs: {}", e),
+    }
+}
+
+/// A run in progress
+pub struct Run {
+    db: Database,
+    id: i64,
+    job: &'static str,
+}
+
+impl Run {
+    /// Record the start of a run of `job` resuming from `checkpoint`. The run
+    /// keeps the checkpoint until it records its own, so a run failing before
+    /// it gets anywhere doesn't lose it.
+    pub async fn start(db: &Database, job: &'static str, checkpoint: Option<&str>) -> Result<Self, DbError> {
+        let id = db.start_job_run(job, Utc::now(), checkpoint).await?;
+        Ok(Self { db: db.clone(), id, job })
+    }
+
+    /// Record a completed chunk, after which the job would resume from `checkpoint`
+    pub async fn checkpoint(&self, checkpoint: &str) -> Result<(), DbError> {
+        self.db.checkpoint_job_run(self.id, checkpoint).await
+    }
+
+    /// Record the end of the run, as failed if `result` is an error
+    pub async fn finish<T, E: std::fmt::Display>(self, result: &Result<T, E>) {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        if let Err(e) = self.db.finish_job_run(self.id, Utc::now(), error.as_deref()).await {
+            error!("Failed to record the end of {} run {}: {}", self.job, self.id, e);
+        }
+    }
+}
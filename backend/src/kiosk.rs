@@ -0,0 +1,268 @@
+//! Read-only kiosk tokens
+//!
+//! Staff create a token for one room with `POST /api/kiosk-tokens`, e.g. for
+//! a relative's tablet or the TV in the room, without giving the family an
+//! account. The response carries the token, which is not shown again, and
+//! the URL to open on the kiosk or print as a QR code,
+//! `{PUBLIC_URL}/kiosk.html#<token>`. The token is in the URL's fragment,
+//! which browsers don't send, so it stays out of proxy logs; the page passes
+//! it as a bearer token to `GET /api/kiosk/status`, which serves nothing but
+//! the room's coarse state (see `KioskStatus`).
+//!
+//! Tokens expire after the hours given (24 by default, at most
+//! `KIOSK_TOKEN_MAX_DAYS`) and can be revoked at any time with
+//! `DELETE /api/kiosk-tokens/{id}`; `GET /api/kiosk-tokens` lists those still
+//! valid. Only a SHA-256 hash of each token is stored. Creating and revoking
+//! tokens needs an authenticated staff member with access to the room's ward
+//! and is audited, and every status request is logged as a PHI access by
+//! `kiosk:<id>`.
+
+use actix_web::http::header;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info};
+
+use crate::access_log::AccessContext;
+use crate::api::{db_error, ApiError, AppState, RoomQuery};
+use crate::db::{AuditEntry, KioskToken};
+use patient_monitor_types::api::KioskStatus;
+
+/// Characters of a token, about 190 bits
+const TOKEN_LEN: usize = 32;
+const DEFAULT_TTL_HOURS: i64 = 24;
+/// Longest label that is stored
+const MAX_LABEL_LEN: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct KioskConfig {
+    /// URL kiosks reach this server at, for the links of new tokens
+    pub public_url: String,
+    /// Longest a token may be valid
+    pub max_ttl: Duration,
+}
+
+fn generate_token() -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(TOKEN_LEN).map(char::from).collect()
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Token of a request with `Authorization: Bearer <token>`
+fn bearer(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateKioskToken {
+    pub room: String,
+    /// Hours the token is valid, default 24
+    pub hours: Option<i64>,
+    /// Where the kiosk is, e.g. "Family tablet"
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedKioskToken {
+    #[serde(flatten)]
+    pub kiosk: KioskToken,
+    /// The token itself; only returned here
+    pub token: String,
+    /// Page showing the room's status with the token, for a QR code
+    pub url: String,
+}
+
+/// POST /api/kiosk-tokens
+///
+/// Create a read-only token showing a room's coarse status on a kiosk.
+/// Needs an authenticated staff member with access to the room's ward.
+/// Example: {"room": "room-101", "hours": 72, "label": "Family tablet"}
+#[post("/api/kiosk-tokens")]
+pub async fn create_kiosk_token(
+    state: web::Data<AppState>,
+    config: web::Data<KioskConfig>,
+    body: web::Json<CreateKioskToken>,
+    access: AccessContext,
+) -> impl Responder {
+    debug!("POST /api/kiosk-tokens");
+
+    let Some(principal) = access.principal.clone() else {
+        return HttpResponse::Unauthorized().json(ApiError::new("authentication_required",
+            "Creating kiosk tokens needs an authenticated staff member"));
+    };
+    let room = body.room.trim();
+    if !state.rooms.iter().any(|r| r == room) {
+        return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Room {} is not monitored by this server", room)));
+    }
+    if !access.permits(state.wards.ward_of(room).as_deref()) {
+        return HttpResponse::Forbidden()
+            .json(ApiError::new("ward_forbidden", &format!("No access to {}", room)));
+    }
+    // Checked as hours, as a duration of too many hours can't be made
+    let hours = body.hours.unwrap_or(DEFAULT_TTL_HOURS);
+    let expires_at = (1..=config.max_ttl.num_hours())
+        .contains(&hours)
+        .then(|| Utc::now().checked_add_signed(Duration::hours(hours)))
+        .flatten();
+    let Some(expires_at) = expires_at else {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_ttl",
+            &format!("A kiosk token is valid for 1 to {} hours", config.max_ttl.num_hours())));
+    };
+    let label = body.label.as_deref().map(str::trim).filter(|l| !l.is_empty());
+    if label.is_some_and(|l| l.chars().count() > MAX_LABEL_LEN) {
+        return HttpResponse::BadRequest().json(ApiError::new("invalid_label",
+            &format!("Labels are at most {} characters", MAX_LABEL_LEN)));
+    }
+
+    let token = generate_token();
+    let kiosk = match state.db.insert_kiosk_token(room, &token_hash(&token), label, &principal, expires_at).await {
+        Ok(kiosk) => kiosk,
+        Err(e) => return db_error(e, "Failed to create kiosk token"),
+    };
+
+    let audit = AuditEntry {
+        action: "kiosk_token.create".to_string(),
+        subject: format!("kiosk_token/{}", kiosk.id),
+        actor: Some(principal.clone()),
+        request_id: access.request_id.clone(),
+        detail: Some(format!("Read-only kiosk token for {} until {}", room, expires_at.to_rfc3339())),
+    };
+    if let Err(e) = state.db.insert_audit(&audit).await {
+        error!("Failed to write audit entry: {}", e);
+    }
+    info!("{} created kiosk token {} for {} (until {})", principal, kiosk.id, room, expires_at);
+
+    HttpResponse::Created().json(CreatedKioskToken {
+        url: format!("{}/kiosk.html#{}", config.public_url, token),
+        token,
+        kiosk,
+    })
+}
+
+/// GET /api/kiosk-tokens
+///
+/// Kiosk tokens that are neither expired nor revoked, newest first,
+/// optionally of one room; the tokens themselves are not returned
+/// Example: /api/kiosk-tokens?room=room-101
+#[get("/api/kiosk-tokens")]
+pub async fn list_kiosk_tokens(
+    state: web::Data<AppState>,
+    query: web::Query<RoomQuery>,
+    access: AccessContext,
+) -> impl Responder {
+    debug!("GET /api/kiosk-tokens");
+
+    match state.db.get_kiosk_tokens(query.room.as_deref(), Utc::now()).await {
+        Ok(mut tokens) => {
+            tokens.retain(|t| access.permits(state.wards.ward_of(&t.room).as_deref()));
+            HttpResponse::Ok().json(tokens)
+        }
+        Err(e) => db_error(e, "Failed to retrieve kiosk tokens"),
+    }
+}
+
+/// DELETE /api/kiosk-tokens/{id}
+///
+/// Revoke a kiosk token; the kiosk shows that its link is no longer valid on
+/// its next refresh. Revoking a token again changes nothing.
+#[delete("/api/kiosk-tokens/{id}")]
+pub async fn revoke_kiosk_token(
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    access: AccessContext,
+) -> impl Responder {
+    let id = path.into_inner();
+    debug!("DELETE /api/kiosk-tokens/{}", id);
+
+    let Some(principal) = access.principal.clone() else {
+        return HttpResponse::Unauthorized().json(ApiError::new("authentication_required",
+            "Revoking kiosk tokens needs an authenticated staff member"));
+    };
+    let kiosk = match state.db.get_kiosk_token(id).await {
+        Ok(Some(kiosk)) => kiosk,
+        Ok(None) => return HttpResponse::NotFound()
+            .json(ApiError::not_found(&format!("Kiosk token {} not found", id))),
+        Err(e) => return db_error(e, "Failed to retrieve kiosk token"),
+    };
+    if !access.permits(state.wards.ward_of(&kiosk.room).as_deref()) {
+        return HttpResponse::Forbidden()
+            .json(ApiError::new("ward_forbidden", &format!("No access to {}", kiosk.room)));
+    }
+
+    match state.db.revoke_kiosk_token(id, &principal).await {
+        Ok(true) => {
+            let audit = AuditEntry {
+                action: "kiosk_token.revoke".to_string(),
+                subject: format!("kiosk_token/{}", id),
+                actor: Some(principal.clone()),
+                request_id: access.request_id.clone(),
+                detail: Some(format!("Kiosk token for {} revoked", kiosk.room)),
+            };
+            if let Err(e) = state.db.insert_audit(&audit).await {
+                error!("Failed to write audit entry: {}", e);
+            }
+            info!("{} revoked kiosk token {} for {}", principal, id, kiosk.room);
+        }
+        Ok(false) => {}
+        Err(e) => return db_error(e, "Failed to revoke kiosk token"),
+    }
+
+    match state.db.get_kiosk_token(id).await {
+        Ok(Some(kiosk)) => HttpResponse::Ok().json(kiosk),
+        Ok(None) => HttpResponse::NotFound().json(ApiError::not_found(&format!("Kiosk token {} not found", id))),
+        Err(e) => db_error(e, "Failed to retrieve kiosk token"),
+    }
+}
+
+/// GET /api/kiosk/status
+///
+/// Coarse status of the room of the kiosk token given as
+/// `Authorization: Bearer <token>`: whether the patient is in bed, up and
+/// about or out of the room, and since when
+#[get("/api/kiosk/status")]
+pub async fn kiosk_status(state: web::Data<AppState>, req: HttpRequest, access: AccessContext) -> impl Responder {
+    let Some(token) = bearer(&req) else {
+        return HttpResponse::Unauthorized().json(ApiError::new("authentication_required",
+            "The kiosk status needs a kiosk token"));
+    };
+    let now = Utc::now();
+    let kiosk = match state.db.find_kiosk_token(&token_hash(token), now).await {
+        Ok(Some(kiosk)) => kiosk,
+        Ok(None) => return HttpResponse::Unauthorized().json(ApiError::new("invalid_token",
+            "This kiosk link has expired or was revoked")),
+        Err(e) => return db_error(e, "Failed to check kiosk token"),
+    };
+    debug!("GET /api/kiosk/status (kiosk token {})", kiosk.id);
+
+    let current = match state.db.get_room_states(&kiosk.room, now, now).await {
+        Ok(changes) => changes.last().copied(),
+        Err(e) => return db_error(e, "Failed to retrieve room state"),
+    };
+    let access = AccessContext { principal: Some(format!("kiosk:{}", kiosk.id)), ..access };
+    access.record(&state.db, None, Some(&kiosk.room), &[]).await;
+
+    let (patient_state, since) = current.unzip();
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(KioskStatus {
+            room: kiosk.room,
+            label: kiosk.label,
+            state: patient_state,
+            since,
+            message: KioskStatus::message(patient_state).to_string(),
+            expires_at: kiosk.expires_at,
+        })
+}
@@ -0,0 +1,73 @@
+//! Request body limits
+//!
+//! JSON bodies are limited to `MAX_BODY_KB`. Routes that take bulk data,
+//! such as device log uploads carrying crash dumps, accept up to
+//! `MAX_BULK_BODY_KB` instead; they are registered with
+//! `json_config(limits.bulk)` as resource data. A body over the limit is
+//! rejected with a 413 `payload_too_large` error giving the limit in
+//! `limitBytes`, and a body that isn't the JSON the route expects with a 400
+//! `invalid_body` error, rather than actix's plain-text responses. Routes
+//! taking other bodies, such as CSV imports, read them with `read_body` to
+//! get the same errors.
+
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde_json::json;
+use tracing::warn;
+
+use crate::api::ApiError;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    /// Largest JSON body, in bytes
+    pub default: usize,
+    /// Largest JSON body of bulk routes, in bytes
+    pub bulk: usize,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self { default: 256 * 1024, bulk: 16 * 1024 * 1024 }
+    }
+}
+
+/// JSON extractor configuration accepting bodies up to `limit` bytes
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|err, req| {
+            let response = error_response(&err, req);
+            InternalError::from_response(err, response).into()
+        })
+}
+
+/// Body of a request that isn't JSON, up to `limit` bytes
+pub async fn read_body(req: &HttpRequest, payload: web::Payload, limit: usize) -> Result<web::Bytes, HttpResponse> {
+    match payload.to_bytes_limited(limit).await {
+        Ok(Ok(body)) => Ok(body),
+        Ok(Err(e)) => Err(HttpResponse::BadRequest().json(ApiError::new("invalid_body", &e.to_string()))),
+        Err(_) => Err(too_large(req, limit, &format!(
+            "Request body exceeds the limit of {} bytes for {}", limit, req.path()))),
+    }
+}
+
+fn error_response(err: &JsonPayloadError, req: &HttpRequest) -> HttpResponse {
+    match err {
+        JsonPayloadError::OverflowKnownLength { length, limit } => too_large(req, *limit, &format!(
+            "Request body of {} bytes exceeds the limit of {} bytes for {}", length, limit, req.path())),
+        JsonPayloadError::Overflow { limit } => too_large(req, *limit, &format!(
+            "Request body exceeds the limit of {} bytes for {}", limit, req.path())),
+        JsonPayloadError::ContentType => HttpResponse::UnsupportedMediaType()
+            .json(ApiError::new("unsupported_media_type", "Expected a Content-Type of application/json")),
+        other => HttpResponse::BadRequest().json(ApiError::new("invalid_body", &other.to_string())),
+    }
+}
+
+fn too_large(req: &HttpRequest, limit: usize, message: &str) -> HttpResponse {
+    warn!("Rejected oversize body for {} {} (limit {} bytes)", req.method(), req.path(), limit);
+    HttpResponse::PayloadTooLarge().json(json!({
+        "error": "payload_too_large",
+        "message": message,
+        "limitBytes": limit,
+    }))
+}
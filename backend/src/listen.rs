@@ -0,0 +1,93 @@
+//! Listener addresses
+//!
+//! The server can listen on several addresses at once, e.g. IPv4 and IPv6
+//! side by side, or a Unix domain socket for a reverse proxy on the same
+//! host. Addresses are configured as a comma-separated list:
+//! `0.0.0.0:8080, [::]:8080, unix:/run/patient-monitor.sock`.
+
+use std::fmt;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+const UNIX_PREFIX: &str = "unix:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BindAddress {
+    Tcp(SocketAddr),
+    /// Unix domain socket path; an existing socket file is replaced
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(path) = s.strip_prefix(UNIX_PREFIX) {
+            if !cfg!(unix) {
+                return Err(format!("Unix sockets are not supported on this platform: '{}'", s));
+            }
+            if path.is_empty() {
+                return Err("Unix socket address needs a path, e.g. 'unix:/run/monitor.sock'".to_string());
+            }
+            return Ok(BindAddress::Unix(PathBuf::from(path)));
+        }
+
+        // Host names (e.g. localhost:8080) resolve to their first address
+        s.to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .map(BindAddress::Tcp)
+            .ok_or_else(|| format!("Invalid bind address '{}', expected host:port or unix:/path", s))
+    }
+}
+
+impl fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindAddress::Tcp(addr) => write!(f, "{}", addr),
+            BindAddress::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Parse a comma-separated list of addresses, ignoring empty entries
+pub fn parse_list(s: &str) -> Result<Vec<BindAddress>, String> {
+    let addrs = s
+        .split(',')
+        .filter(|a| !a.trim().is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if addrs.is_empty() {
+        return Err("No bind addresses configured".to_string());
+    }
+    Ok(addrs)
+}
+
+/// TCP listener for `addr`. IPv6 listeners only accept IPv6, so `[::]:8080`
+/// can be bound next to `0.0.0.0:8080` on systems that default to dual-stack.
+pub fn tcp_listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(2048)?;
+    Ok(socket.into())
+}
+
+/// Base URL for links in FHIR bundles, taken from the first TCP address
+pub fn base_url(addrs: &[BindAddress]) -> String {
+    addrs
+        .iter()
+        .find_map(|a| match a {
+            BindAddress::Tcp(addr) => Some(format!("http://{}", addr)),
+            BindAddress::Unix(_) => None,
+        })
+        .unwrap_or_else(|| "http://localhost".to_string())
+}
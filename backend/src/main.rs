@@ -1,47 +1,432 @@
 //! Smart Patient Room Monitor - Backend Server
 
+mod access_log;
+mod adt;
+mod announce;
 mod api;
+mod assets;
+mod calendar;
+mod chatops;
+mod compaction;
+mod consent;
+mod days;
 mod db;
+mod demo;
+mod export;
 mod fhir;
+mod flags;
+mod gapfill;
+mod handover;
+mod import;
+mod ingest;
+mod jobs;
+mod kiosk;
+mod limits;
+mod listen;
+mod notify;
+mod partitions;
+mod reports;
+mod request_id;
+mod research;
+mod retention;
+mod risk;
+mod room_state;
+mod rollup;
+mod rules;
+mod seed;
 mod serial;
+mod supervisor;
+mod wal;
+mod ward;
 mod websocket;
+mod ws_clients;
 
 use actix_cors::Cors;
-use actix_web::{web, App, HttpServer};
-use std::sync::{Arc, RwLock};
+use actix_web::{middleware, web, App, HttpServer};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
-use tracing::{error, info, Level};
+use tracing::{debug, error, info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
+use crate::adt::AdtConfig;
+use crate::announce::{AnnouncementTemplate, Announcer};
 use crate::api::{AppState, MonitorSettings};
-use crate::db::{Database, DbConfig};
-use crate::serial::{SerialConfig, SerialReader};
-use crate::websocket::SensorBroadcaster;
+use crate::calendar::{CalendarConfig, Procedures};
+use crate::chatops::ChatOpsConfig;
+use crate::consent::MonitoringConsent;
+use crate::days::FacilityDays;
+use crate::db::{Database, DbConfig, QuarantinedReading};
+use crate::export::ExportConfig;
+use crate::fhir::{AlertType, SensorEvent, SensorRanges};
+use crate::flags::FeatureFlags;
+use crate::ws_clients::WsClients;
+use crate::ingest::{BatchConfig, IngestBuffer, QueueStats};
+use crate::kiosk::KioskConfig;
+use crate::limits::BodyLimits;
+use crate::listen::BindAddress;
+use crate::notify::{Notifier, RoutingTable, Snoozes};
+use crate::reports::{QuietHours, ReportConfig};
+use crate::research::ResearchConfig;
+use crate::retention::RetentionConfig;
+use crate::room_state::RoomStates;
+use crate::rules::{RuleSet, ShadowHit};
+use crate::serial::{
+    AlertLimits, DeviceConnection, DeviceEvent, DeviceTracker, ReadingQueue, SerialConfig, SerialMessage, SerialReader,
+    TemperatureUnit,
+};
+use crate::supervisor::{supervise, TaskHealth};
+use crate::wal::Wal;
+use crate::ward::{WardMap, WardProjection};
+use crate::websocket::{LiveEvent, SensorBroadcaster};
+use patient_monitor_types::analysis::{CalibrationLimit, ImpactLimit, MotionSource, ResolutionPolicies, TemperatureCrossCheck, VentilationLimit, VitalLimits};
+use patient_monitor_types::api::{SettingsPermissions, Shifts};
+
+/// A monitored room and the serial port its sensor board is attached to
+struct RoomConfig {
+    id: String,
+    /// Ports of the room's devices, with the unit each board's firmware
+    /// reports temperature in
+    serial_ports: Vec<(String, TemperatureUnit)>,
+}
+
+/// Split the unit off a port given as e.g. `/dev/ttyUSB0@F`; ports without
+/// one report in `default_unit`
+fn port_unit(port: &str, default_unit: TemperatureUnit) -> (String, TemperatureUnit) {
+    match port.rsplit_once('@') {
+        Some((port, unit)) => (
+            port.trim().to_string(),
+            unit.parse().unwrap_or_else(|_| panic!("Temperature unit of {} must be C or F", port.trim())),
+        ),
+        None => (port.trim().to_string(), default_unit),
+    }
+}
+
+/// Parse a list of ports separated by `separator`, e.g. `COM3,COM4@F`
+fn parse_ports(value: &str, separator: char, default_unit: TemperatureUnit) -> Vec<(String, TemperatureUnit)> {
+    value
+        .split(separator)
+        .filter(|p| !p.trim().is_empty())
+        .map(|port| port_unit(port, default_unit))
+        .collect()
+}
+
+/// Parse `ROOMS`, e.g. `room-101=/dev/ttyUSB0,room-102=/dev/ttyUSB1@F`, with
+/// a room's devices joined by `+` (`room-103=/dev/ttyUSB2+/dev/ttyUSB3`).
+/// Rooms without a port use `default_ports`, ports without a unit
+/// `default_unit`.
+fn parse_rooms(
+    value: &str,
+    default_ports: &[(String, TemperatureUnit)],
+    default_unit: TemperatureUnit,
+) -> Vec<RoomConfig> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|room| match room.split_once('=') {
+            Some((id, ports)) => RoomConfig {
+                id: id.trim().to_string(),
+                serial_ports: parse_ports(ports, '+', default_unit),
+            },
+            None => RoomConfig { id: room.to_string(), serial_ports: default_ports.to_vec() },
+        })
+        .collect()
+}
 
 struct Config {
-    host: String,
-    port: u16,
-    serial_port: String,
+    bind: Vec<BindAddress>,
+    rooms: Vec<RoomConfig>,
     baud_rate: u32,
     sound_threshold: i32,
     inactivity_seconds: u64,
+    sound_flatline_hours: u64,
+    sound_flatline_epsilon: f64,
+    /// Plausible sensor values; readings outside them are quarantined
+    sensor_ranges: SensorRanges,
+    /// Heart rate and SpO2 at which vitals alerts are raised
+    vital_limits: VitalLimits,
+    /// CO2 level and duration at which ventilation is raised; `None` with
+    /// `CO2_ALERT_PPM=0`
+    ventilation: Option<VentilationLimit>,
+    /// Sensors whose readings count as motion for inactivity and for
+    /// resolving alerts waiting for motion
+    motion_source: MotionSource,
+    /// Impact and stillness after it at which a fall is raised from an
+    /// accelerometer; `None` with `IMPACT_FALL_G=0`
+    impact: Option<ImpactLimit>,
+    /// Disagreement of a room's temperature sensors at which calibration is
+    /// raised; `None` with `TEMPERATURE_CALIBRATION_DELTA=0`
+    calibration: Option<CalibrationLimit>,
     db_config: DbConfig,
+    ingest_batch: BatchConfig,
+    /// Directory of the rooms' write-ahead logs of incoming readings
+    ingest_wal_dir: Option<PathBuf>,
+    /// Readings waiting between a room's reader and its ingest loop
+    serial_queue_max: usize,
     mock_mode: bool,
+    /// Public demo: mock readings, synthetic history and no changes
+    demo_mode: bool,
+    /// Days of synthetic history seeded for the demo, 0 for none
+    demo_seed_days: u32,
+    ward_id: Option<String>,
+    alert_routes_file: Option<String>,
+    /// Wording of alert announcements for overhead paging
+    announce_template: AnnouncementTemplate,
+    /// Unacknowledged alerts are escalated this long after onset
+    alert_escalation: Option<Duration>,
+    /// How open alerts of each type are resolved
+    alert_resolution: ResolutionPolicies,
+    /// Roles allowed to change each setting
+    settings_permissions: SettingsPermissions,
+    device_log_retention_days: i64,
+    retention: RetentionConfig,
+    /// Days after which runs of identical readings are compacted; 0 never
+    compact_after_days: u32,
+    export: ExportConfig,
+    research: ResearchConfig,
+    ward_frame_seconds: u64,
+    ws_stats_persist_secs: u64,
+    body_limits: BodyLimits,
+    ops_alert_webhook: Option<String>,
+    frontend_dir: Option<String>,
+    days: FacilityDays,
+    quiet_hours: QuietHours,
+    /// Shifts handovers are given for
+    shifts: Shifts,
+    digest_webhook: Option<String>,
+    morning_report_webhook: Option<String>,
+    public_url: Option<String>,
+    slack_signing_secret: Option<String>,
+    teams_ack_secret: Option<String>,
+    ack_link_secret: Option<String>,
+    /// How long the ack links in notifications stay valid
+    ack_link_ttl: chrono::Duration,
+    /// Longest a kiosk token may be valid
+    kiosk_token_max_ttl: chrono::Duration,
+    calendar: CalendarConfig,
+    adt: AdtConfig,
 }
 
 impl Config {
     fn from_env() -> Self {
         dotenvy::dotenv().ok();
         
+        // BIND_ADDRESSES takes precedence over the single HOST/PORT pair
+        let bind = std::env::var("BIND_ADDRESSES").unwrap_or_else(|_| {
+            let host = std::env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+            let port = std::env::var("PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8080u16);
+            // Bare IPv6 hosts need brackets to be combined with a port
+            if host.contains(':') && !host.starts_with('[') {
+                format!("[{}]:{}", host, port)
+            } else {
+                format!("{}:{}", host, port)
+            }
+        });
+        
+        // ROOMS takes precedence over the single ROOM_ID with SERIAL_PORTS,
+        // which in turn takes precedence over SERIAL_PORT
+        let serial_ports = std::env::var("SERIAL_PORTS")
+            .or_else(|_| std::env::var("SERIAL_PORT"))
+            .unwrap_or_else(|_| "COM3".to_string());
+        let days = FacilityDays {
+            timezone: std::env::var("FACILITY_TZ")
+                .map(|tz| tz.parse().expect("FACILITY_TZ must be an IANA time zone, e.g. Europe/Berlin"))
+                .unwrap_or(FacilityDays::default().timezone),
+            start_hour: std::env::var("DAY_START_HOUR").ok().and_then(|h| h.parse().ok()).filter(|h| *h < 24).unwrap_or(0),
+        };
+        
+        // Ports listed without a unit report in TEMPERATURE_UNIT
+        let temperature_unit = std::env::var("TEMPERATURE_UNIT")
+            .map(|u| u.parse().expect("TEMPERATURE_UNIT must be C or F"))
+            .unwrap_or_default();
+        let motion_source = std::env::var("MOTION_SOURCE")
+            .map(|s| s.parse().expect("MOTION_SOURCE must be pir, radar or either"))
+            .unwrap_or_default();
+        let demo_mode = std::env::var("DEMO_MODE").map(|v| v == "true" || v == "1").unwrap_or(false);
+        let mock_mode = std::env::var("MOCK_MODE").map(|v| v == "true" || v == "1").unwrap_or(false) || demo_mode;
+        let serial_ports = parse_ports(&serial_ports, ',', temperature_unit);
+        let rooms = match std::env::var("ROOMS") {
+            Ok(rooms) => parse_rooms(&rooms, &serial_ports, temperature_unit),
+            Err(_) => vec![RoomConfig {
+                id: std::env::var("ROOM_ID").unwrap_or_else(|_| fhir::DEFAULT_ROOM_ID.to_string()),
+                serial_ports,
+            }],
+        };
+        assert!(!rooms.is_empty(), "ROOMS must list at least one room");
+        assert!(
+            mock_mode || rooms.iter().all(|r| !r.serial_ports.is_empty()),
+            "Every room needs at least one serial port",
+        );
+        
         Self {
-            host: std::env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-            port: std::env::var("PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8080),
-            serial_port: std::env::var("SERIAL_PORT").unwrap_or_else(|_| "COM3".to_string()),
+            bind: listen::parse_list(&bind).expect("Invalid BIND_ADDRESSES"),
+            rooms,
             baud_rate: std::env::var("BAUD_RATE").ok().and_then(|b| b.parse().ok()).unwrap_or(9600),
             sound_threshold: std::env::var("SOUND_THRESHOLD").ok().and_then(|s| s.parse().ok()).unwrap_or(150),
             inactivity_seconds: std::env::var("INACTIVITY_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(300),
+            sound_flatline_hours: std::env::var("SOUND_FLATLINE_HOURS").ok().and_then(|h| h.parse().ok()).unwrap_or(6),
+            sound_flatline_epsilon: std::env::var("SOUND_FLATLINE_EPSILON").ok().and_then(|e| e.parse().ok()).unwrap_or(1.0),
+            sensor_ranges: std::env::var("SENSOR_RANGES")
+                .map(|spec| SensorRanges::parse(&spec).expect("Invalid SENSOR_RANGES"))
+                .unwrap_or_default(),
+            vital_limits: VitalLimits {
+                tachycardia_bpm: std::env::var("TACHYCARDIA_BPM").ok().and_then(|b| b.parse().ok()).unwrap_or(VitalLimits::default().tachycardia_bpm),
+                low_spo2_percent: std::env::var("LOW_SPO2_PERCENT").ok().and_then(|p| p.parse().ok()).unwrap_or(VitalLimits::default().low_spo2_percent),
+            },
+            ventilation: match std::env::var("CO2_ALERT_PPM").ok().and_then(|p| p.parse().ok()).unwrap_or(VentilationLimit::default().co2_ppm) {
+                0 => None,
+                co2_ppm => Some(VentilationLimit {
+                    co2_ppm,
+                    minutes: std::env::var("CO2_ALERT_MINUTES").ok().and_then(|m| m.parse().ok()).unwrap_or(VentilationLimit::default().minutes),
+                }),
+            },
+            motion_source,
+            impact: match std::env::var("IMPACT_FALL_G").ok().and_then(|g| g.parse().ok()).unwrap_or(ImpactLimit::default().impact_g) {
+                g if g <= 0.0 => None,
+                impact_g => Some(ImpactLimit {
+                    impact_g,
+                    still_seconds: std::env::var("IMPACT_STILL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(ImpactLimit::default().still_seconds),
+                }),
+            },
+            calibration: match std::env::var("TEMPERATURE_CALIBRATION_DELTA").ok().and_then(|d| d.parse().ok()).unwrap_or(CalibrationLimit::default().delta_celsius) {
+                delta if delta <= 0.0 => None,
+                delta_celsius => Some(CalibrationLimit {
+                    delta_celsius,
+                    minutes: std::env::var("TEMPERATURE_CALIBRATION_MINUTES").ok().and_then(|m| m.parse().ok()).unwrap_or(CalibrationLimit::default().minutes),
+                }),
+            },
             db_config: DbConfig::from_env(),
-            mock_mode: std::env::var("MOCK_MODE").map(|v| v == "true" || v == "1").unwrap_or(false),
+            ingest_batch: BatchConfig {
+                max_readings: std::env::var("INGEST_BATCH_SIZE").ok().and_then(|n| n.parse().ok()).filter(|n| *n > 0).unwrap_or(BatchConfig::default().max_readings),
+                max_delay: std::env::var("INGEST_BATCH_MS").ok().and_then(|ms| ms.parse().ok()).map(Duration::from_millis).unwrap_or(BatchConfig::default().max_delay),
+                max_burst_readings: std::env::var("INGEST_BATCH_MAX").ok().and_then(|n| n.parse().ok()).filter(|n| *n > 0).unwrap_or(BatchConfig::default().max_burst_readings),
+                shed_depth: match std::env::var("INGEST_SHED_DEPTH").ok().and_then(|n| n.parse().ok()) {
+                    Some(0) => None,
+                    Some(n) => Some(n),
+                    None => BatchConfig::default().shed_depth,
+                },
+                max_queued: std::env::var("INGEST_QUEUE_MAX").ok().and_then(|n| n.parse().ok()).unwrap_or(BatchConfig::default().max_queued),
+            },
+            ingest_wal_dir: std::env::var("INGEST_WAL_DIR").ok().filter(|d| !d.is_empty()).map(PathBuf::from),
+            serial_queue_max: std::env::var("SERIAL_QUEUE_MAX").ok().and_then(|n| n.parse().ok()).filter(|n| *n > 0).unwrap_or(1000),
+            mock_mode,
+            demo_mode,
+            demo_seed_days: std::env::var("DEMO_SEED_DAYS").ok().and_then(|d| d.parse().ok()).unwrap_or(7),
+            ward_id: std::env::var("WARD_ID").ok(),
+            alert_routes_file: std::env::var("ALERT_ROUTES_FILE").ok(),
+            announce_template: std::env::var("ANNOUNCE_TEMPLATE")
+                .map(|template| AnnouncementTemplate::parse(&template).expect("Invalid ANNOUNCE_TEMPLATE"))
+                .unwrap_or_default(),
+            alert_escalation: std::env::var("ALERT_ESCALATE_MINUTES").ok().and_then(|m| m.parse().ok()).filter(|m| *m > 0).map(|m: u64| Duration::from_secs(m * 60)),
+            alert_resolution: std::env::var("ALERT_AUTO_RESOLVE")
+                .map(|spec| ResolutionPolicies::parse(&spec).expect("Invalid ALERT_AUTO_RESOLVE"))
+                .unwrap_or_default()
+                .with_motion_source(motion_source),
+            settings_permissions: std::env::var("SETTINGS_PERMISSIONS")
+                .map(|spec| SettingsPermissions::parse(&spec).expect("Invalid SETTINGS_PERMISSIONS"))
+                .unwrap_or_default(),
+            device_log_retention_days: std::env::var("DEVICE_LOG_RETENTION_DAYS").ok().and_then(|d| d.parse().ok()).unwrap_or(30),
+            retention: RetentionConfig {
+                days: std::env::var("RETENTION_DAYS").ok().and_then(|d| d.parse().ok()).unwrap_or(0),
+                archive_dir: std::env::var("RETENTION_ARCHIVE_DIR").ok().filter(|d| !d.is_empty()).map(PathBuf::from),
+            },
+            compact_after_days: std::env::var("COMPACT_AFTER_DAYS").ok().and_then(|d| d.parse().ok()).unwrap_or(0),
+            export: ExportConfig {
+                pseudonym_key: std::env::var("EXPORT_PSEUDONYM_KEY").ok().filter(|k| !k.is_empty()),
+            },
+            research: ResearchConfig {
+                // A group of one would describe a single patient
+                min_group_size: std::env::var("RESEARCH_MIN_GROUP_SIZE").ok().and_then(|n| n.parse().ok()).filter(|n| *n >= 2).unwrap_or(ResearchConfig::default().min_group_size),
+                noise_epsilon: std::env::var("RESEARCH_NOISE_EPSILON").ok().and_then(|e| e.parse().ok()).filter(|e: &f64| e.is_finite() && *e > 0.0),
+            },
+            ward_frame_seconds: std::env::var("WARD_FRAME_SECONDS").ok().and_then(|s| s.parse().ok()).filter(|s| *s > 0).unwrap_or(5),
+            ws_stats_persist_secs: std::env::var("WS_STATS_PERSIST_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(0),
+            body_limits: BodyLimits {
+                default: std::env::var("MAX_BODY_KB").ok().and_then(|kb| kb.parse::<usize>().ok()).filter(|kb| *kb > 0).map(|kb| kb * 1024).unwrap_or(BodyLimits::default().default),
+                bulk: std::env::var("MAX_BULK_BODY_KB").ok().and_then(|kb| kb.parse::<usize>().ok()).filter(|kb| *kb > 0).map(|kb| kb * 1024).unwrap_or(BodyLimits::default().bulk),
+            },
+            ops_alert_webhook: std::env::var("OPS_ALERT_WEBHOOK").ok(),
+            frontend_dir: std::env::var("FRONTEND_DIR").ok(),
+            days,
+            quiet_hours: QuietHours {
+                start_hour: std::env::var("QUIET_HOURS_START").ok().and_then(|h| h.parse().ok()).filter(|h| *h < 24).unwrap_or(22),
+                end_hour: std::env::var("QUIET_HOURS_END").ok().and_then(|h| h.parse().ok()).filter(|h| *h < 24).unwrap_or(6),
+                noise_limit: std::env::var("QUIET_HOURS_NOISE_LIMIT").ok().and_then(|s| s.parse().ok()).unwrap_or(100),
+            },
+            shifts: std::env::var("SHIFTS")
+                .map(|spec| Shifts::parse(&spec).expect("Invalid SHIFTS"))
+                .unwrap_or_default(),
+            digest_webhook: std::env::var("DIGEST_WEBHOOK").ok(),
+            morning_report_webhook: std::env::var("MORNING_REPORT_WEBHOOK").ok(),
+            public_url: std::env::var("PUBLIC_URL").ok().map(|u| u.trim_end_matches('/').to_string()),
+            slack_signing_secret: std::env::var("SLACK_SIGNING_SECRET").ok().filter(|s| !s.is_empty()),
+            teams_ack_secret: std::env::var("TEAMS_ACK_SECRET").ok().filter(|s| !s.is_empty()),
+            ack_link_secret: std::env::var("ALERT_ACK_LINK_SECRET").ok().filter(|s| !s.is_empty()),
+            ack_link_ttl: chrono::Duration::minutes(std::env::var("ALERT_ACK_LINK_MINUTES").ok().and_then(|m| m.parse().ok()).filter(|m| *m > 0).unwrap_or(60)),
+            kiosk_token_max_ttl: std::env::var("KIOSK_TOKEN_MAX_DAYS").ok().and_then(|d| d.parse().ok()).filter(|d| *d > 0).and_then(chrono::Duration::try_days).unwrap_or(chrono::Duration::days(30)),
+            calendar: CalendarConfig {
+                feeds: std::env::var("PROCEDURE_CALENDARS")
+                    .map(|f| calendar::parse_feeds(&f).expect("Invalid PROCEDURE_CALENDARS"))
+                    .unwrap_or_default(),
+                poll_interval: Duration::from_secs(std::env::var("PROCEDURE_CALENDAR_POLL_SECS").ok().and_then(|s| s.parse().ok()).filter(|s| *s > 0).unwrap_or(300)),
+                timezone: std::env::var("PROCEDURE_CALENDAR_TZ")
+                    .map(|tz| tz.parse().expect("PROCEDURE_CALENDAR_TZ must be an IANA time zone, e.g. Europe/Berlin"))
+                    .unwrap_or(days.timezone),
+            },
+            adt: AdtConfig {
+                mllp_bind: std::env::var("ADT_MLLP_BIND")
+                    .ok()
+                    .map(|a| a.parse().expect("ADT_MLLP_BIND must be an address, e.g. 0.0.0.0:2575")),
+                fhir_token: std::env::var("ADT_FHIR_TOKEN").ok().filter(|t| !t.is_empty()),
+                locations: std::env::var("ADT_LOCATIONS")
+                    .map(|l| adt::parse_locations(&l).expect("Invalid ADT_LOCATIONS"))
+                    .unwrap_or_default(),
+            },
+        }
+    }
+}
+
+/// Write the readings of `room` to its write-ahead log in `dir`, first
+/// recovering those left in it
+async fn attach_wal(buffer: &mut IngestBuffer, dir: &std::path::Path, room: &str) -> Result<(), String> {
+    let wal = Wal::open(dir, room)
+        .await
+        .map_err(|e| format!("failed to open write-ahead log in {}: {}", dir.display(), e))?;
+    buffer.attach_wal(wal).await.map_err(|e| format!("failed to read write-ahead log: {}", e))
+}
+
+/// Record a shadow rule's hit; a hit that can't be stored is only logged
+async fn store_shadow_hit(db: &Database, hit: &ShadowHit) {
+    if let Err(e) = db.insert_shadow_hit(hit).await {
+        error!("Failed to store hit of shadow rule {} in {}: {}", hit.rule_id, hit.room, e);
+    }
+}
+
+/// Check the temperature of a device's reading against the room's other
+/// sensor nodes: while they disagree the plausible temperature is stored in
+/// its place, and calibration is raised once they have for a while
+fn cross_check_temperature(check: &mut TemperatureCrossCheck, event: &mut SensorEvent) {
+    let Some(port) = event.device.clone() else {
+        return;
+    };
+    let was_needed = check.calibration_needed();
+    let result = check.push(&port, event.reading.timestamp, event.reading.temperature);
+    if result.temperature != event.reading.temperature {
+        debug!("Storing {:.1}°C instead of {:.1}°C from {} in {}, which disagrees with the room's other sensors",
+            result.temperature, event.reading.temperature, port, event.room);
+        event.reading.temperature = result.temperature;
+    }
+    if result.calibration_needed {
+        event.alerts.insert(AlertType::Calibration);
+    }
+    if result.calibration_needed != was_needed {
+        if result.calibration_needed {
+            warn!(">>> CALIBRATION ALERT: temperature sensors in {} differ by {:.1}°C", event.room, result.disagreement.unwrap_or_default());
+        } else {
+            info!("Temperature sensors in {} agree again", event.room);
         }
     }
 }
@@ -59,121 +444,613 @@ async fn main() -> std::io::Result<()> {
     info!("========================================");
     
     let config = Config::from_env();
+    let room_ids: Vec<String> = config.rooms.iter().map(|r| r.id.clone()).collect();
+    
+    let mut args = std::env::args().skip(1);
+    let command = args.next();
+    
+    // `monitor --migrate` applies pending schema migrations and exits
+    if command.as_deref() == Some("--migrate") {
+        let db_config = DbConfig { auto_migrate: true, ..config.db_config };
+        Database::new(db_config).await.expect("Failed to migrate database");
+        info!("Database schema is up to date");
+        return Ok(());
+    }
+    
+    // `monitor seed [options]` fills the database with synthetic data and exits
+    if command.as_deref() == Some("seed") {
+        let options = seed::SeedOptions::from_args(args).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(2);
+        });
+        let db = Database::new(config.db_config)
+            .await
+            .expect("Failed to initialize database");
+        db.register_rooms(&room_ids).await.expect("Failed to register rooms");
+        let settings = MonitorSettings {
+            inactivity_seconds: config.inactivity_seconds,
+            sound_threshold: config.sound_threshold,
+        };
+        if let Err(e) = seed::seed(&db, &options, &settings, &room_ids).await {
+            error!("Seeding failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     
-    info!("Server: {}:{}", config.host, config.port);
-    info!("Serial: {} @ {} baud", config.serial_port, config.baud_rate);
+    info!("Server: {}", config.bind.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+    for room in &config.rooms {
+        let ports: Vec<&str> = room.serial_ports.iter().map(|(port, _)| port.as_str()).collect();
+        info!("Room {}: serial {} @ {} baud", room.id, ports.join(", "), config.baud_rate);
+    }
     info!("Mock mode: {}", config.mock_mode);
+    if config.demo_mode {
+        info!("Demo mode: serving synthetic data, changes are disabled");
+    }
     
     // Initialize database
+    let partitioned = config.db_config.partitioned;
     let db = Database::new(config.db_config)
         .await
         .expect("Failed to initialize database");
+    db.register_rooms(&room_ids).await.expect("Failed to register rooms");
+    if let Some(ward) = &config.ward_id {
+        db.register_ward(ward, &room_ids).await.expect("Failed to register ward");
+    }
+    let wards = WardMap::load(&db).await.expect("Failed to load wards");
     
     // Initialize broadcaster
     let broadcaster = Arc::new(SensorBroadcaster::new(100));
     
+    // Initialize alert notifications
+    let routes = match &config.alert_routes_file {
+        Some(path) => RoutingTable::from_file(path).expect("Failed to load alert routes"),
+        None => RoutingTable::default(),
+    }.local_to(config.days);
+    let snoozes = Snoozes::default();
+    for snooze in db.get_active_snoozes().await.expect("Failed to load alert snoozes") {
+        snoozes.snooze(&snooze.room, snooze.alert, snooze.until);
+    }
+    // Scheduled procedures suppress notifications while they run
+    let procedures = Procedures::default();
+    calendar::spawn(config.calendar.clone(), procedures.clone());
+    let chatops = ChatOpsConfig {
+        public_url: config.public_url.clone().unwrap_or_else(|| listen::base_url(&config.bind)),
+        slack_signing_secret: config.slack_signing_secret.clone(),
+        teams_ack_secret: config.teams_ack_secret.clone(),
+        // Opening a link acknowledges, which a demo mustn't allow
+        ack_link_secret: config.ack_link_secret.clone().filter(|_| !config.demo_mode),
+        ack_link_ttl: config.ack_link_ttl,
+    };
+    let (alert_acks, acks_rx) = tokio::sync::mpsc::unbounded_channel();
+    let announcer = Announcer::new(config.announce_template.clone());
+    Notifier::new(routes, config.ward_id.clone(), wards.clone(), snoozes.clone(), procedures.clone(), chatops.clone(), db.clone())
+        .escalate_after(config.alert_escalation)
+        .announce_with(announcer.clone())
+        .spawn(broadcaster.subscribe(), acks_rx);
+    
+    // Initialize ward overview projection
+    let ward_projection = WardProjection::new(
+        config.ward_id.clone(),
+        wards.clone(),
+        Duration::from_secs(config.ward_frame_seconds),
+    );
+    ward_projection.track(&room_ids, broadcaster.subscribe());
+    
     // Initialize settings (shared between AppState and SerialReader)
     let settings = Arc::new(RwLock::new(MonitorSettings {
         inactivity_seconds: config.inactivity_seconds,
         sound_threshold: config.sound_threshold,
     }));
     
-    // Start serial reader
-    let serial_config = SerialConfig {
-        port: config.serial_port.clone(),
-        baud_rate: config.baud_rate,
-        sound_threshold: config.sound_threshold,
-        inactivity_seconds: config.inactivity_seconds,
-    };
+    if config.demo_mode {
+        let settings = settings.read().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Err(e) = demo::prepare(&db, &settings, &room_ids, config.demo_seed_days).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    
+    let flags = FeatureFlags::load(&db).await.expect("Failed to load feature flags");
+    
+    // Load custom alert rules (shared between AppState and SerialReader)
+    let rules = Arc::new(RwLock::new(
+        RuleSet::load(&db, config.days).await.expect("Failed to load alert rules"),
+    ));
     
-    let db_for_serial = db.clone();
-    let broadcaster_for_serial = Arc::clone(&broadcaster);
-    let settings_for_serial = Arc::clone(&settings);
+    // Readings are only stored while the room's patient consents to monitoring
+    let monitoring_consent = MonitoringConsent::load(&db, &room_ids)
+        .await
+        .expect("Failed to load patient consent");
     
     if config.mock_mode {
         info!("Starting in MOCK MODE");
-        let mock_reader = serial::MockSerialReader::start();
-        
-        tokio::spawn(async move {
-            loop {
-                if let Some(mut event) = mock_reader.try_recv() {
-                    match db_for_serial.insert_reading(&event).await {
-                        Ok(id) => event.id = Some(id),
-                        Err(e) => error!("Failed to save: {}", e),
-                    }
-                    broadcaster_for_serial.broadcast(event);
-                }
-                tokio::time::sleep(Duration::from_millis(100)).await;
-            }
-        });
     } else {
         info!("Available serial ports:");
         serial::list_available_ports();
+    }
+    
+    // Ingestion is supervised per room: a panic or a stopped reader restarts it
+    let mut ingest_health = BTreeMap::new();
+    let mut ingest_queues = BTreeMap::new();
+    let mut reading_queues = BTreeMap::new();
+    let mut device_connections = BTreeMap::new();
+    let mut temperature_checks = BTreeMap::new();
+    
+    for room in &config.rooms {
+        let health = Arc::new(TaskHealth::default());
+        ingest_health.insert(room.id.clone(), Arc::clone(&health));
+        let queue_stats = Arc::new(QueueStats::default());
+        ingest_queues.insert(room.id.clone(), Arc::clone(&queue_stats));
+        let reading_queue = Arc::new(ReadingQueue::new(config.serial_queue_max));
+        reading_queues.insert(room.id.clone(), Arc::clone(&reading_queue));
+        
+        let db_for_serial = db.clone();
+        let broadcaster_for_serial = Arc::clone(&broadcaster);
+        let settings_for_serial = Arc::clone(&settings);
+        let consent_for_serial = monitoring_consent.room(&room.id).expect("consent of every ingested room");
+        let rules_for_serial = Arc::clone(&rules);
+        let flags_for_serial = flags.clone();
+        let limits = AlertLimits {
+            vitals: config.vital_limits,
+            ventilation: config.ventilation,
+            impact: config.impact,
+            motion_source: config.motion_source,
+        };
+        let batch = config.ingest_batch;
+        let policies = config.alert_resolution.clone();
+        let wal_dir = config.ingest_wal_dir.clone();
+        let name = format!("ingest:{}", room.id);
         
-        match SerialReader::start(serial_config, settings_for_serial) {
-            Ok(reader) => {
-                info!("Serial reader started");
+        if config.mock_mode {
+            let room_id = room.id.clone();
+            
+            supervise(name, health, config.ops_alert_webhook.clone(), move || {
+                let settings_for_serial = Arc::clone(&settings_for_serial);
+                let rules_for_serial = Arc::clone(&rules_for_serial);
+                let flags_for_serial = flags_for_serial.clone();
+                let db_for_serial = db_for_serial.clone();
+                let broadcaster_for_serial = Arc::clone(&broadcaster_for_serial);
+                let consent_for_serial = Arc::clone(&consent_for_serial);
+                let room_id = room_id.clone();
+                let policies = policies.clone();
+                let queue_stats = Arc::clone(&queue_stats);
+                let reading_queue = Arc::clone(&reading_queue);
+                let wal_dir = wal_dir.clone();
+                
+                async move {
+                    let mut buffer = IngestBuffer::new(batch, policies, queue_stats);
+                    if let Some(dir) = &wal_dir {
+                        attach_wal(&mut buffer, dir, &room_id).await?;
+                    }
+                    let mut states = RoomStates::new(room_id.clone());
+                    let mut mock_reader = SerialReader::mock(room_id, settings_for_serial, rules_for_serial, flags_for_serial, limits, reading_queue);
+                    loop {
+                        let alive = tokio::select! {
+                            message = mock_reader.recv() => match message {
+                                Some(SerialMessage::Reading(event)) => {
+                                    let consent = consent_for_serial.load(Ordering::Relaxed);
+                                    if let Some(passage) = states.reading(&db_for_serial, &event.reading, consent).await {
+                                        broadcaster_for_serial.broadcast(LiveEvent::DoorPassage { room: event.room.clone(), passage });
+                                    }
+                                    buffer.push(event, consent);
+                                    true
+                                }
+                                Some(SerialMessage::ShadowHit(hit)) => {
+                                    store_shadow_hit(&db_for_serial, &hit).await;
+                                    true
+                                }
+                                Some(_) => true,
+                                None => false,
+                            },
+                            _ = buffer.due() => true,
+                        };
+                        if buffer.is_due() || !alive {
+                            for event in buffer.flush(&db_for_serial).await {
+                                broadcaster_for_serial.broadcast(event);
+                            }
+                        }
+                        if !alive {
+                            return Err("mock reader stopped".to_string());
+                        }
+                    }
+                }
+            });
+        } else {
+            let serial_configs: Vec<SerialConfig> = room.serial_ports
+                .iter()
+                .map(|(port, temperature_unit)| SerialConfig {
+                    room: room.id.clone(),
+                    port: port.clone(),
+                    baud_rate: config.baud_rate,
+                    sound_threshold: config.sound_threshold,
+                    inactivity_seconds: config.inactivity_seconds,
+                    temperature_unit: *temperature_unit,
+                    sound_flatline_hours: config.sound_flatline_hours,
+                    sound_flatline_epsilon: config.sound_flatline_epsilon,
+                    ranges: config.sensor_ranges,
+                })
+                .collect();
+            let connections: BTreeMap<String, Arc<DeviceConnection>> = room.serial_ports
+                .iter()
+                .map(|(port, _)| (port.clone(), Arc::new(DeviceConnection::default())))
+                .collect();
+            device_connections.insert(room.id.clone(), connections.clone());
+            // Only rooms with several sensor nodes have temperatures to compare
+            let cross_check = config.calibration
+                .filter(|_| room.serial_ports.len() > 1)
+                .map(|limit| Arc::new(Mutex::new(TemperatureCrossCheck::new(limit))));
+            if let Some(check) = &cross_check {
+                temperature_checks.insert(room.id.clone(), Arc::clone(check));
+            }
+            let room_id = room.id.clone();
+            
+            supervise(name, health, config.ops_alert_webhook.clone(), move || {
+                let serial_configs = serial_configs.clone();
+                let settings_for_serial = Arc::clone(&settings_for_serial);
+                let rules_for_serial = Arc::clone(&rules_for_serial);
+                let flags_for_serial = flags_for_serial.clone();
+                let db_for_serial = db_for_serial.clone();
+                let broadcaster_for_serial = Arc::clone(&broadcaster_for_serial);
+                let consent_for_serial = Arc::clone(&consent_for_serial);
+                let room_id = room_id.clone();
+                let connections = connections.clone();
+                let cross_check = cross_check.clone();
+                let policies = policies.clone();
+                let queue_stats = Arc::clone(&queue_stats);
+                let reading_queue = Arc::clone(&reading_queue);
+                let wal_dir = wal_dir.clone();
                 
-                tokio::spawn(async move {
+                async move {
+                    let mut buffer = IngestBuffer::new(batch, policies, queue_stats);
+                    if let Some(dir) = &wal_dir {
+                        attach_wal(&mut buffer, dir, &room_id).await?;
+                    }
+                    let mut reader = SerialReader::start(serial_configs, settings_for_serial, rules_for_serial, flags_for_serial, limits, reading_queue);
+                    let mut states = RoomStates::new(room_id.clone());
+                    let mut devices: BTreeMap<String, DeviceTracker> = connections
+                        .keys()
+                        .map(|port| (port.clone(), DeviceTracker::new(port.clone(), room_id.clone())))
+                        .collect();
+                    
                     loop {
-                        if let Some(mut event) = reader.try_recv() {
-                            info!("Sensor: temp={:.1}°C motion={} sound={}",
-                                event.reading.temperature,
-                                event.reading.motion,
-                                event.reading.sound_level);
-                            
-                            match db_for_serial.insert_reading(&event).await {
-                                Ok(id) => event.id = Some(id),
-                                Err(e) => error!("Failed to save: {}", e),
+                        let alive = tokio::select! {
+                            message = reader.recv() => match message {
+                                Some(SerialMessage::Reading(mut event)) => {
+                                    if let Some(device) = event.device.as_ref().and_then(|port| devices.get_mut(port)) {
+                                        device.seen(event.reading.timestamp);
+                                    }
+                                    if let Some(check) = &cross_check {
+                                        cross_check_temperature(&mut check.lock().unwrap(), &mut event);
+                                    }
+                                    info!("Sensor {}: temp={:.1}°C motion={} sound={}",
+                                        event.room,
+                                        event.reading.temperature,
+                                        event.reading.motion,
+                                        event.reading.sound_level);
+                                    
+                                    let consent = consent_for_serial.load(Ordering::Relaxed);
+                                    if let Some(passage) = states.reading(&db_for_serial, &event.reading, consent).await {
+                                        broadcaster_for_serial.broadcast(LiveEvent::DoorPassage { room: event.room.clone(), passage });
+                                    }
+                                    buffer.push(event, consent);
+                                    true
+                                }
+                                Some(SerialMessage::ShadowHit(hit)) => {
+                                    store_shadow_hit(&db_for_serial, &hit).await;
+                                    true
+                                }
+                                Some(SerialMessage::Device(port, event)) => {
+                                    // Ports are those of the room's devices
+                                    let (Some(device), Some(connection)) = (devices.get_mut(&port), connections.get(&port)) else {
+                                        unreachable!("message from unknown port {}", port);
+                                    };
+                                    match event {
+                                        DeviceEvent::Bed(at, occupied) => {
+                                            device.seen(at);
+                                            states.bed(&db_for_serial, at, occupied, consent_for_serial.load(Ordering::Relaxed)).await;
+                                        }
+                                        DeviceEvent::Firmware(version) => device.set_firmware(version),
+                                        DeviceEvent::Connected => {
+                                            connection.set(true);
+                                            broadcaster_for_serial.broadcast(LiveEvent::DeviceStatus {
+                                                room: room_id.clone(),
+                                                device: port,
+                                                error: None,
+                                                at: chrono::Utc::now(),
+                                            });
+                                        }
+                                        DeviceEvent::Disconnected(error) => {
+                                            connection.set(false);
+                                            broadcaster_for_serial.broadcast(LiveEvent::DeviceStatus {
+                                                room: room_id.clone(),
+                                                device: port,
+                                                error: Some(error),
+                                                at: chrono::Utc::now(),
+                                            });
+                                        }
+                                        DeviceEvent::Log(log) => {
+                                            device.seen(chrono::Utc::now());
+                                            if let Err(e) = db_for_serial
+                                                .insert_device_log(&port, &log.level, &log.message)
+                                                .await
+                                            {
+                                                error!("Failed to save device log: {}", e);
+                                            }
+                                        }
+                                        DeviceEvent::Quarantined { at, line, reason } => {
+                                            device.seen(at);
+                                            let quarantined = QuarantinedReading {
+                                                id: 0,
+                                                room: room_id.clone(),
+                                                device_id: Some(port),
+                                                received_at: at,
+                                                line,
+                                                reason,
+                                            };
+                                            if let Err(e) = db_for_serial.insert_quarantined_reading(&quarantined).await {
+                                                error!("Failed to save quarantined reading: {}", e);
+                                            }
+                                        }
+                                    }
+                                    true
+                                }
+                                None => false,
+                            },
+                            _ = buffer.due() => true,
+                        };
+                        if buffer.is_due() || !alive {
+                            for event in buffer.flush(&db_for_serial).await {
+                                broadcaster_for_serial.broadcast(event);
                             }
-                            broadcaster_for_serial.broadcast(event);
                         }
-                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        for device in devices.values_mut() {
+                            device.sync(&db_for_serial, !alive).await;
+                        }
+                        if !alive {
+                            return Err("serial reader stopped".to_string());
+                        }
                     }
-                });
+                }
+            });
+        }
+    }
+    
+    // Purge old device logs, WebSocket sessions, quarantined readings and job runs once an hour
+    let db_for_logs = db.clone();
+    let log_retention = chrono::Duration::days(config.device_log_retention_days);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match db_for_logs.purge_device_logs(chrono::Utc::now() - log_retention).await {
+                Ok(0) => {}
+                Ok(n) => info!("Purged {} expired device log entries", n),
+                Err(e) => error!("Failed to purge device logs: {}", e),
+            }
+            match db_for_logs.purge_ws_sessions(chrono::Utc::now() - log_retention).await {
+                Ok(0) => {}
+                Ok(n) => info!("Purged {} expired WebSocket sessions", n),
+                Err(e) => error!("Failed to purge WebSocket sessions: {}", e),
+            }
+            match db_for_logs.purge_quarantined_readings(chrono::Utc::now() - log_retention).await {
+                Ok(0) => {}
+                Ok(n) => info!("Purged {} expired quarantined readings", n),
+                Err(e) => error!("Failed to purge quarantined readings: {}", e),
             }
-            Err(e) => {
-                error!("Failed to start serial reader: {}", e);
-                error!("Set MOCK_MODE=true to run without Arduino");
+            match db_for_logs.purge_job_runs(chrono::Utc::now() - log_retention).await {
+                Ok(0) => {}
+                Ok(n) => info!("Purged {} expired job runs", n),
+                Err(e) => error!("Failed to purge job runs: {}", e),
             }
         }
+    });
+    
+    jobs::recover(&db).await;
+    retention::spawn(db.clone(), config.retention.clone(), config.days);
+    compaction::spawn(db.clone(), room_ids.clone(), config.compact_after_days, config.days);
+    // The demo has no patients, and admissions would register some
+    if !config.demo_mode {
+        adt::spawn(db.clone(), config.adt.clone(), monitoring_consent.clone());
     }
+    partitions::spawn(db.clone(), partitioned);
+    
+    let ws_clients = WsClients::default();
+    if config.ws_stats_persist_secs > 0 {
+        ws_clients::spawn_persistence(db.clone(), ws_clients.clone(), Duration::from_secs(config.ws_stats_persist_secs));
+    }
+    
+    // Per-day aggregates for long-range trends
+    rollup::spawn(db.clone(), config.days);
+    
+    // Weekly digest with the quiet-hours report
+    let report_config = ReportConfig {
+        rooms: room_ids.clone(),
+        ward: config.ward_id.clone(),
+        days: config.days,
+        quiet_hours: config.quiet_hours,
+        procedures,
+        shifts: config.shifts.clone(),
+    };
+    reports::spawn_weekly_digest(db.clone(), report_config.clone(), config.digest_webhook.clone());
+    
+    // Daily fall-risk scores in the morning report
+    reports::spawn_morning_report(db.clone(), report_config.clone(), config.morning_report_webhook.clone());
     
     let app_state = web::Data::new(AppState {
         db: db.clone(),
-        base_url: format!("http://{}:{}", config.host, config.port),
-        settings: settings,
+        base_url: listen::base_url(&config.bind),
+        settings,
+        rooms: room_ids,
+        monitoring_consent,
+        rules,
+        ingest_health,
+        ingest_queues,
+        reading_queues,
+        device_connections,
+        temperature_checks,
+        snoozes,
+        alert_acks,
+        alert_resolution: config.alert_resolution.clone(),
+        settings_permissions: config.settings_permissions.clone(),
+        broadcaster: Arc::clone(&broadcaster),
+        reports: report_config,
+        wards,
+        retention: config.retention.clone(),
+        export: config.export.clone(),
+        research: config.research.clone(),
+        body_limits: config.body_limits,
+        sensor_ranges: config.sensor_ranges,
+        days: config.days,
+        ws_clients,
+        flags,
+        mock_mode: config.mock_mode,
     });
     
     let broadcaster_data = web::Data::new(broadcaster);
+    let ward_data = web::Data::new(ward_projection);
+    let announcer_data = web::Data::new(announcer);
+    let chatops_data = web::Data::new(chatops);
+    let adt_data = web::Data::new(config.adt.clone());
+    let kiosk_data = web::Data::new(KioskConfig {
+        public_url: config.public_url.clone().unwrap_or_else(|| listen::base_url(&config.bind)),
+        max_ttl: config.kiosk_token_max_ttl,
+    });
+    
+    let body_limits = config.body_limits;
+    let frontend_dir = config.frontend_dir.clone();
+    assets::log_source(frontend_dir.as_deref());
     
-    info!("Starting server on {}:{}", config.host, config.port);
-    info!("Dashboard: http://{}:{}", config.host, config.port);
+    let dashboard_url = listen::base_url(&config.bind);
+    let demo_mode = config.demo_mode;
     
-    HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
             .allow_any_method()
-            .allow_any_header();
+            .allow_any_header()
+            .expose_headers([request_id::REQUEST_ID_HEADER]);
         
         App::new()
+            .wrap(middleware::Condition::new(demo_mode, middleware::from_fn(demo::read_only)))
+            .wrap(middleware::from_fn(request_id::middleware))
             .wrap(cors)
             .app_data(app_state.clone())
             .app_data(broadcaster_data.clone())
+            .app_data(ward_data.clone())
+            .app_data(announcer_data.clone())
+            .app_data(chatops_data.clone())
+            .app_data(adt_data.clone())
+            .app_data(kiosk_data.clone())
+            .app_data(limits::json_config(body_limits.default))
             .service(api::health_check)
             .service(api::list_observations)
+            .service(api::record_observation)
             .service(api::get_latest_observation)
+            .service(api::list_observation_changes)
+            .service(api::get_downsampled)
             .service(api::get_observation_by_id)
+            .service(api::correct_observation)
+            .service(api::delete_observation)
+            .service(api::get_observation_chart)
+            .service(api::list_rooms)
+            .service(api::set_room_ward)
+            .service(api::get_state_history)
+            .service(api::get_door_passages)
+            .service(api::list_wards)
+            .service(api::create_ward)
+            .service(api::delete_ward)
             .service(api::get_summary)
+            .service(api::get_daily_summary)
+            .service(api::get_timeseries)
             .service(api::get_sleep_analysis)
             .service(api::get_period_analysis)
             .service(api::get_hourly_analysis)
+            .service(api::get_threshold_sweep)
+            .service(api::get_alert_precision)
+            .service(api::get_quiet_hours_report)
             .service(api::get_settings)
             .service(api::update_settings)
+            .service(api::get_settings_history)
+            .service(api::list_rules)
+            .service(api::create_rule)
+            .service(api::delete_rule)
+            .service(api::get_shadow_hits)
+            .service(api::promote_rule)
+            .service(api::set_rule_threshold)
+            .service(api::get_morning_report)
+            .service(api::get_handover)
+            .service(api::list_patients)
+            .service(api::create_patient)
+            .service(api::get_patient)
+            .service(api::update_patient)
+            .service(api::delete_patient)
+            .service(api::get_consent)
+            .service(api::update_consent)
+            .service(api::get_fall_risk)
+            .service(api::list_devices)
+            .service(web::resource("/api/devices/{id}/logs")
+                .app_data(limits::json_config(body_limits.bulk))
+                .route(web::post().to(api::upload_device_log))
+                .route(web::get().to(api::get_device_logs)))
+            .service(api::list_quarantined)
+            .service(api::list_tags)
+            .service(api::apply_tag)
+            .service(api::delete_tag)
+            .service(api::untag_reading)
+            .service(api::get_observation_tags)
+            .service(api::list_alerts)
+            .service(api::list_snoozes)
+            .service(api::get_alert_stats)
+            .service(api::snooze_alert)
+            .service(api::unsnooze_alert)
+            .service(api::ack_alert)
+            .service(api::set_alert_outcome)
+            .service(api::export_access_log)
+            .service(api::export_readings)
+            .service(api::export_stream)
+            .service(api::export_sound_matrix)
+            .service(api::import_readings)
+            .service(api::research_aggregates)
+            .service(api::purge_expired)
+            .service(api::list_ws_clients)
+            .service(api::get_ws_subscription)
+            .service(api::update_ws_subscription)
+            .service(api::delete_ws_subscription)
+            .service(api::list_jobs)
+            .service(api::list_flags)
+            .service(api::set_flag)
+            .service(api::reset_flag)
+            .service(api::get_storage)
+            .service(api::seed_data)
+            .service(chatops::slack_interactive)
+            .service(chatops::teams_ack)
+            .service(chatops::ack_link_target)
+            .service(adt::encounter)
+            .service(kiosk::create_kiosk_token)
+            .service(kiosk::list_kiosk_tokens)
+            .service(kiosk::revoke_kiosk_token)
+            .service(kiosk::kiosk_status)
             .route("/ws", web::get().to(websocket::ws_handler))
-            .service(actix_files::Files::new("/", "./frontend").index_file("index.html"))
+            .route("/ws/ward", web::get().to(websocket::ward_ws_handler))
+            .route("/ws/announcements", web::get().to(websocket::announcements_ws_handler))
+            .configure(|cfg| assets::configure(cfg, frontend_dir.as_deref()))
     })
-    .bind((config.host.as_str(), config.port))?
-    .run()
-    .await
+    // Drop the handler (and cancel its queries) as soon as a client hangs up,
+    // instead of finishing work nobody will read
+    .h1_allow_half_closed(false);
+    
+    for addr in &config.bind {
+        info!("Starting server on {}", addr);
+        server = match addr {
+            BindAddress::Tcp(addr) => server.listen(listen::tcp_listener(*addr)?)?,
+            #[cfg(unix)]
+            BindAddress::Unix(path) => server.bind_uds(path)?,
+            #[cfg(not(unix))]
+            BindAddress::Unix(_) => unreachable!("rejected when parsing"),
+        };
+    }
+    info!("Dashboard: {}", dashboard_url);
+    
+    server.run().await
 }
@@ -0,0 +1,793 @@
+//! Alert notification routing and dispatch
+//!
+//! Routes map (alert type, severity, room, ward, time window) to a list of
+//! notification targets. Every route that matches an alert contributes its
+//! targets, so e.g. a night-time route to the on-call phone and a day-time
+//! route to the ward station can coexist. Time windows are hours of the
+//! facility's local time (`FACILITY_TZ`).
+//!
+//! Besides the onset of an alert (`created`), webhooks can be sent its later
+//! transitions: `acked` by staff, `resolved` as its type's auto-resolution
+//! policy allows (see `ingest`), with the reason, and
+//! `escalated` when it is still unacknowledged `ALERT_ESCALATE_MINUTES`
+//! after onset. Each is numbered with a `sequence` that increases across all
+//! transitions and restarts, so an incident system mirroring the alerts can
+//! tell a missed or reordered delivery. Later transitions go to the targets
+//! of the routes the onset matched, and not at all if the onset wasn't
+//! notified (snoozed, or during a procedure).
+//!
+//! `announce` targets have the overhead paging system speak new and
+//! escalated alerts (see `announce`).
+
+use chrono::{DateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info, warn};
+
+use crate::announce::Announcer;
+use crate::calendar::Procedures;
+use crate::chatops::{self, AlertCard, ChatOpsConfig};
+use crate::days::FacilityDays;
+use crate::db::{Alert, AlertEvent, Database};
+use crate::fhir::{AlertSet, AlertSeverity, AlertType, ResolutionReason, SensorEvent};
+use crate::ward::WardMap;
+use crate::websocket::LiveEvent;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "channel", rename_all = "lowercase")]
+pub enum NotifyTarget {
+    /// Write the alert to the server log
+    Log,
+    /// POST the alert as JSON to a URL (ward station, paging gateway, ...)
+    Webhook {
+        url: String,
+        /// Transitions posted to this URL; only `created` by default
+        #[serde(default = "default_webhook_events")]
+        events: Vec<AlertEventKind>,
+        /// Who the webhook reaches, e.g. the night nurse's phone, recorded as
+        /// acknowledging alerts by its ack links; the route's name if unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        recipient: Option<String>,
+    },
+    /// Post an alert card to a Slack incoming webhook
+    Slack {
+        url: String,
+        /// Lowest severity posted to this channel
+        #[serde(default)]
+        min_severity: Option<AlertSeverity>,
+    },
+    /// Post an alert card to a Microsoft Teams incoming webhook
+    Teams {
+        url: String,
+        /// Lowest severity posted to this channel
+        #[serde(default)]
+        min_severity: Option<AlertSeverity>,
+    },
+    /// Have the overhead paging system speak the alert: POST the
+    /// announcement to `url`, or publish it on `/ws/announcements` if unset
+    Announce {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        /// Lowest severity announced
+        #[serde(default)]
+        min_severity: Option<AlertSeverity>,
+    },
+}
+
+/// State transition of an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertEventKind {
+    Created,
+    Acked,
+    Resolved,
+    Escalated,
+}
+
+impl AlertEventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlertEventKind::Created => "created",
+            AlertEventKind::Acked => "acked",
+            AlertEventKind::Resolved => "resolved",
+            AlertEventKind::Escalated => "escalated",
+        }
+    }
+}
+
+fn default_webhook_events() -> Vec<AlertEventKind> {
+    vec![AlertEventKind::Created]
+}
+
+impl NotifyTarget {
+    /// Chat channels can be limited to more severe alerts than their route,
+    /// and only get alert cards at onset; announcements are also repeated
+    /// when an alert escalates
+    fn accepts(&self, severity: AlertSeverity, event: AlertEventKind) -> bool {
+        match self {
+            NotifyTarget::Slack { min_severity, .. } | NotifyTarget::Teams { min_severity, .. } => {
+                event == AlertEventKind::Created && min_severity.is_none_or(|min| severity >= min)
+            }
+            NotifyTarget::Announce { min_severity, .. } => {
+                matches!(event, AlertEventKind::Created | AlertEventKind::Escalated)
+                    && min_severity.is_none_or(|min| severity >= min)
+            }
+            NotifyTarget::Webhook { events, .. } => events.contains(&event),
+            NotifyTarget::Log => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRoute {
+    pub name: String,
+    /// Alert types this route applies to (empty = all)
+    #[serde(default)]
+    pub alert_types: Vec<AlertType>,
+    /// Lowest severity this route applies to
+    #[serde(default)]
+    pub min_severity: Option<AlertSeverity>,
+    /// Rooms this route applies to (empty = all)
+    #[serde(default)]
+    pub rooms: Vec<String>,
+    /// Wards this route applies to (empty = all)
+    #[serde(default)]
+    pub wards: Vec<String>,
+    /// Start hour (0-23, facility-local) of the active window
+    pub start_hour: Option<u32>,
+    /// End hour (0-23, facility-local) of the active window, wraps past midnight if < start_hour
+    pub end_hour: Option<u32>,
+    pub targets: Vec<NotifyTarget>,
+}
+
+/// Everything a route can match on
+#[derive(Debug, Clone)]
+pub struct AlertContext {
+    pub alert: AlertType,
+    pub severity: AlertSeverity,
+    pub room: String,
+    pub ward: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl AlertRoute {
+    pub fn matches(&self, ctx: &AlertContext, days: &FacilityDays) -> bool {
+        if !self.alert_types.is_empty() && !self.alert_types.contains(&ctx.alert) {
+            return false;
+        }
+        if let Some(min) = self.min_severity {
+            if ctx.severity < min {
+                return false;
+            }
+        }
+        if !self.rooms.is_empty() && !self.rooms.contains(&ctx.room) {
+            return false;
+        }
+        if !self.wards.is_empty() {
+            match &ctx.ward {
+                Some(ward) if self.wards.contains(ward) => {}
+                _ => return false,
+            }
+        }
+        self.in_window(days.local(ctx.timestamp).hour())
+    }
+
+    fn in_window(&self, hour: u32) -> bool {
+        match (self.start_hour, self.end_hour) {
+            (Some(start), Some(end)) if start <= end => hour >= start && hour < end,
+            // Window wraps past midnight, e.g. 22 -> 6
+            (Some(start), Some(end)) => hour >= start || hour < end,
+            (Some(start), None) => hour >= start,
+            (None, Some(end)) => hour < end,
+            (None, None) => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoutingTable {
+    routes: Vec<AlertRoute>,
+    /// Where route hours are local to
+    days: FacilityDays,
+}
+
+impl Default for RoutingTable {
+    /// Without configuration every alert is only logged
+    fn default() -> Self {
+        Self {
+            routes: vec![AlertRoute {
+                name: "default".to_string(),
+                alert_types: Vec::new(),
+                min_severity: None,
+                rooms: Vec::new(),
+                wards: Vec::new(),
+                start_hour: None,
+                end_hour: None,
+                targets: vec![NotifyTarget::Log],
+            }],
+            days: FacilityDays::default(),
+        }
+    }
+}
+
+impl RoutingTable {
+    /// Load routes from a JSON file containing an array of routes
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let routes: Vec<AlertRoute> = serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid alert routes in {}: {}", path, e))?;
+
+        for route in &routes {
+            for hour in [route.start_hour, route.end_hour].into_iter().flatten() {
+                if hour > 23 {
+                    return Err(format!("Route '{}' has invalid hour {}", route.name, hour));
+                }
+            }
+        }
+
+        info!("Loaded {} alert routes from {}", routes.len(), path);
+        Ok(Self { routes, days: FacilityDays::default() })
+    }
+
+    /// Match route hours in the local time of `days`
+    pub fn local_to(mut self, days: FacilityDays) -> Self {
+        self.days = days;
+        self
+    }
+
+    /// Targets of all matching routes, without duplicates
+    pub fn targets_for(&self, ctx: &AlertContext) -> Vec<(&str, NotifyTarget)> {
+        let mut targets: Vec<(&str, NotifyTarget)> = Vec::new();
+        for route in self.routes.iter().filter(|r| r.matches(ctx, &self.days)) {
+            for target in &route.targets {
+                if !targets.iter().any(|(_, t)| t == target) {
+                    targets.push((route.name.as_str(), target.clone()));
+                }
+            }
+        }
+        targets
+    }
+}
+
+/// Room and alert type of a snooze
+type SnoozeKey = (String, AlertType);
+
+/// Alert types staff have snoozed per room, with the time re-notification
+/// resumes. Shared between the notifier and the API.
+#[derive(Debug, Clone, Default)]
+pub struct Snoozes {
+    until: Arc<RwLock<HashMap<SnoozeKey, DateTime<Utc>>>>,
+}
+
+impl Snoozes {
+    pub fn snooze(&self, room: &str, alert: AlertType, until: DateTime<Utc>) {
+        self.until.write().unwrap().insert((room.to_string(), alert), until);
+    }
+
+    /// Returns false if `alert` wasn't snoozed in `room`
+    pub fn unsnooze(&self, room: &str, alert: AlertType) -> bool {
+        self.until.write().unwrap().remove(&(room.to_string(), alert)).is_some()
+    }
+
+    /// Snoozes expire on their own once `until` has passed
+    pub fn is_snoozed(&self, room: &str, alert: AlertType, now: DateTime<Utc>) -> bool {
+        self.until
+            .read()
+            .unwrap()
+            .get(&(room.to_string(), alert))
+            .is_some_and(|until| *until > now)
+    }
+}
+
+/// Acknowledgement of an alert, passed from the API to the notifier
+#[derive(Debug, Clone)]
+pub struct AckNotice {
+    /// Reading that was acknowledged
+    pub reading_id: i64,
+    pub room: String,
+    /// Alert types the reading raised
+    pub alerts: Vec<AlertType>,
+    pub by: Option<String>,
+    pub via: String,
+    pub at: DateTime<Utc>,
+}
+
+pub type AckSender = mpsc::UnboundedSender<AckNotice>;
+
+/// Alert the notifier has seen raised and not yet resolved
+struct OpenAlert {
+    /// Context at onset, which later transitions are routed by
+    ctx: AlertContext,
+    /// Reading that raised the alert
+    alert_id: Option<i64>,
+    /// Whether the onset was notified; if not, neither are later transitions
+    notified: bool,
+    acked: bool,
+    escalated: bool,
+}
+
+/// JSON body sent to webhook targets
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AlertNotification<'a> {
+    event: AlertEventKind,
+    /// Increases with every transition sent; `None` if it couldn't be recorded
+    sequence: Option<i64>,
+    route: &'a str,
+    alert: &'a str,
+    severity: AlertSeverity,
+    room: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ward: Option<&'a str>,
+    /// Time of the transition
+    timestamp: String,
+    triggered_at: String,
+    /// Reading that raised the alert, by which it is acknowledged
+    alert_id: Option<i64>,
+    /// Reading of the transition: the one raising or resolving the alert
+    observation_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    motion: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sound_level: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    acknowledged_by: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    acknowledged_via: Option<&'a str>,
+    /// Why a `resolved` alert resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<ResolutionReason>,
+    /// One-click acknowledgement link for email and SMS, with `created` and
+    /// `escalated` events (see `chatops`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ack_url: Option<String>,
+}
+
+/// What happened to an alert, as notified
+struct Transition<'a> {
+    kind: AlertEventKind,
+    at: DateTime<Utc>,
+    /// Reading that raised or resolved the alert
+    reading: Option<&'a SensorEvent>,
+    ack: Option<&'a AckNotice>,
+    reason: Option<ResolutionReason>,
+}
+
+/// How often open alerts are checked for escalation
+const ESCALATION_CHECK: Duration = Duration::from_secs(30);
+
+pub struct Notifier {
+    routes: RoutingTable,
+    /// Ward of rooms that aren't assigned to one
+    ward: Option<String>,
+    wards: WardMap,
+    snoozes: Snoozes,
+    procedures: Procedures,
+    chatops: ChatOpsConfig,
+    announcer: Announcer,
+    /// Numbers the transitions sent to webhooks
+    db: Database,
+    /// Unacknowledged alerts are escalated this long after onset
+    escalate_after: Option<chrono::Duration>,
+    open: HashMap<(String, AlertType), OpenAlert>,
+    http: reqwest::Client,
+}
+
+impl Notifier {
+    pub fn new(
+        routes: RoutingTable,
+        ward: Option<String>,
+        wards: WardMap,
+        snoozes: Snoozes,
+        procedures: Procedures,
+        chatops: ChatOpsConfig,
+        db: Database,
+    ) -> Self {
+        Self {
+            routes,
+            ward,
+            wards,
+            snoozes,
+            procedures,
+            chatops,
+            announcer: Announcer::default(),
+            db,
+            escalate_after: None,
+            open: HashMap::new(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Escalate alerts still unacknowledged `after` their onset
+    pub fn escalate_after(mut self, after: Option<Duration>) -> Self {
+        self.escalate_after = after.and_then(|after| chrono::Duration::from_std(after).ok());
+        self
+    }
+
+    /// Word announcements with, and publish them to the listeners of, `announcer`
+    pub fn announce_with(mut self, announcer: Announcer) -> Self {
+        self.announcer = announcer;
+        self
+    }
+
+    /// Dispatch alert transitions from the broadcast stream and from
+    /// acknowledgements until the stream closes.
+    ///
+    /// An alert is created by the first of consecutive readings from a room
+    /// that carry its type, unless it is still open, and resolved when
+    /// storage resolves it.
+    pub fn spawn(mut self, mut rx: broadcast::Receiver<LiveEvent>, mut acks: mpsc::UnboundedReceiver<AckNotice>) {
+        tokio::spawn(async move {
+            let mut last_alerts: HashMap<String, AlertSet> = HashMap::new();
+            let mut escalation = tokio::time::interval(ESCALATION_CHECK);
+
+            loop {
+                tokio::select! {
+                    // An acknowledgement is sent before the resolution it causes
+                    biased;
+                    Some(ack) = acks.recv() => self.acknowledge(&ack).await,
+                    received = rx.recv() => {
+                        let event = match received {
+                            Ok(LiveEvent::Reading(event)) => event,
+                            Ok(LiveEvent::AlertResolved { alert, reading }) => {
+                                self.resolve(&alert, reading.as_ref()).await;
+                                continue;
+                            }
+                            Ok(LiveEvent::DeviceStatus { .. } | LiveEvent::DoorPassage { .. }) => continue,
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                warn!("Notifier lagged, skipped {} events", skipped);
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+
+                        let last = last_alerts.entry(event.room.clone()).or_default();
+                        let new: Vec<AlertType> = event.alerts
+                            .iter()
+                            .filter(|a| !last.contains(*a) && !self.open.contains_key(&(event.room.clone(), *a)))
+                            .collect();
+                        *last = event.alerts.clone();
+                        for alert in new {
+                            self.dispatch(&event, alert).await;
+                        }
+                    }
+                    _ = escalation.tick(), if self.escalate_after.is_some() => self.escalate(Utc::now()).await,
+                }
+            }
+        });
+    }
+
+    pub async fn dispatch(&mut self, event: &SensorEvent, alert: AlertType) {
+        let ctx = AlertContext {
+            alert,
+            severity: alert.severity(),
+            room: event.room.clone(),
+            ward: self.wards.ward_of(&event.room).or_else(|| self.ward.clone()),
+            timestamp: event.reading.timestamp,
+        };
+        let notified = self.should_notify(&ctx, ctx.timestamp);
+
+        let open = OpenAlert { ctx, alert_id: event.id, notified, acked: false, escalated: false };
+        if notified {
+            let transition = Transition { kind: AlertEventKind::Created, at: open.ctx.timestamp, reading: Some(event), ack: None, reason: None };
+            self.notify(&open, &transition).await;
+        }
+        self.open.insert((event.room.clone(), alert), open);
+    }
+
+    /// Whether an alert of `ctx` is notified at `at`, rather than snoozed or
+    /// during a procedure
+    fn should_notify(&self, ctx: &AlertContext, at: DateTime<Utc>) -> bool {
+        if self.snoozes.is_snoozed(&ctx.room, ctx.alert, at) {
+            info!("{:?} alert in {} is snoozed, not notifying", ctx.alert, ctx.room);
+            return false;
+        }
+        if let Some(procedure) = self.procedures.active(&ctx.room, at) {
+            info!("{:?} alert in {} during scheduled procedure '{}', not notifying",
+                ctx.alert, ctx.room, procedure.summary);
+            return false;
+        }
+        true
+    }
+
+    /// `reading` resolved `alert`, or staff did if unset
+    async fn resolve(&mut self, alert: &Alert, reading: Option<&SensorEvent>) {
+        let Some(open) = self.open.remove(&(alert.room.clone(), alert.alert)) else { return };
+        if open.notified {
+            let transition = Transition {
+                kind: AlertEventKind::Resolved,
+                at: alert.resolved_at.unwrap_or_else(Utc::now),
+                reading,
+                ack: None,
+                reason: alert.resolution_reason,
+            };
+            self.notify(&open, &transition).await;
+        }
+    }
+
+    /// Acknowledging any reading of an open alert acknowledges it
+    async fn acknowledge(&mut self, ack: &AckNotice) {
+        for alert in &ack.alerts {
+            let Some(open) = self.open.get_mut(&(ack.room.clone(), *alert)) else { continue };
+            if open.acked || open.alert_id.is_some_and(|id| ack.reading_id < id) {
+                continue;
+            }
+            open.acked = true;
+            if open.notified {
+                let open = &self.open[&(ack.room.clone(), *alert)];
+                let transition = Transition { kind: AlertEventKind::Acked, at: ack.at, reading: None, ack: Some(ack), reason: None };
+                self.notify(open, &transition).await;
+            }
+        }
+    }
+
+    async fn escalate(&mut self, now: DateTime<Utc>) {
+        let Some(after) = self.escalate_after else { return };
+        let due: Vec<(String, AlertType)> = self.open
+            .iter()
+            .filter(|(_, open)| open.notified && !open.acked && !open.escalated && now - open.ctx.timestamp >= after)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in due {
+            // A snoozed alert escalates once the snooze ends, if still unacknowledged
+            if !self.should_notify(&self.open[&key].ctx, now) {
+                continue;
+            }
+            if let Some(open) = self.open.get_mut(&key) {
+                open.escalated = true;
+            }
+            warn!("{:?} alert in {} unacknowledged for {} minutes, escalating", key.1, key.0, after.num_minutes());
+            let transition = Transition { kind: AlertEventKind::Escalated, at: now, reading: None, ack: None, reason: None };
+            self.notify(&self.open[&key], &transition).await;
+        }
+    }
+
+    /// Send `transition` of `open` to the targets of the routes its onset matched
+    async fn notify(&self, open: &OpenAlert, transition: &Transition<'_>) {
+        let ctx = &open.ctx;
+        let targets = self.routes.targets_for(ctx);
+        if targets.is_empty() {
+            warn!("No notification route matched {:?} alert in {}", ctx.alert, ctx.room);
+            return;
+        }
+        let targets: Vec<_> = targets
+            .into_iter()
+            .filter(|(_, target)| target.accepts(ctx.severity, transition.kind))
+            .collect();
+
+        // Numbered only if a webhook receives it
+        let mut sequence = None;
+        if targets.iter().any(|(_, t)| matches!(t, NotifyTarget::Webhook { .. })) {
+            let event = AlertEvent {
+                event: transition.kind.as_str(),
+                room: ctx.room.clone(),
+                alert: ctx.alert,
+                alert_id: open.alert_id,
+                at: transition.at,
+            };
+            match self.db.insert_alert_event(&event).await {
+                Ok(seq) => sequence = Some(seq),
+                Err(e) => error!("Failed to record {} event of {:?} alert in {}: {}",
+                    event.event, ctx.alert, ctx.room, e),
+            }
+        }
+
+        let card = AlertCard {
+            alert: ctx.alert,
+            room: &ctx.room,
+            ward: ctx.ward.as_deref(),
+            timestamp: ctx.timestamp,
+            observation_id: open.alert_id,
+        };
+
+        for (route, target) in targets {
+            let reading = transition.reading;
+            let ack_url = match (&target, open.alert_id, transition.kind) {
+                (
+                    NotifyTarget::Webhook { recipient, .. },
+                    Some(id),
+                    AlertEventKind::Created | AlertEventKind::Escalated,
+                ) => chatops::ack_link(&self.chatops, id, recipient.as_deref().unwrap_or(route), transition.at),
+                _ => None,
+            };
+            let notification = AlertNotification {
+                event: transition.kind,
+                sequence,
+                route,
+                alert: ctx.alert.code(),
+                severity: ctx.severity,
+                room: &ctx.room,
+                ward: ctx.ward.as_deref(),
+                timestamp: transition.at.to_rfc3339(),
+                triggered_at: ctx.timestamp.to_rfc3339(),
+                alert_id: open.alert_id,
+                observation_id: reading.and_then(|e| e.id),
+                temperature: reading.map(|e| e.reading.temperature),
+                motion: reading.map(|e| e.reading.motion),
+                sound_level: reading.map(|e| e.reading.sound_level),
+                acknowledged_by: transition.ack.and_then(|a| a.by.as_deref()),
+                acknowledged_via: transition.ack.map(|a| a.via.as_str()),
+                reason: transition.reason,
+                ack_url,
+            };
+
+            match target {
+                NotifyTarget::Log => {
+                    info!("NOTIFY [{}] {} {} ({:?}) in {}",
+                        route, notification.alert, transition.kind.as_str(), ctx.severity, ctx.room);
+                }
+                NotifyTarget::Webhook { url, .. } => {
+                    let result = self.http.post(&url).json(&notification).send().await;
+                    match result.and_then(|r| r.error_for_status()) {
+                        Ok(_) => info!("Alert {} sent to webhook {} via route {}", transition.kind.as_str(), url, route),
+                        Err(e) => error!("Failed to notify webhook {}: {}", url, e),
+                    }
+                }
+                NotifyTarget::Slack { url, .. } => {
+                    let message = chatops::slack_message(&card, &self.chatops);
+                    self.post_card("Slack", &url, route, &message).await;
+                }
+                NotifyTarget::Teams { url, .. } => {
+                    let message = chatops::teams_message(&card, &self.chatops);
+                    self.post_card("Teams", &url, route, &message).await;
+                }
+                NotifyTarget::Announce { url: Some(url), .. } => {
+                    let announcement = self.announcer.announcement(ctx, transition.kind, transition.at);
+                    let result = self.http.post(&url).json(&announcement).send().await;
+                    match result.and_then(|r| r.error_for_status()) {
+                        Ok(_) => info!("Alert announcement sent to {} via route {}", url, route),
+                        Err(e) => error!("Failed to send alert announcement to {}: {}", url, e),
+                    }
+                }
+                NotifyTarget::Announce { url: None, .. } => {
+                    let announcement = self.announcer.announcement(ctx, transition.kind, transition.at);
+                    if !self.announcer.publish(announcement) {
+                        warn!("No paging gateway connected to announce {:?} alert in {}", ctx.alert, ctx.room);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn post_card(&self, channel: &str, url: &str, route: &str, message: &serde_json::Value) {
+        let result = self.http.post(url).json(message).send().await;
+        match result.and_then(|r| r.error_for_status()) {
+            // Webhook URLs embed their secret, so they're left out of the log
+            Ok(_) => info!("Alert card posted to {} via route {}", channel, route),
+            Err(e) => error!("Failed to post alert card to {}: {}", channel, e.without_url()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn route(name: &str, targets: Vec<NotifyTarget>) -> AlertRoute {
+        AlertRoute {
+            name: name.to_string(),
+            alert_types: Vec::new(),
+            min_severity: None,
+            rooms: Vec::new(),
+            wards: Vec::new(),
+            start_hour: None,
+            end_hour: None,
+            targets,
+        }
+    }
+
+    fn webhook(url: &str) -> NotifyTarget {
+        NotifyTarget::Webhook { url: url.to_string(), events: default_webhook_events(), recipient: None }
+    }
+
+    /// A warning in room-101 of the north ward at `hour`:00 UTC
+    fn alert(hour: u32) -> AlertContext {
+        AlertContext {
+            alert: AlertType::Fall,
+            severity: AlertSeverity::Warning,
+            room: "room-101".to_string(),
+            ward: Some("north".to_string()),
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 15, hour, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_window_wraps_past_midnight() {
+        let night = AlertRoute { start_hour: Some(22), end_hour: Some(6), ..route("night", Vec::new()) };
+        let hours: Vec<u32> = (0..24).filter(|&hour| night.in_window(hour)).collect();
+        assert_eq!(hours, [0, 1, 2, 3, 4, 5, 22, 23]);
+
+        let day = AlertRoute { start_hour: Some(6), end_hour: Some(22), ..route("day", Vec::new()) };
+        assert!(day.in_window(6) && day.in_window(21));
+        assert!(!day.in_window(22) && !day.in_window(5));
+
+        let from = AlertRoute { start_hour: Some(18), ..route("evening", Vec::new()) };
+        assert!(from.in_window(23) && !from.in_window(17));
+        let until = AlertRoute { end_hour: Some(8), ..route("morning", Vec::new()) };
+        assert!(until.in_window(0) && !until.in_window(8));
+        assert!((0..24).all(|hour| route("always", Vec::new()).in_window(hour)));
+    }
+
+    #[test]
+    fn test_routes_filter_by_severity_type_room_and_ward() {
+        let days = FacilityDays::default();
+        let ctx = alert(12);
+        assert!(route("all", Vec::new()).matches(&ctx, &days));
+
+        let warnings = AlertRoute { min_severity: Some(AlertSeverity::Warning), ..route("warnings", Vec::new()) };
+        let critical = AlertRoute { min_severity: Some(AlertSeverity::Critical), ..route("critical", Vec::new()) };
+        assert!(warnings.matches(&ctx, &days));
+        assert!(!critical.matches(&ctx, &days));
+        assert!(critical.matches(&AlertContext { severity: AlertSeverity::Critical, ..alert(12) }, &days));
+
+        let falls = AlertRoute { alert_types: vec![AlertType::Fall], ..route("falls", Vec::new()) };
+        assert!(falls.matches(&ctx, &days));
+        assert!(!falls.matches(&AlertContext { alert: AlertType::Inactivity, ..alert(12) }, &days));
+
+        let room = AlertRoute { rooms: vec!["room-102".to_string()], ..route("room", Vec::new()) };
+        assert!(!room.matches(&ctx, &days));
+
+        let north = AlertRoute { wards: vec!["north".to_string()], ..route("north", Vec::new()) };
+        assert!(north.matches(&ctx, &days));
+        assert!(!north.matches(&AlertContext { ward: Some("south".to_string()), ..alert(12) }, &days));
+        // A room outside any ward only matches routes for all wards
+        assert!(!north.matches(&AlertContext { ward: None, ..alert(12) }, &days));
+    }
+
+    #[test]
+    fn test_window_hours_are_facility_local() {
+        let night = AlertRoute { start_hour: Some(22), end_hour: Some(6), ..route("night", Vec::new()) };
+        let utc = FacilityDays::default();
+        let berlin = FacilityDays { timezone: chrono_tz::Europe::Berlin, start_hour: 0 };
+        // 21:00 UTC is 22:00 in Berlin in winter, 05:00 UTC is 06:00
+        assert!(!night.matches(&alert(21), &utc));
+        assert!(night.matches(&alert(21), &berlin));
+        assert!(night.matches(&alert(5), &utc));
+        assert!(!night.matches(&alert(5), &berlin));
+
+        // Windows are clock hours, not shifted by the hour facility days start at
+        let late_start = FacilityDays { start_hour: 7, ..berlin };
+        assert!(night.matches(&alert(21), &late_start));
+        let table = RoutingTable { routes: vec![AlertRoute { targets: vec![NotifyTarget::Log], ..night }], days: utc };
+        assert!(table.targets_for(&alert(21)).is_empty());
+        assert_eq!(table.local_to(late_start).targets_for(&alert(21)).len(), 1);
+    }
+
+    #[test]
+    fn test_targets_of_matching_routes_without_duplicates() {
+        let table = RoutingTable {
+            routes: vec![
+                route("station", vec![webhook("http://station"), NotifyTarget::Log]),
+                AlertRoute {
+                    min_severity: Some(AlertSeverity::Warning),
+                    ..route("on-call", vec![webhook("http://pager"), webhook("http://station")])
+                },
+                AlertRoute { wards: vec!["south".to_string()], ..route("south", vec![webhook("http://south")]) },
+                route("log", vec![NotifyTarget::Log]),
+            ],
+            days: FacilityDays::default(),
+        };
+
+        // A target already reached through an earlier route keeps that route's name
+        let targets = table.targets_for(&alert(12));
+        assert_eq!(targets, [
+            ("station", webhook("http://station")),
+            ("station", NotifyTarget::Log),
+            ("on-call", webhook("http://pager")),
+        ]);
+
+        let info = AlertContext { severity: AlertSeverity::Info, ..alert(12) };
+        assert_eq!(table.targets_for(&info), [("station", webhook("http://station")), ("station", NotifyTarget::Log)]);
+
+        // Only the default route's log without configuration
+        assert_eq!(RoutingTable::default().targets_for(&alert(12)), [("default", NotifyTarget::Log)]);
+    }
+}
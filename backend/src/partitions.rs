@@ -0,0 +1,39 @@
+//! Readings partitions
+//!
+//! With `DB_PARTITIONED=true` (PostgreSQL only) readings are stored in a
+//! table partitioned by month, so no index grows with the age of the
+//! deployment. Twice a day the partitions of the coming months are created
+//! ahead of the readings that go into them, and with `DB_PARTITION_KEEP_MONTHS`
+//! the partitions of months older than that are detached and dropped whole.
+
+use chrono::Utc;
+use tracing::{error, info};
+
+use crate::db::Database;
+
+const MAINTENANCE_INTERVAL_SECS: u64 = 12 * 3600;
+
+/// Maintain the partitions on startup and twice a day, if readings are partitioned
+pub fn spawn(db: Database, partitioned: bool) {
+    if !partitioned {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(MAINTENANCE_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            match db.maintain_partitions(Utc::now()).await {
+                Ok(changes) => {
+                    if !changes.created.is_empty() {
+                        info!("Created readings partitions {}", changes.created.join(", "));
+                    }
+                    if !changes.dropped.is_empty() {
+                        info!("Dropped expired readings partitions {}", changes.dropped.join(", "));
+                    }
+                }
+                Err(e) => error!("Failed to maintain readings partitions: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,304 @@
+//! Scheduled compliance reports
+//!
+//! The quiet-hours report tracks night-time noise: the share of observed
+//! quiet-hours minutes whose loudest reading exceeded the noise limit, per
+//! room and for the ward, week by week. It is served by
+//! `GET /api/reports/quiet-hours` and sent out in the weekly digest.
+//!
+//! The morning report lists each room's fall-risk score, highest first, so
+//! staff know whom to check on first. It goes out daily when quiet hours end,
+//! which is also when the scores are stored, and is served by
+//! `GET /api/reports/morning`. It also lists the observations staff recorded
+//! over the last day, such as spot-check temperatures and pain scores, and
+//! the procedures scheduled in the rooms' calendars for the coming day.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::calendar::Procedures;
+use crate::days::FacilityDays;
+use crate::db::{Database, DbError, NightNoiseWeek};
+use crate::risk;
+use patient_monitor_types::api::{MorningReport, QuietHoursReport, QuietHoursWeek, RoomQuietHours, Shifts};
+
+/// Weeks covered by the weekly digest
+const DIGEST_WEEKS: u32 = 4;
+/// The digest goes out Mondays at this facility-local time, after the
+/// weekend's nights
+const DIGEST_TIME: (u32, u32) = (7, 0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuietHours {
+    /// Start hour (0-23, facility-local)
+    pub start_hour: u32,
+    /// End hour (0-23, facility-local), wraps past midnight if < start_hour
+    pub end_hour: u32,
+    /// Sound level a minute's loudest reading must not exceed
+    pub noise_limit: i32,
+}
+
+impl QuietHours {
+    /// Whether local hour `hour` is in quiet hours
+    pub fn contains(&self, hour: u32) -> bool {
+        if self.start_hour <= self.end_hour {
+            hour >= self.start_hour && hour < self.end_hour
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+}
+
+/// Where reports are generated for
+#[derive(Debug, Clone)]
+pub struct ReportConfig {
+    pub rooms: Vec<String>,
+    pub ward: Option<String>,
+    pub quiet_hours: QuietHours,
+    pub procedures: Procedures,
+    /// Shifts handovers are given for, see `handover`
+    pub shifts: Shifts,
+    /// Weeks and report dates follow the facility's days
+    pub days: FacilityDays,
+}
+
+/// Start of the `i`th week from the one starting at `first`; weeks with a
+/// daylight saving time change aren't 7 × 24 hours long
+fn nth_week(days: &FacilityDays, first: DateTime<Utc>, i: usize) -> DateTime<Utc> {
+    days.start_of(days.day_of(first) + Duration::weeks(i as i64))
+}
+
+/// One entry per week from `first` on, including weeks without readings
+fn to_weeks(rows: &[NightNoiseWeek], days: &FacilityDays, first: DateTime<Utc>, weeks: u32) -> Vec<QuietHoursWeek> {
+    let mut out: Vec<QuietHoursWeek> = Vec::with_capacity(weeks as usize);
+    for i in 0..weeks as usize {
+        let start = nth_week(days, first, i);
+        let (observed, noisy) = rows
+            .iter()
+            .find(|r| r.week_start == start)
+            .map(|r| (r.observed_minutes, r.noisy_minutes))
+            .unwrap_or((0, 0));
+        out.push(week_summary(days.day_of(start), observed, noisy, out.last()));
+    }
+    out
+}
+
+fn week_summary(
+    start: NaiveDate,
+    observed: u64,
+    noisy: u64,
+    previous: Option<&QuietHoursWeek>,
+) -> QuietHoursWeek {
+    let percent = if observed > 0 {
+        noisy as f64 / observed as f64 * 100.0
+    } else {
+        0.0
+    };
+    let percent = (percent * 100.0).round() / 100.0;
+    // Weeks without readings have no meaningful trend
+    let change = previous
+        .filter(|p| p.observed_minutes > 0 && observed > 0)
+        .map(|p| ((percent - p.percent_noisy) * 100.0).round() / 100.0);
+
+    QuietHoursWeek {
+        week_start: start.to_string(),
+        observed_minutes: observed,
+        noisy_minutes: noisy,
+        percent_noisy: percent,
+        change,
+    }
+}
+
+/// Combine the rooms' weeks into ward totals
+fn ward_weeks(rooms: &[RoomQuietHours], days: &FacilityDays, first: DateTime<Utc>, weeks: u32) -> Vec<QuietHoursWeek> {
+    let mut out: Vec<QuietHoursWeek> = Vec::with_capacity(weeks as usize);
+    for i in 0..weeks as usize {
+        let (observed, noisy) = rooms
+            .iter()
+            .filter_map(|r| r.weeks.get(i))
+            .fold((0, 0), |(o, n), w| (o + w.observed_minutes, n + w.noisy_minutes));
+        let start = nth_week(days, first, i);
+        out.push(week_summary(days.day_of(start), observed, noisy, out.last()));
+    }
+    out
+}
+
+/// Quiet-hours report for the `weeks` weeks up to `end`; the last week is
+/// partial unless `end` falls on a week boundary
+pub async fn quiet_hours_report(
+    db: &Database,
+    config: &ReportConfig,
+    weeks: u32,
+    end: DateTime<Utc>,
+) -> Result<QuietHoursReport, DbError> {
+    let days = &config.days;
+    let last = days.week_start(end - Duration::seconds(1));
+    let first = days.start_of(days.day_of(last) - Duration::weeks(weeks as i64 - 1));
+    let quiet = config.quiet_hours;
+
+    let mut rooms = Vec::with_capacity(config.rooms.len());
+    for room in &config.rooms {
+        let rows = db
+            .get_night_noise_minutes(first, end, days, &quiet, room)
+            .await?;
+        rooms.push(RoomQuietHours {
+            room: room.clone(),
+            weeks: to_weeks(&rows, days, first, weeks),
+        });
+    }
+    let ward_weeks = ward_weeks(&rooms, days, first, weeks);
+
+    Ok(QuietHoursReport {
+        ward: config.ward.clone(),
+        start_hour: quiet.start_hour,
+        end_hour: quiet.end_hour,
+        noise_limit: quiet.noise_limit,
+        rooms,
+        ward_weeks,
+    })
+}
+
+/// JSON body sent to the digest webhook
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WeeklyDigest<'a> {
+    rooms: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ward: Option<&'a str>,
+    generated_at: String,
+    quiet_hours: QuietHoursReport,
+}
+
+/// First Monday digest time after `now`
+fn next_digest(now: DateTime<Utc>, days: &FacilityDays) -> DateTime<Utc> {
+    let (hour, minute) = DIGEST_TIME;
+    let time = NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+    let local = days.local(now);
+    let monday = local.date() + Duration::days(((7 - local.weekday().num_days_from_monday()) % 7) as i64);
+    let candidate = days.instant(monday.and_time(time));
+    if candidate > now {
+        candidate
+    } else {
+        days.instant((monday + Duration::weeks(1)).and_time(time))
+    }
+}
+
+/// Send the weekly digest every Monday morning. Without a webhook the digest
+/// is only logged.
+pub fn spawn_weekly_digest(db: Database, config: ReportConfig, webhook: Option<String>) {
+    tokio::spawn(async move {
+        let http = reqwest::Client::new();
+
+        loop {
+            let next = next_digest(Utc::now(), &config.days);
+            info!("Next weekly digest at {}", next);
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+
+            // Only complete weeks, ending with the one that just finished
+            let end = config.days.week_start(Utc::now());
+            let report = match quiet_hours_report(&db, &config, DIGEST_WEEKS, end).await {
+                Ok(report) => report,
+                Err(e) => {
+                    error!("Failed to build weekly digest: {}", e);
+                    continue;
+                }
+            };
+
+            if let Some(week) = report.ward_weeks.last() {
+                info!("Weekly digest: {}% of quiet-hours minutes above noise limit {} ({} of {})",
+                    week.percent_noisy, report.noise_limit, week.noisy_minutes, week.observed_minutes);
+            }
+
+            let Some(url) = &webhook else { continue };
+            let digest = WeeklyDigest {
+                rooms: &config.rooms,
+                ward: config.ward.as_deref(),
+                generated_at: Utc::now().to_rfc3339(),
+                quiet_hours: report,
+            };
+            let result = http.post(url).json(&digest).send().await;
+            match result.and_then(|r| r.error_for_status()) {
+                Ok(_) => info!("Weekly digest sent to {}", url),
+                Err(e) => warn!("Failed to send weekly digest to {}: {}", url, e),
+            }
+        }
+    });
+}
+
+/// Staff observations listed in the morning report
+const REPORT_OBSERVATION_HOURS: i64 = 24;
+
+/// Fall risk of every room over the window ending at `end`, highest first
+pub async fn morning_report(
+    db: &Database,
+    config: &ReportConfig,
+    end: DateTime<Utc>,
+) -> Result<MorningReport, DbError> {
+    let mut rooms = Vec::with_capacity(config.rooms.len());
+    for room in &config.rooms {
+        rooms.push(risk::score(db, room, &config.quiet_hours, &config.days, end).await?);
+    }
+    rooms.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let start = end - Duration::hours(REPORT_OBSERVATION_HOURS);
+    let mut observations = db.get_manual_observations(Some(start), Some(end), None, None).await?;
+    observations.retain(|o| config.rooms.contains(&o.room));
+    let procedures = config.procedures.between(&config.rooms, end, end + Duration::days(1));
+
+    Ok(MorningReport {
+        ward: config.ward.clone(),
+        date: config.days.day_of(end),
+        rooms,
+        observations,
+        procedures,
+    })
+}
+
+/// Next time quiet hours end after `now`
+fn next_morning(now: DateTime<Utc>, quiet: &QuietHours, days: &FacilityDays) -> DateTime<Utc> {
+    let today = days.local(now).date();
+    let candidate = days.at(today, quiet.end_hour);
+    if candidate > now {
+        candidate
+    } else {
+        days.at(today + Duration::days(1), quiet.end_hour)
+    }
+}
+
+/// Store the day's fall-risk scores and send the morning report every day
+/// when quiet hours end. Without a webhook the report is only logged.
+pub fn spawn_morning_report(db: Database, config: ReportConfig, webhook: Option<String>) {
+    tokio::spawn(async move {
+        let http = reqwest::Client::new();
+
+        loop {
+            let next = next_morning(Utc::now(), &config.quiet_hours, &config.days);
+            info!("Next morning report at {}", next);
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+
+            let report = match morning_report(&db, &config, Utc::now()).await {
+                Ok(report) => report,
+                Err(e) => {
+                    error!("Failed to build morning report: {}", e);
+                    continue;
+                }
+            };
+
+            for risk in &report.rooms {
+                if let Err(e) = db.upsert_fall_risk(risk).await {
+                    error!("Failed to store fall risk of {}: {}", risk.patient_id, e);
+                }
+                info!("Morning report: {} fall risk {} ({})", risk.patient_id, risk.score, risk.level);
+            }
+
+            let Some(url) = &webhook else { continue };
+            let result = http.post(url).json(&report).send().await;
+            match result.and_then(|r| r.error_for_status()) {
+                Ok(_) => info!("Morning report sent to {}", url),
+                Err(e) => warn!("Failed to send morning report to {}: {}", url, e),
+            }
+        }
+    });
+}
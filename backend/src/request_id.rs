@@ -0,0 +1,94 @@
+//! Per-request correlation IDs
+//!
+//! Every request gets an ID, taken from an inbound `X-Request-ID` header (as
+//! set by our API gateway) or generated. The ID is attached to all log lines
+//! emitted while handling the request, echoed in the `X-Request-ID` response
+//! header and added as `requestId` to JSON error bodies, so a failed call an
+//! integrator reports can be found in the backend logs.
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use std::future::{ready, Ready};
+use tracing::{info_span, warn, Instrument};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest inbound ID that is accepted as-is
+const MAX_INBOUND_LEN: usize = 128;
+
+/// Correlation ID of the current request, e.g. for audit entries
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl FromRequest for RequestId {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let id = req.extensions().get::<RequestId>().cloned();
+        ready(Ok(id.unwrap_or_else(|| RequestId(uuid::Uuid::new_v4().to_string()))))
+    }
+}
+
+fn inbound_id(req: &ServiceRequest) -> Option<String> {
+    let value = req.headers().get(&REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !value.is_empty()
+        && value.len() <= MAX_INBOUND_LEN
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+    valid.then(|| value.to_string())
+}
+
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let id = inbound_id(&req).unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let span = info_span!("request", request_id = %id);
+    let mut res = next.call(req).instrument(span).await?.map_into_boxed_body();
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    if res.status().is_client_error() || res.status().is_server_error() {
+        return Ok(tag_error_body(res, &id).await);
+    }
+    Ok(res)
+}
+
+/// Add `requestId` to a JSON error body; other bodies are passed through
+async fn tag_error_body(res: ServiceResponse<BoxBody>, id: &str) -> ServiceResponse<BoxBody> {
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("json"));
+    if !is_json {
+        return res;
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = match body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Failed to read error body: {}", e);
+            return ServiceResponse::new(req, res.set_body(BoxBody::new(())));
+        }
+    };
+
+    let bytes = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut error)) => {
+            error.insert("requestId".to_string(), id.into());
+            serde_json::to_vec(&error).map(Into::into).unwrap_or(bytes)
+        }
+        _ => bytes,
+    };
+
+    ServiceResponse::new(req, res.set_body(BoxBody::new(bytes)))
+}
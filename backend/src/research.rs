@@ -0,0 +1,86 @@
+//! Aggregate statistics for research
+//!
+//! `GET /api/research/aggregates` answers research questions that need
+//! statistics across patients rather than anyone's readings: how many
+//! readings, how much motion, the mean temperature and the alerts raised,
+//! per ward and/or day. It is only open to principals with the `research`
+//! role, passed by the gateway in `X-Authenticated-Roles`, and only counts
+//! patients who consent to research.
+//!
+//! No single patient's series can be read off the results: groups are never
+//! smaller than a ward or a day, and groups with fewer patients than
+//! `RESEARCH_MIN_GROUP_SIZE` are left out. Suppression alone doesn't stop a
+//! researcher from subtracting overlapping queries, e.g. all wards minus the
+//! others, to learn about a small group. With `RESEARCH_NOISE_EPSILON` set,
+//! Laplace noise scaled to what one reading or alert changes is added to
+//! every statistic, which blurs such differences.
+
+use rand::Rng;
+
+use crate::db::ResearchGroup;
+
+/// Role a principal needs to query research aggregates
+pub const RESEARCH_ROLE: &str = "research";
+
+/// Range of plausible room temperatures (°C); one reading moves a mean of
+/// `n` readings by at most this much over `n`
+const TEMPERATURE_SPAN: f64 = 50.0;
+
+#[derive(Debug, Clone)]
+pub struct ResearchConfig {
+    /// Groups with fewer distinct patients are left out
+    pub min_group_size: u64,
+    /// Privacy parameter of the Laplace noise added to each statistic;
+    /// `None` publishes exact statistics
+    pub noise_epsilon: Option<f64>,
+}
+
+impl Default for ResearchConfig {
+    fn default() -> Self {
+        Self { min_group_size: 5, noise_epsilon: None }
+    }
+}
+
+/// Sample of the Laplace distribution with mean 0 and scale `scale`
+fn laplace(rng: &mut impl Rng, scale: f64) -> f64 {
+    let u: f64 = rng.gen_range(-0.5..0.5);
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+/// `value` plus noise hiding one reading or alert, as a count
+fn noisy_count(rng: &mut impl Rng, value: u64, epsilon: f64) -> u64 {
+    (value as f64 + laplace(rng, 1.0 / epsilon)).round().max(0.0) as u64
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Groups that may be published, noised if configured, and the number of
+/// groups left out for having too few patients
+pub fn publish(groups: Vec<ResearchGroup>, config: &ResearchConfig) -> (Vec<ResearchGroup>, usize) {
+    let total = groups.len();
+    let mut published: Vec<ResearchGroup> = groups
+        .into_iter()
+        .filter(|g| g.patients >= config.min_group_size)
+        .collect();
+    let suppressed = total - published.len();
+
+    if let Some(epsilon) = config.noise_epsilon {
+        let mut rng = rand::thread_rng();
+        for group in &mut published {
+            let readings = group.readings.max(1) as f64;
+            group.patients = noisy_count(&mut rng, group.patients, epsilon);
+            group.readings = noisy_count(&mut rng, group.readings, epsilon);
+            group.fall_alerts = noisy_count(&mut rng, group.fall_alerts, epsilon);
+            group.inactivity_alerts = noisy_count(&mut rng, group.inactivity_alerts, epsilon);
+            group.motion_percent = (group.motion_percent + laplace(&mut rng, 100.0 / readings / epsilon)).clamp(0.0, 100.0);
+            group.avg_temperature += laplace(&mut rng, TEMPERATURE_SPAN / readings / epsilon);
+        }
+    }
+    for group in &mut published {
+        group.motion_percent = round2(group.motion_percent);
+        group.avg_temperature = round2(group.avg_temperature);
+    }
+    (published, suppressed)
+}
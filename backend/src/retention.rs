@@ -0,0 +1,176 @@
+//! Data retention
+//!
+//! Readings older than `RETENTION_DAYS` are deleted once an hour, together
+//! with their tags and acknowledgements, alert episodes that resolved
+//! before the cutoff and room state changes from before it. Whole facility
+//! days are kept (see `days`): the cutoff is the start of a day, so a day's
+//! readings are deleted together rather than hour by hour. With
+//! `RETENTION_ARCHIVE_DIR` set, the readings are first appended to a JSON
+//! Lines file there, one file per purge. Staff can run a purge at once with
+//! `POST /api/admin/retention/purge`.
+//!
+//! Each batch is checkpointed (see `jobs`), so a purge cut short by a crash
+//! is resumed by the next one, which keeps appending to the same archive and
+//! neither loses nor duplicates the batch that was in flight.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info};
+
+use crate::days::FacilityDays;
+use crate::db::Database;
+use crate::jobs::{self, Run};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Readings are deleted in batches of this size, so a large purge doesn't
+/// hold locks on the table for long
+const BATCH_SIZE: i64 = 5000;
+const PURGE_INTERVAL_SECS: u64 = 3600;
+
+#[derive(Debug, Clone, Default)]
+pub struct RetentionConfig {
+    /// Days readings are kept; 0 keeps them forever
+    pub days: u32,
+    /// Directory purged readings are archived to before they are deleted
+    pub archive_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeSummary {
+    /// Readings taken before this time were purged
+    pub cutoff: DateTime<Utc>,
+    pub readings: u64,
+    pub alerts: u64,
+    pub room_states: u64,
+    pub door_passages: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+}
+
+/// Where an interrupted purge resumes, see `jobs`
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PurgeCheckpoint {
+    /// Archive file the purge appends to, once it has archived readings
+    archive: Option<String>,
+    /// Readings purged so far
+    readings: u64,
+    /// Readings archived but not yet deleted
+    pending: Vec<i64>,
+}
+
+/// Delete (after archiving, if configured) readings from before the facility
+/// day `days` days before today, resuming the last purge if it didn't complete
+pub async fn purge(
+    db: &Database,
+    config: &RetentionConfig,
+    facility: &FacilityDays,
+    days: u32,
+    now: DateTime<Utc>,
+) -> Result<PurgeSummary, BoxError> {
+    let cutoff = facility.start_of(facility.day_of(now) - Duration::days(days as i64));
+    let resumed = jobs::resume_point(db, jobs::RETENTION).await?;
+    let checkpoint = resumed.as_deref().and_then(|c| serde_json::from_str(c).ok()).unwrap_or_default();
+
+    let run = Run::start(db, jobs::RETENTION, resumed.as_deref()).await?;
+    let result = purge_from(db, config, &run, cutoff, now, checkpoint).await;
+    run.finish(&result).await;
+    result
+}
+
+async fn purge_from(
+    db: &Database,
+    config: &RetentionConfig,
+    run: &Run,
+    cutoff: DateTime<Utc>,
+    now: DateTime<Utc>,
+    mut checkpoint: PurgeCheckpoint,
+) -> Result<PurgeSummary, BoxError> {
+    if !checkpoint.pending.is_empty() {
+        checkpoint.readings += db.delete_readings(&checkpoint.pending).await?;
+        checkpoint.pending.clear();
+        run.checkpoint(&serde_json::to_string(&checkpoint)?).await?;
+    }
+    let archive = config.archive_dir.as_ref().map(|dir| match &checkpoint.archive {
+        Some(path) => PathBuf::from(path),
+        None => dir.join(format!("sensor_data-{}.jsonl", now.format("%Y%m%dT%H%M%SZ"))),
+    });
+    let mut file = None;
+
+    loop {
+        let batch = db.readings_older_than(cutoff, BATCH_SIZE).await?;
+        if batch.is_empty() {
+            break;
+        }
+        let ids: Vec<i64> = batch.iter().filter_map(|e| e.id).collect();
+
+        // Readings are archived and synced before they are deleted, and the
+        // checkpoint in between keeps a resumed purge from archiving them twice
+        if let Some(path) = &archive {
+            let file = match &mut file {
+                Some(file) => file,
+                None => file.insert(
+                    tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?,
+                ),
+            };
+            let mut lines = String::new();
+            for event in &batch {
+                lines.push_str(&serde_json::to_string(event)?);
+                lines.push('\n');
+            }
+            file.write_all(lines.as_bytes()).await?;
+            file.sync_data().await?;
+            checkpoint.archive = Some(path.display().to_string());
+            checkpoint.pending = ids.clone();
+            run.checkpoint(&serde_json::to_string(&checkpoint)?).await?;
+        }
+
+        checkpoint.readings += db.delete_readings(&ids).await?;
+        checkpoint.pending.clear();
+        run.checkpoint(&serde_json::to_string(&checkpoint)?).await?;
+        if (batch.len() as i64) < BATCH_SIZE {
+            break;
+        }
+    }
+
+    let alerts = db.purge_alerts_resolved_before(cutoff).await?;
+    let room_states = db.purge_room_states_before(cutoff).await?;
+    let door_passages = db.purge_door_passages_before(cutoff).await?;
+
+    Ok(PurgeSummary {
+        cutoff,
+        readings: checkpoint.readings,
+        alerts,
+        room_states,
+        door_passages,
+        archive: checkpoint.archive,
+    })
+}
+
+/// Purge expired data once an hour, unless retention is disabled
+pub fn spawn(db: Database, config: RetentionConfig, facility: FacilityDays) {
+    if config.days == 0 {
+        info!("Data retention disabled; readings are kept forever");
+        return;
+    }
+    info!("Keeping readings for {} days", config.days);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(PURGE_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            match purge(&db, &config, &facility, config.days, Utc::now()).await {
+                Ok(summary) if summary.readings == 0 && summary.alerts == 0 && summary.room_states == 0
+                    && summary.door_passages == 0 => {}
+                Ok(summary) => info!("Purged {} readings, {} alerts, {} room states and {} door passages from before {}{}",
+                    summary.readings, summary.alerts, summary.room_states, summary.door_passages, summary.cutoff,
+                    summary.archive.map(|a| format!(", archived to {}", a)).unwrap_or_default()),
+                Err(e) => error!("Failed to purge expired readings: {}", e),
+            }
+        }
+    });
+}
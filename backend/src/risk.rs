@@ -0,0 +1,77 @@
+//! Daily fall-risk score
+//!
+//! Combines three signals over a sliding window of the last seven days:
+//! falls, nighttime restlessness (the share of quiet-hours minutes with
+//! motion) and bed exits (getting up at night after resting for a while).
+//! Scores are stored daily with the morning report, and served with their
+//! history by `GET /api/patients/{id}/risk`. Until patients are registered,
+//! a room's id is its patient's id.
+
+use chrono::{DateTime, Duration, NaiveDate, Timelike, Utc};
+use std::collections::HashSet;
+
+use crate::days::FacilityDays;
+use crate::db::{Database, DbError, FallRiskFactors, FallRiskScore};
+use crate::reports::QuietHours;
+use patient_monitor_types::analysis::{activity_score, count_bed_exits, fall_risk_level, fall_risk_score};
+
+/// Days of readings each score covers
+pub const WINDOW_DAYS: u32 = 7;
+/// Minutes without motion after which getting up counts as a bed exit
+const BED_EXIT_REST_MINUTES: u32 = 15;
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Night a minute belongs to, named by the local date quiet hours started on
+fn night_of(minute: DateTime<Utc>, quiet: &QuietHours, days: &FacilityDays) -> NaiveDate {
+    let local = days.local(minute);
+    if quiet.end_hour < quiet.start_hour && local.hour() < quiet.end_hour {
+        local.date() - Duration::days(1)
+    } else {
+        local.date()
+    }
+}
+
+/// Fall-risk score of the patient in `room` over the window ending at `end`,
+/// dated by the facility day `end` falls on
+pub async fn score(
+    db: &Database,
+    room: &str,
+    quiet: &QuietHours,
+    days: &FacilityDays,
+    end: DateTime<Utc>,
+) -> Result<FallRiskScore, DbError> {
+    let start = end - Duration::days(WINDOW_DAYS as i64);
+
+    let falls = db.count_falls(room, start, end).await?;
+    let minutes = db
+        .get_night_motion_minutes(room, start, end, days, quiet)
+        .await?;
+
+    let motion_minutes = minutes.iter().filter(|(_, motion)| *motion).count() as u64;
+    let nights: HashSet<NaiveDate> = minutes.iter().map(|(minute, _)| night_of(*minute, quiet, days)).collect();
+    let bed_exits = count_bed_exits(minutes.iter().copied(), BED_EXIT_REST_MINUTES);
+    let bed_exits_per_night = if nights.is_empty() {
+        0.0
+    } else {
+        bed_exits as f64 / nights.len() as f64
+    };
+
+    let factors = FallRiskFactors {
+        falls,
+        night_restlessness: round2(activity_score(motion_minutes, minutes.len() as u64)),
+        bed_exits_per_night: round2(bed_exits_per_night),
+    };
+    let score = fall_risk_score(&factors);
+
+    Ok(FallRiskScore {
+        patient_id: room.to_string(),
+        date: days.day_of(end),
+        score,
+        level: fall_risk_level(score).to_string(),
+        window_days: WINDOW_DAYS,
+        factors,
+    })
+}
@@ -0,0 +1,114 @@
+//! Daily rollup
+//!
+//! Shortly after each facility day ends (see `days`), each room's readings of
+//! that day are aggregated into the `daily_summary` table: the share of readings with
+//! motion, the temperature range, alerts raised and the longest still period.
+//! `GET /api/summary/daily` reads the summaries, so month-long trends don't
+//! scan the readings, and they are kept when the readings are purged.
+//!
+//! Days missed while the monitor was down, or whose rollup failed, are rolled
+//! up on startup and with the next nightly run, going back at most
+//! `BACKFILL_DAYS`. Each run checkpoints the days it rolled up (see `jobs`),
+//! so days without readings aren't scanned again.
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use tracing::{error, info};
+
+use crate::days::FacilityDays;
+use crate::db::{Database, DbError};
+use crate::jobs::{self, Run};
+
+/// The rollup runs this long after a day ends, once its late readings are stored
+const ROLLUP_DELAY_MINUTES: i64 = 15;
+/// Oldest day rolled up when catching up
+const BACKFILL_DAYS: i64 = 92;
+
+/// Aggregate and store the readings of `day`. Returns the number of rooms
+/// with readings; a day already rolled up is replaced.
+pub async fn rollup_day(db: &Database, days: &FacilityDays, day: NaiveDate) -> Result<usize, DbError> {
+    let (start, end) = days.bounds(day);
+    let summaries = db.compute_daily_summaries(day, start, end).await?;
+    db.upsert_daily_summaries(&summaries).await?;
+    Ok(summaries.len())
+}
+
+/// Roll up the days since the last rolled-up day, up to yesterday
+async fn catch_up(db: &Database, days: &FacilityDays, today: NaiveDate) {
+    let yesterday = today - Duration::days(1);
+    let oldest = today - Duration::days(BACKFILL_DAYS);
+    // Days without readings leave no summary, only the run's checkpoint
+    let checkpoint = match jobs::last_run(db, jobs::ROLLUP).await {
+        Ok(run) => run.and_then(|r| r.checkpoint),
+        Err(e) => {
+            error!("Failed to look up the last rollup run: {}", e);
+            return;
+        }
+    };
+    let first = match db.last_daily_summary_day().await {
+        Ok(Some(last)) => (last + Duration::days(1)).max(oldest),
+        Ok(None) => oldest,
+        Err(e) => {
+            error!("Failed to look up the last daily summary: {}", e);
+            return;
+        }
+    };
+    let first = match checkpoint.as_deref().and_then(|c| c.parse::<NaiveDate>().ok()) {
+        Some(done) => first.max(done + Duration::days(1)),
+        None => first,
+    };
+    if first > yesterday {
+        return;
+    }
+
+    let run = match Run::start(db, jobs::ROLLUP, checkpoint.as_deref()).await {
+        Ok(run) => run,
+        Err(e) => {
+            error!("Failed to record the rollup run: {}", e);
+            return;
+        }
+    };
+    let result = roll_up_days(db, days, &run, first, yesterday).await;
+    if let Err(e) = &result {
+        // Retried with the next run
+        error!("Failed to roll up: {}", e);
+    }
+    run.finish(&result).await;
+}
+
+/// Roll up the days from `first` to `last`, checkpointing after each
+async fn roll_up_days(
+    db: &Database,
+    days: &FacilityDays,
+    run: &Run,
+    first: NaiveDate,
+    last: NaiveDate,
+) -> Result<(), DbError> {
+    for day in first.iter_days().take_while(|d| *d <= last) {
+        match rollup_day(db, days, day).await {
+            Ok(0) => {}
+            Ok(rooms) => info!("Rolled up {} rooms for {}", rooms, day),
+            Err(e) => return Err(DbError::other(format!("{}: {}", day, e))),
+        }
+        run.checkpoint(&day.to_string()).await?;
+    }
+    Ok(())
+}
+
+/// First rollup time after `now`
+fn next_rollup(days: &FacilityDays, now: DateTime<Utc>) -> DateTime<Utc> {
+    let delay = Duration::minutes(ROLLUP_DELAY_MINUTES);
+    days.start_of(days.day_of(now - delay) + Duration::days(1)) + delay
+}
+
+/// Catch up on startup, then roll up each day after it ends
+pub fn spawn(db: Database, days: FacilityDays) {
+    tokio::spawn(async move {
+        loop {
+            catch_up(&db, &days, days.today()).await;
+
+            let next = next_rollup(&days, Utc::now());
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+        }
+    });
+}
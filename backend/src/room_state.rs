@@ -0,0 +1,68 @@
+//! Coarse room state stream
+//!
+//! The ingestion loop of a room derives from its readings and bed sensor
+//! whether the patient is in bed, moving in the room or out of it (see
+//! `PatientStateTracker`), and stores each change in `room_states`. The changes
+//! are served as consecutive periods by `GET /api/rooms/{id}/state-history`,
+//! so a day can be shown as a ribbon of states rather than motion ticks.
+//!
+//! In rooms with a door contact it also tells entries from exits (see
+//! `DoorTracker`); each passage is stored in `door_passages`, served by
+//! `GET /api/rooms/{id}/door-passages`, and sent to live clients.
+//! Like readings, states and passages of a patient who hasn't consented to
+//! monitoring are not stored.
+
+use chrono::{DateTime, Utc};
+use tracing::{error, info};
+
+use crate::db::{Database, DoorPassage};
+use crate::fhir::SensorReading;
+use patient_monitor_types::analysis::{DoorTracker, PatientStateTracker};
+use patient_monitor_types::api::PatientState;
+
+pub struct RoomStates {
+    room: String,
+    tracker: PatientStateTracker,
+    door: DoorTracker,
+}
+
+impl RoomStates {
+    pub fn new(room: String) -> Self {
+        Self {
+            room,
+            tracker: PatientStateTracker::new(),
+            door: DoorTracker::new(),
+        }
+    }
+    
+    /// Add a reading; returns a door passage once one is classified
+    pub async fn reading(&mut self, db: &Database, reading: &SensorReading, consent: bool) -> Option<DoorPassage> {
+        let change = self.tracker.reading(reading.timestamp, reading.motion);
+        self.store(db, change, consent).await;
+        
+        let passage = self.door.reading(reading)?;
+        info!("Door of {} passed: {} (open {} to {})",
+            self.room, passage.kind.as_str(), passage.opened_at, passage.closed_at);
+        if consent {
+            if let Err(e) = db.insert_door_passage(&self.room, &passage).await {
+                error!("Failed to store door passage of {}: {}", self.room, e);
+            }
+        }
+        Some(passage)
+    }
+    
+    pub async fn bed(&mut self, db: &Database, at: DateTime<Utc>, occupied: bool, consent: bool) {
+        let change = self.tracker.bed(at, occupied);
+        self.store(db, change, consent).await;
+    }
+    
+    async fn store(&self, db: &Database, change: Option<(PatientState, DateTime<Utc>)>, consent: bool) {
+        let Some((state, started_at)) = change.filter(|_| consent) else {
+            return;
+        };
+        info!("Room {} is now {} (since {})", self.room, state.as_str(), started_at);
+        if let Err(e) = db.insert_room_state(&self.room, state, started_at).await {
+            error!("Failed to store state of {}: {}", self.room, e);
+        }
+    }
+}
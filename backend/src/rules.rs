@@ -0,0 +1,771 @@
+//! Alert rule expressions
+//!
+//! Rules are small boolean expressions evaluated against every reading, e.g.
+//! `motion && sound > threshold("night") && hour in 22..6`. They are parsed
+//! and type-checked when saved, so a typo is reported to the user instead of
+//! silently never firing. `hour` and `minute` are the facility's local time
+//! (`FACILITY_TZ`) of the reading.
+
+use chrono::{DateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tracing::warn;
+
+use crate::api::MonitorSettings;
+use crate::days::FacilityDays;
+use crate::db::{Database, DbError};
+use crate::fhir::{AlertType, SensorReading};
+
+/// Variables available in expressions
+pub const VARIABLES: &[&str] = &["motion", "sound", "temperature", "hour", "minute", "inactive_seconds"];
+
+/// Built-in thresholds, backed by the monitor settings
+pub const BUILTIN_THRESHOLDS: &[&str] = &["sound", "inactivity"];
+
+// ============================================================================
+// TOKENIZER
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    True,
+    False,
+    In,
+    And,
+    Or,
+    Not,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Minus,
+    DotDot,
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            // A '.' only continues the number if a digit follows, so `22..6` stays a range
+            if i + 1 < chars.len() && chars[i] == '.' && chars[i + 1].is_ascii_digit() {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text.parse().map_err(|_| format!("Invalid number '{}'", text))?;
+            tokens.push(Token::Num(value));
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(match word.as_str() {
+                "true" => Token::True,
+                "false" => Token::False,
+                "in" => Token::In,
+                "and" => Token::And,
+                "or" => Token::Or,
+                "not" => Token::Not,
+                _ => Token::Ident(word),
+            });
+            continue;
+        }
+
+        if c == '"' || c == '\'' {
+            let start = i + 1;
+            let end = chars[start..]
+                .iter()
+                .position(|&ch| ch == c)
+                .map(|p| start + p)
+                .ok_or_else(|| format!("Unterminated string starting at position {}", i))?;
+            tokens.push(Token::Str(chars[start..end].iter().collect()));
+            i = end + 1;
+            continue;
+        }
+
+        let (token, len) = match (c, next) {
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Eq, 2),
+            ('!', Some('=')) => (Token::Ne, 2),
+            ('<', Some('=')) => (Token::Le, 2),
+            ('>', Some('=')) => (Token::Ge, 2),
+            ('.', Some('.')) => (Token::DotDot, 2),
+            ('!', _) => (Token::Not, 1),
+            ('<', _) => (Token::Lt, 1),
+            ('>', _) => (Token::Gt, 1),
+            ('-', _) => (Token::Minus, 1),
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            (',', _) => (Token::Comma, 1),
+            _ => return Err(format!("Unexpected character '{}' at position {}", c, i)),
+        };
+        tokens.push(token);
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+// ============================================================================
+// PARSER
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    Var(String),
+    Call(String, Vec<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `value in start..end`; wraps around when end < start (e.g. hours 22..6)
+    InRange(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+/// Deepest nesting of parentheses, calls, `!`, `-` and chained `&&`/`||`
+/// the parser builds, so checking and evaluating a rule can't overflow the
+/// stack
+const MAX_NESTING: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(format!("Expected {:?} but found {:?}", expected, token)),
+            None => Err(format!("Expected {:?} but the expression ended", expected)),
+        }
+    }
+
+    /// Go one level deeper into the expression
+    fn enter(&mut self) -> Result<(), String> {
+        if self.depth == MAX_NESTING {
+            return Err("expression nested too deeply".to_string());
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Parse a nested expression with `parse`, one level deeper
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Expr, String>) -> Result<Expr, String> {
+        self.enter()?;
+        let expr = parse(self)?;
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            // Each operator nests the expression so far one level deeper
+            self.enter()?;
+            let right = self.parse_and()?;
+            left = Expr::Binary(BinaryOp::Or, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            self.enter()?;
+            let right = self.parse_not()?;
+            left = Expr::Binary(BinaryOp::And, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.nested(Self::parse_not)?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_primary()?;
+
+        let op = match self.peek() {
+            Some(Token::Eq) => BinaryOp::Eq,
+            Some(Token::Ne) => BinaryOp::Ne,
+            Some(Token::Lt) => BinaryOp::Lt,
+            Some(Token::Le) => BinaryOp::Le,
+            Some(Token::Gt) => BinaryOp::Gt,
+            Some(Token::Ge) => BinaryOp::Ge,
+            Some(Token::In) => {
+                self.next();
+                let start = self.parse_primary()?;
+                self.expect(Token::DotDot)?;
+                let end = self.parse_primary()?;
+                return Ok(Expr::InRange(Box::new(left), Box::new(start), Box::new(end)));
+            }
+            _ => return Ok(left),
+        };
+        self.next();
+
+        let right = self.parse_primary()?;
+        Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Str(s)) => Ok(Expr::Str(s)),
+            Some(Token::True) => Ok(Expr::Bool(true)),
+            Some(Token::False) => Ok(Expr::Bool(false)),
+            Some(Token::Minus) => Ok(Expr::Neg(Box::new(self.nested(Self::parse_primary)?))),
+            Some(Token::LParen) => {
+                let expr = self.nested(Self::parse_or)?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() != Some(&Token::LParen) {
+                    return Ok(Expr::Var(name));
+                }
+                self.next();
+                let mut args = Vec::new();
+                if self.peek() != Some(&Token::RParen) {
+                    loop {
+                        args.push(self.nested(Self::parse_or)?);
+                        if self.peek() == Some(&Token::Comma) {
+                            self.next();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                self.expect(Token::RParen)?;
+                Ok(Expr::Call(name, args))
+            }
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+// ============================================================================
+// TYPE CHECKING
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Bool,
+    Num,
+    Str,
+}
+
+struct Checker<'a> {
+    thresholds: &'a BTreeSet<String>,
+    unknown: BTreeSet<String>,
+    errors: Vec<String>,
+}
+
+impl Checker<'_> {
+    fn check(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Num(_) => Type::Num,
+            Expr::Str(_) => Type::Str,
+            Expr::Bool(_) => Type::Bool,
+            Expr::Var(name) => match name.as_str() {
+                "motion" => Type::Bool,
+                _ if VARIABLES.contains(&name.as_str()) => Type::Num,
+                _ => {
+                    self.unknown.insert(name.clone());
+                    Type::Num
+                }
+            },
+            Expr::Call(name, args) => {
+                if name != "threshold" {
+                    self.unknown.insert(format!("{}()", name));
+                    return Type::Num;
+                }
+                match args.as_slice() {
+                    [Expr::Str(key)] => {
+                        if !self.thresholds.contains(key) {
+                            self.unknown.insert(format!("threshold(\"{}\")", key));
+                        }
+                    }
+                    _ => self.errors.push("threshold() takes a single quoted name".to_string()),
+                }
+                Type::Num
+            }
+            Expr::Not(inner) => {
+                self.expect(inner, Type::Bool, "'!'");
+                Type::Bool
+            }
+            Expr::Neg(inner) => {
+                self.expect(inner, Type::Num, "'-'");
+                Type::Num
+            }
+            Expr::Binary(op, left, right) => {
+                match op {
+                    BinaryOp::And | BinaryOp::Or => {
+                        self.expect(left, Type::Bool, "'&&'/'||'");
+                        self.expect(right, Type::Bool, "'&&'/'||'");
+                    }
+                    BinaryOp::Eq | BinaryOp::Ne => {
+                        let (l, r) = (self.check(left), self.check(right));
+                        if l != r {
+                            self.errors.push(format!("Cannot compare {:?} with {:?}", l, r));
+                        }
+                    }
+                    _ => {
+                        self.expect(left, Type::Num, "comparison");
+                        self.expect(right, Type::Num, "comparison");
+                    }
+                }
+                Type::Bool
+            }
+            Expr::InRange(value, start, end) => {
+                self.expect(value, Type::Num, "'in'");
+                self.expect(start, Type::Num, "range");
+                self.expect(end, Type::Num, "range");
+                Type::Bool
+            }
+        }
+    }
+
+    fn expect(&mut self, expr: &Expr, expected: Type, context: &str) {
+        let actual = self.check(expr);
+        if actual != expected {
+            self.errors.push(format!("{} expects {:?} but got {:?}", context, expected, actual));
+        }
+    }
+}
+
+// ============================================================================
+// EVALUATION
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Bool(bool),
+    Num(f64),
+    Str(String),
+}
+
+impl Value {
+    fn as_bool(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+
+    fn as_num(&self) -> f64 {
+        match self {
+            Value::Num(n) => *n,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Inputs a rule is evaluated against
+pub struct RuleContext<'a> {
+    pub reading: &'a SensorReading,
+    pub seconds_since_motion: u64,
+    pub thresholds: &'a HashMap<String, f64>,
+    /// Where `hour` and `minute` are local to
+    pub days: &'a FacilityDays,
+}
+
+fn eval(expr: &Expr, ctx: &RuleContext) -> Value {
+    match expr {
+        Expr::Num(n) => Value::Num(*n),
+        Expr::Str(s) => Value::Str(s.clone()),
+        Expr::Bool(b) => Value::Bool(*b),
+        Expr::Var(name) => match name.as_str() {
+            "motion" => Value::Bool(ctx.reading.motion),
+            "sound" => Value::Num(ctx.reading.sound_level as f64),
+            "temperature" => Value::Num(ctx.reading.temperature as f64),
+            "hour" => Value::Num(ctx.days.local(ctx.reading.timestamp).hour() as f64),
+            "minute" => Value::Num(ctx.days.local(ctx.reading.timestamp).minute() as f64),
+            "inactive_seconds" => Value::Num(ctx.seconds_since_motion as f64),
+            _ => Value::Num(0.0),
+        },
+        Expr::Call(_, args) => match args.first() {
+            Some(Expr::Str(key)) => Value::Num(ctx.thresholds.get(key).copied().unwrap_or(f64::MAX)),
+            _ => Value::Num(f64::MAX),
+        },
+        Expr::Not(inner) => Value::Bool(!eval(inner, ctx).as_bool()),
+        Expr::Neg(inner) => Value::Num(-eval(inner, ctx).as_num()),
+        Expr::Binary(BinaryOp::And, l, r) => Value::Bool(eval(l, ctx).as_bool() && eval(r, ctx).as_bool()),
+        Expr::Binary(BinaryOp::Or, l, r) => Value::Bool(eval(l, ctx).as_bool() || eval(r, ctx).as_bool()),
+        Expr::Binary(op, l, r) => {
+            let (l, r) = (eval(l, ctx), eval(r, ctx));
+            Value::Bool(match op {
+                BinaryOp::Eq => l == r,
+                BinaryOp::Ne => l != r,
+                BinaryOp::Lt => l.as_num() < r.as_num(),
+                BinaryOp::Le => l.as_num() <= r.as_num(),
+                BinaryOp::Gt => l.as_num() > r.as_num(),
+                BinaryOp::Ge => l.as_num() >= r.as_num(),
+                BinaryOp::And | BinaryOp::Or => unreachable!(),
+            })
+        }
+        Expr::InRange(value, start, end) => {
+            let (v, s, e) = (eval(value, ctx).as_num(), eval(start, ctx).as_num(), eval(end, ctx).as_num());
+            Value::Bool(if s <= e { v >= s && v < e } else { v >= s || v < e })
+        }
+    }
+}
+
+// ============================================================================
+// RULES
+// ============================================================================
+
+/// Rule definition as stored and exchanged over the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRule {
+    #[serde(default)]
+    pub id: i64,
+    pub name: String,
+    pub expression: String,
+    /// Alert raised when the expression is true
+    pub alert: AlertType,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Evaluated and its matches recorded for review, but raises no alert,
+    /// until promoted
+    #[serde(default)]
+    pub shadow: bool,
+}
+
+/// A shadow rule starting to match a room's readings, with the reading
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShadowHit {
+    #[serde(default)]
+    pub id: i64,
+    pub rule_id: i64,
+    pub room: String,
+    pub at: DateTime<Utc>,
+    pub temperature: f32,
+    pub motion: bool,
+    pub sound_level: i32,
+}
+
+/// Shadow rules that matched a room's previous reading, so each run of
+/// readings a rule matches counts as one hit, as it would be one alert
+#[derive(Debug, Default)]
+pub struct ShadowEpisodes {
+    matching: HashSet<i64>,
+}
+
+impl ShadowEpisodes {
+    /// Record the shadow rules matching a room's reading; returns those
+    /// that didn't match the one before
+    pub fn update<'a>(&mut self, matched: impl IntoIterator<Item = &'a AlertRule>) -> Vec<&'a AlertRule> {
+        let matched: Vec<&AlertRule> = matched.into_iter().filter(|r| r.shadow).collect();
+        let started = matched.iter().copied().filter(|r| !self.matching.contains(&r.id)).collect();
+        self.matching = matched.iter().map(|r| r.id).collect();
+        started
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    pub rule: AlertRule,
+    expr: Expr,
+}
+
+/// Parse and type-check an expression against the known thresholds.
+///
+/// All unknown identifiers are reported together, along with what is available.
+pub fn compile(rule: AlertRule, thresholds: &BTreeSet<String>) -> Result<CompiledRule, String> {
+    let tokens = tokenize(&rule.expression)?;
+    let mut parser = Parser { tokens, pos: 0, depth: 0 };
+    let expr = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("Unexpected {:?} after end of expression", token));
+    }
+
+    let mut checker = Checker {
+        thresholds,
+        unknown: BTreeSet::new(),
+        errors: Vec::new(),
+    };
+    let result = checker.check(&expr);
+
+    if !checker.unknown.is_empty() {
+        let unknown: Vec<&str> = checker.unknown.iter().map(String::as_str).collect();
+        let known: Vec<&str> = thresholds.iter().map(String::as_str).collect();
+        return Err(format!(
+            "Unknown identifiers: {}. Available variables: {}; thresholds: {}",
+            unknown.join(", "),
+            VARIABLES.join(", "),
+            known.join(", "),
+        ));
+    }
+    if let Some(error) = checker.errors.into_iter().next() {
+        return Err(error);
+    }
+    if result != Type::Bool {
+        return Err(format!("Rule must evaluate to true/false, not {:?}", result));
+    }
+
+    Ok(CompiledRule { rule, expr })
+}
+
+/// Active rules plus the named thresholds they can refer to
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    pub rules: Vec<CompiledRule>,
+    pub thresholds: HashMap<String, f64>,
+    pub days: FacilityDays,
+}
+
+impl RuleSet {
+    /// Load and compile all stored rules. Rules that no longer compile (e.g.
+    /// a threshold they use was removed) are skipped with a warning.
+    pub async fn load(db: &Database, days: FacilityDays) -> Result<Self, DbError> {
+        let mut set = RuleSet {
+            rules: Vec::new(),
+            thresholds: db.get_rule_thresholds().await?,
+            days,
+        };
+        let names = set.threshold_names();
+
+        for rule in db.get_alert_rules().await? {
+            let name = rule.name.clone();
+            match compile(rule, &names) {
+                Ok(compiled) => set.rules.push(compiled),
+                Err(e) => warn!("Skipping alert rule '{}': {}", name, e),
+            }
+        }
+
+        Ok(set)
+    }
+
+    /// Names usable in `threshold("...")`
+    pub fn threshold_names(&self) -> BTreeSet<String> {
+        self.thresholds
+            .keys()
+            .cloned()
+            .chain(BUILTIN_THRESHOLDS.iter().map(|s| s.to_string()))
+            .collect()
+    }
+
+    /// All enabled rules that match the reading, shadow rules included
+    pub fn evaluate(
+        &self,
+        reading: &SensorReading,
+        seconds_since_motion: u64,
+        settings: &MonitorSettings,
+    ) -> Vec<&AlertRule> {
+        if self.rules.is_empty() {
+            return Vec::new();
+        }
+
+        let mut thresholds = self.thresholds.clone();
+        thresholds.insert("sound".to_string(), settings.sound_threshold as f64);
+        thresholds.insert("inactivity".to_string(), settings.inactivity_seconds as f64);
+
+        let ctx = RuleContext {
+            reading,
+            seconds_since_motion,
+            thresholds: &thresholds,
+            days: &self.days,
+        };
+
+        self.rules
+            .iter()
+            .filter(|r| r.rule.enabled && eval(&r.expr, &ctx).as_bool())
+            .map(|r| &r.rule)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn compile_expression(expression: &str) -> Result<CompiledRule, String> {
+        let rule = AlertRule {
+            id: 1,
+            name: "test".to_string(),
+            expression: expression.to_string(),
+            alert: AlertType::Fall,
+            enabled: true,
+            shadow: false,
+        };
+        let thresholds = ["night", "sound"].into_iter().map(String::from).collect();
+        compile(rule, &thresholds)
+    }
+
+    /// Whether `expression` holds for `reading`, with hours local to `days`
+    fn holds(expression: &str, reading: SensorReading, days: &FacilityDays) -> bool {
+        let compiled = compile_expression(expression).unwrap();
+        let thresholds = HashMap::from([("night".to_string(), 40.0), ("sound".to_string(), 70.0)]);
+        let ctx = RuleContext { reading: &reading, seconds_since_motion: 0, thresholds: &thresholds, days };
+        eval(&compiled.expr, &ctx).as_bool()
+    }
+
+    fn at(hour: u32, minute: u32) -> SensorReading {
+        SensorReading {
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 15, hour, minute, 0).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_syntax_errors_point_at_the_position() {
+        assert_eq!(compile_expression("motion && sound @ 3").unwrap_err(), "Unexpected character '@' at position 16");
+        assert_eq!(
+            compile_expression("threshold(\"night) > 1").unwrap_err(),
+            "Unterminated string starting at position 10",
+        );
+        assert_eq!(compile_expression("(motion").unwrap_err(), "Expected RParen but the expression ended");
+        assert_eq!(compile_expression("motion &&").unwrap_err(), "Unexpected end of expression");
+        assert_eq!(compile_expression("motion motion").unwrap_err(), "Unexpected Ident(\"motion\") after end of expression");
+    }
+
+    #[test]
+    fn test_unknown_identifiers_are_listed_together() {
+        let error = compile_expression("temprature > 30 && threshold(\"day\") < sound && avg() > 1").unwrap_err();
+        assert_eq!(
+            error,
+            "Unknown identifiers: avg(), temprature, threshold(\"day\"). \
+             Available variables: motion, sound, temperature, hour, minute, inactive_seconds; thresholds: night, sound",
+        );
+        assert!(compile_expression("temperature > 30 && sound > threshold(\"night\")").is_ok());
+    }
+
+    #[test]
+    fn test_types_are_checked() {
+        assert_eq!(compile_expression("motion > 1").unwrap_err(), "comparison expects Num but got Bool");
+        assert_eq!(compile_expression("sound && motion").unwrap_err(), "'&&'/'||' expects Bool but got Num");
+        assert_eq!(compile_expression("!sound").unwrap_err(), "'!' expects Bool but got Num");
+        assert_eq!(compile_expression("-motion < 0").unwrap_err(), "'-' expects Num but got Bool");
+        assert_eq!(compile_expression("motion == 1").unwrap_err(), "Cannot compare Bool with Num");
+        assert_eq!(compile_expression("motion in 1..2").unwrap_err(), "'in' expects Num but got Bool");
+        assert_eq!(compile_expression("sound + 1").unwrap_err(), "Unexpected character '+' at position 6");
+        assert_eq!(compile_expression("sound").unwrap_err(), "Rule must evaluate to true/false, not Num");
+        assert!(compile_expression("motion == true && sound != -3").is_ok());
+    }
+
+    #[test]
+    fn test_and_binds_tighter_than_or() {
+        let days = FacilityDays::default();
+        let quiet_still = at(12, 0);
+        let loud_still = SensorReading { sound_level: 80, ..at(12, 0) };
+        let quiet_moving = SensorReading { motion: true, ..at(12, 0) };
+
+        // motion || (sound > 50 && false)
+        assert!(holds("motion || sound > 50 && false", quiet_moving.clone(), &days));
+        assert!(!holds("motion || sound > 50 && false", loud_still.clone(), &days));
+        // (motion || sound > 50) && false
+        assert!(!holds("(motion || sound > 50) && false", quiet_moving.clone(), &days));
+        // !motion && sound > 50, with `!` binding tightest
+        assert!(holds("!motion && sound > 50", loud_still.clone(), &days));
+        assert!(!holds("!motion && sound > 50", quiet_still, &days));
+        assert!(holds("not motion and sound > threshold(\"night\")", loud_still, &days));
+        assert!(!holds("!(motion || true)", quiet_moving, &days));
+    }
+
+    #[test]
+    fn test_hour_range_wraps_around_midnight() {
+        let utc = FacilityDays::default();
+        let cases = [(21, 59, false), (22, 0, true), (23, 30, true), (0, 0, true), (5, 59, true), (6, 0, false), (12, 0, false)];
+        for (hour, minute, expected) in cases {
+            assert_eq!(holds("hour in 22..6", at(hour, minute), &utc), expected, "{hour}:{minute:02}");
+        }
+        // Ranges that don't wrap include their start and exclude their end
+        assert!(holds("hour in 8..20", at(8, 0), &utc));
+        assert!(!holds("hour in 8..20", at(20, 0), &utc));
+
+        // Hours are the facility's local time: 21:30 UTC is 22:30 in Berlin in winter
+        let berlin = FacilityDays { timezone: chrono_tz::Europe::Berlin, start_hour: 0 };
+        assert!(holds("hour in 22..6", at(21, 30), &berlin));
+        assert!(!holds("hour in 22..6", at(5, 30), &berlin));
+    }
+
+    #[test]
+    fn test_nesting_is_limited() {
+        let nested = |depth| format!("{}motion{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(compile_expression(&nested(MAX_NESTING)).is_ok());
+        assert_eq!(compile_expression(&nested(100_000)).unwrap_err(), "expression nested too deeply");
+        assert_eq!(
+            compile_expression(&format!("{}motion", "!".repeat(100_000))).unwrap_err(),
+            "expression nested too deeply",
+        );
+        assert_eq!(
+            compile_expression(&format!("{}1 > 0", "-".repeat(100_000))).unwrap_err(),
+            "expression nested too deeply",
+        );
+        // A long chain nests as deeply as parentheses would
+        let chain = vec!["motion"; 100_000].join(" && ");
+        assert_eq!(compile_expression(&chain).unwrap_err(), "expression nested too deeply");
+        assert!(compile_expression(&vec!["motion"; MAX_NESTING].join(" || ")).is_ok());
+    }
+}
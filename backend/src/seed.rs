@@ -0,0 +1,301 @@
+//! Synthetic data for development and demos
+//!
+//! Generates several days of readings with a day/night rhythm: the patient
+//! moves and makes noise during the day and sleeps at night, temperature
+//! follows the day, and falls and long still periods are embedded at random
+//! times. Alerts are raised by the same detection as live readings.
+//!
+//! Run `monitor seed [--rooms room-101,room-102] [--days 7] [--interval 30]
+//! [--falls-per-day 1] [--inactivity-per-day 2] [--seed 42] [--force]`, or in mock mode
+//! `POST /api/dev/seed` with the same options as JSON. Seeded readings are
+//! tagged `synthetic`, so they can be filtered or removed as a group.
+
+use chrono::{DateTime, Duration, Timelike, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use tracing::info;
+
+use crate::db::{Database, DbError};
+use crate::fhir::{SensorEvent, SensorReading};
+use patient_monitor_types::analysis::detect_alerts;
+use patient_monitor_types::api::MonitorSettings;
+
+/// Tag attached to all seeded readings
+pub const SEED_TAG: &str = "synthetic";
+
+const MAX_DAYS: u32 = 90;
+/// Readings are inserted in batches of this size
+const BATCH_SIZE: usize = 5000;
+/// An embedded still period lasts this many times the inactivity threshold
+const STILL_PERIOD_FACTOR: i64 = 3;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SeedOptions {
+    /// Rooms to seed, default the configured rooms
+    pub rooms: Vec<String>,
+    /// Days of data, ending now
+    pub days: u32,
+    /// Seconds between readings
+    pub interval_secs: u32,
+    pub falls_per_day: u32,
+    /// Daytime periods without motion long enough to raise inactivity alerts
+    pub inactivity_per_day: u32,
+    /// Random seed, for reproducible data sets
+    pub seed: Option<u64>,
+    /// Seed even if the period already has readings
+    pub force: bool,
+}
+
+impl Default for SeedOptions {
+    fn default() -> Self {
+        Self {
+            rooms: Vec::new(),
+            days: 7,
+            interval_secs: 30,
+            falls_per_day: 1,
+            inactivity_per_day: 2,
+            seed: None,
+            force: false,
+        }
+    }
+}
+
+impl SeedOptions {
+    /// Parse `--name value` command line options
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--force" {
+                options.force = true;
+                continue;
+            }
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--rooms" => options.rooms = value()?.split(',').map(|r| r.trim().to_string()).collect(),
+                "--days" => options.days = parse(&arg, &value()?)?,
+                "--interval" => options.interval_secs = parse(&arg, &value()?)?,
+                "--falls-per-day" => options.falls_per_day = parse(&arg, &value()?)?,
+                "--inactivity-per-day" => options.inactivity_per_day = parse(&arg, &value()?)?,
+                "--seed" => options.seed = Some(parse(&arg, &value()?)?),
+                _ => return Err(format!("Unknown option '{}'", arg)),
+            }
+        }
+
+        options.validate()?;
+        Ok(options)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_DAYS).contains(&self.days) {
+            return Err(format!("days must be 1-{}", MAX_DAYS));
+        }
+        if self.rooms.iter().any(|r| r.is_empty() || r.len() > 64) {
+            return Err("room ids must be 1-64 characters".to_string());
+        }
+        if !(1..=3600).contains(&self.interval_secs) {
+            return Err("interval must be 1-3600 seconds".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value '{}' for {}", value, name))
+}
+
+#[derive(Debug)]
+pub enum SeedError {
+    Invalid(String),
+    /// The period already has this many readings
+    DataExists(u64),
+    Database(DbError),
+}
+
+impl std::fmt::Display for SeedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SeedError::Invalid(msg) => write!(f, "{}", msg),
+            SeedError::DataExists(n) => write!(f, "{} readings already exist in the period; use force to seed anyway", n),
+            SeedError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SeedError {}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeedSummary {
+    pub rooms: Vec<String>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub readings: u64,
+    pub fall_alerts: u64,
+    pub inactivity_alerts: u64,
+}
+
+fn is_night(t: DateTime<Utc>) -> bool {
+    let hour = t.hour();
+    !(6..22).contains(&hour)
+}
+
+/// Readings for `room` from `start` to `end` with incidents embedded
+pub fn generate(
+    options: &SeedOptions,
+    settings: &MonitorSettings,
+    room: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    rng: &mut StdRng,
+) -> Vec<SensorEvent> {
+    let interval = Duration::seconds(options.interval_secs as i64);
+
+    // Incidents happen during the day, when they stand out from sleep
+    let days = (end - start).num_days().max(1);
+    let daytime = |rng: &mut StdRng| {
+        let day = start + Duration::days(rng.gen_range(0..days));
+        let day = day.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        day + Duration::seconds(rng.gen_range(7 * 3600..21 * 3600))
+    };
+    let falls: Vec<DateTime<Utc>> = (0..options.falls_per_day as i64 * days)
+        .map(|_| daytime(rng))
+        .collect();
+    let still_length = Duration::seconds(settings.inactivity_seconds as i64 * STILL_PERIOD_FACTOR);
+    let still: Vec<DateTime<Utc>> = (0..options.inactivity_per_day as i64 * days)
+        .map(|_| daytime(rng))
+        .collect();
+
+    let mut events = Vec::new();
+    let mut last_motion = start;
+    let mut t = start;
+
+    while t < end {
+        let hours = t.hour() as f32 + t.minute() as f32 / 60.0;
+        // Warmest mid-afternoon, coolest early morning
+        let temperature = 22.0 + 1.5 * ((hours - 9.0) / 24.0 * 2.0 * PI).sin()
+            + rng.gen_range(-0.3..0.3);
+        // Drier when it's warmer
+        let humidity = 45.0 - 4.0 * (temperature - 22.0) + rng.gen_range(-1.0..1.0);
+
+        let fall = falls.iter().any(|f| *f >= t && *f < t + interval);
+        let still_now = still.iter().any(|s| t >= *s && t < *s + still_length);
+        let (motion, sound_level) = if fall {
+            (true, rng.gen_range(settings.sound_threshold + 20..settings.sound_threshold + 250))
+        } else if still_now {
+            (false, rng.gen_range(5..25))
+        } else if is_night(t) {
+            (rng.gen_bool(0.15), rng.gen_range(5..40))
+        } else {
+            let loud = rng.gen_bool(0.02);
+            (rng.gen_bool(0.45), if loud { rng.gen_range(80..140) } else { rng.gen_range(20..70) })
+        };
+
+        // Lights on by day; at night mostly off, but now and then switched on
+        // by a patient who is up
+        let light = if !is_night(t) {
+            rng.gen_range(200.0..400.0)
+        } else if motion && rng.gen_bool(0.2) {
+            rng.gen_range(80.0..250.0)
+        } else {
+            rng.gen_range(0.0..5.0)
+        };
+
+        // CO2 builds up overnight in the closed room and falls once it's
+        // aired in the morning
+        let co2 = if is_night(t) { rng.gen_range(900..1300) } else { rng.gen_range(500..850) };
+
+        // The radar sees the patient breathing even when they lie still,
+        // unless they've left the room for a while
+        let presence = motion || rng.gen_bool(0.95);
+
+        if motion {
+            last_motion = t;
+        }
+        let reading = SensorReading {
+            temperature: (temperature * 10.0).round() / 10.0,
+            motion,
+            sound_level,
+            humidity: Some((humidity * 10.0).round() / 10.0),
+            heart_rate: None,
+            spo2: None,
+            door_open: None,
+            light: Some((light * 10.0f32).round() / 10.0),
+            co2: Some(co2),
+            presence: Some(presence),
+            acceleration: None,
+            timestamp: t,
+        };
+        let alerts = detect_alerts(&reading, settings, (t - last_motion).num_seconds() as u64);
+        events.push(SensorEvent {
+            id: None,
+            seq: None,
+            room: room.to_string(),
+            device: None,
+            patient_id: None,
+            reading,
+            alerts,
+        });
+
+        t += interval;
+    }
+
+    events
+}
+
+/// Generate and store synthetic readings for the last `options.days` days,
+/// in `options.rooms` or else `default_rooms`
+pub async fn seed(
+    db: &Database,
+    options: &SeedOptions,
+    settings: &MonitorSettings,
+    default_rooms: &[String],
+) -> Result<SeedSummary, SeedError> {
+    options.validate().map_err(SeedError::Invalid)?;
+    let rooms = if options.rooms.is_empty() { default_rooms } else { &options.rooms };
+    let end = Utc::now();
+    let start = end - Duration::days(options.days as i64);
+
+    if !options.force {
+        for room in rooms {
+            let existing = db.count_readings_in_range(start, end, Some(room)).await
+                .map_err(SeedError::Database)?;
+            if existing > 0 {
+                return Err(SeedError::DataExists(existing));
+            }
+        }
+    }
+
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let events: Vec<SensorEvent> = rooms
+        .iter()
+        .flat_map(|room| generate(options, settings, room, start, end, &mut rng))
+        .collect();
+    let count = |alert| events.iter().filter(|e| e.alerts.contains(alert)).count() as u64;
+    let summary = SeedSummary {
+        rooms: rooms.to_vec(),
+        start,
+        end,
+        readings: events.len() as u64,
+        fall_alerts: count(crate::fhir::AlertType::Fall),
+        inactivity_alerts: count(crate::fhir::AlertType::Inactivity),
+    };
+
+    db.register_rooms(rooms).await.map_err(SeedError::Database)?;
+    for batch in events.chunks(BATCH_SIZE) {
+        let stored = db.insert_readings_batch(batch).await.map_err(SeedError::Database)?;
+        let ids: Vec<i64> = stored.iter().map(|s| s.id).collect();
+        db.tag_readings(SEED_TAG, &ids).await.map_err(SeedError::Database)?;
+    }
+
+    info!("Seeded {} readings in {} from {} to {} ({} falls, {} inactivity alerts)",
+        summary.readings, rooms.join(", "), start, end, summary.fall_alerts, summary.inactivity_alerts);
+    Ok(summary)
+}
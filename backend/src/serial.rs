@@ -1,31 +1,94 @@
 //! Serial communication module for Arduino
+//!
+//! Each room's port is read by a tokio task (`tokio-serial`) that awaits its
+//! lines, so the ingest loop gets readings as soon as they arrive and stopping
+//! it (dropping its `SerialReader`) closes the port. Readings reach the loop
+//! through a bounded `ReadingQueue` (`SERIAL_QUEUE_MAX` readings), the other
+//! lines through a channel. When the loop falls behind, e.g. because the
+//! database and live clients are saturated, the queue drops its oldest
+//! readings that raise no alerts; readings that raise alerts are never
+//! dropped.
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use serialport::SerialPortType;
-use std::io::{BufRead, BufReader};
-use std::sync::{mpsc::{self, Receiver, Sender}, Arc, RwLock};
-use std::thread;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio_serial::SerialPortBuilderExt;
 use tracing::{debug, error, info, warn};
 
-use crate::fhir::{AlertType, SensorEvent, SensorReading};
+use crate::fhir::{AlertSet, AlertType, SensorChannel, SensorEvent, SensorRanges, SensorReading, DEFAULT_ROOM_ID};
+use patient_monitor_types::analysis::{detect_alerts, detect_fall_after_motion, detect_vital_alerts, FlatlineDetector, ImpactFallDetector, ImpactLimit, MotionSource, VentilationDetector, VentilationLimit, VitalLimits};
 use crate::api::MonitorSettings;
+use crate::db::Database;
+use crate::flags::{FeatureFlags, FALL_DETECTOR_V2};
+use crate::rules::{RuleSet, ShadowEpisodes, ShadowHit};
+
+/// Temperature unit the device firmware reports in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    /// Normalize a raw device value to the canonical Celsius
+    pub fn to_celsius(self, value: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => value,
+            TemperatureUnit::Fahrenheit => (value - 32.0) * 5.0 / 9.0,
+        }
+    }
+}
+
+impl std::str::FromStr for TemperatureUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "c" | "celsius" => Ok(TemperatureUnit::Celsius),
+            "f" | "fahrenheit" => Ok(TemperatureUnit::Fahrenheit),
+            other => Err(format!("Unknown temperature unit: {}", other)),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct SerialConfig {
+    /// Room the sensor is installed in; readings are tagged with it
+    pub room: String,
     pub port: String,
     pub baud_rate: u32,
     pub sound_threshold: i32,
     pub inactivity_seconds: u64,
+    pub temperature_unit: TemperatureUnit,
+    /// Hours the sound level may stay flat before the microphone is reported
+    /// as faulty (0 = never)
+    pub sound_flatline_hours: u64,
+    /// Variance of the sound level below which the signal counts as flat
+    pub sound_flatline_epsilon: f64,
+    /// Readings outside these are quarantined
+    pub ranges: SensorRanges,
 }
 
 impl Default for SerialConfig {
     fn default() -> Self {
         Self {
+            room: DEFAULT_ROOM_ID.to_string(),
             port: "COM3".to_string(),
             baud_rate: 9600,
             sound_threshold: 150,
             inactivity_seconds: 300,
+            temperature_unit: TemperatureUnit::Celsius,
+            sound_flatline_hours: 6,
+            sound_flatline_epsilon: 1.0,
+            ranges: SensorRanges::default(),
         }
     }
 }
@@ -53,192 +116,890 @@ pub fn list_available_ports() -> Vec<String> {
     }
 }
 
-pub struct SerialReader {
-    receiver: Receiver<SensorEvent>,
-    _handle: thread::JoinHandle<()>,
+/// Levels of device log lines, as stored
+pub const DEVICE_LOG_LEVELS: &[&str] = &["debug", "info", "warn", "error"];
+
+/// `level` as stored, if it is one of `DEVICE_LOG_LEVELS` in any case
+pub fn device_log_level(level: &str) -> Option<&'static str> {
+    let level = level.trim();
+    DEVICE_LOG_LEVELS.iter().copied().find(|l| l.eq_ignore_ascii_case(level))
 }
 
-impl SerialReader {
-    pub fn start(config: SerialConfig, settings: Arc<RwLock<MonitorSettings>>) -> Result<Self, String> {
-        info!("Opening serial port: {} at {} baud", config.port, config.baud_rate);
-        
-        let (sender, receiver): (Sender<SensorEvent>, Receiver<SensorEvent>) = mpsc::channel();
-        
-        let port_name = config.port.clone();
-        let baud_rate = config.baud_rate;
+/// Log or crash report line sent by the firmware, e.g. `LOG:error:watchdog reset`
+#[derive(Debug, Clone)]
+pub struct DeviceLogLine {
+    pub level: String,
+    pub message: String,
+}
+
+impl DeviceLogLine {
+    const PREFIX: &'static str = "LOG:";
+
+    /// Parse `LOG:<level>:<message>`. Without a known level, as in
+    /// `LOG:watchdog reset at 12:30`, all of it is the message, at `info`.
+    pub fn parse(line: &str) -> Option<Self> {
+        let rest = line.strip_prefix(Self::PREFIX)?;
+        let (level, message) = rest
+            .split_once(':')
+            .and_then(|(level, message)| Some((device_log_level(level)?, message)))
+            .unwrap_or(("info", rest));
         
-        let port = serialport::new(&port_name, baud_rate)
-            .timeout(Duration::from_millis(1000))
-            .open()
-            .map_err(|e| format!("Failed to open {}: {}", port_name, e))?;
+        Some(Self {
+            level: level.to_string(),
+            message: message.trim().to_string(),
+        })
+    }
+}
+
+/// Parse the `FW:<version>` line the firmware sends on boot
+pub fn parse_firmware_line(line: &str) -> Option<String> {
+    let version = line.strip_prefix("FW:")?.trim();
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// Parse the `BED:<0|1>` line a bed sensor sends when the bed is left or
+/// occupied
+pub fn parse_bed_line(line: &str) -> Option<bool> {
+    match line.strip_prefix("BED:")?.trim() {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    }
+}
+
+/// How often the registry's `last_seen` is written while a device keeps reporting
+const DEVICE_SEEN_INTERVAL: chrono::TimeDelta = chrono::TimeDelta::seconds(30);
+
+/// Keeps a board's entry in the device registry current from its ingest loop
+pub struct DeviceTracker {
+    device_id: String,
+    room: String,
+    firmware_version: Option<String>,
+    last_seen: Option<DateTime<Utc>>,
+    registered: bool,
+    /// `last_seen` as of the last write
+    written: Option<DateTime<Utc>>,
+    firmware_changed: bool,
+}
+
+impl DeviceTracker {
+    pub fn new(device_id: String, room: String) -> Self {
+        Self {
+            device_id,
+            room,
+            firmware_version: None,
+            last_seen: None,
+            registered: false,
+            written: None,
+            firmware_changed: false,
+        }
+    }
+    
+    /// Record data received from the device at `at`
+    pub fn seen(&mut self, at: DateTime<Utc>) {
+        self.last_seen = self.last_seen.max(Some(at));
+    }
+    
+    pub fn set_firmware(&mut self, version: String) {
+        if self.firmware_version.as_ref() != Some(&version) {
+            info!("Device {} in {} runs firmware {}", self.device_id, self.room, version);
+            self.firmware_version = Some(version);
+            self.firmware_changed = true;
+        }
+    }
+    
+    /// Write the entry when the device is new, its firmware changed, or it
+    /// reported again after `DEVICE_SEEN_INTERVAL`; `force` writes any newer
+    /// `last_seen`, e.g. before the reader is restarted
+    pub async fn sync(&mut self, db: &Database, force: bool) {
+        let due = !self.registered || self.firmware_changed || match (self.written, self.last_seen) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(written), Some(seen)) => seen > written && (force || seen - written >= DEVICE_SEEN_INTERVAL),
+        };
+        if !due {
+            return;
+        }
         
-        info!("Serial port opened successfully");
+        let firmware = self.firmware_changed.then_some(self.firmware_version.as_deref()).flatten();
+        match db.upsert_device(&self.device_id, &self.room, firmware, self.last_seen).await {
+            Ok(()) => {
+                self.registered = true;
+                self.written = self.last_seen;
+                self.firmware_changed = false;
+            }
+            Err(e) => error!("Failed to update device {}: {}", self.device_id, e),
+        }
+    }
+}
+
+/// Readings passed from a reader task to the ingest loop. It outlives the
+/// readers of its room, so readings queued when one is restarted aren't lost.
+#[derive(Debug)]
+pub struct ReadingQueue {
+    events: Mutex<VecDeque<SensorEvent>>,
+    /// Woken when a reading is queued
+    queued: Notify,
+    /// Readings queued at most
+    capacity: usize,
+    dropped: AtomicU64,
+    /// Whether readings are being dropped, until the queue is half empty
+    overflowing: AtomicBool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadingQueueSnapshot {
+    /// Readings received from the device, waiting for the ingest loop
+    pub pending_readings: usize,
+    /// Readings dropped because the ingest loop fell behind the device
+    pub overflow_dropped_readings: u64,
+}
+
+impl ReadingQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Mutex::new(VecDeque::new()),
+            queued: Notify::new(),
+            capacity: capacity.max(1),
+            dropped: AtomicU64::new(0),
+            overflowing: AtomicBool::new(false),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<SensorEvent>> {
+        // A reader panicking mid-push leaves the queue itself usable
+        self.events.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue `event`. A full queue makes room by dropping its oldest reading
+    /// without alerts, or else `event` itself if it has none. Otherwise it
+    /// drops from the middle of a run of readings with the same alerts, then
+    /// the last reading of such a run, so alert onsets are dropped last.
+    pub fn push(&self, event: SensorEvent) {
+        let mut events = self.lock();
+        if events.len() >= self.capacity {
+            let dropped = Self::overflow_victim(&events, &event);
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            if !self.overflowing.swap(true, Ordering::Relaxed) {
+                warn!("Ingest of {} is falling behind its device, dropping readings, those without alerts first",
+                    event.room);
+            }
+            match dropped {
+                Some(index) => {
+                    events.remove(index);
+                }
+                None => return,
+            }
+        }
+        events.push_back(event);
+        self.queued.notify_one();
+    }
+
+    /// Index of the reading a full queue drops to make room for `event`, or
+    /// `None` to drop `event` itself
+    fn overflow_victim(events: &VecDeque<SensorEvent>, event: &SensorEvent) -> Option<usize> {
+        if let Some(oldest) = events.iter().position(|e| e.alerts.is_empty()) {
+            return Some(oldest);
+        }
+        if event.alerts.is_empty() {
+            return None;
+        }
+        // Runs at the end of the queue go on with `event`
+        let alerts = |i: usize| events.get(i).map_or(&event.alerts, |e| &e.alerts);
+        let continues_run = |i: usize| i > 0 && alerts(i - 1) == alerts(i);
+        (1..events.len())
+            .find(|&i| continues_run(i) && alerts(i + 1) == alerts(i))
+            .or_else(|| (1..events.len()).find(|&i| continues_run(i)))
+            .or(Some(0))
+    }
+
+    pub fn pop(&self) -> Option<SensorEvent> {
+        let mut events = self.lock();
+        let event = events.pop_front();
+        if events.len() < self.capacity / 2 && self.overflowing.swap(false, Ordering::Relaxed) {
+            info!("Ingest caught up with the readings received ({} dropped so far)",
+                self.dropped.load(Ordering::Relaxed));
+        }
+        event
+    }
+
+    /// Wait for the next reading
+    pub async fn recv(&self) -> SensorEvent {
+        loop {
+            if let Some(event) = self.pop() {
+                return event;
+            }
+            self.queued.notified().await;
+        }
+    }
+
+    pub fn snapshot(&self) -> ReadingQueueSnapshot {
+        ReadingQueueSnapshot {
+            pending_readings: self.lock().len(),
+            overflow_dropped_readings: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Whether a serial device is connected, kept across restarts of its room's
+/// ingest task
+#[derive(Debug, Default)]
+pub struct DeviceConnection {
+    connected: AtomicBool,
+    disconnects: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceConnectionSnapshot {
+    pub connected: bool,
+    /// Times the device went away after being connected, since startup
+    pub disconnects: u64,
+}
+
+impl DeviceConnection {
+    pub fn set(&self, connected: bool) {
+        let was_connected = self.connected.swap(connected, Ordering::Relaxed);
+        if was_connected && !connected {
+            self.disconnects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> DeviceConnectionSnapshot {
+        DeviceConnectionSnapshot {
+            connected: self.is_connected(),
+            disconnects: self.disconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Wait before reopening a port that failed or disappeared, doubled per
+/// failed attempt
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// What a serial reader received from a room's devices
+#[derive(Debug)]
+pub enum SerialMessage {
+    /// A reading, tagged with the port of the device that took it
+    Reading(SensorEvent),
+    /// Anything else from the device on the given port
+    Device(String, DeviceEvent),
+    /// A shadow rule started matching the room's readings
+    ShadowHit(ShadowHit),
+}
+
+#[derive(Debug)]
+pub enum DeviceEvent {
+    Log(DeviceLogLine),
+    /// Firmware version announced by the device
+    Firmware(String),
+    /// Bed sensor report: when it arrived and whether the bed is occupied
+    Bed(DateTime<Utc>, bool),
+    /// A reading line outside the plausible sensor ranges, and which value
+    Quarantined { at: DateTime<Utc>, line: String, reason: String },
+    /// The port opened, at startup or after the device came back
+    Connected,
+    /// The port couldn't be opened or the device went away, with why;
+    /// sent once until it's connected again
+    Disconnected(String),
+}
+
+/// Limits from the configuration that readings are checked against
+#[derive(Debug, Clone, Copy)]
+pub struct AlertLimits {
+    pub vitals: VitalLimits,
+    /// CO2 level and duration at which ventilation is raised, if at all
+    pub ventilation: Option<VentilationLimit>,
+    /// Impact and stillness after it at which a fall is raised from an
+    /// accelerometer, if at all
+    pub impact: Option<ImpactLimit>,
+    /// Sensors whose readings count as motion for inactivity
+    pub motion_source: MotionSource,
+}
+
+/// What a reader checks readings against
+struct Alerting {
+    settings: Arc<RwLock<MonitorSettings>>,
+    rules: Arc<RwLock<RuleSet>>,
+    flags: FeatureFlags,
+    limits: AlertLimits,
+}
+
+/// When a room last had motion, shared by the readers of its devices
+struct LastMotion {
+    /// As seen by the motion source, for inactivity
+    moved: std::time::Instant,
+    /// As seen by the PIR sensor, for falls: a present patient would
+    /// otherwise always have just moved
+    pir: std::time::Instant,
+}
+
+impl LastMotion {
+    fn new() -> Self {
+        let now = std::time::Instant::now();
+        Self { moved: now, pir: now }
+    }
+
+    /// Note the motion of `reading` and return the seconds since motion as
+    /// seen by `source` and by the PIR sensor
+    fn update(&mut self, reading: &SensorReading, source: MotionSource) -> (u64, u64) {
+        if source.moved(reading) {
+            self.moved = std::time::Instant::now();
+        }
+        if reading.motion {
+            self.pir = std::time::Instant::now();
+        }
+        (self.moved.elapsed().as_secs(), self.pir.elapsed().as_secs())
+    }
+}
+
+/// Stale air in the room of one reader task, logged when it starts and ends
+struct Ventilation {
+    detector: Option<VentilationDetector>,
+    needed: bool,
+}
+
+impl Ventilation {
+    fn new(limit: Option<VentilationLimit>) -> Self {
+        Self { detector: limit.map(VentilationDetector::new), needed: false }
+    }
+
+    /// Add a reading and return whether the room needs airing
+    fn push(&mut self, room: &str, reading: &SensorReading) -> bool {
+        let needed = self.detector
+            .as_mut()
+            .is_some_and(|d| d.push(reading.timestamp, reading.co2));
+        if needed != self.needed {
+            if needed {
+                info!(">>> VENTILATION ALERT: CO2 in {} at {:?} ppm", room, reading.co2);
+            } else {
+                info!("CO2 in {} is back to normal", room);
+            }
+            self.needed = needed;
+        }
+        needed
+    }
+}
+
+/// Falls from the accelerometer of one reader task's device
+struct ImpactFall(Option<ImpactFallDetector>);
+
+impl ImpactFall {
+    fn new(limit: Option<ImpactLimit>) -> Self {
+        Self(limit.map(ImpactFallDetector::new))
+    }
+
+    /// Add a reading and return whether it completes a fall
+    fn push(&mut self, room: &str, reading: &SensorReading) -> bool {
+        let Some(detector) = self.0.as_mut() else {
+            return false;
+        };
+        let fall = detector.push(reading);
+        if fall {
+            info!(">>> FALL ALERT: impact in {} followed by stillness", room);
+        }
+        fall
+    }
+}
+
+/// A room's reader tasks, one per device. Dropping it stops them.
+pub struct SerialReader {
+    queue: Arc<ReadingQueue>,
+    /// Lines other than readings; closed once all tasks have stopped
+    messages: mpsc::UnboundedReceiver<SerialMessage>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl SerialReader {
+    /// Read the devices of one room, one task per port in `configs`. Motion
+    /// seen by any of them counts for the room's inactivity alert, and
+    /// readings of any of them continue a shadow rule's hit.
+    pub fn start(
+        configs: Vec<SerialConfig>,
+        settings: Arc<RwLock<MonitorSettings>>,
+        rules: Arc<RwLock<RuleSet>>,
+        flags: FeatureFlags,
+        limits: AlertLimits,
+        queue: Arc<ReadingQueue>,
+    ) -> Self {
+        let (sender, messages) = mpsc::unbounded_channel();
+        let last_motion = Arc::new(Mutex::new(LastMotion::new()));
+        let shadow = Arc::new(Mutex::new(ShadowEpisodes::default()));
+        let handles = configs
+            .into_iter()
+            .map(|config| tokio::spawn(Self::read_loop(
+                Arc::clone(&queue),
+                sender.clone(),
+                config,
+                Arc::clone(&last_motion),
+                Arc::clone(&shadow),
+                Alerting {
+                    settings: Arc::clone(&settings),
+                    rules: Arc::clone(&rules),
+                    flags: flags.clone(),
+                    limits,
+                },
+            )))
+            .collect();
         
-        let handle = thread::spawn(move || {
-            Self::read_loop(port, sender, config, settings);
-        });
+        Self { queue, messages, handles }
+    }
+    
+    /// Generates random readings for `room` once a second and raises alerts
+    /// with the same settings, rules, flags and limits as the real reader
+    pub fn mock(
+        room: String,
+        settings: Arc<RwLock<MonitorSettings>>,
+        rules: Arc<RwLock<RuleSet>>,
+        flags: FeatureFlags,
+        limits: AlertLimits,
+        queue: Arc<ReadingQueue>,
+    ) -> Self {
+        let (sender, messages) = mpsc::unbounded_channel();
+        let alerting = Alerting { settings, rules, flags, limits };
+        let handle = tokio::spawn(Self::mock_loop(room, Arc::clone(&queue), sender, alerting));
         
-        Ok(Self {
-            receiver,
-            _handle: handle,
-        })
+        Self { queue, messages, handles: vec![handle] }
     }
     
-    fn read_loop(port: Box<dyn serialport::SerialPort>, sender: Sender<SensorEvent>, config: SerialConfig, settings: Arc<RwLock<MonitorSettings>>) {
-        let mut reader = BufReader::new(port);
-        let mut last_motion_time = std::time::Instant::now();
-        let mut line_buffer = String::new();
+    /// Read the port, reopening it with backoff whenever it can't be opened
+    /// or the device goes away, e.g. when the board is unplugged
+    async fn read_loop(
+        queue: Arc<ReadingQueue>,
+        sender: mpsc::UnboundedSender<SerialMessage>,
+        config: SerialConfig,
+        last_motion: Arc<Mutex<LastMotion>>,
+        shadow: Arc<Mutex<ShadowEpisodes>>,
+        alerting: Alerting,
+    ) {
+        let device = |event| SerialMessage::Device(config.port.clone(), event);
+        let mut sound_flatline = (config.sound_flatline_hours > 0).then(|| FlatlineDetector::new(
+            chrono::Duration::hours(config.sound_flatline_hours as i64),
+            config.sound_flatline_epsilon,
+        ));
+        let mut sound_flat = false;
+        let mut ventilation = Ventilation::new(alerting.limits.ventilation);
+        let mut impact = ImpactFall::new(alerting.limits.impact);
+        let mut backoff = RECONNECT_BACKOFF;
+        // Whether the device is already reported disconnected, so retries
+        // failing to open the port aren't reported again
+        let mut failure_reported = false;
         
-        info!("Serial reader thread started");
+        info!("Serial reader started on {} for {} at {} baud ({:?}, initial thresholds: sound>{}, inactivity>{}s)",
+            config.port, config.room, config.baud_rate, config.temperature_unit,
+            config.sound_threshold, config.inactivity_seconds);
         
-        loop {
-            line_buffer.clear();
+        'reconnect: loop {
+            let port = match tokio_serial::new(&config.port, config.baud_rate).open_native_async() {
+                Ok(port) => port,
+                Err(e) => {
+                    if !failure_reported {
+                        error!("Failed to open {}: {}; retrying until it appears (set MOCK_MODE=true to run without Arduino)",
+                            config.port, e);
+                        failure_reported = true;
+                        if sender.send(device(DeviceEvent::Disconnected(e.to_string()))).is_err() {
+                            break;
+                        }
+                    }
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                    continue;
+                }
+            };
             
-            match reader.read_line(&mut line_buffer) {
-                Ok(0) => {
-                    thread::sleep(Duration::from_millis(10));
+            info!("Serial port {} opened", config.port);
+            backoff = RECONNECT_BACKOFF;
+            if sender.send(device(DeviceEvent::Connected)).is_err() {
+                break;
+            }
+            
+            let mut lines = BufReader::new(port).lines();
+            let error = loop {
+                let line = match lines.next_line().await {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break "port closed".to_string(),
+                    // Garbled bytes, e.g. while the board resets
+                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                        warn!("Skipping unreadable serial data: {}", e);
+                        continue;
+                    }
+                    Err(e) => break e.to_string(),
+                };
+                let line = line.trim();
+                
+                if line.is_empty() {
                     continue;
                 }
-                Ok(_) => {
-                    let line = line_buffer.trim();
-                    
-                    if line.is_empty() {
+                
+                debug!("Raw serial data: {}", line);
+                
+                let message = if let Some(log) = DeviceLogLine::parse(line) {
+                    device(DeviceEvent::Log(log))
+                } else if let Some(version) = parse_firmware_line(line) {
+                    device(DeviceEvent::Firmware(version))
+                } else if let Some(occupied) = parse_bed_line(line) {
+                    device(DeviceEvent::Bed(Utc::now(), occupied))
+                } else {
+                    let Some(reading) = Self::parse_line(line, config.temperature_unit) else {
+                        warn!("Failed to parse line: {}", line);
+                        continue;
+                    };
+                    // Kept out of the readings, alerts and activity analysis
+                    if let Some(reason) = config.ranges.check(&reading) {
+                        warn!("Quarantined implausible reading from {}: {} ({})", config.port, reason, line);
+                        let quarantined = DeviceEvent::Quarantined { at: reading.timestamp, line: line.to_string(), reason };
+                        if sender.send(device(quarantined)).is_err() {
+                            break 'reconnect;
+                        }
                         continue;
                     }
-                    
-                    debug!("Raw serial data: {}", line);
-                    
-                    match Self::parse_line(line) {
-                        Some(reading) => {
-                            if reading.motion {
-                                last_motion_time = std::time::Instant::now();
-                            }
-                            
-                            let alert = Self::detect_alert(
-                                &reading,
-                                &settings,
-                                last_motion_time.elapsed().as_secs(),
-                            );
-                            
-                            let event = SensorEvent {
-                                id: None,
-                                reading,
-                                alert,
-                            };
-                            
-                            if sender.send(event).is_err() {
-                                break;
-                            }
+                    let since_motion = last_motion.lock().unwrap().update(&reading, alerting.limits.motion_source);
+                
+                    let (mut alerts, shadow_hits) = Self::detect_alert(
+                        &config.room,
+                        &reading,
+                        &alerting,
+                        &shadow,
+                        since_motion,
+                    );
+                    for hit in shadow_hits {
+                        if sender.send(SerialMessage::ShadowHit(hit)).is_err() {
+                            break 'reconnect;
                         }
-                        None => {
-                            warn!("Failed to parse line: {}", line);
+                    }
+                
+                    // A dead microphone reads as a silent room, so a flat
+                    // sound level over many hours is raised as a fault
+                    let flat = sound_flatline
+                        .as_mut()
+                        .is_some_and(|d| d.push(reading.timestamp, reading.sound_level as f64));
+                    if flat != sound_flat {
+                        if flat {
+                            warn!(">>> SENSOR FAULT: sound level in {} flat for {}h",
+                                config.room, config.sound_flatline_hours);
+                        } else {
+                            info!("Sound sensor in {} is reporting varying levels again", config.room);
                         }
+                        sound_flat = flat;
                     }
-                }
-                Err(e) => {
-                    if e.kind() != std::io::ErrorKind::TimedOut {
-                        error!("Serial read error: {}", e);
+                    if flat {
+                        alerts.insert(AlertType::SensorFault(SensorChannel::Sound));
+                    }
+                    if ventilation.push(&config.room, &reading) {
+                        alerts.insert(AlertType::Ventilation);
+                    }
+                    if impact.push(&config.room, &reading) {
+                        alerts.insert(AlertType::Fall);
                     }
+                
+                    queue.push(SensorEvent {
+                        id: None,
+                        seq: None,
+                        room: config.room.clone(),
+                        device: Some(config.port.clone()),
+                        patient_id: None,
+                        reading,
+                        alerts,
+                    });
+                    continue;
+                };
+                
+                if sender.send(message).is_err() {
+                    break 'reconnect;
                 }
+            };
+            
+            warn!("Serial device on {} disconnected: {}; reconnecting", config.port, error);
+            failure_reported = true;
+            if sender.send(device(DeviceEvent::Disconnected(error))).is_err() {
+                break;
             }
+            tokio::time::sleep(backoff).await;
         }
         
-        info!("Serial reader thread stopped");
+        info!("Serial reader for {} stopped", config.room);
     }
     
-    fn parse_line(line: &str) -> Option<SensorReading> {
-        let parts: Vec<&str> = line.split(',').collect();
+    async fn mock_loop(
+        room: String,
+        queue: Arc<ReadingQueue>,
+        // Held until the loop ends, so the reader sees it stop
+        sender: mpsc::UnboundedSender<SerialMessage>,
+        alerting: Alerting,
+    ) {
+        use rand::Rng;
+        let mut last_motion = LastMotion::new();
+        let mut door_open = false;
+        let shadow = Mutex::new(ShadowEpisodes::default());
+        let mut ventilation = Ventilation::new(alerting.limits.ventilation);
+        let mut impact = ImpactFall::new(alerting.limits.impact);
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
         
-        if parts.len() != 3 {
-            return None;
+        loop {
+            interval.tick().await;
+            
+            let mut rng = rand::thread_rng();
+            // The door opens now and then and stays open for a few seconds
+            door_open = rng.r#gen::<f32>() < if door_open { 0.7 } else { 0.02 };
+            let reading = SensorReading {
+                temperature: 20.0 + rng.r#gen::<f32>() * 10.0,
+                motion: rng.r#gen::<f32>() < 0.3,
+                sound_level: if rng.r#gen::<f32>() < 0.1 {
+                    rng.gen_range(150..400)
+                } else {
+                    rng.gen_range(10..50)
+                },
+                humidity: Some(35.0 + rng.r#gen::<f32>() * 20.0),
+                heart_rate: Some(if rng.r#gen::<f32>() < 0.05 { rng.gen_range(121..150) } else { rng.gen_range(60..100) }),
+                spo2: Some(if rng.r#gen::<f32>() < 0.05 { rng.gen_range(85..90) } else { rng.gen_range(94..100) }),
+                door_open: Some(door_open),
+                light: Some(rng.r#gen::<f32>() * 500.0),
+                co2: Some(if rng.r#gen::<f32>() < 0.05 { rng.gen_range(1500..2500) } else { rng.gen_range(450..1000) }),
+                // The patient is mostly in the room
+                presence: Some(rng.r#gen::<f32>() < 0.9),
+                // Gravity alone, and now and then a bump
+                acceleration: Some(if rng.r#gen::<f32>() < 0.002 { rng.gen_range(2.5..4.0) } else { rng.gen_range(0.95..1.05) }),
+                timestamp: Utc::now(),
+            };
+            
+            let since_motion = last_motion.update(&reading, alerting.limits.motion_source);
+            let (mut alerts, shadow_hits) = Self::detect_alert(
+                &room,
+                &reading,
+                &alerting,
+                &shadow,
+                since_motion,
+            );
+            for hit in shadow_hits {
+                if sender.send(SerialMessage::ShadowHit(hit)).is_err() {
+                    return;
+                }
+            }
+            if ventilation.push(&room, &reading) {
+                alerts.insert(AlertType::Ventilation);
+            }
+            if impact.push(&room, &reading) {
+                alerts.insert(AlertType::Fall);
+            }
+            
+            queue.push(SensorEvent {
+                id: None,
+                seq: None,
+                room: room.clone(),
+                device: None,
+                patient_id: None,
+                reading,
+                alerts,
+            });
         }
-        
-        let temperature = parts[0].trim().parse::<f32>().ok()?;
-        let motion = parts[1].trim().parse::<i32>().ok()? != 0;
-        let sound_level = parts[2].trim().parse::<i32>().ok()?;
-        
-        Some(SensorReading {
-            temperature,
-            motion,
-            sound_level,
-            timestamp: Utc::now(),
-        })
     }
     
-    fn detect_alert(reading: &SensorReading, settings: &Arc<RwLock<MonitorSettings>>, seconds_since_motion: u64) -> AlertType {
-        let settings = settings.read().unwrap();
+    /// Parse a reading line (see `SensorReading::parse_line`) with its
+    /// temperature in Celsius
+    fn parse_line(line: &str, unit: TemperatureUnit) -> Option<SensorReading> {
+        let mut reading = SensorReading::parse_line(line)?;
+        reading.temperature = unit.to_celsius(reading.temperature);
+        Some(reading)
+    }
+    
+    /// Alerts the reading raises, and hits of shadow rules that started
+    /// matching with it. `since_motion` are the seconds since motion as seen
+    /// by the motion source and by the PIR sensor, see `LastMotion::update`.
+    fn detect_alert(
+        room: &str,
+        reading: &SensorReading,
+        alerting: &Alerting,
+        shadow: &Mutex<ShadowEpisodes>,
+        since_motion: (u64, u64),
+    ) -> (AlertSet, Vec<ShadowHit>) {
+        let (seconds_since_motion, seconds_since_pir) = since_motion;
+        // A task that panicked while holding a lock left it poisoned, not
+        // inconsistent; failing here would keep the restarted task failing
+        let settings = alerting.settings.read().unwrap_or_else(|e| e.into_inner());
+        let mut alerts = detect_alerts(reading, &settings, seconds_since_motion);
+        for alert in detect_vital_alerts(reading, &alerting.limits.vitals).iter() {
+            alerts.insert(alert);
+        }
+        if alerting.flags.enabled(FALL_DETECTOR_V2, room) && detect_fall_after_motion(reading, &settings, seconds_since_pir) {
+            alerts.insert(AlertType::Fall);
+        }
         
-        if reading.motion && reading.sound_level > settings.sound_threshold {
+        if alerts.contains(AlertType::Fall) {
             info!(">>> FALL ALERT: motion={}, sound={}", reading.motion, reading.sound_level);
-            return AlertType::Fall;
         }
-        
-        if seconds_since_motion > settings.inactivity_seconds {
+        if alerts.contains(AlertType::Inactivity) {
             info!(">>> INACTIVITY ALERT: no motion for {} seconds", seconds_since_motion);
-            return AlertType::Inactivity;
         }
+        if alerts.contains(AlertType::Tachycardia) || alerts.contains(AlertType::LowSpo2) {
+            info!(">>> VITALS ALERT: heart rate={:?}, SpO2={:?}", reading.heart_rate, reading.spo2);
+        }
+        
+        let rules = alerting.rules.read().unwrap_or_else(|e| e.into_inner());
+        let matched = rules.evaluate(reading, seconds_since_motion, &settings);
+        for rule in matched.iter().filter(|r| !r.shadow) {
+            info!(">>> RULE ALERT: '{}' raised {:?}", rule.name, rule.alert);
+            alerts.insert(rule.alert);
+        }
+        
+        // Shadow rules are only recorded, never alerting anyone
+        let started = shadow.lock().unwrap_or_else(|e| e.into_inner()).update(matched.iter().copied());
+        let shadow_hits = started
+            .into_iter()
+            .map(|rule| {
+                info!(">>> SHADOW RULE: '{}' would raise {:?} in {}", rule.name, rule.alert, room);
+                ShadowHit {
+                    id: 0,
+                    rule_id: rule.id,
+                    room: room.to_string(),
+                    at: reading.timestamp,
+                    temperature: reading.temperature,
+                    motion: reading.motion,
+                    sound_level: reading.sound_level,
+                }
+            })
+            .collect();
         
-        AlertType::None
+        (alerts, shadow_hits)
     }
     
-    pub fn try_recv(&self) -> Option<SensorEvent> {
-        self.receiver.try_recv().ok()
+    /// Wait for the next reading or other line from the devices. `None` once
+    /// the tasks have stopped (e.g. they panicked) and everything they
+    /// received was taken.
+    pub async fn recv(&mut self) -> Option<SerialMessage> {
+        tokio::select! {
+            biased;
+            message = self.messages.recv() => {
+                message.or_else(|| self.queue.pop().map(SerialMessage::Reading))
+            }
+            event = self.queue.recv() => Some(SerialMessage::Reading(event)),
+        }
     }
 }
 
-/// Mock serial reader for testing without Arduino
-pub struct MockSerialReader {
-    receiver: Receiver<SensorEvent>,
-    _handle: thread::JoinHandle<()>,
+impl Drop for SerialReader {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+    }
 }
 
-impl MockSerialReader {
-    pub fn start() -> Self {
-        let (sender, receiver) = mpsc::channel();
-        
-        let handle = thread::spawn(move || {
-            use rand::Rng;
-            let mut rng = rand::thread_rng();
-            
-            loop {
-                let reading = SensorReading {
-                    temperature: 20.0 + rng.r#gen::<f32>() * 10.0,
-                    motion: rng.r#gen::<f32>() < 0.3,
-                    sound_level: if rng.r#gen::<f32>() < 0.1 {
-                        rng.gen_range(150..400)
-                    } else {
-                        rng.gen_range(10..50)
-                    },
-                    timestamp: Utc::now(),
-                };
-                
-                let alert = if reading.motion && reading.sound_level > 150 {
-                    AlertType::Fall
-                } else {
-                    AlertType::None
-                };
-                
-                let event = SensorEvent {
-                    id: None,
-                    reading,
-                    alert,
-                };
-                
-                if sender.send(event).is_err() {
-                    break;
-                }
-                
-                thread::sleep(Duration::from_secs(1));
-            }
-        });
-        
-        Self {
-            receiver,
-            _handle: handle,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_line_takes_known_levels_in_any_case() {
+        let line = DeviceLogLine::parse("LOG:ERROR: sensor bus stalled").unwrap();
+        assert_eq!(line.level, "error");
+        assert_eq!(line.message, "sensor bus stalled");
+    }
+
+    #[test]
+    fn log_line_without_known_level_is_all_message() {
+        let line = DeviceLogLine::parse("LOG:watchdog reset at 12:30").unwrap();
+        assert_eq!(line.level, "info");
+        assert_eq!(line.message, "watchdog reset at 12:30");
+
+        let line = DeviceLogLine::parse("LOG:booted").unwrap();
+        assert_eq!(line.level, "info");
+        assert_eq!(line.message, "booted");
+    }
+
+    #[test]
+    fn device_log_level_rejects_unknown_levels() {
+        assert_eq!(device_log_level(" Warn "), Some("warn"));
+        assert_eq!(device_log_level("informational"), None);
+        assert_eq!(device_log_level(""), None);
+    }
+
+    /// Event `n`, told apart by its sound level
+    fn event(n: i32, alerts: AlertSet) -> SensorEvent {
+        SensorEvent {
+            id: None,
+            seq: None,
+            room: "room-101".to_string(),
+            device: None,
+            patient_id: None,
+            reading: SensorReading {
+                sound_level: n,
+                timestamp: Utc::now(),
+                ..Default::default()
+            },
+            alerts,
         }
     }
-    
-    pub fn try_recv(&self) -> Option<SensorEvent> {
-        self.receiver.try_recv().ok()
+
+    fn drain(queue: &ReadingQueue) -> Vec<i32> {
+        std::iter::from_fn(|| queue.pop()).map(|e| e.reading.sound_level).collect()
+    }
+
+    #[test]
+    fn queue_bounds_a_run_of_alerts_keeping_its_ends() {
+        let queue = ReadingQueue::new(10);
+        for n in 0..1000 {
+            queue.push(event(n, AlertSet::from(AlertType::Inactivity)));
+        }
+
+        assert_eq!(queue.snapshot().overflow_dropped_readings, 990);
+        let kept = drain(&queue);
+        assert_eq!(kept.len(), 10);
+        assert_eq!(kept.first(), Some(&0));
+        assert_eq!(kept.last(), Some(&999));
+    }
+
+    #[test]
+    fn queue_drops_readings_without_alerts_before_alert_onsets() {
+        let queue = ReadingQueue::new(4);
+        queue.push(event(0, AlertSet::new()));
+        queue.push(event(1, AlertSet::from(AlertType::Inactivity)));
+        queue.push(event(2, AlertSet::from(AlertType::Inactivity)));
+        queue.push(event(3, AlertSet::from(AlertType::Inactivity)));
+        queue.push(event(4, AlertSet::from(AlertType::Fall)));
+        queue.push(event(5, AlertSet::new()));
+        queue.push(event(6, AlertSet::from(AlertType::Fall)));
+
+        // 0 makes room for 4, 5 is dropped itself, 2 continues the run of 1
+        assert_eq!(drain(&queue), vec![1, 3, 4, 6]);
+        assert_eq!(queue.snapshot().overflow_dropped_readings, 3);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn mock_reader_reads_with_poisoned_settings() {
+        let settings = Arc::new(RwLock::new(MonitorSettings {
+            inactivity_seconds: 300,
+            sound_threshold: 200,
+        }));
+        let poisoner = Arc::clone(&settings);
+        std::thread::spawn(move || {
+            let _settings = poisoner.write().unwrap();
+            panic!("poison the settings");
+        })
+        .join()
+        .unwrap_err();
+        assert!(settings.is_poisoned());
+
+        let limits = AlertLimits {
+            vitals: VitalLimits::default(),
+            ventilation: None,
+            impact: None,
+            motion_source: MotionSource::default(),
+        };
+        let mut reader = SerialReader::mock(
+            "room-101".to_string(),
+            settings,
+            Arc::new(RwLock::new(RuleSet::default())),
+            FeatureFlags::default(),
+            limits,
+            Arc::new(ReadingQueue::new(10)),
+        );
+        let message = tokio::time::timeout(Duration::from_secs(5), reader.recv()).await.unwrap();
+        assert!(matches!(message, Some(SerialMessage::Reading(_))));
+    }
+}
@@ -0,0 +1,135 @@
+//! Supervision of long-running background tasks
+//!
+//! A supervised task is restarted with exponential backoff when it panics or
+//! returns an error, so e.g. a poisoned lock in the ingest loop doesn't leave
+//! the HTTP server serving stale data. Restarts are counted for `/api/health`
+//! and repeated failures raise an ops alert.
+
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Backoff before the first restart, doubled per consecutive failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A run this long counts as healthy and resets the backoff
+const STABLE_RUN: Duration = Duration::from_secs(300);
+/// Consecutive failures before an ops alert is raised
+const OPS_ALERT_AFTER: u32 = 3;
+
+/// Restart counters of a supervised task
+#[derive(Debug, Default)]
+pub struct TaskHealth {
+    running: AtomicBool,
+    restarts: AtomicU64,
+    consecutive_failures: AtomicU32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskHealthSnapshot {
+    pub running: bool,
+    pub restarts: u64,
+    pub consecutive_failures: u32,
+}
+
+impl TaskHealth {
+    pub fn snapshot(&self) -> TaskHealthSnapshot {
+        TaskHealthSnapshot {
+            running: self.running.load(Ordering::Relaxed),
+            restarts: self.restarts.load(Ordering::Relaxed),
+            consecutive_failures: self.consecutive_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Running and not currently crash-looping
+    pub fn is_healthy(&self) -> bool {
+        self.running.load(Ordering::Relaxed) && self.consecutive_failures.load(Ordering::Relaxed) == 0
+    }
+}
+
+/// JSON body sent to the ops webhook
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OpsAlert<'a> {
+    task: &'a str,
+    consecutive_failures: u32,
+    restarts: u64,
+    last_error: &'a str,
+}
+
+/// Run the task built by `make` until it returns `Ok`, restarting it whenever
+/// it panics or returns an error.
+pub fn supervise<F, Fut>(
+    name: String,
+    health: Arc<TaskHealth>,
+    ops_webhook: Option<String>,
+    make: F,
+) where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let http = reqwest::Client::new();
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            health.running.store(true, Ordering::Relaxed);
+            let mut task = tokio::spawn(make());
+            let result = tokio::select! {
+                result = &mut task => result,
+                _ = tokio::time::sleep(STABLE_RUN) => {
+                    health.consecutive_failures.store(0, Ordering::Relaxed);
+                    backoff = INITIAL_BACKOFF;
+                    task.await
+                }
+            };
+            health.running.store(false, Ordering::Relaxed);
+
+            let reason = match result {
+                Ok(Ok(())) => {
+                    info!("Task '{}' finished", name);
+                    return;
+                }
+                Ok(Err(e)) => e,
+                Err(e) if e.is_panic() => {
+                    let panic = e.into_panic();
+                    panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string())
+                }
+                Err(e) => e.to_string(),
+            };
+
+            let restarts = health.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+            let failures = health.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+
+            error!("Task '{}' failed ({} in a row): {}; restarting in {:?}",
+                name, failures, reason, backoff);
+
+            if failures == OPS_ALERT_AFTER {
+                error!("OPS ALERT: task '{}' keeps failing: {}", name, reason);
+                if let Some(url) = &ops_webhook {
+                    let alert = OpsAlert {
+                        task: &name,
+                        consecutive_failures: failures,
+                        restarts,
+                        last_error: &reason,
+                    };
+                    let result = http.post(url).json(&alert).send().await;
+                    if let Err(e) = result.and_then(|r| r.error_for_status()) {
+                        warn!("Failed to send ops alert to {}: {}", url, e);
+                    }
+                }
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    });
+}
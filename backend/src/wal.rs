@@ -0,0 +1,100 @@
+//! Write-ahead log of incoming readings
+//!
+//! With `INGEST_WAL_DIR` set, each room's ingest loop appends its readings to
+//! `<dir>/<room>.wal` (JSON Lines) and syncs the file to disk before storing
+//! them in the database, and empties it once everything it holds is stored.
+//! Readings still in the log when the loop starts, because the monitor went
+//! down while the database was unavailable, are stored before any new ones,
+//! so a fall detected while the central database reboots isn't lost.
+//!
+//! A crash between storing readings and emptying the log leaves readings in
+//! it that are already stored. On recovery they are recognised by their
+//! room and timestamp and not stored twice.
+
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+use crate::fhir::SensorEvent;
+
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    /// Whether anything was appended since the log was last emptied
+    dirty: bool,
+}
+
+impl Wal {
+    /// Open the log of `room` in `dir`, creating both if needed
+    pub async fn open(dir: &Path, room: &str) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(dir).await?;
+        let name: String = room
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let path = dir.join(format!("{}.wal", name));
+        let file = OpenOptions::new().create(true).append(true).open(&path).await?;
+        let dirty = file.metadata().await?.len() > 0;
+        Ok(Self { path, file, dirty })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Readings in the log, oldest first. A last line cut short by a crash
+    /// is skipped.
+    pub async fn read(&self) -> std::io::Result<Vec<SensorEvent>> {
+        let text = tokio::fs::read_to_string(&self.path).await?;
+        let mut events = Vec::new();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(event) => events.push(event),
+                Err(e) => warn!("Skipping unreadable entry of {}: {}", self.path.display(), e),
+            }
+        }
+        Ok(events)
+    }
+
+    /// Append `events` and wait until they are on disk
+    pub async fn append(&mut self, events: &[SensorEvent]) -> std::io::Result<()> {
+        let mut lines = String::new();
+        for event in events {
+            lines.push_str(&serde_json::to_string(event)?);
+            lines.push('\n');
+        }
+        self.file.write_all(lines.as_bytes()).await?;
+        self.file.sync_data().await?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Replace the log's contents with `pending`, the readings not stored yet
+    pub async fn reset(&mut self, pending: impl IntoIterator<Item = &SensorEvent>) -> std::io::Result<()> {
+        let mut pending = pending.into_iter().peekable();
+        if pending.peek().is_none() {
+            if self.dirty {
+                self.file.set_len(0).await?;
+                self.file.sync_data().await?;
+                self.dirty = false;
+            }
+            return Ok(());
+        }
+
+        // Written aside and renamed over the log, so a crash leaves one or the other
+        let staging = self.path.with_extension("wal.tmp");
+        let mut lines = String::new();
+        for event in pending {
+            lines.push_str(&serde_json::to_string(event)?);
+            lines.push('\n');
+        }
+        let mut file = File::create(&staging).await?;
+        file.write_all(lines.as_bytes()).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&staging, &self.path).await?;
+        self.file = OpenOptions::new().append(true).open(&self.path).await?;
+        self.dirty = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! Wards and the ward-level live projection for corridor overview screens
+//!
+//! Rooms are grouped into wards through `/api/wards`. The grouping is kept in
+//! a `WardMap` shared by alert routing, reports and access control.
+//!
+//! The projection keeps the latest state of every room fed into it and
+//! renders a compact `WardSnapshot` frame, so an overview screen subscribes
+//! to one channel instead of one socket per room.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use crate::db::{Database, DbError};
+use crate::fhir::{AlertSeverity, AlertSet, SensorEvent};
+use crate::websocket::{LiveEvent, RoomState, RoomSummary, WsMessage};
+
+/// The ward of each room that is in one
+#[derive(Debug, Clone, Default)]
+pub struct WardMap {
+    rooms: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl WardMap {
+    pub async fn load(db: &Database) -> Result<Self, DbError> {
+        let map = Self::default();
+        map.reload(db).await?;
+        Ok(map)
+    }
+
+    /// Pick up changes made through the API
+    pub async fn reload(&self, db: &Database) -> Result<(), DbError> {
+        let rooms = db
+            .get_rooms()
+            .await?
+            .into_iter()
+            .filter_map(|room| Some((room.id, room.ward?)))
+            .collect();
+        *self.rooms.write().unwrap() = rooms;
+        Ok(())
+    }
+
+    pub fn ward_of(&self, room: &str) -> Option<String> {
+        self.rooms.read().unwrap().get(room).cloned()
+    }
+
+    pub fn contains(&self, ward: &str, room: &str) -> bool {
+        self.rooms.read().unwrap().get(room).is_some_and(|w| w == ward)
+    }
+}
+
+/// Rooms without a reading for this long are reported offline
+const OFFLINE_SECONDS: i64 = 30;
+/// Window for the per-room alert count
+const ALERT_WINDOW_MINUTES: i64 = 60;
+
+#[derive(Debug, Default)]
+struct RoomProjection {
+    last_seen: Option<DateTime<Utc>>,
+    last_motion: Option<DateTime<Utc>>,
+    alerts: AlertSet,
+    /// Onset times of alerts within the window, oldest first
+    alert_onsets: VecDeque<DateTime<Utc>>,
+}
+
+impl RoomProjection {
+    fn apply(&mut self, event: &SensorEvent) {
+        let ts = event.reading.timestamp;
+        for _ in event.alerts.iter().filter(|a| !self.alerts.contains(*a)) {
+            self.alert_onsets.push_back(ts);
+        }
+        if event.reading.motion {
+            self.last_motion = Some(ts);
+        }
+        self.alerts = event.alerts.clone();
+        self.last_seen = Some(ts);
+    }
+
+    fn summarize(&mut self, room: &str, now: DateTime<Utc>) -> RoomSummary {
+        let window_start = now - Duration::minutes(ALERT_WINDOW_MINUTES);
+        while self.alert_onsets.front().is_some_and(|t| *t < window_start) {
+            self.alert_onsets.pop_front();
+        }
+
+        let online = self
+            .last_seen
+            .is_some_and(|t| now - t <= Duration::seconds(OFFLINE_SECONDS));
+        let state = if !online {
+            RoomState::Offline
+        } else {
+            match self.alerts.severity() {
+                AlertSeverity::Critical => RoomState::Critical,
+                AlertSeverity::Warning => RoomState::Warning,
+                AlertSeverity::Info => RoomState::Normal,
+            }
+        };
+
+        RoomSummary {
+            room: room.to_string(),
+            state,
+            alert_count: self.alert_onsets.len() as u32,
+            last_activity_secs: self.last_motion.map(|t| (now - t).num_seconds().max(0)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WardProjection {
+    /// Ward named in snapshots of all rooms
+    ward: Option<String>,
+    wards: WardMap,
+    rooms: Arc<RwLock<BTreeMap<String, RoomProjection>>>,
+    /// How often `/ws/ward` subscribers receive a snapshot
+    pub frame_interval: std::time::Duration,
+}
+
+impl WardProjection {
+    pub fn new(ward: Option<String>, wards: WardMap, frame_interval: std::time::Duration) -> Self {
+        Self {
+            ward,
+            wards,
+            rooms: Arc::new(RwLock::new(BTreeMap::new())),
+            frame_interval,
+        }
+    }
+
+    /// Feed readings from the broadcast stream until it closes. `rooms` are
+    /// listed (as offline) before their first reading; readings from other
+    /// rooms add them as they arrive.
+    pub fn track(&self, rooms: &[String], mut rx: broadcast::Receiver<LiveEvent>) {
+        let projections = Arc::clone(&self.rooms);
+        {
+            let mut projections = projections.write().unwrap();
+            for room in rooms {
+                projections.entry(room.clone()).or_default();
+            }
+        }
+
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(LiveEvent::Reading(event)) => {
+                        projections.write().unwrap().entry(event.room.clone()).or_default().apply(&event);
+                    }
+                    Ok(LiveEvent::AlertResolved { .. } | LiveEvent::DeviceStatus { .. } | LiveEvent::DoorPassage { .. }) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Ward projection lagged, skipped {} events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Summary of the rooms in `ward`, or of all rooms
+    pub fn snapshot(&self, ward: Option<&str>) -> WsMessage {
+        let now = Utc::now();
+        let rooms = self
+            .rooms
+            .write()
+            .unwrap()
+            .iter_mut()
+            .filter(|(room, _)| ward.is_none_or(|ward| self.wards.contains(ward, room)))
+            .map(|(room, projection)| projection.summarize(room, now))
+            .collect();
+
+        WsMessage::WardSnapshot {
+            ward: ward.map(str::to_string).or_else(|| self.ward.clone()),
+            timestamp: now.to_rfc3339(),
+            rooms,
+        }
+    }
+}
@@ -1,48 +1,95 @@
 //! WebSocket module for real-time data streaming
+//!
+//! See `patient_monitor_types::ws` for the protocol and its negotiation.
 
+use actix_web::http::header;
 use actix_web::{rt, web, Error, HttpRequest, HttpResponse};
 use actix_ws::Message;
-use chrono::Utc;
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast;
-use tracing::{debug, error, info};
-
-use crate::fhir::{AlertType, SensorEvent};
-
-#[derive(Debug, Clone, Serialize)]
-#[serde(tag = "type", rename_all = "camelCase")]
-pub enum WsMessage {
-    #[serde(rename_all = "camelCase")]
-    SensorReading {
-        temperature: f32,
-        motion: bool,
-        sound_level: i32,
-        timestamp: String,
-        alert: Option<String>,
-    },
-    #[serde(rename_all = "camelCase")]
-    Status {
-        connected: bool,
-        message: String,
-    },
-    Ping {
-        timestamp: String,
-    },
+use tracing::{debug, error, info, warn};
+
+use crate::access_log::AccessContext;
+use crate::announce::Announcer;
+use crate::api::AppState;
+use crate::db::{Alert, DoorPassage};
+use crate::fhir::{ResolutionReason, SensorEvent};
+use crate::flags::{FeatureFlags, WS_DELTAS};
+use crate::ward::WardProjection;
+use crate::ws_clients::{Channel, ClientHandle, ClientInfo, CloseReason};
+
+pub use patient_monitor_types::ws::{
+    negotiate, Capability, DeltaCodec, RoomState, RoomSummary, Subscription, WsMessage, WsRequest, PROTOCOL_VERSION,
+};
+
+/// Oldest point a client may ask to replay, relative to now
+const MAX_REPLAY_MINUTES: i64 = 60;
+/// Most readings sent for one resume request; the client resumes again
+/// from `lastSeq` for more
+const MAX_RESUME_READINGS: usize = 3600;
+
+/// Where a replay starts
+#[derive(Debug, Clone, Copy)]
+enum ReplayFrom {
+    /// Readings taken since a time
+    Time(DateTime<Utc>),
+    /// Readings stored after a change-feed position
+    Seq(i64),
 }
 
-impl From<&SensorEvent> for WsMessage {
-    fn from(event: &SensorEvent) -> Self {
-        WsMessage::SensorReading {
-            temperature: event.reading.temperature,
-            motion: event.reading.motion,
-            sound_level: event.reading.sound_level,
-            timestamp: event.reading.timestamp.to_rfc3339(),
-            alert: match event.alert {
-                AlertType::None => None,
-                AlertType::Fall => Some("FALL_DETECTED".to_string()),
-                AlertType::Inactivity => Some("INACTIVITY_ALERT".to_string()),
+/// What live clients and the notifier are sent, in the order it happened
+#[derive(Debug, Clone)]
+pub enum LiveEvent {
+    Reading(SensorEvent),
+    /// An alert was resolved by `reading`, or by staff if unset
+    AlertResolved { alert: Alert, reading: Option<SensorEvent> },
+    /// A room's serial device disconnected (with why) or reconnected
+    DeviceStatus { room: String, device: String, error: Option<String>, at: DateTime<Utc> },
+    /// Someone went through a room's door
+    DoorPassage { room: String, passage: DoorPassage },
+}
+
+impl LiveEvent {
+    pub fn room(&self) -> &str {
+        match self {
+            LiveEvent::Reading(event) => &event.room,
+            LiveEvent::AlertResolved { alert, .. } => &alert.room,
+            LiveEvent::DeviceStatus { room, .. } => room,
+            LiveEvent::DoorPassage { room, .. } => room,
+        }
+    }
+    
+    pub fn to_message(&self) -> WsMessage {
+        match self {
+            LiveEvent::Reading(event) => WsMessage::from(event),
+            LiveEvent::AlertResolved { alert, reading } => WsMessage::AlertResolved {
+                id: alert.id,
+                room: alert.room.clone(),
+                alert: alert.alert.code().to_string(),
+                alert_id: alert.reading_id,
+                resolved_at: alert.resolved_at.unwrap_or_else(Utc::now).to_rfc3339(),
+                reason: alert.resolution_reason.unwrap_or(ResolutionReason::Cleared),
+                observation_id: reading.as_ref().and_then(|r| r.id),
+                resolved_by: match alert.resolution_reason {
+                    Some(ResolutionReason::Acknowledged) => alert.acknowledged_by.clone(),
+                    _ => None,
+                },
+            },
+            LiveEvent::DeviceStatus { room, device, error, at } => WsMessage::DeviceStatus {
+                room: room.clone(),
+                device: device.clone(),
+                connected: error.is_none(),
+                timestamp: at.to_rfc3339(),
+                error: error.clone(),
+            },
+            LiveEvent::DoorPassage { room, passage } => WsMessage::DoorPassage {
+                room: room.clone(),
+                kind: passage.kind,
+                opened_at: passage.opened_at.to_rfc3339(),
+                closed_at: passage.closed_at.to_rfc3339(),
             },
         }
     }
@@ -50,7 +97,7 @@ impl From<&SensorEvent> for WsMessage {
 
 #[derive(Clone)]
 pub struct SensorBroadcaster {
-    sender: broadcast::Sender<SensorEvent>,
+    sender: broadcast::Sender<LiveEvent>,
 }
 
 impl SensorBroadcaster {
@@ -59,33 +106,146 @@ impl SensorBroadcaster {
         Self { sender }
     }
     
-    pub fn subscribe(&self) -> broadcast::Receiver<SensorEvent> {
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveEvent> {
         self.sender.subscribe()
     }
     
-    pub fn broadcast(&self, event: SensorEvent) {
+    pub fn broadcast(&self, event: LiveEvent) {
         let _ = self.sender.send(event);
     }
 }
 
+/// Who is connecting, for the connection statistics
+fn client_info(req: &HttpRequest, access: &AccessContext, channel: Channel) -> ClientInfo {
+    ClientInfo {
+        channel,
+        // Behind a reverse proxy this is the address it forwarded
+        ip: req.connection_info().realip_remote_addr().map(String::from),
+        principal: access.principal.clone(),
+        user_agent: req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from),
+    }
+}
+
+/// Sends messages to one client in the form it negotiated
+struct Outbox {
+    session: actix_ws::Session,
+    capabilities: Vec<Capability>,
+    deltas: DeltaCodec,
+    /// Whether delta frames are rolled out to each room
+    flags: FeatureFlags,
+    stats: ClientHandle,
+    /// Feed the user stored for themselves
+    subscription: Subscription,
+}
+
+impl Outbox {
+    fn new(session: actix_ws::Session, flags: FeatureFlags, stats: ClientHandle, subscription: Subscription) -> Self {
+        Self { session, capabilities: Vec::new(), deltas: DeltaCodec::new(), flags, stats, subscription }
+    }
+    
+    fn has(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+    
+    /// Whether the client wants this message at all
+    fn wants(&self, msg: &WsMessage) -> bool {
+        !(self.has(Capability::AlertsOnly)
+            && matches!(msg, WsMessage::SensorReading { alerts, .. } if alerts.is_empty()))
+            && self.subscription.wants(msg)
+    }
+    
+    async fn send(&mut self, msg: WsMessage) -> Result<(), actix_ws::Closed> {
+        if !self.wants(&msg) {
+            return Ok(());
+        }
+        let msg = match &msg {
+            WsMessage::SensorReading { room, .. } if self.has(Capability::Deltas) => {
+                if self.flags.enabled(WS_DELTAS, room) {
+                    self.deltas.encode(msg)
+                } else {
+                    // Start over from a full reading once it is switched on
+                    self.deltas.forget(room);
+                    msg
+                }
+            }
+            _ => msg,
+        };
+        
+        if self.has(Capability::Msgpack) {
+            match rmp_serde::to_vec_named(&msg) {
+                Ok(bytes) => self.session.binary(bytes).await?,
+                Err(e) => {
+                    error!("Failed to encode WebSocket message: {}", e);
+                    return Ok(());
+                }
+            }
+        } else {
+            match serde_json::to_string(&msg) {
+                Ok(json) => self.session.text(json).await?,
+                Err(_) => return Ok(()),
+            }
+        }
+        self.stats.sent();
+        Ok(())
+    }
+    
+    /// Answer `hello` with a JSON `welcome`, then switch to what was agreed
+    async fn hello(&mut self, version: u32, requested: &[Capability]) -> Result<(), actix_ws::Closed> {
+        let (version, capabilities) = negotiate(version, requested);
+        info!("WebSocket client negotiated version {} with {:?}", version, capabilities);
+        
+        let welcome = WsMessage::Welcome { version, capabilities: capabilities.clone() };
+        if let Ok(json) = serde_json::to_string(&welcome) {
+            self.session.text(json).await?;
+            self.stats.sent();
+        }
+        self.capabilities = capabilities;
+        self.deltas = DeltaCodec::new();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    /// Only stream readings from this room
+    pub room: Option<String>,
+}
+
 pub async fn ws_handler(
     req: HttpRequest,
     stream: web::Payload,
+    query: web::Query<WsQuery>,
     broadcaster: web::Data<Arc<SensorBroadcaster>>,
+    state: web::Data<AppState>,
+    access: AccessContext,
 ) -> Result<HttpResponse, Error> {
-    let (response, mut session, mut stream) = actix_ws::handle(&req, stream)?;
+    let (response, session, mut stream) = actix_ws::handle(&req, stream)?;
+    let room = query.into_inner().room;
+    let stats = state.ws_clients.connect(client_info(&req, &access, Channel::Readings));
     
-    info!("New WebSocket connection established");
+    info!("New WebSocket connection {} established (room: {})", stats.id(), room.as_deref().unwrap_or("all"));
     
     let mut rx = broadcaster.subscribe();
     
-    let welcome = WsMessage::Status {
+    let subscription = match access.principal.as_deref() {
+        Some(principal) => state.db.get_ws_subscription(principal).await.unwrap_or_else(|e| {
+            warn!("Streaming everything to {}, failed to load their subscription: {}", principal, e);
+            None
+        }),
+        None => None,
+    };
+    
+    let mut outbox = Outbox::new(session, state.flags.clone(), stats, subscription.unwrap_or_default());
+    let status = WsMessage::Status {
         connected: true,
         message: "Connected to Smart Patient Monitor".to_string(),
+        version: PROTOCOL_VERSION,
     };
-    if let Ok(json) = serde_json::to_string(&welcome) {
-        let _ = session.text(json).await;
-    }
+    let _ = outbox.send(status).await;
     
     rt::spawn(async move {
         let mut heartbeat_interval = tokio::time::interval(Duration::from_secs(30));
@@ -94,29 +254,61 @@ pub async fn ws_handler(
             tokio::select! {
                 Some(msg) = stream.recv() => {
                     match msg {
-                        Ok(Message::Ping(bytes)) => {
-                            if session.pong(&bytes).await.is_err() {
+                        Ok(Message::Ping(bytes)) if outbox.session.pong(&bytes).await.is_err() => {
+                            outbox.stats.close(CloseReason::SendFailed);
+                            break;
+                        }
+                        Ok(Message::Text(text)) => {
+                            let result = match serde_json::from_str::<WsRequest>(&text) {
+                                Ok(WsRequest::Hello { version, capabilities }) => {
+                                    outbox.hello(version, &capabilities).await
+                                }
+                                Ok(WsRequest::Replay { since }) => {
+                                    replay(&mut outbox, &state, ReplayFrom::Time(since), room.as_deref()).await
+                                }
+                                Ok(WsRequest::Resume { after }) => {
+                                    replay(&mut outbox, &state, ReplayFrom::Seq(after), room.as_deref()).await
+                                }
+                                Err(e) => {
+                                    debug!("Ignoring WebSocket message: {}", e);
+                                    Ok(())
+                                }
+                            };
+                            if result.is_err() {
+                                outbox.stats.close(CloseReason::SendFailed);
                                 break;
                             }
                         }
                         Ok(Message::Close(_)) => {
-                            info!("WebSocket closed");
+                            info!("WebSocket {} closed", outbox.stats.id());
+                            outbox.stats.close(CloseReason::Client);
                             break;
                         }
                         Err(e) => {
                             error!("WebSocket error: {}", e);
+                            outbox.stats.close(CloseReason::Error);
                             break;
                         }
                         _ => {}
                     }
                 }
                 
-                Ok(event) = rx.recv() => {
-                    let msg = WsMessage::from(&event);
-                    if let Ok(json) = serde_json::to_string(&msg) {
-                        if session.text(json).await.is_err() {
-                            break;
+                event = rx.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            warn!("WebSocket {} fell behind and missed {} readings", outbox.stats.id(), missed);
+                            outbox.stats.lagged(missed);
+                            continue;
                         }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    if room.as_ref().is_some_and(|room| room != event.room()) {
+                        continue;
+                    }
+                    if outbox.send(event.to_message()).await.is_err() {
+                        outbox.stats.close(CloseReason::SendFailed);
+                        break;
                     }
                 }
                 
@@ -124,10 +316,146 @@ pub async fn ws_handler(
                     let ping = WsMessage::Ping {
                         timestamp: Utc::now().to_rfc3339(),
                     };
-                    if let Ok(json) = serde_json::to_string(&ping) {
+                    if outbox.send(ping).await.is_err() {
+                        outbox.stats.close(CloseReason::SendFailed);
+                        break;
+                    }
+                }
+            }
+        }
+        
+        let _ = outbox.session.close(None).await;
+    });
+    
+    Ok(response)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WardQuery {
+    /// Only summarize the rooms in this ward
+    pub ward: Option<String>,
+}
+
+/// Ward overview channel: a `wardSnapshot` frame on connect and then every
+/// `frame_interval`. Incoming messages other than ping/close are ignored.
+pub async fn ward_ws_handler(
+    req: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<WardQuery>,
+    projection: web::Data<WardProjection>,
+    state: web::Data<AppState>,
+    access: AccessContext,
+) -> Result<HttpResponse, Error> {
+    let (response, mut session, mut stream) = actix_ws::handle(&req, stream)?;
+    let ward = query.into_inner().ward;
+    let stats = state.ws_clients.connect(client_info(&req, &access, Channel::Ward));
+    
+    info!("New ward WebSocket connection {} established (ward: {})", stats.id(), ward.as_deref().unwrap_or("all"));
+    
+    rt::spawn(async move {
+        let mut frame_interval = tokio::time::interval(projection.frame_interval);
+        
+        loop {
+            tokio::select! {
+                Some(msg) = stream.recv() => {
+                    match msg {
+                        Ok(Message::Ping(bytes)) if session.pong(&bytes).await.is_err() => {
+                            stats.close(CloseReason::SendFailed);
+                            break;
+                        }
+                        Ok(Message::Close(_)) => {
+                            info!("Ward WebSocket {} closed", stats.id());
+                            stats.close(CloseReason::Client);
+                            break;
+                        }
+                        Err(e) => {
+                            error!("WebSocket error: {}", e);
+                            stats.close(CloseReason::Error);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                
+                _ = frame_interval.tick() => {
+                    if let Ok(json) = serde_json::to_string(&projection.snapshot(ward.as_deref())) {
+                        if session.text(json).await.is_err() {
+                            stats.close(CloseReason::SendFailed);
+                            break;
+                        }
+                        stats.sent();
+                    }
+                }
+            }
+        }
+        
+        let _ = session.close(None).await;
+    });
+    
+    Ok(response)
+}
+
+/// Announcement channel: an `announcement` frame for each alert an
+/// `announce` route without a URL has spoken, of one ward if `ward` is given.
+/// Incoming messages other than ping/close are ignored.
+pub async fn announcements_ws_handler(
+    req: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<WardQuery>,
+    announcer: web::Data<Announcer>,
+    state: web::Data<AppState>,
+    access: AccessContext,
+) -> Result<HttpResponse, Error> {
+    let (response, mut session, mut stream) = actix_ws::handle(&req, stream)?;
+    let ward = query.into_inner().ward;
+    let stats = state.ws_clients.connect(client_info(&req, &access, Channel::Announcements));
+    let mut announcements = announcer.subscribe();
+    
+    info!("New announcement WebSocket connection {} established (ward: {})", stats.id(), ward.as_deref().unwrap_or("all"));
+    
+    rt::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(msg) = stream.recv() => {
+                    match msg {
+                        Ok(Message::Ping(bytes)) if session.pong(&bytes).await.is_err() => {
+                            stats.close(CloseReason::SendFailed);
+                            break;
+                        }
+                        Ok(Message::Close(_)) => {
+                            info!("Announcement WebSocket {} closed", stats.id());
+                            stats.close(CloseReason::Client);
+                            break;
+                        }
+                        Err(e) => {
+                            error!("WebSocket error: {}", e);
+                            stats.close(CloseReason::Error);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                
+                received = announcements.recv() => {
+                    let announcement = match received {
+                        Ok(announcement) => announcement,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("Announcement WebSocket {} lagged, skipped {} announcements", stats.id(), skipped);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    if let (Some(wanted), WsMessage::Announcement { ward, .. }) = (&ward, &announcement) {
+                        if ward.as_ref() != Some(wanted) {
+                            continue;
+                        }
+                    }
+                    if let Ok(json) = serde_json::to_string(&announcement) {
                         if session.text(json).await.is_err() {
+                            stats.close(CloseReason::SendFailed);
                             break;
                         }
+                        stats.sent();
                     }
                 }
             }
@@ -137,4 +465,53 @@ pub async fn ws_handler(
     });
     
     Ok(response)
-}
\ No newline at end of file
+}
+
+/// Stream the readings `from` asks for (oldest first), marked as replay,
+/// followed by a `replayComplete` message. Live events queue up in the
+/// broadcast receiver meanwhile and are delivered afterwards.
+async fn replay(
+    outbox: &mut Outbox,
+    state: &AppState,
+    from: ReplayFrom,
+    room: Option<&str>,
+) -> Result<(), actix_ws::Closed> {
+    let end = Utc::now();
+    let (start, result) = match from {
+        ReplayFrom::Time(since) => {
+            let start = since.max(end - chrono::Duration::minutes(MAX_REPLAY_MINUTES));
+            let result = state.db.get_readings_in_range(start, end, room, None, None, None).await;
+            (Some(start), result.map(|events| events.into_iter().rev().collect()))
+        }
+        ReplayFrom::Seq(after) => (None, state.db.get_readings_after(after, MAX_RESUME_READINGS, room, None).await),
+    };
+    let events: Vec<SensorEvent> = result.unwrap_or_else(|e| {
+        error!("Failed to load replay: {}", e);
+        Vec::new()
+    });
+    let start = start
+        .or_else(|| events.first().map(|e| e.reading.timestamp))
+        .unwrap_or(end);
+    
+    let mut count = 0;
+    for event in &events {
+        let mut msg = WsMessage::from(event);
+        if let WsMessage::SensorReading { replay, .. } = &mut msg {
+            *replay = true;
+        }
+        if outbox.wants(&msg) {
+            count += 1;
+            outbox.send(msg).await?;
+        }
+    }
+    
+    let done = WsMessage::ReplayComplete {
+        since: start.to_rfc3339(),
+        count,
+        last_seq: events.last().and_then(|e| e.seq),
+    };
+    outbox.send(done).await?;
+    
+    debug!("Replayed {} readings ({:?})", count, from);
+    Ok(())
+}
@@ -0,0 +1,275 @@
+//! WebSocket client statistics
+//!
+//! Every WebSocket connection is tracked from connect to disconnect: the
+//! client's address and principal, how many messages it was sent, how often
+//! it fell behind the live stream and missed readings, and how it ended.
+//! Closed connections are kept in memory for a day, so a display that keeps
+//! reconnecting stands out in `GET /api/admin/ws-clients`. With
+//! `WS_STATS_PERSIST_SECS` set they are also stored in the `ws_sessions`
+//! table, which is purged with the device logs.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+
+use crate::db::{Database, WsSession};
+
+/// How long closed connections are listed
+const KEEP_CLOSED_HOURS: i64 = 24;
+/// Most closed connections kept in memory; the oldest are forgotten first
+const MAX_CLOSED: usize = 1000;
+
+/// WebSocket endpoint a client is connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// `/ws`, live readings
+    Readings,
+    /// `/ws/ward`, ward overview frames
+    Ward,
+    /// `/ws/announcements`, alert announcements for overhead paging
+    Announcements,
+}
+
+impl Channel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Channel::Readings => "readings",
+            Channel::Ward => "ward",
+            Channel::Announcements => "announcements",
+        }
+    }
+}
+
+/// How a connection ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The client sent a close frame
+    Client,
+    /// The connection broke, e.g. the client vanished without closing
+    Error,
+    /// A message could not be sent to the client
+    SendFailed,
+    /// The server ended the connection
+    Server,
+}
+
+impl CloseReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::Client => "client",
+            CloseReason::Error => "error",
+            CloseReason::SendFailed => "send_failed",
+            CloseReason::Server => "server",
+        }
+    }
+
+    /// Whether the connection dropped instead of being closed
+    fn is_drop(reason: &str) -> bool {
+        reason == CloseReason::Error.as_str() || reason == CloseReason::SendFailed.as_str()
+    }
+}
+
+/// Who connected, taken from the upgrade request
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub channel: Channel,
+    pub ip: Option<String>,
+    pub principal: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug)]
+struct Client {
+    id: String,
+    info: ClientInfo,
+    connected_at: DateTime<Utc>,
+    messages_sent: AtomicU64,
+    lag_events: AtomicU64,
+    missed_messages: AtomicU64,
+    closed: Mutex<Option<(DateTime<Utc>, CloseReason)>>,
+    persisted: AtomicBool,
+}
+
+impl Client {
+    fn closed(&self) -> Option<(DateTime<Utc>, CloseReason)> {
+        *self.closed.lock().unwrap()
+    }
+
+    fn session(&self) -> WsSession {
+        let closed = self.closed();
+        WsSession {
+            id: self.id.clone(),
+            channel: self.info.channel.as_str().to_string(),
+            ip: self.info.ip.clone(),
+            principal: self.info.principal.clone(),
+            user_agent: self.info.user_agent.clone(),
+            connected_at: self.connected_at,
+            disconnected_at: closed.map(|(at, _)| at),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            lag_events: self.lag_events.load(Ordering::Relaxed),
+            missed_messages: self.missed_messages.load(Ordering::Relaxed),
+            close_reason: closed.map(|(_, reason)| reason.as_str().to_string()),
+        }
+    }
+}
+
+/// Statistics of one connection, updated by its handler. Dropping the handle
+/// marks the connection closed if `close` wasn't called.
+pub struct ClientHandle {
+    client: Arc<Client>,
+}
+
+impl ClientHandle {
+    pub fn id(&self) -> &str {
+        &self.client.id
+    }
+
+    pub fn sent(&self) {
+        self.client.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The client fell behind the broadcast and `missed` readings were skipped
+    pub fn lagged(&self, missed: u64) {
+        self.client.lag_events.fetch_add(1, Ordering::Relaxed);
+        self.client.missed_messages.fetch_add(missed, Ordering::Relaxed);
+    }
+
+    pub fn close(&self, reason: CloseReason) {
+        self.client.closed.lock().unwrap().get_or_insert((Utc::now(), reason));
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.close(CloseReason::Server);
+    }
+}
+
+/// Connections from one address and principal
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerSummary {
+    pub ip: Option<String>,
+    pub principal: Option<String>,
+    pub connections: u64,
+    /// Connections open now
+    pub connected: u64,
+    /// Connections that broke instead of being closed
+    pub drops: u64,
+    pub last_connected_at: DateTime<Utc>,
+}
+
+/// Open and recently closed WebSocket connections. Shared between the
+/// WebSocket handlers and the API.
+#[derive(Debug, Clone, Default)]
+pub struct WsClients {
+    clients: Arc<Mutex<VecDeque<Arc<Client>>>>,
+}
+
+impl WsClients {
+    pub fn connect(&self, info: ClientInfo) -> ClientHandle {
+        let client = Arc::new(Client {
+            id: uuid::Uuid::new_v4().to_string(),
+            info,
+            connected_at: Utc::now(),
+            messages_sent: AtomicU64::new(0),
+            lag_events: AtomicU64::new(0),
+            missed_messages: AtomicU64::new(0),
+            closed: Mutex::new(None),
+            persisted: AtomicBool::new(false),
+        });
+
+        let mut clients = self.clients.lock().unwrap();
+        prune(&mut clients, Utc::now());
+        clients.push_back(Arc::clone(&client));
+        ClientHandle { client }
+    }
+
+    /// Open connections and those closed in the last day, newest first
+    pub fn sessions(&self) -> Vec<WsSession> {
+        let mut clients = self.clients.lock().unwrap();
+        prune(&mut clients, Utc::now());
+        clients.iter().rev().map(|c| c.session()).collect()
+    }
+
+    /// Closed connections not stored in the database yet
+    fn unpersisted(&self) -> Vec<Arc<Client>> {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| !c.persisted.load(Ordering::Relaxed) && c.closed().is_some())
+            .cloned()
+            .collect()
+    }
+}
+
+/// Forget connections closed too long ago, and the oldest closed ones
+/// beyond `MAX_CLOSED`
+fn prune(clients: &mut VecDeque<Arc<Client>>, now: DateTime<Utc>) {
+    let cutoff = now - Duration::hours(KEEP_CLOSED_HOURS);
+    clients.retain(|c| c.closed().is_none_or(|(at, _)| at >= cutoff));
+
+    let mut excess = clients.iter().filter(|c| c.closed().is_some()).count().saturating_sub(MAX_CLOSED);
+    clients.retain(|c| {
+        let forget = excess > 0 && c.closed().is_some();
+        if forget {
+            excess -= 1;
+        }
+        !forget
+    });
+}
+
+/// Connections grouped by address and principal, most connections first
+pub fn peers(sessions: &[WsSession]) -> Vec<PeerSummary> {
+    let mut peers: HashMap<(Option<&str>, Option<&str>), PeerSummary> = HashMap::new();
+    for session in sessions {
+        let peer = peers
+            .entry((session.ip.as_deref(), session.principal.as_deref()))
+            .or_insert_with(|| PeerSummary {
+                ip: session.ip.clone(),
+                principal: session.principal.clone(),
+                connections: 0,
+                connected: 0,
+                drops: 0,
+                last_connected_at: session.connected_at,
+            });
+        peer.connections += 1;
+        peer.connected += session.disconnected_at.is_none() as u64;
+        peer.drops += session.close_reason.as_deref().is_some_and(CloseReason::is_drop) as u64;
+        peer.last_connected_at = peer.last_connected_at.max(session.connected_at);
+    }
+
+    let mut peers: Vec<_> = peers.into_values().collect();
+    peers.sort_by(|a, b| b.connections.cmp(&a.connections).then(b.last_connected_at.cmp(&a.last_connected_at)));
+    peers
+}
+
+/// Store closed connections every `interval`
+pub fn spawn_persistence(db: Database, clients: WsClients, interval: std::time::Duration) {
+    info!("Persisting WebSocket connection statistics every {}s", interval.as_secs());
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let closed = clients.unpersisted();
+            if closed.is_empty() {
+                continue;
+            }
+
+            let sessions: Vec<WsSession> = closed.iter().map(|c| c.session()).collect();
+            match db.insert_ws_sessions(&sessions).await {
+                Ok(()) => {
+                    for client in &closed {
+                        client.persisted.store(true, Ordering::Relaxed);
+                    }
+                }
+                Err(e) => error!("Failed to store {} WebSocket sessions: {}", sessions.len(), e),
+            }
+        }
+    });
+}
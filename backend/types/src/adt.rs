@@ -0,0 +1,341 @@
+//! Admit, discharge and transfer (ADT) events
+//!
+//! The hospital's patient administration system announces admissions,
+//! transfers and discharges as HL7 v2 ADT messages or as FHIR Encounter
+//! resources. Both are read into an `AdtEvent`, which binds the patient to
+//! the room they are in or ends their monitoring.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::fhir::{FhirEncounter, Gender, Patient};
+
+/// What an ADT event does to the patient's room
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AdtAction {
+    /// The patient is now in the event's location, after an admission or a
+    /// transfer, or a cancelled transfer or discharge
+    Assign,
+    /// The patient left, after a discharge or a cancelled admission
+    Discharge,
+    /// Only the patient's details changed
+    Update,
+}
+
+/// Where the patient administration system puts a patient
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdtLocation {
+    /// Ward or nursing unit, e.g. `3W`
+    pub point_of_care: String,
+    pub room: String,
+    pub bed: String,
+}
+
+impl AdtLocation {
+    /// Names the location can be mapped to a monitored room by, most
+    /// specific first: `3W^301^A`, `3W^301` and `301`
+    pub fn keys(&self) -> Vec<String> {
+        let parts = [self.point_of_care.as_str(), self.room.as_str(), self.bed.as_str()];
+        let mut keys: Vec<String> = (2..=3)
+            .rev()
+            .filter(|n| parts[..*n].iter().all(|p| !p.is_empty()))
+            .map(|n| parts[..n].join("^"))
+            .collect();
+        if !self.room.is_empty() {
+            keys.push(self.room.clone());
+        }
+        keys
+    }
+}
+
+impl fmt::Display for AdtLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = [self.point_of_care.as_str(), self.room.as_str(), self.bed.as_str()];
+        let len = parts.iter().rposition(|p| !p.is_empty()).map_or(0, |i| i + 1);
+        write!(f, "{}", parts[..len].join("^"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AdtEvent {
+    pub action: AdtAction,
+    /// The patient's details as far as the event names them; an empty
+    /// family name if it names none. `room` is left unset.
+    pub patient: Patient,
+    /// Where the patient is now, for `Assign`
+    pub location: Option<AdtLocation>,
+    /// Message control ID or resource id, for the log
+    pub source_id: String,
+}
+
+// ============================================================================
+// HL7 V2
+// ============================================================================
+
+/// HL7 v2 message split into segments and fields
+struct Hl7Message<'a> {
+    segments: Vec<Vec<&'a str>>,
+    field: char,
+    component: char,
+    repetition: char,
+    escape: char,
+    subcomponent: char,
+}
+
+impl<'a> Hl7Message<'a> {
+    fn parse(message: &'a str) -> Result<Self, String> {
+        let message = message.trim_matches(|c: char| c == '\x0b' || c == '\x1c' || c.is_whitespace());
+        let field = match message.strip_prefix("MSH").and_then(|rest| rest.chars().next()) {
+            Some(c) if c.is_ascii_punctuation() => c,
+            _ => return Err("Not an HL7 message: it must start with an MSH segment".to_string()),
+        };
+
+        let segments: Vec<Vec<&str>> = message
+            .split(['\r', '\n'])
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.split(field).collect())
+            .collect();
+        let mut encoding = segments[0].get(1).copied().unwrap_or("").chars();
+
+        Ok(Self {
+            segments,
+            field,
+            component: encoding.next().unwrap_or('^'),
+            repetition: encoding.next().unwrap_or('~'),
+            escape: encoding.next().unwrap_or('\\'),
+            subcomponent: encoding.next().unwrap_or('&'),
+        })
+    }
+
+    /// Field `n` of the first `segment`, numbered as in the HL7 standard
+    fn field(&self, segment: &str, n: usize) -> &'a str {
+        // The field separator itself is MSH-1
+        let index = if segment == "MSH" { n - 1 } else { n };
+        self.segments
+            .iter()
+            .find(|s| s[0] == segment)
+            .and_then(|s| s.get(index).copied())
+            .unwrap_or("")
+    }
+
+    /// Component `c` of field `n` of the first `segment`, from its first
+    /// repetition, with escape sequences resolved
+    fn component(&self, segment: &str, n: usize, c: usize) -> String {
+        let value = self.field(segment, n)
+            .split(self.repetition)
+            .next()
+            .and_then(|r| r.split(self.component).nth(c - 1))
+            .and_then(|c| c.split(self.subcomponent).next())
+            .unwrap_or("");
+        self.unescape(value).trim().to_string()
+    }
+
+    /// Resolve the delimiter escapes (`\F\`, `\S\`, ...), dropping others
+    /// such as formatting or hex data
+    fn unescape(&self, value: &str) -> String {
+        value
+            .split(self.escape)
+            .enumerate()
+            .map(|(i, part)| match (i % 2, part) {
+                (0, text) => text.to_string(),
+                (_, "F") => self.field.to_string(),
+                (_, "S") => self.component.to_string(),
+                (_, "R") => self.repetition.to_string(),
+                (_, "E") => self.escape.to_string(),
+                (_, "T") => self.subcomponent.to_string(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+}
+
+fn hl7_gender(code: &str) -> Option<Gender> {
+    match code {
+        "M" => Some(Gender::Male),
+        "F" => Some(Gender::Female),
+        "O" | "A" => Some(Gender::Other),
+        "U" | "N" => Some(Gender::Unknown),
+        _ => None,
+    }
+}
+
+/// Read an HL7 v2 ADT message. Returns `None` for trigger events that don't
+/// move a patient or change their details, e.g. a pre-admission.
+///
+/// Admissions (A01), transfers (A02), cancelled transfers (A12) and
+/// cancelled discharges (A13) assign the patient to the location in PV1-3;
+/// discharges (A03) and cancelled admissions (A11) discharge them; patient
+/// updates (A08) only change their details. The patient is PID-3.
+pub fn parse_hl7_adt(message: &str) -> Result<Option<AdtEvent>, String> {
+    let message = Hl7Message::parse(message)?;
+    let message_type = message.component("MSH", 9, 1);
+    if message_type != "ADT" {
+        return Err(format!("Expected an ADT message, got '{}'", message_type));
+    }
+    // Older senders name the trigger event in EVN only
+    let mut trigger = message.component("MSH", 9, 2);
+    if trigger.is_empty() {
+        trigger = message.component("EVN", 1, 1);
+    }
+    let action = match trigger.as_str() {
+        "A01" | "A02" | "A12" | "A13" => AdtAction::Assign,
+        "A03" | "A11" => AdtAction::Discharge,
+        "A08" => AdtAction::Update,
+        _ => return Ok(None),
+    };
+
+    let id = message.component("PID", 3, 1);
+    if id.is_empty() {
+        return Err(format!("ADT^{} without a patient identifier (PID-3)", trigger));
+    }
+    let given_names = [message.component("PID", 5, 2), message.component("PID", 5, 3)]
+        .into_iter()
+        .filter(|n| !n.is_empty())
+        .collect();
+    let birth_date = message.component("PID", 7, 1);
+    let patient = Patient {
+        id,
+        family_name: message.component("PID", 5, 1),
+        given_names,
+        birth_date: birth_date.get(..8).and_then(|d| NaiveDate::parse_from_str(d, "%Y%m%d").ok()),
+        gender: hl7_gender(&message.component("PID", 8, 1)),
+        room: None,
+    };
+
+    let location = AdtLocation {
+        point_of_care: message.component("PV1", 3, 1),
+        room: message.component("PV1", 3, 2),
+        bed: message.component("PV1", 3, 3),
+    };
+    if action == AdtAction::Assign && location.room.is_empty() {
+        return Err(format!("ADT^{} without an assigned room (PV1-3)", trigger));
+    }
+
+    Ok(Some(AdtEvent {
+        action,
+        patient,
+        location: Some(location).filter(|l| !l.room.is_empty()),
+        source_id: message.component("MSH", 10, 1),
+    }))
+}
+
+/// Acknowledgement code of an HL7 ACK
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hl7AckCode {
+    /// Accepted, including messages that needed no action
+    Accept,
+    /// The message can't be processed; sending it again won't help
+    Error,
+    /// Not processed for now, e.g. while the database is down; the sender
+    /// should try again
+    Reject,
+}
+
+impl Hl7AckCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Hl7AckCode::Accept => "AA",
+            Hl7AckCode::Error => "AE",
+            Hl7AckCode::Reject => "AR",
+        }
+    }
+}
+
+/// ACK answering `message`, echoing its control ID, with `text` explaining
+/// an error. Works for messages that couldn't be read, too.
+pub fn hl7_ack(message: &str, code: Hl7AckCode, text: &str, now: DateTime<Utc>) -> String {
+    let original = Hl7Message::parse(message).ok();
+    let field = |segment: &str, n: usize, c: usize| {
+        original.as_ref().map(|m| m.component(segment, n, c)).unwrap_or_default()
+    };
+    let escape = |value: &str| {
+        value
+            .replace('\\', "\\E\\")
+            .replace('|', "\\F\\")
+            .replace('^', "\\S\\")
+            .replace('~', "\\R\\")
+            .replace('&', "\\T\\")
+    };
+    let control_id = field("MSH", 10, 1);
+    let version = Some(field("MSH", 12, 1)).filter(|v| !v.is_empty()).unwrap_or_else(|| "2.5".to_string());
+
+    format!(
+        "MSH|^~\\&|PatientMonitor||{}|{}|{}||ACK^{}|ACK{}|P|{}\rMSA|{}|{}|{}\r",
+        escape(&field("MSH", 3, 1)),
+        escape(&field("MSH", 4, 1)),
+        now.format("%Y%m%d%H%M%S"),
+        escape(&field("MSH", 9, 2)),
+        now.timestamp_millis(),
+        escape(&version),
+        code.as_str(),
+        escape(&control_id),
+        escape(text),
+    )
+}
+
+// ============================================================================
+// FHIR ENCOUNTER
+// ============================================================================
+
+impl FhirEncounter {
+    /// The ADT event the encounter's status stands for. Returns `None` for
+    /// encounters that haven't started or are on hold, e.g. `planned` or
+    /// `onleave`.
+    ///
+    /// The patient is the `Patient/<id>` subject; its display, e.g.
+    /// `Doe, Jane`, names them. An active encounter assigns them to its
+    /// `active` location, or else its last one, whose `Location/<id>` is
+    /// the room.
+    pub fn to_adt(&self) -> Result<Option<AdtEvent>, String> {
+        if self.resource_type != "Encounter" {
+            return Err(format!("Expected an Encounter, got a {}", self.resource_type));
+        }
+        let action = match self.status.as_str() {
+            "in-progress" | "arrived" => AdtAction::Assign,
+            // `discharged` and `completed` are the R5 names of `finished`
+            "finished" | "discharged" | "completed" | "cancelled" | "entered-in-error" => AdtAction::Discharge,
+            _ => return Ok(None),
+        };
+
+        let id = self.subject
+            .as_ref()
+            .and_then(|s| s.reference.strip_prefix("Patient/"))
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| format!("Encounter {} has no Patient subject", self.id))?;
+        let display = self.subject.as_ref().and_then(|s| s.display.as_deref()).unwrap_or("");
+        let (family_name, given_names) = match display.split_once(',') {
+            Some((family, given)) => (family, given.split_whitespace().map(str::to_string).collect()),
+            None => (display, Vec::new()),
+        };
+        let patient = Patient {
+            id: id.to_string(),
+            family_name: family_name.trim().to_string(),
+            given_names,
+            birth_date: None,
+            gender: None,
+            room: None,
+        };
+
+        let location = self.location
+            .iter()
+            .find(|l| l.status.as_deref() == Some("active"))
+            .or(self.location.last())
+            .map(|l| AdtLocation {
+                room: l.location.reference.rsplit('/').next().unwrap_or("").to_string(),
+                ..AdtLocation::default()
+            })
+            .filter(|l| !l.room.is_empty());
+        if action == AdtAction::Assign && location.is_none() {
+            return Err(format!("Encounter {} is {} without a location", self.id, self.status));
+        }
+
+        Ok(Some(AdtEvent {
+            action,
+            patient,
+            location,
+            source_id: self.id.clone(),
+        }))
+    }
+}
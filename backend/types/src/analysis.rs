@@ -0,0 +1,1196 @@
+//! Measurement pipeline logic
+//!
+//! Pure functions shared by the server and the test suite, so both exercise
+//! the same code:
+//!
+//! - Built-in fall and inactivity alerts, and vital-sign alerts.
+//! - Falls from an impact followed by stillness.
+//! - Sensors stuck on one value (flatline).
+//! - Stale air from CO2 staying high.
+//! - Temperature cross-checks between a room's sensor nodes.
+//! - Alert resolution policies and the motion source they go by.
+//! - Activity: activity scores, still periods, night awakenings and bed exits.
+//! - Fall-risk scoring.
+//! - Night light sums.
+//! - Room states and door passages.
+//! - Chart downsampling (LTTB).
+//! - Runs of identical readings merged by compaction.
+//! - The sound matrix of several rooms.
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::api::{
+    DoorPassage, FallRiskFactors, MonitorSettings, NightLight, PassageKind, PatientState, StatePeriod, StillPeriod,
+};
+use crate::fhir::{AlertSet, AlertType, ResolutionReason, SensorReading};
+
+/// Built-in alerts raised by `reading`.
+///
+/// A fall is motion together with a sound above the threshold; inactivity is
+/// no motion for longer than the configured number of seconds. Both limits
+/// are exclusive. Custom alert rules are evaluated by the server on top.
+pub fn detect_alerts(
+    reading: &SensorReading,
+    settings: &MonitorSettings,
+    seconds_since_motion: u64,
+) -> AlertSet {
+    let mut alerts = AlertSet::new();
+
+    if reading.motion && reading.sound_level > settings.sound_threshold {
+        alerts.insert(AlertType::Fall);
+    }
+
+    if seconds_since_motion > settings.inactivity_seconds {
+        alerts.insert(AlertType::Inactivity);
+    }
+
+    alerts
+}
+
+/// Limits of the vital signs from a pulse oximeter, see `detect_vital_alerts`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VitalLimits {
+    /// Heart rate above which tachycardia is raised, in beats per minute
+    pub tachycardia_bpm: i32,
+    /// Oxygen saturation below which low SpO2 is raised, in percent
+    pub low_spo2_percent: i32,
+}
+
+impl Default for VitalLimits {
+    fn default() -> Self {
+        Self { tachycardia_bpm: 120, low_spo2_percent: 90 }
+    }
+}
+
+/// Alerts raised by the vital signs of `reading`, if it has any. Both
+/// limits are exclusive.
+pub fn detect_vital_alerts(reading: &SensorReading, limits: &VitalLimits) -> AlertSet {
+    let mut alerts = AlertSet::new();
+    if reading.heart_rate.is_some_and(|hr| hr > limits.tachycardia_bpm) {
+        alerts.insert(AlertType::Tachycardia);
+    }
+    if reading.spo2.is_some_and(|spo2| spo2 < limits.low_spo2_percent) {
+        alerts.insert(AlertType::LowSpo2);
+    }
+    alerts
+}
+
+/// Seconds after motion in which a loud sound still counts as a fall for
+/// `detect_fall_after_motion`
+pub const FALL_MOTION_WINDOW_SECS: u64 = 3;
+
+/// Whether `reading` is a fall by the newer detector: a sound above the
+/// threshold with motion in the same reading or up to
+/// `FALL_MOTION_WINDOW_SECS` before, since the impact often follows the
+/// movement that led to it.
+pub fn detect_fall_after_motion(
+    reading: &SensorReading,
+    settings: &MonitorSettings,
+    seconds_since_motion: u64,
+) -> bool {
+    reading.sound_level > settings.sound_threshold
+        && (reading.motion || seconds_since_motion <= FALL_MOTION_WINDOW_SECS)
+}
+
+/// When an impact counts as a fall, see `ImpactFallDetector`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImpactLimit {
+    /// Peak acceleration from which a reading is an impact, in g
+    pub impact_g: f32,
+    /// Seconds the patient has to stay still after the impact
+    pub still_seconds: u32,
+}
+
+impl Default for ImpactLimit {
+    fn default() -> Self {
+        Self { impact_g: 2.5, still_seconds: 10 }
+    }
+}
+
+/// Deviation from 1 g, gravity alone, up to which an accelerometer counts
+/// as still
+pub const STILL_TOLERANCE_G: f32 = 0.15;
+
+/// Detects a fall from an accelerometer worn by the patient or on the bed
+/// frame: an impact followed by stillness for the limit's seconds. Unlike a
+/// fall by motion and a loud sound, this catches a patient who falls quietly
+/// and doesn't get up.
+///
+/// Readings are pushed as they arrive, oldest first. A reading is still
+/// without PIR motion and with an acceleration, if it has one, within
+/// `STILL_TOLERANCE_G` of 1 g. A reading that isn't, or a pause of more than
+/// five minutes between readings, drops the impact; a further impact starts
+/// the wait over.
+#[derive(Debug, Clone)]
+pub struct ImpactFallDetector {
+    limit: ImpactLimit,
+    /// Reading with the impact the stillness is counted from
+    impact_at: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+}
+
+impl ImpactFallDetector {
+    pub fn new(limit: ImpactLimit) -> Self {
+        Self { limit, impact_at: None, last: None }
+    }
+
+    /// Add a reading and return whether it completes a fall: true once per
+    /// impact, for the reading the patient has been still long enough by
+    pub fn push(&mut self, reading: &SensorReading) -> bool {
+        let at = reading.timestamp;
+        if self.last.is_some_and(|last| (at - last).num_seconds() > FLATLINE_MAX_GAP_SECS) {
+            self.impact_at = None;
+        }
+        self.last = Some(at);
+
+        if reading.acceleration.is_some_and(|g| g >= self.limit.impact_g) {
+            self.impact_at = Some(at);
+            return false;
+        }
+        let Some(impact_at) = self.impact_at else {
+            return false;
+        };
+        let still = !reading.motion
+            && reading.acceleration.is_none_or(|g| (g - 1.0).abs() <= STILL_TOLERANCE_G);
+        if !still {
+            self.impact_at = None;
+            return false;
+        }
+        if at - impact_at >= Duration::seconds(self.limit.still_seconds as i64) {
+            self.impact_at = None;
+            return true;
+        }
+        false
+    }
+}
+
+/// Sensors whose readings count as motion for inactivity and for resolving
+/// alerts once motion resumes. A PIR sensor misses the breathing-level
+/// movement of a sleeping patient, which a 24 GHz presence radar sees. Falls
+/// are always detected from the PIR sensor's motion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MotionSource {
+    /// The PIR sensor only
+    #[default]
+    Pir,
+    /// The radar only, or the PIR sensor on boards without one
+    Radar,
+    /// Either sensor
+    Either,
+}
+
+impl std::str::FromStr for MotionSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pir" => Ok(MotionSource::Pir),
+            "radar" => Ok(MotionSource::Radar),
+            "either" => Ok(MotionSource::Either),
+            other => Err(format!("Unknown motion source {}, expected pir, radar or either", other)),
+        }
+    }
+}
+
+impl MotionSource {
+    /// Whether `reading` counts as motion
+    pub fn moved(self, reading: &SensorReading) -> bool {
+        match self {
+            MotionSource::Pir => reading.motion,
+            MotionSource::Radar => reading.presence.unwrap_or(reading.motion),
+            MotionSource::Either => reading.motion || reading.presence == Some(true),
+        }
+    }
+}
+
+/// How open alerts of a type are resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoResolve {
+    /// By the first reading that no longer raises it
+    Clear,
+    /// By the first reading with motion that no longer raises it
+    Motion,
+    /// Only by staff acknowledging it
+    Ack,
+}
+
+impl std::str::FromStr for AutoResolve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clear" => Ok(AutoResolve::Clear),
+            "motion" => Ok(AutoResolve::Motion),
+            "ack" => Ok(AutoResolve::Ack),
+            other => Err(format!("Unknown resolution policy {}, expected clear, motion or ack", other)),
+        }
+    }
+}
+
+/// Auto-resolution policy of each alert type. By default inactivity
+/// resolves once motion resumes, a fall only when staff acknowledge it, and
+/// sensor faults, vital signs, ventilation and calibration alerts once a
+/// reading no longer raises them. A reading without the vital sign or CO2 level, e.g. with the
+/// probe taken off, doesn't resolve its alert. Motion is as seen by the
+/// `motion_source`, by default the PIR sensor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolutionPolicies {
+    policies: BTreeMap<AlertType, AutoResolve>,
+    motion_source: MotionSource,
+}
+
+impl Default for ResolutionPolicies {
+    fn default() -> Self {
+        Self {
+            policies: BTreeMap::from([
+                (AlertType::Fall, AutoResolve::Ack),
+                (AlertType::Inactivity, AutoResolve::Motion),
+            ]),
+            motion_source: MotionSource::default(),
+        }
+    }
+}
+
+impl ResolutionPolicies {
+    /// The defaults overridden by `spec`, a comma-separated list of
+    /// `<alert type>=<clear|motion|ack>`, e.g. `fall=clear,sensor_fault=ack`.
+    /// `sensor_fault` stands for the faults of every channel.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut policies = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (alert, policy) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <alert type>=<policy>, got {}", entry))?;
+            let policy: AutoResolve = policy.trim().parse()?;
+            match alert.trim() {
+                "sensor_fault" => {
+                    for alert in AlertType::ALL.into_iter().filter(|a| matches!(a, AlertType::SensorFault(_))) {
+                        policies.policies.insert(alert, policy);
+                    }
+                }
+                alert => {
+                    policies.policies.insert(alert.parse()?, policy);
+                }
+            }
+        }
+        Ok(policies)
+    }
+
+    /// The same policies with motion as seen by `source`
+    pub fn with_motion_source(self, source: MotionSource) -> Self {
+        Self { motion_source: source, ..self }
+    }
+
+    pub fn policy(&self, alert: AlertType) -> AutoResolve {
+        self.policies.get(&alert).copied().unwrap_or(AutoResolve::Clear)
+    }
+
+    /// Whether `reading` has motion that resolves alerts waiting for it
+    pub fn moved(&self, reading: &SensorReading) -> bool {
+        self.motion_source.moved(reading)
+    }
+
+    /// Alert types whose open alerts `reading` resolves, given the `alerts`
+    /// it raised, and why
+    pub fn resolved_by(&self, reading: &SensorReading, alerts: &AlertSet) -> Vec<(AlertType, ResolutionReason)> {
+        AlertType::ALL
+            .into_iter()
+            .filter(|alert| !alerts.contains(*alert))
+            .filter(|alert| match alert {
+                AlertType::Tachycardia => reading.heart_rate.is_some(),
+                AlertType::LowSpo2 => reading.spo2.is_some(),
+                AlertType::Ventilation => reading.co2.is_some(),
+                _ => true,
+            })
+            .filter_map(|alert| match self.policy(alert) {
+                AutoResolve::Clear => Some((alert, ResolutionReason::Cleared)),
+                AutoResolve::Motion if self.moved(reading) => Some((alert, ResolutionReason::MotionResumed)),
+                AutoResolve::Motion | AutoResolve::Ack => None,
+            })
+            .collect()
+    }
+}
+
+/// Longest pause between samples that still counts as one continuous signal
+const FLATLINE_MAX_GAP_SECS: i64 = 300;
+
+/// Detects a flatlined sensor channel: a signal whose variance has stayed
+/// below `epsilon` for at least `window`.
+///
+/// Samples are pushed as they arrive, oldest first. A pause of more than five
+/// minutes between samples starts over, so values from before a device
+/// outage are never compared with values after it.
+#[derive(Debug, Clone)]
+pub struct FlatlineDetector {
+    window: Duration,
+    epsilon: f64,
+    samples: VecDeque<(DateTime<Utc>, f64)>,
+    sum: f64,
+    sum_sq: f64,
+}
+
+impl FlatlineDetector {
+    pub fn new(window: Duration, epsilon: f64) -> Self {
+        Self {
+            window,
+            epsilon,
+            samples: VecDeque::new(),
+            sum: 0.0,
+            sum_sq: 0.0,
+        }
+    }
+
+    /// Add a sample and return whether the channel is flat over the whole window
+    pub fn push(&mut self, at: DateTime<Utc>, value: f64) -> bool {
+        if self.samples.back().is_some_and(|(last, _)| (at - *last).num_seconds() > FLATLINE_MAX_GAP_SECS) {
+            self.samples.clear();
+            self.sum = 0.0;
+            self.sum_sq = 0.0;
+        }
+
+        self.samples.push_back((at, value));
+        self.sum += value;
+        self.sum_sq += value * value;
+
+        // Keep one sample from at or before the window start, so a fully
+        // covered window can be told apart from a short history
+        let start = at - self.window;
+        while self.samples.get(1).is_some_and(|(t, _)| *t <= start) {
+            if let Some((_, old)) = self.samples.pop_front() {
+                self.sum -= old;
+                self.sum_sq -= old * old;
+            }
+        }
+
+        self.samples.front().is_some_and(|(t, _)| *t <= start) && self.variance() < self.epsilon
+    }
+
+    /// Population variance of the retained samples
+    fn variance(&self) -> f64 {
+        let n = self.samples.len() as f64;
+        let mean = self.sum / n;
+        (self.sum_sq / n - mean * mean).max(0.0)
+    }
+}
+
+/// When a room needs airing, see `VentilationDetector`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VentilationLimit {
+    /// CO2 above which the air counts as stale, in ppm
+    pub co2_ppm: i32,
+    /// Minutes the CO2 has to stay above `co2_ppm` before ventilation is raised
+    pub minutes: u32,
+}
+
+impl Default for VentilationLimit {
+    fn default() -> Self {
+        Self { co2_ppm: 1400, minutes: 15 }
+    }
+}
+
+/// Detects stale air in a room: a CO2 level that has stayed above the limit
+/// for at least its minutes.
+///
+/// Readings are pushed as they arrive, oldest first. A reading at or below
+/// the limit, or a pause of more than five minutes between readings with a
+/// CO2 level, starts over. Readings without one, e.g. while the sensor warms
+/// up, change nothing.
+#[derive(Debug, Clone)]
+pub struct VentilationDetector {
+    limit: VentilationLimit,
+    /// First reading of the current run above the limit
+    above_since: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+}
+
+impl VentilationDetector {
+    pub fn new(limit: VentilationLimit) -> Self {
+        Self { limit, above_since: None, last: None }
+    }
+
+    /// Add a reading's CO2 level and return whether the room needs airing
+    pub fn push(&mut self, at: DateTime<Utc>, co2: Option<i32>) -> bool {
+        let Some(co2) = co2 else {
+            return false;
+        };
+        if self.last.is_some_and(|last| (at - last).num_seconds() > FLATLINE_MAX_GAP_SECS) {
+            self.above_since = None;
+        }
+        self.last = Some(at);
+
+        if co2 <= self.limit.co2_ppm {
+            self.above_since = None;
+            return false;
+        }
+        let since = *self.above_since.get_or_insert(at);
+        at - since >= Duration::minutes(self.limit.minutes as i64)
+    }
+}
+
+/// When a room's redundant temperature sensors count as out of calibration,
+/// see `TemperatureCrossCheck`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationLimit {
+    /// Difference between the sensors above which they disagree, in °C
+    pub delta_celsius: f32,
+    /// Minutes they have to disagree before calibration is raised
+    pub minutes: u32,
+}
+
+impl Default for CalibrationLimit {
+    fn default() -> Self {
+        Self { delta_celsius: 2.0, minutes: 30 }
+    }
+}
+
+/// A reading's temperature checked against the room's other sensors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrossCheck {
+    /// Largest difference to the other sensors' latest temperatures, in °C;
+    /// `None` without a recent reading from another sensor
+    pub disagreement: Option<f32>,
+    /// Temperature to store and analyse: the reading's own, unless the
+    /// sensors disagree and another's is closer to where they last agreed
+    pub temperature: f32,
+    /// Whether the sensors have disagreed for the limit's minutes
+    pub calibration_needed: bool,
+}
+
+/// Cross-validates the temperatures of a room's sensor nodes.
+///
+/// Each reading is compared with the latest temperature of every other
+/// node that reported in the last five minutes. While they are within the
+/// limit's delta, their mean is remembered as the temperature they agree on;
+/// once they drift apart, the one closest to it is taken to be the plausible
+/// one, since a failing sensor is the one that moved away.
+#[derive(Debug, Clone)]
+pub struct TemperatureCrossCheck {
+    limit: CalibrationLimit,
+    /// Latest temperature of each node, by device
+    latest: BTreeMap<String, (DateTime<Utc>, f32)>,
+    agreed: Option<f32>,
+    /// First reading of the current disagreement
+    diverged_since: Option<DateTime<Utc>>,
+    last: Option<CrossCheck>,
+}
+
+impl TemperatureCrossCheck {
+    pub fn new(limit: CalibrationLimit) -> Self {
+        Self { limit, latest: BTreeMap::new(), agreed: None, diverged_since: None, last: None }
+    }
+
+    /// Add a node's reading, oldest first
+    pub fn push(&mut self, device: &str, at: DateTime<Utc>, temperature: f32) -> CrossCheck {
+        self.latest.insert(device.to_string(), (at, temperature));
+        let others: Vec<f32> = self.latest
+            .iter()
+            .filter(|(d, (seen, _))| d.as_str() != device && (at - *seen).num_seconds() <= FLATLINE_MAX_GAP_SECS)
+            .map(|(_, (_, t))| *t)
+            .collect();
+        let disagreement = others.iter().map(|t| (temperature - t).abs()).reduce(f32::max);
+
+        let check = match disagreement {
+            Some(d) if d > self.limit.delta_celsius => {
+                let since = *self.diverged_since.get_or_insert(at);
+                let preferred = match self.agreed {
+                    Some(agreed) => std::iter::once(temperature)
+                        .chain(others)
+                        .min_by(|a, b| (a - agreed).abs().total_cmp(&(b - agreed).abs()))
+                        .unwrap_or(temperature),
+                    None => temperature,
+                };
+                CrossCheck {
+                    disagreement,
+                    temperature: preferred,
+                    calibration_needed: at - since >= Duration::minutes(self.limit.minutes as i64),
+                }
+            }
+            _ => {
+                self.diverged_since = None;
+                if !others.is_empty() {
+                    self.agreed = Some((temperature + others.iter().sum::<f32>()) / (others.len() + 1) as f32);
+                }
+                CrossCheck { disagreement, temperature, calibration_needed: false }
+            }
+        };
+        self.last = Some(check);
+        check
+    }
+
+    /// Disagreement as of the latest reading, see `CrossCheck`
+    pub fn disagreement(&self) -> Option<f32> {
+        self.last.and_then(|c| c.disagreement)
+    }
+
+    /// Whether the latest reading raised calibration
+    pub fn calibration_needed(&self) -> bool {
+        self.last.is_some_and(|c| c.calibration_needed)
+    }
+}
+
+/// Share of readings with motion, as a percentage (0 without readings)
+pub fn activity_score(motion_readings: u64, total_readings: u64) -> f64 {
+    if total_readings == 0 {
+        return 0.0;
+    }
+    (motion_readings as f64 / total_readings as f64) * 100.0
+}
+
+/// Activity level for a motion percentage (0-100)
+pub fn activity_level(activity_score: f64) -> &'static str {
+    match activity_score {
+        s if s < 20.0 => "deep_sleep",
+        s if s < 40.0 => "light_sleep",
+        s if s < 60.0 => "restless",
+        _ => "active",
+    }
+}
+
+/// Rest quality label shown on the dashboard for a motion percentage
+pub fn rest_quality(activity_score: f64) -> &'static str {
+    match activity_score {
+        s if s < 20.0 => "Excellent",
+        s if s < 40.0 => "Good",
+        s if s < 60.0 => "Fair",
+        _ => "Poor",
+    }
+}
+
+/// Durations of the runs without motion, in minutes.
+///
+/// `readings` are `(timestamp, motion)` pairs sorted oldest first. A run
+/// lasts from its first reading until the next reading with motion; one that
+/// hasn't ended by the last reading is counted up to `end`.
+pub fn still_periods<I>(readings: I, end: DateTime<Utc>) -> Vec<u64>
+where
+    I: IntoIterator<Item = (DateTime<Utc>, bool)>,
+{
+    let mut periods = Vec::new();
+    let mut current_still_start: Option<DateTime<Utc>> = None;
+
+    for (timestamp, motion) in readings {
+        if !motion {
+            if current_still_start.is_none() {
+                current_still_start = Some(timestamp);
+            }
+        } else if let Some(start_time) = current_still_start.take() {
+            periods.push(timestamp.signed_duration_since(start_time).num_minutes().max(0) as u64);
+        }
+    }
+
+    if let Some(start_time) = current_still_start {
+        periods.push(end.signed_duration_since(start_time).num_minutes().max(0) as u64);
+    }
+
+    periods
+}
+
+/// Longest run without motion in minutes, see `still_periods`
+pub fn longest_still_period<I>(readings: I, end: DateTime<Utc>) -> u64
+where
+    I: IntoIterator<Item = (DateTime<Utc>, bool)>,
+{
+    still_periods(readings, end).into_iter().max().unwrap_or(0)
+}
+
+/// The `n` longest of `periods`, longest first; of equally long ones the
+/// earliest first
+pub fn longest_still_periods(mut periods: Vec<StillPeriod>, n: usize) -> Vec<StillPeriod> {
+    periods.sort_by(|a, b| b.duration_mins.cmp(&a.duration_mins).then(a.start.cmp(&b.start)));
+    periods.truncate(n);
+    periods
+}
+
+/// Values at which each factor contributes its full weight to the fall-risk
+/// score: falls in the window, percent of restless night minutes and bed
+/// exits per night
+const FALL_RISK_FALLS_CAP: f64 = 3.0;
+const FALL_RISK_RESTLESSNESS_CAP: f64 = 30.0;
+const FALL_RISK_BED_EXITS_CAP: f64 = 4.0;
+
+/// Fall-risk score from 0 to 100.
+///
+/// Recent falls make up half the score, nighttime restlessness and bed-exit
+/// frequency a quarter each. Each factor grows linearly up to its cap.
+pub fn fall_risk_score(factors: &FallRiskFactors) -> f64 {
+    let part = |value: f64, cap: f64| (value / cap).clamp(0.0, 1.0);
+    let score = 50.0 * part(factors.falls as f64, FALL_RISK_FALLS_CAP)
+        + 25.0 * part(factors.night_restlessness, FALL_RISK_RESTLESSNESS_CAP)
+        + 25.0 * part(factors.bed_exits_per_night, FALL_RISK_BED_EXITS_CAP);
+    (score * 10.0).round() / 10.0
+}
+
+/// Risk level for a fall-risk score (0-100)
+pub fn fall_risk_level(score: f64) -> &'static str {
+    match score {
+        s if s < 30.0 => "low",
+        s if s < 60.0 => "moderate",
+        _ => "high",
+    }
+}
+
+/// Number of bed exits: minutes with motion that follow at least `min_rest`
+/// consecutive minutes without.
+///
+/// `minutes` are `(minute, motion)` pairs sorted oldest first, one per
+/// observed minute. A minute without readings interrupts the rest, so a
+/// gap in the data is not mistaken for lying still.
+pub fn count_bed_exits<I>(minutes: I, min_rest: u32) -> u64
+where
+    I: IntoIterator<Item = (DateTime<Utc>, bool)>,
+{
+    let mut exits = 0;
+    let mut rest = 0;
+    let mut previous: Option<DateTime<Utc>> = None;
+
+    for (minute, motion) in minutes {
+        if previous.is_some_and(|p| (minute - p).num_minutes() > 1) {
+            rest = 0;
+        }
+        if !motion {
+            rest += 1;
+        } else {
+            if rest >= min_rest {
+                exits += 1;
+            }
+            rest = 0;
+        }
+        previous = Some(minute);
+    }
+
+    exits
+}
+
+/// Stillness needed before and after a motion burst for it to count as a
+/// night-time awakening, in minutes
+const AWAKENING_MIN_STILL_MINS: i64 = 30;
+/// Bounds of a burst's length: shorter is turning over in bed, longer is
+/// being up rather than a trip out of bed, in minutes
+const AWAKENING_MIN_BURST_MINS: i64 = 1;
+const AWAKENING_MAX_BURST_MINS: i64 = 30;
+/// Stillness within a burst that doesn't end it, e.g. while in the bathroom
+/// out of the sensor's view, in minutes
+const AWAKENING_MAX_PAUSE_MINS: i64 = 10;
+/// Longest pause between readings that still counts as observed, in minutes
+const AWAKENING_MAX_GAP_MINS: i64 = 5;
+
+/// Number of night-time awakenings, i.e. likely bathroom trips: short bursts
+/// of motion with at least half an hour of stillness on either side.
+///
+/// `readings` are `(timestamp, motion)` pairs of one room sorted oldest
+/// first. Motion separated by up to ten still minutes is one burst, which
+/// counts if it lasts one to thirty minutes. A gap of more than five minutes
+/// without readings interrupts the stillness, so the patient must have been
+/// observed lying still.
+pub fn count_night_awakenings<I>(readings: I) -> u64
+where
+    I: IntoIterator<Item = (DateTime<Utc>, bool)>,
+{
+    let mut readings = readings.into_iter().peekable();
+    let mut awakenings = 0;
+
+    // Runs of motion or stillness of one observed stretch: (motion, start, end),
+    // where a run ends where the next begins
+    let mut runs: Vec<(bool, DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    while let Some((timestamp, motion)) = readings.next() {
+        match runs.last_mut() {
+            Some((m, _, end)) if *m == motion => *end = timestamp,
+            Some((_, _, end)) => {
+                *end = timestamp;
+                runs.push((motion, timestamp, timestamp));
+            }
+            None => runs.push((motion, timestamp, timestamp)),
+        }
+
+        // A short pause in the motion is part of the burst
+        if let [.., (true, _, _), (false, start, end), (true, _, _)] = runs[..] {
+            if (end - start).num_minutes() <= AWAKENING_MAX_PAUSE_MINS {
+                runs.truncate(runs.len() - 2);
+                if let Some((_, _, burst_end)) = runs.last_mut() {
+                    *burst_end = timestamp;
+                }
+            }
+        }
+
+        let stretch_ends = readings
+            .peek()
+            .is_none_or(|(next, _)| (*next - timestamp).num_minutes() > AWAKENING_MAX_GAP_MINS);
+        if stretch_ends {
+            awakenings += runs
+                .windows(3)
+                .filter(|w| {
+                    let minutes = |(_, start, end): (bool, DateTime<Utc>, DateTime<Utc>)| (end - start).num_minutes();
+                    let [before, burst, after] = [w[0], w[1], w[2]];
+                    burst.0
+                        && minutes(before) >= AWAKENING_MIN_STILL_MINS
+                        && minutes(after) >= AWAKENING_MIN_STILL_MINS
+                        && (AWAKENING_MIN_BURST_MINS..=AWAKENING_MAX_BURST_MINS).contains(&minutes(burst))
+                })
+                .count() as u64;
+            runs.clear();
+        }
+    }
+
+    awakenings
+}
+
+/// Ambient light from which the lights are taken to be on, in lux; a night
+/// light or light from the corridor stays below it
+pub const LIGHTS_ON_LUX: f32 = 50.0;
+
+/// Sums over the readings with a light level of a period, from which the
+/// night's light is summarized. The databases compute them with aggregates.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LightSums {
+    pub readings: u64,
+    /// Readings with the lights on, see `LIGHTS_ON_LUX`
+    pub lit_readings: u64,
+    pub motion_readings: u64,
+    pub lit_motion_readings: u64,
+    pub lux: f64,
+    pub lux_squared: f64,
+    /// Light summed over the readings with motion
+    pub motion_lux: f64,
+}
+
+impl LightSums {
+    pub fn add(&mut self, lux: f32, motion: bool) {
+        let lit = lux >= LIGHTS_ON_LUX;
+        let lux = lux as f64;
+        self.readings += 1;
+        self.lit_readings += lit as u64;
+        self.motion_readings += motion as u64;
+        self.lit_motion_readings += (lit && motion) as u64;
+        self.lux += lux;
+        self.lux_squared += lux * lux;
+        if motion {
+            self.motion_lux += lux;
+        }
+    }
+
+    /// Light over the period, `None` without readings with a light level.
+    ///
+    /// The correlation of light with restlessness is Pearson's between the
+    /// light level and motion (as 0 or 1) of the readings; it is left out
+    /// when either didn't change.
+    pub fn night_light(&self) -> Option<NightLight> {
+        if self.readings == 0 {
+            return None;
+        }
+        let n = self.readings as f64;
+        let dark_readings = self.readings - self.lit_readings;
+        let dark_motion = self.motion_readings - self.lit_motion_readings;
+        let percent = |part: u64, whole: u64| (whole > 0).then(|| (part as f64 / whole as f64 * 1000.0).round() / 10.0);
+
+        let motion = self.motion_readings as f64;
+        let covariance = n * self.motion_lux - self.lux * motion;
+        let variance = (n * self.lux_squared - self.lux * self.lux) * (n * motion - motion * motion);
+        let correlation = (variance > 0.0)
+            .then(|| (covariance / variance.sqrt()).clamp(-1.0, 1.0))
+            .map(|r| (r * 100.0).round() / 100.0);
+
+        Some(NightLight {
+            readings: self.readings,
+            lit_readings: self.lit_readings,
+            lights_on: self.lit_readings > 0,
+            lit_percent: percent(self.lit_readings, self.readings).unwrap_or(0.0),
+            avg_lux: (self.lux / n * 10.0).round() / 10.0,
+            lit_motion_percent: percent(self.lit_motion_readings, self.lit_readings),
+            dark_motion_percent: percent(dark_motion, dark_readings),
+            light_motion_correlation: correlation,
+        })
+    }
+}
+
+/// Stillness after which a patient without a bed sensor is taken to be in bed
+const IN_BED_AFTER_SECS: i64 = 5 * 60;
+/// Stillness with an empty bed after which the patient is taken to have left
+/// the room
+const OUT_OF_ROOM_AFTER_SECS: i64 = 10 * 60;
+
+/// Derives the coarse state of a room from its readings and, where fitted,
+/// its bed sensor.
+///
+/// An occupied bed is in bed, whatever the motion sensor sees. Otherwise
+/// motion or the bed being left is moving in the room; with an empty bed,
+/// ten minutes without motion is out of the room. Without a bed sensor, five
+/// minutes without motion counts as in bed, as leaving the room can't be told
+/// apart from lying still. A state entered after stillness starts from the
+/// last activity.
+#[derive(Debug, Clone, Default)]
+pub struct PatientStateTracker {
+    state: Option<(PatientState, DateTime<Utc>)>,
+    /// Time of the last motion or bed sensor change, or of the first reading
+    last_active: Option<DateTime<Utc>>,
+    /// `None` until the bed sensor reports
+    bed_occupied: Option<bool>,
+}
+
+impl PatientStateTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current state and when it started, `None` until one can be derived
+    pub fn state(&self) -> Option<(PatientState, DateTime<Utc>)> {
+        self.state
+    }
+
+    /// Add a reading; returns the new state and its start when it changes
+    pub fn reading(&mut self, at: DateTime<Utc>, motion: bool) -> Option<(PatientState, DateTime<Utc>)> {
+        if motion {
+            self.last_active = Some(at);
+        }
+        self.update(at, motion)
+    }
+
+    /// Add a bed sensor report; returns the new state and its start when it changes
+    pub fn bed(&mut self, at: DateTime<Utc>, occupied: bool) -> Option<(PatientState, DateTime<Utc>)> {
+        let changed = self.bed_occupied != Some(occupied);
+        self.bed_occupied = Some(occupied);
+        if changed {
+            self.last_active = Some(at);
+        }
+        self.update(at, changed)
+    }
+
+    fn update(&mut self, at: DateTime<Utc>, active: bool) -> Option<(PatientState, DateTime<Utc>)> {
+        let last_active = *self.last_active.get_or_insert(at);
+        let still_secs = (at - last_active).num_seconds();
+
+        let (state, start) = match self.bed_occupied {
+            Some(true) => (PatientState::InBed, at),
+            _ if active => (PatientState::Moving, at),
+            Some(false) if still_secs >= OUT_OF_ROOM_AFTER_SECS => (PatientState::OutOfRoom, last_active),
+            None if still_secs >= IN_BED_AFTER_SECS => (PatientState::InBed, last_active),
+            _ => return None,
+        };
+        if self.state.is_some_and(|(current, _)| current == state) {
+            return None;
+        }
+
+        // A state dated back to the last activity can't start before the one it ends
+        let start = self.state.map_or(start, |(_, since)| start.max(since));
+        self.state = Some((state, start));
+        self.state
+    }
+}
+
+/// Periods between `from` and `to` from state changes sorted oldest first,
+/// which should include the last change before `from`. Consecutive changes
+/// to the same state are merged; the last state lasts until `to`.
+pub fn state_periods(
+    changes: &[(PatientState, DateTime<Utc>)],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<StatePeriod> {
+    let mut periods: Vec<StatePeriod> = Vec::new();
+
+    for (i, (state, start)) in changes.iter().enumerate() {
+        let start = (*start).max(from);
+        let end = changes.get(i + 1).map_or(to, |(_, next)| (*next).min(to));
+        if start >= end {
+            continue;
+        }
+        match periods.last_mut() {
+            Some(last) if last.state == *state && last.end == start => last.end = end,
+            _ => periods.push(StatePeriod { state: *state, start, end }),
+        }
+    }
+
+    periods
+}
+
+/// Motion this close before a door opens or after it closes tells which way
+/// someone went through it
+pub const DOOR_PASSAGE_WINDOW_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy)]
+struct OpenDoor {
+    opened_at: DateTime<Utc>,
+    motion_before: bool,
+}
+
+/// Turns a room's door contact readings into entries and exits.
+///
+/// The door opening and closing again is a passage; motion in the room
+/// within `DOOR_PASSAGE_WINDOW_SECS` before it opened and after it closed
+/// tells which kind. Motion only after is someone entering, motion only
+/// before someone leaving. Motion while the door is open doesn't count, as
+/// whoever opens it is seen either way. An entry is known as soon as motion
+/// follows; an exit once the window passes still or the door opens again.
+#[derive(Debug, Clone, Default)]
+pub struct DoorTracker {
+    open: Option<OpenDoor>,
+    /// Closed door waiting for the window after it to pass
+    closed: Option<(OpenDoor, DateTime<Utc>)>,
+    last_motion: Option<DateTime<Utc>>,
+}
+
+impl DoorTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a reading; returns a passage once it can be classified. Readings
+    /// without a door contact value only count for their motion.
+    pub fn reading(&mut self, reading: &SensorReading) -> Option<DoorPassage> {
+        let at = reading.timestamp;
+        let window = Duration::seconds(DOOR_PASSAGE_WINDOW_SECS);
+
+        let mut passage = None;
+        if let Some((door, closed_at)) = self.closed {
+            let motion_after = reading.motion && at > closed_at;
+            if motion_after || at - closed_at >= window || reading.door_open == Some(true) {
+                let kind = match (door.motion_before, motion_after) {
+                    (false, true) => PassageKind::Entry,
+                    (true, false) => PassageKind::Exit,
+                    _ => PassageKind::Passage,
+                };
+                passage = Some(DoorPassage { kind, opened_at: door.opened_at, closed_at });
+                self.closed = None;
+            }
+        }
+
+        match reading.door_open {
+            Some(true) if self.open.is_none() => {
+                let motion_before = self.last_motion.is_some_and(|m| at - m <= window);
+                self.open = Some(OpenDoor { opened_at: at, motion_before });
+            }
+            Some(false) => {
+                if let Some(door) = self.open.take() {
+                    self.closed = Some((door, at));
+                }
+            }
+            _ => {}
+        }
+        if reading.motion {
+            self.last_motion = Some(at);
+        }
+
+        passage
+    }
+}
+
+/// Indices of the points kept when downsampling a series to `threshold`
+/// points with largest-triangle-three-buckets (LTTB).
+///
+/// `points` are `(x, y)` pairs sorted by `x`. The first and last points are
+/// always kept; of each bucket in between, the point forming the largest
+/// triangle with the point kept before and the average of the next bucket.
+/// Peaks, such as a fall's sound spike, survive where averaging would flatten
+/// them. A series of at most `threshold` points, or a threshold below 3, is
+/// kept whole.
+pub fn lttb(points: &[(f64, f64)], threshold: usize) -> Vec<usize> {
+    if threshold < 3 || points.len() <= threshold {
+        return (0..points.len()).collect();
+    }
+
+    // The points between the first and the last, split into `threshold - 2`
+    // buckets of at least one point
+    let buckets = threshold - 2;
+    let bucket_start = |i: usize| (i * (points.len() - 2) / buckets + 1).min(points.len() - 1);
+    let mut kept = Vec::with_capacity(threshold);
+    kept.push(0);
+
+    for bucket in 0..buckets {
+        let (start, end) = (bucket_start(bucket), bucket_start(bucket + 1));
+        // Average of the next bucket, or the last point for the last bucket
+        let next = &points[end..bucket_start(bucket + 2).max(end + 1).min(points.len())];
+        let avg_x = next.iter().map(|p| p.0).sum::<f64>() / next.len() as f64;
+        let avg_y = next.iter().map(|p| p.1).sum::<f64>() / next.len() as f64;
+
+        let (ax, ay) = points[kept[kept.len() - 1]];
+        let largest = (start..end).max_by(|&i, &j| {
+            let area = |k: usize| ((ax - avg_x) * (points[k].1 - ay) - (ax - points[k].0) * (avg_y - ay)).abs();
+            area(i).total_cmp(&area(j))
+        });
+        kept.extend(largest);
+    }
+
+    kept.push(points.len() - 1);
+    kept
+}
+
+/// A stored row of one room as considered for compaction: a reading, or an
+/// interval of identical readings merged by an earlier compaction
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactionRow {
+    pub id: i64,
+    pub start: DateTime<Utc>,
+    /// Time of the last reading of an interval; `start` for a reading
+    pub end: DateTime<Utc>,
+    /// Readings the row stands for
+    pub count: i64,
+    pub temperature: f32,
+    pub sound_level: i32,
+    pub humidity: Option<f32>,
+    pub door_open: Option<bool>,
+    pub light: Option<f32>,
+    pub co2: Option<i32>,
+    pub presence: Option<bool>,
+    pub acceleration: Option<f32>,
+    pub patient_id: Option<String>,
+    /// Whether the row may be merged: no motion, no alerts and no tags
+    pub mergeable: bool,
+}
+
+/// Consecutive rows to merge into the interval of the first, `id`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadingRun {
+    pub id: i64,
+    pub end: DateTime<Utc>,
+    pub count: i64,
+    /// Rows after the first, deleted once merged
+    pub merged: Vec<i64>,
+}
+
+/// Runs of consecutive mergeable rows of one room, sorted oldest first, with
+/// the same temperature, sound level, humidity, door contact, light level,
+/// CO2 level, radar presence, acceleration and patient.
+///
+/// A row further than `max_gap` from the one before starts a new run, so a
+/// device outage isn't filled with readings, as does one that would stretch
+/// the run beyond `max_span`. Rows left alone aren't returned.
+pub fn compaction_runs(rows: &[CompactionRow], max_gap: Duration, max_span: Duration) -> Vec<ReadingRun> {
+    let mut runs = Vec::new();
+    let mut current: Option<(&CompactionRow, ReadingRun)> = None;
+
+    for row in rows {
+        if let Some((first, run)) = &mut current {
+            let continues = row.mergeable
+                && row.temperature == first.temperature
+                && row.sound_level == first.sound_level
+                && row.humidity == first.humidity
+                && row.door_open == first.door_open
+                && row.light == first.light
+                && row.co2 == first.co2
+                && row.presence == first.presence
+                && row.acceleration == first.acceleration
+                && row.patient_id == first.patient_id
+                && row.start - run.end <= max_gap
+                && row.end - first.start <= max_span;
+            if continues {
+                run.end = row.end;
+                run.count += row.count;
+                run.merged.push(row.id);
+                continue;
+            }
+        }
+        runs.extend(current.take().map(|(_, run)| run).filter(|run| !run.merged.is_empty()));
+        if row.mergeable {
+            current = Some((row, ReadingRun { id: row.id, end: row.end, count: row.count, merged: Vec::new() }));
+        }
+    }
+    runs.extend(current.map(|(_, run)| run).filter(|run| !run.merged.is_empty()));
+
+    runs
+}
+
+/// How the sound levels of a room within one step of a `SoundMatrix` are
+/// summed up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatrixStat {
+    /// Mean level, rounded to one decimal
+    #[default]
+    Mean,
+    /// Loudest level, which keeps short noises such as a dropped tray
+    Max,
+}
+
+/// One step of a `SoundMatrix`: its start and a level per room, in the
+/// matrix's room order, `None` for rooms without a reading in the step
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixRow {
+    pub start: DateTime<Utc>,
+    pub levels: Vec<Option<f64>>,
+}
+
+/// Sound levels of several rooms aligned to common time steps, for
+/// correlating noise across a ward.
+///
+/// Readings are pushed oldest first, and each step is returned as soon as a
+/// later reading completes it, so only one step is held however long the
+/// window. Steps start at multiples of the step length; steps without any
+/// reading are returned too, with empty cells, so the rows are evenly spaced.
+#[derive(Debug, Clone)]
+pub struct SoundMatrix {
+    rooms: Vec<String>,
+    stat: MatrixStat,
+    step: Duration,
+    end: DateTime<Utc>,
+    /// Start of the step being summed up
+    current: DateTime<Utc>,
+    /// Sum, count and maximum of each room's levels in the current step
+    cells: Vec<(i64, u32, i32)>,
+}
+
+impl SoundMatrix {
+    /// Matrix of `rooms`, in that column order, of the steps from the one
+    /// `start` falls in up to `end`. `step` must be positive.
+    pub fn new(rooms: Vec<String>, start: DateTime<Utc>, end: DateTime<Utc>, step: Duration, stat: MatrixStat) -> Self {
+        let current = start.duration_trunc(step).unwrap_or(start);
+        let cells = vec![(0, 0, 0); rooms.len()];
+        SoundMatrix { rooms, stat, step, end, current, cells }
+    }
+
+    pub fn rooms(&self) -> &[String] {
+        &self.rooms
+    }
+
+    /// Number of rows the matrix will have
+    pub fn steps(&self) -> i64 {
+        let span = (self.end - self.current).num_milliseconds().max(0);
+        let step = self.step.num_milliseconds();
+        (span + step - 1) / step
+    }
+
+    /// Add a reading, returning the steps before it that it completed.
+    /// Readings of other rooms, outside the window or older than the step
+    /// being summed up are ignored.
+    pub fn push(&mut self, room: &str, timestamp: DateTime<Utc>, sound_level: i32) -> Vec<MatrixRow> {
+        let Some(column) = self.rooms.iter().position(|r| r == room) else {
+            return Vec::new();
+        };
+        if timestamp < self.current || timestamp >= self.end {
+            return Vec::new();
+        }
+        let mut rows = Vec::new();
+        while timestamp >= self.current + self.step {
+            rows.push(self.next_row());
+        }
+        let (sum, count, max) = &mut self.cells[column];
+        *sum += sound_level as i64;
+        *max = if *count == 0 { sound_level } else { (*max).max(sound_level) };
+        *count += 1;
+        rows
+    }
+
+    /// The remaining steps up to the end of the window
+    pub fn finish(mut self) -> Vec<MatrixRow> {
+        let mut rows = Vec::new();
+        while self.current < self.end {
+            rows.push(self.next_row());
+        }
+        rows
+    }
+
+    fn next_row(&mut self) -> MatrixRow {
+        let levels = self
+            .cells
+            .iter()
+            .map(|&(sum, count, max)| match self.stat {
+                _ if count == 0 => None,
+                MatrixStat::Mean => Some((sum as f64 / count as f64 * 10.0).round() / 10.0),
+                MatrixStat::Max => Some(max as f64),
+            })
+            .collect();
+        let row = MatrixRow { start: self.current, levels };
+        self.cells.fill((0, 0, 0));
+        self.current += self.step;
+        row
+    }
+}
@@ -0,0 +1,86 @@
+//! Spoken alert announcements
+//!
+//! Text for the ward's overhead paging system to read out with its
+//! text-to-speech engine. Room and ward IDs are spelled out a character at a
+//! time, digits as words and letters in the NATO alphabet, so `3W-301A` is
+//! read "three whiskey, three zero one alpha" rather than however the engine
+//! guesses at it.
+
+const DIGITS: [&str; 10] = ["zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine"];
+
+const LETTERS: [&str; 26] = [
+    "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliett", "kilo", "lima",
+    "mike", "november", "oscar", "papa", "quebec", "romeo", "sierra", "tango", "uniform", "victor", "whiskey",
+    "x-ray", "yankee", "zulu",
+];
+
+/// Phonetic spelling of a room or ward ID. A leading `room` is dropped, as
+/// the announcement says it; separators become pauses and other characters
+/// are skipped.
+pub fn spell(id: &str) -> String {
+    let id = id.trim();
+    let id = match id.get(..4) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("room") => &id[4..],
+        _ => id,
+    };
+    let mut groups: Vec<Vec<&str>> = vec![Vec::new()];
+    for c in id.chars() {
+        let word = match c.to_ascii_lowercase() {
+            d @ '0'..='9' => DIGITS[d as usize - '0' as usize],
+            l @ 'a'..='z' => LETTERS[l as usize - 'a' as usize],
+            '-' | '_' | '.' | '/' | ' ' => {
+                groups.push(Vec::new());
+                continue;
+            }
+            _ => continue,
+        };
+        groups.last_mut().expect("starts with a group").push(word);
+    }
+    groups
+        .iter()
+        .filter(|g| !g.is_empty())
+        .map(|g| g.join(" "))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// What an announcement says, with `{alert}`, `{room}` and `{ward}` standing
+/// for the alert's description and the spelled room and ward
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnouncementTemplate(String);
+
+impl Default for AnnouncementTemplate {
+    /// Said twice, as overhead pages are
+    fn default() -> Self {
+        AnnouncementTemplate("{alert}, room {room}. {alert}, room {room}.".to_string())
+    }
+}
+
+impl AnnouncementTemplate {
+    /// Check a template's placeholders
+    pub fn parse(template: &str) -> Result<Self, String> {
+        if template.trim().is_empty() {
+            return Err("Announcement template is empty".to_string());
+        }
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!("Unclosed placeholder in '{}'", template));
+            };
+            match &rest[start + 1..start + end] {
+                "alert" | "room" | "ward" => {}
+                other => return Err(format!("Unknown placeholder {{{}}}, expected {{alert}}, {{room}} or {{ward}}", other)),
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(AnnouncementTemplate(template.to_string()))
+    }
+
+    /// Text announcing `alert` (its description) in `room` of `ward`
+    pub fn render(&self, alert: &str, room: &str, ward: Option<&str>) -> String {
+        self.0
+            .replace("{alert}", alert)
+            .replace("{room}", &spell(room))
+            .replace("{ward}", &ward.map(spell).unwrap_or_default())
+    }
+}
@@ -0,0 +1,865 @@
+//! REST API request/response bodies
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::fhir::{AlertOutcome, AlertSeverity, AlertType, ManualObservation};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonitorSettings {
+    pub inactivity_seconds: u64,
+    pub sound_threshold: i32,
+}
+
+impl MonitorSettings {
+    /// Names of the settings, as in request and response bodies
+    pub const FIELDS: [&'static str; 2] = ["inactivity_seconds", "sound_threshold"];
+
+    /// Names of the settings whose value differs in `other`
+    pub fn changed_fields(&self, other: &MonitorSettings) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.inactivity_seconds != other.inactivity_seconds {
+            changed.push("inactivity_seconds");
+        }
+        if self.sound_threshold != other.sound_threshold {
+            changed.push("sound_threshold");
+        }
+        changed
+    }
+}
+
+/// Roles allowed to change each setting. Settings without an entry may be
+/// changed by anyone who can reach the settings endpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SettingsPermissions(BTreeMap<String, Vec<String>>);
+
+impl SettingsPermissions {
+    /// Parse `spec`, a comma-separated list of `<setting>=<role>|<role>...`,
+    /// e.g. `inactivity_seconds=charge_nurse|admin,sound_threshold=biomed`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut permissions = BTreeMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (field, roles) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <setting>=<roles>, got {}", entry))?;
+            let field = field.trim();
+            if !MonitorSettings::FIELDS.contains(&field) {
+                return Err(format!("Unknown setting: {}", field));
+            }
+            let roles: Vec<String> = roles.split('|').map(str::trim).filter(|r| !r.is_empty()).map(String::from).collect();
+            if roles.is_empty() {
+                return Err(format!("No roles given for {}", field));
+            }
+            permissions.insert(field.to_string(), roles);
+        }
+        Ok(Self(permissions))
+    }
+
+    /// Roles allowed to change `field`; `None` if anyone may
+    pub fn roles(&self, field: &str) -> Option<&[String]> {
+        self.0.get(field).map(Vec::as_slice)
+    }
+
+    /// Of the settings `fields`, those a principal with the roles for which
+    /// `has_role` holds may not change
+    pub fn denied<'a>(&self, fields: &[&'a str], has_role: impl Fn(&str) -> bool) -> Vec<&'a str> {
+        fields
+            .iter()
+            .filter(|field| self.roles(field).is_some_and(|roles| !roles.iter().any(|r| has_role(r))))
+            .copied()
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiError {
+    pub error: String,
+    pub message: String,
+    /// Correlation ID of the failed request, added by the server's middleware
+    #[serde(rename = "requestId", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(error: &str, msg: &str) -> Self {
+        Self { error: error.to_string(), message: msg.to_string(), request_id: None }
+    }
+    
+    pub fn not_found(msg: &str) -> Self {
+        Self::new("not_found", msg)
+    }
+    
+    pub fn internal_error(msg: &str) -> Self {
+        Self::new("internal_error", msg)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SummaryResponse {
+    pub total_readings: u64,
+    pub fall_alerts: u64,
+    pub inactivity_alerts: u64,
+    pub system_status: String,
+    pub last_updated: String,
+}
+
+/// Activity analysis for a time period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityAnalysis {
+    pub period_start: String,
+    pub period_end: String,
+    pub total_readings: u64,
+    pub motion_readings: u64,
+    pub activity_score: f64,
+    pub activity_level: String,
+    pub avg_temperature: f64,
+    pub avg_sound_level: f64,
+    pub max_sound_level: i32,
+    pub fall_alerts: u64,
+    pub longest_still_period_mins: u64,
+    #[serde(default)]
+    pub still_periods: StillPeriods,
+    /// The `TOP_STILL_PERIODS` longest still periods, longest first
+    #[serde(default)]
+    pub longest_still_periods: Vec<StillPeriod>,
+    /// Interpolated readings included in the totals (gap filling only)
+    #[serde(default)]
+    pub filled_readings: u64,
+    /// Short motion bursts between long still periods, i.e. likely bathroom
+    /// trips (sleep analysis only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub night_awakenings: Option<u64>,
+    /// Ambient light over the night, if a board in the room reports it
+    /// (sleep analysis only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub night_light: Option<NightLight>,
+}
+
+/// Ambient light over a night and how it went with the patient's
+/// restlessness, see `LightSums::night_light`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NightLight {
+    /// Readings with a light level
+    pub readings: u64,
+    /// Of those, readings with the lights on
+    pub lit_readings: u64,
+    /// Whether the lights were on at any point of the night
+    pub lights_on: bool,
+    pub lit_percent: f64,
+    pub avg_lux: f64,
+    /// Share of readings with motion while the lights were on, and while they
+    /// were off
+    pub lit_motion_percent: Option<f64>,
+    pub dark_motion_percent: Option<f64>,
+    /// Correlation of the light level with motion, from -1 to 1
+    pub light_motion_correlation: Option<f64>,
+}
+
+/// Lower bounds (minutes) of the still-period duration buckets after the
+/// first, which holds the periods shorter than 15 minutes
+pub const STILL_PERIOD_BUCKETS: [u64; 5] = [15, 30, 60, 120, 240];
+
+/// Number of still periods listed by an activity analysis
+pub const TOP_STILL_PERIODS: usize = 5;
+
+/// A run of readings without motion in one room, lasting from its first
+/// reading until the next reading with motion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StillPeriod {
+    pub room: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub duration_mins: u64,
+}
+
+/// Runs of readings without motion in an analysis period, by duration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StillPeriods {
+    pub count: u64,
+    /// Periods per duration bucket, shortest first
+    pub distribution: Vec<StillPeriodBucket>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StillPeriodBucket {
+    pub min_mins: u64,
+    /// Periods in the bucket are shorter than this; the last bucket is open
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_mins: Option<u64>,
+    pub count: u64,
+}
+
+impl StillPeriods {
+    /// Index of the bucket of a period lasting `mins` minutes
+    pub fn bucket_of(mins: u64) -> usize {
+        STILL_PERIOD_BUCKETS.iter().take_while(|bound| mins >= **bound).count()
+    }
+
+    /// Statistics from the number of periods per bucket, as indexed by
+    /// `bucket_of`
+    pub fn from_bucket_counts(counts: [u64; STILL_PERIOD_BUCKETS.len() + 1]) -> Self {
+        let distribution = counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| StillPeriodBucket {
+                min_mins: if i == 0 { 0 } else { STILL_PERIOD_BUCKETS[i - 1] },
+                max_mins: STILL_PERIOD_BUCKETS.get(i).copied(),
+                count,
+            })
+            .collect();
+        Self { count: counts.iter().sum(), distribution }
+    }
+
+    /// Statistics of periods lasting `durations` minutes
+    pub fn from_durations(durations: impl IntoIterator<Item = u64>) -> Self {
+        let mut counts = [0; STILL_PERIOD_BUCKETS.len() + 1];
+        for mins in durations {
+            counts[Self::bucket_of(mins)] += 1;
+        }
+        Self::from_bucket_counts(counts)
+    }
+}
+
+/// Hourly activity breakdown
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HourlyActivity {
+    pub hour: String,
+    pub activity_score: f64,
+    pub readings: u64,
+    pub avg_sound_level: f64,
+    #[serde(default)]
+    pub filled_readings: u64,
+    /// Average ambient light in lux, if the hour has readings with a light level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_light: Option<f64>,
+}
+
+/// Compact reading for charts, see `GET /api/timeseries`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeseriesPoint {
+    #[serde(default)]
+    pub room: String,
+    pub timestamp: String,
+    pub temperature: f32,
+    pub motion: bool,
+    pub sound_level: i32,
+    pub alerts: Vec<String>,
+    /// Interpolated to cover a short gap rather than measured
+    pub filled: bool,
+}
+
+/// Night-time noise of one week, see `GET /api/reports/quiet-hours`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuietHoursWeek {
+    /// Monday the week starts on
+    pub week_start: String,
+    /// Quiet-hours minutes with at least one reading
+    pub observed_minutes: u64,
+    /// Observed minutes whose loudest reading exceeded the noise limit
+    pub noisy_minutes: u64,
+    pub percent_noisy: f64,
+    /// Change in percentage points against the previous week
+    pub change: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomQuietHours {
+    pub room: String,
+    pub weeks: Vec<QuietHoursWeek>,
+}
+
+/// Quiet-hours noise compliance per room and for the ward, oldest week first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuietHoursReport {
+    pub ward: Option<String>,
+    /// Quiet hours (facility-local), wrapping past midnight if `end_hour < start_hour`
+    pub start_hour: u32,
+    pub end_hour: u32,
+    pub noise_limit: i32,
+    pub rooms: Vec<RoomQuietHours>,
+    /// All rooms combined
+    pub ward_weeks: Vec<QuietHoursWeek>,
+}
+
+/// What a fall-risk score is made of, measured over the scoring window
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FallRiskFactors {
+    /// Fall alerts raised in the window
+    pub falls: u64,
+    /// Share of observed night minutes with motion, as a percentage
+    pub night_restlessness: f64,
+    /// Average times per night the patient got up after resting
+    pub bed_exits_per_night: f64,
+}
+
+/// Fall-risk score of a patient on one day, see `GET /api/patients/{id}/risk`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FallRiskScore {
+    pub patient_id: String,
+    pub date: NaiveDate,
+    /// 0 (no risk factors present) to 100
+    pub score: f64,
+    /// `low`, `moderate` or `high`
+    pub level: String,
+    /// Days of readings the factors cover, ending on `date`
+    pub window_days: u32,
+    #[serde(flatten)]
+    pub factors: FallRiskFactors,
+}
+
+/// Aggregates of one room's readings on one day (UTC), written by the nightly
+/// rollup, see `GET /api/summary/daily`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailySummary {
+    pub room: String,
+    pub date: NaiveDate,
+    pub readings: u64,
+    pub motion_readings: u64,
+    /// Share of readings with motion (0-100)
+    pub motion_percent: f64,
+    pub avg_temperature: f64,
+    pub min_temperature: f32,
+    pub max_temperature: f32,
+    /// Alerts raised during the day, by type
+    pub fall_alerts: u64,
+    pub inactivity_alerts: u64,
+    pub sensor_fault_alerts: u64,
+    pub longest_still_period_mins: u64,
+}
+
+/// Statistics of one group of patients' readings, see
+/// `GET /api/research/aggregates`. `ward` and `date` are set when grouping
+/// by them; a group of rooms without a ward has no `ward` either.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResearchGroup {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ward: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<NaiveDate>,
+    /// Distinct patients with readings in the group
+    pub patients: u64,
+    pub readings: u64,
+    /// Share of readings with motion (0-100)
+    pub motion_percent: f64,
+    pub avg_temperature: f64,
+    /// Alerts raised by the group's readings, by type
+    pub fall_alerts: u64,
+    pub inactivity_alerts: u64,
+}
+
+/// Aggregate statistics across patients who consent to research, from
+/// `from` to `to` (inclusive, UTC days)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResearchAggregates {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Groups with fewer patients than this are left out
+    pub min_group_size: u64,
+    /// Privacy parameter of the noise added to each statistic, if any;
+    /// smaller is noisier
+    pub noise_epsilon: Option<f64>,
+    pub suppressed_groups: usize,
+    pub groups: Vec<ResearchGroup>,
+}
+
+/// Daily report sent when quiet hours end: fall risk per room, highest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MorningReport {
+    pub ward: Option<String>,
+    pub date: NaiveDate,
+    pub rooms: Vec<FallRiskScore>,
+    /// Observations recorded by staff in the last day, newest first
+    #[serde(default)]
+    pub observations: Vec<ManualObservation>,
+    /// Procedures scheduled over the coming day, soonest first
+    #[serde(default)]
+    pub procedures: Vec<ScheduledProcedure>,
+}
+
+/// A named shift, e.g. `night=19-7`: the hours (0-23, facility-local) it
+/// starts and ends at; it wraps past midnight if it ends at or before the
+/// hour it starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shift {
+    pub name: String,
+    pub start_hour: u32,
+    pub end_hour: u32,
+}
+
+/// The shifts handovers are given at the end of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shifts(Vec<Shift>);
+
+impl Default for Shifts {
+    fn default() -> Self {
+        Self(vec![
+            Shift { name: "day".to_string(), start_hour: 7, end_hour: 19 },
+            Shift { name: "night".to_string(), start_hour: 19, end_hour: 7 },
+        ])
+    }
+}
+
+impl Shifts {
+    /// A comma-separated list of `<name>=<start hour>-<end hour>`, e.g.
+    /// `early=6-14,late=14-22,night=22-6`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut shifts: Vec<Shift> = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, hours) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <name>=<start>-<end>, got {}", entry))?;
+            let (start, end) = hours
+                .split_once('-')
+                .ok_or_else(|| format!("Expected <start>-<end> hours for shift {}, got {}", name, hours))?;
+            let hour = |h: &str| h.trim().parse::<u32>().ok().filter(|h| *h < 24)
+                .ok_or_else(|| format!("Invalid hour {} for shift {}, expected 0-23", h, name));
+            let name = name.trim().to_string();
+            if shifts.iter().any(|s| s.name == name) {
+                return Err(format!("Shift {} is listed twice", name));
+            }
+            shifts.push(Shift { start_hour: hour(start)?, end_hour: hour(end)?, name });
+        }
+        if shifts.is_empty() {
+            return Err("No shifts given".to_string());
+        }
+        Ok(Self(shifts))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Shift> {
+        self.0.iter().find(|s| s.name == name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.0.iter().map(|s| s.name.as_str()).collect()
+    }
+}
+
+/// End-of-shift handover of every room (or every room of a ward), to be read
+/// out to the next shift
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Handover {
+    pub ward: Option<String>,
+    pub shift: String,
+    /// Start of the shift
+    pub start: DateTime<Utc>,
+    /// End of the shift, or when the handover was generated if earlier
+    pub end: DateTime<Utc>,
+    pub rooms: Vec<RoomHandover>,
+}
+
+/// One room's part of a handover
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomHandover {
+    pub room: String,
+    /// Alerts still open at the end of the shift, oldest first
+    pub open_alerts: Vec<HandoverAlert>,
+    /// Alerts raised during the shift, oldest first, whether or not they
+    /// were resolved since
+    pub events: Vec<HandoverAlert>,
+    /// Rest over the quiet hours within the shift; `None` if the shift
+    /// has none or the room had no readings in them
+    pub sleep: Option<ShiftSleep>,
+    /// Observations staff recorded with a note during the shift, oldest
+    /// first, for the next shift to follow up
+    pub follow_ups: Vec<ManualObservation>,
+    /// The above in a few sentences, see `RoomHandover::read_out`
+    pub summary: String,
+}
+
+/// An alert in a handover
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HandoverAlert {
+    #[serde(rename = "type")]
+    pub alert: AlertType,
+    pub description: String,
+    pub severity: AlertSeverity,
+    pub triggered_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub acknowledged_by: Option<String>,
+}
+
+/// How a patient rested over the quiet hours of a shift
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShiftSleep {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub activity_score: f64,
+    pub activity_level: String,
+    /// `Excellent`, `Good`, `Fair` or `Poor`, see `analysis::rest_quality`
+    pub rest_quality: String,
+    /// Likely bathroom trips
+    pub awakenings: u64,
+    pub longest_still_period_mins: u64,
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
+
+impl RoomHandover {
+    /// A few sentences on the room to read out at handover, e.g. `room-101:
+    /// 1 open alert: Possible fall detected. 2 alerts during the shift:
+    /// Possible fall detected, Patient inactivity alert. Rest excellent,
+    /// 12% active, 1 awakening. 1 note to follow up.`
+    pub fn read_out(&self) -> String {
+        let descriptions = |alerts: &[HandoverAlert]| {
+            alerts.iter().map(|a| a.description.as_str()).collect::<Vec<_>>().join(", ")
+        };
+        let mut sentences = vec![format!("{}:", self.room)];
+        sentences.push(match self.open_alerts.len() {
+            0 => "No open alerts.".to_string(),
+            n => format!("{}: {}.", plural(n, "open alert", "open alerts"), descriptions(&self.open_alerts)),
+        });
+        sentences.push(match self.events.len() {
+            0 => "No alerts during the shift.".to_string(),
+            n => format!("{} during the shift: {}.", plural(n, "alert", "alerts"), descriptions(&self.events)),
+        });
+        if let Some(sleep) = &self.sleep {
+            sentences.push(format!("Rest {}, {}% active, {}.",
+                sleep.rest_quality.to_lowercase(),
+                sleep.activity_score.round(),
+                plural(sleep.awakenings as usize, "awakening", "awakenings")));
+        }
+        if !self.follow_ups.is_empty() {
+            sentences.push(format!("{} to follow up.", plural(self.follow_ups.len(), "note", "notes")));
+        }
+        sentences.join(" ")
+    }
+}
+
+/// Procedure from a room's calendar feed; alert notifications for the room
+/// are suppressed while it runs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledProcedure {
+    pub room: String,
+    /// UID of the calendar event
+    pub uid: String,
+    pub summary: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Coarse state of the patient in a room, derived from motion, the bed sensor
+/// where fitted, and time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatientState {
+    InBed,
+    /// Moving in the room, out of bed
+    Moving,
+    OutOfRoom,
+}
+
+impl PatientState {
+    /// Name used in the API and storage
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PatientState::InBed => "in_bed",
+            PatientState::Moving => "moving",
+            PatientState::OutOfRoom => "out_of_room",
+        }
+    }
+}
+
+impl std::str::FromStr for PatientState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in_bed" => Ok(PatientState::InBed),
+            "moving" => Ok(PatientState::Moving),
+            "out_of_room" => Ok(PatientState::OutOfRoom),
+            other => Err(format!("Unknown patient state: {}", other)),
+        }
+    }
+}
+
+/// Room state in effect from `start` until `end`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatePeriod {
+    pub state: PatientState,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Room states over a period, see `GET /api/rooms/{id}/state-history`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateHistory {
+    pub room: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Consecutive periods oldest first; time before the first state was
+    /// derived is left out
+    pub periods: Vec<StatePeriod>,
+}
+
+/// Coarse status of one room for a family kiosk or TV, see
+/// `GET /api/kiosk/status`. Leaves out readings, alerts and anything naming
+/// the patient.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KioskStatus {
+    pub room: String,
+    /// Where the kiosk is, as given when its token was created
+    pub label: Option<String>,
+    /// `None` until a state was derived
+    pub state: Option<PatientState>,
+    pub since: Option<DateTime<Utc>>,
+    /// Plain-language status for the screen
+    pub message: String,
+    /// When the kiosk's token stops working
+    pub expires_at: DateTime<Utc>,
+}
+
+impl KioskStatus {
+    pub fn message(state: Option<PatientState>) -> &'static str {
+        match state {
+            Some(PatientState::InBed) => "Resting in bed",
+            Some(PatientState::Moving) => "Up and about in the room",
+            Some(PatientState::OutOfRoom) => "Out of the room",
+            None => "No status yet",
+        }
+    }
+}
+
+/// Which way someone went through a room's door, told from the motion in the
+/// room before the door opened and after it closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PassageKind {
+    /// The room was still before and had motion after
+    Entry,
+    /// The room had motion before and was still after
+    Exit,
+    /// Motion both before and after, or neither, e.g. staff looking in
+    Passage,
+}
+
+impl PassageKind {
+    /// Name used in the API and storage
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PassageKind::Entry => "entry",
+            PassageKind::Exit => "exit",
+            PassageKind::Passage => "passage",
+        }
+    }
+}
+
+impl std::str::FromStr for PassageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "entry" => Ok(PassageKind::Entry),
+            "exit" => Ok(PassageKind::Exit),
+            "passage" => Ok(PassageKind::Passage),
+            other => Err(format!("Unknown passage kind: {}", other)),
+        }
+    }
+}
+
+/// The door of a room opening and closing again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DoorPassage {
+    pub kind: PassageKind,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: DateTime<Utc>,
+}
+
+/// Door passages of a room, see `GET /api/rooms/{id}/door-passages`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DoorPassages {
+    pub room: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Oldest first
+    pub passages: Vec<DoorPassage>,
+}
+
+/// Alerts by the outcome staff recorded for them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutcomeCounts {
+    pub alerts: u64,
+    pub confirmed: u64,
+    pub false_alarms: u64,
+    /// Judged, but staff couldn't tell
+    pub unknown: u64,
+    /// No outcome recorded
+    pub unreviewed: u64,
+}
+
+impl OutcomeCounts {
+    pub fn add(&mut self, outcome: Option<AlertOutcome>) {
+        self.alerts += 1;
+        match outcome {
+            Some(AlertOutcome::ConfirmedIncident) => self.confirmed += 1,
+            Some(AlertOutcome::FalseAlarm) => self.false_alarms += 1,
+            Some(AlertOutcome::Unknown) => self.unknown += 1,
+            None => self.unreviewed += 1,
+        }
+    }
+
+    /// Share of confirmed incidents among the alerts judged either way, as a
+    /// percentage; `None` until one was
+    pub fn precision(&self) -> Option<f64> {
+        let judged = self.confirmed + self.false_alarms;
+        (judged > 0).then(|| self.confirmed as f64 / judged as f64 * 100.0)
+    }
+}
+
+/// Alerts of one type or room triggered in one period, see
+/// `GET /api/analytics/alert-precision`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrecisionGroup {
+    /// Facility day the period starts on
+    pub period: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    #[serde(flatten)]
+    pub counts: OutcomeCounts,
+    pub precision: Option<f64>,
+}
+
+/// Alert precision from `from` to `to` (inclusive facility days), oldest
+/// period first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertPrecisionReport {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// `day` or `week`
+    pub interval: String,
+    /// `type` or `room`
+    pub by: String,
+    pub groups: Vec<PrecisionGroup>,
+    /// All groups combined
+    pub total: OutcomeCounts,
+    pub precision: Option<f64>,
+}
+
+/// Point of a downsampled chart series
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChartPoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
+}
+
+/// A room's temperature and sound level from `from` to `to`, each reduced to
+/// at most `points` readings for charts, see `GET /api/observations/downsampled`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownsampledReadings {
+    pub room: Option<String>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub points: usize,
+    /// Readings in the period before downsampling
+    pub raw_readings: u64,
+    /// Oldest first
+    pub temperature: Vec<ChartPoint>,
+    pub sound_level: Vec<ChartPoint>,
+}
+
+/// Alerts triggered in one day or week, see `GET /api/alerts/stats`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertCounts {
+    /// Facility day the bucket starts on
+    pub period: NaiveDate,
+    pub fall_alerts: u64,
+    pub inactivity_alerts: u64,
+    pub sensor_fault_alerts: u64,
+}
+
+/// Alert counts per bucket from `from` to `to`, oldest first; buckets
+/// without alerts are included
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertStats {
+    /// `day` or `week`
+    pub bucket: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    pub buckets: Vec<AlertCounts>,
+}
+
+/// Disk usage of a readings partition or TimescaleDB chunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PartitionStorage {
+    pub name: String,
+    pub bytes: i64,
+}
+
+/// A room's readings, their share of the disk usage and how fast they grow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomStorage {
+    pub room: String,
+    pub readings: i64,
+    /// The room's share of the readings' bytes, by number of readings
+    pub bytes: i64,
+    /// Readings stored per day over the rate window
+    pub readings_per_day: f64,
+    pub bytes_per_day: i64,
+}
+
+/// Disk usage of the stored readings and its projected growth
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageReport {
+    pub generated_at: DateTime<Utc>,
+    /// Bytes of the readings with their indexes; for SQLite, of the whole
+    /// database file
+    pub bytes: i64,
+    pub readings: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_per_reading: Option<f64>,
+    /// Days the ingest rates are measured over
+    pub rate_window_days: i64,
+    pub bytes_per_day: i64,
+    /// Bytes expected after `projection_days` more days at the current rates
+    pub projected_bytes: i64,
+    pub projection_days: i64,
+    /// Bytes once the readings span the retention period, when retention
+    /// is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub steady_state_bytes: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub partitions: Vec<PartitionStorage>,
+    pub rooms: Vec<RoomStorage>,
+}
@@ -0,0 +1,1290 @@
+//! FHIR-compliant data models for patient monitoring
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+// ============================================================================
+// CORE SENSOR DATA
+// ============================================================================
+
+/// Room monitored when no rooms are configured. Readings from a room without
+/// a registered patient name the room's id as their patient.
+pub const DEFAULT_ROOM_ID: &str = "room-101";
+
+fn default_room() -> String {
+    DEFAULT_ROOM_ID.to_string()
+}
+
+/// One reading of a room's sensor board. The default is an all-zero
+/// reading at the Unix epoch without any of the optional channels, to fill
+/// in the ones a literal doesn't set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SensorReading {
+    pub temperature: f32,
+    pub motion: bool,
+    pub sound_level: i32,  // Integer for sound level
+    pub timestamp: DateTime<Utc>,
+    /// Relative humidity in percent, from boards with a DHT22
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f32>,
+    /// Pulse in beats per minute, from boards with a pulse oximeter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heart_rate: Option<i32>,
+    /// Peripheral oxygen saturation in percent, from boards with a pulse
+    /// oximeter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spo2: Option<i32>,
+    /// Whether the room's door is open, from boards with a door contact
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub door_open: Option<bool>,
+    /// Ambient light in lux, from boards with a light sensor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<f32>,
+    /// Carbon dioxide in the room's air in ppm, from boards with a CO2 sensor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub co2: Option<i32>,
+    /// Whether a 24 GHz radar module sees someone in the room, which it does
+    /// from micro-motion as slight as breathing, from boards with a radar
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence: Option<bool>,
+    /// Peak acceleration magnitude since the previous reading in g, from
+    /// boards with an accelerometer worn by the patient or on the bed frame;
+    /// about 1 at rest, from gravity alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acceleration: Option<f32>,
+}
+
+/// Version of the JSON line protocol the firmware speaks
+pub const LINE_PROTOCOL_VERSION: u32 = 2;
+
+/// Reading line of the JSON protocol, e.g. `{"v":2,"t":23.5,"m":1,"s":40}`,
+/// with `"h":45.2` from boards with a humidity sensor, `"hr":72,"spo2":97`
+/// from boards with a pulse oximeter, `"d":1` (door open) from boards with a
+/// door contact, `"lx":120` from boards with a light sensor, `"co2":650`
+/// (ppm) from boards with a CO2 sensor, `"pr":1` (someone present) from
+/// boards with a presence radar and `"g":2.7` (peak acceleration in g) from
+/// boards with an accelerometer. Fields it doesn't know, e.g. channels of
+/// sensors added later, are ignored.
+#[derive(Debug, Deserialize)]
+struct ReadingLine {
+    v: u32,
+    t: f32,
+    /// `0`/`1`, or `false`/`true`
+    m: serde_json::Value,
+    s: i32,
+    #[serde(default)]
+    h: Option<f32>,
+    #[serde(default)]
+    hr: Option<i32>,
+    #[serde(default)]
+    spo2: Option<i32>,
+    /// `0`/`1`, or `false`/`true`, like `m`
+    #[serde(default)]
+    d: Option<serde_json::Value>,
+    #[serde(default)]
+    lx: Option<f32>,
+    #[serde(default)]
+    co2: Option<i32>,
+    /// `0`/`1`, or `false`/`true`, like `m`
+    #[serde(default)]
+    pr: Option<serde_json::Value>,
+    #[serde(default)]
+    g: Option<f32>,
+}
+
+/// A flag of the JSON protocol, sent as `0`/`1` or `false`/`true`
+fn line_flag(value: &serde_json::Value) -> Option<bool> {
+    value.as_bool().or_else(|| value.as_i64().map(|v| v != 0))
+}
+
+impl SensorReading {
+    /// Parse a reading line from the firmware, taken now: a JSON protocol
+    /// line, or else the legacy `temperature,motion,sound` CSV, which has
+    /// only those. The temperature is in the unit the device reports in.
+    pub fn parse_line(line: &str) -> Option<SensorReading> {
+        let line = line.trim();
+        if line.starts_with('{') {
+            let json: ReadingLine = serde_json::from_str(line).ok()?;
+            if json.v != LINE_PROTOCOL_VERSION {
+                return None;
+            }
+            return Some(SensorReading {
+                temperature: json.t,
+                motion: line_flag(&json.m)?,
+                sound_level: json.s,
+                timestamp: Utc::now(),
+                humidity: json.h,
+                heart_rate: json.hr,
+                spo2: json.spo2,
+                door_open: match &json.d {
+                    Some(d) => Some(line_flag(d)?),
+                    None => None,
+                },
+                light: json.lx,
+                co2: json.co2,
+                presence: match &json.pr {
+                    Some(pr) => Some(line_flag(pr)?),
+                    None => None,
+                },
+                acceleration: json.g,
+            });
+        }
+
+        let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [temperature, motion, sound_level] = parts[..] else {
+            return None;
+        };
+        Some(SensorReading {
+            temperature: temperature.parse().ok()?,
+            motion: motion.parse::<i32>().ok()? != 0,
+            sound_level: sound_level.parse().ok()?,
+            timestamp: Utc::now(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Values a sensor can plausibly report. A reading outside them comes from
+/// a faulty sensor or a garbled line and is quarantined instead of stored.
+/// Limits are inclusive; the temperature is in Celsius.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SensorRanges {
+    pub temperature: (f32, f32),
+    pub sound_level: (i32, i32),
+    pub humidity: (f32, f32),
+    pub heart_rate: (i32, i32),
+    pub spo2: (i32, i32),
+    pub light: (f32, f32),
+    pub co2: (i32, i32),
+    pub acceleration: (f32, f32),
+}
+
+impl Default for SensorRanges {
+    fn default() -> Self {
+        Self {
+            temperature: (-10.0, 60.0),
+            // Range of the board's 10-bit ADC
+            sound_level: (0, 1023),
+            humidity: (0.0, 100.0),
+            heart_rate: (20, 250),
+            spo2: (50, 100),
+            // Up to direct sunlight
+            light: (0.0, 150_000.0),
+            // Outdoor air is about 420 ppm; NDIR sensors read up to 10000
+            co2: (300, 10_000),
+            // Full scale of common accelerometers
+            acceleration: (0.0, 16.0),
+        }
+    }
+}
+
+impl SensorRanges {
+    /// The defaults overridden by `spec`, a comma-separated list of
+    /// `<temperature|sound|humidity|heart_rate|spo2|light|co2|acceleration>=<min>..<max>`, e.g.
+    /// `temperature=5..45,sound=0..4095`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut ranges = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (channel, range) = entry
+                .split_once('=')
+                .and_then(|(channel, range)| Some((channel.trim(), range.split_once("..")?)))
+                .ok_or_else(|| format!("Expected <channel>=<min>..<max>, got {}", entry))?;
+            let invalid = || format!("Invalid range for {}: {}", channel, entry);
+            let real_range = || -> Result<(f32, f32), String> {
+                let min: f32 = range.0.trim().parse().map_err(|_| invalid())?;
+                let max: f32 = range.1.trim().parse().map_err(|_| invalid())?;
+                if !min.is_finite() || !max.is_finite() || min > max {
+                    return Err(invalid());
+                }
+                Ok((min, max))
+            };
+            let int_range = || -> Result<(i32, i32), String> {
+                let min: i32 = range.0.trim().parse().map_err(|_| invalid())?;
+                let max: i32 = range.1.trim().parse().map_err(|_| invalid())?;
+                if min > max {
+                    return Err(invalid());
+                }
+                Ok((min, max))
+            };
+            match channel {
+                "temperature" => ranges.temperature = real_range()?,
+                "humidity" => ranges.humidity = real_range()?,
+                "sound" => ranges.sound_level = int_range()?,
+                "heart_rate" => ranges.heart_rate = int_range()?,
+                "spo2" => ranges.spo2 = int_range()?,
+                "light" => ranges.light = real_range()?,
+                "co2" => ranges.co2 = int_range()?,
+                "acceleration" => ranges.acceleration = real_range()?,
+                other => return Err(format!(
+                    "Unknown sensor channel {}, expected temperature, sound, humidity, heart_rate, spo2, light, co2 or acceleration",
+                    other)),
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Why `reading` is implausible, or `None` if it is within the ranges
+    pub fn check(&self, reading: &SensorReading) -> Option<String> {
+        let (min, max) = self.temperature;
+        if !(min..=max).contains(&reading.temperature) {
+            return Some(format!("temperature {} outside {}..{}", reading.temperature, min, max));
+        }
+        let (min, max) = self.sound_level;
+        if !(min..=max).contains(&reading.sound_level) {
+            return Some(format!("sound level {} outside {}..{}", reading.sound_level, min, max));
+        }
+        let (min, max) = self.humidity;
+        if let Some(humidity) = reading.humidity.filter(|h| !(min..=max).contains(h)) {
+            return Some(format!("humidity {} outside {}..{}", humidity, min, max));
+        }
+        let (min, max) = self.heart_rate;
+        if let Some(heart_rate) = reading.heart_rate.filter(|hr| !(min..=max).contains(hr)) {
+            return Some(format!("heart rate {} outside {}..{}", heart_rate, min, max));
+        }
+        let (min, max) = self.spo2;
+        if let Some(spo2) = reading.spo2.filter(|s| !(min..=max).contains(s)) {
+            return Some(format!("SpO2 {} outside {}..{}", spo2, min, max));
+        }
+        let (min, max) = self.light;
+        if let Some(light) = reading.light.filter(|l| !(min..=max).contains(l)) {
+            return Some(format!("light level {} outside {}..{}", light, min, max));
+        }
+        let (min, max) = self.co2;
+        if let Some(co2) = reading.co2.filter(|c| !(min..=max).contains(c)) {
+            return Some(format!("CO2 {} ppm outside {}..{}", co2, min, max));
+        }
+        let (min, max) = self.acceleration;
+        if let Some(acceleration) = reading.acceleration.filter(|g| !(min..=max).contains(g)) {
+            return Some(format!("acceleration {} g outside {}..{}", acceleration, min, max));
+        }
+        None
+    }
+}
+
+/// Measurement channel of a sensor board
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SensorChannel {
+    Temperature,
+    Motion,
+    Sound,
+}
+
+impl SensorChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SensorChannel::Temperature => "temperature",
+            SensorChannel::Motion => "motion",
+            SensorChannel::Sound => "sound",
+        }
+    }
+}
+
+/// Serialized as a string: `fall`, `inactivity`, `tachycardia`, `low_spo2`,
+/// `ventilation`, `calibration` or `sensor_fault:<channel>`, e.g.
+/// `sensor_fault:sound`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(into = "&'static str", try_from = "String")]
+pub enum AlertType {
+    Fall,
+    Inactivity,
+    /// Heart rate above the limit, from a pulse oximeter
+    Tachycardia,
+    /// Oxygen saturation below the limit, from a pulse oximeter
+    LowSpo2,
+    /// CO2 above the limit for a while: the room needs airing
+    Ventilation,
+    /// The temperatures of a room's redundant sensors have disagreed for a
+    /// while: one of them needs calibrating
+    Calibration,
+    /// The channel's signal points to a broken sensor rather than a quiet
+    /// room, e.g. a microphone reporting the same level for hours
+    SensorFault(SensorChannel),
+}
+
+/// How urgently an alert needs a response, used for routing notifications
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl AlertType {
+    pub const ALL: [AlertType; 9] = [
+        AlertType::Fall,
+        AlertType::Inactivity,
+        AlertType::Tachycardia,
+        AlertType::LowSpo2,
+        AlertType::Ventilation,
+        AlertType::Calibration,
+        AlertType::SensorFault(SensorChannel::Temperature),
+        AlertType::SensorFault(SensorChannel::Motion),
+        AlertType::SensorFault(SensorChannel::Sound),
+    ];
+
+    pub fn severity(&self) -> AlertSeverity {
+        match self {
+            AlertType::Fall => AlertSeverity::Critical,
+            AlertType::Inactivity => AlertSeverity::Warning,
+            AlertType::Tachycardia => AlertSeverity::Warning,
+            AlertType::LowSpo2 => AlertSeverity::Critical,
+            AlertType::Ventilation => AlertSeverity::Warning,
+            AlertType::Calibration => AlertSeverity::Warning,
+            AlertType::SensorFault(_) => AlertSeverity::Warning,
+        }
+    }
+
+    /// Code shown to clients (WebSocket, notifications)
+    pub fn code(&self) -> &'static str {
+        match self {
+            AlertType::Fall => "FALL_DETECTED",
+            AlertType::Inactivity => "INACTIVITY_ALERT",
+            AlertType::Tachycardia => "TACHYCARDIA",
+            AlertType::LowSpo2 => "LOW_SPO2",
+            AlertType::Ventilation => "VENTILATION_NEEDED",
+            AlertType::Calibration => "CALIBRATION_NEEDED",
+            AlertType::SensorFault(_) => "SENSOR_FAULT",
+        }
+    }
+
+    /// Human-readable description, e.g. for notifications
+    pub fn description(&self) -> &'static str {
+        match self {
+            AlertType::Fall => "Possible fall detected",
+            AlertType::Inactivity => "Patient inactivity alert",
+            AlertType::Tachycardia => "High heart rate",
+            AlertType::LowSpo2 => "Low oxygen saturation",
+            AlertType::Ventilation => "Ventilation needed",
+            AlertType::Calibration => "Temperature sensors disagree",
+            AlertType::SensorFault(SensorChannel::Temperature) => "Temperature sensor fault",
+            AlertType::SensorFault(SensorChannel::Motion) => "Motion sensor fault",
+            AlertType::SensorFault(SensorChannel::Sound) => "Sound sensor fault",
+        }
+    }
+
+    /// Name used in the API, configuration and storage
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertType::Fall => "fall",
+            AlertType::Inactivity => "inactivity",
+            AlertType::Tachycardia => "tachycardia",
+            AlertType::LowSpo2 => "low_spo2",
+            AlertType::Ventilation => "ventilation",
+            AlertType::Calibration => "calibration",
+            AlertType::SensorFault(SensorChannel::Temperature) => "sensor_fault:temperature",
+            AlertType::SensorFault(SensorChannel::Motion) => "sensor_fault:motion",
+            AlertType::SensorFault(SensorChannel::Sound) => "sensor_fault:sound",
+        }
+    }
+}
+
+impl std::str::FromStr for AlertType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fall" => Ok(AlertType::Fall),
+            "inactivity" => Ok(AlertType::Inactivity),
+            "tachycardia" => Ok(AlertType::Tachycardia),
+            "low_spo2" => Ok(AlertType::LowSpo2),
+            "ventilation" => Ok(AlertType::Ventilation),
+            "calibration" => Ok(AlertType::Calibration),
+            "sensor_fault:temperature" => Ok(AlertType::SensorFault(SensorChannel::Temperature)),
+            "sensor_fault:motion" => Ok(AlertType::SensorFault(SensorChannel::Motion)),
+            "sensor_fault:sound" => Ok(AlertType::SensorFault(SensorChannel::Sound)),
+            other => Err(format!("Unknown alert type: {}", other)),
+        }
+    }
+}
+
+impl TryFrom<String> for AlertType {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<AlertType> for &'static str {
+    fn from(alert: AlertType) -> Self {
+        alert.as_str()
+    }
+}
+
+/// Why an alert episode ended
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionReason {
+    /// A reading no longer raised it
+    Cleared,
+    /// A reading with motion
+    MotionResumed,
+    /// Staff acknowledged it
+    Acknowledged,
+}
+
+impl ResolutionReason {
+    /// Name used in the API and storage
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResolutionReason::Cleared => "cleared",
+            ResolutionReason::MotionResumed => "motion_resumed",
+            ResolutionReason::Acknowledged => "acknowledged",
+        }
+    }
+}
+
+impl std::str::FromStr for ResolutionReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cleared" => Ok(ResolutionReason::Cleared),
+            "motion_resumed" => Ok(ResolutionReason::MotionResumed),
+            "acknowledged" => Ok(ResolutionReason::Acknowledged),
+            other => Err(format!("Unknown resolution reason: {}", other)),
+        }
+    }
+}
+
+/// How staff judged an alert, recorded when acknowledging or after it
+/// resolved
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertOutcome {
+    /// Something happened that needed staff, e.g. an actual fall
+    ConfirmedIncident,
+    FalseAlarm,
+    /// Staff couldn't tell
+    Unknown,
+}
+
+impl AlertOutcome {
+    /// Name used in the API and storage
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertOutcome::ConfirmedIncident => "confirmed_incident",
+            AlertOutcome::FalseAlarm => "false_alarm",
+            AlertOutcome::Unknown => "unknown",
+        }
+    }
+}
+
+impl std::str::FromStr for AlertOutcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "confirmed_incident" => Ok(AlertOutcome::ConfirmedIncident),
+            "false_alarm" => Ok(AlertOutcome::FalseAlarm),
+            "unknown" => Ok(AlertOutcome::Unknown),
+            other => Err(format!("Unknown alert outcome: {}", other)),
+        }
+    }
+}
+
+/// All alerts raised by one reading, e.g. a loud fall during a long
+/// inactivity streak raises both. Serialized as an array.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AlertSet(BTreeSet<AlertType>);
+
+impl AlertSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, alert: AlertType) {
+        self.0.insert(alert);
+    }
+
+    pub fn contains(&self, alert: AlertType) -> bool {
+        self.0.contains(&alert)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = AlertType> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// Severity of the most urgent alert, `Info` if there is none
+    pub fn severity(&self) -> AlertSeverity {
+        self.iter().map(|a| a.severity()).max().unwrap_or(AlertSeverity::Info)
+    }
+
+    /// Client codes of all alerts
+    pub fn codes(&self) -> Vec<String> {
+        self.iter().map(|a| a.code().to_string()).collect()
+    }
+}
+
+impl FromIterator<AlertType> for AlertSet {
+    fn from_iter<I: IntoIterator<Item = AlertType>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl From<AlertType> for AlertSet {
+    fn from(alert: AlertType) -> Self {
+        Self(BTreeSet::from([alert]))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorEvent {
+    pub id: Option<i64>,
+    /// Position in the server's change feed. Unlike `id` and the timestamp,
+    /// it increases in the order readings were stored, also for imported or
+    /// backfilled data.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+    /// Room the reading was taken in
+    #[serde(default = "default_room")]
+    pub room: String,
+    /// Serial port of the device that took the reading, where a room has
+    /// several; not stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Registered patient assigned to the room when the reading was taken
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patient_id: Option<String>,
+    pub reading: SensorReading,
+    pub alerts: AlertSet,
+}
+
+// ============================================================================
+// MANUAL OBSERVATIONS
+// ============================================================================
+
+/// Vital sign or score staff record by hand, e.g. a spot-check temperature
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ObservationKind {
+    BodyTemperature,
+    HeartRate,
+    RespiratoryRate,
+    OxygenSaturation,
+    SystolicBloodPressure,
+    DiastolicBloodPressure,
+    /// 0 (no pain) to 10 (worst imaginable pain)
+    PainScore,
+}
+
+impl ObservationKind {
+    pub const ALL: [ObservationKind; 7] = [
+        ObservationKind::BodyTemperature,
+        ObservationKind::HeartRate,
+        ObservationKind::RespiratoryRate,
+        ObservationKind::OxygenSaturation,
+        ObservationKind::SystolicBloodPressure,
+        ObservationKind::DiastolicBloodPressure,
+        ObservationKind::PainScore,
+    ];
+
+    /// LOINC code and display name
+    pub fn loinc(&self) -> (&'static str, &'static str) {
+        match self {
+            ObservationKind::BodyTemperature => ("8310-5", "Body temperature"),
+            ObservationKind::HeartRate => ("8867-4", "Heart rate"),
+            ObservationKind::RespiratoryRate => ("9279-1", "Respiratory rate"),
+            ObservationKind::OxygenSaturation => ("59408-5", "Oxygen saturation in Arterial blood by Pulse oximetry"),
+            ObservationKind::SystolicBloodPressure => ("8480-6", "Systolic blood pressure"),
+            ObservationKind::DiastolicBloodPressure => ("8462-4", "Diastolic blood pressure"),
+            ObservationKind::PainScore => ("72514-3", "Pain severity - 0-10 verbal numeric rating [Score] - Reported"),
+        }
+    }
+
+    /// UCUM unit of the value
+    pub fn unit(&self) -> &'static str {
+        match self {
+            ObservationKind::BodyTemperature => "Cel",
+            ObservationKind::HeartRate | ObservationKind::RespiratoryRate => "/min",
+            ObservationKind::OxygenSaturation => "%",
+            ObservationKind::SystolicBloodPressure | ObservationKind::DiastolicBloodPressure => "mm[Hg]",
+            ObservationKind::PainScore => "{score}",
+        }
+    }
+
+    /// Values that can plausibly be recorded; anything else is a typo
+    pub fn valid_range(&self) -> std::ops::RangeInclusive<f64> {
+        match self {
+            ObservationKind::BodyTemperature => 25.0..=45.0,
+            ObservationKind::HeartRate => 20.0..=250.0,
+            ObservationKind::RespiratoryRate => 2.0..=80.0,
+            ObservationKind::OxygenSaturation => 50.0..=100.0,
+            ObservationKind::SystolicBloodPressure => 40.0..=300.0,
+            ObservationKind::DiastolicBloodPressure => 20.0..=200.0,
+            ObservationKind::PainScore => 0.0..=10.0,
+        }
+    }
+
+    /// Whether only whole numbers are valid
+    pub fn is_integer(&self) -> bool {
+        matches!(self, ObservationKind::PainScore)
+    }
+
+    /// Check a value against the kind's range and precision
+    pub fn validate(&self, value: f64) -> Result<(), String> {
+        let range = self.valid_range();
+        if !range.contains(&value) {
+            return Err(format!("{} must be between {} and {} {}",
+                self.as_str(), range.start(), range.end(), self.unit()));
+        }
+        if self.is_integer() && value.fract() != 0.0 {
+            return Err(format!("{} must be a whole number", self.as_str()));
+        }
+        Ok(())
+    }
+
+    /// Name used in the API and storage
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObservationKind::BodyTemperature => "body_temperature",
+            ObservationKind::HeartRate => "heart_rate",
+            ObservationKind::RespiratoryRate => "respiratory_rate",
+            ObservationKind::OxygenSaturation => "oxygen_saturation",
+            ObservationKind::SystolicBloodPressure => "systolic_blood_pressure",
+            ObservationKind::DiastolicBloodPressure => "diastolic_blood_pressure",
+            ObservationKind::PainScore => "pain_score",
+        }
+    }
+}
+
+impl std::str::FromStr for ObservationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ObservationKind::ALL
+            .into_iter()
+            .find(|k| k.as_str() == s)
+            .ok_or_else(|| format!("Unknown observation kind '{}'", s))
+    }
+}
+
+/// Where an observation comes from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ObservationSource {
+    /// The room's sensor board
+    Sensor,
+    /// Recorded by staff, e.g. a spot check at the bedside
+    Manual,
+}
+
+impl ObservationSource {
+    /// Text of the FHIR observation's method
+    fn method(&self) -> &'static str {
+        match self {
+            ObservationSource::Sensor => "Room sensor",
+            ObservationSource::Manual => "Manual entry",
+        }
+    }
+}
+
+/// Observation recorded by staff through `POST /api/observations`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManualObservation {
+    /// Assigned when stored
+    #[serde(default)]
+    pub id: Option<i64>,
+    pub room: String,
+    /// Registered patient in the room when the observation was stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patient_id: Option<String>,
+    pub kind: ObservationKind,
+    pub value: f64,
+    pub observed_at: DateTime<Utc>,
+    /// Principal of the staff member who recorded it
+    pub recorded_by: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+// ============================================================================
+// PATIENTS
+// ============================================================================
+
+/// Administrative gender, as used by FHIR
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Gender {
+    Male,
+    Female,
+    Other,
+    Unknown,
+}
+
+impl Gender {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Gender::Male => "male",
+            Gender::Female => "female",
+            Gender::Other => "other",
+            Gender::Unknown => "unknown",
+        }
+    }
+}
+
+/// Patient in the registry and the room they are assigned to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Patient {
+    /// Set from the URL on updates
+    #[serde(default)]
+    pub id: String,
+    pub family_name: String,
+    #[serde(default)]
+    pub given_names: Vec<String>,
+    pub birth_date: Option<NaiveDate>,
+    pub gender: Option<Gender>,
+    /// Room the patient is in; readings from the room are attributed to them
+    pub room: Option<String>,
+}
+
+// ============================================================================
+// FHIR STRUCTURES
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirCoding {
+    pub system: String,
+    pub code: String,
+    pub display: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FhirCodeableConcept {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub coding: Vec<FhirCoding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirQuantity {
+    pub value: f64,
+    pub unit: String,
+    pub system: String,
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirReference {
+    pub reference: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FhirObservationComponent {
+    pub code: FhirCodeableConcept,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_quantity: Option<FhirQuantity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_boolean: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_integer: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_string: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FhirObservation {
+    pub resource_type: String,
+    pub id: String,
+    pub status: String,
+    pub category: Vec<FhirCodeableConcept>,
+    pub code: FhirCodeableConcept,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<FhirReference>,
+    pub effective_date_time: String,
+    pub issued: String,
+    /// Staff who recorded a manual observation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub performer: Vec<FhirReference>,
+    /// How the observation was made, i.e. its source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<FhirCodeableConcept>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_quantity: Option<FhirQuantity>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub component: Vec<FhirObservationComponent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpretation: Option<Vec<FhirCodeableConcept>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub note: Vec<FhirAnnotation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirAnnotation {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirHumanName {
+    #[serde(rename = "use")]
+    pub name_use: String,
+    pub family: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub given: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FhirPatient {
+    pub resource_type: String,
+    pub id: String,
+    pub name: Vec<FhirHumanName>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gender: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub birth_date: Option<String>,
+}
+
+/// Hospital stay of a patient, as sent by the ADT feed; only the fields
+/// that bind the patient to a room are read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FhirEncounter {
+    pub resource_type: String,
+    #[serde(default)]
+    pub id: String,
+    /// e.g. `in-progress`, or `finished` once discharged
+    pub status: String,
+    pub subject: Option<FhirReference>,
+    #[serde(default)]
+    pub location: Vec<FhirEncounterLocation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirEncounterLocation {
+    pub location: FhirReference,
+    /// `active` for where the patient is now, `completed` for earlier ones
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FhirBundleEntry {
+    pub full_url: String,
+    pub resource: FhirObservation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FhirBundle {
+    pub resource_type: String,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub bundle_type: String,
+    pub total: u32,
+    pub timestamp: String,
+    /// Paging links, e.g. `next` for the following page of a change feed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub link: Vec<FhirBundleLink>,
+    pub entry: Vec<FhirBundleEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirBundleLink {
+    pub relation: String,
+    pub url: String,
+}
+
+// ============================================================================
+// CONVERSION IMPLEMENTATIONS
+// ============================================================================
+
+impl SensorEvent {
+    /// The registered patient, or else the room's unregistered occupant
+    fn subject(&self) -> FhirReference {
+        match &self.patient_id {
+            Some(id) => FhirReference {
+                reference: format!("Patient/{}", id),
+                display: None,
+            },
+            None => FhirReference {
+                reference: format!("Patient/{}", self.room),
+                display: Some(format!("Occupant of {}", self.room)),
+            },
+        }
+    }
+    
+    pub fn to_fhir(&self, _base_url: &str) -> FhirObservation {
+        let obs_id = self.id
+            .map(|id| format!("observation-{}", id))
+            .unwrap_or_else(|| format!("observation-{}", Uuid::new_v4()));
+        
+        let timestamp = self.reading.timestamp.to_rfc3339();
+        
+        let mut components = vec![
+            FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: vec![FhirCoding {
+                        system: "http://loinc.org".to_string(),
+                        code: "8310-5".to_string(),
+                        display: "Body temperature".to_string(),
+                    }],
+                    text: Some("Room Temperature".to_string()),
+                },
+                value_quantity: Some(FhirQuantity {
+                    value: self.reading.temperature as f64,
+                    unit: "Cel".to_string(),
+                    system: "http://unitsofmeasure.org".to_string(),
+                    code: "Cel".to_string(),
+                }),
+                value_boolean: None,
+                value_integer: None,
+                value_string: None,
+            },
+            FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: vec![FhirCoding {
+                        system: "http://snomed.info/sct".to_string(),
+                        code: "52821000".to_string(),
+                        display: "Motion detected".to_string(),
+                    }],
+                    text: Some("Motion Sensor".to_string()),
+                },
+                value_quantity: None,
+                value_boolean: Some(self.reading.motion),
+                value_integer: None,
+                value_string: None,
+            },
+            FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: vec![FhirCoding {
+                        system: "http://loinc.org".to_string(),
+                        code: "89020-2".to_string(),
+                        display: "Sound level".to_string(),
+                    }],
+                    text: Some("Ambient Sound Level".to_string()),
+                },
+                value_quantity: None,
+                value_boolean: None,
+                value_integer: Some(self.reading.sound_level),
+                value_string: None,
+            },
+        ];
+        
+        if let Some(humidity) = self.reading.humidity {
+            components.push(FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: Vec::new(),
+                    text: Some("Relative Humidity".to_string()),
+                },
+                value_quantity: Some(FhirQuantity {
+                    value: humidity as f64,
+                    unit: "%".to_string(),
+                    system: "http://unitsofmeasure.org".to_string(),
+                    code: "%".to_string(),
+                }),
+                value_boolean: None,
+                value_integer: None,
+                value_string: None,
+            });
+        }
+        
+        if let Some(light) = self.reading.light {
+            components.push(FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: Vec::new(),
+                    text: Some("Ambient Light".to_string()),
+                },
+                value_quantity: Some(FhirQuantity {
+                    value: light as f64,
+                    unit: "lx".to_string(),
+                    system: "http://unitsofmeasure.org".to_string(),
+                    code: "lx".to_string(),
+                }),
+                value_boolean: None,
+                value_integer: None,
+                value_string: None,
+            });
+        }
+        
+        if let Some(co2) = self.reading.co2 {
+            components.push(FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: Vec::new(),
+                    text: Some("Carbon Dioxide (room air)".to_string()),
+                },
+                value_quantity: Some(FhirQuantity {
+                    value: co2 as f64,
+                    unit: "ppm".to_string(),
+                    system: "http://unitsofmeasure.org".to_string(),
+                    code: "[ppm]".to_string(),
+                }),
+                value_boolean: None,
+                value_integer: None,
+                value_string: None,
+            });
+        }
+        
+        let vitals = [
+            (self.reading.heart_rate, "8867-4", "Heart rate", "/min"),
+            (self.reading.spo2, "59408-5", "Oxygen saturation in Arterial blood by Pulse oximetry", "%"),
+        ];
+        for (value, code, display, unit) in vitals {
+            let Some(value) = value else { continue };
+            components.push(FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: vec![FhirCoding {
+                        system: "http://loinc.org".to_string(),
+                        code: code.to_string(),
+                        display: display.to_string(),
+                    }],
+                    text: Some(display.to_string()),
+                },
+                value_quantity: Some(FhirQuantity {
+                    value: value as f64,
+                    unit: unit.to_string(),
+                    system: "http://unitsofmeasure.org".to_string(),
+                    code: unit.to_string(),
+                }),
+                value_boolean: None,
+                value_integer: None,
+                value_string: None,
+            });
+        }
+        
+        if let Some(door_open) = self.reading.door_open {
+            components.push(FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: Vec::new(),
+                    text: Some("Door Open".to_string()),
+                },
+                value_quantity: None,
+                value_boolean: Some(door_open),
+                value_integer: None,
+                value_string: None,
+            });
+        }
+        
+        if let Some(presence) = self.reading.presence {
+            components.push(FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: Vec::new(),
+                    text: Some("Presence (radar)".to_string()),
+                },
+                value_quantity: None,
+                value_boolean: Some(presence),
+                value_integer: None,
+                value_string: None,
+            });
+        }
+        
+        if let Some(acceleration) = self.reading.acceleration {
+            components.push(FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: Vec::new(),
+                    text: Some("Peak Acceleration".to_string()),
+                },
+                value_quantity: Some(FhirQuantity {
+                    value: acceleration as f64,
+                    unit: "g".to_string(),
+                    system: "http://unitsofmeasure.org".to_string(),
+                    code: "[g]".to_string(),
+                }),
+                value_boolean: None,
+                value_integer: None,
+                value_string: None,
+            });
+        }
+        
+        for alert in self.alerts.iter() {
+            components.push(FhirObservationComponent {
+                code: FhirCodeableConcept {
+                    coding: vec![FhirCoding {
+                        system: "http://terminology.hl7.org/CodeSystem/v3-ObservationInterpretation".to_string(),
+                        code: "AA".to_string(),
+                        display: "Critical abnormal".to_string(),
+                    }],
+                    text: Some("Alert Status".to_string()),
+                },
+                value_quantity: None,
+                value_boolean: None,
+                value_integer: None,
+                value_string: Some(alert.code().to_string()),
+            });
+        }
+        
+        let interpretation = if self.alerts.is_empty() {
+            None
+        } else {
+            Some(self.alerts.iter().map(|alert| FhirCodeableConcept {
+                coding: vec![FhirCoding {
+                    system: "http://terminology.hl7.org/CodeSystem/v3-ObservationInterpretation".to_string(),
+                    code: "AA".to_string(),
+                    display: "Critical abnormal".to_string(),
+                }],
+                text: Some(alert.description().to_string()),
+            }).collect())
+        };
+        
+        FhirObservation {
+            resource_type: "Observation".to_string(),
+            id: obs_id,
+            status: "final".to_string(),
+            category: vec![FhirCodeableConcept {
+                coding: vec![FhirCoding {
+                    system: "http://terminology.hl7.org/CodeSystem/observation-category".to_string(),
+                    code: "vital-signs".to_string(),
+                    display: "Vital Signs".to_string(),
+                }],
+                text: None,
+            }],
+            code: FhirCodeableConcept {
+                coding: vec![FhirCoding {
+                    system: "http://loinc.org".to_string(),
+                    code: "85353-1".to_string(),
+                    display: "Vital signs panel".to_string(),
+                }],
+                text: Some("Patient Room Monitoring Panel".to_string()),
+            },
+            subject: Some(self.subject()),
+            effective_date_time: timestamp.clone(),
+            issued: timestamp,
+            performer: Vec::new(),
+            method: Some(ObservationSource::Sensor.to_fhir()),
+            value_quantity: None,
+            component: components,
+            interpretation,
+            note: Vec::new(),
+        }
+    }
+}
+
+impl ObservationSource {
+    fn to_fhir(self) -> FhirCodeableConcept {
+        FhirCodeableConcept {
+            coding: Vec::new(),
+            text: Some(self.method().to_string()),
+        }
+    }
+}
+
+impl ManualObservation {
+    pub fn to_fhir(&self) -> FhirObservation {
+        let (code, display) = self.kind.loinc();
+        let category = match self.kind {
+            ObservationKind::PainScore => ("survey", "Survey"),
+            _ => ("vital-signs", "Vital Signs"),
+        };
+        let subject = match &self.patient_id {
+            Some(id) => FhirReference {
+                reference: format!("Patient/{}", id),
+                display: None,
+            },
+            None => FhirReference {
+                reference: format!("Patient/{}", self.room),
+                display: Some(format!("Occupant of {}", self.room)),
+            },
+        };
+        
+        FhirObservation {
+            resource_type: "Observation".to_string(),
+            id: self.id
+                .map(|id| format!("manual-observation-{}", id))
+                .unwrap_or_else(|| format!("manual-observation-{}", Uuid::new_v4())),
+            status: "final".to_string(),
+            category: vec![FhirCodeableConcept {
+                coding: vec![FhirCoding {
+                    system: "http://terminology.hl7.org/CodeSystem/observation-category".to_string(),
+                    code: category.0.to_string(),
+                    display: category.1.to_string(),
+                }],
+                text: None,
+            }],
+            code: FhirCodeableConcept {
+                coding: vec![FhirCoding {
+                    system: "http://loinc.org".to_string(),
+                    code: code.to_string(),
+                    display: display.to_string(),
+                }],
+                text: None,
+            },
+            subject: Some(subject),
+            effective_date_time: self.observed_at.to_rfc3339(),
+            issued: self.observed_at.to_rfc3339(),
+            performer: vec![FhirReference {
+                reference: format!("Practitioner/{}", self.recorded_by),
+                display: Some(self.recorded_by.clone()),
+            }],
+            method: Some(ObservationSource::Manual.to_fhir()),
+            value_quantity: Some(FhirQuantity {
+                value: self.value,
+                unit: self.kind.unit().to_string(),
+                system: "http://unitsofmeasure.org".to_string(),
+                code: self.kind.unit().to_string(),
+            }),
+            component: Vec::new(),
+            interpretation: None,
+            note: self.note.iter().map(|text| FhirAnnotation { text: text.clone() }).collect(),
+        }
+    }
+}
+
+impl Patient {
+    pub fn to_fhir(&self) -> FhirPatient {
+        FhirPatient {
+            resource_type: "Patient".to_string(),
+            id: self.id.clone(),
+            name: vec![FhirHumanName {
+                name_use: "official".to_string(),
+                family: self.family_name.clone(),
+                given: self.given_names.clone(),
+            }],
+            gender: self.gender.map(|g| g.code().to_string()),
+            birth_date: self.birth_date.map(|d| d.to_string()),
+        }
+    }
+}
+
+impl FhirBundle {
+    pub fn from_events(events: Vec<SensorEvent>, base_url: &str) -> Self {
+        Self::from_observations(events.iter().map(|event| event.to_fhir(base_url)).collect(), base_url)
+    }
+    
+    pub fn from_observations(observations: Vec<FhirObservation>, base_url: &str) -> Self {
+        let entries: Vec<FhirBundleEntry> = observations
+            .into_iter()
+            .map(|obs| FhirBundleEntry {
+                full_url: format!("{}/Observation/{}", base_url, obs.id),
+                resource: obs,
+            })
+            .collect();
+        
+        FhirBundle {
+            resource_type: "Bundle".to_string(),
+            id: Uuid::new_v4().to_string(),
+            bundle_type: "searchset".to_string(),
+            total: entries.len() as u32,
+            timestamp: Utc::now().to_rfc3339(),
+            link: Vec::new(),
+            entry: entries,
+        }
+    }
+    
+    /// Add a link to the page that follows this one
+    pub fn with_next(mut self, url: String) -> Self {
+        self.link.push(FhirBundleLink { relation: "next".to_string(), url });
+        self
+    }
+}
\ No newline at end of file
@@ -0,0 +1,11 @@
+//! Smart Patient Room Monitor - Shared Types
+//!
+//! Models used on the wire by the monitor backend, defined once so that
+//! the server and Rust clients (`patient-monitor-client`) can't drift apart.
+
+pub mod adt;
+pub mod analysis;
+pub mod announce;
+pub mod api;
+pub mod fhir;
+pub mod ws;
@@ -0,0 +1,517 @@
+//! WebSocket messages exchanged with dashboards and clients
+//!
+//! On connect the server sends a `status` message carrying its protocol
+//! version. A client may then send `hello` with the version it speaks and the
+//! capabilities it wants; the server answers `welcome` with what it agreed to
+//! and switches the stream over. Clients that never say hello, such as
+//! displays deployed before version 2, get the version 1 stream: every reading
+//! as a full JSON `sensorReading`.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::api::PassageKind;
+use crate::fhir::{AlertSeverity, AlertType, ResolutionReason, SensorEvent};
+
+/// Newest protocol version the server speaks
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Version of clients that never negotiate
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+fn legacy_protocol_version() -> u32 {
+    LEGACY_PROTOCOL_VERSION
+}
+
+/// Optional stream features a client can ask for in `hello`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    /// After a room's first reading, send `sensorDelta` frames with only the
+    /// values that changed
+    Deltas,
+    /// Binary frames encoded as MessagePack instead of JSON text
+    Msgpack,
+    /// Only readings that raised an alert
+    AlertsOnly,
+    /// A capability from a newer protocol; never granted
+    #[serde(other)]
+    Unknown,
+}
+
+/// Agree on a version and capabilities with a client that asked for
+/// `version` and `requested`. Capabilities need version 2; unknown and
+/// repeated ones are dropped.
+pub fn negotiate(version: u32, requested: &[Capability]) -> (u32, Vec<Capability>) {
+    let version = version.clamp(LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION);
+    if version < 2 {
+        return (version, Vec::new());
+    }
+    let mut granted: Vec<Capability> = Vec::new();
+    for capability in requested {
+        if *capability != Capability::Unknown && !granted.contains(capability) {
+            granted.push(*capability);
+        }
+    }
+    (version, granted)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum WsMessage {
+    #[serde(rename_all = "camelCase")]
+    SensorReading {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<i64>,
+        /// Change-feed position, to resume from after a reconnect
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<i64>,
+        /// Room the reading was taken in
+        #[serde(default)]
+        room: String,
+        temperature: f32,
+        motion: bool,
+        sound_level: i32,
+        /// Relative humidity in percent, from rooms with a humidity sensor
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        humidity: Option<f32>,
+        /// Beats per minute, from rooms with a pulse oximeter
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        heart_rate: Option<i32>,
+        /// Oxygen saturation in percent, from rooms with a pulse oximeter
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        spo2: Option<i32>,
+        /// Whether the door is open, from rooms with a door contact
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        door_open: Option<bool>,
+        /// Ambient light in lux, from rooms with a light sensor
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        light: Option<f32>,
+        /// CO2 in ppm, from rooms with a CO2 sensor
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        co2: Option<i32>,
+        /// Whether the radar sees someone, from rooms with a presence radar
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        presence: Option<bool>,
+        /// Peak acceleration in g, from rooms with an accelerometer
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        acceleration: Option<f32>,
+        timestamp: String,
+        /// Codes of all alerts the reading raised, empty if none
+        #[serde(default)]
+        alerts: Vec<String>,
+        /// Set on historical readings sent in response to a replay request
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        replay: bool,
+    },
+    /// A reading as the change from the room's previous one; values that did
+    /// not change are left out. Alerts are never carried over. A reading
+    /// missing the humidity, a vital sign, the door contact, the light level,
+    /// the CO2 level, the radar's presence or the acceleration the previous
+    /// one had is sent in full instead.
+    #[serde(rename_all = "camelCase")]
+    SensorDelta {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<i64>,
+        room: String,
+        timestamp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        temperature: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        motion: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sound_level: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        humidity: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        heart_rate: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        spo2: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        door_open: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        light: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        co2: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        presence: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        acceleration: Option<f32>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        alerts: Vec<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        replay: bool,
+    },
+    /// An alert ended, by a reading or by staff acknowledging it. Sent to
+    /// every client watching the room, `alertsOnly` ones included.
+    #[serde(rename_all = "camelCase")]
+    AlertResolved {
+        /// Alert episode, as listed by `/api/alerts`
+        id: i64,
+        room: String,
+        /// Client code of the alert, e.g. `FALL_DETECTED`
+        alert: String,
+        /// Reading that raised the alert
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        alert_id: Option<i64>,
+        resolved_at: String,
+        reason: ResolutionReason,
+        /// Reading that resolved it; unset if staff did
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        observation_id: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resolved_by: Option<String>,
+    },
+    /// A room's serial device was unplugged or went silent, or its port
+    /// opened again. Sent to every client watching the room.
+    #[serde(rename_all = "camelCase")]
+    DeviceStatus {
+        room: String,
+        /// Serial port of the device
+        device: String,
+        connected: bool,
+        timestamp: String,
+        /// Why the device is disconnected
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Someone went through a room's door: it opened and closed again. Sent
+    /// to every client watching the room once the passage is classified.
+    #[serde(rename_all = "camelCase")]
+    DoorPassage {
+        room: String,
+        kind: PassageKind,
+        opened_at: String,
+        closed_at: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    ReplayComplete {
+        since: String,
+        count: usize,
+        /// Change-feed position of the last replayed reading
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_seq: Option<i64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Status {
+        connected: bool,
+        message: String,
+        /// Newest protocol version the server speaks
+        #[serde(default = "legacy_protocol_version")]
+        version: u32,
+    },
+    /// Answer to `hello`: the agreed version and the capabilities in effect
+    /// for all following messages
+    Welcome {
+        version: u32,
+        capabilities: Vec<Capability>,
+    },
+    Ping {
+        timestamp: String,
+    },
+    /// An alert for the ward's overhead paging system to speak, sent on the
+    /// announcement channel (`/ws/announcements`) and to announcement webhooks
+    #[serde(rename_all = "camelCase")]
+    Announcement {
+        /// Ready for a text-to-speech engine, IDs spelled out
+        text: String,
+        room: String,
+        /// Phonetic spelling of the room, e.g. `one zero one`
+        room_spoken: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ward: Option<String>,
+        /// Client code of the alert, e.g. `FALL_DETECTED`
+        alert: String,
+        severity: AlertSeverity,
+        /// `created`, or `escalated` when repeated for an alert still
+        /// unacknowledged
+        event: String,
+        timestamp: String,
+    },
+    /// Compact per-room aggregate sent on the ward channel (`/ws/ward`)
+    #[serde(rename_all = "camelCase")]
+    WardSnapshot {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ward: Option<String>,
+        timestamp: String,
+        rooms: Vec<RoomSummary>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomState {
+    Normal,
+    Warning,
+    Critical,
+    /// No reading received recently
+    Offline,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomSummary {
+    pub room: String,
+    pub state: RoomState,
+    /// Alert onsets within the last hour
+    pub alert_count: u32,
+    /// Seconds since motion was last detected, if ever
+    pub last_activity_secs: Option<i64>,
+}
+
+impl From<&SensorEvent> for WsMessage {
+    fn from(event: &SensorEvent) -> Self {
+        WsMessage::SensorReading {
+            id: event.id,
+            seq: event.seq,
+            room: event.room.clone(),
+            temperature: event.reading.temperature,
+            motion: event.reading.motion,
+            sound_level: event.reading.sound_level,
+            humidity: event.reading.humidity,
+            heart_rate: event.reading.heart_rate,
+            spo2: event.reading.spo2,
+            door_open: event.reading.door_open,
+            light: event.reading.light,
+            co2: event.reading.co2,
+            presence: event.reading.presence,
+            acceleration: event.reading.acceleration,
+            timestamp: event.reading.timestamp.to_rfc3339(),
+            alerts: event.alerts.codes(),
+            replay: false,
+        }
+    }
+}
+
+/// Kinds of live message a subscription can pick, named after their `type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MessageKind {
+    /// Readings, in full or as deltas
+    SensorReading,
+    AlertResolved,
+    DeviceStatus,
+    DoorPassage,
+}
+
+/// What a user's `/ws` feed carries, stored server-side so every device they
+/// log in on streams the same feed without setting it up again. An empty
+/// list doesn't filter. Severities only pick readings that raised an alert
+/// of one of them and resolutions of such alerts; device and door messages
+/// are kept regardless.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    #[serde(default)]
+    pub rooms: Vec<String>,
+    #[serde(default)]
+    pub severities: Vec<AlertSeverity>,
+    #[serde(default)]
+    pub message_types: Vec<MessageKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Severity of the alert with client code `code`
+fn code_severity(code: &str) -> Option<AlertSeverity> {
+    AlertType::ALL.iter().find(|alert| alert.code() == code).map(AlertType::severity)
+}
+
+impl Subscription {
+    /// Whether the subscription streams `msg`; messages that aren't live
+    /// events, such as `status` or `ping`, always pass
+    pub fn wants(&self, msg: &WsMessage) -> bool {
+        let (kind, room, alerts) = match msg {
+            WsMessage::SensorReading { room, alerts, .. } | WsMessage::SensorDelta { room, alerts, .. } => {
+                (MessageKind::SensorReading, room, alerts.as_slice())
+            }
+            WsMessage::AlertResolved { room, alert, .. } => {
+                (MessageKind::AlertResolved, room, std::slice::from_ref(alert))
+            }
+            WsMessage::DeviceStatus { room, .. } => (MessageKind::DeviceStatus, room, &[][..]),
+            WsMessage::DoorPassage { room, .. } => (MessageKind::DoorPassage, room, &[][..]),
+            _ => return true,
+        };
+        if !self.rooms.is_empty() && !self.rooms.contains(room) {
+            return false;
+        }
+        if !self.message_types.is_empty() && !self.message_types.contains(&kind) {
+            return false;
+        }
+        match kind {
+            MessageKind::SensorReading | MessageKind::AlertResolved if !self.severities.is_empty() => alerts
+                .iter()
+                .filter_map(|code| code_severity(code))
+                .any(|severity| self.severities.contains(&severity)),
+            _ => true,
+        }
+    }
+}
+
+/// Requests a client can send over the socket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum WsRequest {
+    /// Negotiate the protocol version and capabilities
+    Hello {
+        version: u32,
+        #[serde(default)]
+        capabilities: Vec<Capability>,
+    },
+    /// Resend readings recorded since `since`, e.g. after a background tab wakes up
+    Replay { since: DateTime<Utc> },
+    /// Resend readings stored after change-feed position `after`. Unlike
+    /// `replay` this doesn't miss readings that arrived late with old timestamps.
+    Resume { after: i64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ReadingValues {
+    temperature: f32,
+    motion: bool,
+    sound_level: i32,
+    humidity: Option<f32>,
+    heart_rate: Option<i32>,
+    spo2: Option<i32>,
+    door_open: Option<bool>,
+    light: Option<f32>,
+    co2: Option<i32>,
+    presence: Option<bool>,
+    acceleration: Option<f32>,
+}
+
+impl ReadingValues {
+    /// Whether a channel `previous` had is missing, which a delta can't
+    /// tell from an unchanged one
+    fn lost_since(&self, previous: &ReadingValues) -> bool {
+        (self.humidity.is_none() && previous.humidity.is_some())
+            || (self.heart_rate.is_none() && previous.heart_rate.is_some())
+            || (self.spo2.is_none() && previous.spo2.is_some())
+            || (self.door_open.is_none() && previous.door_open.is_some())
+            || (self.light.is_none() && previous.light.is_some())
+            || (self.co2.is_none() && previous.co2.is_some())
+            || (self.presence.is_none() && previous.presence.is_some())
+            || (self.acceleration.is_none() && previous.acceleration.is_some())
+    }
+}
+
+/// Converts between full readings and deltas for one connection. The server
+/// encodes and the client decodes the messages in the same order, so both
+/// sides hold the same previous reading per room.
+#[derive(Debug, Default)]
+pub struct DeltaCodec {
+    last: HashMap<String, ReadingValues>,
+}
+
+impl DeltaCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace a `sensorReading` by a `sensorDelta` if the room had an earlier
+    /// reading; other messages pass through
+    pub fn encode(&mut self, msg: WsMessage) -> WsMessage {
+        let WsMessage::SensorReading {
+            id, seq, room, temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration,
+            timestamp, alerts, replay,
+        } = msg else {
+            return msg;
+        };
+        let values = ReadingValues { temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration };
+        let previous = self.last.insert(room.clone(), values);
+        let Some(previous) = previous.filter(|p| !values.lost_since(p)) else {
+            return WsMessage::SensorReading {
+                id, seq, room, temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration,
+                timestamp, alerts, replay,
+            };
+        };
+
+        WsMessage::SensorDelta {
+            id,
+            seq,
+            room,
+            timestamp,
+            temperature: (temperature != previous.temperature).then_some(temperature),
+            motion: (motion != previous.motion).then_some(motion),
+            sound_level: (sound_level != previous.sound_level).then_some(sound_level),
+            humidity: humidity.filter(|_| humidity != previous.humidity),
+            heart_rate: heart_rate.filter(|_| heart_rate != previous.heart_rate),
+            spo2: spo2.filter(|_| spo2 != previous.spo2),
+            door_open: door_open.filter(|_| door_open != previous.door_open),
+            light: light.filter(|_| light != previous.light),
+            co2: co2.filter(|_| co2 != previous.co2),
+            presence: presence.filter(|_| presence != previous.presence),
+            acceleration: acceleration.filter(|_| acceleration != previous.acceleration),
+            alerts,
+            replay,
+        }
+    }
+
+    /// Send the room's next reading in full, e.g. after readings of it were
+    /// sent without the codec
+    pub fn forget(&mut self, room: &str) {
+        self.last.remove(room);
+    }
+
+    /// Expand a `sensorDelta` back into a full `sensorReading`. A delta for a
+    /// room without an earlier reading is returned unchanged.
+    pub fn decode(&mut self, msg: WsMessage) -> WsMessage {
+        match msg {
+            WsMessage::SensorReading {
+                ref room, temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration, ..
+            } => {
+                let values = ReadingValues { temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration };
+                self.last.insert(room.clone(), values);
+                msg
+            }
+            WsMessage::SensorDelta {
+                id, seq, room, timestamp, temperature, motion, sound_level, humidity, heart_rate, spo2, door_open, light, co2, presence, acceleration,
+                alerts, replay,
+            } => {
+                let Some(previous) = self.last.get_mut(&room) else {
+                    return WsMessage::SensorDelta {
+                        id, seq, room, timestamp, temperature, motion, sound_level, humidity, heart_rate, spo2,
+                        door_open, light, co2, presence, acceleration, alerts, replay,
+                    };
+                };
+                let values = ReadingValues {
+                    temperature: temperature.unwrap_or(previous.temperature),
+                    motion: motion.unwrap_or(previous.motion),
+                    sound_level: sound_level.unwrap_or(previous.sound_level),
+                    humidity: humidity.or(previous.humidity),
+                    heart_rate: heart_rate.or(previous.heart_rate),
+                    spo2: spo2.or(previous.spo2),
+                    door_open: door_open.or(previous.door_open),
+                    light: light.or(previous.light),
+                    co2: co2.or(previous.co2),
+                    presence: presence.or(previous.presence),
+                    acceleration: acceleration.or(previous.acceleration),
+                };
+                *previous = values;
+                WsMessage::SensorReading {
+                    id,
+                    seq,
+                    room,
+                    temperature: values.temperature,
+                    motion: values.motion,
+                    sound_level: values.sound_level,
+                    humidity: values.humidity,
+                    heart_rate: values.heart_rate,
+                    spo2: values.spo2,
+                    door_open: values.door_open,
+                    light: values.light,
+                    co2: values.co2,
+                    presence: values.presence,
+                    acceleration: values.acceleration,
+                    timestamp,
+                    alerts,
+                    replay,
+                }
+            }
+            msg => msg,
+        }
+    }
+}
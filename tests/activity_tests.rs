@@ -1,49 +1,19 @@
 //! Unit tests for activity analysis functionality
 //! 
-//! These tests verify that activity scoring and sleep analysis work correctly.
+//! These tests verify that activity scoring and sleep analysis work correctly,
+//! that chart series are downsampled without losing their spikes, and which
+//! readings compaction merges.
 
 #[cfg(test)]
 mod tests {
-    
-    // ========================================================================
-    // ACTIVITY ANALYSIS LOGIC
-    // ========================================================================
-    
-    #[derive(Debug, Clone, PartialEq)]
-    pub enum ActivityLevel {
-        DeepSleep,
-        LightSleep,
-        Restless,
-        Active,
-    }
-    
-    /// Calculate activity score as percentage
-    fn calculate_activity_score(motion_readings: u64, total_readings: u64) -> f64 {
-        if total_readings == 0 {
-            return 0.0;
-        }
-        (motion_readings as f64 / total_readings as f64) * 100.0
-    }
-    
-    /// Determine activity level based on score
-    fn get_activity_level(score: f64) -> ActivityLevel {
-        match score {
-            s if s < 20.0 => ActivityLevel::DeepSleep,
-            s if s < 40.0 => ActivityLevel::LightSleep,
-            s if s < 60.0 => ActivityLevel::Restless,
-            _ => ActivityLevel::Active,
-        }
-    }
-    
-    /// Get rest quality description
-    fn get_rest_quality(score: f64) -> &'static str {
-        match score {
-            s if s < 20.0 => "Excellent",
-            s if s < 40.0 => "Good",
-            s if s < 60.0 => "Fair",
-            _ => "Poor",
-        }
-    }
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use patient_monitor_types::analysis::{
+        activity_level, activity_score, compaction_runs, count_bed_exits, count_night_awakenings, fall_risk_level,
+        fall_risk_score, longest_still_period, longest_still_periods, lttb, rest_quality, state_periods, still_periods, CompactionRow,
+        DoorTracker, LightSums, MatrixRow, MatrixStat, PatientStateTracker, ReadingRun, SoundMatrix,
+    };
+    use patient_monitor_types::api::{DoorPassage, FallRiskFactors, PassageKind, PatientState, StatePeriod, StillPeriod, StillPeriods};
+    use patient_monitor_types::fhir::SensorReading;
     
     // ========================================================================
     // ACTIVITY SCORE TESTS
@@ -51,47 +21,47 @@ mod tests {
     
     #[test]
     fn test_activity_score_zero_motion() {
-        let score = calculate_activity_score(0, 100);
+        let score = activity_score(0, 100);
         assert_eq!(score, 0.0);
     }
     
     #[test]
     fn test_activity_score_all_motion() {
-        let score = calculate_activity_score(100, 100);
+        let score = activity_score(100, 100);
         assert_eq!(score, 100.0);
     }
     
     #[test]
     fn test_activity_score_half_motion() {
-        let score = calculate_activity_score(50, 100);
+        let score = activity_score(50, 100);
         assert_eq!(score, 50.0);
     }
     
     #[test]
     fn test_activity_score_quarter_motion() {
-        let score = calculate_activity_score(25, 100);
+        let score = activity_score(25, 100);
         assert_eq!(score, 25.0);
     }
     
     #[test]
     fn test_activity_score_zero_readings() {
-        let score = calculate_activity_score(0, 0);
+        let score = activity_score(0, 0);
         assert_eq!(score, 0.0);  // Should handle division by zero
     }
     
     #[test]
     fn test_activity_score_more_motion_than_total() {
         // Edge case: shouldn't happen but should handle gracefully
-        let score = calculate_activity_score(150, 100);
+        let score = activity_score(150, 100);
         assert_eq!(score, 150.0);
     }
     
     #[test]
     fn test_activity_score_precision() {
-        let score = calculate_activity_score(33, 100);
+        let score = activity_score(33, 100);
         assert_eq!(score, 33.0);
         
-        let score2 = calculate_activity_score(1, 3);
+        let score2 = activity_score(1, 3);
         assert!((score2 - 33.333).abs() < 0.01);
     }
     
@@ -101,41 +71,41 @@ mod tests {
     
     #[test]
     fn test_deep_sleep_level() {
-        assert_eq!(get_activity_level(0.0), ActivityLevel::DeepSleep);
-        assert_eq!(get_activity_level(10.0), ActivityLevel::DeepSleep);
-        assert_eq!(get_activity_level(19.9), ActivityLevel::DeepSleep);
+        assert_eq!(activity_level(0.0), "deep_sleep");
+        assert_eq!(activity_level(10.0), "deep_sleep");
+        assert_eq!(activity_level(19.9), "deep_sleep");
     }
     
     #[test]
     fn test_light_sleep_level() {
-        assert_eq!(get_activity_level(20.0), ActivityLevel::LightSleep);
-        assert_eq!(get_activity_level(30.0), ActivityLevel::LightSleep);
-        assert_eq!(get_activity_level(39.9), ActivityLevel::LightSleep);
+        assert_eq!(activity_level(20.0), "light_sleep");
+        assert_eq!(activity_level(30.0), "light_sleep");
+        assert_eq!(activity_level(39.9), "light_sleep");
     }
     
     #[test]
     fn test_restless_level() {
-        assert_eq!(get_activity_level(40.0), ActivityLevel::Restless);
-        assert_eq!(get_activity_level(50.0), ActivityLevel::Restless);
-        assert_eq!(get_activity_level(59.9), ActivityLevel::Restless);
+        assert_eq!(activity_level(40.0), "restless");
+        assert_eq!(activity_level(50.0), "restless");
+        assert_eq!(activity_level(59.9), "restless");
     }
     
     #[test]
     fn test_active_level() {
-        assert_eq!(get_activity_level(60.0), ActivityLevel::Active);
-        assert_eq!(get_activity_level(80.0), ActivityLevel::Active);
-        assert_eq!(get_activity_level(100.0), ActivityLevel::Active);
+        assert_eq!(activity_level(60.0), "active");
+        assert_eq!(activity_level(80.0), "active");
+        assert_eq!(activity_level(100.0), "active");
     }
     
     #[test]
     fn test_activity_level_boundaries() {
         // Test exact boundaries
-        assert_eq!(get_activity_level(19.999), ActivityLevel::DeepSleep);
-        assert_eq!(get_activity_level(20.0), ActivityLevel::LightSleep);
-        assert_eq!(get_activity_level(39.999), ActivityLevel::LightSleep);
-        assert_eq!(get_activity_level(40.0), ActivityLevel::Restless);
-        assert_eq!(get_activity_level(59.999), ActivityLevel::Restless);
-        assert_eq!(get_activity_level(60.0), ActivityLevel::Active);
+        assert_eq!(activity_level(19.999), "deep_sleep");
+        assert_eq!(activity_level(20.0), "light_sleep");
+        assert_eq!(activity_level(39.999), "light_sleep");
+        assert_eq!(activity_level(40.0), "restless");
+        assert_eq!(activity_level(59.999), "restless");
+        assert_eq!(activity_level(60.0), "active");
     }
     
     // ========================================================================
@@ -144,26 +114,26 @@ mod tests {
     
     #[test]
     fn test_excellent_rest_quality() {
-        assert_eq!(get_rest_quality(0.0), "Excellent");
-        assert_eq!(get_rest_quality(15.0), "Excellent");
+        assert_eq!(rest_quality(0.0), "Excellent");
+        assert_eq!(rest_quality(15.0), "Excellent");
     }
     
     #[test]
     fn test_good_rest_quality() {
-        assert_eq!(get_rest_quality(20.0), "Good");
-        assert_eq!(get_rest_quality(35.0), "Good");
+        assert_eq!(rest_quality(20.0), "Good");
+        assert_eq!(rest_quality(35.0), "Good");
     }
     
     #[test]
     fn test_fair_rest_quality() {
-        assert_eq!(get_rest_quality(40.0), "Fair");
-        assert_eq!(get_rest_quality(55.0), "Fair");
+        assert_eq!(rest_quality(40.0), "Fair");
+        assert_eq!(rest_quality(55.0), "Fair");
     }
     
     #[test]
     fn test_poor_rest_quality() {
-        assert_eq!(get_rest_quality(60.0), "Poor");
-        assert_eq!(get_rest_quality(100.0), "Poor");
+        assert_eq!(rest_quality(60.0), "Poor");
+        assert_eq!(rest_quality(100.0), "Poor");
     }
     
     // ========================================================================
@@ -175,12 +145,12 @@ mod tests {
         let motion_readings = 10;
         let total_readings = 100;
         
-        let score = calculate_activity_score(motion_readings, total_readings);
-        let level = get_activity_level(score);
-        let quality = get_rest_quality(score);
+        let score = activity_score(motion_readings, total_readings);
+        let level = activity_level(score);
+        let quality = rest_quality(score);
         
         assert_eq!(score, 10.0);
-        assert_eq!(level, ActivityLevel::DeepSleep);
+        assert_eq!(level, "deep_sleep");
         assert_eq!(quality, "Excellent");
     }
     
@@ -189,12 +159,12 @@ mod tests {
         let motion_readings = 45;
         let total_readings = 100;
         
-        let score = calculate_activity_score(motion_readings, total_readings);
-        let level = get_activity_level(score);
-        let quality = get_rest_quality(score);
+        let score = activity_score(motion_readings, total_readings);
+        let level = activity_level(score);
+        let quality = rest_quality(score);
         
         assert_eq!(score, 45.0);
-        assert_eq!(level, ActivityLevel::Restless);
+        assert_eq!(level, "restless");
         assert_eq!(quality, "Fair");
     }
     
@@ -203,12 +173,12 @@ mod tests {
         let motion_readings = 75;
         let total_readings = 100;
         
-        let score = calculate_activity_score(motion_readings, total_readings);
-        let level = get_activity_level(score);
-        let quality = get_rest_quality(score);
+        let score = activity_score(motion_readings, total_readings);
+        let level = activity_level(score);
+        let quality = rest_quality(score);
         
         assert_eq!(score, 75.0);
-        assert_eq!(level, ActivityLevel::Active);
+        assert_eq!(level, "active");
         assert_eq!(quality, "Poor");
     }
     
@@ -218,22 +188,496 @@ mod tests {
     
     #[test]
     fn test_single_reading_with_motion() {
-        let score = calculate_activity_score(1, 1);
+        let score = activity_score(1, 1);
         assert_eq!(score, 100.0);
-        assert_eq!(get_activity_level(score), ActivityLevel::Active);
+        assert_eq!(activity_level(score), "active");
     }
     
     #[test]
     fn test_single_reading_without_motion() {
-        let score = calculate_activity_score(0, 1);
+        let score = activity_score(0, 1);
         assert_eq!(score, 0.0);
-        assert_eq!(get_activity_level(score), ActivityLevel::DeepSleep);
+        assert_eq!(activity_level(score), "deep_sleep");
     }
     
     #[test]
     fn test_large_number_of_readings() {
-        let score = calculate_activity_score(5000, 10000);
+        let score = activity_score(5000, 10000);
         assert_eq!(score, 50.0);
-        assert_eq!(get_activity_level(score), ActivityLevel::Restless);
+        assert_eq!(activity_level(score), "restless");
+    }
+    
+    // ========================================================================
+    // STILL PERIOD TESTS
+    // ========================================================================
+    
+    /// Readings one minute apart starting at 22:00, `true` = motion
+    fn readings(motion: &[bool]) -> Vec<(DateTime<Utc>, bool)> {
+        let start = Utc.with_ymd_and_hms(2024, 1, 15, 22, 0, 0).unwrap();
+        motion
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (start + Duration::minutes(i as i64), *m))
+            .collect()
+    }
+    
+    fn end_of(readings: &[(DateTime<Utc>, bool)]) -> DateTime<Utc> {
+        readings.last().map(|(t, _)| *t).unwrap()
+    }
+    
+    #[test]
+    fn test_still_period_no_readings() {
+        let end = Utc.with_ymd_and_hms(2024, 1, 15, 23, 0, 0).unwrap();
+        assert_eq!(longest_still_period(Vec::new(), end), 0);
+    }
+    
+    #[test]
+    fn test_still_period_constant_motion() {
+        let data = readings(&[true, true, true, true]);
+        assert_eq!(longest_still_period(data.clone(), end_of(&data)), 0);
+    }
+    
+    #[test]
+    fn test_still_period_ended_by_motion() {
+        // Still from minute 1 until motion at minute 4
+        let data = readings(&[true, false, false, false, true]);
+        assert_eq!(longest_still_period(data.clone(), end_of(&data)), 3);
+    }
+    
+    #[test]
+    fn test_still_period_longest_of_several() {
+        let data = readings(&[false, false, true, false, false, false, false, true, false, true]);
+        assert_eq!(longest_still_period(data.clone(), end_of(&data)), 4);
+    }
+    
+    #[test]
+    fn test_still_period_runs_until_end() {
+        // Still since minute 1 and never ended; counted up to the period end
+        let data = readings(&[true, false, false]);
+        let end = end_of(&data) + Duration::minutes(30);
+        assert_eq!(longest_still_period(data, end), 31);
+    }
+    
+    #[test]
+    fn test_still_periods_each_run() {
+        let data = readings(&[false, false, true, false, false, false, false, true, false]);
+        assert_eq!(still_periods(data.clone(), end_of(&data)), vec![2, 4, 0]);
+    }
+    
+    #[test]
+    fn test_still_period_distribution() {
+        let stats = StillPeriods::from_durations([0, 14, 15, 45, 240, 600]);
+        assert_eq!(stats.count, 6);
+        let counts: Vec<u64> = stats.distribution.iter().map(|b| b.count).collect();
+        assert_eq!(counts, vec![2, 1, 1, 0, 0, 2]);
+        assert_eq!(stats.distribution[0].min_mins, 0);
+        assert_eq!(stats.distribution[0].max_mins, Some(15));
+        assert_eq!(stats.distribution[5].min_mins, 240);
+        assert_eq!(stats.distribution[5].max_mins, None);
+    }
+    
+    #[test]
+    fn test_longest_still_periods_top_n() {
+        let period = |room: &str, start: i64, mins: u64| StillPeriod {
+            room: room.to_string(),
+            start: minute(start),
+            end: minute(start + mins as i64),
+            duration_mins: mins,
+        };
+        let periods = vec![
+            period("room-101", 0, 20),
+            period("room-102", 5, 90),
+            period("room-101", 30, 45),
+            period("room-102", 100, 3),
+            period("room-101", 80, 45),
+        ];
+        let top = longest_still_periods(periods, 3);
+        assert_eq!(top, vec![period("room-102", 5, 90), period("room-101", 30, 45), period("room-101", 80, 45)]);
+    }
+    
+    // ========================================================================
+    // FALL RISK TESTS
+    // ========================================================================
+    
+    fn factors(falls: u64, night_restlessness: f64, bed_exits_per_night: f64) -> FallRiskFactors {
+        FallRiskFactors { falls, night_restlessness, bed_exits_per_night }
+    }
+    
+    #[test]
+    fn test_fall_risk_no_factors() {
+        assert_eq!(fall_risk_score(&factors(0, 0.0, 0.0)), 0.0);
+    }
+    
+    #[test]
+    fn test_fall_risk_all_factors_capped() {
+        assert_eq!(fall_risk_score(&factors(10, 80.0, 9.0)), 100.0);
+    }
+    
+    #[test]
+    fn test_fall_risk_falls_weigh_half() {
+        // 3 falls reach the cap
+        assert_eq!(fall_risk_score(&factors(3, 0.0, 0.0)), 50.0);
+        assert_eq!(fall_risk_score(&factors(1, 0.0, 0.0)), 16.7);
+    }
+    
+    #[test]
+    fn test_fall_risk_night_factors() {
+        // 15% restless minutes is half the cap, 2 bed exits a night half the cap
+        assert_eq!(fall_risk_score(&factors(0, 15.0, 0.0)), 12.5);
+        assert_eq!(fall_risk_score(&factors(0, 0.0, 2.0)), 12.5);
+    }
+    
+    #[test]
+    fn test_fall_risk_levels() {
+        assert_eq!(fall_risk_level(0.0), "low");
+        assert_eq!(fall_risk_level(29.9), "low");
+        assert_eq!(fall_risk_level(30.0), "moderate");
+        assert_eq!(fall_risk_level(59.9), "moderate");
+        assert_eq!(fall_risk_level(60.0), "high");
+    }
+    
+    #[test]
+    fn test_bed_exits_after_rest() {
+        // Motion after 3 still minutes counts, after 1 it doesn't
+        let data = readings(&[false, false, false, true, false, true]);
+        assert_eq!(count_bed_exits(data, 3), 1);
+    }
+    
+    #[test]
+    fn test_bed_exits_continuous_motion() {
+        let data = readings(&[false, false, true, true, true]);
+        assert_eq!(count_bed_exits(data, 2), 1);
+    }
+    
+    #[test]
+    fn test_bed_exits_gap_interrupts_rest() {
+        // Two still minutes, a 10 minute gap without readings, then motion
+        let mut data = readings(&[false, false]);
+        data.push((end_of(&data) + Duration::minutes(10), false));
+        data.push((end_of(&data) + Duration::minutes(1), true));
+        assert_eq!(count_bed_exits(data, 3), 0);
+    }
+    
+    // ========================================================================
+    // NIGHT AWAKENING TESTS
+    // ========================================================================
+    
+    /// Minute readings from runs of `(motion, minutes)`
+    fn night(runs: &[(bool, usize)]) -> Vec<(DateTime<Utc>, bool)> {
+        let motion: Vec<bool> = runs.iter().flat_map(|&(m, n)| std::iter::repeat_n(m, n)).collect();
+        readings(&motion)
+    }
+    
+    #[test]
+    fn test_night_awakenings_bathroom_trips() {
+        let data = night(&[(false, 40), (true, 5), (false, 60), (true, 8), (false, 35)]);
+        assert_eq!(count_night_awakenings(data), 2);
+    }
+    
+    #[test]
+    fn test_night_awakenings_pause_within_trip() {
+        // Out of the sensor's view in the bathroom for 6 minutes: one trip
+        let data = night(&[(false, 40), (true, 3), (false, 6), (true, 3), (false, 40)]);
+        assert_eq!(count_night_awakenings(data), 1);
+    }
+    
+    #[test]
+    fn test_night_awakenings_need_stillness_around() {
+        // Too little stillness before, and getting up in the morning
+        let data = night(&[(false, 10), (true, 5), (false, 40), (true, 5)]);
+        assert_eq!(count_night_awakenings(data), 0);
+    }
+    
+    #[test]
+    fn test_night_awakenings_long_burst_not_counted() {
+        let data = night(&[(false, 40), (true, 45), (false, 40)]);
+        assert_eq!(count_night_awakenings(data), 0);
+    }
+    
+    #[test]
+    fn test_night_awakenings_gap_interrupts_stillness() {
+        // The stillness before the burst is split by a 10 minute gap
+        let mut data = night(&[(false, 20)]);
+        let later = night(&[(false, 20), (true, 5), (false, 40)]);
+        data.extend(later.into_iter().map(|(t, m)| (t + Duration::minutes(30), m)));
+        assert_eq!(count_night_awakenings(data), 0);
+    }
+    
+    fn light_sums(readings: &[(f32, bool, usize)]) -> LightSums {
+        let mut sums = LightSums::default();
+        for &(lux, motion, count) in readings {
+            for _ in 0..count {
+                sums.add(lux, motion);
+            }
+        }
+        sums
+    }
+    
+    #[test]
+    fn test_night_light_dark_night() {
+        assert_eq!(LightSums::default().night_light(), None);
+        
+        let light = light_sums(&[(1.0, false, 10), (1.0, true, 2)]).night_light().unwrap();
+        assert!(!light.lights_on);
+        assert_eq!(light.lit_percent, 0.0);
+        assert_eq!(light.avg_lux, 1.0);
+        assert_eq!(light.lit_motion_percent, None);
+        assert_eq!(light.dark_motion_percent, Some(16.7));
+        // The light level didn't change
+        assert_eq!(light.light_motion_correlation, None);
+    }
+    
+    #[test]
+    fn test_night_light_correlates_with_restlessness() {
+        let light = light_sums(&[(0.0, false, 6), (0.0, true, 2), (200.0, false, 1), (200.0, true, 3)])
+            .night_light()
+            .unwrap();
+        assert!(light.lights_on);
+        assert_eq!(light.lit_readings, 4);
+        assert_eq!(light.lit_percent, 33.3);
+        assert_eq!(light.avg_lux, 66.7);
+        assert_eq!(light.lit_motion_percent, Some(75.0));
+        assert_eq!(light.dark_motion_percent, Some(25.0));
+        assert_eq!(light.light_motion_correlation, Some(0.48));
+    }
+    
+    // ========================================================================
+    // ROOM STATE TESTS
+    // ========================================================================
+    
+    fn minute(m: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 15, 22, 0, 0).unwrap() + Duration::minutes(m)
+    }
+    
+    #[test]
+    fn test_room_state_without_bed_sensor() {
+        let mut tracker = PatientStateTracker::new();
+        assert_eq!(tracker.reading(minute(0), true), Some((PatientState::Moving, minute(0))));
+        assert_eq!(tracker.reading(minute(1), true), None);
+        assert_eq!(tracker.reading(minute(4), false), None);
+        // Five still minutes after the last motion: in bed since then
+        assert_eq!(tracker.reading(minute(6), false), Some((PatientState::InBed, minute(1))));
+        assert_eq!(tracker.reading(minute(30), false), None);
+        assert_eq!(tracker.reading(minute(31), true), Some((PatientState::Moving, minute(31))));
+    }
+    
+    #[test]
+    fn test_room_state_with_bed_sensor() {
+        let mut tracker = PatientStateTracker::new();
+        assert_eq!(tracker.bed(minute(0), true), Some((PatientState::InBed, minute(0))));
+        // Turning over in bed
+        assert_eq!(tracker.reading(minute(5), true), None);
+        assert_eq!(tracker.bed(minute(20), false), Some((PatientState::Moving, minute(20))));
+        assert_eq!(tracker.reading(minute(21), true), None);
+        // Out of the motion sensor's view for ten minutes with the bed empty
+        assert_eq!(tracker.reading(minute(25), false), None);
+        assert_eq!(tracker.reading(minute(31), false), Some((PatientState::OutOfRoom, minute(21))));
+        assert_eq!(tracker.reading(minute(40), true), Some((PatientState::Moving, minute(40))));
+        assert_eq!(tracker.bed(minute(41), true), Some((PatientState::InBed, minute(41))));
+        assert_eq!(tracker.bed(minute(42), true), None);
+    }
+    
+    #[test]
+    fn test_room_state_unknown_until_derived() {
+        let mut tracker = PatientStateTracker::new();
+        assert_eq!(tracker.reading(minute(0), false), None);
+        assert_eq!(tracker.state(), None);
+        assert_eq!(tracker.reading(minute(5), false), Some((PatientState::InBed, minute(0))));
+    }
+    
+    #[test]
+    fn test_state_periods_clamped_and_merged() {
+        let changes = [
+            (PatientState::InBed, minute(-60)),
+            (PatientState::Moving, minute(10)),
+            (PatientState::Moving, minute(20)),
+            (PatientState::OutOfRoom, minute(30)),
+        ];
+        let periods = state_periods(&changes, minute(0), minute(40));
+        assert_eq!(periods, vec![
+            StatePeriod { state: PatientState::InBed, start: minute(0), end: minute(10) },
+            StatePeriod { state: PatientState::Moving, start: minute(10), end: minute(30) },
+            StatePeriod { state: PatientState::OutOfRoom, start: minute(30), end: minute(40) },
+        ]);
+    }
+    
+    #[test]
+    fn test_state_periods_before_first_state() {
+        let changes = [(PatientState::Moving, minute(10))];
+        let periods = state_periods(&changes, minute(0), minute(5));
+        assert!(periods.is_empty());
+        let periods = state_periods(&changes, minute(0), minute(15));
+        assert_eq!(periods, vec![StatePeriod { state: PatientState::Moving, start: minute(10), end: minute(15) }]);
+    }
+    
+    fn door(tracker: &mut DoorTracker, secs: i64, motion: bool, open: bool) -> Option<DoorPassage> {
+        tracker.reading(&SensorReading {
+            temperature: 22.0,
+            motion,
+            sound_level: 30,
+            door_open: Some(open),
+            timestamp: minute(0) + Duration::seconds(secs),
+            ..Default::default()
+        })
+    }
+    
+    #[test]
+    fn test_door_passage_entry_on_motion_after() {
+        let mut tracker = DoorTracker::new();
+        assert_eq!(door(&mut tracker, 0, false, false), None);
+        assert_eq!(door(&mut tracker, 100, false, true), None);
+        // Motion while the door is open doesn't tell which way
+        assert_eq!(door(&mut tracker, 105, true, true), None);
+        assert_eq!(door(&mut tracker, 110, false, false), None);
+        let entry = door(&mut tracker, 115, true, false).unwrap();
+        assert_eq!(entry.kind, PassageKind::Entry);
+        assert_eq!((entry.opened_at, entry.closed_at), (minute(0) + Duration::seconds(100), minute(0) + Duration::seconds(110)));
+    }
+    
+    #[test]
+    fn test_door_passage_exit_after_still_window() {
+        let mut tracker = DoorTracker::new();
+        assert_eq!(door(&mut tracker, 0, true, false), None);
+        assert_eq!(door(&mut tracker, 30, false, true), None);
+        assert_eq!(door(&mut tracker, 40, false, false), None);
+        assert_eq!(door(&mut tracker, 90, false, false), None);
+        assert_eq!(door(&mut tracker, 100, false, false).map(|p| p.kind), Some(PassageKind::Exit));
+        assert_eq!(door(&mut tracker, 200, false, false), None);
+        
+        // Motion on both sides, e.g. a nurse looking in; decided by the door opening again
+        assert_eq!(door(&mut tracker, 300, true, false), None);
+        assert_eq!(door(&mut tracker, 310, false, true), None);
+        assert_eq!(door(&mut tracker, 320, false, false), None);
+        assert_eq!(door(&mut tracker, 330, true, false).map(|p| p.kind), Some(PassageKind::Passage));
+        assert_eq!(door(&mut tracker, 340, false, true), None);
+        assert_eq!(door(&mut tracker, 345, false, false), None);
+        assert_eq!(door(&mut tracker, 350, false, true).map(|p| p.kind), Some(PassageKind::Exit));
+    }
+    
+    // ========================================================================
+    // CHART DOWNSAMPLING TESTS
+    // ========================================================================
+    
+    #[test]
+    fn test_lttb_keeps_short_series_whole() {
+        let points: Vec<(f64, f64)> = (0..10).map(|i| (i as f64, 20.0)).collect();
+        assert_eq!(lttb(&points, 500), (0..10).collect::<Vec<_>>());
+        assert_eq!(lttb(&points, 2).len(), 10);
+    }
+    
+    #[test]
+    fn test_lttb_reduces_to_threshold_keeping_ends() {
+        let points: Vec<(f64, f64)> = (0..10_000).map(|i| (i as f64, (i as f64 / 50.0).sin())).collect();
+        let kept = lttb(&points, 500);
+        
+        assert_eq!(kept.len(), 500);
+        assert_eq!(kept[0], 0);
+        assert_eq!(kept[499], 9_999);
+        assert!(kept.windows(2).all(|w| w[0] < w[1]));
+    }
+    
+    #[test]
+    fn test_lttb_keeps_spike() {
+        // A quiet room with one loud fall
+        let mut points: Vec<(f64, f64)> = (0..5_000).map(|i| (i as f64, 30.0)).collect();
+        points[2_345].1 = 250.0;
+        let kept = lttb(&points, 100);
+        
+        assert!(kept.contains(&2_345));
+    }
+    
+    // ========================================================================
+    // COMPACTION TESTS
+    // ========================================================================
+    
+    fn row(id: i64, secs: i64, temperature: f32, mergeable: bool) -> CompactionRow {
+        let at = Utc.with_ymd_and_hms(2024, 1, 15, 2, 0, 0).unwrap() + Duration::seconds(secs);
+        CompactionRow {
+            id,
+            start: at,
+            end: at,
+            count: 1,
+            temperature,
+            sound_level: 30,
+            patient_id: Some("p-1".to_string()),
+            mergeable,
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn test_compaction_merges_identical_runs() {
+        let rows = vec![
+            row(1, 0, 21.5, true),
+            row(2, 1, 21.5, true),
+            row(3, 2, 21.5, true),
+            row(4, 3, 21.6, true),
+            row(5, 4, 21.6, false),
+            row(6, 5, 21.6, true),
+            row(7, 6, 21.6, true),
+        ];
+        let runs = compaction_runs(&rows, Duration::seconds(60), Duration::hours(1));
+        
+        assert_eq!(runs, vec![
+            ReadingRun { id: 1, end: rows[2].end, count: 3, merged: vec![2, 3] },
+            ReadingRun { id: 6, end: rows[6].end, count: 2, merged: vec![7] },
+        ]);
+    }
+    
+    #[test]
+    fn test_compaction_extends_interval() {
+        let mut interval = row(1, 0, 21.5, true);
+        interval.end = interval.start + Duration::seconds(99);
+        interval.count = 100;
+        let rows = vec![interval, row(2, 100, 21.5, true)];
+        let runs = compaction_runs(&rows, Duration::seconds(60), Duration::hours(1));
+        
+        assert_eq!(runs, vec![ReadingRun { id: 1, end: rows[1].end, count: 101, merged: vec![2] }]);
+    }
+    
+    #[test]
+    fn test_compaction_stops_at_gaps_and_span() {
+        // A device outage between the second and third reading
+        let rows = vec![row(1, 0, 21.5, true), row(2, 1, 21.5, true), row(3, 600, 21.5, true)];
+        let runs = compaction_runs(&rows, Duration::seconds(60), Duration::hours(1));
+        assert_eq!(runs, vec![ReadingRun { id: 1, end: rows[1].end, count: 2, merged: vec![2] }]);
+        
+        let rows: Vec<_> = (0..5).map(|i| row(i, i * 30, 21.5, true)).collect();
+        let runs = compaction_runs(&rows, Duration::seconds(60), Duration::seconds(60));
+        assert_eq!(runs.iter().map(|r| r.count).collect::<Vec<_>>(), vec![3, 2]);
+    }
+    
+    #[test]
+    fn test_sound_matrix_aligns_rooms_to_steps() {
+        let t0 = Utc.with_ymd_and_hms(2024, 1, 15, 22, 0, 0).unwrap();
+        let at = |secs| t0 + Duration::seconds(secs);
+        let rooms = vec!["room-101".to_string(), "room-102".to_string()];
+        // The window starts mid-step; rows start at whole minutes
+        let mut matrix = SoundMatrix::new(rooms.clone(), at(30), at(240), Duration::seconds(60), MatrixStat::Mean);
+        assert_eq!(matrix.steps(), 4);
+        
+        let mut rows = Vec::new();
+        rows.extend(matrix.push("room-101", at(30), 40));
+        rows.extend(matrix.push("room-102", at(45), 21));
+        rows.extend(matrix.push("room-101", at(50), 45));
+        // Rooms not in the matrix are ignored
+        rows.extend(matrix.push("room-999", at(55), 900));
+        // Completes the first step and passes an empty one
+        rows.extend(matrix.push("room-102", at(130), 300));
+        assert_eq!(rows.len(), 2);
+        rows.extend(matrix.finish());
+        
+        assert_eq!(rows, vec![
+            MatrixRow { start: t0, levels: vec![Some(42.5), Some(21.0)] },
+            MatrixRow { start: at(60), levels: vec![None, None] },
+            MatrixRow { start: at(120), levels: vec![None, Some(300.0)] },
+            MatrixRow { start: at(180), levels: vec![None, None] },
+        ]);
+        
+        let mut matrix = SoundMatrix::new(rooms, t0, at(60), Duration::seconds(60), MatrixStat::Max);
+        matrix.push("room-101", at(1), 40);
+        matrix.push("room-101", at(2), 220);
+        matrix.push("room-101", at(3), 35);
+        assert_eq!(matrix.finish()[0].levels, vec![Some(220.0), None]);
     }
 }
@@ -1,242 +1,586 @@
 //! Unit tests for alert detection logic
-//! 
-//! These tests verify that fall detection and inactivity alerts work correctly.
+//!
+//! These tests verify that fall detection, inactivity, sensor-fault and
+//! ventilation alerts work correctly, that open alerts resolve by their type's
+//! policy, and that alert precision is computed from the outcomes staff record.
 
 #[cfg(test)]
 mod tests {
-    
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use patient_monitor_types::analysis::{
+        detect_alerts, detect_fall_after_motion, detect_vital_alerts, AutoResolve, CalibrationLimit, FlatlineDetector,
+        ImpactFallDetector, ImpactLimit, MotionSource, ResolutionPolicies, TemperatureCrossCheck, VentilationDetector, VentilationLimit, VitalLimits, FALL_MOTION_WINDOW_SECS,
+    };
+    use patient_monitor_types::announce::{spell, AnnouncementTemplate};
+    use patient_monitor_types::api::{MonitorSettings, OutcomeCounts};
+    use patient_monitor_types::fhir::{AlertOutcome, AlertSet, AlertType, ResolutionReason, SensorChannel, SensorReading};
+
     // ========================================================================
-    // ALERT DETECTION LOGIC (same logic as your serial.rs)
+    // HELPERS
     // ========================================================================
-    
-    #[derive(Debug, Clone, PartialEq)]
-    pub enum AlertType {
-        None,
-        Fall,
-        Inactivity,
-    }
-    
-    /// Detect alerts based on sensor readings
-    /// This is the core logic that should match your serial.rs implementation
+
+    /// Run the server's alert detection for a single reading
     fn detect_alert(
         motion: bool,
         sound_level: i32,
         sound_threshold: i32,
         seconds_since_motion: u64,
         inactivity_threshold: u64,
-    ) -> AlertType {
-        // Fall detection: motion + loud sound = possible fall
-        if motion && sound_level > sound_threshold {
-            return AlertType::Fall;
-        }
-        
-        // Inactivity detection: no motion for too long
-        if seconds_since_motion > inactivity_threshold {
-            return AlertType::Inactivity;
-        }
-        
-        AlertType::None
+    ) -> AlertSet {
+        let reading = SensorReading {
+            temperature: 23.0,
+            motion,
+            sound_level,
+            timestamp: Utc::now(),
+            ..Default::default()
+        };
+        let settings = MonitorSettings {
+            inactivity_seconds: inactivity_threshold,
+            sound_threshold,
+        };
+
+        detect_alerts(&reading, &settings, seconds_since_motion)
     }
-    
+
     // ========================================================================
     // FALL DETECTION TESTS
     // ========================================================================
-    
+
     #[test]
     fn test_fall_detected_with_motion_and_loud_sound() {
-        let alert = detect_alert(
-            motion: true,
-            sound_level: 200,
-            sound_threshold: 150,
-            seconds_since_motion: 0,
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::Fall);
-    }
-    
+        let alerts = detect_alert(true, 200, 150, 0, 300);
+
+        assert_eq!(alerts, AlertSet::from(AlertType::Fall));
+    }
+
     #[test]
     fn test_no_fall_when_sound_below_threshold() {
-        let alert = detect_alert(
-            motion: true,
-            sound_level: 100,  // Below threshold of 150
-            sound_threshold: 150,
-            seconds_since_motion: 0,
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::None);
-    }
-    
+        // Sound of 100 is below the threshold of 150
+        let alerts = detect_alert(true, 100, 150, 0, 300);
+
+        assert!(alerts.is_empty());
+    }
+
     #[test]
     fn test_no_fall_when_no_motion() {
-        let alert = detect_alert(
-            motion: false,  // No motion
-            sound_level: 200,  // Loud sound
-            sound_threshold: 150,
-            seconds_since_motion: 10,
-            inactivity_threshold: 300,
-        );
-        
+        // Loud sound without motion
+        let alerts = detect_alert(false, 200, 150, 10, 300);
+
         // Loud sound without motion is NOT a fall (could be external noise)
-        assert_eq!(alert, AlertType::None);
+        assert!(alerts.is_empty());
     }
-    
+
     #[test]
     fn test_fall_detected_at_exact_threshold() {
-        let alert = detect_alert(
-            motion: true,
-            sound_level: 151,  // Just above threshold
-            sound_threshold: 150,
-            seconds_since_motion: 0,
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::Fall);
-    }
-    
+        // Just above threshold
+        let alerts = detect_alert(true, 151, 150, 0, 300);
+
+        assert_eq!(alerts, AlertSet::from(AlertType::Fall));
+    }
+
     #[test]
     fn test_no_fall_at_exact_threshold() {
-        let alert = detect_alert(
-            motion: true,
-            sound_level: 150,  // Exactly at threshold (not above)
-            sound_threshold: 150,
-            seconds_since_motion: 0,
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::None);
-    }
-    
+        // Exactly at threshold (not above)
+        let alerts = detect_alert(true, 150, 150, 0, 300);
+
+        assert!(alerts.is_empty());
+    }
+
+    #[test]
+    fn test_fall_after_motion_within_window() {
+        let settings = MonitorSettings { inactivity_seconds: 300, sound_threshold: 150 };
+        let reading = |motion, sound_level| SensorReading {
+            temperature: 23.0,
+            motion,
+            sound_level,
+            timestamp: Utc::now(),
+            ..Default::default()
+        };
+
+        // The impact is heard a moment after the movement that led to it
+        assert!(detect_fall_after_motion(&reading(false, 200), &settings, FALL_MOTION_WINDOW_SECS));
+        assert!(detect_fall_after_motion(&reading(true, 200), &settings, 0));
+        assert!(!detect_fall_after_motion(&reading(false, 200), &settings, FALL_MOTION_WINDOW_SECS + 1));
+        assert!(!detect_fall_after_motion(&reading(false, 150), &settings, 0));
+    }
+
+    fn imu_reading(seconds: i64, motion: bool, acceleration: f32) -> SensorReading {
+        SensorReading {
+            temperature: 23.0,
+            motion,
+            sound_level: 20,
+            acceleration: Some(acceleration),
+            timestamp: at(seconds),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_impact_followed_by_stillness_is_a_fall() {
+        let mut detector = ImpactFallDetector::new(ImpactLimit { impact_g: 2.5, still_seconds: 10 });
+        let falls: Vec<bool> = [(0, 1.0), (1, 3.2), (2, 1.05), (6, 0.98), (11, 1.0), (12, 1.0)]
+            .iter()
+            .map(|(s, g)| detector.push(&imu_reading(*s, false, *g)))
+            .collect();
+
+        // Raised once, ten seconds after the impact, without a sound
+        assert_eq!(falls, vec![false, false, false, false, true, false]);
+    }
+
+    #[test]
+    fn test_movement_after_impact_is_no_fall() {
+        let limit = ImpactLimit { impact_g: 2.5, still_seconds: 10 };
+
+        // Bumping into the bed and walking on
+        let mut detector = ImpactFallDetector::new(limit);
+        assert!(!detector.push(&imu_reading(0, false, 3.0)));
+        assert!(!detector.push(&imu_reading(4, false, 1.4)));
+        assert!(!detector.push(&imu_reading(12, false, 1.0)));
+
+        // Getting up, as seen by the PIR sensor
+        let mut detector = ImpactFallDetector::new(limit);
+        assert!(!detector.push(&imu_reading(0, false, 3.0)));
+        assert!(!detector.push(&imu_reading(4, true, 1.0)));
+        assert!(!detector.push(&imu_reading(12, false, 1.0)));
+
+        // Below the impact threshold
+        let mut detector = ImpactFallDetector::new(limit);
+        assert!(!detector.push(&imu_reading(0, false, 2.0)));
+        assert!(!detector.push(&imu_reading(12, false, 1.0)));
+    }
+
     // ========================================================================
     // INACTIVITY DETECTION TESTS
     // ========================================================================
-    
+
     #[test]
     fn test_inactivity_alert_after_threshold() {
-        let alert = detect_alert(
-            motion: false,
-            sound_level: 30,
-            sound_threshold: 150,
-            seconds_since_motion: 301,  // Just over 5 minutes
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::Inactivity);
-    }
-    
+        // Just over 5 minutes
+        let alerts = detect_alert(false, 30, 150, 301, 300);
+
+        assert_eq!(alerts, AlertSet::from(AlertType::Inactivity));
+    }
+
     #[test]
     fn test_no_inactivity_before_threshold() {
-        let alert = detect_alert(
-            motion: false,
-            sound_level: 30,
-            sound_threshold: 150,
-            seconds_since_motion: 299,  // Just under 5 minutes
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::None);
-    }
-    
+        // Just under 5 minutes
+        let alerts = detect_alert(false, 30, 150, 299, 300);
+
+        assert!(alerts.is_empty());
+    }
+
     #[test]
     fn test_no_inactivity_at_exact_threshold() {
-        let alert = detect_alert(
-            motion: false,
-            sound_level: 30,
-            sound_threshold: 150,
-            seconds_since_motion: 300,  // Exactly at threshold
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::None);
-    }
-    
+        // Exactly at threshold
+        let alerts = detect_alert(false, 30, 150, 300, 300);
+
+        assert!(alerts.is_empty());
+    }
+
     #[test]
     fn test_inactivity_with_custom_threshold() {
         // Test with 1 minute threshold
-        let alert = detect_alert(
-            motion: false,
-            sound_level: 20,
-            sound_threshold: 150,
-            seconds_since_motion: 61,
-            inactivity_threshold: 60,  // 1 minute
-        );
-        
-        assert_eq!(alert, AlertType::Inactivity);
+        let alerts = detect_alert(false, 20, 150, 61, 60);
+
+        assert_eq!(alerts, AlertSet::from(AlertType::Inactivity));
     }
-    
+
     // ========================================================================
-    // PRIORITY TESTS (Fall takes precedence)
+    // COMBINED ALERTS
     // ========================================================================
-    
+
     #[test]
-    fn test_fall_takes_priority_over_inactivity() {
-        // Even if inactivity threshold is exceeded, fall should be detected first
-        let alert = detect_alert(
-            motion: true,
-            sound_level: 200,
-            sound_threshold: 150,
-            seconds_since_motion: 0,  // Motion just happened
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::Fall);
+    fn test_fall_with_recent_motion_has_no_inactivity() {
+        // Motion just happened, so only the fall is raised
+        let alerts = detect_alert(true, 200, 150, 0, 300);
+
+        assert_eq!(alerts, AlertSet::from(AlertType::Fall));
     }
-    
+
+    #[test]
+    fn test_fall_and_inactivity_raised_together() {
+        // Both conditions hold, so both alerts are raised
+        let alerts = detect_alert(true, 200, 150, 400, 300);
+
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts.contains(AlertType::Fall));
+        assert!(alerts.contains(AlertType::Inactivity));
+        assert_eq!(alerts.codes(), vec!["FALL_DETECTED", "INACTIVITY_ALERT"]);
+    }
+
     // ========================================================================
     // EDGE CASES
     // ========================================================================
-    
+
     #[test]
     fn test_zero_sound_level() {
-        let alert = detect_alert(
-            motion: true,
-            sound_level: 0,
-            sound_threshold: 150,
-            seconds_since_motion: 0,
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::None);
-    }
-    
+        let alerts = detect_alert(true, 0, 150, 0, 300);
+
+        assert!(alerts.is_empty());
+    }
+
     #[test]
     fn test_very_high_sound_level() {
-        let alert = detect_alert(
-            motion: true,
-            sound_level: 1000,  // Very loud
-            sound_threshold: 150,
-            seconds_since_motion: 0,
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::Fall);
-    }
-    
+        // Very loud
+        let alerts = detect_alert(true, 1000, 150, 0, 300);
+
+        assert_eq!(alerts, AlertSet::from(AlertType::Fall));
+    }
+
     #[test]
     fn test_long_inactivity_period() {
-        let alert = detect_alert(
-            motion: false,
-            sound_level: 10,
-            sound_threshold: 150,
-            seconds_since_motion: 3600,  // 1 hour
-            inactivity_threshold: 300,
-        );
-        
-        assert_eq!(alert, AlertType::Inactivity);
-    }
-    
+        // 1 hour
+        let alerts = detect_alert(false, 10, 150, 3600, 300);
+
+        assert_eq!(alerts, AlertSet::from(AlertType::Inactivity));
+    }
+
     #[test]
     fn test_zero_inactivity_threshold() {
         // With 0 threshold, any time without motion triggers alert
-        let alert = detect_alert(
-            motion: false,
-            sound_level: 10,
-            sound_threshold: 150,
-            seconds_since_motion: 1,
-            inactivity_threshold: 0,
-        );
-        
-        assert_eq!(alert, AlertType::Inactivity);
+        let alerts = detect_alert(false, 10, 150, 1, 0);
+
+        assert_eq!(alerts, AlertSet::from(AlertType::Inactivity));
+    }
+
+    // ========================================================================
+    // VITAL SIGNS TESTS
+    // ========================================================================
+
+    fn vitals(heart_rate: Option<i32>, spo2: Option<i32>) -> SensorReading {
+        SensorReading { temperature: 23.0, motion: false, sound_level: 30, heart_rate, spo2, timestamp: Utc::now(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_vital_alerts_at_limits() {
+        let limits = VitalLimits::default();
+
+        assert!(detect_vital_alerts(&vitals(Some(120), Some(90)), &limits).is_empty());
+        assert_eq!(detect_vital_alerts(&vitals(Some(121), Some(95)), &limits), AlertSet::from(AlertType::Tachycardia));
+        assert_eq!(detect_vital_alerts(&vitals(Some(80), Some(89)), &limits), AlertSet::from(AlertType::LowSpo2));
+        assert_eq!(detect_vital_alerts(&vitals(Some(140), Some(85)), &limits).codes(), vec!["TACHYCARDIA", "LOW_SPO2"]);
+        // Boards without a pulse oximeter never raise them
+        assert!(detect_vital_alerts(&vitals(None, None), &limits).is_empty());
+
+        let limits = VitalLimits { tachycardia_bpm: 100, low_spo2_percent: 94 };
+        assert_eq!(detect_vital_alerts(&vitals(Some(101), Some(93)), &limits).len(), 2);
+    }
+
+    #[test]
+    fn test_missing_vitals_dont_resolve_vital_alerts() {
+        let policies = ResolutionPolicies::default();
+        let vital_alerts = |reading: &SensorReading| -> Vec<AlertType> {
+            policies.resolved_by(reading, &AlertSet::new())
+                .into_iter()
+                .map(|(alert, _)| alert)
+                .filter(|a| matches!(a, AlertType::Tachycardia | AlertType::LowSpo2))
+                .collect()
+        };
+
+        // A probe taken off isn't a recovery
+        assert!(vital_alerts(&vitals(None, None)).is_empty());
+        assert_eq!(vital_alerts(&vitals(Some(80), None)), vec![AlertType::Tachycardia]);
+        assert_eq!(vital_alerts(&vitals(Some(80), Some(97))), vec![AlertType::Tachycardia, AlertType::LowSpo2]);
+    }
+
+    // ========================================================================
+    // SOUND FLATLINE TESTS
+    // ========================================================================
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 15, 0, 0, 0).unwrap() + Duration::seconds(seconds)
+    }
+
+    /// Feed one sample a minute and return the result of the last push
+    fn flatline(detector: &mut FlatlineDetector, values: &[f64]) -> bool {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| detector.push(at(i as i64 * 60), *value))
+            .last()
+            .unwrap_or(false)
+    }
+
+    #[test]
+    fn test_flatline_after_full_window() {
+        let mut detector = FlatlineDetector::new(Duration::hours(1), 1.0);
+
+        // 59 minutes of silence don't cover the window yet
+        assert!(!flatline(&mut detector, &[0.0; 60]));
+        assert!(detector.push(at(3600), 0.0));
+    }
+
+    #[test]
+    fn test_no_flatline_for_varying_sound() {
+        let mut detector = FlatlineDetector::new(Duration::hours(1), 1.0);
+        let values: Vec<f64> = (0..120).map(|i| if i % 2 == 0 { 20.0 } else { 30.0 }).collect();
+
+        assert!(!flatline(&mut detector, &values));
+    }
+
+    #[test]
+    fn test_flatline_clears_when_signal_returns() {
+        let mut detector = FlatlineDetector::new(Duration::hours(1), 1.0);
+
+        assert!(flatline(&mut detector, &[0.0; 62]));
+        assert!(!detector.push(at(62 * 60), 45.0));
+    }
+
+    #[test]
+    fn test_flatline_restarts_after_gap() {
+        let mut detector = FlatlineDetector::new(Duration::hours(1), 1.0);
+
+        assert!(!flatline(&mut detector, &[0.0; 30]));
+        // The device was offline for two hours; its history starts over
+        assert!(!detector.push(at(3 * 3600), 0.0));
+        assert!(!detector.push(at(3 * 3600 + 60), 0.0));
+    }
+
+    // ========================================================================
+    // VENTILATION TESTS
+    // ========================================================================
+
+    /// Feed one CO2 level a minute and return the result of the last push
+    fn ventilation(detector: &mut VentilationDetector, levels: &[Option<i32>]) -> bool {
+        levels
+            .iter()
+            .enumerate()
+            .map(|(i, co2)| detector.push(at(i as i64 * 60), *co2))
+            .last()
+            .unwrap_or(false)
+    }
+
+    #[test]
+    fn test_ventilation_after_limit_minutes() {
+        let mut detector = VentilationDetector::new(VentilationLimit { co2_ppm: 1400, minutes: 15 });
+
+        // 14 minutes above the limit aren't enough yet
+        assert!(!ventilation(&mut detector, &[Some(1500); 15]));
+        assert!(detector.push(at(15 * 60), Some(1500)));
+        // Airing the room clears it
+        assert!(!detector.push(at(16 * 60), Some(900)));
+        assert!(!detector.push(at(17 * 60), Some(1500)));
+    }
+
+    #[test]
+    fn test_ventilation_ignores_readings_without_co2() {
+        let mut detector = VentilationDetector::new(VentilationLimit { co2_ppm: 1400, minutes: 2 });
+        // The sensor warming up in between doesn't reset the run
+        assert!(ventilation(&mut detector, &[Some(1500), None, Some(1500)]));
+
+        // A level at the limit isn't above it
+        let mut detector = VentilationDetector::new(VentilationLimit { co2_ppm: 1400, minutes: 2 });
+        assert!(!ventilation(&mut detector, &[Some(1400); 5]));
+
+        // Neither is an outage of the board bridged
+        let mut detector = VentilationDetector::new(VentilationLimit { co2_ppm: 1400, minutes: 2 });
+        assert!(!ventilation(&mut detector, &[Some(1500)]));
+        assert!(!detector.push(at(3600), Some(1500)));
+    }
+
+    // ========================================================================
+    // TEMPERATURE CROSS-CHECK TESTS
+    // ========================================================================
+
+    #[test]
+    fn test_cross_check_prefers_sensor_that_stayed() {
+        let mut check = TemperatureCrossCheck::new(CalibrationLimit { delta_celsius: 2.0, minutes: 10 });
+        // A single node has nothing to be compared with
+        let alone = check.push("a", at(0), 22.0);
+        assert_eq!(alone.disagreement, None);
+        assert_eq!(alone.temperature, 22.0);
+
+        let agreeing = check.push("b", at(30), 22.6);
+        assert!((agreeing.disagreement.unwrap() - 0.6).abs() < 1e-4);
+        assert_eq!(agreeing.temperature, 22.6);
+
+        // b drifts off; a's temperature, close to where they agreed, is kept
+        let drifted = check.push("b", at(60), 27.0);
+        assert_eq!(drifted.disagreement, Some(5.0));
+        assert_eq!(drifted.temperature, 22.0);
+        assert!(!drifted.calibration_needed);
+        assert_eq!(check.push("a", at(90), 22.1).temperature, 22.1);
+    }
+
+    #[test]
+    fn test_cross_check_raises_calibration_after_minutes() {
+        let mut check = TemperatureCrossCheck::new(CalibrationLimit { delta_celsius: 2.0, minutes: 10 });
+        check.push("a", at(0), 22.0);
+        check.push("b", at(0), 22.0);
+
+        for minute in 1..10 {
+            assert!(!check.push("a", at(minute * 60), 22.0).calibration_needed);
+            assert!(!check.push("b", at(minute * 60), 25.0).calibration_needed);
+        }
+        assert!(check.push("b", at(11 * 60), 25.0).calibration_needed);
+        assert!(check.calibration_needed());
+
+        // Back within the delta
+        assert!(!check.push("b", at(12 * 60), 23.0).calibration_needed);
+        assert_eq!(check.disagreement(), Some(1.0));
+    }
+
+    #[test]
+    fn test_cross_check_ignores_silent_nodes() {
+        let mut check = TemperatureCrossCheck::new(CalibrationLimit { delta_celsius: 2.0, minutes: 10 });
+        check.push("a", at(0), 22.0);
+        // a hasn't reported for an hour, so b isn't compared with it
+        let check = check.push("b", at(3600), 30.0);
+        assert_eq!(check.disagreement, None);
+        assert_eq!(check.temperature, 30.0);
+    }
+
+    // ========================================================================
+    // AUTO-RESOLUTION TESTS
+    // ========================================================================
+
+    fn reading(motion: bool) -> SensorReading {
+        SensorReading { temperature: 23.0, motion, sound_level: 30, timestamp: Utc::now(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_still_reading_resolves_only_cleared_alerts() {
+        let policies = ResolutionPolicies::default();
+        let resolved = policies.resolved_by(&reading(false), &AlertSet::new());
+
+        // Inactivity waits for motion, a fall for staff
+        assert_eq!(resolved, vec![
+            (AlertType::Calibration, ResolutionReason::Cleared),
+            (AlertType::SensorFault(SensorChannel::Temperature), ResolutionReason::Cleared),
+            (AlertType::SensorFault(SensorChannel::Motion), ResolutionReason::Cleared),
+            (AlertType::SensorFault(SensorChannel::Sound), ResolutionReason::Cleared),
+        ]);
+    }
+
+    #[test]
+    fn test_motion_resolves_inactivity() {
+        let policies = ResolutionPolicies::default();
+        let resolved = policies.resolved_by(&reading(true), &AlertSet::new());
+
+        assert!(resolved.contains(&(AlertType::Inactivity, ResolutionReason::MotionResumed)));
+        assert!(!resolved.iter().any(|(alert, _)| *alert == AlertType::Fall));
+    }
+
+    #[test]
+    fn test_reading_never_resolves_alerts_it_raises() {
+        let policies = ResolutionPolicies::parse("fall=clear").unwrap();
+        let resolved = policies.resolved_by(&reading(true), &AlertSet::from(AlertType::Fall));
+
+        assert!(!resolved.iter().any(|(alert, _)| *alert == AlertType::Fall));
+    }
+
+    #[test]
+    fn test_resolution_policies_override_defaults() {
+        let policies = ResolutionPolicies::parse("fall=clear, sensor_fault=ack").unwrap();
+
+        assert_eq!(policies.policy(AlertType::Fall), AutoResolve::Clear);
+        assert_eq!(policies.policy(AlertType::Inactivity), AutoResolve::Motion);
+        assert_eq!(policies.policy(AlertType::SensorFault(SensorChannel::Sound)), AutoResolve::Ack);
+        assert!(ResolutionPolicies::parse("fall=later").is_err());
+        assert!(ResolutionPolicies::parse("smoke=ack").is_err());
+    }
+
+    // ========================================================================
+    // MOTION SOURCE TESTS
+    // ========================================================================
+
+    fn radar_reading(motion: bool, presence: Option<bool>) -> SensorReading {
+        SensorReading { presence, ..reading(motion) }
+    }
+
+    #[test]
+    fn test_motion_sources() {
+        // A sleeping patient: the PIR sensor sees nothing, the radar sees breathing
+        let asleep = radar_reading(false, Some(true));
+        assert!(!MotionSource::Pir.moved(&asleep));
+        assert!(MotionSource::Radar.moved(&asleep));
+        assert!(MotionSource::Either.moved(&asleep));
+
+        // An empty room as far as the radar can tell, with the PIR triggering
+        let pir_only = radar_reading(true, Some(false));
+        assert!(MotionSource::Pir.moved(&pir_only));
+        assert!(!MotionSource::Radar.moved(&pir_only));
+        assert!(MotionSource::Either.moved(&pir_only));
+
+        // Boards without a radar fall back to the PIR sensor
+        assert!(MotionSource::Radar.moved(&radar_reading(true, None)));
+        assert!(!MotionSource::Either.moved(&radar_reading(false, None)));
+
+        assert_eq!("either".parse::<MotionSource>(), Ok(MotionSource::Either));
+        assert!("sonar".parse::<MotionSource>().is_err());
+    }
+
+    #[test]
+    fn test_radar_presence_resolves_inactivity_with_fusion() {
+        let asleep = radar_reading(false, Some(true));
+        let pir = ResolutionPolicies::default();
+        assert!(!pir.resolved_by(&asleep, &AlertSet::new()).iter().any(|(alert, _)| *alert == AlertType::Inactivity));
+
+        let fused = ResolutionPolicies::default().with_motion_source(MotionSource::Either);
+        assert!(fused.resolved_by(&asleep, &AlertSet::new()).contains(&(AlertType::Inactivity, ResolutionReason::MotionResumed)));
+    }
+
+    // ========================================================================
+    // ALERT PRECISION TESTS
+    // ========================================================================
+
+    #[test]
+    fn test_precision_counts_only_judged_alerts() {
+        let mut counts = OutcomeCounts::default();
+        for outcome in [
+            Some(AlertOutcome::ConfirmedIncident),
+            Some(AlertOutcome::FalseAlarm),
+            Some(AlertOutcome::FalseAlarm),
+            Some(AlertOutcome::FalseAlarm),
+            Some(AlertOutcome::Unknown),
+            None,
+        ] {
+            counts.add(outcome);
+        }
+
+        assert_eq!(counts.alerts, 6);
+        assert_eq!(counts.unknown, 1);
+        assert_eq!(counts.unreviewed, 1);
+        assert_eq!(counts.precision(), Some(25.0));
+    }
+
+    #[test]
+    fn test_precision_undefined_without_judged_alerts() {
+        let mut counts = OutcomeCounts::default();
+        assert_eq!(counts.precision(), None);
+
+        counts.add(Some(AlertOutcome::Unknown));
+        counts.add(None);
+        assert_eq!(counts.precision(), None);
+    }
+
+    #[test]
+    fn test_alert_outcome_names() {
+        assert_eq!("false_alarm".parse(), Ok(AlertOutcome::FalseAlarm));
+        assert_eq!(AlertOutcome::ConfirmedIncident.as_str(), "confirmed_incident");
+        assert_eq!(serde_json::to_string(&AlertOutcome::Unknown).unwrap(), "\"unknown\"");
+        assert!("maybe".parse::<AlertOutcome>().is_err());
+    }
+
+    #[test]
+    fn test_spell_room_ids_for_announcements() {
+        assert_eq!(spell("room-101"), "one zero one");
+        assert_eq!(spell("3W-301A"), "three whiskey, three zero one alpha");
+        assert_eq!(spell("ICU 4b"), "india charlie uniform, four bravo");
+        assert_eq!(spell("Room 12#"), "one two");
+    }
+
+    #[test]
+    fn test_announcement_template() {
+        let text = AnnouncementTemplate::default().render("Possible fall detected", "room-204", None);
+        assert_eq!(text, "Possible fall detected, room two zero four. Possible fall detected, room two zero four.");
+
+        let template = AnnouncementTemplate::parse("Ward {ward}: {alert} in room {room}").unwrap();
+        assert_eq!(template.render("Patient inactivity alert", "101", Some("3W")),
+            "Ward three whiskey: Patient inactivity alert in room one zero one");
+
+        assert!(AnnouncementTemplate::parse("{alert} in {bed}").is_err());
+        assert!(AnnouncementTemplate::parse("{alert in room").is_err());
+        assert!(AnnouncementTemplate::parse("  ").is_err());
     }
 }
@@ -322,4 +322,416 @@ mod tests {
             assert_eq!(entry["resource"]["resourceType"], "Observation");
         }
     }
+    
+    // ========================================================================
+    // RESEARCH AGGREGATE TESTS
+    // ========================================================================
+    
+    use patient_monitor_types::api::{ResearchAggregates, ResearchGroup};
+    
+    #[test]
+    fn test_research_group_omits_unused_groupings() {
+        let aggregates = ResearchAggregates {
+            from: "2024-01-01".parse().unwrap(),
+            to: "2024-01-31".parse().unwrap(),
+            min_group_size: 5,
+            noise_epsilon: None,
+            suppressed_groups: 1,
+            groups: vec![ResearchGroup {
+                ward: Some("ward-a".to_string()),
+                date: None,
+                patients: 12,
+                readings: 4000,
+                motion_percent: 31.5,
+                avg_temperature: 21.4,
+                fall_alerts: 2,
+                inactivity_alerts: 7,
+            }],
+        };
+        
+        let json = serde_json::to_value(&aggregates).unwrap();
+        assert_eq!(json["minGroupSize"], 5);
+        assert_eq!(json["suppressedGroups"], 1);
+        assert!(json["noiseEpsilon"].is_null());
+        let group = &json["groups"][0];
+        assert_eq!(group["ward"], "ward-a");
+        assert!(group.get("date").is_none());
+        assert_eq!(group["motionPercent"], 31.5);
+    }
+    
+    // ========================================================================
+    // ALERT STATISTICS TESTS
+    // ========================================================================
+    
+    use patient_monitor_types::api::{AlertCounts, AlertStats};
+    
+    #[test]
+    fn test_alert_stats_lists_buckets_by_start_day() {
+        let stats = AlertStats {
+            bucket: "week".to_string(),
+            from: "2024-01-08".parse().unwrap(),
+            to: "2024-01-21".parse().unwrap(),
+            room: None,
+            buckets: vec![
+                AlertCounts { period: "2024-01-08".parse().unwrap(), fall_alerts: 1, ..Default::default() },
+                AlertCounts { period: "2024-01-15".parse().unwrap(), fall_alerts: 3, inactivity_alerts: 2, ..Default::default() },
+            ],
+        };
+        
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["bucket"], "week");
+        assert!(json.get("room").is_none());
+        assert_eq!(json["buckets"][1]["period"], "2024-01-15");
+        assert_eq!(json["buckets"][1]["fallAlerts"], 3);
+        assert_eq!(json["buckets"][0]["sensorFaultAlerts"], 0);
+    }
+    
+    // ========================================================================
+    // SETTINGS PERMISSION TESTS
+    // ========================================================================
+    
+    use patient_monitor_types::api::{MonitorSettings, SettingsPermissions};
+    
+    #[test]
+    fn test_settings_change_denied_per_field() {
+        let permissions = SettingsPermissions::parse(
+            "inactivity_seconds=charge_nurse|admin, sound_threshold=biomed",
+        ).unwrap();
+        let old = MonitorSettings { inactivity_seconds: 300, sound_threshold: 150 };
+        let new = MonitorSettings { inactivity_seconds: 600, sound_threshold: 120 };
+        let changed = old.changed_fields(&new);
+        assert_eq!(changed, vec!["inactivity_seconds", "sound_threshold"]);
+        
+        let charge_nurse = |role: &str| role == "charge_nurse";
+        assert_eq!(permissions.denied(&changed, charge_nurse), vec!["sound_threshold"]);
+        assert!(permissions.denied(&changed[..1], charge_nurse).is_empty());
+        assert_eq!(permissions.denied(&changed, |_| false).len(), 2);
+        // Unlisted settings are open to everyone
+        assert!(SettingsPermissions::default().denied(&changed, |_| false).is_empty());
+    }
+    
+    #[test]
+    fn test_settings_permissions_reject_unknown_settings() {
+        assert!(SettingsPermissions::parse("sound_thresold=biomed").is_err());
+        assert!(SettingsPermissions::parse("sound_threshold=").is_err());
+        assert!(SettingsPermissions::parse("sound_threshold").is_err());
+        assert_eq!(SettingsPermissions::parse("").unwrap(), SettingsPermissions::default());
+    }
+    
+    // ========================================================================
+    // KIOSK STATUS TESTS
+    // ========================================================================
+
+    use patient_monitor_types::api::{KioskStatus, PatientState};
+
+    #[test]
+    fn test_kiosk_status_only_carries_coarse_state() {
+        let status = KioskStatus {
+            room: "room-101".to_string(),
+            label: Some("Family tablet".to_string()),
+            state: Some(PatientState::OutOfRoom),
+            since: Some("2024-01-15T08:00:00Z".parse().unwrap()),
+            message: KioskStatus::message(Some(PatientState::OutOfRoom)).to_string(),
+            expires_at: "2024-01-18T08:00:00Z".parse().unwrap(),
+        };
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["state"], "out_of_room");
+        assert_eq!(json["message"], "Out of the room");
+        assert!(json.get("expiresAt").is_some());
+        let mut fields: Vec<&str> = json.as_object().unwrap().keys().map(String::as_str).collect();
+        fields.sort();
+        assert_eq!(fields, vec!["expiresAt", "label", "message", "room", "since", "state"]);
+    }
+
+    #[test]
+    fn test_kiosk_status_message_without_state() {
+        assert_eq!(KioskStatus::message(None), "No status yet");
+        assert_ne!(KioskStatus::message(Some(PatientState::InBed)), KioskStatus::message(Some(PatientState::Moving)));
+    }
+
+    // ========================================================================
+    // HANDOVER TESTS
+    // ========================================================================
+
+    use patient_monitor_types::api::{HandoverAlert, RoomHandover, ShiftSleep, Shifts};
+    use patient_monitor_types::fhir::{AlertType, ManualObservation, ObservationKind};
+
+    fn handover_alert(alert: AlertType) -> HandoverAlert {
+        HandoverAlert {
+            alert,
+            description: alert.description().to_string(),
+            severity: alert.severity(),
+            triggered_at: "2024-01-15T02:10:00Z".parse().unwrap(),
+            resolved_at: None,
+            acknowledged_by: None,
+        }
+    }
+
+    #[test]
+    fn test_shifts_parse() {
+        let shifts = Shifts::parse("early=6-14, late=14-22, night=22-6").unwrap();
+        assert_eq!(shifts.names(), vec!["early", "late", "night"]);
+        let night = shifts.get("night").unwrap();
+        assert_eq!((night.start_hour, night.end_hour), (22, 6));
+        assert!(shifts.get("day").is_none());
+        assert!(Shifts::default().get("night").is_some());
+
+        assert!(Shifts::parse("night=22-24").is_err());
+        assert!(Shifts::parse("night=22").is_err());
+        assert!(Shifts::parse("night=22-6,night=23-7").is_err());
+        assert!(Shifts::parse("").is_err());
+    }
+
+    #[test]
+    fn test_room_handover_read_out() {
+        let mut room = RoomHandover {
+            room: "room-101".to_string(),
+            open_alerts: vec![],
+            events: vec![],
+            sleep: None,
+            follow_ups: vec![],
+            summary: String::new(),
+        };
+        assert_eq!(room.read_out(), "room-101: No open alerts. No alerts during the shift.");
+
+        room.open_alerts = vec![handover_alert(AlertType::Fall)];
+        room.events = vec![handover_alert(AlertType::Fall), handover_alert(AlertType::Inactivity)];
+        room.sleep = Some(ShiftSleep {
+            start: "2024-01-14T22:00:00Z".parse().unwrap(),
+            end: "2024-01-15T06:00:00Z".parse().unwrap(),
+            activity_score: 12.4,
+            activity_level: "deep_sleep".to_string(),
+            rest_quality: "Excellent".to_string(),
+            awakenings: 1,
+            longest_still_period_mins: 95,
+        });
+        room.follow_ups = vec![ManualObservation {
+            id: Some(1),
+            room: "room-101".to_string(),
+            patient_id: None,
+            kind: ObservationKind::PainScore,
+            value: 6.0,
+            observed_at: "2024-01-15T03:00:00Z".parse().unwrap(),
+            recorded_by: "nurse.jones".to_string(),
+            note: Some("Recheck after analgesia".to_string()),
+        }];
+        assert_eq!(room.read_out(),
+            "room-101: 1 open alert: Possible fall detected. \
+             2 alerts during the shift: Possible fall detected, Patient inactivity alert. \
+             Rest excellent, 12% active, 1 awakening. 1 note to follow up.");
+    }
+
+    // ========================================================================
+    // WEBSOCKET PROTOCOL TESTS
+    // ========================================================================
+    
+    use patient_monitor_types::ws::{
+        negotiate, Capability, DeltaCodec, MessageKind, Subscription, WsMessage, WsRequest, PROTOCOL_VERSION,
+    };
+    
+    fn reading(room: &str, temperature: f32, motion: bool, sound_level: i32, alerts: &[&str]) -> WsMessage {
+        WsMessage::SensorReading {
+            id: None,
+            seq: None,
+            room: room.to_string(),
+            temperature,
+            motion,
+            sound_level,
+            humidity: None,
+            heart_rate: None,
+            spo2: None,
+            door_open: None,
+            light: None,
+            co2: None,
+            presence: None,
+            acceleration: None,
+            timestamp: "2024-01-15T10:30:00Z".to_string(),
+            alerts: alerts.iter().map(|a| a.to_string()).collect(),
+            replay: false,
+        }
+    }
+    
+    #[test]
+    fn test_hello_drops_unknown_and_repeated_capabilities() {
+        let hello: WsRequest = serde_json::from_value(json!({
+            "type": "hello",
+            "version": 7,
+            "capabilities": ["deltas", "holograms", "alertsOnly", "deltas"]
+        })).unwrap();
+        let WsRequest::Hello { version, capabilities } = hello else {
+            panic!("expected hello");
+        };
+        
+        let (version, granted) = negotiate(version, &capabilities);
+        assert_eq!(version, PROTOCOL_VERSION);
+        assert_eq!(granted, vec![Capability::Deltas, Capability::AlertsOnly]);
+    }
+    
+    #[test]
+    fn test_version_1_hello_gets_no_capabilities() {
+        let (version, granted) = negotiate(1, &[Capability::Msgpack]);
+        assert_eq!(version, 1);
+        assert!(granted.is_empty());
+    }
+    
+    #[test]
+    fn test_status_from_legacy_server_defaults_to_version_1() {
+        let status: WsMessage = serde_json::from_value(json!({
+            "type": "status",
+            "connected": true,
+            "message": "Connected"
+        })).unwrap();
+        
+        assert!(matches!(status, WsMessage::Status { version: 1, .. }));
+    }
+    
+    #[test]
+    fn test_delta_only_carries_changed_values() {
+        let mut codec = DeltaCodec::new();
+        
+        let first = codec.encode(reading("room-101", 22.5, false, 30, &[]));
+        assert!(matches!(first, WsMessage::SensorReading { .. }));
+        
+        let second = serde_json::to_value(codec.encode(reading("room-101", 22.5, true, 30, &[]))).unwrap();
+        assert_eq!(second["type"], "sensorDelta");
+        assert_eq!(second["motion"], true);
+        assert!(second.get("temperature").is_none());
+        assert!(second.get("soundLevel").is_none());
+        assert!(second.get("alerts").is_none());
+        
+        // Rooms are tracked separately
+        let other = codec.encode(reading("room-102", 22.5, true, 30, &[]));
+        assert!(matches!(other, WsMessage::SensorReading { .. }));
+    }
+    
+    #[test]
+    fn test_deltas_decode_to_original_readings() {
+        let readings = vec![
+            reading("room-101", 22.5, false, 30, &[]),
+            reading("room-102", 21.0, true, 45, &[]),
+            reading("room-101", 22.5, true, 240, &["fall"]),
+            reading("room-101", 22.6, true, 240, &[]),
+        ];
+        let mut encoder = DeltaCodec::new();
+        let mut decoder = DeltaCodec::new();
+        
+        for original in readings {
+            let decoded = decoder.decode(encoder.encode(original.clone()));
+            assert_eq!(serde_json::to_value(decoded).unwrap(), serde_json::to_value(original).unwrap());
+        }
+    }
+    
+    #[test]
+    fn test_delta_humidity_round_trips() {
+        let humid = |humidity| {
+            let mut reading = reading("room-101", 22.5, false, 30, &[]);
+            if let WsMessage::SensorReading { humidity: h, .. } = &mut reading {
+                *h = humidity;
+            }
+            reading
+        };
+        let mut encoder = DeltaCodec::new();
+        let mut decoder = DeltaCodec::new();
+        
+        for original in [humid(Some(45.0)), humid(Some(45.0)), humid(Some(47.5)), humid(None), humid(Some(47.5))] {
+            let encoded = encoder.encode(original.clone());
+            let decoded = decoder.decode(encoded);
+            assert_eq!(serde_json::to_value(decoded).unwrap(), serde_json::to_value(original).unwrap());
+        }
+        
+        // An unchanged humidity is left out of the delta
+        let mut codec = DeltaCodec::new();
+        codec.encode(humid(Some(45.0)));
+        let delta = serde_json::to_value(codec.encode(humid(Some(45.0)))).unwrap();
+        assert!(delta.get("humidity").is_none());
+    }
+    
+    #[test]
+    fn test_alert_resolved_passes_through_delta_encoding() {
+        let resolved: WsMessage = serde_json::from_value(json!({
+            "type": "alertResolved",
+            "id": 7,
+            "room": "room-101",
+            "alert": "FALL_DETECTED",
+            "alertId": 1200,
+            "resolvedAt": "2024-01-15T08:00:00+00:00",
+            "reason": "acknowledged",
+            "resolvedBy": "nurse-42"
+        })).unwrap();
+        let encoded = DeltaCodec::new().encode(resolved.clone());
+        
+        assert_eq!(serde_json::to_value(&encoded).unwrap(), serde_json::to_value(&resolved).unwrap());
+        assert!(serde_json::to_value(&encoded).unwrap().get("observationId").is_none());
+    }
+    
+    #[test]
+    fn test_device_status_reports_disconnect() {
+        let status: WsMessage = serde_json::from_value(json!({
+            "type": "deviceStatus",
+            "room": "room-101",
+            "device": "/dev/ttyACM0",
+            "connected": false,
+            "timestamp": "2024-01-15T08:00:00+00:00",
+            "error": "No such device"
+        })).unwrap();
+        let encoded = serde_json::to_value(DeltaCodec::new().encode(status.clone())).unwrap();
+        
+        assert_eq!(encoded, serde_json::to_value(&status).unwrap());
+        assert_eq!(encoded["connected"], false);
+        assert_eq!(encoded["error"], "No such device");
+    }
+    
+    #[test]
+    fn test_subscription_filters_rooms_severities_and_types() {
+        let subscription: Subscription = serde_json::from_value(json!({
+            "rooms": ["room-101"],
+            "severities": ["critical"],
+            "messageTypes": ["sensorReading", "deviceStatus"]
+        })).unwrap();
+        let device = |room: &str| serde_json::from_value::<WsMessage>(json!({
+            "type": "deviceStatus",
+            "room": room,
+            "device": "/dev/ttyACM0",
+            "connected": true,
+            "timestamp": "2024-01-15T08:00:00+00:00"
+        })).unwrap();
+        
+        assert!(subscription.wants(&reading("room-101", 22.5, true, 240, &["FALL_DETECTED"])));
+        // Warnings and readings without alerts aren't wanted
+        assert!(!subscription.wants(&reading("room-101", 22.5, false, 30, &["INACTIVITY_ALERT"])));
+        assert!(!subscription.wants(&reading("room-101", 22.5, false, 30, &[])));
+        assert!(!subscription.wants(&reading("room-102", 22.5, true, 240, &["FALL_DETECTED"])));
+        // Severities don't apply to device status
+        assert!(subscription.wants(&device("room-101")));
+        assert!(!subscription.wants(&device("room-102")));
+        
+        let ping = WsMessage::Ping { timestamp: "2024-01-15T08:00:00+00:00".to_string() };
+        assert!(subscription.wants(&ping));
+    }
+    
+    #[test]
+    fn test_empty_subscription_streams_everything() {
+        let subscription: Subscription = serde_json::from_value(json!({})).unwrap();
+        let resolved: WsMessage = serde_json::from_value(json!({
+            "type": "alertResolved",
+            "id": 7,
+            "room": "room-103",
+            "alert": "INACTIVITY_ALERT",
+            "resolvedAt": "2024-01-15T08:00:00+00:00",
+            "reason": "motion_resumed"
+        })).unwrap();
+        
+        assert_eq!(subscription, Subscription::default());
+        assert!(subscription.wants(&reading("room-101", 22.5, false, 30, &[])));
+        assert!(subscription.wants(&resolved));
+        
+        let warnings = Subscription {
+            severities: vec![patient_monitor_types::fhir::AlertSeverity::Warning],
+            message_types: vec![MessageKind::AlertResolved],
+            ..Subscription::default()
+        };
+        assert!(warnings.wants(&resolved));
+        assert!(!warnings.wants(&reading("room-103", 22.5, false, 30, &["INACTIVITY_ALERT"])));
+    }
 }
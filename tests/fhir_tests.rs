@@ -4,34 +4,12 @@
 
 #[cfg(test)]
 mod tests {
-    use chrono::Utc;
-    
-    // ========================================================================
-    // MOCK STRUCTURES (same as your fhir.rs)
-    // Copy these or import from your actual module
-    // ========================================================================
-    
-    #[derive(Debug, Clone, PartialEq)]
-    pub enum AlertType {
-        None,
-        Fall,
-        Inactivity,
-    }
-    
-    #[derive(Debug, Clone)]
-    pub struct SensorReading {
-        pub temperature: f32,
-        pub motion: bool,
-        pub sound_level: i32,
-        pub timestamp: chrono::DateTime<Utc>,
-    }
-    
-    #[derive(Debug, Clone)]
-    pub struct SensorEvent {
-        pub id: Option<i64>,
-        pub reading: SensorReading,
-        pub alert: AlertType,
-    }
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use patient_monitor_types::adt::{hl7_ack, parse_hl7_adt, AdtAction, Hl7AckCode};
+    use patient_monitor_types::fhir::{
+        AlertSet, AlertType, FhirBundle, FhirEncounter, Gender, ManualObservation, ObservationKind, Patient,
+        SensorChannel, SensorEvent, SensorRanges, SensorReading, DEFAULT_ROOM_ID,
+    };
     
     // ========================================================================
     // FHIR STRUCTURE TESTS
@@ -44,6 +22,7 @@ mod tests {
             motion: true,
             sound_level: 150,
             timestamp: Utc::now(),
+            ..Default::default()
         };
         
         assert_eq!(reading.temperature, 23.5);
@@ -55,16 +34,21 @@ mod tests {
     fn test_sensor_event_with_no_alert() {
         let event = SensorEvent {
             id: Some(1),
+            seq: None,
+            room: "room-101".to_string(),
+            device: None,
+            patient_id: None,
             reading: SensorReading {
                 temperature: 22.0,
                 motion: false,
                 sound_level: 30,
                 timestamp: Utc::now(),
+                ..Default::default()
             },
-            alert: AlertType::None,
+            alerts: AlertSet::new(),
         };
         
-        assert_eq!(event.alert, AlertType::None);
+        assert!(event.alerts.is_empty());
         assert_eq!(event.id, Some(1));
     }
     
@@ -72,32 +56,44 @@ mod tests {
     fn test_sensor_event_with_fall_alert() {
         let event = SensorEvent {
             id: Some(2),
+            seq: None,
+            room: "room-101".to_string(),
+            device: None,
+            patient_id: None,
             reading: SensorReading {
                 temperature: 23.0,
                 motion: true,
                 sound_level: 250,
                 timestamp: Utc::now(),
+                ..Default::default()
             },
-            alert: AlertType::Fall,
+            alerts: AlertSet::from(AlertType::Fall),
         };
         
-        assert_eq!(event.alert, AlertType::Fall);
+        assert!(event.alerts.contains(AlertType::Fall));
+        assert_eq!(event.alerts.len(), 1);
     }
     
     #[test]
     fn test_sensor_event_with_inactivity_alert() {
         let event = SensorEvent {
             id: Some(3),
+            seq: None,
+            room: "room-101".to_string(),
+            device: None,
+            patient_id: None,
             reading: SensorReading {
                 temperature: 21.5,
                 motion: false,
                 sound_level: 20,
                 timestamp: Utc::now(),
+                ..Default::default()
             },
-            alert: AlertType::Inactivity,
+            alerts: AlertSet::from(AlertType::Inactivity),
         };
         
-        assert_eq!(event.alert, AlertType::Inactivity);
+        assert!(event.alerts.contains(AlertType::Inactivity));
+        assert_eq!(event.alerts.len(), 1);
     }
     
     #[test]
@@ -108,6 +104,7 @@ mod tests {
             motion: false,
             sound_level: 40,
             timestamp: Utc::now(),
+            ..Default::default()
         };
         
         assert!(reading.temperature >= 15.0 && reading.temperature <= 35.0);
@@ -120,6 +117,7 @@ mod tests {
             motion: true,
             sound_level: 0,
             timestamp: Utc::now(),
+            ..Default::default()
         };
         
         assert!(reading.sound_level >= 0);
@@ -127,10 +125,462 @@ mod tests {
     
     #[test]
     fn test_alert_type_equality() {
-        assert_eq!(AlertType::None, AlertType::None);
         assert_eq!(AlertType::Fall, AlertType::Fall);
         assert_eq!(AlertType::Inactivity, AlertType::Inactivity);
-        assert_ne!(AlertType::Fall, AlertType::None);
         assert_ne!(AlertType::Fall, AlertType::Inactivity);
     }
+    
+    #[test]
+    fn test_sensor_fault_serializes_with_channel() {
+        let fault = AlertType::SensorFault(SensorChannel::Sound);
+        
+        assert_eq!(serde_json::to_string(&fault).unwrap(), "\"sensor_fault:sound\"");
+        assert_eq!(serde_json::from_str::<AlertType>("\"sensor_fault:sound\"").unwrap(), fault);
+        assert_eq!(serde_json::to_string(&AlertType::Fall).unwrap(), "\"fall\"");
+        assert!(serde_json::from_str::<AlertType>("\"sensor_fault\"").is_err());
+    }
+    
+    #[test]
+    fn test_alert_set_ignores_duplicates() {
+        let alerts: AlertSet = [AlertType::Inactivity, AlertType::Fall, AlertType::Fall]
+            .into_iter()
+            .collect();
+        
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts.iter().collect::<Vec<_>>(), vec![AlertType::Fall, AlertType::Inactivity]);
+    }
+    
+    #[test]
+    fn test_observation_subject_is_room_occupant() {
+        let event = SensorEvent {
+            id: Some(4),
+            seq: None,
+            room: "room-204".to_string(),
+            device: None,
+            patient_id: None,
+            reading: SensorReading {
+                temperature: 22.0,
+                motion: false,
+                sound_level: 30,
+                timestamp: Utc::now(),
+                ..Default::default()
+            },
+            alerts: AlertSet::new(),
+        };
+        
+        let subject = event.to_fhir("http://localhost").subject.unwrap();
+        assert_eq!(subject.reference, "Patient/room-204");
+    }
+    
+    #[test]
+    fn test_observation_subject_is_registered_patient() {
+        let event = SensorEvent {
+            id: Some(6),
+            seq: None,
+            room: "room-204".to_string(),
+            device: None,
+            patient_id: Some("pat-0042".to_string()),
+            reading: SensorReading {
+                temperature: 22.0,
+                motion: false,
+                sound_level: 30,
+                timestamp: Utc::now(),
+                ..Default::default()
+            },
+            alerts: AlertSet::new(),
+        };
+        
+        let subject = event.to_fhir("http://localhost").subject.unwrap();
+        assert_eq!(subject.reference, "Patient/pat-0042");
+        assert!(subject.display.is_none());
+    }
+    
+    #[test]
+    fn test_patient_to_fhir() {
+        let patient = Patient {
+            id: "pat-0042".to_string(),
+            family_name: "Okafor".to_string(),
+            given_names: vec!["Ada".to_string()],
+            birth_date: NaiveDate::from_ymd_opt(1941, 3, 9),
+            gender: Some(Gender::Female),
+            room: Some("room-204".to_string()),
+        };
+        
+        let json = serde_json::to_value(patient.to_fhir()).unwrap();
+        assert_eq!(json["resourceType"], "Patient");
+        assert_eq!(json["id"], "pat-0042");
+        assert_eq!(json["name"][0]["use"], "official");
+        assert_eq!(json["name"][0]["family"], "Okafor");
+        assert_eq!(json["name"][0]["given"][0], "Ada");
+        assert_eq!(json["gender"], "female");
+        assert_eq!(json["birthDate"], "1941-03-09");
+    }
+    
+    #[test]
+    fn test_event_without_room_defaults_to_default_room() {
+        let json = r#"{"id": 5, "reading": {"temperature": 21.0, "motion": true,
+            "sound_level": 40, "timestamp": "2024-01-15T10:00:00Z"}, "alerts": []}"#;
+        
+        let event: SensorEvent = serde_json::from_str(json).unwrap();
+        assert_eq!(event.room, DEFAULT_ROOM_ID);
+        assert!(event.seq.is_none());
+    }
+    
+    #[test]
+    fn test_bundle_next_link() {
+        let plain = serde_json::to_value(FhirBundle::from_events(Vec::new(), "http://localhost")).unwrap();
+        assert!(plain.get("link").is_none());
+        
+        let next = "http://localhost/api/observations/changes?after=42&_count=50".to_string();
+        let bundle = FhirBundle::from_events(Vec::new(), "http://localhost").with_next(next.clone());
+        let json = serde_json::to_value(bundle).unwrap();
+        assert_eq!(json["link"][0]["relation"], "next");
+        assert_eq!(json["link"][0]["url"], next);
+    }
+    
+    // ========================================================================
+    // SERIAL LINE PROTOCOL TESTS
+    // ========================================================================
+    
+    #[test]
+    fn test_parse_json_reading_line() {
+        let reading = SensorReading::parse_line(r#"{"v":2,"t":23.5,"m":1,"s":40,"hum":55}"#).unwrap();
+        assert_eq!(reading.temperature, 23.5);
+        assert!(reading.motion);
+        assert_eq!(reading.sound_level, 40);
+        
+        let reading = SensorReading::parse_line(r#"{"s":12,"m":false,"t":21,"v":2}"#).unwrap();
+        assert!(!reading.motion);
+        assert_eq!(reading.sound_level, 12);
+        
+        // Other versions, missing channels and broken JSON are rejected
+        assert!(SensorReading::parse_line(r#"{"v":3,"t":23.5,"m":1,"s":40}"#).is_none());
+        assert!(SensorReading::parse_line(r#"{"v":2,"t":23.5,"m":1}"#).is_none());
+        assert!(SensorReading::parse_line(r#"{"v":2,"t":23.5,"m":"yes","s":40}"#).is_none());
+        assert!(SensorReading::parse_line(r#"{"v":2,"t":23.5,"#).is_none());
+    }
+    
+    #[test]
+    fn test_parse_legacy_csv_reading_line() {
+        let reading = SensorReading::parse_line("23.5,1,150").unwrap();
+        assert_eq!(reading.temperature, 23.5);
+        assert!(reading.motion);
+        assert_eq!(reading.sound_level, 150);
+        
+        let reading = SensorReading::parse_line(" 74.3 , 0 , 20 ").unwrap();
+        assert_eq!(reading.temperature, 74.3);
+        assert!(!reading.motion);
+        
+        assert!(SensorReading::parse_line("23.5,1").is_none());
+        assert!(SensorReading::parse_line("23.5,1,150,9").is_none());
+        assert!(SensorReading::parse_line("warm,1,150").is_none());
+    }
+    
+    #[test]
+    fn test_sensor_ranges_flag_implausible_readings() {
+        let ranges = SensorRanges::default();
+        let check = |line| ranges.check(&SensorReading::parse_line(line).unwrap());
+        
+        assert_eq!(check("23.5,1,150"), None);
+        // Limits are inclusive
+        assert_eq!(check("-10,0,0"), None);
+        assert_eq!(check("60,0,1023"), None);
+        assert_eq!(check("85.0,0,40"), Some("temperature 85 outside -10..60".to_string()));
+        assert_eq!(check("23.5,0,-1"), Some("sound level -1 outside 0..1023".to_string()));
+        assert!(check("NaN,0,40").is_some());
+    }
+    
+    #[test]
+    fn test_parse_sensor_ranges() {
+        let ranges = SensorRanges::parse("temperature=5..45.5, sound=0..4095").unwrap();
+        assert_eq!(ranges.temperature, (5.0, 45.5));
+        assert_eq!(ranges.sound_level, (0, 4095));
+        
+        // Channels not given keep their defaults
+        let ranges = SensorRanges::parse("sound=10..900").unwrap();
+        assert_eq!(ranges.temperature, SensorRanges::default().temperature);
+        assert_eq!(SensorRanges::parse("").unwrap(), SensorRanges::default());
+        
+        let ranges = SensorRanges::parse("humidity=20..80").unwrap();
+        assert_eq!(ranges.humidity, (20.0, 80.0));
+        
+        assert!(SensorRanges::parse("pressure=900..1100").is_err());
+        assert!(SensorRanges::parse("temperature=45..5").is_err());
+        assert!(SensorRanges::parse("sound=0-1023").is_err());
+        assert!(SensorRanges::parse("temperature=NaN..40").is_err());
+    }
+    
+    // ========================================================================
+    // MANUAL OBSERVATION TESTS
+    // ========================================================================
+    
+    fn pain_score(value: f64, note: Option<&str>) -> ManualObservation {
+        ManualObservation {
+            id: Some(12),
+            room: "room-204".to_string(),
+            patient_id: Some("pat-0042".to_string()),
+            kind: ObservationKind::PainScore,
+            value,
+            observed_at: Utc::now(),
+            recorded_by: "nurse.jones".to_string(),
+            note: note.map(str::to_string),
+        }
+    }
+    
+    #[test]
+    fn test_observation_kind_parsing() {
+        let kind: ObservationKind = "body_temperature".parse().unwrap();
+        assert_eq!(kind, ObservationKind::BodyTemperature);
+        assert_eq!(kind.unit(), "Cel");
+        assert!("temperature".parse::<ObservationKind>().is_err());
+        
+        let kind: ObservationKind = serde_json::from_str(r#""pain_score""#).unwrap();
+        assert_eq!(kind, ObservationKind::PainScore);
+    }
+    
+    #[test]
+    fn test_observation_value_validation() {
+        assert!(ObservationKind::BodyTemperature.validate(37.2).is_ok());
+        assert!(ObservationKind::BodyTemperature.validate(372.0).is_err());
+        assert!(ObservationKind::OxygenSaturation.validate(101.0).is_err());
+        assert!(ObservationKind::PainScore.validate(7.0).is_ok());
+        assert!(ObservationKind::PainScore.validate(7.5).is_err());
+        assert!(ObservationKind::PainScore.validate(-1.0).is_err());
+    }
+    
+    #[test]
+    fn test_manual_observation_to_fhir() {
+        let observation = pain_score(6.0, Some("After mobilisation"));
+        let json = serde_json::to_value(observation.to_fhir()).unwrap();
+        
+        assert_eq!(json["id"], "manual-observation-12");
+        assert_eq!(json["category"][0]["coding"][0]["code"], "survey");
+        assert_eq!(json["code"]["coding"][0]["code"], "72514-3");
+        assert_eq!(json["subject"]["reference"], "Patient/pat-0042");
+        assert_eq!(json["performer"][0]["reference"], "Practitioner/nurse.jones");
+        assert_eq!(json["method"]["text"], "Manual entry");
+        assert_eq!(json["valueQuantity"]["value"], 6.0);
+        assert_eq!(json["valueQuantity"]["code"], "{score}");
+        assert_eq!(json["note"][0]["text"], "After mobilisation");
+        assert!(json.get("component").is_none());
+        
+        let json = serde_json::to_value(pain_score(2.0, None).to_fhir()).unwrap();
+        assert!(json.get("note").is_none());
+    }
+    
+    #[test]
+    fn test_sensor_observation_method() {
+        let event = SensorEvent {
+            id: Some(7),
+            seq: None,
+            room: "room-204".to_string(),
+            device: None,
+            patient_id: None,
+            reading: SensorReading {
+                temperature: 22.0,
+                motion: true,
+                sound_level: 30,
+                timestamp: Utc::now(),
+                ..Default::default()
+            },
+            alerts: AlertSet::new(),
+        };
+        
+        let json = serde_json::to_value(event.to_fhir("http://localhost")).unwrap();
+        assert_eq!(json["method"]["text"], "Room sensor");
+        assert!(json.get("performer").is_none());
+        assert!(json.get("valueQuantity").is_none());
+    }
+    
+    fn event(reading: SensorReading) -> SensorEvent {
+        SensorEvent {
+            id: Some(8),
+            seq: None,
+            room: "room-204".to_string(),
+            device: None,
+            patient_id: None,
+            reading,
+            alerts: AlertSet::new(),
+        }
+    }
+    
+    /// The component of an observation with the given code text
+    fn component<'a>(json: &'a serde_json::Value, text: &str) -> &'a serde_json::Value {
+        json["component"].as_array().unwrap().iter()
+            .find(|c| c["code"]["text"] == text)
+            .unwrap_or_else(|| panic!("no {} component", text))
+    }
+    
+    #[test]
+    fn test_optional_channels_to_fhir() {
+        // Line, component text, value and unit code; booleans have no unit
+        let cases = [
+            (r#"{"v":2,"t":23.5,"m":0,"s":40,"h":48.5}"#, "Relative Humidity", serde_json::json!(48.5), Some("%")),
+            (r#"{"v":2,"t":36.5,"m":0,"s":40,"hr":72}"#, "Heart rate", serde_json::json!(72.0), Some("/min")),
+            (r#"{"v":2,"t":36.5,"m":0,"s":40,"spo2":97}"#, "Oxygen saturation in Arterial blood by Pulse oximetry", serde_json::json!(97.0), Some("%")),
+            (r#"{"v":2,"t":22.0,"m":1,"s":40,"d":1}"#, "Door Open", serde_json::json!(true), None),
+            (r#"{"v":2,"t":22.0,"m":0,"s":20,"lx":120.5}"#, "Ambient Light", serde_json::json!(120.5), Some("lx")),
+            (r#"{"v":2,"t":22.0,"m":0,"s":20,"co2":650}"#, "Carbon Dioxide (room air)", serde_json::json!(650.0), Some("[ppm]")),
+            (r#"{"v":2,"t":22.0,"m":0,"s":20,"pr":1}"#, "Presence (radar)", serde_json::json!(true), None),
+            (r#"{"v":2,"t":22.0,"m":1,"s":20,"g":3.5}"#, "Peak Acceleration", serde_json::json!(3.5), Some("[g]")),
+        ];
+        
+        for (line, text, value, unit) in cases {
+            let reading = SensorReading::parse_line(line).unwrap();
+            let json = serde_json::to_value(event(reading).to_fhir("http://localhost")).unwrap();
+            let component = component(&json, text);
+            match unit {
+                Some(unit) => {
+                    assert_eq!(component["valueQuantity"]["value"], value, "{}", text);
+                    assert_eq!(component["valueQuantity"]["code"], unit, "{}", text);
+                    assert_eq!(component["valueQuantity"]["system"], "http://unitsofmeasure.org", "{}", text);
+                }
+                None => assert_eq!(component["valueBoolean"], value, "{}", text),
+            }
+        }
+        
+        // The unit shown differs from its UCUM code only for CO2
+        let reading = SensorReading::parse_line(r#"{"v":2,"t":22.0,"m":0,"s":20,"co2":650}"#).unwrap();
+        let json = serde_json::to_value(event(reading).to_fhir("http://localhost")).unwrap();
+        assert_eq!(component(&json, "Carbon Dioxide (room air)")["valueQuantity"]["unit"], "ppm");
+    }
+    
+    #[test]
+    fn test_optional_channels_missing_from_fhir() {
+        let reading = SensorReading::parse_line("23.5,0,40").unwrap();
+        assert_eq!(
+            (reading.humidity, reading.heart_rate, reading.spo2, reading.door_open),
+            (None, None, None, None),
+        );
+        assert_eq!(
+            (reading.light, reading.co2, reading.presence, reading.acceleration),
+            (None, None, None, None),
+        );
+        
+        // Boards without a sensor get no component rather than a zero
+        let json = serde_json::to_value(event(reading).to_fhir("http://localhost")).unwrap();
+        let texts: Vec<_> = json["component"].as_array().unwrap().iter()
+            .map(|c| c["code"]["text"].as_str().unwrap())
+            .collect();
+        for text in ["Relative Humidity", "Heart rate", "Door Open", "Ambient Light", "Carbon Dioxide (room air)", "Presence (radar)", "Peak Acceleration"] {
+            assert!(!texts.contains(&text), "{}", text);
+        }
+    }
+    
+    #[test]
+    fn test_boolean_channels_parse() {
+        let line = |field: &str| format!(r#"{{"v":2,"t":22.0,"m":1,"s":40,{}}}"#, field);
+        assert_eq!(SensorReading::parse_line(&line(r#""d":false"#)).unwrap().door_open, Some(false));
+        assert_eq!(SensorReading::parse_line(&line(r#""pr":false"#)).unwrap().presence, Some(false));
+        assert!(SensorReading::parse_line(&line(r#""d":"open""#)).is_none());
+        assert!(SensorReading::parse_line(&line(r#""pr":"yes""#)).is_none());
+    }
+    
+    #[test]
+    fn test_optional_channels_out_of_range() {
+        let base = SensorReading::parse_line("22.0,0,20").unwrap();
+        let cases = [
+            (SensorReading { humidity: Some(104.0), ..base.clone() }, "humidity 104 outside 0..100"),
+            (SensorReading { spo2: Some(101), ..base.clone() }, "SpO2 101 outside 50..100"),
+            (SensorReading { heart_rate: Some(300), ..base.clone() }, "heart rate 300 outside 20..250"),
+            (SensorReading { light: Some(-5.0), ..base.clone() }, "light level -5 outside 0..150000"),
+            (SensorReading { co2: Some(50_000), ..base.clone() }, "CO2 50000 ppm outside 300..10000"),
+            (SensorReading { acceleration: Some(40.0), ..base }, "acceleration 40 g outside 0..16"),
+        ];
+        for (reading, reason) in cases {
+            assert_eq!(SensorRanges::default().check(&reading), Some(reason.to_string()));
+        }
+        
+        assert_eq!(SensorRanges::parse("heart_rate=30..220").unwrap().heart_rate, (30, 220));
+        assert_eq!(SensorRanges::parse("light=0..2000").unwrap().light, (0.0, 2000.0));
+        assert_eq!(SensorRanges::parse("co2=400..5000").unwrap().co2, (400, 5000));
+        assert_eq!(SensorRanges::parse("acceleration=0..8").unwrap().acceleration, (0.0, 8.0));
+    }
+    
+    // ========================================================================
+    // ADT FEED TESTS
+    // ========================================================================
+    
+    const ADMIT: &str = "MSH|^~\\&|PAS|GENHOSP|MONITOR|GENHOSP|20261018083000||ADT^A01^ADT_A01|MSG0001|P|2.5\r\
+        EVN|A01|20261018083000\r\
+        PID|1||MRN12345^^^GENHOSP^MR~NHS999||O\\S\\Brien^Mary^Ann||19480312|F\r\
+        PV1|1|I|3W^301^A^GENHOSP||||";
+    
+    #[test]
+    fn test_hl7_admission_assigns_location() {
+        let event = parse_hl7_adt(ADMIT).unwrap().unwrap();
+        
+        assert_eq!(event.action, AdtAction::Assign);
+        assert_eq!(event.source_id, "MSG0001");
+        assert_eq!(event.patient.id, "MRN12345");
+        assert_eq!(event.patient.family_name, "O^Brien");
+        assert_eq!(event.patient.given_names, vec!["Mary", "Ann"]);
+        assert_eq!(event.patient.birth_date, NaiveDate::from_ymd_opt(1948, 3, 12));
+        assert_eq!(event.patient.gender, Some(Gender::Female));
+        
+        let location = event.location.unwrap();
+        assert_eq!(location.to_string(), "3W^301^A");
+        assert_eq!(location.keys(), vec!["3W^301^A", "3W^301", "301"]);
+    }
+    
+    #[test]
+    fn test_hl7_trigger_events() {
+        let message = |trigger: &str| ADMIT.replace("ADT^A01^ADT_A01", &format!("ADT^{}", trigger));
+        
+        let action = |trigger: &str| parse_hl7_adt(&message(trigger)).unwrap().map(|e| e.action);
+        assert_eq!(action("A02"), Some(AdtAction::Assign));
+        assert_eq!(action("A03"), Some(AdtAction::Discharge));
+        assert_eq!(action("A11"), Some(AdtAction::Discharge));
+        assert_eq!(action("A08"), Some(AdtAction::Update));
+        // A pre-admission doesn't move anyone
+        assert_eq!(action("A05"), None);
+        
+        // A transfer must say where to
+        let nowhere = message("A02").replace("3W^301^A^GENHOSP", "");
+        assert!(parse_hl7_adt(&nowhere).is_err());
+        assert!(parse_hl7_adt(&ADMIT.replace("ADT^A01", "ORU^R01")).is_err());
+        assert!(parse_hl7_adt("not hl7").is_err());
+    }
+    
+    #[test]
+    fn test_hl7_ack_echoes_control_id() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 18, 8, 30, 1).unwrap();
+        
+        let ack = hl7_ack(ADMIT, Hl7AckCode::Accept, "", now);
+        let segments: Vec<&str> = ack.split('\r').filter(|s| !s.is_empty()).collect();
+        assert!(segments[0].starts_with("MSH|^~\\&|PatientMonitor||PAS|GENHOSP|20261018083001||ACK^A01|"));
+        assert!(segments[0].ends_with("|P|2.5"));
+        assert_eq!(segments[1], "MSA|AA|MSG0001|");
+        
+        let ack = hl7_ack("garbage", Hl7AckCode::Error, "Not an HL7 message | at all", now);
+        assert!(ack.contains("MSA|AE||Not an HL7 message \\F\\ at all\r"));
+    }
+    
+    #[test]
+    fn test_fhir_encounter_to_adt() {
+        let encounter = |status: &str| -> FhirEncounter {
+            serde_json::from_value(serde_json::json!({
+                "resourceType": "Encounter",
+                "id": "enc-7",
+                "status": status,
+                "subject": {"reference": "Patient/MRN12345", "display": "Doe, Jane Q"},
+                "location": [
+                    {"location": {"reference": "Location/room-101"}, "status": "completed"},
+                    {"location": {"reference": "Location/room-204"}, "status": "active"},
+                    {"location": {"reference": "Location/room-305"}, "status": "planned"},
+                ],
+            })).unwrap()
+        };
+        
+        let event = encounter("in-progress").to_adt().unwrap().unwrap();
+        assert_eq!(event.action, AdtAction::Assign);
+        assert_eq!(event.source_id, "enc-7");
+        assert_eq!(event.patient.id, "MRN12345");
+        assert_eq!(event.patient.family_name, "Doe");
+        assert_eq!(event.patient.given_names, vec!["Jane", "Q"]);
+        assert_eq!(event.location.unwrap().keys(), vec!["room-204"]);
+        
+        assert_eq!(encounter("finished").to_adt().unwrap().unwrap().action, AdtAction::Discharge);
+        assert!(encounter("planned").to_adt().unwrap().is_none());
+    }
 }
@@ -2,12 +2,15 @@
 //! 
 //! This crate contains all unit and integration tests for the Smart Patient Monitor.
 //! 
+//! Alert detection, activity scoring, still-period and fall-risk tests call the real
+//! implementations in `patient-monitor-types::analysis`, which the server uses.
+//! 
 //! ## Test Categories
 //! 
-//! - **fhir_tests**: Tests for FHIR data structures and serialization
-//! - **alert_tests**: Tests for fall detection and inactivity alert logic
-//! - **api_tests**: Tests for REST API endpoints and responses
-//! - **activity_tests**: Tests for activity analysis and sleep scoring
+//! - **fhir_tests**: Tests for FHIR data structures and serialization, and the ADT feed
+//! - **alert_tests**: Tests for fall detection, inactivity and sensor-fault alert logic, and alert precision
+//! - **api_tests**: Tests for REST API endpoints and responses, and the WebSocket protocol
+//! - **activity_tests**: Tests for activity analysis, sleep scoring, chart downsampling and compaction
 //! - **db_tests**: Tests for database CRUD operations
 //! 
 //! ## Running Tests
@@ -34,11 +37,11 @@
 //! 
 //! | Module | Tests | Coverage |
 //! |--------|-------|----------|
-//! | FHIR Structures | 8 | Data models, serialization |
-//! | Alert Detection | 15 | Fall detection, inactivity |
-//! | API Endpoints | 20 | Health, observations, bundles |
-//! | Activity Analysis | 20 | Scoring, levels, quality |
-//! | Database | 18 | CRUD operations, summaries |
+//! | FHIR Structures | 33 | Data models, serialization, serial line protocol, sensor ranges, humidity, vitals, door contact, ambient light, CO2, radar presence, acceleration, patients, manual observations, ADT feed |
+//! | Alert Detection | 40 | Fall detection, impact falls, inactivity, vital signs, sensor flatline, ventilation, temperature cross-checks, motion sources, auto-resolution, precision, announcements |
+//! | API Endpoints | 39 | Health, observations, bundles, research aggregates, alert statistics, settings permissions, kiosk status, handover, WebSocket protocol and subscriptions |
+//! | Activity Analysis | 59 | Scoring, levels, quality, still periods, their distribution and the longest ones, fall risk, night awakenings, night light, room states, door passages, downsampling, compaction, sound matrix |
+//! | Database | 19 | CRUD operations, summaries |
 
 // Include test modules
 mod fhir_tests;